@@ -0,0 +1,78 @@
+//! Future types for the `Batch` middleware.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::ready;
+use pin_project::pin_project;
+
+use super::{error::Closed, message, BoxError};
+
+/// Future that completes when the batch processing is complete.
+#[pin_project]
+#[derive(Debug)]
+pub struct ResponseFuture<T> {
+    /// The response future from the inner service, once the worker has
+    /// called it.
+    #[pin]
+    response: Option<T>,
+    /// The channel for the inner service's response future.
+    rx: Option<message::Rx<T>>,
+    /// An error from the worker, if the request couldn't be sent.
+    failed: Option<BoxError>,
+}
+
+impl<T> ResponseFuture<T> {
+    pub(crate) fn new(rx: message::Rx<T>) -> Self {
+        ResponseFuture {
+            response: None,
+            rx: Some(rx),
+            failed: None,
+        }
+    }
+
+    pub(crate) fn failed(error: BoxError) -> Self {
+        ResponseFuture {
+            response: None,
+            rx: None,
+            failed: Some(error),
+        }
+    }
+}
+
+impl<T, R, E> Future for ResponseFuture<T>
+where
+    T: Future<Output = Result<R, E>>,
+    E: Into<BoxError>,
+{
+    type Output = Result<R, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+
+        loop {
+            if let Some(response) = this.response.as_mut().as_pin_mut() {
+                return response.poll(cx).map_err(Into::into);
+            }
+            if let Some(error) = this.failed.take() {
+                return Poll::Ready(Err(error));
+            }
+
+            let rx = this
+                .rx
+                .as_mut()
+                .expect("ResponseFuture polled after completion");
+            match ready!(Pin::new(rx).poll(cx)) {
+                Ok(Ok(response)) => {
+                    *this.rx = None;
+                    this.response.set(Some(response));
+                }
+                Ok(Err(e)) => return Poll::Ready(Err(e.into())),
+                Err(_) => return Poll::Ready(Err(Closed::new().into())),
+            }
+        }
+    }
+}
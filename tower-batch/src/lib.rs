@@ -0,0 +1,46 @@
+//! Tower middleware for batch request processing
+//!
+//! This crate provides generic middleware for managing a batch of
+//! requests. It functions like
+//! [`tower::buffer`](https://docs.rs/tower/0.3.1/tower/buffer/index.html):
+//! the [`Batch`] service is a cheaply cloneable handle, which sends requests
+//! over a channel to a worker task that owns the underlying service.
+//!
+//! The underlying service receives [`BatchControl`] messages: each request
+//! is passed through as an `Item`, and the worker sends a `Flush` when the
+//! batch reaches `max_items`, or when `max_latency` has passed since the
+//! first item in the batch. Callers can also flush the batch early, using
+//! [`Batch::flush`]. Services should process all their pending items when
+//! they are flushed, then resolve the response futures for those items.
+//!
+//! This is useful for cryptographic verification, where verifying a batch of
+//! signatures or proofs is much cheaper than verifying each one separately.
+//! The caller gets a future for each individual item, and the batching is
+//! hidden behind the `Service` interface.
+
+#![deny(missing_docs)]
+
+pub mod error;
+pub mod future;
+mod message;
+mod service;
+mod worker;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// Signaling mechanism for services that allow processing in batches.
+#[derive(Debug, Eq, PartialEq)]
+pub enum BatchControl<R> {
+    /// Collect a new batch item.
+    Item(R),
+    /// The current batch should be flushed.
+    Flush,
+}
+
+impl<R> From<R> for BatchControl<R> {
+    fn from(req: R) -> BatchControl<R> {
+        BatchControl::Item(req)
+    }
+}
+
+pub use self::service::Batch;
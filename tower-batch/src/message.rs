@@ -0,0 +1,23 @@
+use super::{error::ServiceError, BatchControl};
+use tokio::sync::oneshot;
+
+/// Message sent to the batch worker
+#[derive(Debug)]
+pub(crate) struct Message<Request, Fut> {
+    /// A batch item, or an explicit flush from a caller.
+    pub(crate) request: BatchControl<Request>,
+    pub(crate) tx: Tx<Fut>,
+}
+
+impl<Request, Fut> Message<Request, Fut> {
+    /// Returns true if this message flushes the batch.
+    pub(crate) fn is_flush(&self) -> bool {
+        matches!(self.request, BatchControl::Flush)
+    }
+}
+
+/// Response sender
+pub(crate) type Tx<Fut> = oneshot::Sender<Result<Fut, ServiceError>>;
+
+/// Response receiver
+pub(crate) type Rx<Fut> = oneshot::Receiver<Result<Fut, ServiceError>>;
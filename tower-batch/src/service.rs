@@ -0,0 +1,140 @@
+use std::{
+    fmt,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::ready;
+use tokio::sync::{mpsc, oneshot};
+use tower::Service;
+
+use super::{
+    future::ResponseFuture,
+    message::Message,
+    worker::{Handle, Worker},
+    BatchControl, BoxError,
+};
+
+/// Allows batch processing of requests.
+///
+/// See the module documentation for more details.
+pub struct Batch<T, Request>
+where
+    T: Service<BatchControl<Request>>,
+{
+    tx: mpsc::Sender<Message<Request, T::Future>>,
+    handle: Handle,
+}
+
+impl<T, Request> fmt::Debug for Batch<T, Request>
+where
+    T: Service<BatchControl<Request>>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Batch").finish()
+    }
+}
+
+impl<T, Request> Batch<T, Request>
+where
+    T: Service<BatchControl<Request>>,
+    T::Error: Into<BoxError>,
+{
+    /// Creates a new `Batch` wrapping `service`.
+    ///
+    /// The wrapper is responsible for telling the inner service when to flush a
+    /// batch of requests. Two parameters control this policy:
+    ///
+    /// * `max_items` gives the maximum number of items per batch.
+    /// * `max_latency` gives the maximum latency for a batch item.
+    ///
+    /// The default Tokio executor is used to run the given service, which means
+    /// that this method must be called while on the Tokio runtime.
+    pub fn new(service: T, max_items: usize, max_latency: Duration) -> Self
+    where
+        T: Send + 'static,
+        T::Future: Send,
+        T::Error: Send + Sync,
+        Request: Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel(max_items);
+        let (handle, worker) = Worker::new(service, rx, max_items, max_latency);
+        tokio::spawn(worker.run());
+        Batch { tx, handle }
+    }
+
+    /// Flushes the current batch, without waiting for it to fill up or for
+    /// `max_latency` to pass.
+    ///
+    /// Callers should flush when they know that no more items are coming
+    /// soon. The flush is queued behind any items that were already sent, so
+    /// it includes them in the batch.
+    pub async fn flush(&mut self) -> Result<T::Response, BoxError> {
+        let (tx, rx) = oneshot::channel();
+        let message = Message {
+            request: BatchControl::Flush,
+            tx,
+        };
+        if self.tx.send(message).await.is_err() {
+            return Err(self.get_worker_error());
+        }
+        ResponseFuture::new(rx).await
+    }
+
+    fn get_worker_error(&self) -> BoxError {
+        self.handle.get_error_on_closed()
+    }
+}
+
+impl<T, Request> Service<Request> for Batch<T, Request>
+where
+    T: Service<BatchControl<Request>>,
+    T::Error: Into<BoxError>,
+{
+    type Response = T::Response;
+    type Error = BoxError;
+    type Future = ResponseFuture<T::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // If the inner service has errored, then we error here.
+        if ready!(self.tx.poll_ready(cx)).is_err() {
+            Poll::Ready(Err(self.get_worker_error()))
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        // The worker sends back the inner service's response future, so that
+        // it can keep accepting batch items while the batch is processed.
+        let (tx, rx) = oneshot::channel();
+        let message = Message {
+            request: BatchControl::Item(request),
+            tx,
+        };
+        match self.tx.try_send(message) {
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                ResponseFuture::failed(self.get_worker_error())
+            }
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                // When `mpsc::Sender::poll_ready` returns `Ready`, a slot
+                // in the channel is reserved for the handle. Other `Sender`
+                // handles may not send a message using that slot.
+                panic!("buffer full; poll_ready must be called first");
+            }
+            Ok(_) => ResponseFuture::new(rx),
+        }
+    }
+}
+
+impl<T, Request> Clone for Batch<T, Request>
+where
+    T: Service<BatchControl<Request>>,
+{
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            handle: self.handle.clone(),
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{
+    future::{select, Either},
+    StreamExt, TryFutureExt,
+};
+use tokio::{
+    sync::mpsc,
+    time::{delay_for, Delay},
+};
+use tower::{Service, ServiceExt};
+
+use super::{
+    error::{Closed, ServiceError},
+    message::{self, Message},
+    BatchControl, BoxError,
+};
+
+/// Task that handles processing the buffer.
+///
+/// The worker owns the inner service, and is spawned by [`Batch::new`].
+///
+/// [`Batch::new`]: super::Batch::new
+pub struct Worker<T, Request>
+where
+    T: Service<BatchControl<Request>>,
+    T::Error: Into<BoxError>,
+{
+    rx: mpsc::Receiver<Message<Request, T::Future>>,
+    service: T,
+    failed: Option<ServiceError>,
+    handle: Handle,
+    max_items: usize,
+    max_latency: Duration,
+}
+
+/// Get the error out
+#[derive(Debug)]
+pub(crate) struct Handle {
+    inner: Arc<Mutex<Option<ServiceError>>>,
+}
+
+impl<T, Request> Worker<T, Request>
+where
+    T: Service<BatchControl<Request>>,
+    T::Error: Into<BoxError>,
+{
+    pub(crate) fn new(
+        service: T,
+        rx: mpsc::Receiver<Message<Request, T::Future>>,
+        max_items: usize,
+        max_latency: Duration,
+    ) -> (Handle, Worker<T, Request>) {
+        let handle = Handle {
+            inner: Arc::new(Mutex::new(None)),
+        };
+
+        let worker = Worker {
+            rx,
+            service,
+            handle: handle.clone(),
+            failed: None,
+            max_items,
+            max_latency,
+        };
+
+        (handle, worker)
+    }
+
+    async fn process_req(&mut self, req: BatchControl<Request>, tx: message::Tx<T::Future>) {
+        if let Some(ref failed) = self.failed {
+            let _ = tx.send(Err(failed.clone()));
+        } else {
+            match self.service.ready_and().await {
+                Ok(svc) => {
+                    let rsp = svc.call(req);
+                    let _ = tx.send(Ok(rsp));
+                }
+                Err(e) => {
+                    self.failed(e.into());
+                    let _ = tx.send(Err(self.failed.as_ref().expect("just set failed").clone()));
+                }
+            }
+        }
+    }
+
+    async fn flush_service(&mut self) {
+        if let Err(e) = self
+            .service
+            .ready_and()
+            .and_then(|svc| svc.call(BatchControl::Flush))
+            .await
+        {
+            self.failed(e.into());
+        }
+    }
+
+    pub async fn run(mut self) {
+        // The timer is started when the first entry of a new batch is
+        // submitted, so that the batch latency of all entries is at most
+        // self.max_latency. However, we don't keep the timer running unless
+        // there is a pending request to prevent wakeups on idle services.
+        let mut timer: Option<Delay> = None;
+        let mut pending_items = 0usize;
+        loop {
+            match timer.take() {
+                None => match self.rx.next().await {
+                    // A caller flushed an empty batch.
+                    Some(msg) if msg.is_flush() => {
+                        self.process_req(msg.request, msg.tx).await;
+                    }
+                    // The first message in a new batch.
+                    Some(msg) => {
+                        self.process_req(msg.request, msg.tx).await;
+                        timer = Some(delay_for(self.max_latency));
+                        pending_items = 1;
+                    }
+                    // No more messages, ever.
+                    None => return,
+                },
+                Some(delay) => {
+                    // Wait on either a new message or the batch timer.
+                    match select(self.rx.next(), delay).await {
+                        Either::Left((Some(msg), _delay)) if msg.is_flush() => {
+                            // A caller flushed the batch, so stop the timer.
+                            self.process_req(msg.request, msg.tx).await;
+                            pending_items = 0;
+                        }
+                        Either::Left((Some(msg), delay)) => {
+                            self.process_req(msg.request, msg.tx).await;
+                            pending_items += 1;
+                            // Check whether we have too many pending items.
+                            if pending_items >= self.max_items {
+                                // Flush the service, and don't restart the timer.
+                                self.flush_service().await;
+                                pending_items = 0;
+                            } else {
+                                // The timer is still running.
+                                timer = Some(delay);
+                            }
+                        }
+                        Either::Left((None, _delay)) => {
+                            // No more messages, so flush the last batch.
+                            self.flush_service().await;
+                            return;
+                        }
+                        Either::Right(((), _next)) => {
+                            // The timer expired, so flush the batch.
+                            self.flush_service().await;
+                            pending_items = 0;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn failed(&mut self, error: BoxError) {
+        // The underlying service failed when we called `poll_ready` or
+        // `Flush` on it with the given `error`. We need to communicate this to
+        // all the `Batch` handles. To do so, we wrap the error in an `Arc`,
+        // send a copy to all pending requests, and store it so that
+        // subsequent requests will also fail with the same error.
+
+        // Note that we need to handle the case where some handle is
+        // concurrently trying to send us a request. We need to make sure that
+        // *either* the send of the request fails *or* it receives an error on
+        // the `oneshot` it constructed. Specifically, we want to avoid the
+        // case where we send errors to all outstanding requests, and *then*
+        // the caller sends its request. We do this by *first* exposing the
+        // error, *then* closing the channel used to send more requests (so
+        // the client will see the error when the send fails), and *then*
+        // sending the error to all outstanding requests.
+        let error = ServiceError::new(error);
+
+        let mut inner = self.handle.inner.lock().unwrap();
+
+        if inner.is_some() {
+            // Future::poll was called after we've already errored out!
+            return;
+        }
+
+        *inner = Some(error.clone());
+        drop(inner);
+
+        self.rx.close();
+
+        // By closing the mpsc::Receiver, we know that the run() loop will
+        // drain all pending requests. We just need to make sure that any
+        // requests that we receive before we've exhausted the receiver receive
+        // the error:
+        self.failed = Some(error);
+    }
+}
+
+impl Handle {
+    pub(crate) fn get_error_on_closed(&self) -> BoxError {
+        self.inner
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|svc_err| svc_err.clone().into())
+            .unwrap_or_else(|| Closed::new().into())
+    }
+}
+
+impl Clone for Handle {
+    fn clone(&self) -> Handle {
+        Handle {
+            inner: self.inner.clone(),
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{
+    channel::oneshot,
+    future::{join_all, BoxFuture, FutureExt},
+};
+use tower::{Service, ServiceExt};
+use tower_batch::{Batch, BatchControl};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// Responds to each item with the number of items in its batch.
+#[derive(Default)]
+struct BatchSize {
+    pending: Vec<oneshot::Sender<usize>>,
+}
+
+impl Service<BatchControl<()>> for BatchSize {
+    type Response = usize;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<usize, BoxError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: BatchControl<()>) -> Self::Future {
+        match req {
+            BatchControl::Item(()) => {
+                let (tx, rx) = oneshot::channel();
+                self.pending.push(tx);
+                async move { rx.await.map_err(Into::into) }.boxed()
+            }
+            BatchControl::Flush => {
+                let size = self.pending.len();
+                for tx in self.pending.drain(..) {
+                    let _ = tx.send(size);
+                }
+                async { Ok(0) }.boxed()
+            }
+        }
+    }
+}
+
+async fn send_items(
+    batch: &mut Batch<BatchSize, ()>,
+    count: usize,
+) -> Result<Vec<usize>, BoxError> {
+    let mut responses = Vec::new();
+    for _ in 0..count {
+        responses.push(batch.ready_and().await?.call(()));
+    }
+    join_all(responses).await.into_iter().collect()
+}
+
+#[tokio::test]
+async fn batch_flushes_at_max_items() -> Result<(), BoxError> {
+    let mut batch = Batch::new(BatchSize::default(), 10, Duration::from_secs(1000));
+
+    assert_eq!(send_items(&mut batch, 10).await?, vec![10; 10]);
+    assert_eq!(send_items(&mut batch, 10).await?, vec![10; 10]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn batch_flushes_at_max_latency() -> Result<(), BoxError> {
+    let mut batch = Batch::new(BatchSize::default(), 10, Duration::from_millis(10));
+
+    assert_eq!(send_items(&mut batch, 3).await?, vec![3; 3]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn batch_flushes_on_request() -> Result<(), BoxError> {
+    let mut batch = Batch::new(BatchSize::default(), 10, Duration::from_secs(1000));
+
+    let mut responses = Vec::new();
+    for _ in 0..3 {
+        responses.push(batch.ready_and().await?.call(()));
+    }
+    batch.flush().await?;
+    let sizes: Result<Vec<_>, _> = join_all(responses).await.into_iter().collect();
+    assert_eq!(sizes?, vec![3; 3]);
+
+    // Flushing an empty batch is harmless.
+    batch.flush().await?;
+    assert_eq!(send_items(&mut batch, 10).await?, vec![10; 10]);
+
+    Ok(())
+}
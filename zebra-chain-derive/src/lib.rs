@@ -0,0 +1,346 @@
+//! Derive macros for Zebra's consensus-critical serialization traits.
+//!
+//! `#[derive(ZcashSerialize, ZcashDeserialize)]` encodes each field of a
+//! struct in declaration order, using the field type's own implementation.
+//! Field attributes change the encoding of array and vector fields:
+//!
+//! - `#[zcash(fixed)]` on a `[T; N]` field writes its `N` elements, with no
+//!   length prefix. Byte arrays are written directly.
+//! - `#[zcash(length_prefixed)]` on a `Vec<T>` field writes a `CompactSize`
+//!   count, followed by the elements. `Vec<u8>` fields are written as byte
+//!   strings. Other element types must implement `TrustedPreallocate`, which
+//!   bounds the count when deserializing.
+//!
+//! The generated code refers to `zebra_chain::serialization`, so these
+//! derives should be used through their re-exports in that module.
+
+#![deny(missing_docs)]
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, parse_quote, Data, DeriveInput, Expr, Fields, GenericArgument, Lit, Member,
+    Meta, NestedMeta, PathArguments, Type,
+};
+
+/// Derive `ZcashSerialize` for a struct, by serializing its fields in order.
+#[proc_macro_derive(ZcashSerialize, attributes(zcash))]
+pub fn derive_zcash_serialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_serialize(input)
+        .unwrap_or_else(|error| error.to_compile_error())
+        .into()
+}
+
+/// Derive `ZcashDeserialize` for a struct, by deserializing its fields in
+/// order.
+#[proc_macro_derive(ZcashDeserialize, attributes(zcash))]
+pub fn derive_zcash_deserialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_deserialize(input)
+        .unwrap_or_else(|error| error.to_compile_error())
+        .into()
+}
+
+/// How a field is encoded.
+enum Encoding {
+    /// Use the field type's own trait implementation.
+    Inherent,
+    /// Write each array element, with no length prefix.
+    Fixed,
+    /// Write a `CompactSize` count, followed by each vector element.
+    LengthPrefixed,
+}
+
+/// A struct field, and how to encode it.
+struct Field {
+    member: Member,
+    ty: Type,
+    encoding: Encoding,
+}
+
+fn expand_serialize(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = parse_fields(&input)?;
+    add_bounds(
+        &mut input,
+        quote!(::zebra_chain::serialization::ZcashSerialize),
+    );
+
+    let writes = fields
+        .iter()
+        .map(|field| {
+            let member = &field.member;
+            match field.encoding {
+                Encoding::Inherent => Ok(quote! {
+                    ::zebra_chain::serialization::ZcashSerialize::zcash_serialize(
+                        &self.#member,
+                        &mut writer,
+                    )?;
+                }),
+                Encoding::Fixed => {
+                    let (elem, _len) = array_parts(field)?;
+                    if is_u8(elem) {
+                        Ok(quote! {
+                            ::std::io::Write::write_all(&mut writer, &self.#member[..])?;
+                        })
+                    } else {
+                        Ok(quote! {
+                            for item in self.#member.iter() {
+                                ::zebra_chain::serialization::ZcashSerialize::zcash_serialize(
+                                    item,
+                                    &mut writer,
+                                )?;
+                            }
+                        })
+                    }
+                }
+                Encoding::LengthPrefixed => {
+                    if is_u8(vec_elem(field)?) {
+                        Ok(quote! {
+                            ::zebra_chain::serialization::WriteZcashExt::write_compact_bytes(
+                                &mut writer,
+                                &self.#member[..],
+                            )?;
+                        })
+                    } else {
+                        Ok(quote! {
+                            ::zebra_chain::serialization::zcash_serialize_vec(
+                                &self.#member[..],
+                                &mut writer,
+                            )?;
+                        })
+                    }
+                }
+            }
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    // Structs without fields don't use the writer.
+    let unused_writer = if fields.is_empty() {
+        quote!(let _ = &mut writer;)
+    } else {
+        quote!()
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::zebra_chain::serialization::ZcashSerialize for #name #ty_generics
+        #where_clause
+        {
+            fn zcash_serialize<W: ::std::io::Write>(
+                &self,
+                mut writer: W,
+            ) -> ::std::result::Result<(), ::std::io::Error> {
+                #(#writes)*
+                #unused_writer
+                Ok(())
+            }
+        }
+    })
+}
+
+fn expand_deserialize(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = parse_fields(&input)?;
+    add_bounds(
+        &mut input,
+        quote!(::zebra_chain::serialization::ZcashDeserialize),
+    );
+
+    let reads = fields
+        .iter()
+        .map(|field| {
+            let ty = &field.ty;
+            match field.encoding {
+                Encoding::Inherent => Ok(quote! {
+                    <#ty as ::zebra_chain::serialization::ZcashDeserialize>::zcash_deserialize(
+                        &mut reader,
+                    )?
+                }),
+                Encoding::Fixed => {
+                    let (elem, len) = array_parts(field)?;
+                    if is_u8(elem) {
+                        return Ok(quote! {{
+                            let mut bytes = [0u8; #len];
+                            ::std::io::Read::read_exact(&mut reader, &mut bytes[..])?;
+                            bytes
+                        }});
+                    }
+                    let count = literal_len(len).ok_or_else(|| {
+                        syn::Error::new_spanned(
+                            len,
+                            "#[zcash(fixed)] arrays of non-byte elements need a literal length",
+                        )
+                    })?;
+                    let items = (0..count).map(|_| {
+                        quote! {
+                            <#elem as ::zebra_chain::serialization::ZcashDeserialize>::zcash_deserialize(
+                                &mut reader,
+                            )?
+                        }
+                    });
+                    Ok(quote! { [#(#items),*] })
+                }
+                Encoding::LengthPrefixed => {
+                    if is_u8(vec_elem(field)?) {
+                        Ok(quote! {
+                            ::zebra_chain::serialization::ReadZcashExt::read_compact_bytes(
+                                &mut reader,
+                            )?
+                        })
+                    } else {
+                        Ok(quote! {
+                            ::zebra_chain::serialization::zcash_deserialize_vec(&mut reader)?
+                        })
+                    }
+                }
+            }
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let name = &input.ident;
+    let construct = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(_) => {
+                let members = fields.iter().map(|field| &field.member);
+                quote! { #name { #(#members: #reads),* } }
+            }
+            Fields::Unnamed(_) => quote! { #name(#(#reads),*) },
+            Fields::Unit => quote! { #name },
+        },
+        _ => unreachable!("parse_fields only accepts structs"),
+    };
+
+    let unused_reader = if fields.is_empty() {
+        quote!(let _ = &mut reader;)
+    } else {
+        quote!()
+    };
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::zebra_chain::serialization::ZcashDeserialize for #name #ty_generics
+        #where_clause
+        {
+            fn zcash_deserialize<R: ::std::io::Read>(
+                mut reader: R,
+            ) -> ::std::result::Result<Self, ::zebra_chain::serialization::SerializationError> {
+                #unused_reader
+                Ok(#construct)
+            }
+        }
+    })
+}
+
+/// Parse the fields of a struct, and their `#[zcash(...)]` attributes.
+fn parse_fields(input: &DeriveInput) -> syn::Result<Vec<Field>> {
+    let data = match &input.data {
+        Data::Struct(data) => data,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "Zcash serialization can only be derived for structs",
+            ))
+        }
+    };
+
+    data.fields
+        .iter()
+        .enumerate()
+        .map(|(index, field)| {
+            let member = match &field.ident {
+                Some(ident) => Member::Named(ident.clone()),
+                None => Member::Unnamed(index.into()),
+            };
+            let mut encoding = Encoding::Inherent;
+            for attr in field
+                .attrs
+                .iter()
+                .filter(|attr| attr.path.is_ident("zcash"))
+            {
+                let list = match attr.parse_meta()? {
+                    Meta::List(list) => list,
+                    meta => return Err(syn::Error::new_spanned(meta, "expected #[zcash(...)]")),
+                };
+                for nested in list.nested.iter() {
+                    encoding = match nested {
+                        NestedMeta::Meta(Meta::Path(path)) if path.is_ident("fixed") => {
+                            Encoding::Fixed
+                        }
+                        NestedMeta::Meta(Meta::Path(path)) if path.is_ident("length_prefixed") => {
+                            Encoding::LengthPrefixed
+                        }
+                        _ => {
+                            return Err(syn::Error::new_spanned(
+                                nested,
+                                "expected `fixed` or `length_prefixed`",
+                            ))
+                        }
+                    };
+                }
+            }
+            Ok(Field {
+                member,
+                ty: field.ty.clone(),
+                encoding,
+            })
+        })
+        .collect()
+}
+
+/// Require each type parameter to implement `bound`.
+fn add_bounds(input: &mut DeriveInput, bound: TokenStream2) {
+    for param in input.generics.type_params_mut() {
+        param.bounds.push(parse_quote!(#bound));
+    }
+}
+
+/// Returns the element type and length of an array field.
+fn array_parts(field: &Field) -> syn::Result<(&Type, &Expr)> {
+    match &field.ty {
+        Type::Array(array) => Ok((&array.elem, &array.len)),
+        ty => Err(syn::Error::new_spanned(
+            ty,
+            "#[zcash(fixed)] can only be used on arrays",
+        )),
+    }
+}
+
+/// Returns the element type of a `Vec` field.
+fn vec_elem(field: &Field) -> syn::Result<&Type> {
+    if let Type::Path(path) = &field.ty {
+        if let Some(segment) = path.path.segments.last() {
+            if segment.ident == "Vec" {
+                if let PathArguments::AngleBracketed(args) = &segment.arguments {
+                    if let Some(GenericArgument::Type(elem)) = args.args.first() {
+                        return Ok(elem);
+                    }
+                }
+            }
+        }
+    }
+    Err(syn::Error::new_spanned(
+        &field.ty,
+        "#[zcash(length_prefixed)] can only be used on vectors",
+    ))
+}
+
+fn is_u8(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path.qself.is_none() && path.path.is_ident("u8"),
+        _ => false,
+    }
+}
+
+fn literal_len(len: &Expr) -> Option<usize> {
+    match len {
+        Expr::Lit(expr) => match &expr.lit {
+            Lit::Int(int) => int.base10_parse().ok(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
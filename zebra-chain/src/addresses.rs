@@ -1,5 +1,4 @@
 //! Address types.
 
-pub mod sapling;
 pub mod sprout;
-pub mod transparent;
+pub mod unified;
@@ -2,7 +2,7 @@
 
 use std::{fmt, io};
 
-#[cfg(test)]
+#[cfg(any(test, feature = "proptest-impl"))]
 use proptest::{arbitrary::Arbitrary, array, prelude::*};
 
 use crate::{
@@ -109,7 +109,7 @@ impl std::str::FromStr for SproutShieldedAddress {
     }
 }
 
-#[cfg(test)]
+#[cfg(any(test, feature = "proptest-impl"))]
 impl Arbitrary for SproutShieldedAddress {
     type Parameters = ();
 
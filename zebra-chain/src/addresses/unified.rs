@@ -0,0 +1,687 @@
+//! Unified Address types.
+//!
+//! A unified address bundles receivers for several pools into one string, so
+//! that a sender can pay to the best pool they support.
+//!
+//! https://zips.z.cash/zip-0316
+
+use std::fmt;
+
+use bech32::{u5, FromBase32, ToBase32};
+use thiserror::Error;
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest::{collection::vec, prelude::*};
+
+use crate::{
+    sapling,
+    serialization::{ReadZcashExt, SerializationError, WriteZcashExt},
+    transparent, Network,
+};
+
+/// Human-Readable Parts for input to bech32m encoding.
+mod human_readable_parts {
+    pub const MAINNET: &str = "u";
+    pub const TESTNET: &str = "utest";
+    pub const REGTEST: &str = "uregtest";
+}
+
+/// The receiver typecodes defined by ZIP-316.
+mod typecodes {
+    pub const P2PKH: u64 = 0x00;
+    pub const P2SH: u64 = 0x01;
+    pub const SAPLING: u64 = 0x02;
+    pub const ORCHARD: u64 = 0x03;
+}
+
+/// The length of the zero-padded human-readable part that is appended to
+/// the receivers before jumbling.
+const PADDING_LEN: usize = 16;
+
+/// The smallest message that F4Jumble accepts.
+const MIN_JUMBLE_LEN: usize = 48;
+
+/// The largest message that F4Jumble accepts.
+const MAX_JUMBLE_LEN: usize = 4_194_368;
+
+/// A receiver in a [`UnifiedAddress`].
+///
+/// Receivers are listed in priority order: senders should pay to the first
+/// receiver variant they support.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Receiver {
+    /// An Orchard receiver, which is a raw Orchard payment address: an
+    /// 11-byte diversifier followed by a 32-byte transmission key.
+    ///
+    /// Zebra doesn't parse the Orchard components yet.
+    Orchard([u8; 43]),
+    /// A Sapling receiver.
+    Sapling(sapling::Address),
+    /// A transparent P2PKH or P2SH receiver.
+    Transparent(transparent::Address),
+    /// A receiver with a typecode that Zebra doesn't know.
+    ///
+    /// Unknown receivers are kept, so addresses round-trip.
+    Unknown {
+        /// The receiver typecode.
+        typecode: u64,
+        /// The encoded receiver.
+        data: Vec<u8>,
+    },
+}
+
+impl Receiver {
+    /// The ZIP-316 typecode for this receiver.
+    pub fn typecode(&self) -> u64 {
+        match self {
+            Receiver::Orchard(_) => typecodes::ORCHARD,
+            Receiver::Sapling(_) => typecodes::SAPLING,
+            Receiver::Transparent(transparent::Address::PayToScriptHash { .. }) => typecodes::P2SH,
+            Receiver::Transparent(transparent::Address::PayToPublicKeyHash { .. }) => {
+                typecodes::P2PKH
+            }
+            Receiver::Unknown { typecode, .. } => *typecode,
+        }
+    }
+
+    /// The priority of this receiver, lower is preferred.
+    fn priority(&self) -> u8 {
+        match self {
+            Receiver::Orchard(_) => 0,
+            Receiver::Sapling(_) => 1,
+            Receiver::Transparent(_) => 2,
+            Receiver::Unknown { .. } => 3,
+        }
+    }
+
+    fn is_transparent(&self) -> bool {
+        matches!(self, Receiver::Transparent(_))
+    }
+
+    /// The raw encoding of this receiver.
+    fn data(&self) -> Vec<u8> {
+        match self {
+            Receiver::Orchard(data) => data.to_vec(),
+            Receiver::Sapling(address) => address.to_raw_bytes().to_vec(),
+            Receiver::Transparent(transparent::Address::PayToScriptHash {
+                script_hash: hash,
+                ..
+            })
+            | Receiver::Transparent(transparent::Address::PayToPublicKeyHash {
+                pub_key_hash: hash,
+                ..
+            }) => hash.to_vec(),
+            Receiver::Unknown { data, .. } => data.clone(),
+        }
+    }
+
+    /// Parse the raw encoding of a receiver with `typecode` on `network`.
+    fn from_data(
+        network: Network,
+        typecode: u64,
+        data: Vec<u8>,
+    ) -> Result<Receiver, UnifiedAddressError> {
+        use UnifiedAddressError::InvalidReceiver;
+
+        let fixed = |len: usize| {
+            if data.len() == len {
+                Ok(&data[..])
+            } else {
+                Err(InvalidReceiver(SerializationError::Parse(
+                    "unified address receiver has the wrong length",
+                )))
+            }
+        };
+
+        Ok(match typecode {
+            typecodes::P2PKH | typecodes::P2SH => {
+                let mut hash = [0; 20];
+                hash.copy_from_slice(fixed(20)?);
+                Receiver::Transparent(if typecode == typecodes::P2PKH {
+                    transparent::Address::PayToPublicKeyHash {
+                        network,
+                        pub_key_hash: hash,
+                    }
+                } else {
+                    transparent::Address::PayToScriptHash {
+                        network,
+                        script_hash: hash,
+                    }
+                })
+            }
+            typecodes::SAPLING => {
+                let mut bytes = [0; 43];
+                bytes.copy_from_slice(fixed(43)?);
+                Receiver::Sapling(
+                    sapling::Address::from_raw_bytes(network, bytes).map_err(InvalidReceiver)?,
+                )
+            }
+            typecodes::ORCHARD => {
+                let mut bytes = [0; 43];
+                bytes.copy_from_slice(fixed(43)?);
+                Receiver::Orchard(bytes)
+            }
+            _ => Receiver::Unknown { typecode, data },
+        })
+    }
+
+    fn network(&self) -> Option<Network> {
+        match self {
+            Receiver::Sapling(address) => Some(address.network()),
+            Receiver::Transparent(transparent::Address::PayToScriptHash { network, .. })
+            | Receiver::Transparent(transparent::Address::PayToPublicKeyHash { network, .. }) => {
+                Some(*network)
+            }
+            Receiver::Orchard(_) | Receiver::Unknown { .. } => None,
+        }
+    }
+}
+
+/// An error constructing or parsing a [`UnifiedAddress`].
+#[derive(Error, Debug)]
+pub enum UnifiedAddressError {
+    /// The string isn't a valid Bech32m unified address encoding.
+    #[error("invalid unified address encoding: {0}")]
+    Encoding(&'static str),
+    /// The address has two receivers with the same typecode.
+    #[error("unified address has more than one receiver with typecode {0}")]
+    DuplicateTypecode(u64),
+    /// The address has both a P2PKH and a P2SH receiver.
+    #[error("unified address has more than one transparent receiver")]
+    MultipleTransparent,
+    /// The address only has transparent receivers.
+    #[error("unified address has no shielded receivers")]
+    NoShieldedReceiver,
+    /// A receiver is for a different network than the address.
+    #[error("unified address receiver is for a different network")]
+    NetworkMismatch,
+    /// A receiver's encoding is invalid.
+    #[error("invalid unified address receiver: {0}")]
+    InvalidReceiver(SerializationError),
+}
+
+/// A ZIP-316 _unified address_.
+///
+/// Unified addresses are encoded as a Bech32m string, whose data is the
+/// F4Jumbled list of receivers. Unlike other Bech32 encodings, unified
+/// addresses can be longer than 90 characters.
+///
+/// https://zips.z.cash/zip-0316
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UnifiedAddress {
+    network: Network,
+    /// The receivers, in ascending typecode order.
+    receivers: Vec<Receiver>,
+}
+
+impl UnifiedAddress {
+    /// Create a unified address on `network` from `receivers`, in any order.
+    ///
+    /// A unified address must have at least one shielded receiver, at most
+    /// one transparent receiver, and at most one receiver of each typecode.
+    pub fn new(
+        network: Network,
+        mut receivers: Vec<Receiver>,
+    ) -> Result<UnifiedAddress, UnifiedAddressError> {
+        use UnifiedAddressError::*;
+
+        receivers.sort_by_key(Receiver::typecode);
+        for pair in receivers.windows(2) {
+            if pair[0].typecode() == pair[1].typecode() {
+                return Err(DuplicateTypecode(pair[0].typecode()));
+            }
+        }
+        if receivers.iter().filter(|r| r.is_transparent()).count() > 1 {
+            return Err(MultipleTransparent);
+        }
+        if receivers.iter().all(Receiver::is_transparent) {
+            return Err(NoShieldedReceiver);
+        }
+        if receivers
+            .iter()
+            .filter_map(Receiver::network)
+            .any(|receiver_network| receiver_network != network)
+        {
+            return Err(NetworkMismatch);
+        }
+
+        Ok(UnifiedAddress { network, receivers })
+    }
+
+    /// The network this address is for.
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// The receivers in this address, in ascending typecode order.
+    pub fn receivers(&self) -> &[Receiver] {
+        &self.receivers
+    }
+
+    /// The receiver that senders should prefer, if they support every
+    /// known receiver type.
+    ///
+    /// Orchard is preferred over Sapling, which is preferred over
+    /// transparent receivers. Unknown receivers are never preferred.
+    pub fn preferred_receiver(&self) -> Option<&Receiver> {
+        self.receivers
+            .iter()
+            .filter(|r| !matches!(r, Receiver::Unknown { .. }))
+            .min_by_key(|r| r.priority())
+    }
+
+    fn hrp(network: Network) -> &'static str {
+        match network {
+            Network::Mainnet => human_readable_parts::MAINNET,
+            Network::Testnet => human_readable_parts::TESTNET,
+            Network::Regtest => human_readable_parts::REGTEST,
+        }
+    }
+
+    fn padding(hrp: &str) -> [u8; PADDING_LEN] {
+        let mut padding = [0; PADDING_LEN];
+        padding[..hrp.len()].copy_from_slice(hrp.as_bytes());
+        padding
+    }
+}
+
+impl fmt::Display for UnifiedAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hrp = Self::hrp(self.network);
+
+        let mut message = Vec::new();
+        for receiver in &self.receivers {
+            let data = receiver.data();
+            let _ = message.write_compactsize(receiver.typecode());
+            let _ = message.write_compact_bytes(&data);
+        }
+        message.extend_from_slice(&Self::padding(hrp));
+
+        let jumbled = f4jumble::jumble(&message).map_err(|_| fmt::Error)?;
+        f.write_str(&bech32m::encode(hrp, &jumbled.to_base32()))
+    }
+}
+
+impl std::str::FromStr for UnifiedAddress {
+    type Err = UnifiedAddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use UnifiedAddressError::*;
+
+        let (hrp, data) = bech32m::decode(s).map_err(Encoding)?;
+        let network = match hrp.as_str() {
+            human_readable_parts::MAINNET => Network::Mainnet,
+            human_readable_parts::TESTNET => Network::Testnet,
+            human_readable_parts::REGTEST => Network::Regtest,
+            _ => return Err(Encoding("unknown unified address prefix")),
+        };
+
+        let jumbled =
+            Vec::<u8>::from_base32(&data).map_err(|_| Encoding("invalid bech32m padding"))?;
+        let message = f4jumble::unjumble(&jumbled).map_err(Encoding)?;
+
+        let (mut reader, padding) = message.split_at(message.len() - PADDING_LEN);
+        if padding != Self::padding(&hrp) {
+            return Err(Encoding("unified address padding doesn't match its prefix"));
+        }
+
+        let mut receivers = Vec::new();
+        while !reader.is_empty() {
+            let typecode = reader.read_compactsize().map_err(InvalidReceiver)?;
+            let data = reader.read_compact_bytes().map_err(InvalidReceiver)?;
+            receivers.push(Receiver::from_data(network, typecode, data)?);
+        }
+
+        // Receivers must be encoded in ascending typecode order.
+        if receivers
+            .windows(2)
+            .any(|pair| pair[0].typecode() > pair[1].typecode())
+        {
+            return Err(Encoding("unified address receivers are out of order"));
+        }
+
+        UnifiedAddress::new(network, receivers)
+    }
+}
+
+/// The F4Jumble unkeyed permutation, which makes sure that changing any
+/// part of a unified address changes most of its encoding.
+///
+/// https://zips.z.cash/zip-0316#jumbling
+mod f4jumble {
+    use super::{MAX_JUMBLE_LEN, MIN_JUMBLE_LEN};
+
+    /// The output length of the hash used in the `G` round function.
+    const BLOCK_LEN: usize = 64;
+
+    fn h_round(i: u8, input: &[u8], output_len: usize) -> Vec<u8> {
+        let mut personal = [0; 16];
+        personal[..13].copy_from_slice(b"UA_F4Jumble_H");
+        personal[13] = i;
+        blake2b_simd::Params::new()
+            .hash_length(output_len)
+            .personal(&personal)
+            .hash(input)
+            .as_bytes()
+            .to_vec()
+    }
+
+    fn g_round(i: u8, input: &[u8], output_len: usize) -> Vec<u8> {
+        let mut output = Vec::with_capacity(output_len + BLOCK_LEN);
+        let mut j: u16 = 0;
+        while output.len() < output_len {
+            let mut personal = [0; 16];
+            personal[..13].copy_from_slice(b"UA_F4Jumble_G");
+            personal[13] = i;
+            personal[14..].copy_from_slice(&j.to_le_bytes());
+            output.extend_from_slice(
+                blake2b_simd::Params::new()
+                    .hash_length(BLOCK_LEN)
+                    .personal(&personal)
+                    .hash(input)
+                    .as_bytes(),
+            );
+            j += 1;
+        }
+        output.truncate(output_len);
+        output
+    }
+
+    fn xor(a: &mut [u8], b: &[u8]) {
+        for (a, b) in a.iter_mut().zip(b) {
+            *a ^= b;
+        }
+    }
+
+    fn split(message: &[u8]) -> Result<(Vec<u8>, Vec<u8>), &'static str> {
+        if message.len() < MIN_JUMBLE_LEN || message.len() > MAX_JUMBLE_LEN {
+            return Err("unified address has an invalid length");
+        }
+        let left_len = (message.len() / 2).min(BLOCK_LEN);
+        let (left, right) = message.split_at(left_len);
+        Ok((left.to_vec(), right.to_vec()))
+    }
+
+    /// Apply F4Jumble to `message`.
+    pub fn jumble(message: &[u8]) -> Result<Vec<u8>, &'static str> {
+        let (mut a, mut b) = split(message)?;
+
+        xor(&mut b, &g_round(0, &a, b.len()));
+        xor(&mut a, &h_round(0, &b, a.len()));
+        xor(&mut b, &g_round(1, &a, b.len()));
+        xor(&mut a, &h_round(1, &b, a.len()));
+
+        a.extend_from_slice(&b);
+        Ok(a)
+    }
+
+    /// Invert F4Jumble on `jumbled`.
+    pub fn unjumble(jumbled: &[u8]) -> Result<Vec<u8>, &'static str> {
+        let (mut c, mut d) = split(jumbled)?;
+
+        xor(&mut c, &h_round(1, &d, c.len()));
+        xor(&mut d, &g_round(1, &c, d.len()));
+        xor(&mut c, &h_round(0, &d, c.len()));
+        xor(&mut d, &g_round(0, &c, d.len()));
+
+        c.extend_from_slice(&d);
+        Ok(c)
+    }
+}
+
+/// The Bech32m encoding, without Bech32's 90-character limit.
+///
+/// https://github.com/bitcoin/bips/blob/master/bip-0350.mediawiki
+mod bech32m {
+    use super::u5;
+
+    const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+    const CHECKSUM_CONST: u32 = 0x2bc8_30a3;
+
+    const CHECKSUM_LEN: usize = 6;
+
+    fn polymod(values: impl Iterator<Item = u8>) -> u32 {
+        const GENERATORS: [u32; 5] = [
+            0x3b6a_57b2,
+            0x2650_8e6d,
+            0x1ea1_19fa,
+            0x3d42_33dd,
+            0x2a14_62b3,
+        ];
+
+        values.fold(1, |chk, value| {
+            let top = chk >> 25;
+            let chk = ((chk & 0x1ff_ffff) << 5) ^ u32::from(value);
+            GENERATORS
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| (top >> i) & 1 == 1)
+                .fold(chk, |chk, (_, generator)| chk ^ generator)
+        })
+    }
+
+    fn hrp_expand(hrp: &str) -> impl Iterator<Item = u8> + '_ {
+        hrp.bytes()
+            .map(|c| c >> 5)
+            .chain(std::iter::once(0))
+            .chain(hrp.bytes().map(|c| c & 0x1f))
+    }
+
+    /// Encode `data` with the human-readable part `hrp`.
+    pub fn encode(hrp: &str, data: &[u5]) -> String {
+        let values = data.iter().map(|v| v.to_u8());
+        let checksum = polymod(
+            hrp_expand(hrp)
+                .chain(values.clone())
+                .chain(std::iter::repeat(0).take(CHECKSUM_LEN)),
+        ) ^ CHECKSUM_CONST;
+
+        let mut encoded = String::with_capacity(hrp.len() + 1 + data.len() + CHECKSUM_LEN);
+        encoded.push_str(hrp);
+        encoded.push('1');
+        encoded.extend(values.map(|v| CHARSET[v as usize] as char));
+        encoded.extend(
+            (0..CHECKSUM_LEN)
+                .map(|i| (checksum >> (5 * (CHECKSUM_LEN - 1 - i))) & 0x1f)
+                .map(|v| CHARSET[v as usize] as char),
+        );
+        encoded
+    }
+
+    /// Decode `s`, checking its checksum, and return its human-readable
+    /// part and data.
+    pub fn decode(s: &str) -> Result<(String, Vec<u5>), &'static str> {
+        if s.chars().any(|c| c.is_ascii_lowercase()) && s.chars().any(|c| c.is_ascii_uppercase()) {
+            return Err("bech32m string has mixed case");
+        }
+        let s = s.to_ascii_lowercase();
+
+        let separator = s.rfind('1').ok_or("bech32m string has no separator")?;
+        let (hrp, data) = (&s[..separator], &s[separator + 1..]);
+        if hrp.is_empty() || data.len() < CHECKSUM_LEN {
+            return Err("bech32m string is too short");
+        }
+        if hrp.bytes().any(|c| !(33..=126).contains(&c)) {
+            return Err("bech32m prefix has an invalid character");
+        }
+
+        let values = data
+            .bytes()
+            .map(|c| {
+                CHARSET
+                    .iter()
+                    .position(|&x| x == c)
+                    .map(|v| v as u8)
+                    .ok_or("bech32m data has an invalid character")
+            })
+            .collect::<Result<Vec<u8>, _>>()?;
+        if polymod(hrp_expand(hrp).chain(values.iter().copied())) != CHECKSUM_CONST {
+            return Err("bech32m checksum is invalid");
+        }
+
+        let data = values[..values.len() - CHECKSUM_LEN]
+            .iter()
+            .map(|&v| u5::try_from_u8(v).expect("charset positions are 5-bit values"))
+            .collect();
+        Ok((hrp.to_string(), data))
+    }
+}
+
+#[cfg(any(test, feature = "proptest-impl"))]
+impl Arbitrary for UnifiedAddress {
+    type Parameters = ();
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        (
+            any::<Network>(),
+            any::<Option<sapling::Address>>(),
+            vec(any::<u8>(), 43),
+            any::<Option<transparent::Address>>(),
+        )
+            .prop_map(|(network, sapling, orchard, transparent)| {
+                let mut orchard_bytes = [0; 43];
+                orchard_bytes.copy_from_slice(&orchard);
+
+                let mut receivers = vec![Receiver::Orchard(orchard_bytes)];
+                if let Some(sapling) = sapling {
+                    let sapling = sapling::Address::from_raw_bytes(network, sapling.to_raw_bytes())
+                        .expect("valid sapling addresses are valid on every network");
+                    receivers.push(Receiver::Sapling(sapling));
+                }
+                if let Some(transparent) = transparent {
+                    receivers.push(Receiver::Transparent(match transparent {
+                        transparent::Address::PayToScriptHash { script_hash, .. } => {
+                            transparent::Address::PayToScriptHash {
+                                network,
+                                script_hash,
+                            }
+                        }
+                        transparent::Address::PayToPublicKeyHash { pub_key_hash, .. } => {
+                            transparent::Address::PayToPublicKeyHash {
+                                network,
+                                pub_key_hash,
+                            }
+                        }
+                    }));
+                }
+
+                UnifiedAddress::new(network, receivers).expect("receivers follow the rules")
+            })
+            .boxed()
+    }
+
+    type Strategy = BoxedStrategy<Self>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p2pkh(network: Network) -> Receiver {
+        Receiver::Transparent(transparent::Address::PayToPublicKeyHash {
+            network,
+            pub_key_hash: [7; 20],
+        })
+    }
+
+    #[test]
+    fn receiver_rules() {
+        let network = Network::Mainnet;
+        let p2sh = Receiver::Transparent(transparent::Address::PayToScriptHash {
+            network,
+            script_hash: [8; 20],
+        });
+
+        assert!(matches!(
+            UnifiedAddress::new(network, vec![p2pkh(network)]),
+            Err(UnifiedAddressError::NoShieldedReceiver)
+        ));
+        assert!(matches!(
+            UnifiedAddress::new(
+                network,
+                vec![Receiver::Orchard([1; 43]), p2pkh(network), p2sh]
+            ),
+            Err(UnifiedAddressError::MultipleTransparent)
+        ));
+        assert!(matches!(
+            UnifiedAddress::new(
+                network,
+                vec![Receiver::Orchard([1; 43]), Receiver::Orchard([2; 43])]
+            ),
+            Err(UnifiedAddressError::DuplicateTypecode(typecodes::ORCHARD))
+        ));
+        assert!(matches!(
+            UnifiedAddress::new(
+                network,
+                vec![Receiver::Orchard([1; 43]), p2pkh(Network::Testnet)]
+            ),
+            Err(UnifiedAddressError::NetworkMismatch)
+        ));
+    }
+
+    #[test]
+    fn preferred_receiver() {
+        let network = Network::Testnet;
+        let unknown = Receiver::Unknown {
+            typecode: 0x7f,
+            data: vec![1, 2, 3],
+        };
+        let ua = UnifiedAddress::new(
+            network,
+            vec![unknown.clone(), p2pkh(network), Receiver::Orchard([1; 43])],
+        )
+        .unwrap();
+
+        assert_eq!(ua.preferred_receiver(), Some(&Receiver::Orchard([1; 43])));
+        assert_eq!(ua.receivers().first(), Some(&p2pkh(network)));
+        assert_eq!(ua.receivers().last(), Some(&unknown));
+
+        // Unknown receivers survive a round-trip.
+        let ua2: UnifiedAddress = ua.to_string().parse().unwrap();
+        assert_eq!(ua, ua2);
+    }
+
+    #[test]
+    fn encoding_prefix_and_checksum() {
+        let ua = UnifiedAddress::new(
+            Network::Mainnet,
+            vec![Receiver::Orchard([1; 43]), p2pkh(Network::Mainnet)],
+        )
+        .unwrap();
+        let encoded = ua.to_string();
+        assert!(encoded.starts_with("u1"));
+        assert!(encoded.len() > 90);
+
+        // Changing any character breaks the checksum.
+        let mut corrupted = encoded.into_bytes();
+        let last = corrupted.len() - 1;
+        corrupted[last] = if corrupted[last] == b'q' { b'p' } else { b'q' };
+        let corrupted = String::from_utf8(corrupted).unwrap();
+        assert!(corrupted.parse::<UnifiedAddress>().is_err());
+    }
+
+    #[test]
+    fn f4jumble_roundtrip() {
+        for len in &[48, 83, 128, 129, 1000] {
+            let message: Vec<u8> = (0..*len).map(|i| i as u8).collect();
+            let jumbled = f4jumble::jumble(&message).unwrap();
+            assert_ne!(jumbled, message);
+            assert_eq!(f4jumble::unjumble(&jumbled).unwrap(), message);
+        }
+        assert!(f4jumble::jumble(&[0; 47]).is_err());
+    }
+}
+
+#[cfg(test)]
+proptest! {
+    #[test]
+    fn unified_address_roundtrip(ua in any::<UnifiedAddress>()) {
+        let string = ua.to_string();
+
+        let ua2 = string.parse::<UnifiedAddress>()
+            .expect("randomized unified address should deserialize");
+
+        prop_assert_eq![ua, ua2];
+    }
+}
@@ -0,0 +1,359 @@
+//! Strongly-typed zatoshi amounts that prevent under/overflows.
+//!
+//! The [`Amount`] type is parameterized by a [`Constraint`] implementation
+//! that declares the range of allowed values. In contrast to regular
+//! arithmetic operations, which return values, arithmetic on [`Amount`]s
+//! returns [`Result`](std::result::Result)s.
+
+use std::{
+    convert::{TryFrom, TryInto},
+    fmt, io,
+    marker::PhantomData,
+    ops::RangeInclusive,
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+use crate::serialization::{SerializationError, ZcashDeserialize, ZcashSerialize};
+
+/// The number of zatoshis in 1 ZEC.
+pub const COIN: i64 = 100_000_000;
+
+/// The maximum zatoshi amount: 21 million ZEC.
+pub const MAX_MONEY: i64 = 21_000_000 * COIN;
+
+/// A runtime validated type for representing amounts of zatoshis.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Amount<C = NegativeAllowed>(i64, PhantomData<C>);
+
+/// A result type for [`Amount`] arithmetic and conversions.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl<C> fmt::Debug for Amount<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple(&format!("Amount<{}>", std::any::type_name::<C>()))
+            .field(&self.0)
+            .finish()
+    }
+}
+
+impl<C: Constraint> Amount<C> {
+    /// Convert this amount to a different `Amount` type, if it satisfies
+    /// the new constraint.
+    pub fn constrain<C2: Constraint>(self) -> Result<Amount<C2>> {
+        self.0.try_into()
+    }
+
+    /// The zero amount, which satisfies every constraint.
+    pub fn zero() -> Self {
+        Amount(0, PhantomData)
+    }
+
+    /// Add `rhs` to this amount, clamping the result to the constraint's
+    /// range instead of failing.
+    pub fn saturating_add(self, rhs: Amount<C>) -> Self {
+        let range = C::valid_range();
+        Amount(
+            self.0
+                .saturating_add(rhs.0)
+                .max(*range.start())
+                .min(*range.end()),
+            PhantomData,
+        )
+    }
+
+    /// Subtract `rhs` from this amount, clamping the result to the
+    /// constraint's range instead of failing.
+    pub fn saturating_sub(self, rhs: Amount<C>) -> Self {
+        let range = C::valid_range();
+        Amount(
+            self.0
+                .saturating_sub(rhs.0)
+                .max(*range.start())
+                .min(*range.end()),
+            PhantomData,
+        )
+    }
+}
+
+impl<C: Constraint> std::ops::Add<Amount<C>> for Amount<C> {
+    type Output = Result<Amount<C>>;
+
+    fn add(self, rhs: Amount<C>) -> Self::Output {
+        let value = self.0.checked_add(rhs.0).ok_or(Error::Overflow {
+            a: self.0,
+            b: rhs.0,
+        })?;
+        value.try_into()
+    }
+}
+
+impl<C: Constraint> std::ops::Add<Amount<C>> for Result<Amount<C>> {
+    type Output = Result<Amount<C>>;
+
+    fn add(self, rhs: Amount<C>) -> Self::Output {
+        self? + rhs
+    }
+}
+
+impl<C: Constraint> std::ops::Sub<Amount<C>> for Amount<C> {
+    type Output = Result<Amount<C>>;
+
+    fn sub(self, rhs: Amount<C>) -> Self::Output {
+        let value = self.0.checked_sub(rhs.0).ok_or(Error::Overflow {
+            a: self.0,
+            b: -rhs.0,
+        })?;
+        value.try_into()
+    }
+}
+
+impl<C: Constraint> std::ops::Sub<Amount<C>> for Result<Amount<C>> {
+    type Output = Result<Amount<C>>;
+
+    fn sub(self, rhs: Amount<C>) -> Self::Output {
+        self? - rhs
+    }
+}
+
+impl std::ops::Neg for Amount<NegativeAllowed> {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        // The valid range is symmetric, so this can't overflow.
+        Amount(-self.0, PhantomData)
+    }
+}
+
+impl<C: Constraint> std::iter::Sum<Amount<C>> for Result<Amount<C>> {
+    fn sum<I: Iterator<Item = Amount<C>>>(iter: I) -> Self {
+        iter.fold(Ok(Amount::zero()), |acc, amount| acc + amount)
+    }
+}
+
+impl<C> From<Amount<C>> for i64 {
+    fn from(amount: Amount<C>) -> Self {
+        amount.0
+    }
+}
+
+impl From<Amount<NonNegative>> for u64 {
+    fn from(amount: Amount<NonNegative>) -> Self {
+        amount.0 as _
+    }
+}
+
+impl<C: Constraint> TryFrom<i64> for Amount<C> {
+    type Error = Error;
+
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        C::validate(value).map(|v| Self(v, PhantomData))
+    }
+}
+
+impl<C: Constraint> TryFrom<u64> for Amount<C> {
+    type Error = Error;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        let value = i64::try_from(value).map_err(|_| Error::Convert { value })?;
+        value.try_into()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<C> Serialize for Amount<C> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, C: Constraint> Deserialize<'de> for Amount<C> {
+    /// Deserialize a zatoshi amount, checking that it satisfies `C`.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        i64::deserialize(deserializer)?
+            .try_into()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Errors that can be returned when validating `Amount`s.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The input value is outside the valid range for this constraint.
+    #[error("input {value} is outside of valid range for zatoshi Amount, valid_range={range:?}")]
+    Contains {
+        /// The invalid value.
+        value: i64,
+        /// The range that the value must be in.
+        range: RangeInclusive<i64>,
+    },
+    /// The input value is too large to be an `i64`.
+    #[error("u64 {value} could not be converted to an i64 Amount")]
+    Convert {
+        /// The invalid value.
+        value: u64,
+    },
+    /// The arithmetic operation overflowed an `i64`.
+    #[error("i64 overflow when adding {a} and {b}")]
+    Overflow {
+        /// The left operand.
+        a: i64,
+        /// The right operand, negated for subtractions.
+        b: i64,
+    },
+}
+
+/// Marker type for `Amount` that allows negative values.
+///
+/// ```
+/// # use zebra_chain::amount::{Constraint, MAX_MONEY, NegativeAllowed};
+/// use std::ops::RangeInclusive;
+///
+/// assert_eq!(
+///     NegativeAllowed::valid_range(),
+///     RangeInclusive::new(-MAX_MONEY, MAX_MONEY)
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct NegativeAllowed;
+
+impl Constraint for NegativeAllowed {
+    fn valid_range() -> RangeInclusive<i64> {
+        -MAX_MONEY..=MAX_MONEY
+    }
+}
+
+/// Marker type for `Amount` that requires non-negative values.
+///
+/// ```
+/// # use zebra_chain::amount::{Constraint, MAX_MONEY, NonNegative};
+/// use std::ops::RangeInclusive;
+///
+/// assert_eq!(
+///     NonNegative::valid_range(),
+///     RangeInclusive::new(0, MAX_MONEY)
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct NonNegative;
+
+impl Constraint for NonNegative {
+    fn valid_range() -> RangeInclusive<i64> {
+        0..=MAX_MONEY
+    }
+}
+
+/// The allowed range of an `Amount`.
+pub trait Constraint {
+    /// Returns the range of values that are valid under this constraint.
+    fn valid_range() -> RangeInclusive<i64>;
+
+    /// Check that `value` is in the valid range.
+    fn validate(value: i64) -> Result<i64, Error> {
+        let range = Self::valid_range();
+
+        if !range.contains(&value) {
+            Err(Error::Contains { value, range })
+        } else {
+            Ok(value)
+        }
+    }
+}
+
+impl ZcashSerialize for Amount<NegativeAllowed> {
+    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        writer.write_i64::<LittleEndian>(self.0)
+    }
+}
+
+impl ZcashDeserialize for Amount<NegativeAllowed> {
+    fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
+        reader
+            .read_i64::<LittleEndian>()?
+            .try_into()
+            .map_err(|_| SerializationError::Parse("amount is outside the valid range"))
+    }
+}
+
+impl ZcashSerialize for Amount<NonNegative> {
+    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        writer.write_u64::<LittleEndian>(u64::from(*self))
+    }
+}
+
+impl ZcashDeserialize for Amount<NonNegative> {
+    fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
+        reader
+            .read_u64::<LittleEndian>()?
+            .try_into()
+            .map_err(|_| SerializationError::Parse("amount is outside the valid range"))
+    }
+}
+
+#[cfg(any(test, feature = "proptest-impl"))]
+mod arbitrary {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    impl<C: Constraint + 'static> Arbitrary for Amount<C> {
+        type Parameters = ();
+
+        fn arbitrary_with(_args: ()) -> Self::Strategy {
+            C::valid_range()
+                .prop_map(|v| Amount(v, PhantomData))
+                .boxed()
+        }
+
+        type Strategy = BoxedStrategy<Self>;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_and_sub_check_the_range() {
+        let one = Amount::<NonNegative>::try_from(1i64).unwrap();
+        let max = Amount::<NonNegative>::try_from(MAX_MONEY).unwrap();
+
+        assert!((max + one).is_err());
+        assert!((Amount::zero() - one).is_err());
+        assert_eq!((one + one).map(i64::from), Ok(2));
+        assert_eq!(max.saturating_add(one), max);
+        assert_eq!(Amount::zero().saturating_sub(one), Amount::zero());
+
+        let negative = Amount::<NegativeAllowed>::try_from(-1i64).unwrap();
+        assert!(negative.constrain::<NonNegative>().is_err());
+        assert_eq!(-negative, one.constrain().unwrap());
+        assert!(Amount::<NonNegative>::try_from(u64::max_value()).is_err());
+    }
+
+    #[test]
+    fn sum_fails_on_overflow() {
+        let max = Amount::<NonNegative>::try_from(MAX_MONEY).unwrap();
+        let sum: Result<Amount<NonNegative>> = vec![max, Amount::zero()].into_iter().sum();
+        assert_eq!(sum, Ok(max));
+        let sum: Result<Amount<NonNegative>> = vec![max, max].into_iter().sum();
+        assert!(sum.is_err());
+    }
+
+    #[test]
+    fn out_of_range_amounts_fail_to_deserialize() {
+        let bytes = (MAX_MONEY + 1).to_le_bytes();
+        assert!(Amount::<NonNegative>::zcash_deserialize(&bytes[..]).is_err());
+        assert!(Amount::<NegativeAllowed>::zcash_deserialize(&bytes[..]).is_err());
+
+        let bytes = (-1i64).to_le_bytes();
+        assert!(Amount::<NonNegative>::zcash_deserialize(&bytes[..]).is_err());
+        assert_eq!(
+            i64::from(Amount::<NegativeAllowed>::zcash_deserialize(&bytes[..]).unwrap()),
+            -1
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use chrono::{TimeZone, Utc};
+use proptest::{arbitrary::any, prelude::*};
+
+use crate::{
+    sapling,
+    work::{difficulty::CompactDifficulty, equihash},
+};
+
+use super::*;
+
+impl Arbitrary for Header {
+    type Parameters = ();
+
+    fn arbitrary_with(_args: ()) -> Self::Strategy {
+        (
+            (4u32..2_147_483_647u32),
+            any::<Hash>(),
+            any::<merkle::Root>(),
+            any::<sapling::tree::Root>(),
+            (0i64..4_294_967_296i64),
+            any::<CompactDifficulty>(),
+            any::<[u8; 32]>(),
+            any::<equihash::Solution>(),
+        )
+            .prop_map(
+                |(
+                    version,
+                    previous_block_hash,
+                    merkle_root,
+                    final_sapling_root_hash,
+                    timestamp,
+                    bits,
+                    nonce,
+                    solution,
+                )| Header {
+                    version,
+                    previous_block_hash,
+                    merkle_root,
+                    final_sapling_root_hash,
+                    time: Utc.timestamp(timestamp, 0),
+                    bits,
+                    nonce,
+                    solution,
+                },
+            )
+            .boxed()
+    }
+
+    type Strategy = BoxedStrategy<Self>;
+}
@@ -0,0 +1,222 @@
+//! The block commitment field in the block header.
+//!
+//! The header has a 32-byte field that commits to different data depending
+//! on the network upgrade:
+//! - before Sapling, it is reserved, and can contain any value,
+//! - from Sapling, it is `hashFinalSaplingRoot`, the root of the Sapling note
+//!   commitment tree after the block,
+//! - in the Heartwood activation block, it is reserved, and must be all
+//!   zeroes,
+//! - after Heartwood activation, it is `hashLightClientRoot`, the root of the
+//!   chain history Merkle mountain range before the block,
+//! - from NU5, it is `hashBlockCommitments`, which combines the chain history
+//!   root with a commitment to the block's authorizing data, as defined in
+//!   [ZIP-244](https://zips.z.cash/zip-0244).
+
+use std::fmt;
+
+use blake2b_simd::Params;
+use thiserror::Error;
+
+use crate::{
+    network_upgrade::NetworkUpgrade, sapling, serialization::reversed_hex, transaction::AuthDigest,
+    Network,
+};
+
+use super::{Block, Height};
+
+/// The personalization for the nodes of the authorizing data tree.
+const AUTH_DATA_PERSONALIZATION: &[u8; 16] = b"ZcashAuthDatHash";
+
+/// The personalization for `hashBlockCommitments`.
+const BLOCK_COMMITMENTS_PERSONALIZATION: &[u8; 16] = b"ZcashBlockCommit";
+
+/// The value of the block commitment field, interpreted according to the
+/// network upgrade at the block's height.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Commitment {
+    /// Before Sapling, the field is reserved, and isn't checked.
+    PreSaplingReserved([u8; 32]),
+    /// From Sapling, and before Heartwood activation, the root of the Sapling
+    /// note commitment tree after this block.
+    FinalSaplingRoot(sapling::tree::Root),
+    /// The Heartwood activation block has all zeroes in this field.
+    ChainHistoryActivationReserved,
+    /// After Heartwood activation, and before NU5, the root of the chain
+    /// history tree for the blocks before this block.
+    ChainHistoryRoot(ChainHistoryMmrRootHash),
+    /// From NU5, a commitment to the chain history root and the
+    /// authorizing data of this block's transactions.
+    ChainHistoryBlockTxAuthCommitment(ChainHistoryBlockTxAuthCommitmentHash),
+}
+
+/// An error interpreting the block commitment field.
+#[derive(Error, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CommitmentError {
+    /// The Heartwood activation block's commitment field isn't all zeroes.
+    #[error("the Heartwood activation block commitment is not all zeroes: {actual:?}")]
+    InvalidChainHistoryActivationReserved {
+        /// The bytes in the commitment field.
+        actual: [u8; 32],
+    },
+    /// The block doesn't have a coinbase height, so the network upgrade is
+    /// unknown.
+    #[error("the block has no coinbase height")]
+    MissingBlockHeight,
+}
+
+impl Commitment {
+    /// Interpret the commitment field `bytes` of a block at `height`.
+    pub fn from_bytes(
+        bytes: [u8; 32],
+        network: Network,
+        height: Height,
+    ) -> Result<Commitment, CommitmentError> {
+        use Commitment::*;
+        use NetworkUpgrade::*;
+
+        let upgrade = NetworkUpgrade::current(network, height);
+        if Heartwood.activation_height(network) == Some(height) {
+            return if bytes == [0; 32] {
+                Ok(ChainHistoryActivationReserved)
+            } else {
+                Err(CommitmentError::InvalidChainHistoryActivationReserved { actual: bytes })
+            };
+        }
+
+        Ok(match upgrade {
+            Genesis | BeforeOverwinter | Overwinter => PreSaplingReserved(bytes),
+            Sapling | Blossom => FinalSaplingRoot(sapling::tree::Root(bytes)),
+            Heartwood | Canopy => ChainHistoryRoot(ChainHistoryMmrRootHash(bytes)),
+            Nu5 => ChainHistoryBlockTxAuthCommitment(ChainHistoryBlockTxAuthCommitmentHash(bytes)),
+        })
+    }
+
+    /// Returns the bytes of the commitment field for this commitment.
+    pub fn to_bytes(self) -> [u8; 32] {
+        use Commitment::*;
+
+        match self {
+            PreSaplingReserved(bytes) => bytes,
+            FinalSaplingRoot(root) => root.0,
+            ChainHistoryActivationReserved => [0; 32],
+            ChainHistoryRoot(hash) => hash.0,
+            ChainHistoryBlockTxAuthCommitment(hash) => hash.0,
+        }
+    }
+}
+
+impl Block {
+    /// Returns this block's commitment field, interpreted according to the
+    /// network upgrade at its coinbase height.
+    pub fn commitment(&self, network: Network) -> Result<Commitment, CommitmentError> {
+        let height = self
+            .coinbase_height()
+            .ok_or(CommitmentError::MissingBlockHeight)?;
+        Commitment::from_bytes(self.header.final_sapling_root_hash.0, network, height)
+    }
+
+    /// Returns the root of this block's authorizing data tree, as defined in
+    /// ZIP-244.
+    pub fn auth_data_root(&self) -> AuthDataRoot {
+        self.transactions
+            .iter()
+            .map(|tx| tx.auth_digest())
+            .collect()
+    }
+}
+
+/// The root of the chain history Merkle mountain range.
+#[derive(Clone, Copy, Eq, PartialEq, Hash)]
+pub struct ChainHistoryMmrRootHash(pub [u8; 32]);
+
+impl fmt::Debug for ChainHistoryMmrRootHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("ChainHistoryMmrRootHash")
+            .field(&reversed_hex::encode(&self.0))
+            .finish()
+    }
+}
+
+/// The root of a Merkle tree of the [`AuthDigest`]s of a block's
+/// transactions.
+///
+/// The leaves are padded to a power of two with all-zero leaves.
+#[derive(Clone, Copy, Eq, PartialEq, Hash)]
+pub struct AuthDataRoot(pub [u8; 32]);
+
+impl fmt::Debug for AuthDataRoot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("AuthDataRoot")
+            .field(&reversed_hex::encode(&self.0))
+            .finish()
+    }
+}
+
+impl std::iter::FromIterator<AuthDigest> for AuthDataRoot {
+    fn from_iter<I>(digests: I) -> Self
+    where
+        I: IntoIterator<Item = AuthDigest>,
+    {
+        let mut nodes: Vec<[u8; 32]> = digests.into_iter().map(|digest| digest.0).collect();
+        nodes.resize(nodes.len().next_power_of_two(), [0; 32]);
+
+        while nodes.len() > 1 {
+            nodes = nodes
+                .chunks(2)
+                .map(|pair| hash_pair(AUTH_DATA_PERSONALIZATION, &pair[0], &pair[1]))
+                .collect();
+        }
+
+        AuthDataRoot(nodes.pop().unwrap_or([0; 32]))
+    }
+}
+
+/// The `hashBlockCommitments` field of NU5 blocks.
+#[derive(Clone, Copy, Eq, PartialEq, Hash)]
+pub struct ChainHistoryBlockTxAuthCommitmentHash(pub [u8; 32]);
+
+impl ChainHistoryBlockTxAuthCommitmentHash {
+    /// Combine the chain history root before a block, and the block's
+    /// authorizing data root.
+    pub fn from_commitments(
+        history_root: &ChainHistoryMmrRootHash,
+        auth_data_root: &AuthDataRoot,
+    ) -> Self {
+        let hash = Params::new()
+            .hash_length(32)
+            .personal(BLOCK_COMMITMENTS_PERSONALIZATION)
+            .to_state()
+            .update(&history_root.0)
+            .update(&auth_data_root.0)
+            .update(&[0; 32])
+            .finalize();
+
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(hash.as_bytes());
+        ChainHistoryBlockTxAuthCommitmentHash(bytes)
+    }
+}
+
+impl fmt::Debug for ChainHistoryBlockTxAuthCommitmentHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("ChainHistoryBlockTxAuthCommitmentHash")
+            .field(&reversed_hex::encode(&self.0))
+            .finish()
+    }
+}
+
+/// Returns the BLAKE2b-256 hash of `left || right`, with `personal`.
+fn hash_pair(personal: &[u8; 16], left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let hash = Params::new()
+        .hash_length(32)
+        .personal(personal)
+        .to_state()
+        .update(left)
+        .update(right)
+        .finalize();
+
+    let mut bytes = [0; 32];
+    bytes.copy_from_slice(hash.as_bytes());
+    bytes
+}
@@ -0,0 +1,329 @@
+//! Compact block filters, as defined in [BIP 158].
+//!
+//! A compact block filter is a probabilistic set of the transparent scripts
+//! created and spent by a block, which lets light clients check whether a
+//! block is relevant to their wallet without downloading it.
+//!
+//! Zcash only has transparent scripts, so shielded transfers never match a
+//! filter.
+//!
+//! [BIP 158]: https://github.com/bitcoin/bips/blob/master/bip-0158.mediawiki
+#![allow(clippy::unit_arg)]
+
+use std::{
+    collections::BTreeSet,
+    fmt,
+    hash::Hasher,
+    io::{self, Cursor},
+};
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest_derive::Arbitrary;
+
+use crate::{
+    serialization::{
+        reversed_hex, ReadZcashExt, SerializationError, TrustedPreallocate, WriteZcashExt,
+        ZcashDeserialize, ZcashSerialize, MAX_PROTOCOL_MESSAGE_LEN,
+    },
+    sha256d_writer::Sha256dWriter,
+    transparent::Script,
+};
+
+use super::{Block, Hash};
+
+/// The filter type byte for BIP 158 basic filters.
+pub const BASIC_FILTER_TYPE: u8 = 0x00;
+
+/// The Golomb-Rice coding parameter for basic filters.
+const BASIC_FILTER_P: u8 = 19;
+
+/// The inverse false positive rate for basic filters.
+const BASIC_FILTER_M: u64 = 784_931;
+
+/// The `OP_RETURN` opcode, which marks provably unspendable outputs.
+const OP_RETURN: u8 = 0x6a;
+
+/// An encoded BIP 158 basic filter.
+///
+/// The encoding is the number of items as a `CompactSize`, followed by the
+/// Golomb-Rice coded set of item hashes.
+#[derive(Clone, Eq, PartialEq, ZcashSerialize, ZcashDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BlockFilter(
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::serde_hex"))]
+    #[zcash(length_prefixed)]
+    pub Vec<u8>,
+);
+
+impl fmt::Debug for BlockFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("BlockFilter")
+            .field(&hex::encode(&self.0))
+            .finish()
+    }
+}
+
+impl BlockFilter {
+    /// Build the basic filter for `block`.
+    ///
+    /// `spent_scripts` are the output scripts of the previous outputs spent
+    /// by the block's transparent inputs. They aren't part of the block, so
+    /// the caller must look them up in the UTXO set.
+    pub fn basic<'a>(block: &Block, spent_scripts: impl IntoIterator<Item = &'a Script>) -> Self {
+        let created = block
+            .transactions
+            .iter()
+            .flat_map(|transaction| transaction.outputs())
+            .map(|output| &output.pk_script.0[..])
+            .filter(|script| script.first() != Some(&OP_RETURN));
+        let spent = spent_scripts.into_iter().map(|script| &script.0[..]);
+
+        let items: BTreeSet<&[u8]> = created
+            .chain(spent)
+            .filter(|script| !script.is_empty())
+            .collect();
+
+        let hash = Hash::from(block);
+        let range = items.len() as u64 * BASIC_FILTER_M;
+        let mut values: Vec<u64> = items
+            .iter()
+            .map(|item| hash_to_range(&hash, range, item))
+            .collect();
+        values.sort_unstable();
+
+        let mut bytes = Vec::new();
+        bytes
+            .write_compactsize(values.len() as u64)
+            .expect("writing to a Vec never fails");
+        let mut writer = BitWriter::new(bytes);
+        let mut last = 0;
+        for value in values {
+            writer.write_golomb_rice(value - last, BASIC_FILTER_P);
+            last = value;
+        }
+
+        BlockFilter(writer.finish())
+    }
+
+    /// Returns true if `item` may be in this filter, which was built for the
+    /// block with hash `block_hash`.
+    ///
+    /// False positives happen with probability `1/784931`, but there are no
+    /// false negatives.
+    pub fn contains(&self, block_hash: &Hash, item: &[u8]) -> Result<bool, SerializationError> {
+        let mut reader = Cursor::new(&self.0[..]);
+        let count = reader.read_compactsize()?;
+        let range = count
+            .checked_mul(BASIC_FILTER_M)
+            .ok_or(SerializationError::Parse("filter item count is too large"))?;
+        let target = hash_to_range(block_hash, range, item);
+
+        let mut reader = BitReader::new(&self.0[reader.position() as usize..]);
+        let mut value: u64 = 0;
+        for _ in 0..count {
+            value = value
+                .checked_add(reader.read_golomb_rice(BASIC_FILTER_P)?)
+                .ok_or(SerializationError::Parse("filter value is too large"))?;
+            if value == target {
+                return Ok(true);
+            }
+            if value > target {
+                return Ok(false);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Returns the hash of this filter, which is committed to by the
+    /// filter header chain.
+    pub fn hash(&self) -> FilterHash {
+        let mut hash_writer = Sha256dWriter::default();
+        io::Write::write_all(&mut hash_writer, &self.0).expect("Sha256dWriter is infallible");
+        FilterHash(hash_writer.finish())
+    }
+}
+
+/// A SHA-256d hash of an encoded [`BlockFilter`].
+///
+/// As in BIP 157, filter hashes and headers are displayed in reversed byte
+/// order, like block hashes.
+#[derive(Copy, Clone, Eq, PartialEq, ZcashSerialize, ZcashDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
+pub struct FilterHash(
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::reversed_hex"))]
+    #[zcash(fixed)]
+    pub [u8; 32],
+);
+
+impl fmt::Display for FilterHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&reversed_hex::encode(&self.0))
+    }
+}
+
+impl fmt::Debug for FilterHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("FilterHash")
+            .field(&reversed_hex::encode(&self.0))
+            .finish()
+    }
+}
+
+impl TrustedPreallocate for FilterHash {
+    fn max_allocation() -> u64 {
+        (MAX_PROTOCOL_MESSAGE_LEN / 32) as u64
+    }
+}
+
+impl std::str::FromStr for FilterHash {
+    type Err = SerializationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        reversed_hex::decode(s).map(FilterHash)
+    }
+}
+
+/// A commitment to a block's filter and all the filters before it.
+///
+/// Each header is the SHA-256d hash of the block's [`FilterHash`] followed by
+/// the previous block's filter header. The genesis block's previous header
+/// is all zeroes.
+#[derive(Copy, Clone, Default, Eq, PartialEq, ZcashSerialize, ZcashDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
+pub struct FilterHeader(
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::reversed_hex"))]
+    #[zcash(fixed)]
+    pub [u8; 32],
+);
+
+impl fmt::Display for FilterHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&reversed_hex::encode(&self.0))
+    }
+}
+
+impl fmt::Debug for FilterHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("FilterHeader")
+            .field(&reversed_hex::encode(&self.0))
+            .finish()
+    }
+}
+
+impl TrustedPreallocate for FilterHeader {
+    fn max_allocation() -> u64 {
+        (MAX_PROTOCOL_MESSAGE_LEN / 32) as u64
+    }
+}
+
+impl std::str::FromStr for FilterHeader {
+    type Err = SerializationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        reversed_hex::decode(s).map(FilterHeader)
+    }
+}
+
+impl FilterHeader {
+    /// Returns the filter header for the block following this one, given the
+    /// hash of that block's filter.
+    pub fn next(&self, filter_hash: FilterHash) -> FilterHeader {
+        let mut hash_writer = Sha256dWriter::default();
+        io::Write::write_all(&mut hash_writer, &filter_hash.0)
+            .and_then(|()| io::Write::write_all(&mut hash_writer, &self.0))
+            .expect("Sha256dWriter is infallible");
+        FilterHeader(hash_writer.finish())
+    }
+}
+
+/// Map `item` uniformly onto `[0, range)`, using SipHash-2-4 keyed by the
+/// first 16 bytes of `block_hash`.
+fn hash_to_range(block_hash: &Hash, range: u64, item: &[u8]) -> u64 {
+    let mut k0 = [0; 8];
+    let mut k1 = [0; 8];
+    k0.copy_from_slice(&block_hash.0[0..8]);
+    k1.copy_from_slice(&block_hash.0[8..16]);
+
+    // `SipHasher` is deprecated because its algorithm might change, but it is
+    // still SipHash-2-4, which is what BIP 158 specifies.
+    #[allow(deprecated)]
+    let mut hasher =
+        std::hash::SipHasher::new_with_keys(u64::from_le_bytes(k0), u64::from_le_bytes(k1));
+    hasher.write(item);
+
+    ((u128::from(hasher.finish()) * u128::from(range)) >> 64) as u64
+}
+
+/// Writes bits into a byte vector, most significant bit first.
+struct BitWriter {
+    bytes: Vec<u8>,
+    /// The number of bits used in the last byte, or 0 if it is full.
+    used: u8,
+}
+
+impl BitWriter {
+    fn new(bytes: Vec<u8>) -> Self {
+        BitWriter { bytes, used: 0 }
+    }
+
+    fn write_bit(&mut self, bit: bool) {
+        if self.used == 0 {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().expect("just pushed a byte") |= 0x80 >> self.used;
+        }
+        self.used = (self.used + 1) % 8;
+    }
+
+    /// Write `value` as a unary quotient and a `p`-bit remainder.
+    fn write_golomb_rice(&mut self, value: u64, p: u8) {
+        for _ in 0..(value >> p) {
+            self.write_bit(true);
+        }
+        self.write_bit(false);
+        for i in (0..p).rev() {
+            self.write_bit((value >> i) & 1 == 1);
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reads bits from a byte slice, most significant bit first.
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        BitReader { bytes, position: 0 }
+    }
+
+    fn read_bit(&mut self) -> Result<bool, SerializationError> {
+        let byte = self
+            .bytes
+            .get(self.position / 8)
+            .ok_or(SerializationError::Parse("filter ended unexpectedly"))?;
+        let bit = byte & (0x80 >> (self.position % 8)) != 0;
+        self.position += 1;
+        Ok(bit)
+    }
+
+    fn read_golomb_rice(&mut self, p: u8) -> Result<u64, SerializationError> {
+        let mut quotient = 0u64;
+        while self.read_bit()? {
+            quotient += 1;
+        }
+        let mut remainder = 0u64;
+        for _ in 0..p {
+            remainder = (remainder << 1) | u64::from(self.read_bit()?);
+        }
+        Ok((quotient << p) | remainder)
+    }
+}
@@ -0,0 +1,85 @@
+#![allow(clippy::unit_arg)]
+use std::fmt;
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest_derive::Arbitrary;
+
+use crate::{
+    serialization::{
+        reversed_hex, SerializationError, TrustedPreallocate, ZcashDeserialize, ZcashSerialize,
+        MAX_PROTOCOL_MESSAGE_LEN,
+    },
+    sha256d_writer::Sha256dWriter,
+};
+
+use super::{Block, Header};
+
+/// A SHA-256d hash of a block [`Header`].
+///
+/// This is useful when one block header is pointing to its parent
+/// block header in the block chain. ⛓️
+///
+/// This is usually called a 'block hash', as it is frequently used
+/// to identify the entire block, since the hash preimage includes
+/// the merkle root of the transactions in this block. But
+/// _technically_, this is just a hash of the block _header_, not
+/// the direct bytes of the transactions as well as the header.
+///
+/// The inner bytes are in internal (serialized) byte order. Like
+/// `zcashd`, the `Display`, `Debug`, and `FromStr` impls use the
+/// reversed byte order shown by block explorers and RPC methods. Serde uses
+/// the same hex string in human-readable formats.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, ZcashSerialize, ZcashDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
+pub struct Hash(
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::reversed_hex"))]
+    #[zcash(fixed)]
+    pub [u8; 32],
+);
+
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&reversed_hex::encode(&self.0))
+    }
+}
+
+impl fmt::Debug for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("block::Hash")
+            .field(&reversed_hex::encode(&self.0))
+            .finish()
+    }
+}
+
+impl<'a> From<&'a Header> for Hash {
+    fn from(block_header: &'a Header) -> Self {
+        let mut hash_writer = Sha256dWriter::default();
+        block_header
+            .zcash_serialize(&mut hash_writer)
+            .expect("Sha256dWriter is infallible, and header times are in the DateTime32 range");
+        Self(hash_writer.finish())
+    }
+}
+
+impl<'a> From<&'a Block> for Hash {
+    fn from(block: &'a Block) -> Hash {
+        (&block.header).into()
+    }
+}
+
+/// Block locators and inventory messages are lists of hashes.
+impl TrustedPreallocate for Hash {
+    fn max_allocation() -> u64 {
+        (MAX_PROTOCOL_MESSAGE_LEN / 32) as u64
+    }
+}
+
+impl std::str::FromStr for Hash {
+    type Err = SerializationError;
+
+    /// Parse a hash in the reversed byte order shown by block explorers.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        reversed_hex::decode(s).map(Hash)
+    }
+}
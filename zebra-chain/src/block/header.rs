@@ -0,0 +1,170 @@
+use std::{convert::TryFrom, io};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use chrono::{DateTime, Utc};
+
+use crate::{
+    sapling,
+    serialization::{
+        DateTime32, ReadZcashExt, SerializationError, TrustedPreallocate, ZcashDeserialize,
+        ZcashSerialize, MAX_PROTOCOL_MESSAGE_LEN,
+    },
+    work::{difficulty::CompactDifficulty, equihash},
+};
+
+use super::{merkle, Hash};
+
+/// Block header.
+///
+/// How are blocks chained together? They are chained together via the
+/// backwards reference (previous header hash) present in the block
+/// header. Each block points backwards to its parent, all the way
+/// back to the genesis block (the first block in the blockchain).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Header {
+    /// The block's version field. This is supposed to be `4`:
+    ///
+    /// > The current and only defined block version number for Zcash is 4.
+    ///
+    /// but this was not enforced by the consensus rules, and defective mining
+    /// software created blocks with other versions, so instead it's effectively
+    /// a free field. The only constraint is that it must be at least `4` when
+    /// interpreted as an `i32`.
+    pub version: u32,
+
+    /// A SHA-256d hash in internal byte order of the previous block’s
+    /// header. This ensures no previous block can be changed without
+    /// also changing this block’s header.
+    pub previous_block_hash: Hash,
+
+    /// A SHA-256d hash in internal byte order. The merkle root is
+    /// derived from the SHA256d hashes of all transactions included
+    /// in this block as assembled in a binary tree, ensuring that
+    /// none of those transactions can be modied without modifying the
+    /// header.
+    pub merkle_root: merkle::Root,
+
+    /// [Sapling onward] The root LEBS2OSP256(rt) of the Sapling note
+    /// commitment tree corresponding to the final Sapling treestate of
+    /// this block.
+    pub final_sapling_root_hash: sapling::tree::Root,
+
+    /// The block timestamp is a Unix epoch time (UTC) when the miner
+    /// started hashing the header (according to the miner).
+    ///
+    /// This is serialized as a [`DateTime32`], so it must be between the
+    /// UNIX epoch and the year 2106.
+    pub time: DateTime<Utc>,
+
+    /// An encoded version of the target threshold this block’s header
+    /// hash must be less than or equal to, in the same nBits format
+    /// used by Bitcoin.
+    ///
+    /// For a block at block height height, bits MUST be equal to
+    /// ThresholdBits(height).
+    ///
+    /// [Bitcoin-nBits](https://bitcoin.org/en/developer-reference#target-nbits)
+    pub bits: CompactDifficulty,
+
+    /// An arbitrary field that miners can change to modify the header
+    /// hash in order to produce a hash less than or equal to the
+    /// target threshold.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::serde_hex::bytes32")
+    )]
+    pub nonce: [u8; 32],
+
+    /// The Equihash solution.
+    pub solution: equihash::Solution,
+}
+
+impl Header {
+    /// Compute the hash of this header, which identifies the block.
+    ///
+    /// The hash covers every header field, including the Equihash solution.
+    pub fn hash(&self) -> Hash {
+        Hash::from(self)
+    }
+}
+
+/// The size of a serialized mainnet or testnet header, in bytes.
+///
+/// The Equihash solution is prefixed by its 3-byte `CompactSize` length.
+/// Regtest headers are smaller, but peers send at most 160 headers in each
+/// message, which is well under this limit.
+const HEADER_BYTES: usize = 4 + 32 + 32 + 32 + 4 + 4 + 32 + 3 + equihash::SOLUTION_SIZE;
+
+/// Headers messages are lists of headers.
+impl TrustedPreallocate for Header {
+    fn max_allocation() -> u64 {
+        (MAX_PROTOCOL_MESSAGE_LEN / HEADER_BYTES) as u64
+    }
+}
+
+impl ZcashSerialize for Header {
+    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        writer.write_u32::<LittleEndian>(self.version)?;
+        self.previous_block_hash.zcash_serialize(&mut writer)?;
+        writer.write_all(&self.merkle_root.0[..])?;
+        writer.write_all(&self.final_sapling_root_hash.0[..])?;
+        DateTime32::try_from(self.time)
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "header time is outside the DateTime32 range",
+                )
+            })?
+            .zcash_serialize(&mut writer)?;
+        writer.write_u32::<LittleEndian>(self.bits.0)?;
+        writer.write_all(&self.nonce[..])?;
+        self.solution.zcash_serialize(&mut writer)?;
+        Ok(())
+    }
+}
+
+impl ZcashDeserialize for Header {
+    fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
+        // The Zcash specification says that
+        // "The current and only defined block version number for Zcash is 4."
+        // but this is not actually part of the consensus rules, and in fact
+        // broken mining software created blocks that do not have version 4.
+        // There are approximately 4,000 blocks with version 536870912; this
+        // is the bit-reversal of the value 4, indicating that that mining pool
+        // reversed bit-ordering of the version field. Because the version field
+        // was not properly validated, these blocks were added to the chain.
+        //
+        // The only possible way to work around this is to do a similar hack
+        // as the overwintered field in transaction parsing, which we do here:
+        // treat the high bit (which zcashd interprets as a sign bit) as an
+        // indicator that the version field is meaningful.
+        //
+        //
+        let (version, future_version_flag) = {
+            const LOW_31_BITS: u32 = (1 << 31) - 1;
+            let raw_version = reader.read_u32::<LittleEndian>()?;
+            (raw_version & LOW_31_BITS, raw_version >> 31 != 0)
+        };
+
+        if future_version_flag {
+            return Err(SerializationError::Parse(
+                "high bit was set in version field",
+            ));
+        }
+        if version < 4 {
+            return Err(SerializationError::Parse("version must be at least 4"));
+        }
+
+        Ok(Header {
+            version,
+            previous_block_hash: Hash::zcash_deserialize(&mut reader)?,
+            merkle_root: merkle::Root(reader.read_32_bytes()?),
+            final_sapling_root_hash: sapling::tree::Root(reader.read_32_bytes()?),
+            time: DateTime32::zcash_deserialize(&mut reader)?.to_chrono(),
+            bits: CompactDifficulty(reader.read_u32::<LittleEndian>()?),
+            nonce: reader.read_32_bytes()?,
+            solution: equihash::Solution::zcash_deserialize(reader)?,
+        })
+    }
+}
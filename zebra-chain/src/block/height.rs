@@ -0,0 +1,114 @@
+//! Block heights.
+
+use std::convert::TryFrom;
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest::prelude::*;
+
+/// The height of a block, which is the length of the chain from the genesis
+/// block to that block.
+///
+/// The genesis block has height 0.
+///
+/// # Invariants
+///
+/// Heights are at most [`Height::MAX`]. Heights that are used in lock times
+/// must also be less than `500_000_000`, because larger values are
+/// interpreted as timestamps.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Height(pub u32);
+
+impl Height {
+    /// The height of the genesis block.
+    pub const MIN: Height = Height(0);
+
+    /// The largest block height.
+    ///
+    /// `zcashd` stores heights as signed 32-bit integers, so larger heights
+    /// can't be represented.
+    pub const MAX: Height = Height(i32::MAX as u32);
+
+    /// Returns `self + delta`, or `None` if the result is greater than
+    /// [`Height::MAX`].
+    pub fn checked_add(self, delta: u32) -> Option<Height> {
+        let height = self.0.checked_add(delta)?;
+        if height <= Height::MAX.0 {
+            Some(Height(height))
+        } else {
+            None
+        }
+    }
+
+    /// Returns `self - delta`, or `None` if the result would be below the
+    /// genesis block.
+    pub fn checked_sub(self, delta: u32) -> Option<Height> {
+        self.0.checked_sub(delta).map(Height)
+    }
+
+    /// Returns the height of the next block, or `None` if this is
+    /// [`Height::MAX`].
+    pub fn next(self) -> Option<Height> {
+        self.checked_add(1)
+    }
+
+    /// Returns the height of the previous block, or `None` if this is the
+    /// genesis block.
+    pub fn previous(self) -> Option<Height> {
+        self.checked_sub(1)
+    }
+
+    /// Returns the number of blocks from `earlier` to `self`, or `None` if
+    /// `earlier` is greater than `self`.
+    pub fn distance_since(self, earlier: Height) -> Option<u32> {
+        self.0.checked_sub(earlier.0)
+    }
+}
+
+impl TryFrom<u32> for Height {
+    type Error = &'static str;
+
+    /// Returns an error if `height` is greater than [`Height::MAX`].
+    fn try_from(height: u32) -> Result<Self, Self::Error> {
+        if height <= Height::MAX.0 {
+            Ok(Height(height))
+        } else {
+            Err("heights must be less than or equal to Height::MAX")
+        }
+    }
+}
+
+#[cfg(any(test, feature = "proptest-impl"))]
+impl Arbitrary for Height {
+    type Parameters = ();
+
+    fn arbitrary_with(_args: ()) -> Self::Strategy {
+        // Heights in transactions are also used in lock times, which only
+        // have room for heights below 500_000_000.
+        (Height::MIN.0..500_000_000).prop_map(Height).boxed()
+    }
+
+    type Strategy = BoxedStrategy<Self>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn height_arithmetic_is_checked() {
+        assert_eq!(Height::MIN.previous(), None);
+        assert_eq!(Height::MIN.next(), Some(Height(1)));
+        assert_eq!(Height::MAX.next(), None);
+        assert_eq!(Height::MAX.previous(), Some(Height(i32::MAX as u32 - 1)));
+        assert_eq!(Height(10).checked_add(u32::MAX), None);
+        assert_eq!(Height(10).checked_sub(11), None);
+        assert_eq!(Height(10).checked_sub(10), Some(Height::MIN));
+
+        assert_eq!(Height(10).distance_since(Height(4)), Some(6));
+        assert_eq!(Height(4).distance_since(Height(10)), None);
+
+        assert_eq!(Height::try_from(i32::MAX as u32), Ok(Height::MAX));
+        assert!(Height::try_from(i32::MAX as u32 + 1).is_err());
+    }
+}
@@ -0,0 +1,109 @@
+//! The Bitcoin-inherited Merkle tree of transactions.
+#![allow(clippy::unit_arg)]
+
+use std::{fmt, io::Write, iter, sync::Arc};
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest_derive::Arbitrary;
+
+use crate::{
+    serialization::{reversed_hex, SerializationError},
+    sha256d_writer::Sha256dWriter,
+    transaction::Transaction,
+};
+
+/// The root of the Bitcoin-inherited transaction Merkle tree, binding the
+/// block header to the transactions in the block.
+///
+/// Each leaf is a transaction ID, and each internal node is the SHA-256d
+/// hash of its two children. When a level has an odd number of nodes, the
+/// last node is paired with itself.
+///
+/// # Malleability
+///
+/// Because the last node is duplicated, this tree is malleable: a list of
+/// transactions that ends in a repeated pair has the same root as the list
+/// with the pair removed, so listing [a, b, c] and [a, b, c, c] gives the
+/// same root. ([CVE-2012-2459])
+///
+/// A block with a duplicated transaction is invalid, because it double-spends
+/// its inputs. But when the merkle root check fails, we can't be sure that
+/// the block with that header is invalid, only that these transactions don't
+/// match it. So a block that fails this check must not be marked as invalid
+/// by its hash.
+///
+/// Like transaction IDs, roots are displayed in reversed byte order.
+///
+/// [CVE-2012-2459]: https://en.bitcoin.it/wiki/Common_Vulnerabilities_and_Exposures#CVE-2012-2459
+#[derive(Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
+pub struct Root(
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::reversed_hex"))] pub [u8; 32],
+);
+
+impl fmt::Display for Root {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&reversed_hex::encode(&self.0))
+    }
+}
+
+impl fmt::Debug for Root {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Root")
+            .field(&reversed_hex::encode(&self.0))
+            .finish()
+    }
+}
+
+impl std::str::FromStr for Root {
+    type Err = SerializationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        reversed_hex::decode(s).map(Root)
+    }
+}
+
+impl Root {
+    /// Compute the merkle root of `transactions`, in block order.
+    ///
+    /// The root of an empty list is all zeroes, but valid blocks always
+    /// have a coinbase transaction.
+    pub fn from_transactions(transactions: &[Arc<Transaction>]) -> Root {
+        transactions
+            .iter()
+            .map(|tx| crate::transaction::Hash::from(tx.as_ref()))
+            .collect()
+    }
+}
+
+impl iter::FromIterator<crate::transaction::Hash> for Root {
+    fn from_iter<I>(hashes: I) -> Self
+    where
+        I: IntoIterator<Item = crate::transaction::Hash>,
+    {
+        let mut level: Vec<[u8; 32]> = hashes.into_iter().map(|hash| hash.0).collect();
+        if level.is_empty() {
+            return Root([0; 32]);
+        }
+
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [h1, h2] => hash(h1, h2),
+                    [h1] => hash(h1, h1),
+                    _ => unreachable!("chunks(2) returns one or two hashes"),
+                })
+                .collect();
+        }
+        Root(level[0])
+    }
+}
+
+fn hash(h1: &[u8; 32], h2: &[u8; 32]) -> [u8; 32] {
+    let mut w = Sha256dWriter::default();
+    w.write_all(h1).expect("Sha256dWriter is infallible");
+    w.write_all(h2).expect("Sha256dWriter is infallible");
+    w.finish()
+}
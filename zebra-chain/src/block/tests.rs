@@ -1,68 +1,29 @@
 use std::io::{Cursor, Write};
 
-use chrono::NaiveDateTime;
-use proptest::{
-    arbitrary::{any, Arbitrary},
-    prelude::*,
-};
+use bytes::Bytes;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use proptest::{arbitrary::any, prelude::*};
 
-use crate::sha256d_writer::Sha256dWriter;
+use crate::{
+    sapling,
+    serialization::{SharedBytes, WriteZcashExt},
+    sha256d_writer::Sha256dWriter,
+    work::{difficulty::CompactDifficulty, equihash},
+};
 
 use super::*;
 
-#[cfg(test)]
-impl Arbitrary for BlockHeader {
-    type Parameters = ();
-
-    fn arbitrary_with(_args: ()) -> Self::Strategy {
-        (
-            (4u32..2_147_483_647u32),
-            any::<BlockHeaderHash>(),
-            any::<MerkleTreeRootHash>(),
-            any::<SaplingNoteTreeRootHash>(),
-            (0i64..4_294_967_296i64),
-            any::<u32>(),
-            any::<[u8; 32]>(),
-            any::<EquihashSolution>(),
-        )
-            .prop_map(
-                |(
-                    version,
-                    previous_block_hash,
-                    merkle_root_hash,
-                    final_sapling_root_hash,
-                    timestamp,
-                    bits,
-                    nonce,
-                    solution,
-                )| BlockHeader {
-                    version,
-                    previous_block_hash,
-                    merkle_root_hash,
-                    final_sapling_root_hash,
-                    time: Utc.timestamp(timestamp, 0),
-                    bits,
-                    nonce,
-                    solution,
-                },
-            )
-            .boxed()
-    }
-
-    type Strategy = BoxedStrategy<Self>;
-}
-
 #[test]
 fn blockheaderhash_debug() {
     let preimage = b"foo bar baz";
     let mut sha_writer = Sha256dWriter::default();
     let _ = sha_writer.write_all(preimage);
 
-    let hash = BlockHeaderHash(sha_writer.finish());
+    let hash = Hash(sha_writer.finish());
 
     assert_eq!(
         format!("{:?}", hash),
-        "BlockHeaderHash(\"bf46b4b5030752fedac6f884976162bbfb29a9398f104a280b3e34d51b416631\")"
+        "block::Hash(\"3166411bd5343e0b284a108f39a929fbbb62619784f8c6dafe520703b5b446bf\")"
     );
 }
 
@@ -70,22 +31,22 @@ fn blockheaderhash_debug() {
 fn blockheaderhash_from_blockheader() {
     let some_bytes = [0; 32];
 
-    let blockheader = BlockHeader {
+    let blockheader = Header {
         version: 4,
-        previous_block_hash: BlockHeaderHash(some_bytes),
-        merkle_root_hash: MerkleTreeRootHash(some_bytes),
-        final_sapling_root_hash: SaplingNoteTreeRootHash(some_bytes),
+        previous_block_hash: Hash(some_bytes),
+        merkle_root: merkle::Root(some_bytes),
+        final_sapling_root_hash: sapling::tree::Root(some_bytes),
         time: DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(61, 0), Utc),
-        bits: 0,
+        bits: CompactDifficulty(0),
         nonce: some_bytes,
-        solution: EquihashSolution([0; 1344]),
+        solution: equihash::Solution::Common([0; 1344]),
     };
 
-    let hash = BlockHeaderHash::from(&blockheader);
+    let hash = Hash::from(&blockheader);
 
     assert_eq!(
         format!("{:?}", hash),
-        "BlockHeaderHash(\"39c92b8c6b582797830827c78d58674c7205fcb21991887c124d1dbe4b97d6d1\")"
+        "block::Hash(\"d1d6974bbe1d4d127c889119b2fc05724c67588dc72708839727586b8c2bc939\")"
     );
 
     let mut bytes = Cursor::new(Vec::new());
@@ -95,18 +56,125 @@ fn blockheaderhash_from_blockheader() {
         .expect("these bytes to serialize from a blockheader without issue");
 
     bytes.set_position(0);
-    let other_header = BlockHeader::zcash_deserialize(&mut bytes)
+    let other_header = Header::zcash_deserialize(&mut bytes)
         .expect("these bytes to deserialize into a blockheader without issue");
 
     assert_eq!(blockheader, other_header);
+
+    // Times after 2106 don't fit in the header.
+    let late_header = Header {
+        time: DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(1 << 32, 0), Utc),
+        ..blockheader
+    };
+    assert!(late_header.zcash_serialize(&mut Vec::new()).is_err());
 }
 
 #[test]
 fn deserialize_blockheader() {
     // https://explorer.zcha.in/blocks/415000
-    let _header =
-        BlockHeader::zcash_deserialize(&zebra_test_vectors::HEADER_MAINNET_415000_BYTES[..])
-            .expect("blockheader test vector should deserialize");
+    let _header = Header::zcash_deserialize(&zebra_test_vectors::HEADER_MAINNET_415000_BYTES[..])
+        .expect("blockheader test vector should deserialize");
+}
+
+#[test]
+fn genesis_header_hash() {
+    let block = Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])
+        .expect("block test vector should deserialize");
+
+    assert_eq!(
+        block.header.hash().to_string(),
+        "00040fe8ec8471911baa1db1266ea15dd06b4a8a5c453883c000b031973dce08"
+    );
+    assert_eq!(block.header.hash(), Hash::from(&block));
+}
+
+#[test]
+fn block_size_and_coinbase() {
+    for bytes in &[
+        &zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..],
+        &zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..],
+        &zebra_test_vectors::BLOCK_MAINNET_415000_BYTES[..],
+    ] {
+        let block = Block::zcash_deserialize(*bytes).expect("block test vector should deserialize");
+        assert_eq!(block.serialized_size(), bytes.len());
+        assert!(block.serialized_size() <= MAX_BLOCK_BYTES);
+        assert!(block.coinbase().is_some());
+        assert!(block.has_coinbase_only_first());
+
+        let mut second_coinbase = block.clone();
+        second_coinbase
+            .transactions
+            .push(block.transactions[0].clone());
+        assert!(!second_coinbase.has_coinbase_only_first());
+    }
+}
+
+#[test]
+fn oversized_blocks_are_rejected() {
+    let genesis = Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])
+        .expect("block test vector should deserialize");
+
+    let mut too_many_transactions = Vec::new();
+    genesis
+        .header
+        .zcash_serialize(&mut too_many_transactions)
+        .unwrap();
+    too_many_transactions
+        .write_compactsize(MAX_BLOCK_TRANSACTIONS + 1)
+        .unwrap();
+    assert!(matches!(
+        Block::zcash_deserialize(&too_many_transactions[..]),
+        Err(SerializationError::Parse(_))
+    ));
+
+    let large_output = transaction::TransparentOutput {
+        value: crate::amount::Amount::zero(),
+        pk_script: crate::transparent::Script(vec![0; MAX_BLOCK_BYTES].into()),
+    };
+    let mut too_large = genesis;
+    too_large.transactions.push(Arc::new(Transaction::V1 {
+        inputs: vec![],
+        outputs: vec![large_output],
+        lock_time: transaction::LockTime::unlocked(),
+    }));
+    let mut bytes = Vec::new();
+    too_large.zcash_serialize(&mut bytes).unwrap();
+    assert!(matches!(
+        Block::zcash_deserialize(&bytes[..]),
+        Err(SerializationError::Parse(_))
+    ));
+}
+
+#[test]
+fn merkle_root_matches_block_vectors() {
+    for bytes in &[
+        &zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..],
+        &zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..],
+        &zebra_test_vectors::BLOCK_MAINNET_415000_BYTES[..],
+        &zebra_test_vectors::BLOCK_MAINNET_434873_BYTES[..],
+    ] {
+        let block = Block::zcash_deserialize(*bytes).expect("block test vector should deserialize");
+        assert_eq!(
+            merkle::Root::from_transactions(&block.transactions),
+            block.header.merkle_root
+        );
+    }
+}
+
+#[test]
+fn merkle_root_duplicates_the_last_node() {
+    use crate::transaction;
+
+    let hashes = |ids: &[u8]| ids.iter().map(|&id| transaction::Hash([id; 32]));
+    let root = |ids: &[u8]| hashes(ids).collect::<merkle::Root>();
+
+    // A single transaction is its own root.
+    assert_eq!(root(&[1]).0, [1; 32]);
+    // Listing the last transaction twice gives the same root, which is why
+    // a failed merkle root check doesn't make the header invalid.
+    assert_eq!(root(&[1, 2, 3]), root(&[1, 2, 3, 3]));
+    assert_ne!(root(&[1, 2, 3]), root(&[1, 2, 3, 4]));
+    assert_ne!(root(&[1, 2]), root(&[2, 1]));
 }
 
 #[test]
@@ -124,26 +192,245 @@ fn deserialize_block() {
         .expect("block test vector should deserialize");
 }
 
+#[test]
+fn shared_blocks_match_copied_blocks() {
+    for bytes in &[
+        &zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..],
+        &zebra_test_vectors::BLOCK_MAINNET_415000_BYTES[..],
+        &zebra_test_vectors::BLOCK_MAINNET_434873_BYTES[..],
+    ] {
+        let copied =
+            Block::zcash_deserialize(*bytes).expect("block test vector should deserialize");
+        let buffer = Bytes::copy_from_slice(bytes);
+        let shared = Block::zcash_deserialize_shared(SharedBytes::new(buffer.clone()))
+            .expect("block test vector should deserialize");
+        assert_eq!(shared, copied);
+
+        // Scripts point into the buffer, instead of being copied.
+        let range = buffer.as_ptr() as usize..buffer.as_ptr() as usize + buffer.len();
+        for tx in &shared.transactions {
+            let input_scripts = tx.inputs().filter_map(|input| match input {
+                transaction::TransparentInput::PrevOut { script, .. } => Some(script),
+                transaction::TransparentInput::Coinbase { .. } => None,
+            });
+            let output_scripts = tx.outputs().map(|output| &output.pk_script);
+            for script in input_scripts.chain(output_scripts) {
+                if !script.0.is_empty() {
+                    assert!(range.contains(&(script.0.as_ptr() as usize)));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn blockheaderhash_display_from_str() {
+    let hash: Hash = "00040fe8ec8471911baa1db1266ea15dd06b4a8a5c453883c000b031973dce08"
+        .parse()
+        .expect("hex hash should parse");
+
+    // The internal byte order is the reverse of the display order.
+    assert_eq!(hash.0[0], 0x08);
+    assert_eq!(hash.0[31], 0x00);
+    assert_eq!(
+        hash.to_string(),
+        "00040fe8ec8471911baa1db1266ea15dd06b4a8a5c453883c000b031973dce08"
+    );
+}
+
+#[test]
+fn merkle_root_display_matches_zcashd() {
+    let genesis = Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])
+        .expect("block test vector should deserialize");
+
+    // zcashd's chainparams check the genesis merkle root against this value.
+    let root = genesis.header.merkle_root;
+    assert_eq!(
+        root.to_string(),
+        "c4eaa58879081de3c24a7b117ed2b28300e7ec4c4c1dff1d3f1268b7857a4cf8"
+    );
+    assert_eq!(root.to_string().parse::<merkle::Root>().unwrap(), root);
+
+    // Short, long, and non-hex strings are rejected.
+    assert!("c4eaa588".parse::<Hash>().is_err());
+    assert!(format!("{}00", root).parse::<Hash>().is_err());
+    assert!("xx".repeat(32).parse::<Hash>().is_err());
+}
+
+#[test]
+fn basic_filter_contains_block_scripts() {
+    use super::filter::BlockFilter;
+    use crate::transparent::Script;
+
+    let block = Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_415000_BYTES[..])
+        .expect("block test vector should deserialize");
+    let hash = Hash::from(&block);
+    let spent = Script(vec![0x76, 0xa9, 0x14, 0x01, 0x02, 0x03].into());
+
+    let filter = BlockFilter::basic(&block, vec![&spent]);
+
+    // Empty and OP_RETURN scripts are excluded from basic filters.
+    for script in block
+        .transactions
+        .iter()
+        .flat_map(|tx| tx.outputs())
+        .map(|output| &output.pk_script.0)
+        .filter(|script| !script.is_empty() && script[0] != 0x6a)
+    {
+        assert!(filter
+            .contains(&hash, script)
+            .expect("filter should decode"));
+    }
+    assert!(filter
+        .contains(&hash, &spent.0)
+        .expect("filter should decode"));
+}
+
+#[test]
+fn basic_filter_empty() {
+    use super::filter::BlockFilter;
+
+    let block = Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_415000_BYTES[..])
+        .expect("block test vector should deserialize");
+    let block = Block {
+        header: block.header,
+        transactions: Vec::new(),
+    };
+
+    let filter = BlockFilter::basic(&block, Vec::new());
+    assert_eq!(filter.0, vec![0]);
+    assert!(!filter
+        .contains(&Hash::from(&block), b"script")
+        .expect("filter should decode"));
+}
+
+#[test]
+fn basic_filter_with_huge_count_is_rejected() {
+    use super::filter::BlockFilter;
+
+    // A CompactSize item count of u64::MAX.
+    let mut bytes = vec![0xff];
+    bytes.extend_from_slice(&u64::MAX.to_le_bytes());
+    let filter = BlockFilter(bytes);
+
+    assert!(filter.contains(&Hash([0; 32]), b"script").is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn block_serde_json_round_trip() {
+    let block = Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_415000_BYTES[..])
+        .expect("block test vector should deserialize");
+
+    let json = serde_json::to_value(&block).expect("block should serialize to JSON");
+    assert_eq!(
+        json["header"]["previous_block_hash"],
+        block.header.previous_block_hash.to_string()
+    );
+    assert!(json["header"]["merkle_root"].is_string());
+
+    let other_block: Block = serde_json::from_value(json).expect("JSON should deserialize");
+    assert_eq!(block, other_block);
+}
+
+#[test]
+fn commitment_depends_on_network_upgrade() {
+    use crate::Network::*;
+
+    let bytes = [0x11; 32];
+    let commitment = |height| Commitment::from_bytes(bytes, Mainnet, Height(height));
+
+    assert_eq!(commitment(1), Ok(Commitment::PreSaplingReserved(bytes)));
+    assert_eq!(
+        commitment(419_200),
+        Ok(Commitment::FinalSaplingRoot(sapling::tree::Root(bytes)))
+    );
+    assert_eq!(
+        commitment(903_001),
+        Ok(Commitment::ChainHistoryRoot(ChainHistoryMmrRootHash(bytes)))
+    );
+    assert_eq!(
+        commitment(1_687_104),
+        Ok(Commitment::ChainHistoryBlockTxAuthCommitment(
+            ChainHistoryBlockTxAuthCommitmentHash(bytes)
+        ))
+    );
+
+    // The Heartwood activation block must have an all-zero commitment.
+    assert_eq!(
+        commitment(903_000),
+        Err(CommitmentError::InvalidChainHistoryActivationReserved { actual: bytes })
+    );
+    assert_eq!(
+        Commitment::from_bytes([0; 32], Mainnet, Height(903_000)),
+        Ok(Commitment::ChainHistoryActivationReserved)
+    );
+
+    for commitment in (&[1, 419_200, 903_001, 1_687_104])
+        .iter()
+        .map(|h| commitment(*h))
+    {
+        assert_eq!(commitment.map(Commitment::to_bytes), Ok(bytes));
+    }
+}
+
+#[test]
+fn auth_data_root_pads_leaves() {
+    use std::iter::FromIterator;
+
+    use crate::transaction::AuthDigest;
+
+    // A single leaf is its own root.
+    let leaf = AuthDigest([0xff; 32]);
+    assert_eq!(
+        AuthDataRoot::from_iter(vec![leaf]),
+        AuthDataRoot([0xff; 32])
+    );
+
+    // Three leaves are padded with an all-zero leaf.
+    let three = AuthDataRoot::from_iter(vec![leaf, leaf, leaf]);
+    let padded = AuthDataRoot::from_iter(vec![leaf, leaf, leaf, AuthDigest([0; 32])]);
+    assert_eq!(three, padded);
+    assert_ne!(three, AuthDataRoot::from_iter(vec![leaf, leaf, leaf, leaf]));
+}
+
 proptest! {
 
     #[test]
-    fn blockheaderhash_roundtrip(hash in any::<BlockHeaderHash>()) {
+    fn blockheaderhash_roundtrip(hash in any::<Hash>()) {
         let mut bytes = Cursor::new(Vec::new());
         hash.zcash_serialize(&mut bytes)?;
 
         bytes.set_position(0);
-        let other_hash = BlockHeaderHash::zcash_deserialize(&mut bytes)?;
+        let other_hash = Hash::zcash_deserialize(&mut bytes)?;
 
         prop_assert_eq![hash, other_hash];
     }
 
     #[test]
-    fn blockheader_roundtrip(header in any::<BlockHeader>()) {
+    fn blockheaderhash_display_roundtrip(hash in any::<Hash>()) {
+        let other_hash: Hash = hash.to_string().parse()?;
+
+        prop_assert_eq![hash, other_hash];
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn blockheaderhash_serde_roundtrip(hash in any::<Hash>()) {
+        let json = serde_json::to_string(&hash)?;
+        prop_assert_eq!(&json, &format!("\"{}\"", hash));
+
+        let other_hash: Hash = serde_json::from_str(&json)?;
+        prop_assert_eq![hash, other_hash];
+    }
+
+    #[test]
+    fn blockheader_roundtrip(header in any::<Header>()) {
         let mut bytes = Cursor::new(Vec::new());
         header.zcash_serialize(&mut bytes)?;
 
         bytes.set_position(0);
-        let other_header = BlockHeader::zcash_deserialize(&mut bytes)?;
+        let other_header = Header::zcash_deserialize(&mut bytes)?;
 
         prop_assert_eq![header, other_header];
     }
@@ -159,4 +446,14 @@ proptest! {
         prop_assert_eq![block, other_block];
     }
 
+    #[test]
+    fn filter_header_roundtrip(header in any::<filter::FilterHeader>()) {
+        let mut bytes = Cursor::new(Vec::new());
+        header.zcash_serialize(&mut bytes)?;
+
+        bytes.set_position(0);
+        let other_header = filter::FilterHeader::zcash_deserialize(&mut bytes)?;
+
+        prop_assert_eq![header, other_header];
+    }
 }
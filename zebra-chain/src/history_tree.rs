@@ -0,0 +1,511 @@
+//! The chain history tree, a Merkle mountain range of the blocks in each
+//! network upgrade, from Heartwood activation.
+//!
+//! Each block after Heartwood activation commits to the root of the tree
+//! before it, so light clients can check that a block is in the chain, and
+//! how much work the chain has, without downloading every header.
+//!
+//! The tree starts again at each network upgrade activation, and its nodes
+//! are hashed with the upgrade's consensus branch ID.
+//!
+//! [ZIP-221](https://zips.z.cash/zip-0221)
+
+use std::io;
+
+use blake2b_simd::Params;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use thiserror::Error;
+
+use crate::{
+    block::{Block, ChainHistoryMmrRootHash},
+    network_upgrade::{ConsensusBranchId, NetworkUpgrade, CONSENSUS_BRANCH_IDS},
+    orchard, sapling,
+    serialization::{
+        ReadZcashExt, SerializationError, WriteZcashExt, ZcashDeserialize, ZcashSerialize,
+    },
+    Network,
+};
+
+/// An error adding a block to a [`HistoryTree`].
+#[derive(Error, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HistoryTreeError {
+    /// The block doesn't have a coinbase height, so the network upgrade is
+    /// unknown.
+    #[error("the block has no coinbase height")]
+    MissingBlockHeight,
+}
+
+/// The Orchard fields of a node, which are only in nodes from NU5.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct OrchardNodeData {
+    /// The Orchard note commitment tree root after the first block.
+    start_root: [u8; 32],
+    /// The Orchard note commitment tree root after the last block.
+    end_root: [u8; 32],
+    /// The number of transactions with Orchard actions.
+    tx: u64,
+}
+
+/// A node in the chain history tree, which summarises a range of blocks.
+///
+/// The fields, and their serialization, are the same as `NodeData` in the
+/// `zcash_history` crate.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Node {
+    /// The hash of the block for leaves, or the hash of the two children for
+    /// internal nodes.
+    subtree_commitment: [u8; 32],
+    start_time: u32,
+    end_time: u32,
+    start_target: u32,
+    end_target: u32,
+    /// The Sapling note commitment tree root after the first block.
+    start_sapling_root: [u8; 32],
+    /// The Sapling note commitment tree root after the last block.
+    end_sapling_root: [u8; 32],
+    /// The total work of the blocks, which is serialized as a 256-bit
+    /// integer.
+    subtree_total_work: u128,
+    start_height: u64,
+    end_height: u64,
+    /// The number of transactions with Sapling spends or outputs.
+    sapling_tx: u64,
+    orchard: Option<OrchardNodeData>,
+}
+
+impl Node {
+    /// Returns the leaf for `block`, at `height`, in a tree for `upgrade`.
+    ///
+    /// The roots are the note commitment tree roots after the block.
+    fn leaf(
+        block: &Block,
+        height: u64,
+        upgrade: NetworkUpgrade,
+        sapling_root: sapling::tree::Root,
+        orchard_root: orchard::tree::Root,
+    ) -> Node {
+        let time = block.header.time.timestamp() as u32;
+        let target = block.header.bits.0;
+        let sapling_tx = block
+            .transactions
+            .iter()
+            .filter(|tx| {
+                tx.sapling_spends().next().is_some() || tx.sapling_outputs().next().is_some()
+            })
+            .count() as u64;
+        let orchard = if upgrade == NetworkUpgrade::Nu5 {
+            Some(OrchardNodeData {
+                start_root: orchard_root.0,
+                end_root: orchard_root.0,
+                tx: block
+                    .transactions
+                    .iter()
+                    .filter(|tx| tx.orchard_shielded_data().is_some())
+                    .count() as u64,
+            })
+        } else {
+            None
+        };
+
+        Node {
+            subtree_commitment: block.hash().0,
+            start_time: time,
+            end_time: time,
+            start_target: target,
+            end_target: target,
+            start_sapling_root: sapling_root.0,
+            end_sapling_root: sapling_root.0,
+            // The verifier rejects invalid difficulty thresholds, so blocks
+            // in the state always have work.
+            subtree_total_work: block
+                .header
+                .bits
+                .to_work()
+                .map(|work| work.as_u128())
+                .unwrap_or_default(),
+            start_height: height,
+            end_height: height,
+            sapling_tx,
+            orchard,
+        }
+    }
+
+    /// Returns the parent of `left` and `right`, which are adjacent
+    /// subtrees.
+    fn combine(branch_id: ConsensusBranchId, left: &Node, right: &Node) -> Node {
+        let orchard = match (&left.orchard, &right.orchard) {
+            (Some(left), Some(right)) => Some(OrchardNodeData {
+                start_root: left.start_root,
+                end_root: right.end_root,
+                tx: left.tx + right.tx,
+            }),
+            _ => None,
+        };
+
+        let mut children = Vec::new();
+        left.write(&mut children)
+            .expect("writing to a Vec never fails");
+        right
+            .write(&mut children)
+            .expect("writing to a Vec never fails");
+
+        Node {
+            subtree_commitment: hash(branch_id, &children),
+            start_time: left.start_time,
+            end_time: right.end_time,
+            start_target: left.start_target,
+            end_target: right.end_target,
+            start_sapling_root: left.start_sapling_root,
+            end_sapling_root: right.end_sapling_root,
+            subtree_total_work: left
+                .subtree_total_work
+                .saturating_add(right.subtree_total_work),
+            start_height: left.start_height,
+            end_height: right.end_height,
+            sapling_tx: left.sapling_tx + right.sapling_tx,
+            orchard,
+        }
+    }
+
+    /// Returns the number of blocks in this node's subtree.
+    fn leaf_count(&self) -> u64 {
+        self.end_height - self.start_height + 1
+    }
+
+    fn write<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        writer.write_all(&self.subtree_commitment)?;
+        writer.write_u32::<LittleEndian>(self.start_time)?;
+        writer.write_u32::<LittleEndian>(self.end_time)?;
+        writer.write_u32::<LittleEndian>(self.start_target)?;
+        writer.write_u32::<LittleEndian>(self.end_target)?;
+        writer.write_all(&self.start_sapling_root)?;
+        writer.write_all(&self.end_sapling_root)?;
+        let mut work = [0; 32];
+        work[..16].copy_from_slice(&self.subtree_total_work.to_le_bytes());
+        writer.write_all(&work)?;
+        writer.write_compactsize(self.start_height)?;
+        writer.write_compactsize(self.end_height)?;
+        writer.write_compactsize(self.sapling_tx)?;
+        if let Some(orchard) = &self.orchard {
+            writer.write_all(&orchard.start_root)?;
+            writer.write_all(&orchard.end_root)?;
+            writer.write_compactsize(orchard.tx)?;
+        }
+        Ok(())
+    }
+
+    fn read<R: io::Read>(mut reader: R, has_orchard: bool) -> Result<Node, SerializationError> {
+        let subtree_commitment = reader.read_32_bytes()?;
+        let start_time = reader.read_u32::<LittleEndian>()?;
+        let end_time = reader.read_u32::<LittleEndian>()?;
+        let start_target = reader.read_u32::<LittleEndian>()?;
+        let end_target = reader.read_u32::<LittleEndian>()?;
+        let start_sapling_root = reader.read_32_bytes()?;
+        let end_sapling_root = reader.read_32_bytes()?;
+        let work = reader.read_32_bytes()?;
+        if work[16..] != [0; 16] {
+            return Err(SerializationError::Parse(
+                "history tree node work is larger than 128 bits",
+            ));
+        }
+        let mut subtree_total_work = [0; 16];
+        subtree_total_work.copy_from_slice(&work[..16]);
+        let subtree_total_work = u128::from_le_bytes(subtree_total_work);
+        let start_height = reader.read_compactsize()?;
+        let end_height = reader.read_compactsize()?;
+        if end_height < start_height {
+            return Err(SerializationError::Parse(
+                "history tree node ends before it starts",
+            ));
+        }
+        let sapling_tx = reader.read_compactsize()?;
+        let orchard = if has_orchard {
+            Some(OrchardNodeData {
+                start_root: reader.read_32_bytes()?,
+                end_root: reader.read_32_bytes()?,
+                tx: reader.read_compactsize()?,
+            })
+        } else {
+            None
+        };
+
+        Ok(Node {
+            subtree_commitment,
+            start_time,
+            end_time,
+            start_target,
+            end_target,
+            start_sapling_root,
+            end_sapling_root,
+            subtree_total_work,
+            start_height,
+            end_height,
+            sapling_tx,
+            orchard,
+        })
+    }
+}
+
+/// Returns the BLAKE2b-256 hash of `data`, personalized with `branch_id`.
+fn hash(branch_id: ConsensusBranchId, data: &[u8]) -> [u8; 32] {
+    let mut personal = [0; 16];
+    personal[..12].copy_from_slice(b"ZcashHistory");
+    personal[12..].copy_from_slice(&branch_id.0.to_le_bytes());
+
+    let hash = Params::new().hash_length(32).personal(&personal).hash(data);
+
+    let mut bytes = [0; 32];
+    bytes.copy_from_slice(hash.as_bytes());
+    bytes
+}
+
+/// The chain history tree after a block.
+///
+/// The tree only stores the roots of its complete subtrees, which are its
+/// peaks. That is enough to add blocks and calculate the root, but not to
+/// make inclusion proofs.
+///
+/// Before Heartwood activation, the tree is empty.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct HistoryTree {
+    /// The network upgrade of the blocks in the tree, or `None` if it is
+    /// empty.
+    network_upgrade: Option<NetworkUpgrade>,
+    /// The peaks, from the leftmost and largest to the rightmost and
+    /// smallest.
+    peaks: Vec<Node>,
+}
+
+impl HistoryTree {
+    /// Adds `block` to the tree, which must be the tree after the block's
+    /// parent on `network`.
+    ///
+    /// The roots are the note commitment tree roots after the block. If the
+    /// block activates a network upgrade, the tree starts again with just
+    /// this block. Before Heartwood activation, the tree stays empty.
+    pub fn push(
+        &mut self,
+        network: Network,
+        block: &Block,
+        sapling_root: sapling::tree::Root,
+        orchard_root: orchard::tree::Root,
+    ) -> Result<(), HistoryTreeError> {
+        use NetworkUpgrade::*;
+
+        let height = block
+            .coinbase_height()
+            .ok_or(HistoryTreeError::MissingBlockHeight)?;
+        let upgrade = NetworkUpgrade::current(network, height);
+        let branch_id = match upgrade {
+            Genesis | BeforeOverwinter | Overwinter | Sapling | Blossom => {
+                *self = HistoryTree::default();
+                return Ok(());
+            }
+            Heartwood | Canopy | Nu5 => upgrade
+                .branch_id()
+                .expect("upgrades after Heartwood have branch IDs"),
+        };
+        if self.network_upgrade != Some(upgrade) {
+            *self = HistoryTree {
+                network_upgrade: Some(upgrade),
+                peaks: Vec::new(),
+            };
+        }
+
+        // Merge the new leaf with the peaks that have the same size, from
+        // right to left.
+        let mut node = Node::leaf(block, height.0.into(), upgrade, sapling_root, orchard_root);
+        while let Some(peak) = self.peaks.pop() {
+            if peak.leaf_count() != node.leaf_count() {
+                self.peaks.push(peak);
+                break;
+            }
+            node = Node::combine(branch_id, &peak, &node);
+        }
+        self.peaks.push(node);
+
+        Ok(())
+    }
+
+    /// Returns the root of the tree, or `None` if it is empty.
+    ///
+    /// The root joins the peaks from left to right, like `zcash_history`.
+    pub fn hash(&self) -> Option<ChainHistoryMmrRootHash> {
+        let branch_id = self.network_upgrade?.branch_id()?;
+        let mut peaks = self.peaks.iter();
+        let first = peaks.next()?.clone();
+        let root = peaks.fold(first, |root, peak| Node::combine(branch_id, &root, peak));
+
+        let mut data = Vec::new();
+        root.write(&mut data).expect("writing to a Vec never fails");
+        Some(ChainHistoryMmrRootHash(hash(branch_id, &data)))
+    }
+
+    /// Returns the network upgrade of the blocks in the tree, or `None` if it
+    /// is empty.
+    pub fn network_upgrade(&self) -> Option<NetworkUpgrade> {
+        self.network_upgrade
+    }
+
+    /// Returns the number of blocks in the tree.
+    pub fn len(&self) -> u64 {
+        self.peaks.iter().map(Node::leaf_count).sum()
+    }
+
+    /// Returns true if the tree has no blocks.
+    pub fn is_empty(&self) -> bool {
+        self.peaks.is_empty()
+    }
+}
+
+impl ZcashSerialize for HistoryTree {
+    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        // Empty trees have a zero branch ID.
+        let branch_id = self
+            .network_upgrade
+            .and_then(|upgrade| upgrade.branch_id())
+            .map_or(0, u32::from);
+        writer.write_u32::<LittleEndian>(branch_id)?;
+        writer.write_compactsize(self.peaks.len() as u64)?;
+        for peak in &self.peaks {
+            peak.write(&mut writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl ZcashDeserialize for HistoryTree {
+    fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let branch_id = reader.read_u32::<LittleEndian>()?;
+        let network_upgrade = match branch_id {
+            0 => None,
+            _ => Some(
+                CONSENSUS_BRANCH_IDS
+                    .iter()
+                    .find(|(upgrade, id)| {
+                        id.0 == branch_id
+                            && matches!(
+                                upgrade,
+                                NetworkUpgrade::Heartwood
+                                    | NetworkUpgrade::Canopy
+                                    | NetworkUpgrade::Nu5
+                            )
+                    })
+                    .map(|(upgrade, _)| *upgrade)
+                    .ok_or(SerializationError::Parse(
+                        "history tree has an unknown branch ID",
+                    ))?,
+            ),
+        };
+
+        // Each peak is smaller than the one before it, so there are at most
+        // 64.
+        let peak_count = reader.read_compactsize()?;
+        if peak_count > 64 || (network_upgrade.is_none() && peak_count > 0) {
+            return Err(SerializationError::Parse(
+                "history tree has an invalid number of peaks",
+            ));
+        }
+        let has_orchard = network_upgrade == Some(NetworkUpgrade::Nu5);
+        let peaks = (0..peak_count)
+            .map(|_| Node::read(&mut reader, has_orchard))
+            .collect::<Result<_, _>>()?;
+
+        Ok(HistoryTree {
+            network_upgrade,
+            peaks,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use crate::{
+        block::Height,
+        transaction::{Transaction, TransparentInput},
+    };
+
+    /// Returns mainnet block 1, with its coinbase height changed to
+    /// `height`.
+    fn block_at(height: Height) -> Block {
+        let mut block = Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])
+            .expect("block test vector is valid");
+        let mut coinbase = block.transactions[0].as_ref().clone();
+        if let Transaction::V1 { inputs, .. } = &mut coinbase {
+            if let TransparentInput::Coinbase { height: h, .. } = &mut inputs[0] {
+                *h = height;
+            }
+        }
+        block.transactions[0] = Arc::new(coinbase);
+        block
+    }
+
+    fn push(tree: &mut HistoryTree, height: u32) {
+        tree.push(
+            Network::Regtest,
+            &block_at(Height(height)),
+            sapling::tree::Root::default(),
+            orchard::tree::Root::default(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn tree_starts_at_heartwood() {
+        // Regtest activates Heartwood at height 5, and Canopy at height 6.
+        let mut tree = HistoryTree::default();
+        push(&mut tree, 4);
+        assert!(tree.is_empty());
+        assert_eq!(tree.hash(), None);
+
+        push(&mut tree, 5);
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree.network_upgrade(), Some(NetworkUpgrade::Heartwood));
+
+        // A single leaf is the root.
+        let leaf = &tree.peaks[0];
+        let mut data = Vec::new();
+        leaf.write(&mut data).unwrap();
+        let branch_id = NetworkUpgrade::Heartwood.branch_id().unwrap();
+        assert_eq!(
+            tree.hash(),
+            Some(ChainHistoryMmrRootHash(hash(branch_id, &data)))
+        );
+
+        push(&mut tree, 6);
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree.network_upgrade(), Some(NetworkUpgrade::Canopy));
+    }
+
+    #[test]
+    fn peaks_merge_and_round_trip() {
+        // Regtest activates NU5 at height 7, so these nodes have Orchard data.
+        let mut tree = HistoryTree::default();
+        let mut roots = Vec::new();
+        for height in 7..10 {
+            push(&mut tree, height);
+            roots.push(tree.hash().unwrap());
+        }
+        assert_eq!(tree.len(), 3);
+        assert_eq!(tree.peaks.len(), 2);
+        assert_eq!(tree.peaks[0].leaf_count(), 2);
+        assert!(tree.peaks.iter().all(|peak| peak.orchard.is_some()));
+        assert_ne!(roots[0], roots[1]);
+        assert_ne!(roots[1], roots[2]);
+
+        push(&mut tree, 10);
+        assert_eq!(tree.peaks.len(), 1);
+        let work = block_at(Height(7)).header.bits.to_work().unwrap();
+        assert_eq!(tree.peaks[0].subtree_total_work, 4 * work.as_u128());
+
+        let mut bytes = Vec::new();
+        tree.zcash_serialize(&mut bytes).unwrap();
+        let other = HistoryTree::zcash_deserialize(&bytes[..]).unwrap();
+        assert_eq!(other, tree);
+        assert_eq!(other.hash(), tree.hash());
+    }
+}
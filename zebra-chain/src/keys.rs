@@ -1,5 +1,17 @@
 //! Key types.
 
+use std::fmt;
+
 pub mod sapling;
 pub mod sprout;
 pub mod transparent;
+
+/// Shown in place of secret key bytes in `Debug` output, so that keys don't
+/// end up in logs.
+struct Redacted;
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
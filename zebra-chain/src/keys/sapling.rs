@@ -9,6 +9,8 @@
 //! [3.1]: https://zips.z.cash/protocol/protocol.pdf#addressesandkeys
 #![allow(clippy::unit_arg)]
 
+#[cfg(any(test, feature = "proptest-impl"))]
+mod arbitrary;
 #[cfg(test)]
 mod test_vectors;
 #[cfg(test)]
@@ -23,8 +25,9 @@ use std::{
 
 use bech32::{self, FromBase32, ToBase32};
 use rand_core::{CryptoRng, RngCore};
+use zeroize::Zeroize;
 
-#[cfg(test)]
+#[cfg(any(test, feature = "proptest-impl"))]
 use proptest_derive::Arbitrary;
 
 use crate::{
@@ -33,6 +36,8 @@ use crate::{
     Network,
 };
 
+use super::Redacted;
+
 /// The [Randomness Beacon][1] ("URS").
 ///
 /// First 64 bytes of the BLAKE2s input during JubJub group hash.  URS
@@ -124,7 +129,7 @@ fn jubjub_group_hash(d: [u8; 8], m: &[u8]) -> Option<jubjub::ExtendedPoint> {
 ///
 /// [0]: https://github.com/zcash/librustzcash/blob/master/zcash_primitives/src/jubjub/mod.rs#L409
 /// https://zips.z.cash/protocol/protocol.pdf#concretegrouphashjubjub
-fn find_group_hash(d: [u8; 8], m: &[u8]) -> jubjub::ExtendedPoint {
+pub(crate) fn find_group_hash(d: [u8; 8], m: &[u8]) -> jubjub::ExtendedPoint {
     let mut tag = m.to_vec();
     let i = tag.len();
     tag.push(0u8);
@@ -161,12 +166,21 @@ fn diversify_hash(d: [u8; 11]) -> Option<jubjub::ExtendedPoint> {
 // exported.
 type Scalar = jubjub::Fr;
 
+/// Overwrite `scalar` with zero, in a way that the compiler won't optimize
+/// out, because `jubjub` doesn't implement `Zeroize`.
+fn zeroize_scalar(scalar: &mut Scalar) {
+    // Safety: `scalar` is a valid, aligned, and exclusive reference.
+    unsafe { std::ptr::write_volatile(scalar, Scalar::zero()) };
+    std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+}
+
 /// Magic human-readable strings used to identify what networks
 /// Sapling Spending Keys are associated with when encoded/decoded
 /// with bech32.
 mod sk_hrp {
     pub const MAINNET: &str = "secret-spending-key-main";
     pub const TESTNET: &str = "secret-spending-key-test";
+    pub const REGTEST: &str = "secret-spending-key-regtest";
 }
 
 /// A _Spending Key_, as described in [protocol specification
@@ -176,13 +190,37 @@ mod sk_hrp {
 /// Sapling key types derive from the SpendingKey value.
 ///
 /// [ps]: https://zips.z.cash/protocol/protocol.pdf#saplingkeycomponents
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-#[cfg_attr(test, derive(Arbitrary))]
+///
+/// Spending keys are zeroized when they are dropped, so they aren't `Copy`,
+/// and their `Debug` output doesn't include the key.
+#[derive(Clone, Eq, PartialEq)]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
 pub struct SpendingKey {
     network: Network,
     bytes: [u8; 32],
 }
 
+impl fmt::Debug for SpendingKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SpendingKey")
+            .field("network", &self.network)
+            .field("bytes", &Redacted)
+            .finish()
+    }
+}
+
+impl Zeroize for SpendingKey {
+    fn zeroize(&mut self) {
+        self.bytes.zeroize();
+    }
+}
+
+impl Drop for SpendingKey {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
 // TODO: impl a From that accepts a Network?
 
 impl From<[u8; 32]> for SpendingKey {
@@ -199,7 +237,8 @@ impl fmt::Display for SpendingKey {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let hrp = match self.network {
             Network::Mainnet => sk_hrp::MAINNET,
-            _ => sk_hrp::TESTNET,
+            Network::Testnet => sk_hrp::TESTNET,
+            Network::Regtest => sk_hrp::REGTEST,
         };
 
         bech32::encode_to_fmt(f, hrp, &self.bytes.to_base32()).unwrap()
@@ -220,6 +259,7 @@ impl FromStr for SpendingKey {
                 Ok(SpendingKey {
                     network: match hrp.as_str() {
                         sk_hrp::MAINNET => Network::Mainnet,
+                        sk_hrp::REGTEST => Network::Regtest,
                         _ => Network::Testnet,
                     },
                     bytes: decoded_bytes,
@@ -250,30 +290,42 @@ impl SpendingKey {
 /// _Spend Description_, proving ownership of notes.
 ///
 /// [ps]: https://zips.z.cash/protocol/protocol.pdf#saplingkeycomponents
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Clone, Eq, PartialEq)]
 pub struct SpendAuthorizingKey(pub Scalar);
 
 impl fmt::Debug for SpendAuthorizingKey {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_tuple("SpendAuthorizingKey")
-            .field(&hex::encode(<[u8; 32]>::from(*self)))
+            .field(&Redacted)
             .finish()
     }
 }
 
+impl Zeroize for SpendAuthorizingKey {
+    fn zeroize(&mut self) {
+        zeroize_scalar(&mut self.0);
+    }
+}
+
+impl Drop for SpendAuthorizingKey {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
 impl From<SpendAuthorizingKey> for [u8; 32] {
     fn from(sk: SpendAuthorizingKey) -> Self {
         sk.0.to_bytes()
     }
 }
 
-impl From<SpendingKey> for SpendAuthorizingKey {
+impl From<&SpendingKey> for SpendAuthorizingKey {
     /// Invokes Blake2b-512 as _PRF^expand_, t=0, to derive a
     /// SpendAuthorizingKey from a SpendingKey.
     ///
     /// https://zips.z.cash/protocol/protocol.pdf#saplingkeycomponents
     /// https://zips.z.cash/protocol/protocol.pdf#concreteprfs
-    fn from(spending_key: SpendingKey) -> SpendAuthorizingKey {
+    fn from(spending_key: &SpendingKey) -> SpendAuthorizingKey {
         let hash_bytes = prf_expand(spending_key.bytes, &[0]);
 
         Self(Scalar::from_bytes_wide(&hash_bytes))
@@ -282,7 +334,7 @@ impl From<SpendingKey> for SpendAuthorizingKey {
 
 impl PartialEq<[u8; 32]> for SpendAuthorizingKey {
     fn eq(&self, other: &[u8; 32]) -> bool {
-        <[u8; 32]>::from(*self) == *other
+        self.0.to_bytes() == *other
     }
 }
 
@@ -292,29 +344,41 @@ impl PartialEq<[u8; 32]> for SpendAuthorizingKey {
 /// Used in the _Spend Statement_ to prove nullifier integrity.
 ///
 /// [ps]: https://zips.z.cash/protocol/protocol.pdf#saplingkeycomponents
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Clone, Eq, PartialEq)]
 pub struct ProofAuthorizingKey(pub Scalar);
 
 impl fmt::Debug for ProofAuthorizingKey {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_tuple("ProofAuthorizingKey")
-            .field(&hex::encode(<[u8; 32]>::from(*self)))
+            .field(&Redacted)
             .finish()
     }
 }
 
+impl Zeroize for ProofAuthorizingKey {
+    fn zeroize(&mut self) {
+        zeroize_scalar(&mut self.0);
+    }
+}
+
+impl Drop for ProofAuthorizingKey {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
 impl From<ProofAuthorizingKey> for [u8; 32] {
     fn from(nsk: ProofAuthorizingKey) -> Self {
         nsk.0.to_bytes()
     }
 }
 
-impl From<SpendingKey> for ProofAuthorizingKey {
+impl From<&SpendingKey> for ProofAuthorizingKey {
     /// For this invocation of Blake2b-512 as _PRF^expand_, t=1.
     ///
     /// https://zips.z.cash/protocol/protocol.pdf#saplingkeycomponents
     /// https://zips.z.cash/protocol/protocol.pdf#concreteprfs
-    fn from(spending_key: SpendingKey) -> ProofAuthorizingKey {
+    fn from(spending_key: &SpendingKey) -> ProofAuthorizingKey {
         let hash_bytes = prf_expand(spending_key.bytes, &[1]);
 
         Self(Scalar::from_bytes_wide(&hash_bytes))
@@ -323,7 +387,7 @@ impl From<SpendingKey> for ProofAuthorizingKey {
 
 impl PartialEq<[u8; 32]> for ProofAuthorizingKey {
     fn eq(&self, other: &[u8; 32]) -> bool {
-        <[u8; 32]>::from(*self) == *other
+        self.0.to_bytes() == *other
     }
 }
 
@@ -333,13 +397,16 @@ impl PartialEq<[u8; 32]> for ProofAuthorizingKey {
 /// Used to decrypt outgoing notes without spending them.
 ///
 /// [ps]: https://zips.z.cash/protocol/protocol.pdf#saplingkeycomponents
-#[derive(Copy, Clone, Eq, PartialEq)]
+///
+/// Outgoing viewing keys are zeroized when they are dropped, so they aren't
+/// `Copy`.
+#[derive(Clone, Eq, PartialEq)]
 pub struct OutgoingViewingKey(pub [u8; 32]);
 
 impl fmt::Debug for OutgoingViewingKey {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_tuple("OutgoingViewingKey")
-            .field(&hex::encode(&self.0))
+            .field(&Redacted)
             .finish()
     }
 }
@@ -357,12 +424,24 @@ impl From<OutgoingViewingKey> for [u8; 32] {
     }
 }
 
-impl From<SpendingKey> for OutgoingViewingKey {
+impl Zeroize for OutgoingViewingKey {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl Drop for OutgoingViewingKey {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl From<&SpendingKey> for OutgoingViewingKey {
     /// For this invocation of Blake2b-512 as _PRF^expand_, t=2.
     ///
     /// https://zips.z.cash/protocol/protocol.pdf#saplingkeycomponents
     /// https://zips.z.cash/protocol/protocol.pdf#concreteprfs
-    fn from(spending_key: SpendingKey) -> OutgoingViewingKey {
+    fn from(spending_key: &SpendingKey) -> OutgoingViewingKey {
         let hash_bytes = prf_expand(spending_key.bytes, &[2]);
 
         let mut bytes = [0u8; 32];
@@ -386,13 +465,13 @@ impl PartialEq<[u8; 32]> for OutgoingViewingKey {
 ///
 /// [ps]: https://zips.z.cash/protocol/protocol.pdf#saplingkeycomponents
 #[derive(Copy, Clone, Debug)]
-pub struct AuthorizingKey(pub redjubjub::PublicKey<SpendAuth>);
+pub struct AuthorizingKey(pub redjubjub::VerificationKey<SpendAuth>);
 
 impl Eq for AuthorizingKey {}
 
 impl From<[u8; 32]> for AuthorizingKey {
     fn from(bytes: [u8; 32]) -> Self {
-        Self(redjubjub::PublicKey::try_from(bytes).unwrap())
+        Self(redjubjub::VerificationKey::try_from(bytes).unwrap())
     }
 }
 
@@ -404,8 +483,8 @@ impl From<AuthorizingKey> for [u8; 32] {
 
 impl From<SpendAuthorizingKey> for AuthorizingKey {
     fn from(ask: SpendAuthorizingKey) -> Self {
-        let sk = redjubjub::SecretKey::<SpendAuth>::try_from(<[u8; 32]>::from(ask)).unwrap();
-        Self(redjubjub::PublicKey::from(&sk))
+        let sk = redjubjub::SigningKey::<SpendAuth>::try_from(<[u8; 32]>::from(ask)).unwrap();
+        Self(redjubjub::VerificationKey::from(&sk))
     }
 }
 
@@ -486,6 +565,7 @@ impl PartialEq<[u8; 32]> for NullifierDerivingKey {
 mod ivk_hrp {
     pub const MAINNET: &str = "zivks";
     pub const TESTNET: &str = "zivktestsapling";
+    pub const REGTEST: &str = "zivkregtestsapling";
 }
 
 /// An _Incoming Viewing Key_, as described in [protocol specification
@@ -494,7 +574,10 @@ mod ivk_hrp {
 /// Used to decrypt incoming notes without spending them.
 ///
 /// [ps]: https://zips.z.cash/protocol/protocol.pdf#saplingkeycomponents
-#[derive(Copy, Clone, Eq, PartialEq)]
+///
+/// Incoming viewing keys are zeroized when they are dropped, so they aren't
+/// `Copy`.
+#[derive(Clone, Eq, PartialEq)]
 pub struct IncomingViewingKey {
     network: Network,
     scalar: Scalar,
@@ -504,17 +587,51 @@ pub struct IncomingViewingKey {
 
 impl fmt::Debug for IncomingViewingKey {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_tuple("IncomingViewingKey")
-            .field(&hex::encode(self.scalar.to_bytes()))
+        f.debug_struct("IncomingViewingKey")
+            .field("network", &self.network)
+            .field("scalar", &Redacted)
             .finish()
     }
 }
 
+impl IncomingViewingKey {
+    /// The network this key is for.
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Derive the diversified payment address for `diversifier`, so that
+    /// notes sent to it can be detected with this key.
+    ///
+    /// Returns `None` if `diversifier` has no diversified base, which is
+    /// true for about half of all diversifiers.
+    ///
+    /// https://zips.z.cash/protocol/protocol.pdf#saplingkeycomponents
+    pub fn payment_address(&self, diversifier: Diversifier) -> Option<crate::sapling::Address> {
+        let transmission_key = TransmissionKey::derive(self, diversifier)?;
+
+        crate::sapling::Address::new(self.network, diversifier, transmission_key)
+    }
+}
+
+impl Zeroize for IncomingViewingKey {
+    fn zeroize(&mut self) {
+        zeroize_scalar(&mut self.scalar);
+    }
+}
+
+impl Drop for IncomingViewingKey {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
 impl fmt::Display for IncomingViewingKey {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let hrp = match self.network {
             Network::Mainnet => ivk_hrp::MAINNET,
-            _ => ivk_hrp::TESTNET,
+            Network::Testnet => ivk_hrp::TESTNET,
+            Network::Regtest => ivk_hrp::REGTEST,
         };
 
         bech32::encode_to_fmt(f, hrp, &self.scalar.to_bytes().to_base32()).unwrap()
@@ -575,6 +692,7 @@ impl FromStr for IncomingViewingKey {
                 Ok(IncomingViewingKey {
                     network: match hrp.as_str() {
                         ivk_hrp::MAINNET => Network::Mainnet,
+                        ivk_hrp::REGTEST => Network::Regtest,
                         _ => Network::Testnet,
                     },
                     scalar: Scalar::from_bytes(&scalar_bytes).unwrap(),
@@ -598,7 +716,7 @@ impl PartialEq<[u8; 32]> for IncomingViewingKey {
 ///
 /// [ps]: https://zips.z.cash/protocol/protocol.pdf#saplingkeycomponents
 #[derive(Copy, Clone, Eq, PartialEq)]
-#[cfg_attr(test, derive(Arbitrary))]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
 pub struct Diversifier(pub [u8; 11]);
 
 impl fmt::Debug for Diversifier {
@@ -621,7 +739,7 @@ impl From<Diversifier> for [u8; 11] {
     }
 }
 
-impl From<SpendingKey> for Diversifier {
+impl From<&SpendingKey> for Diversifier {
     /// Derives a [_default diversifier_][4.2.2] from a SpendingKey.
     ///
     /// 'For each spending key, there is also a default diversified
@@ -632,7 +750,7 @@ impl From<SpendingKey> for Diversifier {
     /// spending key) from one with a random diversifier...'
     ///
     /// [4.2.2]: https://zips.z.cash/protocol/protocol.pdf#saplingkeycomponents
-    fn from(sk: SpendingKey) -> Diversifier {
+    fn from(sk: &SpendingKey) -> Diversifier {
         let mut i = 0u8;
 
         loop {
@@ -657,6 +775,14 @@ impl PartialEq<[u8; 11]> for Diversifier {
 }
 
 impl Diversifier {
+    /// The diversified base _G_d_ for this diversifier, which is `None` if
+    /// this diversifier can't be used in a payment address.
+    ///
+    /// https://zips.z.cash/protocol/protocol.pdf#concretediversifyhash
+    pub fn diversified_base(&self) -> Option<jubjub::ExtendedPoint> {
+        diversify_hash(self.0)
+    }
+
     /// Generate a new _Diversifier_ that has already been confirmed
     /// as a preimage to a valid diversified base point when used to
     /// derive a diversified payment address.
@@ -678,6 +804,69 @@ impl Diversifier {
     }
 }
 
+/// A _Diversifier Key_, as described in [ZIP-32][zip32].
+///
+/// Wallets use the diversifier key to derive a sequence of diversifiers,
+/// and their diversified payment addresses, from one spending key.
+///
+/// Diversifier keys are zeroized when they are dropped, so they aren't
+/// `Copy`.
+///
+/// [zip32]: https://zips.z.cash/zip-0032#sapling-diversifier-derivation
+#[derive(Clone, Eq, PartialEq)]
+pub struct DiversifierKey([u8; 32]);
+
+impl fmt::Debug for DiversifierKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("DiversifierKey").field(&Redacted).finish()
+    }
+}
+
+impl Zeroize for DiversifierKey {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl Drop for DiversifierKey {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl From<[u8; 32]> for DiversifierKey {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<&DiversifierKey> for [u8; 32] {
+    fn from(dk: &DiversifierKey) -> [u8; 32] {
+        dk.0
+    }
+}
+
+impl From<&SpendingKey> for DiversifierKey {
+    /// For this invocation of Blake2b-512 as _PRF^expand_, t=0x10, as
+    /// used for ZIP-32 master keys.
+    ///
+    /// https://zips.z.cash/zip-0032#sapling-master-key-generation
+    fn from(spending_key: &SpendingKey) -> DiversifierKey {
+        let hash_bytes = prf_expand(spending_key.bytes, &[0x10]);
+
+        let mut bytes = [0u8; 32];
+        bytes[..].copy_from_slice(&hash_bytes[0..32]);
+
+        Self(bytes)
+    }
+}
+
+impl PartialEq<[u8; 32]> for DiversifierKey {
+    fn eq(&self, other: &[u8; 32]) -> bool {
+        self.0 == *other
+    }
+}
+
 /// A (diversified) _TransmissionKey_
 ///
 /// In Sapling, secrets need to be transmitted to a recipient of funds
@@ -727,10 +916,23 @@ impl From<(IncomingViewingKey, Diversifier)> for TransmissionKey {
     ///
     /// https://zips.z.cash/protocol/protocol.pdf#saplingkeycomponents
     /// https://zips.z.cash/protocol/protocol.pdf#concretesaplingkeyagreement
+    ///
+    /// Panics if `d` has no diversified base, use
+    /// [`TransmissionKey::derive`] for diversifiers that haven't been
+    /// checked.
     fn from((ivk, d): (IncomingViewingKey, Diversifier)) -> Self {
-        Self(jubjub::AffinePoint::from(
-            diversify_hash(d.0).unwrap() * ivk.scalar,
-        ))
+        Self::derive(&ivk, d).expect("diversifier has a diversified base")
+    }
+}
+
+impl TransmissionKey {
+    /// Derive the transmission key _pk_d = [ivk] G_d_ for the diversifier
+    /// `d`, returning `None` if `d` has no diversified base _G_d_.
+    ///
+    /// https://zips.z.cash/protocol/protocol.pdf#saplingkeycomponents
+    pub fn derive(ivk: &IncomingViewingKey, d: Diversifier) -> Option<Self> {
+        d.diversified_base()
+            .map(|g_d| Self(jubjub::AffinePoint::from(g_d * ivk.scalar)))
     }
 }
 
@@ -746,6 +948,7 @@ impl PartialEq<[u8; 32]> for TransmissionKey {
 mod fvk_hrp {
     pub const MAINNET: &str = "zviews";
     pub const TESTNET: &str = "zviewtestsapling";
+    pub const REGTEST: &str = "zviewregtestsapling";
 }
 
 /// Full Viewing Keys
@@ -758,7 +961,10 @@ mod fvk_hrp {
 /// test network, the Human-Readable Part is “zviewtestsapling”.
 ///
 /// https://zips.z.cash/protocol/protocol.pdf#saplingfullviewingkeyencoding
-#[derive(Copy, Clone, Eq, PartialEq)]
+///
+/// The outgoing viewing key is zeroized when it is dropped, so full viewing
+/// keys aren't `Copy`.
+#[derive(Clone, Eq, PartialEq)]
 pub struct FullViewingKey {
     network: Network,
     authorizing_key: AuthorizingKey,
@@ -768,6 +974,33 @@ pub struct FullViewingKey {
 
 // TODO: impl a From that accepts a Network?
 
+impl From<&SpendingKey> for FullViewingKey {
+    /// Derive the full viewing key _(ak, nk, ovk)_ of a spending key, on
+    /// the same network.
+    ///
+    /// https://zips.z.cash/protocol/protocol.pdf#saplingkeycomponents
+    fn from(spending_key: &SpendingKey) -> FullViewingKey {
+        FullViewingKey {
+            network: spending_key.network,
+            authorizing_key: AuthorizingKey::from(SpendAuthorizingKey::from(spending_key)),
+            nullifier_deriving_key: NullifierDerivingKey::from(ProofAuthorizingKey::from(
+                spending_key,
+            )),
+            outgoing_viewing_key: OutgoingViewingKey::from(spending_key),
+        }
+    }
+}
+
+impl From<FullViewingKey> for IncomingViewingKey {
+    /// Derive the incoming viewing key of a full viewing key, on the same
+    /// network.
+    fn from(fvk: FullViewingKey) -> IncomingViewingKey {
+        let mut ivk = IncomingViewingKey::from((fvk.authorizing_key, fvk.nullifier_deriving_key));
+        ivk.network = fvk.network;
+        ivk
+    }
+}
+
 impl fmt::Debug for FullViewingKey {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("FullViewingKey")
@@ -785,11 +1018,12 @@ impl fmt::Display for FullViewingKey {
 
         let _ = bytes.write_all(&<[u8; 32]>::from(self.authorizing_key));
         let _ = bytes.write_all(&<[u8; 32]>::from(self.nullifier_deriving_key));
-        let _ = bytes.write_all(&<[u8; 32]>::from(self.outgoing_viewing_key));
+        let _ = bytes.write_all(&self.outgoing_viewing_key.0);
 
         let hrp = match self.network {
             Network::Mainnet => fvk_hrp::MAINNET,
-            _ => fvk_hrp::TESTNET,
+            Network::Testnet => fvk_hrp::TESTNET,
+            Network::Regtest => fvk_hrp::REGTEST,
         };
 
         bech32::encode_to_fmt(f, hrp, bytes.get_ref().to_base32()).unwrap()
@@ -811,6 +1045,7 @@ impl FromStr for FullViewingKey {
                 Ok(FullViewingKey {
                     network: match hrp.as_str() {
                         fvk_hrp::MAINNET => Network::Mainnet,
+                        fvk_hrp::REGTEST => Network::Regtest,
                         _ => Network::Testnet,
                     },
                     authorizing_key: AuthorizingKey::from(authorizing_key_bytes),
@@ -0,0 +1,28 @@
+use proptest::prelude::*;
+
+use super::*;
+
+impl Arbitrary for TransmissionKey {
+    type Parameters = ();
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        (any::<SpendingKey>())
+            .prop_map(|spending_key| {
+                let spend_authorizing_key = SpendAuthorizingKey::from(&spending_key);
+                let proof_authorizing_key = ProofAuthorizingKey::from(&spending_key);
+
+                let authorizing_key = AuthorizingKey::from(spend_authorizing_key);
+                let nullifier_deriving_key = NullifierDerivingKey::from(proof_authorizing_key);
+
+                let incoming_viewing_key =
+                    IncomingViewingKey::from((authorizing_key, nullifier_deriving_key));
+
+                let diversifier = Diversifier::from(&spending_key);
+
+                Self::from((incoming_viewing_key, diversifier))
+            })
+            .boxed()
+    }
+
+    type Strategy = BoxedStrategy<Self>;
+}
@@ -1,35 +1,8 @@
 #![allow(clippy::module_inception)]
 use super::*;
 
-#[cfg(test)]
 use proptest::prelude::*;
 
-#[cfg(test)]
-impl Arbitrary for TransmissionKey {
-    type Parameters = ();
-
-    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
-        (any::<SpendingKey>())
-            .prop_map(|spending_key| {
-                let spend_authorizing_key = SpendAuthorizingKey::from(spending_key);
-                let proof_authorizing_key = ProofAuthorizingKey::from(spending_key);
-
-                let authorizing_key = AuthorizingKey::from(spend_authorizing_key);
-                let nullifier_deriving_key = NullifierDerivingKey::from(proof_authorizing_key);
-
-                let incoming_viewing_key =
-                    IncomingViewingKey::from((authorizing_key, nullifier_deriving_key));
-
-                let diversifier = Diversifier::from(spending_key);
-
-                Self::from((incoming_viewing_key, diversifier))
-            })
-            .boxed()
-    }
-
-    type Strategy = BoxedStrategy<Self>;
-}
-
 #[cfg(test)]
 mod tests {
 
@@ -40,11 +13,11 @@ mod tests {
         for test_vector in test_vectors::TEST_VECTORS.iter() {
             let spending_key = SpendingKey::from(test_vector.sk);
 
-            let spend_authorizing_key = SpendAuthorizingKey::from(spending_key);
+            let spend_authorizing_key = SpendAuthorizingKey::from(&spending_key);
             assert_eq!(spend_authorizing_key, test_vector.ask);
-            let proof_authorizing_key = ProofAuthorizingKey::from(spending_key);
+            let proof_authorizing_key = ProofAuthorizingKey::from(&spending_key);
             assert_eq!(proof_authorizing_key, test_vector.nsk);
-            let outgoing_viewing_key = OutgoingViewingKey::from(spending_key);
+            let outgoing_viewing_key = OutgoingViewingKey::from(&spending_key);
             assert_eq!(outgoing_viewing_key, test_vector.ovk);
 
             let authorizing_key = AuthorizingKey::from(spend_authorizing_key);
@@ -55,18 +28,96 @@ mod tests {
                 IncomingViewingKey::from((authorizing_key, nullifier_deriving_key));
             assert_eq!(incoming_viewing_key, test_vector.ivk);
 
-            let diversifier = Diversifier::from(spending_key);
+            let diversifier = Diversifier::from(&spending_key);
             assert_eq!(diversifier, test_vector.default_d);
 
             let transmission_key = TransmissionKey::from((incoming_viewing_key, diversifier));
             assert_eq!(transmission_key, test_vector.default_pk_d);
 
-            let _full_viewing_key = FullViewingKey {
+            let full_viewing_key = FullViewingKey {
                 network: Network::default(),
                 authorizing_key,
                 nullifier_deriving_key,
                 outgoing_viewing_key,
             };
+            assert_eq!(FullViewingKey::from(&spending_key), full_viewing_key);
+            assert_eq!(IncomingViewingKey::from(full_viewing_key), test_vector.ivk);
+        }
+    }
+
+    #[test]
+    fn payment_address_for_each_test_vector() {
+        for test_vector in test_vectors::TEST_VECTORS.iter() {
+            let incoming_viewing_key = IncomingViewingKey::from(test_vector.ivk);
+
+            let address = incoming_viewing_key
+                .payment_address(Diversifier(test_vector.default_d))
+                .expect("default diversifiers have a diversified base");
+            assert_eq!(address.diversifier(), test_vector.default_d);
+            assert_eq!(address.transmission_key(), test_vector.default_pk_d);
+        }
+    }
+
+    #[test]
+    fn payment_address_rejects_invalid_diversifiers() {
+        let incoming_viewing_key = IncomingViewingKey::from(test_vectors::TEST_VECTORS[0].ivk);
+        let diversifier = (0..=u8::max_value())
+            .map(|i| Diversifier([i; 11]))
+            .find(|d| d.diversified_base().is_none())
+            .expect("about half of all diversifiers are invalid");
+
+        assert!(incoming_viewing_key.payment_address(diversifier).is_none());
+        assert!(TransmissionKey::derive(&incoming_viewing_key, diversifier).is_none());
+    }
+
+    #[test]
+    fn secret_keys_zeroize() {
+        let spending_key = SpendingKey::from([1; 32]);
+
+        let mut spend_authorizing_key = SpendAuthorizingKey::from(&spending_key);
+        spend_authorizing_key.zeroize();
+        assert_eq!(spend_authorizing_key, [0; 32]);
+
+        let mut proof_authorizing_key = ProofAuthorizingKey::from(&spending_key);
+        proof_authorizing_key.zeroize();
+        assert_eq!(proof_authorizing_key, [0; 32]);
+
+        let mut diversifier_key = DiversifierKey::from(&spending_key);
+        assert_ne!(diversifier_key, [0; 32]);
+        diversifier_key.zeroize();
+        assert_eq!(diversifier_key, [0; 32]);
+
+        let mut outgoing_viewing_key = OutgoingViewingKey::from(&spending_key);
+        outgoing_viewing_key.zeroize();
+        assert_eq!(outgoing_viewing_key, [0; 32]);
+
+        let mut spending_key = spending_key;
+        spending_key.zeroize();
+        assert_eq!(spending_key.bytes, [0; 32]);
+    }
+
+    #[test]
+    fn secret_keys_are_redacted() {
+        let test_vector = &test_vectors::TEST_VECTORS[0];
+        let spending_key = SpendingKey::from(test_vector.sk);
+
+        for (debug, secret) in &[
+            (format!("{:?}", spending_key), hex::encode(test_vector.sk)),
+            (
+                format!("{:?}", SpendAuthorizingKey::from(&spending_key)),
+                hex::encode(test_vector.ask),
+            ),
+            (
+                format!("{:?}", FullViewingKey::from(&spending_key)),
+                hex::encode(test_vector.ovk),
+            ),
+            (
+                format!("{:?}", IncomingViewingKey::from(test_vector.ivk)),
+                hex::encode(test_vector.ivk),
+            ),
+        ] {
+            assert!(debug.contains("<redacted>"));
+            assert!(!debug.contains(secret.as_str()));
         }
     }
 }
@@ -78,11 +129,11 @@ proptest! {
     fn string_roundtrips(spending_key in any::<SpendingKey>()) {
         let sk_string = spending_key.to_string();
         let spending_key_2: SpendingKey = sk_string.parse().unwrap();
-        prop_assert_eq![spending_key, spending_key_2];
+        prop_assert_eq![&spending_key, &spending_key_2];
 
-        let spend_authorizing_key = SpendAuthorizingKey::from(spending_key);
-        let proof_authorizing_key = ProofAuthorizingKey::from(spending_key);
-        let outgoing_viewing_key = OutgoingViewingKey::from(spending_key);
+        let spend_authorizing_key = SpendAuthorizingKey::from(&spending_key);
+        let proof_authorizing_key = ProofAuthorizingKey::from(&spending_key);
+        let outgoing_viewing_key = OutgoingViewingKey::from(&spending_key);
 
         let authorizing_key = AuthorizingKey::from(spend_authorizing_key);
         let nullifier_deriving_key = NullifierDerivingKey::from(proof_authorizing_key);
@@ -11,10 +11,11 @@ use std::{fmt, io};
 
 use byteorder::{ByteOrder, LittleEndian};
 use rand_core::{CryptoRng, RngCore};
+use zeroize::Zeroize;
 
-#[cfg(test)]
+#[cfg(any(test, feature = "proptest-impl"))]
 use proptest::{array, prelude::*};
-#[cfg(test)]
+#[cfg(any(test, feature = "proptest-impl"))]
 use proptest_derive::Arbitrary;
 
 use crate::{
@@ -22,6 +23,8 @@ use crate::{
     Network,
 };
 
+use super::Redacted;
+
 /// Magic numbers used to identify with what networks Sprout Spending
 /// Keys are associated.
 mod sk_magics {
@@ -56,8 +59,11 @@ fn prf_addr(x: [u8; 32], t: u8) -> [u8; 32] {
 ///
 /// All other Sprout key types derive from the SpendingKey value.
 /// Actually 252 bits.
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-#[cfg_attr(test, derive(Arbitrary))]
+///
+/// Spending keys are zeroized when they are dropped, so they aren't `Copy`,
+/// and their `Debug` output doesn't include the key.
+#[derive(Clone, Eq, PartialEq)]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
 pub struct SpendingKey {
     /// What would normally be the value inside a tuple struct.
     pub bytes: [u8; 32],
@@ -65,6 +71,27 @@ pub struct SpendingKey {
     pub network: Network,
 }
 
+impl fmt::Debug for SpendingKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SpendingKey")
+            .field("bytes", &Redacted)
+            .field("network", &self.network)
+            .finish()
+    }
+}
+
+impl Zeroize for SpendingKey {
+    fn zeroize(&mut self) {
+        self.bytes.zeroize();
+    }
+}
+
+impl Drop for SpendingKey {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
 impl ZcashSerialize for SpendingKey {
     fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
         match self.network {
@@ -149,16 +176,18 @@ impl SpendingKey {
 }
 
 /// Derived from a _SpendingKey_.
+///
+/// `x25519_dalek` zeroizes receiving keys when they are dropped.
 pub type ReceivingKey = x25519_dalek::StaticSecret;
 
-impl From<SpendingKey> for ReceivingKey {
+impl From<&SpendingKey> for ReceivingKey {
     /// For this invocation of SHA256Compress as PRF^addr, t=0, which
     /// is populated by default in an empty block of all zeros to
     /// start.
     ///
     /// https://zips.z.cash/protocol/protocol.pdf#sproutkeycomponents
     /// https://zips.z.cash/protocol/protocol.pdf#concreteprfs
-    fn from(spending_key: SpendingKey) -> ReceivingKey {
+    fn from(spending_key: &SpendingKey) -> ReceivingKey {
         let derived_bytes = prf_addr(spending_key.bytes, 0);
 
         ReceivingKey::from(derived_bytes)
@@ -177,12 +206,12 @@ impl fmt::Debug for PayingKey {
     }
 }
 
-impl From<SpendingKey> for PayingKey {
+impl From<&SpendingKey> for PayingKey {
     /// For this invocation of SHA256Compress as PRF^addr, t=1.
     ///
     /// https://zips.z.cash/protocol/protocol.pdf#sproutkeycomponents
     /// https://zips.z.cash/protocol/protocol.pdf#concreteprfs
-    fn from(spending_key: SpendingKey) -> PayingKey {
+    fn from(spending_key: &SpendingKey) -> PayingKey {
         let derived_bytes = prf_addr(spending_key.bytes, 1);
 
         PayingKey(derived_bytes)
@@ -222,10 +251,7 @@ impl fmt::Debug for IncomingViewingKey {
         f.debug_struct("IncomingViewingKey")
             .field("network", &self.network)
             .field("paying_key", &hex::encode(&self.paying_key.0))
-            .field(
-                "receiving_key",
-                &hex::encode(&self.receiving_key.to_bytes()),
-            )
+            .field("receiving_key", &Redacted)
             .finish()
     }
 }
@@ -287,7 +313,7 @@ impl std::str::FromStr for IncomingViewingKey {
     }
 }
 
-#[cfg(test)]
+#[cfg(any(test, feature = "proptest-impl"))]
 impl Arbitrary for IncomingViewingKey {
     type Parameters = ();
 
@@ -320,7 +346,7 @@ mod tests {
     fn derive_keys() {
         let spending_key = SpendingKey::new(&mut OsRng);
 
-        let receiving_key = ReceivingKey::from(spending_key);
+        let receiving_key = ReceivingKey::from(&spending_key);
 
         let _transmission_key = TransmissionKey::from(&receiving_key);
     }
@@ -4,37 +4,67 @@
 #![doc(html_root_url = "https://doc.zebra.zfnd.org/zebra_chain")]
 #![deny(missing_docs)]
 
+#[cfg(feature = "serde")]
 #[macro_use]
 extern crate serde;
 
-mod merkle_tree;
+// Lets the serialization derives refer to `zebra_chain` inside this crate.
+extern crate self as zebra_chain;
+
 mod sha256d_writer;
 
 pub mod addresses;
+pub mod amount;
 pub mod block;
-pub mod equihash_solution;
+pub mod history_tree;
 pub mod keys;
-pub mod note_commitment_tree;
+pub mod network_upgrade;
 pub mod notes;
+pub mod orchard;
+pub mod parameters;
 pub mod proofs;
+pub mod sapling;
 pub mod serialization;
+pub mod sprout;
 pub mod transaction;
+pub mod transparent;
 pub mod types;
+pub mod value_balance;
+pub mod work;
 
 pub use ed25519_zebra;
 pub use redjubjub;
 
-#[cfg(test)]
-use proptest_derive::Arbitrary;
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest::prelude::*;
 
 /// An enum describing the possible network choices.
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
-#[cfg_attr(test, derive(Arbitrary))]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Network {
     /// The production mainnet.
     Mainnet,
     /// The testnet.
     Testnet,
+    /// A local regression test network.
+    ///
+    /// Like `zcashd`'s regtest, it has no DNS seeders, an easy proof of work
+    /// limit, and no difficulty adjustment. Regtest uses the testnet
+    /// encodings for transparent and Sprout addresses, and the small
+    /// Equihash (48, 5) parameters, so tests can mine regtest blocks
+    /// quickly.
+    Regtest,
+}
+
+impl Network {
+    /// Returns the default peer-to-peer port for this network.
+    pub fn default_port(&self) -> u16 {
+        match self {
+            Network::Mainnet => 8233,
+            Network::Testnet => 18233,
+            Network::Regtest => 18344,
+        }
+    }
 }
 
 impl Default for Network {
@@ -42,3 +72,16 @@ impl Default for Network {
         Network::Mainnet
     }
 }
+
+#[cfg(any(test, feature = "proptest-impl"))]
+impl Arbitrary for Network {
+    type Parameters = ();
+
+    fn arbitrary_with(_args: ()) -> Self::Strategy {
+        // Regtest shares testnet's encodings for most keys and addresses, so
+        // it doesn't round-trip through them.
+        prop_oneof![Just(Network::Mainnet), Just(Network::Testnet)].boxed()
+    }
+
+    type Strategy = BoxedStrategy<Self>;
+}
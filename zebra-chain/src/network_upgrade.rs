@@ -0,0 +1,342 @@
+//! Network upgrades and their activation heights.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Bound::*;
+
+use chrono::Duration;
+
+use crate::block;
+use crate::Network;
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest_derive::Arbitrary;
+
+/// A Zcash network upgrade.
+///
+/// Network upgrades can change the Zcash network protocol or consensus rules in
+/// incompatible ways.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
+pub enum NetworkUpgrade {
+    /// The Zcash protocol for a Genesis block.
+    ///
+    /// Zcash genesis blocks use a different set of consensus rules from
+    /// other BeforeOverwinter blocks, so we treat them like a separate network
+    /// upgrade.
+    Genesis,
+    /// The Zcash protocol before the Overwinter upgrade.
+    ///
+    /// We avoid using `Sprout`, because the specification says that Sprout
+    /// is the name of the pre-Sapling protocol, before and after Overwinter.
+    BeforeOverwinter,
+    /// The Zcash protocol after the Overwinter upgrade.
+    Overwinter,
+    /// The Zcash protocol after the Sapling upgrade.
+    Sapling,
+    /// The Zcash protocol after the Blossom upgrade.
+    Blossom,
+    /// The Zcash protocol after the Heartwood upgrade.
+    Heartwood,
+    /// The Zcash protocol after the Canopy upgrade.
+    Canopy,
+    /// The Zcash protocol after the NU5 upgrade.
+    Nu5,
+}
+
+/// Mainnet network upgrade activation heights.
+///
+/// This is actually a bijective map, but it is const, so we use a vector, and
+/// do the uniqueness check in the unit tests.
+pub(crate) const MAINNET_ACTIVATION_HEIGHTS: &[(block::Height, NetworkUpgrade)] = {
+    use NetworkUpgrade::*;
+    &[
+        (block::Height(0), Genesis),
+        (block::Height(1), BeforeOverwinter),
+        (block::Height(347_500), Overwinter),
+        (block::Height(419_200), Sapling),
+        (block::Height(653_600), Blossom),
+        (block::Height(903_000), Heartwood),
+        (block::Height(1_046_400), Canopy),
+        (block::Height(1_687_104), Nu5),
+    ]
+};
+
+/// Testnet network upgrade activation heights.
+///
+/// This is actually a bijective map, but it is const, so we use a vector, and
+/// do the uniqueness check in the unit tests.
+pub(crate) const TESTNET_ACTIVATION_HEIGHTS: &[(block::Height, NetworkUpgrade)] = {
+    use NetworkUpgrade::*;
+    &[
+        (block::Height(0), Genesis),
+        (block::Height(1), BeforeOverwinter),
+        (block::Height(207_500), Overwinter),
+        (block::Height(280_000), Sapling),
+        (block::Height(584_000), Blossom),
+        (block::Height(903_800), Heartwood),
+        (block::Height(1_028_500), Canopy),
+        (block::Height(1_842_420), Nu5),
+    ]
+};
+
+/// Regtest network upgrade activation heights.
+///
+/// `zcashd` doesn't activate any upgrades on regtest unless they are set
+/// with `-nuparams`. These are the heights for a `zcashd` regtest node
+/// started with `-nuparams=5ba81b19:2 -nuparams=76b809bb:3
+/// -nuparams=2bb40e60:4 -nuparams=f5b9230b:5 -nuparams=e9ff75a6:6
+/// -nuparams=c2d6d0b4:7`. Block 1 follows the pre-Overwinter rules, like a
+/// default `zcashd` regtest chain, and later upgrades activate as early as
+/// possible, so tests can use the current consensus rules without mining
+/// many blocks.
+pub(crate) const REGTEST_ACTIVATION_HEIGHTS: &[(block::Height, NetworkUpgrade)] = {
+    use NetworkUpgrade::*;
+    &[
+        (block::Height(0), Genesis),
+        (block::Height(1), BeforeOverwinter),
+        (block::Height(2), Overwinter),
+        (block::Height(3), Sapling),
+        (block::Height(4), Blossom),
+        (block::Height(5), Heartwood),
+        (block::Height(6), Canopy),
+        (block::Height(7), Nu5),
+    ]
+};
+
+/// A consensus branch ID, which identifies the consensus rules for a network
+/// upgrade.
+///
+/// Branch IDs are used in transaction signature hashes and v5 transactions,
+/// so that transactions are only valid under the rules they were created for.
+///
+/// [ZIP-200](https://zips.z.cash/zip-0200)
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ConsensusBranchId(pub u32);
+
+impl From<ConsensusBranchId> for u32 {
+    fn from(branch: ConsensusBranchId) -> u32 {
+        branch.0
+    }
+}
+
+impl fmt::Display for ConsensusBranchId {
+    /// Branch IDs are displayed in hex, like `zcashd`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:08x}", self.0)
+    }
+}
+
+/// Network upgrade consensus branch IDs.
+///
+/// Branch IDs are the same for all networks. Genesis and BeforeOverwinter
+/// don't have branch IDs.
+pub(crate) const CONSENSUS_BRANCH_IDS: &[(NetworkUpgrade, ConsensusBranchId)] = {
+    use NetworkUpgrade::*;
+    &[
+        (Overwinter, ConsensusBranchId(0x5ba8_1b19)),
+        (Sapling, ConsensusBranchId(0x76b8_09bb)),
+        (Blossom, ConsensusBranchId(0x2bb4_0e60)),
+        (Heartwood, ConsensusBranchId(0xf5b9_230b)),
+        (Canopy, ConsensusBranchId(0xe9ff_75a6)),
+        (Nu5, ConsensusBranchId(0xc2d6_d0b4)),
+    ]
+};
+
+/// The target block spacing before Blossom, in seconds.
+pub const PRE_BLOSSOM_POW_TARGET_SPACING: i64 = 150;
+
+/// The target block spacing after Blossom, in seconds.
+///
+/// [ZIP-208](https://zips.z.cash/zip-0208)
+pub const POST_BLOSSOM_POW_TARGET_SPACING: i64 = 75;
+
+impl NetworkUpgrade {
+    /// Returns a BTreeMap of activation heights and network upgrades for
+    /// `network`.
+    ///
+    /// If the activation height of a future upgrade is not known, that
+    /// network upgrade does not appear in the list.
+    pub fn activation_list(network: Network) -> BTreeMap<block::Height, NetworkUpgrade> {
+        match network {
+            Network::Mainnet => MAINNET_ACTIVATION_HEIGHTS,
+            Network::Testnet => TESTNET_ACTIVATION_HEIGHTS,
+            Network::Regtest => REGTEST_ACTIVATION_HEIGHTS,
+        }
+        .iter()
+        .cloned()
+        .collect()
+    }
+
+    /// Returns the current network upgrade for `network` and `height`.
+    pub fn current(network: Network, height: block::Height) -> NetworkUpgrade {
+        NetworkUpgrade::activation_list(network)
+            .range(..=height)
+            .map(|(_, nu)| *nu)
+            .next_back()
+            .expect("every height has a current network upgrade")
+    }
+
+    /// Returns the next network upgrade for `network` and `height`.
+    ///
+    /// Returns None if the name of the next upgrade has not been decided yet.
+    pub fn next(network: Network, height: block::Height) -> Option<NetworkUpgrade> {
+        NetworkUpgrade::activation_list(network)
+            .range((Excluded(height), Unbounded))
+            .map(|(_, nu)| *nu)
+            .next()
+    }
+
+    /// Returns the activation height for this network upgrade on `network`.
+    ///
+    /// Returns None if this network upgrade is a future upgrade, and its
+    /// activation height has not been set yet.
+    pub fn activation_height(&self, network: Network) -> Option<block::Height> {
+        NetworkUpgrade::activation_list(network)
+            .iter()
+            .filter(|(_, nu)| nu == &self)
+            .map(|(height, _)| *height)
+            .next()
+    }
+
+    /// Returns the consensus branch ID for this network upgrade.
+    ///
+    /// Returns None for Genesis and BeforeOverwinter, which don't have
+    /// branch IDs.
+    pub fn branch_id(&self) -> Option<ConsensusBranchId> {
+        CONSENSUS_BRANCH_IDS
+            .iter()
+            .filter(|(nu, _)| nu == self)
+            .map(|(_, branch_id)| *branch_id)
+            .next()
+    }
+
+    /// Returns the target block spacing for this network upgrade.
+    pub fn target_spacing(&self) -> Duration {
+        use NetworkUpgrade::*;
+        let seconds = match self {
+            Genesis | BeforeOverwinter | Overwinter | Sapling => PRE_BLOSSOM_POW_TARGET_SPACING,
+            Blossom | Heartwood | Canopy | Nu5 => POST_BLOSSOM_POW_TARGET_SPACING,
+        };
+        Duration::seconds(seconds)
+    }
+
+    /// Returns the target block spacing for `network` and `height`.
+    pub fn target_spacing_for_height(network: Network, height: block::Height) -> Duration {
+        NetworkUpgrade::current(network, height).target_spacing()
+    }
+}
+
+impl ConsensusBranchId {
+    /// Returns the current consensus branch ID for `network` and `height`.
+    ///
+    /// Returns None if the current network upgrade doesn't have a branch ID.
+    pub fn current(network: Network, height: block::Height) -> Option<ConsensusBranchId> {
+        NetworkUpgrade::current(network, height).branch_id()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+
+    /// Check that the activation height lists are bijective maps, and are
+    /// ordered by height.
+    #[test]
+    fn activation_heights_bijective_and_ordered() {
+        for list in &[
+            MAINNET_ACTIVATION_HEIGHTS,
+            TESTNET_ACTIVATION_HEIGHTS,
+            REGTEST_ACTIVATION_HEIGHTS,
+        ] {
+            let heights: HashSet<_> = list.iter().map(|(height, _)| *height).collect();
+            let upgrades: HashSet<_> = list.iter().map(|(_, nu)| *nu).collect();
+            assert_eq!(heights.len(), list.len());
+            assert_eq!(upgrades.len(), list.len());
+
+            assert!(list.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        }
+    }
+
+    #[test]
+    fn regtest_heights_match_the_documented_nuparams() {
+        let nuparams: Vec<_> = NetworkUpgrade::activation_list(Network::Regtest)
+            .into_iter()
+            .filter_map(|(height, nu)| {
+                nu.branch_id()
+                    .map(|branch_id| format!("-nuparams={}:{}", branch_id, height.0))
+            })
+            .collect();
+
+        assert_eq!(
+            nuparams.join(" "),
+            "-nuparams=5ba81b19:2 -nuparams=76b809bb:3 -nuparams=2bb40e60:4 \
+             -nuparams=f5b9230b:5 -nuparams=e9ff75a6:6 -nuparams=c2d6d0b4:7"
+        );
+    }
+
+    #[test]
+    fn current_and_next_upgrades() {
+        use NetworkUpgrade::*;
+
+        for &network in &[Network::Mainnet, Network::Testnet, Network::Regtest] {
+            assert_eq!(NetworkUpgrade::current(network, block::Height(0)), Genesis);
+            assert_eq!(
+                NetworkUpgrade::next(network, block::Height(0)),
+                Some(BeforeOverwinter)
+            );
+
+            let sapling = Sapling
+                .activation_height(network)
+                .expect("Sapling activation height is known");
+            assert_eq!(NetworkUpgrade::current(network, sapling), Sapling);
+            assert_eq!(
+                NetworkUpgrade::current(network, sapling.previous().unwrap()),
+                Overwinter
+            );
+            assert_eq!(NetworkUpgrade::next(network, sapling), Some(Blossom));
+        }
+    }
+
+    #[test]
+    fn branch_ids_and_target_spacing() {
+        use NetworkUpgrade::*;
+
+        let branch_ids: HashSet<_> = CONSENSUS_BRANCH_IDS.iter().map(|(_, id)| *id).collect();
+        assert_eq!(branch_ids.len(), CONSENSUS_BRANCH_IDS.len());
+
+        assert_eq!(Genesis.branch_id(), None);
+        assert_eq!(BeforeOverwinter.branch_id(), None);
+        assert_eq!(
+            Sapling.branch_id().map(|id| id.to_string()),
+            Some("76b809bb".to_string())
+        );
+
+        let sapling = Sapling.activation_height(Network::Mainnet).unwrap();
+        assert_eq!(
+            ConsensusBranchId::current(Network::Mainnet, sapling),
+            Sapling.branch_id()
+        );
+        assert_eq!(
+            ConsensusBranchId::current(Network::Mainnet, block::Height(1)),
+            None
+        );
+
+        let blossom = Blossom.activation_height(Network::Mainnet).unwrap();
+        assert_eq!(
+            NetworkUpgrade::target_spacing_for_height(Network::Mainnet, blossom),
+            Duration::seconds(75)
+        );
+        assert_eq!(
+            NetworkUpgrade::target_spacing_for_height(
+                Network::Mainnet,
+                blossom.previous().unwrap()
+            ),
+            Duration::seconds(150)
+        );
+    }
+}
@@ -1,12 +1,17 @@
 //!
 #![allow(dead_code)]
 
-use std::{fmt, io};
+use std::fmt;
 
-#[cfg(test)]
+#[cfg(any(test, feature = "proptest-impl"))]
 use proptest::{arbitrary::Arbitrary, collection::vec, prelude::*};
 
-use crate::serialization::{SerializationError, ZcashDeserialize, ZcashSerialize};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[cfg(feature = "serde")]
+use crate::serialization::serde_hex;
+use crate::serialization::{ZcashDeserialize, ZcashSerialize};
 
 use super::*;
 
@@ -39,7 +44,8 @@ pub struct NotePlaintext {
 }
 
 /// A ciphertext component for encrypted output notes.
-pub struct EncryptedCiphertext(pub [u8; 580]);
+#[derive(ZcashSerialize, ZcashDeserialize)]
+pub struct EncryptedCiphertext(#[zcash(fixed)] pub [u8; 580]);
 
 impl fmt::Debug for EncryptedCiphertext {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -69,22 +75,23 @@ impl PartialEq for EncryptedCiphertext {
 
 impl Eq for EncryptedCiphertext {}
 
-impl ZcashSerialize for EncryptedCiphertext {
-    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
-        writer.write_all(&self.0[..])?;
-        Ok(())
+#[cfg(feature = "serde")]
+impl Serialize for EncryptedCiphertext {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde_hex::serialize(&self.0[..], serializer)
     }
 }
 
-impl ZcashDeserialize for EncryptedCiphertext {
-    fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for EncryptedCiphertext {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         let mut bytes = [0; 580];
-        reader.read_exact(&mut bytes[..])?;
+        serde_hex::deserialize_into(deserializer, &mut bytes)?;
         Ok(Self(bytes))
     }
 }
 
-#[cfg(test)]
+#[cfg(any(test, feature = "proptest-impl"))]
 impl Arbitrary for EncryptedCiphertext {
     type Parameters = ();
 
@@ -102,7 +109,8 @@ impl Arbitrary for EncryptedCiphertext {
 }
 
 /// A ciphertext component for encrypted output notes.
-pub struct OutCiphertext(pub [u8; 80]);
+#[derive(ZcashSerialize, ZcashDeserialize)]
+pub struct OutCiphertext(#[zcash(fixed)] pub [u8; 80]);
 
 impl fmt::Debug for OutCiphertext {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -132,22 +140,23 @@ impl PartialEq for OutCiphertext {
 
 impl Eq for OutCiphertext {}
 
-impl ZcashSerialize for OutCiphertext {
-    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
-        writer.write_all(&self.0[..])?;
-        Ok(())
+#[cfg(feature = "serde")]
+impl Serialize for OutCiphertext {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde_hex::serialize(&self.0[..], serializer)
     }
 }
 
-impl ZcashDeserialize for OutCiphertext {
-    fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for OutCiphertext {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         let mut bytes = [0; 80];
-        reader.read_exact(&mut bytes[..])?;
+        serde_hex::deserialize_into(deserializer, &mut bytes)?;
         Ok(Self(bytes))
     }
 }
 
-#[cfg(test)]
+#[cfg(any(test, feature = "proptest-impl"))]
 impl Arbitrary for OutCiphertext {
     type Parameters = ();
 
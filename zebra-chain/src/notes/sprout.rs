@@ -1,15 +1,17 @@
 //!
 #![allow(dead_code)]
 
-use std::{
-    fmt,
-    io::{self},
-};
+use std::fmt;
 
-#[cfg(test)]
+#[cfg(any(test, feature = "proptest-impl"))]
 use proptest::{collection::vec, prelude::*};
 
-use crate::serialization::{SerializationError, ZcashDeserialize, ZcashSerialize};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[cfg(feature = "serde")]
+use crate::serialization::serde_hex;
+use crate::serialization::{ZcashDeserialize, ZcashSerialize};
 
 use super::{memo::Memo, *};
 
@@ -34,7 +36,8 @@ pub struct NotePlaintext {
 }
 
 /// A ciphertext component for encrypted output notes.
-pub struct EncryptedCiphertext(pub [u8; 601]);
+#[derive(ZcashSerialize, ZcashDeserialize)]
+pub struct EncryptedCiphertext(#[zcash(fixed)] pub [u8; 601]);
 
 impl fmt::Debug for EncryptedCiphertext {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -64,22 +67,23 @@ impl PartialEq for EncryptedCiphertext {
 
 impl Eq for EncryptedCiphertext {}
 
-impl ZcashSerialize for EncryptedCiphertext {
-    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
-        writer.write_all(&self.0[..])?;
-        Ok(())
+#[cfg(feature = "serde")]
+impl Serialize for EncryptedCiphertext {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde_hex::serialize(&self.0[..], serializer)
     }
 }
 
-impl ZcashDeserialize for EncryptedCiphertext {
-    fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for EncryptedCiphertext {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         let mut bytes = [0; 601];
-        reader.read_exact(&mut bytes[..])?;
+        serde_hex::deserialize_into(deserializer, &mut bytes)?;
         Ok(Self(bytes))
     }
 }
 
-#[cfg(test)]
+#[cfg(any(test, feature = "proptest-impl"))]
 impl Arbitrary for EncryptedCiphertext {
     type Parameters = ();
 
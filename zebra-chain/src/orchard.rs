@@ -0,0 +1,13 @@
+//! Orchard shielded transfers, introduced in NU5.
+
+mod action;
+mod note;
+mod nullifier;
+mod shielded_data;
+
+pub mod tree;
+
+pub use action::Action;
+pub use note::{EncryptedNote, WrappedNoteKey};
+pub use nullifier::Nullifier;
+pub use shielded_data::{Flags, RedPallasSignature, ShieldedData};
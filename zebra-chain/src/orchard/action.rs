@@ -0,0 +1,101 @@
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest::{arbitrary::Arbitrary, array, prelude::*};
+
+use super::{EncryptedNote, Nullifier, RedPallasSignature, WrappedNoteKey};
+
+/// An _Action Description_, as described in [protocol specification §7.5][ps].
+///
+/// Each action spends one note and creates another, so that the number of
+/// spends and outputs in a transaction is hidden.
+///
+/// [ps]: https://zips.z.cash/protocol/nu5.pdf#actionencodingandconsensus
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Action {
+    /// A value commitment to the net value of the input note minus the output
+    /// note.
+    ///
+    /// XXX refine to a specific type.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::serde_hex::bytes32")
+    )]
+    pub cv: [u8; 32],
+    /// The nullifier of the input note.
+    pub nullifier: Nullifier,
+    /// The randomized validating key for `spend_auth_sig`.
+    ///
+    /// XXX refine to a specific type.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::serde_hex::bytes32")
+    )]
+    pub rk: [u8; 32],
+    /// The x-coordinate of the note commitment for the output note.
+    ///
+    /// XXX refine to a specific type.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::serde_hex::bytes32")
+    )]
+    pub cm_x: [u8; 32],
+    /// An encoding of an ephemeral Pallas public key.
+    ///
+    /// XXX refine to a specific type.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::serde_hex::bytes32")
+    )]
+    pub ephemeral_key: [u8; 32],
+    /// The encrypted output note.
+    pub enc_ciphertext: EncryptedNote,
+    /// The output note's encryption key, encrypted for the sender.
+    pub out_ciphertext: WrappedNoteKey,
+    /// A signature authorizing the spend in this action.
+    ///
+    /// This is serialized after all the actions in the transaction, but
+    /// belongs to a single action.
+    pub spend_auth_sig: RedPallasSignature,
+}
+
+#[cfg(any(test, feature = "proptest-impl"))]
+impl Arbitrary for Action {
+    type Parameters = ();
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        (
+            array::uniform32(any::<u8>()),
+            any::<Nullifier>(),
+            array::uniform32(any::<u8>()),
+            array::uniform32(any::<u8>()),
+            array::uniform32(any::<u8>()),
+            any::<EncryptedNote>(),
+            any::<WrappedNoteKey>(),
+            any::<RedPallasSignature>(),
+        )
+            .prop_map(
+                |(
+                    cv,
+                    nullifier,
+                    rk,
+                    cm_x,
+                    ephemeral_key,
+                    enc_ciphertext,
+                    out_ciphertext,
+                    spend_auth_sig,
+                )| Self {
+                    cv,
+                    nullifier,
+                    rk,
+                    cm_x,
+                    ephemeral_key,
+                    enc_ciphertext,
+                    out_ciphertext,
+                    spend_auth_sig,
+                },
+            )
+            .boxed()
+    }
+
+    type Strategy = BoxedStrategy<Self>;
+}
@@ -0,0 +1,140 @@
+use std::fmt;
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest::{arbitrary::Arbitrary, collection::vec, prelude::*};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[cfg(feature = "serde")]
+use crate::serialization::serde_hex;
+use crate::serialization::{ZcashDeserialize, ZcashSerialize};
+
+/// An encrypted Orchard note, which contains the note plaintext and memo.
+#[derive(ZcashSerialize, ZcashDeserialize)]
+pub struct EncryptedNote(#[zcash(fixed)] pub [u8; 580]);
+
+impl fmt::Debug for EncryptedNote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("EncryptedNote")
+            .field(&hex::encode(&self.0[..]))
+            .finish()
+    }
+}
+
+// These impls all only exist because of array length restrictions.
+
+impl Copy for EncryptedNote {}
+
+impl Clone for EncryptedNote {
+    fn clone(&self) -> Self {
+        let mut bytes = [0; 580];
+        bytes[..].copy_from_slice(&self.0[..]);
+        Self(bytes)
+    }
+}
+
+impl PartialEq for EncryptedNote {
+    fn eq(&self, other: &Self) -> bool {
+        self.0[..] == other.0[..]
+    }
+}
+
+impl Eq for EncryptedNote {}
+
+#[cfg(feature = "serde")]
+impl Serialize for EncryptedNote {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde_hex::serialize(&self.0[..], serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for EncryptedNote {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut bytes = [0; 580];
+        serde_hex::deserialize_into(deserializer, &mut bytes)?;
+        Ok(Self(bytes))
+    }
+}
+
+/// The note encryption key, encrypted to the sender's outgoing viewing key,
+/// so that senders can recover the notes they sent.
+#[derive(ZcashSerialize, ZcashDeserialize)]
+pub struct WrappedNoteKey(#[zcash(fixed)] pub [u8; 80]);
+
+impl fmt::Debug for WrappedNoteKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("WrappedNoteKey")
+            .field(&hex::encode(&self.0[..]))
+            .finish()
+    }
+}
+
+impl Copy for WrappedNoteKey {}
+
+impl Clone for WrappedNoteKey {
+    fn clone(&self) -> Self {
+        let mut bytes = [0; 80];
+        bytes[..].copy_from_slice(&self.0[..]);
+        Self(bytes)
+    }
+}
+
+impl PartialEq for WrappedNoteKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0[..] == other.0[..]
+    }
+}
+
+impl Eq for WrappedNoteKey {}
+
+#[cfg(feature = "serde")]
+impl Serialize for WrappedNoteKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde_hex::serialize(&self.0[..], serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for WrappedNoteKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut bytes = [0; 80];
+        serde_hex::deserialize_into(deserializer, &mut bytes)?;
+        Ok(Self(bytes))
+    }
+}
+
+#[cfg(any(test, feature = "proptest-impl"))]
+impl Arbitrary for EncryptedNote {
+    type Parameters = ();
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        (vec(any::<u8>(), 580))
+            .prop_map(|v| {
+                let mut bytes = [0; 580];
+                bytes.copy_from_slice(v.as_slice());
+                Self(bytes)
+            })
+            .boxed()
+    }
+
+    type Strategy = BoxedStrategy<Self>;
+}
+
+#[cfg(any(test, feature = "proptest-impl"))]
+impl Arbitrary for WrappedNoteKey {
+    type Parameters = ();
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        (vec(any::<u8>(), 80))
+            .prop_map(|v| {
+                let mut bytes = [0; 80];
+                bytes.copy_from_slice(v.as_slice());
+                Self(bytes)
+            })
+            .boxed()
+    }
+
+    type Strategy = BoxedStrategy<Self>;
+}
@@ -0,0 +1,44 @@
+#![allow(clippy::unit_arg)]
+use std::fmt;
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest_derive::Arbitrary;
+
+use crate::serialization::{ZcashDeserialize, ZcashSerialize};
+
+/// An Orchard nullifier, which is revealed when an Action spends a note.
+///
+/// Orchard nullifiers are elements of the Pallas base field, encoded as 32
+/// little-endian bytes. Each pool has its own nullifier set, because a Sapling
+/// and an Orchard nullifier with the same bytes spend different notes.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, ZcashSerialize, ZcashDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
+pub struct Nullifier(
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::serde_hex::bytes32")
+    )]
+    #[zcash(fixed)]
+    pub [u8; 32],
+);
+
+impl fmt::Debug for Nullifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("orchard::Nullifier")
+            .field(&hex::encode(&self.0))
+            .finish()
+    }
+}
+
+impl From<[u8; 32]> for Nullifier {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<Nullifier> for [u8; 32] {
+    fn from(nullifier: Nullifier) -> Self {
+        nullifier.0
+    }
+}
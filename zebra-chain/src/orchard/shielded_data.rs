@@ -0,0 +1,210 @@
+#![allow(clippy::unit_arg)]
+use std::fmt;
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest::{arbitrary::Arbitrary, collection::vec, prelude::*};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[cfg(feature = "serde")]
+use crate::serialization::serde_hex;
+use crate::{amount::Amount, proofs::Halo2Proof};
+
+use super::{tree, Action, EncryptedNote, Nullifier};
+
+/// A RedPallas signature, encoded as bytes.
+///
+/// Signatures are decoded when they are verified, so a malformed signature
+/// is a verification failure, not a parse error.
+pub struct RedPallasSignature(pub [u8; 64]);
+
+impl fmt::Debug for RedPallasSignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("RedPallasSignature")
+            .field(&hex::encode(&self.0[..]))
+            .finish()
+    }
+}
+
+// These impls all only exist because of array length restrictions.
+
+impl Copy for RedPallasSignature {}
+
+impl Clone for RedPallasSignature {
+    fn clone(&self) -> Self {
+        let mut bytes = [0; 64];
+        bytes[..].copy_from_slice(&self.0[..]);
+        Self(bytes)
+    }
+}
+
+impl PartialEq for RedPallasSignature {
+    fn eq(&self, other: &Self) -> bool {
+        self.0[..] == other.0[..]
+    }
+}
+
+impl Eq for RedPallasSignature {}
+
+#[cfg(feature = "serde")]
+impl Serialize for RedPallasSignature {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde_hex::serialize(&self.0[..], serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for RedPallasSignature {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut bytes = [0; 64];
+        serde_hex::deserialize_into(deserializer, &mut bytes)?;
+        Ok(Self(bytes))
+    }
+}
+
+impl From<[u8; 64]> for RedPallasSignature {
+    fn from(bytes: [u8; 64]) -> Self {
+        Self(bytes)
+    }
+}
+
+/// The flags that enable spends and outputs in an Orchard bundle.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    any(test, feature = "proptest-impl"),
+    derive(proptest_derive::Arbitrary)
+)]
+pub struct Flags {
+    /// Whether the actions may spend notes.
+    pub enable_spends: bool,
+    /// Whether the actions may create new notes.
+    pub enable_outputs: bool,
+}
+
+impl Flags {
+    const ENABLE_SPENDS: u8 = 1 << 0;
+    const ENABLE_OUTPUTS: u8 = 1 << 1;
+
+    /// Encode these flags as a byte.
+    pub fn to_byte(self) -> u8 {
+        let mut byte = 0;
+        if self.enable_spends {
+            byte |= Self::ENABLE_SPENDS;
+        }
+        if self.enable_outputs {
+            byte |= Self::ENABLE_OUTPUTS;
+        }
+        byte
+    }
+
+    /// Decode flags from a byte, returning `None` if any reserved bits are
+    /// set.
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        if byte & !(Self::ENABLE_SPENDS | Self::ENABLE_OUTPUTS) != 0 {
+            return None;
+        }
+        Some(Flags {
+            enable_spends: byte & Self::ENABLE_SPENDS != 0,
+            enable_outputs: byte & Self::ENABLE_OUTPUTS != 0,
+        })
+    }
+}
+
+/// Orchard actions, and the data that applies to all of them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ShieldedData {
+    /// The flags for all the actions.
+    pub flags: Flags,
+    /// The net value of Orchard spends minus outputs.
+    pub value_balance: Amount,
+    /// The root of the Orchard note commitment tree that all the spends use.
+    pub shared_anchor: tree::Root,
+    /// The aggregated proof for all the actions.
+    pub proof: Halo2Proof,
+    /// The first action.
+    ///
+    /// Storing this separately from `rest` ensures that it is impossible
+    /// to construct an invalid `ShieldedData` with no actions.
+    ///
+    /// The [`ShieldedData::actions`] method provides an iterator over all of
+    /// the `Action`s.
+    pub first: Action,
+    /// The rest of the actions.
+    pub rest: Vec<Action>,
+    /// A signature on the transaction hash.
+    pub binding_sig: RedPallasSignature,
+}
+
+impl ShieldedData {
+    /// Iterate over the [`Action`]s in `self`.
+    pub fn actions(&self) -> impl Iterator<Item = &Action> {
+        std::iter::once(&self.first).chain(self.rest.iter())
+    }
+
+    /// Iterate over the nullifiers of the notes spent by `self`.
+    pub fn nullifiers(&self) -> impl Iterator<Item = &Nullifier> {
+        self.actions().map(|action| &action.nullifier)
+    }
+
+    /// Iterate over the x-coordinates of the note commitments for the notes
+    /// created by `self`.
+    pub fn note_commitments(&self) -> impl Iterator<Item = &[u8; 32]> {
+        self.actions().map(|action| &action.cm_x)
+    }
+
+    /// Iterate over the encrypted notes created by `self`.
+    pub fn encrypted_notes(&self) -> impl Iterator<Item = &EncryptedNote> {
+        self.actions().map(|action| &action.enc_ciphertext)
+    }
+}
+
+#[cfg(any(test, feature = "proptest-impl"))]
+impl Arbitrary for RedPallasSignature {
+    type Parameters = ();
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        vec(any::<u8>(), 64)
+            .prop_map(|sig_bytes| {
+                let mut b = [0u8; 64];
+                b.copy_from_slice(sig_bytes.as_slice());
+                Self(b)
+            })
+            .boxed()
+    }
+
+    type Strategy = BoxedStrategy<Self>;
+}
+
+#[cfg(any(test, feature = "proptest-impl"))]
+impl Arbitrary for ShieldedData {
+    type Parameters = ();
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        (
+            any::<Flags>(),
+            any::<Amount>(),
+            any::<tree::Root>(),
+            any::<Halo2Proof>(),
+            any::<Action>(),
+            vec(any::<Action>(), 0..10),
+            any::<RedPallasSignature>(),
+        )
+            .prop_map(
+                |(flags, value_balance, shared_anchor, proof, first, rest, binding_sig)| Self {
+                    flags,
+                    value_balance,
+                    shared_anchor,
+                    proof,
+                    first,
+                    rest,
+                    binding_sig,
+                },
+            )
+            .boxed()
+    }
+
+    type Strategy = BoxedStrategy<Self>;
+}
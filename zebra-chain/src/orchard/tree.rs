@@ -0,0 +1,357 @@
+//! The Orchard note commitment tree.
+//!
+//! Like the [Sapling tree](crate::sapling::tree), this is an incremental
+//! Merkle tree of depth 32, but its leaves are the x-coordinates of Orchard
+//! note commitments, and its nodes are hashed with Sinsemilla over the Pallas
+//! curve.
+//!
+//! Each treestate has a root, called an anchor, which Orchard actions use to
+//! prove that the notes they spend exist.
+#![allow(clippy::unit_arg)]
+
+use std::{fmt, io};
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use lazy_static::lazy_static;
+use once_cell::sync::OnceCell;
+use pasta_curves::{
+    arithmetic::{Coordinates, CurveAffine, CurveExt},
+    group::ff::{Field, PrimeField},
+    pallas,
+};
+use thiserror::Error;
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest_derive::Arbitrary;
+
+use crate::serialization::{
+    ReadZcashExt, SerializationError, WriteZcashExt, ZcashDeserialize, ZcashSerialize,
+};
+
+/// The depth of the Orchard note commitment tree.
+pub const MERKLE_DEPTH: usize = 32;
+
+/// The number of bits in a node, which is an element of the Pallas base
+/// field.
+const NODE_BITS: usize = 255;
+
+/// The number of bits in each Sinsemilla chunk.
+const CHUNK_BITS: usize = 10;
+
+lazy_static! {
+    /// The Sinsemilla generators, one for each value of a chunk.
+    static ref SINSEMILLA_S: Vec<pallas::Point> = {
+        let hash = pallas::Point::hash_to_curve("z.cash:SinsemillaS");
+        (0..1u32 << CHUNK_BITS)
+            .map(|j| hash(&j.to_le_bytes()))
+            .collect()
+    };
+
+    /// The initial point of `MerkleCRH^Orchard`'s Sinsemilla hash.
+    static ref MERKLE_CRH_Q: pallas::Point =
+        pallas::Point::hash_to_curve("z.cash:SinsemillaQ")(b"z.cash:Orchard-MerkleCRH");
+
+    /// The roots of empty subtrees, indexed by their height above the
+    /// leaves.
+    ///
+    /// The empty leaf is `Uncommitted^Orchard`, which is 2 in the base field.
+    static ref EMPTY_ROOTS: Vec<[u8; 32]> = {
+        let mut uncommitted = [0; 32];
+        uncommitted[0] = 2;
+        let mut roots = vec![uncommitted];
+        for height in 0..MERKLE_DEPTH {
+            let below = roots[height];
+            roots.push(merkle_crh(height, below, below));
+        }
+        roots
+    };
+}
+
+/// Iterate over the bits of `bytes`, least significant bit first.
+fn le_bits(bytes: [u8; 32]) -> impl Iterator<Item = bool> {
+    (0..NODE_BITS).map(move |i| (bytes[i / 8] >> (i % 8)) & 1 == 1)
+}
+
+/// The Sinsemilla hash of `bits`, starting from `q`, as a point on the
+/// Pallas curve.
+///
+/// `bits` must be a whole number of chunks.
+///
+/// https://zips.z.cash/protocol/protocol.pdf#concretesinsemillahash
+fn sinsemilla_hash_to_point(q: pallas::Point, bits: &[bool]) -> pallas::Point {
+    let mut acc = q;
+    for chunk in bits.chunks(CHUNK_BITS) {
+        let j = chunk
+            .iter()
+            .enumerate()
+            .fold(0, |j, (i, bit)| j | ((*bit as usize) << i));
+        acc = (acc + SINSEMILLA_S[j]) + acc;
+    }
+    acc
+}
+
+/// MerkleCRH^Orchard, the hash of two child nodes at `height` above the
+/// leaves.
+///
+/// If the hash is the identity, which has no x-coordinate, the node is 0.
+///
+/// https://zips.z.cash/protocol/protocol.pdf#orchardmerklecrh
+fn merkle_crh(height: usize, left: [u8; 32], right: [u8; 32]) -> [u8; 32] {
+    let mut bits = Vec::with_capacity(CHUNK_BITS + 2 * NODE_BITS);
+    bits.extend((0..CHUNK_BITS).map(|i| (height >> i) & 1 == 1));
+    bits.extend(le_bits(left));
+    bits.extend(le_bits(right));
+
+    // Extract the x-coordinate of the point.
+    let point = pallas::Affine::from(sinsemilla_hash_to_point(*MERKLE_CRH_Q, &bits));
+    Option::<Coordinates<pallas::Affine>>::from(point.coordinates())
+        .map(|coordinates| *coordinates.x())
+        .unwrap_or_else(pallas::Base::zero)
+        .to_repr()
+}
+
+/// The root of an Orchard note commitment tree, also known as an anchor.
+///
+/// The root is an element of the Pallas base field, encoded as 32
+/// little-endian bytes.
+#[derive(Clone, Copy, Default, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
+pub struct Root(
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::serde_hex::bytes32")
+    )]
+    pub [u8; 32],
+);
+
+impl fmt::Debug for Root {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("orchard::tree::Root")
+            .field(&hex::encode(&self.0))
+            .finish()
+    }
+}
+
+/// An error appending a note commitment to a [`NoteCommitmentTree`].
+#[derive(Error, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum NoteCommitmentTreeError {
+    /// The tree already has 2^32 note commitments.
+    #[error("the note commitment tree is full")]
+    FullTree,
+    /// The note commitment isn't an element of the Pallas base field.
+    #[error("the note commitment is not a valid Pallas base field element")]
+    NonCanonicalCommitment,
+}
+
+/// An incremental Orchard note commitment tree.
+///
+/// Like the Sapling tree, the tree only stores its frontier, and it
+/// serializes the same way as `zcashd`'s legacy Orchard tree encoding, which
+/// `z_gettreestate` returns.
+#[derive(Clone, Debug, Default)]
+pub struct NoteCommitmentTree {
+    left: Option<[u8; 32]>,
+    right: Option<[u8; 32]>,
+    /// The roots of complete subtrees, with `parents[i]` at height `i + 1`.
+    parents: Vec<Option<[u8; 32]>>,
+    /// The root, if it has been computed since the last append.
+    cached_root: OnceCell<Root>,
+}
+
+impl NoteCommitmentTree {
+    /// Append the note commitment `cm_x` as the next leaf of the tree.
+    ///
+    /// This is the x-coordinate of the commitment in an Orchard action.
+    pub fn append(&mut self, cm_x: [u8; 32]) -> Result<(), NoteCommitmentTreeError> {
+        if Option::<pallas::Base>::from(pallas::Base::from_repr(cm_x)).is_none() {
+            return Err(NoteCommitmentTreeError::NonCanonicalCommitment);
+        }
+        if self.is_complete() {
+            return Err(NoteCommitmentTreeError::FullTree);
+        }
+        self.cached_root = OnceCell::new();
+
+        let (left, right) = match (self.left, self.right) {
+            (None, _) => {
+                self.left = Some(cm_x);
+                return Ok(());
+            }
+            (Some(_), None) => {
+                self.right = Some(cm_x);
+                return Ok(());
+            }
+            (Some(left), Some(right)) => (left, right),
+        };
+
+        // Both leaves are full, so carry their hash up into the parents.
+        self.left = Some(cm_x);
+        self.right = None;
+        let mut combined = merkle_crh(0, left, right);
+        for (i, parent) in self.parents.iter_mut().enumerate() {
+            match parent.take() {
+                Some(p) => combined = merkle_crh(i + 1, p, combined),
+                None => {
+                    *parent = Some(combined);
+                    return Ok(());
+                }
+            }
+        }
+        self.parents.push(Some(combined));
+        Ok(())
+    }
+
+    /// Return the root of the tree, which is the anchor for spends of the
+    /// notes in it.
+    pub fn root(&self) -> Root {
+        *self.cached_root.get_or_init(|| self.compute_root())
+    }
+
+    fn compute_root(&self) -> Root {
+        let empty_leaf = EMPTY_ROOTS[0];
+        let mut root = merkle_crh(
+            0,
+            self.left.unwrap_or(empty_leaf),
+            self.right.unwrap_or(empty_leaf),
+        );
+        for height in 1..MERKLE_DEPTH {
+            root = match self.parents.get(height - 1) {
+                Some(Some(parent)) => merkle_crh(height, *parent, root),
+                _ => merkle_crh(height, root, EMPTY_ROOTS[height]),
+            };
+        }
+
+        Root(root)
+    }
+
+    /// Return the number of note commitments in the tree.
+    pub fn count(&self) -> u64 {
+        let leaves = self.left.is_some() as u64 + self.right.is_some() as u64;
+        self.parents
+            .iter()
+            .enumerate()
+            .filter(|(_, parent)| parent.is_some())
+            .fold(leaves, |count, (i, _)| count + (1 << (i + 1)))
+    }
+
+    fn is_complete(&self) -> bool {
+        self.left.is_some()
+            && self.right.is_some()
+            && self.parents.len() == MERKLE_DEPTH - 1
+            && self.parents.iter().all(Option::is_some)
+    }
+}
+
+impl PartialEq for NoteCommitmentTree {
+    fn eq(&self, other: &Self) -> bool {
+        self.left == other.left && self.right == other.right && self.parents == other.parents
+    }
+}
+
+impl Eq for NoteCommitmentTree {}
+
+fn write_node<W: io::Write>(node: Option<[u8; 32]>, mut writer: W) -> Result<(), io::Error> {
+    match node {
+        Some(node) => {
+            writer.write_u8(1)?;
+            writer.write_all(&node[..])
+        }
+        None => writer.write_u8(0),
+    }
+}
+
+fn read_node<R: io::Read>(mut reader: R) -> Result<Option<[u8; 32]>, SerializationError> {
+    match reader.read_u8()? {
+        0 => Ok(None),
+        1 => Ok(Some(reader.read_32_bytes()?)),
+        _ => Err(SerializationError::Parse(
+            "invalid note commitment tree node",
+        )),
+    }
+}
+
+impl ZcashSerialize for NoteCommitmentTree {
+    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        write_node(self.left, &mut writer)?;
+        write_node(self.right, &mut writer)?;
+        writer.write_compactsize(self.parents.len() as u64)?;
+        for parent in &self.parents {
+            write_node(*parent, &mut writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl ZcashDeserialize for NoteCommitmentTree {
+    fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let left = read_node(&mut reader)?;
+        let right = read_node(&mut reader)?;
+        if left.is_none() && right.is_some() {
+            return Err(SerializationError::Parse(
+                "note commitment tree has a right leaf without a left leaf",
+            ));
+        }
+        let parent_count = reader.read_compactsize()? as usize;
+        if parent_count >= MERKLE_DEPTH {
+            return Err(SerializationError::Parse(
+                "note commitment tree is deeper than the Orchard tree",
+            ));
+        }
+        let parents = (0..parent_count)
+            .map(|_| read_node(&mut reader))
+            .collect::<Result<_, _>>()?;
+        Ok(NoteCommitmentTree {
+            left,
+            right,
+            parents,
+            cached_root: OnceCell::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex_node(s: &str) -> [u8; 32] {
+        let mut node = [0; 32];
+        hex::decode_to_slice(s, &mut node[..]).unwrap();
+        node
+    }
+
+    #[test]
+    fn empty_root() {
+        assert_eq!(
+            NoteCommitmentTree::default().root(),
+            Root(hex_node(
+                "ae2935f1dfd8a24aed7c70df7de3a668eb7a49b1319880dde2bbd9031ae5d82f"
+            ))
+        );
+    }
+
+    #[test]
+    fn append_changes_the_root() {
+        let mut tree = NoteCommitmentTree::default();
+        let mut roots = vec![tree.root()];
+        for i in 0..5u8 {
+            tree.append([i; 32]).unwrap();
+            roots.push(tree.root());
+        }
+        assert_eq!(tree.count(), 5);
+        for (i, root) in roots.iter().enumerate() {
+            assert!(!roots[i + 1..].contains(root));
+        }
+
+        // Commitments must be canonical field elements.
+        assert_eq!(
+            tree.append([0xff; 32]),
+            Err(NoteCommitmentTreeError::NonCanonicalCommitment)
+        );
+
+        let mut bytes = Vec::new();
+        tree.zcash_serialize(&mut bytes).unwrap();
+        let other = NoteCommitmentTree::zcash_deserialize(&bytes[..]).unwrap();
+        assert_eq!(other, tree);
+        assert_eq!(other.root(), tree.root());
+    }
+}
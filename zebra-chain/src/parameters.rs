@@ -0,0 +1,4 @@
+//! Consensus parameters for each Zcash network.
+
+pub mod genesis;
+pub mod subsidy;
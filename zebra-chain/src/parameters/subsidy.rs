@@ -0,0 +1,527 @@
+//! Block subsidies, the founders' reward, and funding streams.
+//!
+//! https://zips.z.cash/protocol/protocol.pdf#subsidies
+//! https://zips.z.cash/zip-0207
+//! https://zips.z.cash/zip-0214
+
+mod addresses;
+
+use std::{collections::HashMap, convert::TryFrom, ops::Range};
+
+use crate::{
+    amount::{self, Amount, NonNegative, COIN},
+    block,
+    network_upgrade::{
+        NetworkUpgrade, POST_BLOSSOM_POW_TARGET_SPACING, PRE_BLOSSOM_POW_TARGET_SPACING,
+    },
+    transparent, Network,
+};
+
+use addresses::*;
+
+/// The largest block subsidy, used before the first halving.
+pub const MAX_BLOCK_SUBSIDY: u64 = ((25 * COIN) / 2) as u64;
+
+/// The ratio between the pre-Blossom and post-Blossom target block spacing.
+///
+/// Blossom halved the block spacing, so it also halved the block subsidy and
+/// doubled the halving interval.
+pub const BLOSSOM_POW_TARGET_SPACING_RATIO: u32 =
+    (PRE_BLOSSOM_POW_TARGET_SPACING / POST_BLOSSOM_POW_TARGET_SPACING) as u32;
+
+/// The denominator of the fraction of the block subsidy that goes to the
+/// founders' reward, before Canopy.
+pub const FOUNDERS_FRACTION_DIVISOR: u64 = 5;
+
+/// The denominator of the funding stream fractions.
+pub const FUNDING_STREAM_RECEIVER_DENOMINATOR: u64 = 100;
+
+/// The number of funding stream address periods in each halving interval.
+const FUNDING_STREAM_ADDRESS_PERIODS: u32 = 48;
+
+/// The number of blocks at the start of the chain with a reduced subsidy.
+pub fn slow_start_interval(network: Network) -> block::Height {
+    match network {
+        Network::Mainnet | Network::Testnet => block::Height(20_000),
+        Network::Regtest => block::Height(0),
+    }
+}
+
+/// The shift in the halving schedule caused by the slow start.
+pub fn slow_start_shift(network: Network) -> block::Height {
+    block::Height(slow_start_interval(network).0 / 2)
+}
+
+/// The number of blocks between halvings, before Blossom.
+pub fn pre_blossom_halving_interval(network: Network) -> u32 {
+    match network {
+        Network::Mainnet | Network::Testnet => 840_000,
+        Network::Regtest => 144,
+    }
+}
+
+/// The number of blocks between halvings, after Blossom.
+pub fn post_blossom_halving_interval(network: Network) -> u32 {
+    pre_blossom_halving_interval(network) * BLOSSOM_POW_TARGET_SPACING_RATIO
+}
+
+fn blossom_height(network: Network) -> block::Height {
+    NetworkUpgrade::Blossom
+        .activation_height(network)
+        .expect("Blossom activation height is known on every network")
+}
+
+/// The number of halvings that have happened by `height`.
+///
+/// Heights in the slow start shift have no halvings.
+pub fn halving(height: block::Height, network: Network) -> u32 {
+    let shift = slow_start_shift(network).0;
+    let blossom = blossom_height(network).0;
+    let pre_interval = pre_blossom_halving_interval(network);
+
+    if height.0 < shift {
+        0
+    } else if height.0 < blossom {
+        (height.0 - shift) / pre_interval
+    } else {
+        // Scale the pre-Blossom blocks up, so all the blocks are counted in
+        // post-Blossom intervals.
+        let scaled_pre_blossom = blossom.saturating_sub(shift) * BLOSSOM_POW_TARGET_SPACING_RATIO;
+        let post_blossom = height.0 - blossom;
+        (scaled_pre_blossom + post_blossom) / post_blossom_halving_interval(network)
+    }
+}
+
+/// The first height that has `halving(height) == 1`.
+pub fn height_for_first_halving(network: Network) -> block::Height {
+    let shift = slow_start_shift(network).0;
+    let blossom = blossom_height(network).0;
+    let pre_blossom_halving = shift + pre_blossom_halving_interval(network);
+
+    if pre_blossom_halving < blossom {
+        block::Height(pre_blossom_halving)
+    } else {
+        let scaled_pre_blossom = (blossom - shift) * BLOSSOM_POW_TARGET_SPACING_RATIO;
+        block::Height(blossom + post_blossom_halving_interval(network) - scaled_pre_blossom)
+    }
+}
+
+/// The total block subsidy at `height`, which is shared between the miner,
+/// the founders' reward, and the funding streams.
+///
+/// The subsidy ramps up linearly during the slow start interval, and halves
+/// every halving interval after that.
+pub fn block_subsidy(height: block::Height, network: Network) -> Amount<NonNegative> {
+    let slow_start = slow_start_interval(network).0;
+
+    let subsidy = if height.0 < slow_start {
+        let slow_start_rate = MAX_BLOCK_SUBSIDY / u64::from(slow_start);
+        // The slow start skips the subsidy for `slow_start / 2`, so that the
+        // total slow start subsidy is half of the full subsidy.
+        if height.0 < slow_start / 2 {
+            slow_start_rate * u64::from(height.0)
+        } else {
+            slow_start_rate * (u64::from(height.0) + 1)
+        }
+    } else {
+        let divisor = if height < blossom_height(network) {
+            1u64.checked_shl(halving(height, network))
+        } else {
+            1u64.checked_shl(halving(height, network))
+                .and_then(|d| d.checked_mul(u64::from(BLOSSOM_POW_TARGET_SPACING_RATIO)))
+        };
+        // After 64 halvings, the subsidy is zero.
+        divisor.map(|d| MAX_BLOCK_SUBSIDY / d).unwrap_or(0)
+    };
+
+    Amount::try_from(subsidy).expect("the subsidy is at most MAX_BLOCK_SUBSIDY")
+}
+
+/// The founders' reward at `height`, which is a fifth of the block subsidy
+/// before the first halving and Canopy, and zero after that.
+pub fn founders_reward(height: block::Height, network: Network) -> Amount<NonNegative> {
+    if halving(height, network) >= 1 || is_canopy_activated(height, network) {
+        return Amount::zero();
+    }
+
+    let subsidy = u64::from(block_subsidy(height, network));
+    Amount::try_from(subsidy / FOUNDERS_FRACTION_DIVISOR)
+        .expect("the founders' reward is less than the subsidy")
+}
+
+fn is_canopy_activated(height: block::Height, network: Network) -> bool {
+    NetworkUpgrade::Canopy
+        .activation_height(network)
+        .map(|canopy| height >= canopy)
+        .unwrap_or(false)
+}
+
+fn founders_reward_addresses(network: Network) -> &'static [&'static str] {
+    match network {
+        Network::Mainnet => &FOUNDERS_REWARD_ADDRESSES_MAINNET[..],
+        Network::Testnet => &FOUNDERS_REWARD_ADDRESSES_TESTNET[..],
+        Network::Regtest => &FOUNDERS_REWARD_ADDRESSES_REGTEST[..],
+    }
+}
+
+/// The address that the founders' reward must be paid to at `height`.
+///
+/// Returns `None` if there is no founders' reward at `height`. Regtest
+/// addresses use the testnet encoding, so they are parsed as testnet
+/// addresses.
+pub fn founders_reward_address(
+    height: block::Height,
+    network: Network,
+) -> Option<transparent::Address> {
+    if founders_reward(height, network) == Amount::zero() {
+        return None;
+    }
+
+    let addresses = founders_reward_addresses(network);
+    let count = addresses.len() as u32;
+    let shift = slow_start_shift(network).0;
+    // The change interval is rounded up, so there are enough addresses for
+    // the whole founders' reward period.
+    let change_interval = (shift + pre_blossom_halving_interval(network) + count - 1) / count;
+
+    // The address periods are the same length in time, so they are longer
+    // in blocks after Blossom.
+    let blossom = blossom_height(network).0;
+    let adjusted_height = if height.0 < blossom {
+        height.0
+    } else {
+        blossom + (height.0 - blossom) / BLOSSOM_POW_TARGET_SPACING_RATIO
+    };
+
+    let index = (adjusted_height / change_interval) as usize;
+    let address = addresses.get(index)?;
+    Some(
+        address
+            .parse()
+            .expect("founders' reward addresses are valid"),
+    )
+}
+
+/// A funding stream recipient, as defined in ZIP-214.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum FundingStreamReceiver {
+    /// The Electric Coin Company.
+    Ecc,
+    /// The Zcash Foundation.
+    ZcashFoundation,
+    /// The Zcash Foundation's Major Grants program.
+    MajorGrants,
+}
+
+impl FundingStreamReceiver {
+    /// Every funding stream receiver.
+    pub const ALL: [FundingStreamReceiver; 3] = [
+        FundingStreamReceiver::Ecc,
+        FundingStreamReceiver::ZcashFoundation,
+        FundingStreamReceiver::MajorGrants,
+    ];
+
+    /// The numerator of this receiver's fraction of the block subsidy.
+    pub fn numerator(&self) -> u64 {
+        match self {
+            FundingStreamReceiver::Ecc => 7,
+            FundingStreamReceiver::ZcashFoundation => 5,
+            FundingStreamReceiver::MajorGrants => 8,
+        }
+    }
+}
+
+/// The heights with funding streams on `network`, or `None` if `network`
+/// has no funding streams.
+///
+/// Every receiver's stream covers the same heights, starting at Canopy and
+/// ending at the second halving.
+pub fn funding_stream_height_range(network: Network) -> Option<Range<block::Height>> {
+    match network {
+        Network::Mainnet => Some(block::Height(1_046_400)..block::Height(2_726_400)),
+        Network::Testnet => Some(block::Height(1_028_500)..block::Height(2_796_000)),
+        Network::Regtest => None,
+    }
+}
+
+/// The amount paid to each funding stream receiver at `height`.
+///
+/// The map is empty outside the funding stream heights.
+pub fn funding_stream_values(
+    height: block::Height,
+    network: Network,
+) -> HashMap<FundingStreamReceiver, Amount<NonNegative>> {
+    let mut values = HashMap::new();
+
+    if funding_stream_height_range(network)
+        .map(|range| range.contains(&height))
+        .unwrap_or(false)
+    {
+        let subsidy = u64::from(block_subsidy(height, network));
+        for receiver in FundingStreamReceiver::ALL.iter() {
+            let value = subsidy * receiver.numerator() / FUNDING_STREAM_RECEIVER_DENOMINATOR;
+            values.insert(
+                *receiver,
+                Amount::try_from(value).expect("funding streams are less than the subsidy"),
+            );
+        }
+    }
+
+    values
+}
+
+/// The funding stream address period of `height`.
+fn funding_stream_address_period(height: block::Height, network: Network) -> u32 {
+    let post_interval = post_blossom_halving_interval(network);
+    let change_interval = post_interval / FUNDING_STREAM_ADDRESS_PERIODS;
+
+    (height.0 + post_interval - height_for_first_halving(network).0) / change_interval
+}
+
+/// The index into each receiver's address list, for `height`.
+///
+/// Returns `None` outside the funding stream heights.
+pub fn funding_stream_address_index(height: block::Height, network: Network) -> Option<usize> {
+    let range = funding_stream_height_range(network)?;
+    if !range.contains(&height) {
+        return None;
+    }
+
+    let index = funding_stream_address_period(height, network)
+        - funding_stream_address_period(range.start, network);
+    Some(index as usize)
+}
+
+/// The address that `receiver`'s funding stream must be paid to at
+/// `height`.
+///
+/// Returns `None` outside the funding stream heights.
+pub fn funding_stream_address(
+    height: block::Height,
+    network: Network,
+    receiver: FundingStreamReceiver,
+) -> Option<transparent::Address> {
+    let index = funding_stream_address_index(height, network)?;
+
+    let address = match (receiver, network) {
+        (FundingStreamReceiver::ZcashFoundation, Network::Mainnet) => {
+            FUNDING_STREAM_ZF_ADDRESS_MAINNET
+        }
+        (FundingStreamReceiver::ZcashFoundation, Network::Testnet) => {
+            FUNDING_STREAM_ZF_ADDRESS_TESTNET
+        }
+        (FundingStreamReceiver::MajorGrants, Network::Mainnet) => FUNDING_STREAM_MG_ADDRESS_MAINNET,
+        (FundingStreamReceiver::MajorGrants, Network::Testnet) => FUNDING_STREAM_MG_ADDRESS_TESTNET,
+        (FundingStreamReceiver::Ecc, Network::Mainnet) => {
+            FUNDING_STREAM_ECC_ADDRESSES_MAINNET[index]
+        }
+        (FundingStreamReceiver::Ecc, Network::Testnet) => {
+            FUNDING_STREAM_ECC_ADDRESSES_TESTNET[index]
+        }
+        (_, Network::Regtest) => return None,
+    };
+
+    Some(address.parse().expect("funding stream addresses are valid"))
+}
+
+/// The part of the block subsidy that the miner can claim at `height`,
+/// which is the subsidy minus the founders' reward and funding streams.
+///
+/// Block validation must also check the founders' reward and funding
+/// stream outputs. The miner also claims the transaction fees.
+pub fn miner_subsidy(
+    height: block::Height,
+    network: Network,
+) -> amount::Result<Amount<NonNegative>> {
+    let funding_streams: amount::Result<Amount<NonNegative>> =
+        funding_stream_values(height, network)
+            .values()
+            .copied()
+            .sum();
+
+    (block_subsidy(height, network) - founders_reward(height, network))? - funding_streams?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn amount(value: u64) -> Amount<NonNegative> {
+        Amount::try_from(value).unwrap()
+    }
+
+    #[test]
+    fn halving_heights() {
+        assert_eq!(
+            height_for_first_halving(Network::Mainnet),
+            block::Height(1_046_400)
+        );
+        assert_eq!(
+            height_for_first_halving(Network::Testnet),
+            block::Height(1_116_000)
+        );
+
+        for network in &[Network::Mainnet, Network::Testnet, Network::Regtest] {
+            let first = height_for_first_halving(*network);
+            assert_eq!(halving(first.previous().unwrap(), *network), 0);
+            assert_eq!(halving(first, *network), 1);
+        }
+
+        assert_eq!(halving(block::Height(2_726_399), Network::Mainnet), 1);
+        assert_eq!(halving(block::Height(2_726_400), Network::Mainnet), 2);
+    }
+
+    #[test]
+    fn mainnet_block_subsidy() {
+        let network = Network::Mainnet;
+        let subsidy = |height| u64::from(block_subsidy(block::Height(height), network));
+
+        // Slow start
+        assert_eq!(subsidy(0), 0);
+        assert_eq!(subsidy(1), 62_500);
+        assert_eq!(subsidy(9_999), 62_500 * 9_999);
+        assert_eq!(subsidy(10_000), 62_500 * 10_001);
+        assert_eq!(subsidy(19_999), 62_500 * 20_000);
+
+        // Before and after Blossom
+        assert_eq!(subsidy(20_000), MAX_BLOCK_SUBSIDY);
+        assert_eq!(subsidy(653_599), MAX_BLOCK_SUBSIDY);
+        assert_eq!(subsidy(653_600), MAX_BLOCK_SUBSIDY / 2);
+
+        // Halvings
+        assert_eq!(subsidy(1_046_399), MAX_BLOCK_SUBSIDY / 2);
+        assert_eq!(subsidy(1_046_400), MAX_BLOCK_SUBSIDY / 4);
+        assert_eq!(subsidy(2_726_400), MAX_BLOCK_SUBSIDY / 8);
+
+        // The subsidy eventually runs out.
+        assert_eq!(subsidy(u32::max_value()), 0);
+    }
+
+    #[test]
+    fn founders_reward_and_funding_streams() {
+        let network = Network::Mainnet;
+
+        // Before Canopy, the founders get a fifth of the subsidy.
+        let height = block::Height(20_000);
+        assert_eq!(founders_reward(height, network), amount(250_000_000));
+        assert!(funding_stream_values(height, network).is_empty());
+        assert_eq!(miner_subsidy(height, network), Ok(amount(1_000_000_000)));
+
+        // After Canopy, the funding streams get a fifth of the subsidy.
+        let height = block::Height(1_046_400);
+        assert_eq!(founders_reward(height, network), Amount::zero());
+        assert_eq!(founders_reward_address(height, network), None);
+        let values = funding_stream_values(height, network);
+        assert_eq!(values[&FundingStreamReceiver::Ecc], amount(21_875_000));
+        assert_eq!(
+            values[&FundingStreamReceiver::ZcashFoundation],
+            amount(15_625_000)
+        );
+        assert_eq!(
+            values[&FundingStreamReceiver::MajorGrants],
+            amount(25_000_000)
+        );
+        assert_eq!(miner_subsidy(height, network), Ok(amount(250_000_000)));
+
+        // After the second halving, the miner gets the whole subsidy.
+        let height = block::Height(2_726_400);
+        assert_eq!(
+            miner_subsidy(height, network),
+            Ok(block_subsidy(height, network))
+        );
+    }
+
+    #[test]
+    fn founders_reward_addresses_change() {
+        let address = |height, network| founders_reward_address(block::Height(height), network);
+
+        for network in &[Network::Mainnet, Network::Testnet] {
+            let addresses = founders_reward_addresses(*network);
+            assert_eq!(addresses.len(), 48);
+            for a in addresses {
+                a.parse::<transparent::Address>()
+                    .expect("founders' reward addresses are valid");
+            }
+
+            // The last address is used until the founders' reward ends.
+            let last = block::Height(
+                NetworkUpgrade::Canopy
+                    .activation_height(*network)
+                    .unwrap()
+                    .0
+                    .min(height_for_first_halving(*network).0)
+                    - 1,
+            );
+            assert!(founders_reward_address(last, *network).is_some());
+        }
+
+        assert_eq!(
+            address(1, Network::Mainnet),
+            Some(FOUNDERS_REWARD_ADDRESSES_MAINNET[0].parse().unwrap())
+        );
+        assert_eq!(
+            address(17_708, Network::Mainnet),
+            Some(FOUNDERS_REWARD_ADDRESSES_MAINNET[0].parse().unwrap())
+        );
+        assert_eq!(
+            address(17_709, Network::Mainnet),
+            Some(FOUNDERS_REWARD_ADDRESSES_MAINNET[1].parse().unwrap())
+        );
+        assert_eq!(
+            address(1_046_399, Network::Mainnet),
+            Some(FOUNDERS_REWARD_ADDRESSES_MAINNET[47].parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn funding_stream_address_indexes() {
+        for network in &[Network::Mainnet, Network::Testnet] {
+            let range = funding_stream_height_range(*network).unwrap();
+            assert_eq!(funding_stream_address_index(range.start, *network), Some(0));
+            assert_eq!(funding_stream_address_index(range.end, *network), None);
+            assert!(funding_stream_address(
+                range.start,
+                *network,
+                FundingStreamReceiver::ZcashFoundation
+            )
+            .is_some());
+        }
+
+        // Every receiver has an address at every funding stream height.
+        for network in &[Network::Mainnet, Network::Testnet] {
+            let range = funding_stream_height_range(*network).unwrap();
+            let change_interval =
+                post_blossom_halving_interval(*network) / FUNDING_STREAM_ADDRESS_PERIODS;
+            let heights = (range.start.0..range.end.0)
+                .step_by(change_interval as usize)
+                .chain(std::iter::once(range.end.0 - 1));
+            for height in heights {
+                for receiver in FundingStreamReceiver::ALL.iter() {
+                    assert!(
+                        funding_stream_address(block::Height(height), *network, *receiver)
+                            .is_some(),
+                        "{:?} has no {:?} address at {}",
+                        network,
+                        receiver,
+                        height
+                    );
+                }
+            }
+        }
+
+        let last_mainnet = block::Height(2_726_399);
+        assert_eq!(
+            funding_stream_address(last_mainnet, Network::Mainnet, FundingStreamReceiver::Ecc),
+            "t3XHAGxRP2FNfhAjxGjxbrQPYtQQjc3RCQD".parse().ok()
+        );
+        assert_eq!(
+            funding_stream_address_index(last_mainnet, Network::Mainnet),
+            Some(47)
+        );
+        let last_testnet = block::Height(2_795_999);
+        assert_eq!(
+            funding_stream_address_index(last_testnet, Network::Testnet),
+            Some(50)
+        );
+    }
+}
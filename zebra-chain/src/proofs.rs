@@ -6,9 +6,11 @@ use crate::serialization::{ZcashDeserialize, ZcashSerialize};
 
 mod bctv14;
 mod groth16;
+mod halo2;
 
 pub use bctv14::Bctv14Proof;
 pub use groth16::Groth16Proof;
+pub use halo2::Halo2Proof;
 
 /// A marker trait used to abstract over BCTV14 or Groth16 proofs.
 pub trait ZkSnarkProof:
@@ -1,9 +1,15 @@
-use std::{fmt, io};
+use std::fmt;
 
-use crate::serialization::{SerializationError, ZcashDeserialize, ZcashSerialize};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[cfg(feature = "serde")]
+use crate::serialization::serde_hex;
+use crate::serialization::{ZcashDeserialize, ZcashSerialize};
 
 /// An encoding of a BCTV14 proof, as used in Zcash.
-pub struct Bctv14Proof(pub [u8; 296]);
+#[derive(ZcashSerialize, ZcashDeserialize)]
+pub struct Bctv14Proof(#[zcash(fixed)] pub [u8; 296]);
 
 impl fmt::Debug for Bctv14Proof {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -33,25 +39,26 @@ impl PartialEq for Bctv14Proof {
 
 impl Eq for Bctv14Proof {}
 
-impl ZcashSerialize for Bctv14Proof {
-    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
-        writer.write_all(&self.0[..])?;
-        Ok(())
+#[cfg(feature = "serde")]
+impl Serialize for Bctv14Proof {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde_hex::serialize(&self.0[..], serializer)
     }
 }
 
-impl ZcashDeserialize for Bctv14Proof {
-    fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Bctv14Proof {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         let mut bytes = [0; 296];
-        reader.read_exact(&mut bytes[..])?;
+        serde_hex::deserialize_into(deserializer, &mut bytes)?;
         Ok(Self(bytes))
     }
 }
 
-#[cfg(test)]
+#[cfg(any(test, feature = "proptest-impl"))]
 use proptest::{arbitrary::Arbitrary, collection::vec, prelude::*};
 
-#[cfg(test)]
+#[cfg(any(test, feature = "proptest-impl"))]
 impl Arbitrary for Bctv14Proof {
     type Parameters = ();
 
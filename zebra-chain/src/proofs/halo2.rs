@@ -0,0 +1,60 @@
+use std::{fmt, io};
+
+use bytes::Bytes;
+
+use crate::serialization::{
+    CopyReader, SerializationError, SharedRead, WriteZcashExt, ZcashDeserialize,
+    ZcashDeserializeShared, ZcashSerialize,
+};
+
+/// An encoding of a Halo2 proof, as used in Zcash.
+///
+/// Unlike BCTV14 and Groth16 proofs, Halo2 proofs are aggregated over all the
+/// actions in a transaction, so their length varies.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Halo2Proof(
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::serde_hex"))] pub Bytes,
+);
+
+impl fmt::Debug for Halo2Proof {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Halo2Proof")
+            .field(&hex::encode(&self.0[..]))
+            .finish()
+    }
+}
+
+impl ZcashSerialize for Halo2Proof {
+    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        writer.write_compact_bytes(&self.0[..])
+    }
+}
+
+impl ZcashDeserializeShared for Halo2Proof {
+    fn zcash_deserialize_shared<R: SharedRead>(mut reader: R) -> Result<Self, SerializationError> {
+        Ok(Halo2Proof(reader.read_shared_compact_bytes()?))
+    }
+}
+
+impl ZcashDeserialize for Halo2Proof {
+    fn zcash_deserialize<R: io::Read>(reader: R) -> Result<Self, SerializationError> {
+        Halo2Proof::zcash_deserialize_shared(CopyReader(reader))
+    }
+}
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest::{arbitrary::Arbitrary, collection::vec, prelude::*};
+
+#[cfg(any(test, feature = "proptest-impl"))]
+impl Arbitrary for Halo2Proof {
+    type Parameters = ();
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        (vec(any::<u8>(), 0..1024))
+            .prop_map(|bytes| Halo2Proof(bytes.into()))
+            .boxed()
+    }
+
+    type Strategy = BoxedStrategy<Self>;
+}
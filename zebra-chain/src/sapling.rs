@@ -0,0 +1,9 @@
+//! Sapling shielded transfers.
+
+mod address;
+mod nullifier;
+
+pub mod tree;
+
+pub use address::Address;
+pub use nullifier::Nullifier;
@@ -0,0 +1,272 @@
+//! Sapling Shielded Payment Address types.
+
+use std::{fmt, io::Read};
+
+use bech32::{self, FromBase32, ToBase32};
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest::prelude::*;
+
+use crate::{
+    keys::sapling,
+    serialization::{ReadZcashExt, SerializationError},
+    Network,
+};
+
+/// Human-Readable Parts for input to bech32 encoding.
+mod human_readable_parts {
+    pub const MAINNET: &str = "zs";
+    pub const TESTNET: &str = "ztestsapling";
+    pub const REGTEST: &str = "zregtestsapling";
+}
+
+/// A Sapling _shielded payment address_.
+///
+/// Also known as a _diversified payment address_ for Sapling, as
+/// defined in [§4.2.2][4.2.2].
+///
+/// [4.2.2]: https://zips.z.cash/protocol/protocol.pdf#saplingkeycomponents
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct Address {
+    network: Network,
+    diversifier: sapling::Diversifier,
+    transmission_key: sapling::TransmissionKey,
+}
+
+impl Address {
+    /// Create a payment address from its components, if `diversifier` can
+    /// be used in a payment address.
+    pub fn new(
+        network: Network,
+        diversifier: sapling::Diversifier,
+        transmission_key: sapling::TransmissionKey,
+    ) -> Option<Self> {
+        diversifier.diversified_base().map(|_| Address {
+            network,
+            diversifier,
+            transmission_key,
+        })
+    }
+
+    /// The network this address is for.
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// The diversifier _d_ of this address.
+    pub fn diversifier(&self) -> sapling::Diversifier {
+        self.diversifier
+    }
+
+    /// The diversified transmission key _pk_d_ of this address.
+    pub fn transmission_key(&self) -> sapling::TransmissionKey {
+        self.transmission_key
+    }
+
+    /// The raw encoding of this address, which is its diversifier followed
+    /// by its transmission key.
+    ///
+    /// https://zips.z.cash/protocol/protocol.pdf#saplingpaymentaddrencoding
+    pub fn to_raw_bytes(&self) -> [u8; 43] {
+        let mut bytes = [0; 43];
+        bytes[..11].copy_from_slice(&<[u8; 11]>::from(self.diversifier));
+        bytes[11..].copy_from_slice(&<[u8; 32]>::from(self.transmission_key));
+        bytes
+    }
+
+    /// Parse the raw encoding of an address on `network`, checking that its
+    /// diversifier has a diversified base, and that its transmission key is
+    /// a Jubjub point.
+    pub fn from_raw_bytes(network: Network, bytes: [u8; 43]) -> Result<Self, SerializationError> {
+        let mut reader = &bytes[..];
+
+        let mut diversifier_bytes = [0; 11];
+        reader.read_exact(&mut diversifier_bytes)?;
+        let diversifier = sapling::Diversifier::from(diversifier_bytes);
+        if diversifier.diversified_base().is_none() {
+            return Err(SerializationError::Parse(
+                "sapling address diversifier has no diversified base",
+            ));
+        }
+
+        let transmission_key = jubjub::AffinePoint::from_bytes(reader.read_32_bytes()?);
+        if transmission_key.is_some().unwrap_u8() == 0 {
+            return Err(SerializationError::Parse(
+                "sapling address transmission key is not a jubjub point",
+            ));
+        }
+
+        Ok(Address {
+            network,
+            diversifier,
+            transmission_key: sapling::TransmissionKey(transmission_key.unwrap()),
+        })
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("sapling::Address")
+            .field("network", &self.network)
+            .field("diversifier", &self.diversifier)
+            .field("transmission_key", &self.transmission_key)
+            .finish()
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hrp = match self.network {
+            Network::Mainnet => human_readable_parts::MAINNET,
+            Network::Testnet => human_readable_parts::TESTNET,
+            Network::Regtest => human_readable_parts::REGTEST,
+        };
+
+        bech32::encode_to_fmt(f, hrp, self.to_raw_bytes().to_base32()).unwrap()
+    }
+}
+
+impl std::str::FromStr for Address {
+    type Err = SerializationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hrp, data) =
+            bech32::decode(s).map_err(|_| SerializationError::Parse("bech32 decoding error"))?;
+
+        let network = match hrp.as_str() {
+            human_readable_parts::MAINNET => Network::Mainnet,
+            human_readable_parts::TESTNET => Network::Testnet,
+            human_readable_parts::REGTEST => Network::Regtest,
+            _ => return Err(SerializationError::Parse("unknown sapling address prefix")),
+        };
+
+        let bytes = Vec::<u8>::from_base32(&data)
+            .map_err(|_| SerializationError::Parse("bech32 decoding error"))?;
+        if bytes.len() != 43 {
+            return Err(SerializationError::Parse(
+                "sapling address has the wrong length",
+            ));
+        }
+        let mut raw = [0; 43];
+        raw.copy_from_slice(&bytes);
+
+        Address::from_raw_bytes(network, raw)
+    }
+}
+
+#[cfg(any(test, feature = "proptest-impl"))]
+impl Arbitrary for Address {
+    type Parameters = ();
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        (
+            any::<Network>(),
+            any::<sapling::Diversifier>()
+                .prop_filter("diversifiers must have a diversified base", |d| {
+                    d.diversified_base().is_some()
+                }),
+            any::<sapling::TransmissionKey>(),
+        )
+            .prop_map(|(network, diversifier, transmission_key)| Self {
+                network,
+                diversifier,
+                transmission_key,
+            })
+            .boxed()
+    }
+
+    type Strategy = BoxedStrategy<Self>;
+}
+
+#[cfg(test)]
+mod tests {
+
+    use rand_core::OsRng;
+
+    use super::*;
+
+    #[test]
+    fn from_str_display() {
+        let zs_addr: Address =
+            "zs1qqqqqqqqqqqqqqqqqrjq05nyfku05msvu49mawhg6kr0wwljahypwyk2h88z6975u563j8nfaxd"
+                .parse()
+                .unwrap();
+
+        assert_eq!(
+            format!("{}", zs_addr),
+            "zs1qqqqqqqqqqqqqqqqqrjq05nyfku05msvu49mawhg6kr0wwljahypwyk2h88z6975u563j8nfaxd"
+        );
+    }
+
+    #[test]
+    fn derive_keys_and_addresses() {
+        let spending_key = sapling::SpendingKey::new(&mut OsRng);
+
+        let spend_authorizing_key = sapling::SpendAuthorizingKey::from(&spending_key);
+        let proof_authorizing_key = sapling::ProofAuthorizingKey::from(&spending_key);
+
+        let authorizing_key = sapling::AuthorizingKey::from(spend_authorizing_key);
+        let nullifier_deriving_key = sapling::NullifierDerivingKey::from(proof_authorizing_key);
+        let incoming_viewing_key =
+            sapling::IncomingViewingKey::from((authorizing_key, nullifier_deriving_key));
+
+        let diversifier = sapling::Diversifier::new(&mut OsRng);
+        let transmission_key = sapling::TransmissionKey::from((incoming_viewing_key, diversifier));
+
+        let sapling_shielded_address =
+            Address::new(Network::Mainnet, diversifier, transmission_key)
+                .expect("generated diversifiers have a diversified base");
+
+        assert_eq!(sapling_shielded_address.diversifier(), diversifier);
+        assert_eq!(
+            sapling_shielded_address.transmission_key(),
+            transmission_key
+        );
+    }
+
+    #[test]
+    fn invalid_diversifier() {
+        let diversifier = (0..=u8::max_value())
+            .map(|i| sapling::Diversifier([i; 11]))
+            .find(|d| d.diversified_base().is_none())
+            .expect("about half of all diversifiers are invalid");
+        let valid: Address =
+            "zs1qqqqqqqqqqqqqqqqqrjq05nyfku05msvu49mawhg6kr0wwljahypwyk2h88z6975u563j8nfaxd"
+                .parse()
+                .unwrap();
+
+        assert!(Address::new(Network::Mainnet, diversifier, valid.transmission_key()).is_none());
+
+        let invalid = Address {
+            diversifier,
+            ..valid
+        };
+        assert!(invalid.to_string().parse::<Address>().is_err());
+    }
+
+    #[test]
+    fn unknown_prefix() {
+        let valid: Address =
+            "zs1qqqqqqqqqqqqqqqqqrjq05nyfku05msvu49mawhg6kr0wwljahypwyk2h88z6975u563j8nfaxd"
+                .parse()
+                .unwrap();
+        let other = bech32::encode("zxyz", valid.to_raw_bytes().to_base32()).unwrap();
+
+        assert!(other.parse::<Address>().is_err());
+    }
+}
+
+#[cfg(test)]
+proptest! {
+
+    #[test]
+    fn sapling_address_roundtrip(zaddr in any::<Address>()) {
+
+        let string = zaddr.to_string();
+
+        let zaddr2 = string.parse::<Address>()
+            .expect("randomized sapling z-addr should deserialize");
+
+        prop_assert_eq![zaddr, zaddr2];
+    }
+}
@@ -0,0 +1,45 @@
+#![allow(clippy::unit_arg)]
+use std::fmt;
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest_derive::Arbitrary;
+
+use crate::serialization::{ZcashDeserialize, ZcashSerialize};
+
+/// A Sapling nullifier, which is revealed when a Spend spends a note.
+///
+/// Sapling nullifiers are derived from the note's position in the note
+/// commitment tree, so notes with the same contents have distinct nullifiers.
+/// They are in a separate domain from Sprout and Orchard nullifiers, and
+/// the state keeps a separate set for each pool.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, ZcashSerialize, ZcashDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
+pub struct Nullifier(
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::serde_hex::bytes32")
+    )]
+    #[zcash(fixed)]
+    pub [u8; 32],
+);
+
+impl fmt::Debug for Nullifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("sapling::Nullifier")
+            .field(&hex::encode(&self.0))
+            .finish()
+    }
+}
+
+impl From<[u8; 32]> for Nullifier {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<Nullifier> for [u8; 32] {
+    fn from(nullifier: Nullifier) -> Self {
+        nullifier.0
+    }
+}
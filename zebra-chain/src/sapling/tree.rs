@@ -0,0 +1,376 @@
+//! The Sapling note commitment tree.
+//!
+//! The tree is an incremental Merkle tree of fixed depth, whose leaves are
+//! the note commitments produced by Sapling outputs. Like Bitcoin's UTXO set,
+//! it expresses the existence of value and the capability to spend it, but
+//! it is append-only, so it doesn't protect against double-spends.
+//!
+//! Each treestate has a root, called an anchor, which Sapling spends use to
+//! prove that the notes they spend exist.
+#![allow(clippy::unit_arg)]
+
+use std::{fmt, io};
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use lazy_static::lazy_static;
+use once_cell::sync::OnceCell;
+use thiserror::Error;
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest_derive::Arbitrary;
+
+use crate::{
+    keys::sapling::find_group_hash,
+    serialization::{
+        ReadZcashExt, SerializationError, WriteZcashExt, ZcashDeserialize, ZcashSerialize,
+    },
+};
+
+/// The depth of the Sapling note commitment tree.
+pub const MERKLE_DEPTH: usize = 32;
+
+/// The number of bits in a node, which is an element of the Jubjub base
+/// field.
+const NODE_BITS: usize = 255;
+
+/// The number of 3-bit chunks in each segment of a Pedersen hash input.
+const CHUNKS_PER_SEGMENT: usize = 63;
+
+lazy_static! {
+    /// The Pedersen hash generators, one for each segment of a node hash
+    /// input.
+    static ref GENERATORS: Vec<jubjub::ExtendedPoint> = {
+        let input_bits = 6 + 2 * NODE_BITS;
+        let segments = (input_bits + 3 * CHUNKS_PER_SEGMENT - 1) / (3 * CHUNKS_PER_SEGMENT);
+        (0..segments as u32)
+            .map(|i| find_group_hash(*b"Zcash_PH", &i.to_le_bytes()))
+            .collect()
+    };
+
+    /// The roots of empty subtrees, indexed by their height above the
+    /// leaves.
+    ///
+    /// The empty leaf is `Uncommitted^Sapling`, which is 1 in the base field.
+    static ref EMPTY_ROOTS: Vec<[u8; 32]> = {
+        let mut uncommitted = [0; 32];
+        uncommitted[0] = 1;
+        let mut roots = vec![uncommitted];
+        for height in 0..MERKLE_DEPTH {
+            let below = roots[height];
+            roots.push(merkle_crh(height, below, below));
+        }
+        roots
+    };
+}
+
+/// Iterate over the bits of `bytes`, least significant bit first.
+fn le_bits(bytes: [u8; 32]) -> impl Iterator<Item = bool> {
+    (0..NODE_BITS).map(move |i| (bytes[i / 8] >> (i % 8)) & 1 == 1)
+}
+
+/// The Pedersen hash of `bits`, using the node hash generators, as a point
+/// on the Jubjub curve.
+///
+/// https://zips.z.cash/protocol/protocol.pdf#concretepedersenhash
+fn pedersen_hash_to_point(bits: &[bool]) -> jubjub::ExtendedPoint {
+    let mut result = jubjub::ExtendedPoint::identity();
+    for (segment, generator) in bits.chunks(3 * CHUNKS_PER_SEGMENT).zip(GENERATORS.iter()) {
+        // The scalar is the sum of each chunk's encoding, times 2^(4j).
+        let mut scalar = jubjub::Fr::zero();
+        let mut shift = jubjub::Fr::one();
+        for chunk in segment.chunks(3) {
+            let bit = |i: usize| chunk.get(i).copied().unwrap_or(false);
+            let mut enc = shift;
+            if bit(0) {
+                enc += shift;
+            }
+            if bit(1) {
+                enc += shift.double();
+            }
+            if bit(2) {
+                enc = -enc;
+            }
+            scalar += enc;
+            shift = shift.double().double().double().double();
+        }
+        result += generator * scalar;
+    }
+    result
+}
+
+/// MerkleCRH^Sapling, the hash of two child nodes at `height` above the
+/// leaves.
+///
+/// https://zips.z.cash/protocol/protocol.pdf#merklecrh
+fn merkle_crh(height: usize, left: [u8; 32], right: [u8; 32]) -> [u8; 32] {
+    let mut bits = Vec::with_capacity(6 + 2 * NODE_BITS);
+    bits.extend((0..6).map(|i| (height >> i) & 1 == 1));
+    bits.extend(le_bits(left));
+    bits.extend(le_bits(right));
+
+    // Extract the u-coordinate of the point.
+    jubjub::AffinePoint::from(pedersen_hash_to_point(&bits))
+        .get_u()
+        .to_bytes()
+}
+
+/// The root of a Sapling note commitment tree, also known as an anchor.
+///
+/// The root is encoded as LEBS2OSP256(rt), and each treestate has one.
+#[derive(Clone, Copy, Default, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
+pub struct Root(
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::serde_hex::bytes32")
+    )]
+    pub [u8; 32],
+);
+
+impl fmt::Debug for Root {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("sapling::tree::Root")
+            .field(&hex::encode(&self.0))
+            .finish()
+    }
+}
+
+/// An error appending a note commitment to a [`NoteCommitmentTree`].
+#[derive(Error, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum NoteCommitmentTreeError {
+    /// The tree already has 2^32 note commitments.
+    #[error("the note commitment tree is full")]
+    FullTree,
+}
+
+/// An incremental Sapling note commitment tree.
+///
+/// The tree only stores its frontier: the rightmost leaves, and the roots of
+/// the complete subtrees to their left. That is enough to append note
+/// commitments and compute the root. This is the same representation as
+/// `zcashd`'s `SaplingMerkleTree`, and it serializes the same way.
+#[derive(Clone, Debug, Default)]
+pub struct NoteCommitmentTree {
+    left: Option<[u8; 32]>,
+    right: Option<[u8; 32]>,
+    /// The roots of complete subtrees, with `parents[i]` at height `i + 1`.
+    parents: Vec<Option<[u8; 32]>>,
+    /// The root, if it has been computed since the last append.
+    ///
+    /// Trees are shared between threads by the state, so the cache must be
+    /// `Sync`.
+    cached_root: OnceCell<Root>,
+}
+
+impl NoteCommitmentTree {
+    /// Append the note commitment `cm_u` as the next leaf of the tree.
+    ///
+    /// This is the u-coordinate of the commitment in a Sapling output.
+    pub fn append(&mut self, cm_u: [u8; 32]) -> Result<(), NoteCommitmentTreeError> {
+        if self.is_complete() {
+            return Err(NoteCommitmentTreeError::FullTree);
+        }
+        self.cached_root = OnceCell::new();
+
+        let (left, right) = match (self.left, self.right) {
+            (None, _) => {
+                self.left = Some(cm_u);
+                return Ok(());
+            }
+            (Some(_), None) => {
+                self.right = Some(cm_u);
+                return Ok(());
+            }
+            (Some(left), Some(right)) => (left, right),
+        };
+
+        // Both leaves are full, so carry their hash up into the parents.
+        self.left = Some(cm_u);
+        self.right = None;
+        let mut combined = merkle_crh(0, left, right);
+        for (i, parent) in self.parents.iter_mut().enumerate() {
+            match parent.take() {
+                Some(p) => combined = merkle_crh(i + 1, p, combined),
+                None => {
+                    *parent = Some(combined);
+                    return Ok(());
+                }
+            }
+        }
+        self.parents.push(Some(combined));
+        Ok(())
+    }
+
+    /// Return the root of the tree, which is the anchor for spends of the
+    /// notes in it.
+    pub fn root(&self) -> Root {
+        *self.cached_root.get_or_init(|| self.compute_root())
+    }
+
+    fn compute_root(&self) -> Root {
+        let empty_leaf = EMPTY_ROOTS[0];
+        let mut root = merkle_crh(
+            0,
+            self.left.unwrap_or(empty_leaf),
+            self.right.unwrap_or(empty_leaf),
+        );
+        for height in 1..MERKLE_DEPTH {
+            root = match self.parents.get(height - 1) {
+                Some(Some(parent)) => merkle_crh(height, *parent, root),
+                _ => merkle_crh(height, root, EMPTY_ROOTS[height]),
+            };
+        }
+
+        Root(root)
+    }
+
+    /// Return the number of note commitments in the tree.
+    pub fn count(&self) -> u64 {
+        let leaves = self.left.is_some() as u64 + self.right.is_some() as u64;
+        self.parents
+            .iter()
+            .enumerate()
+            .filter(|(_, parent)| parent.is_some())
+            .fold(leaves, |count, (i, _)| count + (1 << (i + 1)))
+    }
+
+    /// Return the position of the most recently appended note commitment,
+    /// which spends use to derive its nullifier.
+    ///
+    /// Returns `None` if the tree is empty.
+    pub fn position(&self) -> Option<u64> {
+        self.count().checked_sub(1)
+    }
+
+    fn is_complete(&self) -> bool {
+        self.left.is_some()
+            && self.right.is_some()
+            && self.parents.len() == MERKLE_DEPTH - 1
+            && self.parents.iter().all(Option::is_some)
+    }
+}
+
+impl PartialEq for NoteCommitmentTree {
+    fn eq(&self, other: &Self) -> bool {
+        self.left == other.left && self.right == other.right && self.parents == other.parents
+    }
+}
+
+impl Eq for NoteCommitmentTree {}
+
+fn write_node<W: io::Write>(node: Option<[u8; 32]>, mut writer: W) -> Result<(), io::Error> {
+    match node {
+        Some(node) => {
+            writer.write_u8(1)?;
+            writer.write_all(&node[..])
+        }
+        None => writer.write_u8(0),
+    }
+}
+
+fn read_node<R: io::Read>(mut reader: R) -> Result<Option<[u8; 32]>, SerializationError> {
+    match reader.read_u8()? {
+        0 => Ok(None),
+        1 => Ok(Some(reader.read_32_bytes()?)),
+        _ => Err(SerializationError::Parse(
+            "invalid note commitment tree node",
+        )),
+    }
+}
+
+impl ZcashSerialize for NoteCommitmentTree {
+    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        write_node(self.left, &mut writer)?;
+        write_node(self.right, &mut writer)?;
+        writer.write_compactsize(self.parents.len() as u64)?;
+        for parent in &self.parents {
+            write_node(*parent, &mut writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl ZcashDeserialize for NoteCommitmentTree {
+    fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let left = read_node(&mut reader)?;
+        let right = read_node(&mut reader)?;
+        if left.is_none() && right.is_some() {
+            return Err(SerializationError::Parse(
+                "note commitment tree has a right leaf without a left leaf",
+            ));
+        }
+        let parent_count = reader.read_compactsize()? as usize;
+        if parent_count >= MERKLE_DEPTH {
+            return Err(SerializationError::Parse(
+                "note commitment tree is deeper than the Sapling tree",
+            ));
+        }
+        let parents = (0..parent_count)
+            .map(|_| read_node(&mut reader))
+            .collect::<Result<_, _>>()?;
+        Ok(NoteCommitmentTree {
+            left,
+            right,
+            parents,
+            cached_root: OnceCell::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex_node(s: &str) -> [u8; 32] {
+        let mut node = [0; 32];
+        hex::decode_to_slice(s, &mut node[..]).unwrap();
+        node
+    }
+
+    #[test]
+    fn empty_roots() {
+        assert_eq!(
+            EMPTY_ROOTS[1],
+            hex_node("817de36ab2d57feb077634bca77819c8e0bd298c04f6fed0e6a83cc1356ca155")
+        );
+        assert_eq!(
+            NoteCommitmentTree::default().root(),
+            Root(hex_node(
+                "fbc2f4300c01f0b7820d00e3347c8da4ee614674376cbc45359daa54f9b5493e"
+            ))
+        );
+    }
+
+    #[test]
+    fn append_tracks_position_and_root() {
+        let mut tree = NoteCommitmentTree::default();
+        assert_eq!(tree.position(), None);
+
+        let mut roots = vec![tree.root()];
+        for i in 0..5u8 {
+            tree.append([i; 32]).unwrap();
+            assert_eq!(tree.position(), Some(i as u64));
+            roots.push(tree.root());
+        }
+        assert_eq!(tree.count(), 5);
+
+        // Every append changes the anchor.
+        for (i, root) in roots.iter().enumerate() {
+            assert!(!roots[i + 1..].contains(root));
+        }
+
+        // The frontier round-trips without losing the root.
+        let mut bytes = Vec::new();
+        tree.zcash_serialize(&mut bytes).unwrap();
+        let other = NoteCommitmentTree::zcash_deserialize(&bytes[..]).unwrap();
+        assert_eq!(other, tree);
+        assert_eq!(other.root(), tree.root());
+    }
+
+    #[test]
+    fn trees_can_be_shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<NoteCommitmentTree>();
+    }
+}
@@ -5,13 +5,43 @@
 //! consensus-critical Zcash serialization formats, and `WriteZcashExt` and
 //! `ReadZcashExt`, extension traits for `io::Read` and `io::Write` with utility functions
 //! for reading and writing data (e.g., the Bitcoin variable-integer format).
+//! Types that appear in vectors also implement `TrustedPreallocate`, which
+//! bounds the length of those vectors.
+//!
+//! Structs that are serialized field by field can use
+//! `#[derive(ZcashSerialize, ZcashDeserialize)]`, which is documented in the
+//! `zebra-chain-derive` crate.
+//!
+//! Blocks and transactions also implement `ZcashDeserializeShared`, which
+//! shares their scripts and proofs with the buffer they are read from,
+//! rather than copying them.
 
 use std::io;
+use std::mem::size_of;
 use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
 
 use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
 use thiserror::Error;
 
+mod date_time;
+pub(crate) mod reversed_hex;
+#[cfg(feature = "serde")]
+pub(crate) mod serde_hex;
+mod shared;
+
+pub use date_time::DateTime32;
+pub use shared::{
+    zcash_deserialize_shared_vec, CopyReader, SharedBytes, SharedRead, ZcashDeserializeShared,
+};
+pub use zebra_chain_derive::{ZcashDeserialize, ZcashSerialize};
+
+/// The maximum length of a Zcash network message payload, in bytes.
+///
+/// This bounds the length of vectors in network messages. Blocks have their
+/// own limit, `block::MAX_BLOCK_BYTES`, which is slightly smaller.
+pub const MAX_PROTOCOL_MESSAGE_LEN: usize = 2 * 1024 * 1024;
+
 /// A serialization error.
 // XXX refine error types -- better to use boxed errors?
 #[derive(Error, Debug)]
@@ -43,6 +73,29 @@ pub trait ZcashSerialize: Sized {
     /// In other words, any type implementing `ZcashSerialize` must make illegal
     /// states unrepresentable.
     fn zcash_serialize<W: io::Write>(&self, writer: W) -> Result<(), io::Error>;
+
+    /// Return the length of `self` in the canonical format, without
+    /// allocating a buffer for the serialized bytes.
+    fn zcash_serialized_size(&self) -> usize {
+        let mut counter = ByteCounter(0);
+        self.zcash_serialize(&mut counter)
+            .expect("ByteCounter is infallible");
+        counter.0
+    }
+}
+
+/// A writer that discards its input, and counts the number of bytes written.
+struct ByteCounter(usize);
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 /// Consensus-critical serialization for Zcash.
@@ -61,30 +114,135 @@ pub trait ZcashDeserialize: Sized {
 }
 
 impl<T: ZcashSerialize> ZcashSerialize for Vec<T> {
+    fn zcash_serialize<W: io::Write>(&self, writer: W) -> Result<(), io::Error> {
+        zcash_serialize_vec(self, writer)
+    }
+}
+
+/// A type whose vectors have a maximum length in any valid message or block.
+///
+/// Vector lengths are read from untrusted data, so `Vec<T>` only preallocates
+/// space for vectors that are at most `T::max_allocation()` items long, and
+/// rejects longer vectors.
+pub trait TrustedPreallocate {
+    /// Returns the maximum number of items of this type in a vector.
+    ///
+    /// This is usually the maximum message or block size, divided by the
+    /// smallest serialized size of an item.
+    fn max_allocation() -> u64;
+}
+
+impl<T: TrustedPreallocate> TrustedPreallocate for Arc<T> {
+    fn max_allocation() -> u64 {
+        T::max_allocation()
+    }
+}
+
+impl<T: ZcashDeserialize + TrustedPreallocate> ZcashDeserialize for Vec<T> {
+    fn zcash_deserialize<R: io::Read>(reader: R) -> Result<Self, SerializationError> {
+        zcash_deserialize_vec(reader)
+    }
+}
+
+impl ZcashSerialize for u8 {
     fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
-        writer.write_compactsize(self.len() as u64)?;
-        for x in self {
-            x.zcash_serialize(&mut writer)?;
-        }
-        Ok(())
+        writer.write_u8(*self)
     }
 }
 
-impl<T: ZcashDeserialize> ZcashDeserialize for Vec<T> {
+impl ZcashDeserialize for u8 {
     fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
-        let len = reader.read_compactsize()?;
-        // We're given len, so we could preallocate. But blindly preallocating
-        // without a size bound can allow DOS attacks, and there's no way to
-        // pass a size bound in a ZcashDeserialize impl, so instead we allocate
-        // as we read from the reader.
-        let mut vec = Vec::new();
-        for _ in 0..len {
-            vec.push(T::zcash_deserialize(&mut reader)?);
-        }
-        Ok(vec)
+        Ok(reader.read_u8()?)
+    }
+}
+
+impl TrustedPreallocate for u8 {
+    fn max_allocation() -> u64 {
+        MAX_PROTOCOL_MESSAGE_LEN as u64
     }
 }
 
+/// Implement the Zcash traits for integers, which are little-endian.
+macro_rules! impl_little_endian {
+    ($($ty:ty => $write:ident, $read:ident;)*) => {
+        $(
+            impl ZcashSerialize for $ty {
+                fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
+                    writer.$write::<LittleEndian>(*self)
+                }
+            }
+
+            impl ZcashDeserialize for $ty {
+                fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
+                    Ok(reader.$read::<LittleEndian>()?)
+                }
+            }
+
+            impl TrustedPreallocate for $ty {
+                fn max_allocation() -> u64 {
+                    (MAX_PROTOCOL_MESSAGE_LEN / size_of::<$ty>()) as u64
+                }
+            }
+        )*
+    };
+}
+
+impl_little_endian! {
+    u16 => write_u16, read_u16;
+    u32 => write_u32, read_u32;
+    u64 => write_u64, read_u64;
+    i32 => write_i32, read_i32;
+    i64 => write_i64, read_i64;
+}
+
+/// Socket addresses use the Bitcoin encoding, an IPv6 address followed by a
+/// big-endian port. IPv4 addresses are mapped into IPv6.
+impl ZcashSerialize for SocketAddr {
+    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        writer.write_socket_addr(*self)
+    }
+}
+
+impl ZcashDeserialize for SocketAddr {
+    fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
+        Ok(reader.read_socket_addr()?)
+    }
+}
+
+/// Write `items` as a `CompactSize` count, followed by each item.
+///
+/// This is the Bitcoin vector encoding, which is also used by `Vec<T>`.
+pub fn zcash_serialize_vec<T: ZcashSerialize, W: io::Write>(
+    items: &[T],
+    mut writer: W,
+) -> Result<(), io::Error> {
+    writer.write_compactsize(items.len() as u64)?;
+    for item in items {
+        item.zcash_serialize(&mut writer)?;
+    }
+    Ok(())
+}
+
+/// Read a `CompactSize` count, followed by that many items.
+///
+/// The count comes from untrusted data, so this returns an error if it is
+/// greater than `T::max_allocation()`, before preallocating any space.
+pub fn zcash_deserialize_vec<T: ZcashDeserialize + TrustedPreallocate, R: io::Read>(
+    mut reader: R,
+) -> Result<Vec<T>, SerializationError> {
+    let count = reader.read_compactsize()?;
+    if count > T::max_allocation() {
+        return Err(SerializationError::Parse(
+            "vector is longer than the maximum allocation for its items",
+        ));
+    }
+    let mut items = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        items.push(T::zcash_deserialize(&mut reader)?);
+    }
+    Ok(items)
+}
+
 /// Extends [`Write`] with methods for writing Zcash/Bitcoin types.
 ///
 /// [`Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
@@ -153,11 +311,17 @@ pub trait WriteZcashExt: io::Write {
         self.write_u16::<BigEndian>(addr.port())
     }
 
+    /// Write `bytes` with a `CompactSize` length prefix.
+    #[inline]
+    fn write_compact_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.write_compactsize(bytes.len() as u64)?;
+        self.write_all(bytes)
+    }
+
     /// Write a string in Bitcoin format.
     #[inline]
     fn write_string(&mut self, string: &str) -> io::Result<()> {
-        self.write_compactsize(string.len() as u64)?;
-        self.write_all(string.as_bytes())
+        self.write_compact_bytes(string.as_bytes())
     }
 }
 
@@ -246,12 +410,26 @@ pub trait ReadZcashExt: io::Read {
         Ok(SocketAddr::new(ip_addr, port))
     }
 
+    /// Read bytes with a `CompactSize` length prefix.
+    ///
+    /// The length comes from untrusted data, so this allocates as it reads,
+    /// rather than preallocating `len` bytes. Returns an error if the reader
+    /// ends before `len` bytes are read.
+    #[inline]
+    fn read_compact_bytes(&mut self) -> Result<Vec<u8>, SerializationError> {
+        let len = self.read_compactsize()?;
+        let mut bytes = Vec::new();
+        self.take(len).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != len {
+            return Err(SerializationError::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+        Ok(bytes)
+    }
+
     /// Read a Bitcoin-encoded UTF-8 string.
     #[inline]
     fn read_string(&mut self) -> Result<String, SerializationError> {
-        let len = self.read_compactsize()?;
-        let mut buf = vec![0; len as usize];
-        self.read_exact(&mut buf)?;
+        let buf = self.read_compact_bytes()?;
         String::from_utf8(buf).map_err(|_| SerializationError::Parse("invalid utf-8"))
     }
 
@@ -327,5 +505,87 @@ mod tests {
                 prop_assert_eq!(bytes, expect_bytes);
             }
         }
+
+        #[test]
+        fn compact_bytes_round_trip(bytes in prop::collection::vec(any::<u8>(), 0..300)) {
+            let mut buf = Vec::new();
+            buf.write_compact_bytes(&bytes).unwrap();
+            prop_assert_eq!(Cursor::new(&buf).read_compact_bytes().unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn compactsize_rejects_non_canonical_encodings() {
+        for bytes in &[
+            &b"\xfd\xfc\x00"[..],
+            &b"\xfe\xff\xff\x00\x00"[..],
+            &b"\xff\xff\xff\xff\xff\x00\x00\x00\x00"[..],
+        ] {
+            assert!(Cursor::new(bytes).read_compactsize().is_err());
+        }
+    }
+
+    #[derive(ZcashSerialize, ZcashDeserialize, Debug, PartialEq)]
+    struct Derived {
+        version: u32,
+        #[zcash(fixed)]
+        hash: [u8; 4],
+        #[zcash(fixed)]
+        pair: [u16; 2],
+        #[zcash(length_prefixed)]
+        data: Vec<u8>,
+        #[zcash(length_prefixed)]
+        values: Vec<i64>,
+        inner: DerivedTuple,
+    }
+
+    #[derive(ZcashSerialize, ZcashDeserialize, Debug, PartialEq)]
+    struct DerivedTuple(u8, #[zcash(fixed)] [u8; 2]);
+
+    #[test]
+    fn derived_fields_are_serialized_in_order() {
+        let derived = Derived {
+            version: 4,
+            hash: *b"abcd",
+            pair: [1, 2],
+            data: b"xy".to_vec(),
+            values: vec![-1],
+            inner: DerivedTuple(7, *b"zz"),
+        };
+
+        let mut bytes = Vec::new();
+        derived.zcash_serialize(&mut bytes).unwrap();
+        assert_eq!(
+            bytes,
+            [
+                &b"\x04\x00\x00\x00abcd\x01\x00\x02\x00\x02xy"[..],
+                &b"\x01\xff\xff\xff\xff\xff\xff\xff\xff\x07zz"[..],
+            ]
+            .concat()
+        );
+        assert_eq!(derived.zcash_serialized_size(), bytes.len());
+
+        assert_eq!(Derived::zcash_deserialize(&bytes[..]).unwrap(), derived);
+        assert!(Derived::zcash_deserialize(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn truncated_compact_bytes_fail() {
+        // A length of 3, followed by 2 bytes
+        let buf = b"\x03ab";
+        assert!(Cursor::new(&buf[..]).read_compact_bytes().is_err());
+
+        let items: Result<Vec<u8>, _> = zcash_deserialize_vec(Cursor::new(&buf[..]));
+        assert!(items.is_err());
+    }
+
+    #[test]
+    fn vectors_longer_than_max_allocation_fail() {
+        let mut buf = Vec::new();
+        buf.write_compactsize(u64::max_allocation() + 1).unwrap();
+        buf.extend_from_slice(&[0; 64]);
+
+        let items: Result<Vec<u64>, _> = zcash_deserialize_vec(Cursor::new(&buf[..]));
+        assert!(matches!(items, Err(SerializationError::Parse(_))));
     }
 }
@@ -0,0 +1,202 @@
+//! A 32-bit timestamp type, used in block headers and `addr` messages.
+#![allow(clippy::unit_arg)]
+
+use std::{
+    convert::{TryFrom, TryInto},
+    fmt,
+    num::TryFromIntError,
+    time::Duration,
+};
+
+use chrono::{DateTime, TimeZone, Utc};
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest_derive::Arbitrary;
+
+use super::{ZcashDeserialize, ZcashSerialize};
+
+/// A date and time, represented by a 32-bit number of seconds since the UNIX
+/// epoch.
+///
+/// Zcash serializes some times as unsigned 32-bit integers. Using this type
+/// keeps those times in range, so they can be serialized without truncating
+/// them. Arithmetic on `DateTime32`s saturates or is checked, rather than
+/// overflowing.
+#[derive(
+    Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash, ZcashSerialize, ZcashDeserialize,
+)]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
+pub struct DateTime32 {
+    timestamp: u32,
+}
+
+impl DateTime32 {
+    /// The earliest possible `DateTime32` value.
+    pub const MIN: DateTime32 = DateTime32 {
+        timestamp: u32::MIN,
+    };
+
+    /// The latest possible `DateTime32` value.
+    pub const MAX: DateTime32 = DateTime32 {
+        timestamp: u32::MAX,
+    };
+
+    /// Returns the number of seconds since the UNIX epoch.
+    pub fn timestamp(&self) -> u32 {
+        self.timestamp
+    }
+
+    /// Returns this time as a [`chrono::DateTime<Utc>`].
+    pub fn to_chrono(self) -> DateTime<Utc> {
+        self.into()
+    }
+
+    /// Returns the current time.
+    ///
+    /// Saturates at [`DateTime32::MAX`] after the year 2106.
+    pub fn now() -> DateTime32 {
+        Utc::now().try_into().unwrap_or(DateTime32::MAX)
+    }
+
+    /// Returns `self + duration`, or `None` if the result is out of range.
+    ///
+    /// Ignores any fractional seconds in `duration`.
+    pub fn checked_add(&self, duration: Duration) -> Option<DateTime32> {
+        let secs = u32::try_from(duration.as_secs()).ok()?;
+        self.timestamp.checked_add(secs).map(DateTime32::from)
+    }
+
+    /// Returns `self - duration`, or `None` if the result is out of range.
+    ///
+    /// Ignores any fractional seconds in `duration`.
+    pub fn checked_sub(&self, duration: Duration) -> Option<DateTime32> {
+        let secs = u32::try_from(duration.as_secs()).ok()?;
+        self.timestamp.checked_sub(secs).map(DateTime32::from)
+    }
+
+    /// Returns `self + duration`, saturating at [`DateTime32::MAX`].
+    pub fn saturating_add(&self, duration: Duration) -> DateTime32 {
+        self.checked_add(duration).unwrap_or(DateTime32::MAX)
+    }
+
+    /// Returns `self - duration`, saturating at [`DateTime32::MIN`].
+    pub fn saturating_sub(&self, duration: Duration) -> DateTime32 {
+        self.checked_sub(duration).unwrap_or(DateTime32::MIN)
+    }
+
+    /// Returns the time elapsed from `earlier` to `self`, or zero if `earlier`
+    /// is later than `self`.
+    pub fn saturating_duration_since(&self, earlier: DateTime32) -> Duration {
+        Duration::from_secs(self.timestamp.saturating_sub(earlier.timestamp).into())
+    }
+}
+
+impl fmt::Debug for DateTime32 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DateTime32")
+            .field("timestamp", &self.timestamp)
+            .field("calendar", &self.to_chrono())
+            .finish()
+    }
+}
+
+impl fmt::Display for DateTime32 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.to_chrono(), f)
+    }
+}
+
+impl From<u32> for DateTime32 {
+    fn from(timestamp: u32) -> Self {
+        DateTime32 { timestamp }
+    }
+}
+
+impl From<DateTime32> for u32 {
+    fn from(time: DateTime32) -> Self {
+        time.timestamp
+    }
+}
+
+impl From<DateTime32> for DateTime<Utc> {
+    fn from(time: DateTime32) -> Self {
+        Utc.timestamp(time.timestamp.into(), 0)
+    }
+}
+
+impl TryFrom<DateTime<Utc>> for DateTime32 {
+    type Error = TryFromIntError;
+
+    /// Convert `time` to a `DateTime32`, truncating any fractional seconds.
+    ///
+    /// Returns an error if `time` is before the UNIX epoch, or after the year
+    /// 2106.
+    fn try_from(time: DateTime<Utc>) -> Result<Self, Self::Error> {
+        Ok(u32::try_from(time.timestamp())?.into())
+    }
+}
+
+impl TryFrom<&DateTime<Utc>> for DateTime32 {
+    type Error = TryFromIntError;
+
+    fn try_from(time: &DateTime<Utc>) -> Result<Self, Self::Error> {
+        (*time).try_into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn chrono_conversion_is_checked() {
+        assert!(DateTime32::try_from(Utc.timestamp(-1, 0)).is_err());
+        assert!(DateTime32::try_from(Utc.timestamp(i64::from(u32::MAX) + 1, 0)).is_err());
+        assert_eq!(
+            DateTime32::try_from(Utc.timestamp(i64::from(u32::MAX), 0)),
+            Ok(DateTime32::MAX)
+        );
+    }
+
+    #[test]
+    fn arithmetic_saturates() {
+        let one_second = Duration::from_secs(1);
+
+        assert_eq!(DateTime32::MAX.checked_add(one_second), None);
+        assert_eq!(DateTime32::MAX.saturating_add(one_second), DateTime32::MAX);
+        assert_eq!(DateTime32::MIN.checked_sub(one_second), None);
+        assert_eq!(DateTime32::MIN.saturating_sub(one_second), DateTime32::MIN);
+        assert_eq!(
+            DateTime32::MIN.saturating_add(Duration::from_secs(u64::max_value())),
+            DateTime32::MAX
+        );
+
+        let time = DateTime32::from(100);
+        assert_eq!(time.checked_add(one_second), Some(DateTime32::from(101)));
+        assert_eq!(
+            time.saturating_duration_since(DateTime32::from(40)),
+            Duration::from_secs(60)
+        );
+        assert_eq!(
+            DateTime32::from(40).saturating_duration_since(time),
+            Duration::from_secs(0)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn chrono_round_trip(time in any::<DateTime32>()) {
+            prop_assert_eq!(DateTime32::try_from(time.to_chrono()), Ok(time));
+        }
+
+        #[test]
+        fn serialization_round_trip(time in any::<DateTime32>()) {
+            let mut bytes = Vec::new();
+            time.zcash_serialize(&mut bytes).unwrap();
+            prop_assert_eq!(bytes.len(), 4);
+            prop_assert_eq!(DateTime32::zcash_deserialize(&bytes[..]).unwrap(), time);
+        }
+    }
+}
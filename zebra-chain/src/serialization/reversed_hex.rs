@@ -0,0 +1,80 @@
+//! Hex encodings of hashes, in the byte order shown to users.
+//!
+//! `zcashd` treats hashes as little-endian 256-bit integers, and prints them
+//! with the most significant byte first. So the hex strings in RPC responses
+//! and block explorers are the reverse of the serialized bytes.
+
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serializer};
+
+#[cfg(feature = "serde")]
+use super::serde_hex;
+use super::SerializationError;
+
+/// Returns the hex encoding of `bytes`, in reversed byte order.
+pub fn encode(bytes: &[u8; 32]) -> String {
+    let mut reversed_bytes = *bytes;
+    reversed_bytes.reverse();
+    hex::encode(&reversed_bytes)
+}
+
+/// Parse a hex string in reversed byte order, returning the bytes in
+/// serialized order.
+pub fn decode(s: &str) -> Result<[u8; 32], SerializationError> {
+    if s.len() != 64 {
+        return Err(SerializationError::Parse(
+            "hash hex strings must be 64 characters long",
+        ));
+    }
+    let mut bytes = [0; 32];
+    hex::decode_to_slice(s, &mut bytes[..])
+        .map_err(|_| SerializationError::Parse("hash hex strings must only contain hex digits"))?;
+    bytes.reverse();
+    Ok(bytes)
+}
+
+/// Serialize `bytes` as a reversed hex string in human-readable formats, and
+/// as raw bytes otherwise.
+#[cfg(feature = "serde")]
+pub fn serialize<S: Serializer>(bytes: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&encode(bytes))
+    } else {
+        serde_hex::bytes32::serialize(bytes, serializer)
+    }
+}
+
+/// Deserialize bytes written by [`serialize`].
+#[cfg(feature = "serde")]
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 32], D::Error> {
+    if deserializer.is_human_readable() {
+        decode(&String::deserialize(deserializer)?).map_err(de::Error::custom)
+    } else {
+        serde_hex::bytes32::deserialize(deserializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_reverses_encode() {
+        let mut bytes = [0; 32];
+        bytes[0] = 0x01;
+        bytes[31] = 0xab;
+
+        let hex = encode(&bytes);
+        assert!(hex.starts_with("ab"));
+        assert!(hex.ends_with("01"));
+        assert_eq!(decode(&hex).unwrap(), bytes);
+    }
+
+    #[test]
+    fn decode_rejects_bad_strings() {
+        assert!(decode("").is_err());
+        assert!(decode(&"00".repeat(31)).is_err());
+        assert!(decode(&"00".repeat(33)).is_err());
+        assert!(decode(&"zz".repeat(32)).is_err());
+    }
+}
@@ -0,0 +1,209 @@
+//! Serde helpers for byte strings.
+//!
+//! Human-readable formats, like JSON, get lowercase hex strings. Other
+//! formats get raw bytes.
+//!
+//! The module-level functions can be used with `#[serde(with = "serde_hex")]`
+//! on `Vec<u8>` and `Bytes` fields, and the submodules handle fixed-size
+//! fields.
+
+use std::fmt;
+
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serializer,
+};
+
+/// Serialize `bytes` as a hex string or a byte string, depending on the
+/// format.
+pub fn serialize<T, S>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: AsRef<[u8]> + ?Sized,
+    S: Serializer,
+{
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&hex::encode(bytes))
+    } else {
+        serializer.serialize_bytes(bytes.as_ref())
+    }
+}
+
+/// Deserialize bytes written by [`serialize`].
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: From<Vec<u8>>,
+    D: Deserializer<'de>,
+{
+    let bytes = if deserializer.is_human_readable() {
+        let string = String::deserialize(deserializer)?;
+        hex::decode(&string).map_err(de::Error::custom)?
+    } else {
+        deserializer.deserialize_byte_buf(BytesVisitor)?
+    };
+    Ok(bytes.into())
+}
+
+/// Deserialize bytes written by [`serialize`] into `bytes`, failing if the
+/// input has a different length.
+pub fn deserialize_into<'de, D: Deserializer<'de>>(
+    deserializer: D,
+    bytes: &mut [u8],
+) -> Result<(), D::Error> {
+    let decoded: Vec<u8> = deserialize(deserializer)?;
+    if decoded.len() != bytes.len() {
+        return Err(de::Error::custom(format!(
+            "expected {} bytes, found {}",
+            bytes.len(),
+            decoded.len()
+        )));
+    }
+    bytes.copy_from_slice(&decoded);
+    Ok(())
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a byte string")
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Self::Value, E> {
+        Ok(bytes.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(bytes)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(bytes)
+    }
+}
+
+/// Serde `with` functions for `[u8; 32]`, and for types that convert to and
+/// from it, like key and signature encodings.
+pub mod bytes32 {
+    use serde::{Deserializer, Serializer};
+
+    /// Serialize `value` as 32 bytes.
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Copy + Into<[u8; 32]>,
+        S: Serializer,
+    {
+        let bytes: [u8; 32] = (*value).into();
+        super::serialize(&bytes, serializer)
+    }
+
+    /// Deserialize a value from 32 bytes.
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: From<[u8; 32]>,
+        D: Deserializer<'de>,
+    {
+        let mut bytes = [0; 32];
+        super::deserialize_into(deserializer, &mut bytes)?;
+        Ok(bytes.into())
+    }
+}
+
+/// Serde `with` functions for pairs of `[u8; 32]`.
+pub mod bytes32_pair {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Bytes32(#[serde(with = "super::bytes32")] [u8; 32]);
+
+    /// Serialize `pair` as a sequence of two byte strings.
+    pub fn serialize<S: Serializer>(
+        pair: &[[u8; 32]; 2],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        [Bytes32(pair[0]), Bytes32(pair[1])].serialize(serializer)
+    }
+
+    /// Deserialize a sequence of two byte strings.
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<[[u8; 32]; 2], D::Error> {
+        let [Bytes32(first), Bytes32(second)] = <[Bytes32; 2]>::deserialize(deserializer)?;
+        Ok([first, second])
+    }
+}
+
+/// Serde `with` functions for types that convert to and from `[u8; 64]`,
+/// like signature encodings.
+pub mod bytes64 {
+    use serde::{Deserializer, Serializer};
+
+    /// Serialize `value` as 64 bytes.
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Copy + Into<[u8; 64]>,
+        S: Serializer,
+    {
+        let bytes: [u8; 64] = (*value).into();
+        super::serialize(&bytes[..], serializer)
+    }
+
+    /// Deserialize a value from 64 bytes.
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: From<[u8; 64]>,
+        D: Deserializer<'de>,
+    {
+        let mut bytes = [0; 64];
+        super::deserialize_into(deserializer, &mut bytes)?;
+        Ok(bytes.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Serialize;
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Fields {
+        #[serde(with = "super")]
+        vec: Vec<u8>,
+        #[serde(with = "bytes32")]
+        array: [u8; 32],
+        #[serde(with = "bytes32_pair")]
+        pair: [[u8; 32]; 2],
+    }
+
+    #[test]
+    fn json_uses_hex() {
+        let fields = Fields {
+            vec: vec![0x01, 0xab],
+            array: [0x11; 32],
+            pair: [[0x22; 32], [0x33; 32]],
+        };
+
+        let json = serde_json::to_value(&fields).unwrap();
+        assert_eq!(json["vec"], "01ab");
+        assert_eq!(json["array"], "11".repeat(32));
+        assert_eq!(json["pair"][1], "33".repeat(32));
+
+        assert_eq!(serde_json::from_value::<Fields>(json).unwrap(), fields);
+    }
+
+    #[test]
+    fn wrong_lengths_are_rejected() {
+        let json = serde_json::json!({
+            "vec": "",
+            "array": "11",
+            "pair": ["22", "33"],
+        });
+        assert!(serde_json::from_value::<Fields>(json).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+//! Deserialization that shares byte strings with the buffer it reads from.
+//!
+//! Blocks and transactions from peers arrive in a [`Bytes`] buffer. Reading
+//! them through a [`SharedBytes`] reader makes their variable-length byte
+//! strings, like scripts and Halo2 proofs, slices of that buffer, instead of
+//! copies. Every other field is parsed as usual.
+//!
+//! Other readers can be wrapped in a [`CopyReader`], which copies byte
+//! strings out of the reader, so each type only needs one deserializer.
+
+use std::io::{self, Read};
+
+use bytes::{Buf, Bytes};
+
+use super::{ReadZcashExt, SerializationError, TrustedPreallocate};
+
+/// A reader that can return byte strings without copying them.
+pub trait SharedRead: io::Read {
+    /// Read the next `len` bytes, sharing them with the reader's buffer if it
+    /// has one.
+    ///
+    /// Returns an error if the reader ends before `len` bytes are read.
+    fn read_shared(&mut self, len: u64) -> io::Result<Bytes>;
+
+    /// Read bytes with a `CompactSize` length prefix, like
+    /// [`ReadZcashExt::read_compact_bytes`].
+    fn read_shared_compact_bytes(&mut self) -> Result<Bytes, SerializationError> {
+        let len = self.read_compactsize()?;
+        Ok(self.read_shared(len)?)
+    }
+}
+
+impl<R: SharedRead + ?Sized> SharedRead for &mut R {
+    fn read_shared(&mut self, len: u64) -> io::Result<Bytes> {
+        (**self).read_shared(len)
+    }
+}
+
+/// A reader that runs out at its limit, so a [`SharedBytes`] can be limited
+/// to the size of a block.
+impl<R: SharedRead> SharedRead for io::Take<R> {
+    fn read_shared(&mut self, len: u64) -> io::Result<Bytes> {
+        let limit = self.limit();
+        if len > limit {
+            // Consume the rest of the limit, so callers can tell that the
+            // reader ran out at the limit.
+            io::copy(self, &mut io::sink())?;
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let bytes = self.get_mut().read_shared(len)?;
+        self.set_limit(limit - len);
+        Ok(bytes)
+    }
+}
+
+/// A [`SharedRead`]er over a [`Bytes`] buffer.
+///
+/// Byte strings are split off the buffer, so they keep the whole buffer
+/// alive until they are dropped.
+#[derive(Clone, Debug, Default)]
+pub struct SharedBytes(Bytes);
+
+impl SharedBytes {
+    /// Returns a reader for `bytes`.
+    pub fn new(bytes: Bytes) -> SharedBytes {
+        SharedBytes(bytes)
+    }
+
+    /// Returns the bytes that haven't been read yet.
+    pub fn remaining(&self) -> &[u8] {
+        &self.0[..]
+    }
+}
+
+impl io::Read for SharedBytes {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = std::cmp::min(buf.len(), self.0.len());
+        buf[..len].copy_from_slice(&self.0[..len]);
+        self.0.advance(len);
+        Ok(len)
+    }
+}
+
+impl SharedRead for SharedBytes {
+    fn read_shared(&mut self, len: u64) -> io::Result<Bytes> {
+        if len > self.0.len() as u64 {
+            self.0.advance(self.0.len());
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(self.0.split_to(len as usize))
+    }
+}
+
+/// A [`SharedRead`]er that copies byte strings out of any other reader.
+#[derive(Debug)]
+pub struct CopyReader<R>(pub R);
+
+impl<R: io::Read> io::Read for CopyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<R: io::Read> SharedRead for CopyReader<R> {
+    fn read_shared(&mut self, len: u64) -> io::Result<Bytes> {
+        // The length comes from untrusted data, so allocate as we read.
+        let mut bytes = Vec::new();
+        (&mut self.0).take(len).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(bytes.into())
+    }
+}
+
+/// Consensus-critical deserialization that shares byte strings with a
+/// [`SharedRead`]er.
+///
+/// Types that implement this trait usually implement
+/// [`ZcashDeserialize`](super::ZcashDeserialize) by reading from a
+/// [`CopyReader`].
+pub trait ZcashDeserializeShared: Sized {
+    /// Try to read `self` from the given `reader`, sharing its byte strings
+    /// with the reader's buffer.
+    fn zcash_deserialize_shared<R: SharedRead>(reader: R) -> Result<Self, SerializationError>;
+}
+
+impl<T: ZcashDeserializeShared> ZcashDeserializeShared for std::sync::Arc<T> {
+    fn zcash_deserialize_shared<R: SharedRead>(reader: R) -> Result<Self, SerializationError> {
+        Ok(std::sync::Arc::new(T::zcash_deserialize_shared(reader)?))
+    }
+}
+
+/// Read a `CompactSize` count, followed by that many items, like
+/// [`zcash_deserialize_vec`](super::zcash_deserialize_vec).
+pub fn zcash_deserialize_shared_vec<T, R>(mut reader: R) -> Result<Vec<T>, SerializationError>
+where
+    T: ZcashDeserializeShared + TrustedPreallocate,
+    R: SharedRead,
+{
+    let count = reader.read_compactsize()?;
+    if count > T::max_allocation() {
+        return Err(SerializationError::Parse(
+            "vector is longer than the maximum allocation for its items",
+        ));
+    }
+    let mut items = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        items.push(T::zcash_deserialize_shared(&mut reader)?);
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_byte_strings_are_slices_of_the_buffer() {
+        let buffer = Bytes::from(&b"\x03abc\x02de"[..]);
+        let mut reader = SharedBytes::new(buffer.clone());
+
+        let abc = reader.read_shared_compact_bytes().unwrap();
+        let de = reader.read_shared_compact_bytes().unwrap();
+        assert_eq!(&abc[..], b"abc");
+        assert_eq!(&de[..], b"de");
+        assert_eq!(abc.as_ptr(), buffer[1..].as_ptr());
+        assert_eq!(de.as_ptr(), buffer[5..].as_ptr());
+        assert!(reader.remaining().is_empty());
+
+        let mut truncated = SharedBytes::new(Bytes::from(&b"\x03ab"[..]));
+        assert!(truncated.read_shared_compact_bytes().is_err());
+    }
+
+    #[test]
+    fn copied_byte_strings_match_shared_ones() {
+        let data = b"\x03abc\x02de";
+        let mut reader = CopyReader(&data[..]);
+        assert_eq!(&reader.read_shared_compact_bytes().unwrap()[..], b"abc");
+        assert_eq!(&reader.read_shared_compact_bytes().unwrap()[..], b"de");
+        assert!(reader.read_shared_compact_bytes().is_err());
+
+        let mut limited = SharedBytes::new(Bytes::from(&data[..])).take(3);
+        assert!(limited.read_shared_compact_bytes().is_err());
+        assert_eq!(limited.limit(), 0);
+    }
+}
@@ -0,0 +1,12 @@
+//! Sprout shielded transfers.
+//!
+//! Sprout JoinSplits are generic over their proof system: transactions
+//! before Sapling use BCTV14 proofs, and later transactions use Groth16.
+
+mod joinsplit;
+mod nullifier;
+
+pub mod tree;
+
+pub use joinsplit::{JoinSplit, JoinSplitData};
+pub use nullifier::Nullifier;
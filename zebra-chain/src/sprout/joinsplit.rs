@@ -1,45 +1,54 @@
-#[cfg(test)]
+#[cfg(any(test, feature = "proptest-impl"))]
 use proptest::{array, collection::vec, prelude::*};
 
-use crate::{ed25519_zebra, notes::sprout, proofs::ZkSnarkProof};
+#[cfg(feature = "serde")]
+use crate::serialization::serde_hex;
+use crate::{
+    amount::{Amount, NonNegative},
+    ed25519_zebra,
+    notes::sprout,
+    proofs::ZkSnarkProof,
+};
+
+use super::Nullifier;
 
 /// A _JoinSplit Description_, as described in [protocol specification §7.2][ps].
 ///
 /// [ps]: https://zips.z.cash/protocol/protocol.pdf#joinsplitencoding
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct JoinSplit<P: ZkSnarkProof> {
     /// A value that the JoinSplit transfer removes from the transparent value
     /// pool.
-    ///
-    /// XXX refine to an Amount
-    pub vpub_old: u64,
+    pub vpub_old: Amount<NonNegative>,
     /// A value that the JoinSplit transfer inserts into the transparent value
     /// pool.
-    ///
-    /// XXX refine to an Amount
-    pub vpub_new: u64,
+    pub vpub_new: Amount<NonNegative>,
     /// A root of the Sprout note commitment tree at some block height in the
     /// past, or the root produced by a previous JoinSplit transfer in this
     /// transaction.
     ///
     /// XXX refine type
+    #[cfg_attr(feature = "serde", serde(with = "serde_hex::bytes32"))]
     pub anchor: [u8; 32],
     /// A nullifier for the input notes.
-    ///
-    /// XXX refine type to [T; 2] -- there are two nullifiers
-    pub nullifiers: [[u8; 32]; 2],
+    pub nullifiers: [Nullifier; 2],
     /// A note commitment for this output note.
     ///
     /// XXX refine type to [T; 2] -- there are two commitments
+    #[cfg_attr(feature = "serde", serde(with = "serde_hex::bytes32_pair"))]
     pub commitments: [[u8; 32]; 2],
     /// An X25519 public key.
+    #[cfg_attr(feature = "serde", serde(with = "x25519_public_key"))]
     pub ephemeral_key: x25519_dalek::PublicKey,
     /// A 256-bit seed that must be chosen independently at random for each
     /// JoinSplit description.
+    #[cfg_attr(feature = "serde", serde(with = "serde_hex::bytes32"))]
     pub random_seed: [u8; 32],
     /// A message authentication tag.
     ///
     /// XXX refine type to [T; 2] -- there are two macs
+    #[cfg_attr(feature = "serde", serde(with = "serde_hex::bytes32_pair"))]
     pub vmacs: [[u8; 32]; 2],
     /// A ZK JoinSplit proof, either a
     /// [`Groth16Proof`](crate::proofs::Groth16Proof) or a
@@ -49,6 +58,28 @@ pub struct JoinSplit<P: ZkSnarkProof> {
     pub enc_ciphertexts: [sprout::EncryptedCiphertext; 2],
 }
 
+/// Serde `with` functions for X25519 public keys, which don't implement the
+/// byte array conversions that [`serde_hex::bytes32`] needs.
+#[cfg(feature = "serde")]
+mod x25519_public_key {
+    use serde::{Deserializer, Serializer};
+
+    use crate::serialization::serde_hex;
+
+    pub fn serialize<S: Serializer>(
+        key: &x25519_dalek::PublicKey,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serde_hex::serialize(key.as_bytes(), serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<x25519_dalek::PublicKey, D::Error> {
+        serde_hex::bytes32::deserialize::<[u8; 32], _>(deserializer).map(Into::into)
+    }
+}
+
 // Because x25519_dalek::PublicKey does not impl PartialEq
 impl<P: ZkSnarkProof> PartialEq for JoinSplit<P> {
     fn eq(&self, other: &Self) -> bool {
@@ -68,16 +99,16 @@ impl<P: ZkSnarkProof> PartialEq for JoinSplit<P> {
 // Because x25519_dalek::PublicKey does not impl Eq
 impl<P: ZkSnarkProof> Eq for JoinSplit<P> {}
 
-#[cfg(test)]
+#[cfg(any(test, feature = "proptest-impl"))]
 impl<P: ZkSnarkProof + Arbitrary + 'static> Arbitrary for JoinSplit<P> {
     type Parameters = ();
 
     fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
         (
-            any::<u64>(),
-            any::<u64>(),
+            any::<Amount<NonNegative>>(),
+            any::<Amount<NonNegative>>(),
             array::uniform32(any::<u8>()),
-            array::uniform2(array::uniform32(any::<u8>())),
+            array::uniform2(any::<Nullifier>()),
             array::uniform2(array::uniform32(any::<u8>())),
             array::uniform32(any::<u8>()),
             array::uniform32(any::<u8>()),
@@ -120,6 +151,7 @@ impl<P: ZkSnarkProof + Arbitrary + 'static> Arbitrary for JoinSplit<P> {
 
 /// A bundle of JoinSplit descriptions and signature data.
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct JoinSplitData<P: ZkSnarkProof> {
     /// The first JoinSplit description, using proofs of type `P`.
     ///
@@ -136,8 +168,10 @@ pub struct JoinSplitData<P: ZkSnarkProof> {
     /// all `JoinSplit`s.
     pub rest: Vec<JoinSplit<P>>,
     /// The public key for the JoinSplit signature.
-    pub pub_key: ed25519_zebra::PublicKeyBytes,
+    #[cfg_attr(feature = "serde", serde(with = "serde_hex::bytes32"))]
+    pub pub_key: ed25519_zebra::VerificationKeyBytes,
     /// The JoinSplit signature.
+    #[cfg_attr(feature = "serde", serde(with = "serde_hex::bytes64"))]
     pub sig: ed25519_zebra::Signature,
 }
 
@@ -148,7 +182,7 @@ impl<P: ZkSnarkProof> JoinSplitData<P> {
     }
 }
 
-#[cfg(test)]
+#[cfg(any(test, feature = "proptest-impl"))]
 impl<P: ZkSnarkProof + Arbitrary + 'static> Arbitrary for JoinSplitData<P> {
     type Parameters = ();
 
@@ -162,7 +196,7 @@ impl<P: ZkSnarkProof + Arbitrary + 'static> Arbitrary for JoinSplitData<P> {
             .prop_map(|(first, rest, pub_key_bytes, sig_bytes)| Self {
                 first,
                 rest,
-                pub_key: ed25519_zebra::PublicKeyBytes::from(pub_key_bytes),
+                pub_key: ed25519_zebra::VerificationKeyBytes::from(pub_key_bytes),
                 sig: ed25519_zebra::Signature::from({
                     let mut b = [0u8; 64];
                     b.copy_from_slice(sig_bytes.as_slice());
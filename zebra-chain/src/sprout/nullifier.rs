@@ -0,0 +1,44 @@
+#![allow(clippy::unit_arg)]
+use std::fmt;
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest_derive::Arbitrary;
+
+use crate::serialization::{ZcashDeserialize, ZcashSerialize};
+
+/// A Sprout nullifier, which is revealed when a JoinSplit spends a note.
+///
+/// Sprout nullifiers are the output of PRF^nf, a SHA-256 compression of the
+/// note's spending key and ρ. Each nullifier can only appear once in the
+/// chain, so the state tracks them to prevent double-spends.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, ZcashSerialize, ZcashDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
+pub struct Nullifier(
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::serde_hex::bytes32")
+    )]
+    #[zcash(fixed)]
+    pub [u8; 32],
+);
+
+impl fmt::Debug for Nullifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("sprout::Nullifier")
+            .field(&hex::encode(&self.0))
+            .finish()
+    }
+}
+
+impl From<[u8; 32]> for Nullifier {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<Nullifier> for [u8; 32] {
+    fn from(nullifier: Nullifier) -> Self {
+        nullifier.0
+    }
+}
@@ -0,0 +1,308 @@
+//! The Sprout note commitment tree.
+//!
+//! Like the [Sapling tree](crate::sapling::tree), this is an incremental
+//! Merkle tree, but it is only 29 levels deep, and its nodes are hashed with
+//! the SHA-256 compression function.
+//!
+//! Each JoinSplit has an anchor, which is the root of the tree after a block,
+//! or after an earlier JoinSplit in the same transaction.
+#![allow(clippy::unit_arg)]
+
+use std::{fmt, io};
+
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use lazy_static::lazy_static;
+use once_cell::sync::OnceCell;
+use thiserror::Error;
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest_derive::Arbitrary;
+
+use crate::serialization::{
+    ReadZcashExt, SerializationError, WriteZcashExt, ZcashDeserialize, ZcashSerialize,
+};
+
+/// The depth of the Sprout note commitment tree.
+pub const MERKLE_DEPTH: usize = 29;
+
+/// The SHA-256 initial hash value, which SHA256Compress starts from.
+const SHA256_IV: [u32; 8] = [
+    0x6a09_e667,
+    0xbb67_ae85,
+    0x3c6e_f372,
+    0xa54f_f53a,
+    0x510e_527f,
+    0x9b05_688c,
+    0x1f83_d9ab,
+    0x5be0_cd19,
+];
+
+lazy_static! {
+    /// The roots of empty subtrees, indexed by their height above the
+    /// leaves.
+    ///
+    /// The empty leaf is all zeroes.
+    static ref EMPTY_ROOTS: Vec<[u8; 32]> = {
+        let mut roots = vec![[0; 32]];
+        for height in 0..MERKLE_DEPTH {
+            let below = roots[height];
+            roots.push(merkle_crh(below, below));
+        }
+        roots
+    };
+}
+
+/// MerkleCRH^Sprout, the SHA-256 compression of two child nodes.
+///
+/// Unlike the later trees, the hash doesn't depend on the height.
+///
+/// https://zips.z.cash/protocol/protocol.pdf#merklecrh
+fn merkle_crh(left: [u8; 32], right: [u8; 32]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    block[..32].copy_from_slice(&left[..]);
+    block[32..].copy_from_slice(&right[..]);
+
+    let mut state = SHA256_IV;
+    sha2::compress256(&mut state, &block);
+
+    let mut node = [0u8; 32];
+    BigEndian::write_u32_into(&state, &mut node);
+    node
+}
+
+/// The root of a Sprout note commitment tree, also known as an anchor.
+#[derive(Clone, Copy, Default, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
+pub struct Root(
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serialization::serde_hex::bytes32")
+    )]
+    pub [u8; 32],
+);
+
+impl fmt::Debug for Root {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("sprout::tree::Root")
+            .field(&hex::encode(&self.0))
+            .finish()
+    }
+}
+
+/// An error appending a note commitment to a [`NoteCommitmentTree`].
+#[derive(Error, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum NoteCommitmentTreeError {
+    /// The tree already has 2^29 note commitments.
+    #[error("the note commitment tree is full")]
+    FullTree,
+}
+
+/// An incremental Sprout note commitment tree.
+///
+/// Like the Sapling tree, the tree only stores its frontier, and it
+/// serializes the same way as `zcashd`'s `SproutMerkleTree`.
+#[derive(Clone, Debug, Default)]
+pub struct NoteCommitmentTree {
+    left: Option<[u8; 32]>,
+    right: Option<[u8; 32]>,
+    /// The roots of complete subtrees, with `parents[i]` at height `i + 1`.
+    parents: Vec<Option<[u8; 32]>>,
+    /// The root, if it has been computed since the last append.
+    cached_root: OnceCell<Root>,
+}
+
+impl NoteCommitmentTree {
+    /// Append the note commitment `cm` as the next leaf of the tree.
+    ///
+    /// Each JoinSplit has two commitments, which are appended in order.
+    pub fn append(&mut self, cm: [u8; 32]) -> Result<(), NoteCommitmentTreeError> {
+        if self.is_complete() {
+            return Err(NoteCommitmentTreeError::FullTree);
+        }
+        self.cached_root = OnceCell::new();
+
+        let (left, right) = match (self.left, self.right) {
+            (None, _) => {
+                self.left = Some(cm);
+                return Ok(());
+            }
+            (Some(_), None) => {
+                self.right = Some(cm);
+                return Ok(());
+            }
+            (Some(left), Some(right)) => (left, right),
+        };
+
+        // Both leaves are full, so carry their hash up into the parents.
+        self.left = Some(cm);
+        self.right = None;
+        let mut combined = merkle_crh(left, right);
+        for parent in self.parents.iter_mut() {
+            match parent.take() {
+                Some(p) => combined = merkle_crh(p, combined),
+                None => {
+                    *parent = Some(combined);
+                    return Ok(());
+                }
+            }
+        }
+        self.parents.push(Some(combined));
+        Ok(())
+    }
+
+    /// Return the root of the tree, which is the anchor for JoinSplits that
+    /// spend the notes in it.
+    pub fn root(&self) -> Root {
+        *self.cached_root.get_or_init(|| self.compute_root())
+    }
+
+    fn compute_root(&self) -> Root {
+        let empty_leaf = EMPTY_ROOTS[0];
+        let mut root = merkle_crh(
+            self.left.unwrap_or(empty_leaf),
+            self.right.unwrap_or(empty_leaf),
+        );
+        for height in 1..MERKLE_DEPTH {
+            root = match self.parents.get(height - 1) {
+                Some(Some(parent)) => merkle_crh(*parent, root),
+                _ => merkle_crh(root, EMPTY_ROOTS[height]),
+            };
+        }
+
+        Root(root)
+    }
+
+    /// Return the number of note commitments in the tree.
+    pub fn count(&self) -> u64 {
+        let leaves = self.left.is_some() as u64 + self.right.is_some() as u64;
+        self.parents
+            .iter()
+            .enumerate()
+            .filter(|(_, parent)| parent.is_some())
+            .fold(leaves, |count, (i, _)| count + (1 << (i + 1)))
+    }
+
+    fn is_complete(&self) -> bool {
+        self.left.is_some()
+            && self.right.is_some()
+            && self.parents.len() == MERKLE_DEPTH - 1
+            && self.parents.iter().all(Option::is_some)
+    }
+}
+
+impl PartialEq for NoteCommitmentTree {
+    fn eq(&self, other: &Self) -> bool {
+        self.left == other.left && self.right == other.right && self.parents == other.parents
+    }
+}
+
+impl Eq for NoteCommitmentTree {}
+
+fn write_node<W: io::Write>(node: Option<[u8; 32]>, mut writer: W) -> Result<(), io::Error> {
+    match node {
+        Some(node) => {
+            writer.write_u8(1)?;
+            writer.write_all(&node[..])
+        }
+        None => writer.write_u8(0),
+    }
+}
+
+fn read_node<R: io::Read>(mut reader: R) -> Result<Option<[u8; 32]>, SerializationError> {
+    match reader.read_u8()? {
+        0 => Ok(None),
+        1 => Ok(Some(reader.read_32_bytes()?)),
+        _ => Err(SerializationError::Parse(
+            "invalid note commitment tree node",
+        )),
+    }
+}
+
+impl ZcashSerialize for NoteCommitmentTree {
+    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        write_node(self.left, &mut writer)?;
+        write_node(self.right, &mut writer)?;
+        writer.write_compactsize(self.parents.len() as u64)?;
+        for parent in &self.parents {
+            write_node(*parent, &mut writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl ZcashDeserialize for NoteCommitmentTree {
+    fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let left = read_node(&mut reader)?;
+        let right = read_node(&mut reader)?;
+        if left.is_none() && right.is_some() {
+            return Err(SerializationError::Parse(
+                "note commitment tree has a right leaf without a left leaf",
+            ));
+        }
+        let parent_count = reader.read_compactsize()? as usize;
+        if parent_count >= MERKLE_DEPTH {
+            return Err(SerializationError::Parse(
+                "note commitment tree is deeper than the Sprout tree",
+            ));
+        }
+        let parents = (0..parent_count)
+            .map(|_| read_node(&mut reader))
+            .collect::<Result<_, _>>()?;
+        Ok(NoteCommitmentTree {
+            left,
+            right,
+            parents,
+            cached_root: OnceCell::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex_node(s: &str) -> [u8; 32] {
+        let mut node = [0; 32];
+        hex::decode_to_slice(s, &mut node[..]).unwrap();
+        node
+    }
+
+    #[test]
+    fn empty_roots() {
+        assert_eq!(
+            EMPTY_ROOTS[1],
+            hex_node("da5698be17b9b46962335799779fbeca8ce5d491c0d26243bafef9ea1837a9d8")
+        );
+        assert_eq!(
+            EMPTY_ROOTS[2],
+            hex_node("dc766fab492ccf3d1e49d4f374b5235fa56506aac2224d39f943fcd49202974c")
+        );
+        assert_eq!(
+            NoteCommitmentTree::default().root(),
+            Root(hex_node(
+                "d7c612c817793191a1e68652121876d6b3bde40f4fa52bc314145ce6e5cdd259"
+            ))
+        );
+    }
+
+    #[test]
+    fn append_matches_the_empty_subtrees() {
+        // Appending empty leaves doesn't change the root.
+        let mut tree = NoteCommitmentTree::default();
+        for _ in 0..5 {
+            tree.append([0; 32]).unwrap();
+        }
+        assert_eq!(tree.count(), 5);
+        assert_eq!(tree.root(), NoteCommitmentTree::default().root());
+
+        tree.append([1; 32]).unwrap();
+        assert_ne!(tree.root(), NoteCommitmentTree::default().root());
+
+        let mut bytes = Vec::new();
+        tree.zcash_serialize(&mut bytes).unwrap();
+        let other = NoteCommitmentTree::zcash_deserialize(&bytes[..]).unwrap();
+        assert_eq!(other, tree);
+        assert_eq!(other.root(), tree.root());
+    }
+}
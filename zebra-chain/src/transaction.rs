@@ -1,23 +1,39 @@
 //! Transaction types.
 
+mod auth_digest;
 mod hash;
-mod joinsplit;
+mod lock_time;
 mod serialize;
 mod shielded_data;
+mod sighash;
 mod transparent;
+mod txid;
 
+#[cfg(any(test, feature = "proptest-impl"))]
+mod arbitrary;
 #[cfg(test)]
 mod test_vectors;
 #[cfg(test)]
 mod tests;
 
-pub use hash::TransactionHash;
-pub use joinsplit::{JoinSplit, JoinSplitData};
+pub use auth_digest::{AuthDigest, UnminedTxId, WtxId};
+pub use hash::Hash;
+pub use lock_time::LockTime;
 pub use shielded_data::{Output, ShieldedData, Spend};
-pub use transparent::{CoinbaseData, OutPoint, TransparentInput, TransparentOutput};
+pub use sighash::{HashType, SigHash, SigHashError};
+pub use transparent::{
+    CoinbaseData, OutPoint, TransparentInput, TransparentOutput, MAX_COINBASE_HEIGHT_LEN,
+    MAX_COINBASE_SCRIPT_LEN,
+};
 
+use chrono::{DateTime, Utc};
+
+use crate::amount::Amount;
+use crate::block;
+use crate::orchard;
 use crate::proofs::{Bctv14Proof, Groth16Proof};
-use crate::types::{BlockHeight, LockTime};
+use crate::sapling;
+use crate::sprout::{self, JoinSplitData};
 
 /// A Zcash transaction.
 ///
@@ -31,6 +47,7 @@ use crate::types::{BlockHeight, LockTime};
 /// internally by different enum variants. Because we checkpoint on Sapling
 /// activation, we do not parse any pre-Sapling transaction types.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 // XXX consider boxing the Optional fields of V4 txs
 #[allow(clippy::large_enum_variant)]
 pub enum Transaction {
@@ -66,7 +83,7 @@ pub enum Transaction {
         /// chain.
         lock_time: LockTime,
         /// The latest block height that this transaction can be added to the chain.
-        expiry_height: BlockHeight,
+        expiry_height: block::Height,
         /// The JoinSplit data for this transaction, if any.
         joinsplit_data: Option<JoinSplitData<Bctv14Proof>>,
     },
@@ -80,15 +97,43 @@ pub enum Transaction {
         /// chain.
         lock_time: LockTime,
         /// The latest block height that this transaction can be added to the chain.
-        expiry_height: BlockHeight,
+        expiry_height: block::Height,
         /// The net value of Sapling spend transfers minus output transfers.
-        // XXX refine this to an Amount type.
-        value_balance: i64,
+        value_balance: Amount,
         /// The shielded data for this transaction, if any.
         shielded_data: Option<ShieldedData>,
         /// The JoinSplit data for this transaction, if any.
         joinsplit_data: Option<JoinSplitData<Groth16Proof>>,
     },
+    /// An NU5 transaction (`version = 5`).
+    V5 {
+        /// The transparent inputs to the transaction.
+        inputs: Vec<TransparentInput>,
+        /// The transparent outputs from the transaction.
+        outputs: Vec<TransparentOutput>,
+        /// The earliest time or block height that this transaction can be added to the
+        /// chain.
+        lock_time: LockTime,
+        /// The latest block height that this transaction can be added to the chain.
+        expiry_height: block::Height,
+        /// The consensus branch ID of the network upgrade this transaction
+        /// was created for.
+        ///
+        /// XXX refine to a NetworkUpgrade.
+        consensus_branch_id: u32,
+        /// The net value of Sapling spend transfers minus output transfers.
+        ///
+        /// This is only serialized if there is shielded data, so it must be
+        /// zero otherwise.
+        sapling_value_balance: Amount,
+        /// The Sapling shielded data for this transaction, if any.
+        ///
+        /// Version 5 transactions have a single anchor for all their spends,
+        /// so every spend must have the same anchor.
+        sapling_shielded_data: Option<ShieldedData>,
+        /// The Orchard shielded data for this transaction, if any.
+        orchard_shielded_data: Option<orchard::ShieldedData>,
+    },
 }
 
 impl Transaction {
@@ -99,6 +144,7 @@ impl Transaction {
             Transaction::V2 { ref inputs, .. } => inputs.iter(),
             Transaction::V3 { ref inputs, .. } => inputs.iter(),
             Transaction::V4 { ref inputs, .. } => inputs.iter(),
+            Transaction::V5 { ref inputs, .. } => inputs.iter(),
         }
     }
 
@@ -109,9 +155,49 @@ impl Transaction {
             Transaction::V2 { ref outputs, .. } => outputs.iter(),
             Transaction::V3 { ref outputs, .. } => outputs.iter(),
             Transaction::V4 { ref outputs, .. } => outputs.iter(),
+            Transaction::V5 { ref outputs, .. } => outputs.iter(),
         }
     }
 
+    /// Returns true if this is a coinbase transaction, which has a single
+    /// coinbase input.
+    ///
+    /// Only the first transaction in a block can be a coinbase transaction.
+    pub fn is_coinbase(&self) -> bool {
+        let mut inputs = self.inputs();
+        match (inputs.next(), inputs.next()) {
+            (Some(TransparentInput::Coinbase { .. }), None) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if any of this transaction's inputs is a coinbase input.
+    ///
+    /// Transactions that contain a coinbase input, but aren't coinbase
+    /// transactions, are invalid.
+    pub fn contains_coinbase_input(&self) -> bool {
+        self.inputs()
+            .any(|input| matches!(input, TransparentInput::Coinbase { .. }))
+    }
+
+    /// Returns the number of signature operations in this transaction's
+    /// transparent input and output scripts, counted like `zcashd`'s legacy
+    /// sigop count.
+    ///
+    /// The redeem scripts of P2SH inputs aren't counted.
+    pub fn legacy_sigop_count(&self) -> u32 {
+        let inputs = self.inputs().map(|input| match input {
+            TransparentInput::PrevOut { script, .. } => script.legacy_sigop_count(),
+            TransparentInput::Coinbase { height, data, .. } => {
+                serialize::coinbase_script(*height, data).legacy_sigop_count()
+            }
+        });
+        let outputs = self
+            .outputs()
+            .map(|output| output.pk_script.legacy_sigop_count());
+        inputs.chain(outputs).sum()
+    }
+
     /// Get this transaction's lock time.
     pub fn lock_time(&self) -> LockTime {
         match self {
@@ -119,16 +205,187 @@ impl Transaction {
             Transaction::V2 { lock_time, .. } => *lock_time,
             Transaction::V3 { lock_time, .. } => *lock_time,
             Transaction::V4 { lock_time, .. } => *lock_time,
+            Transaction::V5 { lock_time, .. } => *lock_time,
+        }
+    }
+
+    /// Iterate over the Sprout nullifiers revealed by this transaction's
+    /// JoinSplits, if any.
+    pub fn sprout_nullifiers(&self) -> Box<dyn Iterator<Item = &sprout::Nullifier> + '_> {
+        match self {
+            Transaction::V2 { joinsplit_data, .. } | Transaction::V3 { joinsplit_data, .. } => {
+                Box::new(
+                    joinsplit_data
+                        .iter()
+                        .flat_map(|jsd| jsd.joinsplits())
+                        .flat_map(|joinsplit| joinsplit.nullifiers.iter()),
+                )
+            }
+            Transaction::V4 { joinsplit_data, .. } => Box::new(
+                joinsplit_data
+                    .iter()
+                    .flat_map(|jsd| jsd.joinsplits())
+                    .flat_map(|joinsplit| joinsplit.nullifiers.iter()),
+            ),
+            Transaction::V1 { .. } | Transaction::V5 { .. } => Box::new(std::iter::empty()),
         }
     }
 
+    /// Iterate over the anchor of each of this transaction's JoinSplits, and
+    /// the note commitments it creates, in order.
+    pub fn sprout_anchors_and_commitments(
+        &self,
+    ) -> Box<dyn Iterator<Item = ([u8; 32], [[u8; 32]; 2])> + '_> {
+        match self {
+            Transaction::V2 { joinsplit_data, .. } | Transaction::V3 { joinsplit_data, .. } => {
+                Box::new(
+                    joinsplit_data
+                        .iter()
+                        .flat_map(|jsd| jsd.joinsplits())
+                        .map(|joinsplit| (joinsplit.anchor, joinsplit.commitments)),
+                )
+            }
+            Transaction::V4 { joinsplit_data, .. } => Box::new(
+                joinsplit_data
+                    .iter()
+                    .flat_map(|jsd| jsd.joinsplits())
+                    .map(|joinsplit| (joinsplit.anchor, joinsplit.commitments)),
+            ),
+            Transaction::V1 { .. } | Transaction::V5 { .. } => Box::new(std::iter::empty()),
+        }
+    }
+
+    /// Iterate over the Sprout note commitments created by this
+    /// transaction's JoinSplits, if any.
+    pub fn sprout_note_commitments(&self) -> impl Iterator<Item = [u8; 32]> + '_ {
+        self.sprout_anchors_and_commitments()
+            .flat_map(|(_, commitments)| commitments.to_vec())
+    }
+
+    /// Returns the Sapling shielded data in this transaction, if any.
+    pub fn sapling_shielded_data(&self) -> Option<&ShieldedData> {
+        match self {
+            Transaction::V4 { shielded_data, .. } => shielded_data.as_ref(),
+            Transaction::V5 {
+                sapling_shielded_data,
+                ..
+            } => sapling_shielded_data.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Returns the net value of this transaction's Sapling spends minus its
+    /// Sapling outputs.
+    ///
+    /// Transactions without Sapling shielded data have a zero balance.
+    pub fn sapling_value_balance(&self) -> Amount {
+        match self {
+            Transaction::V4 { value_balance, .. } => *value_balance,
+            Transaction::V5 {
+                sapling_value_balance,
+                ..
+            } => *sapling_value_balance,
+            _ => Amount::zero(),
+        }
+    }
+
+    /// Iterate over the Sapling spend descriptions in this transaction, if
+    /// any.
+    pub fn sapling_spends(&self) -> impl Iterator<Item = &Spend> {
+        self.sapling_shielded_data()
+            .into_iter()
+            .flat_map(|sd| sd.spends())
+    }
+
+    /// Iterate over the Sapling nullifiers revealed by this transaction's
+    /// spends, if any.
+    pub fn sapling_nullifiers(&self) -> impl Iterator<Item = &sapling::Nullifier> {
+        self.sapling_spends().map(|spend| &spend.nullifier)
+    }
+
+    /// Iterate over the Sapling output descriptions in this transaction, if
+    /// any.
+    pub fn sapling_outputs(&self) -> impl Iterator<Item = &Output> {
+        self.sapling_shielded_data()
+            .into_iter()
+            .flat_map(|sd| sd.outputs())
+    }
+
+    /// Returns this transaction's Orchard shielded data, if it has any.
+    pub fn orchard_shielded_data(&self) -> Option<&orchard::ShieldedData> {
+        match self {
+            Transaction::V5 {
+                orchard_shielded_data,
+                ..
+            } => orchard_shielded_data.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Iterate over the Orchard nullifiers revealed by this transaction's
+    /// actions, if any.
+    pub fn orchard_nullifiers(&self) -> impl Iterator<Item = &orchard::Nullifier> {
+        self.orchard_shielded_data()
+            .into_iter()
+            .flat_map(|sd| sd.nullifiers())
+    }
+
+    /// Iterate over the x-coordinates of the Orchard note commitments created
+    /// by this transaction's actions, if any.
+    pub fn orchard_note_commitments(&self) -> impl Iterator<Item = &[u8; 32]> {
+        self.orchard_shielded_data()
+            .into_iter()
+            .flat_map(|sd| sd.note_commitments())
+    }
+
     /// Get this transaction's expiry height, if any.
-    pub fn expiry_height(&self) -> Option<BlockHeight> {
+    pub fn expiry_height(&self) -> Option<block::Height> {
         match self {
             Transaction::V1 { .. } => None,
             Transaction::V2 { .. } => None,
             Transaction::V3 { expiry_height, .. } => Some(*expiry_height),
             Transaction::V4 { expiry_height, .. } => Some(*expiry_height),
+            Transaction::V5 { expiry_height, .. } => Some(*expiry_height),
+        }
+    }
+
+    /// Returns true if this transaction has expired, so it can't be mined in
+    /// a block at `height`.
+    ///
+    /// The expiry height is the last height the transaction can be mined at.
+    /// Transactions without an expiry height, or with an expiry height of
+    /// zero, never expire. Neither do coinbase transactions. See
+    /// [ZIP-203](https://zips.z.cash/zip-0203) for details.
+    pub fn is_expired_at(&self, height: block::Height) -> bool {
+        match self.expiry_height() {
+            Some(block::Height(0)) | None => false,
+            Some(_) if self.is_coinbase() => false,
+            Some(expiry_height) => height > expiry_height,
         }
     }
+
+    /// Returns true if this transaction's lock time allows it to be mined in
+    /// a block at `height`, with time `block_time`.
+    ///
+    /// The lock time is ignored if every input has the final sequence number
+    /// `0xffff_ffff`.
+    pub fn is_final(&self, height: block::Height, block_time: DateTime<Utc>) -> bool {
+        let all_inputs_final = self.inputs().all(|input| match input {
+            TransparentInput::PrevOut { sequence, .. } => *sequence == u32::MAX,
+            TransparentInput::Coinbase { sequence, .. } => *sequence == u32::MAX,
+        });
+        all_inputs_final || self.lock_time().has_passed(height, block_time)
+    }
 }
+
+/// The size of the smallest serialized transaction, in bytes.
+///
+/// A v1 transaction with no inputs or outputs has a 4-byte header, two
+/// 1-byte counts, and a 4-byte lock time.
+pub const MIN_TRANSACTION_BYTES: usize = 4 + 1 + 1 + 4;
+
+/// The largest expiry height that transactions can have.
+///
+/// Like lock times, expiry heights must be below `500_000_000`. See
+/// [ZIP-203](https://zips.z.cash/zip-0203) for details.
+pub const MAX_EXPIRY_HEIGHT: block::Height = LockTime::MAX_HEIGHT;
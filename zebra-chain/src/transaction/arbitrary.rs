@@ -0,0 +1,209 @@
+//! Arbitrary implementations for transactions.
+
+use proptest::{arbitrary::any, collection::vec, option, prelude::*};
+
+use crate::transparent::Script;
+
+use super::*;
+
+impl Transaction {
+    pub fn v1_strategy() -> impl Strategy<Value = Self> {
+        (
+            vec(any::<TransparentInput>(), 0..10),
+            vec(any::<TransparentOutput>(), 0..10),
+            any::<LockTime>(),
+        )
+            .prop_map(|(inputs, outputs, lock_time)| Transaction::V1 {
+                inputs,
+                outputs,
+                lock_time,
+            })
+            .boxed()
+    }
+
+    pub fn v2_strategy() -> impl Strategy<Value = Self> {
+        (
+            vec(any::<TransparentInput>(), 0..10),
+            vec(any::<TransparentOutput>(), 0..10),
+            any::<LockTime>(),
+            option::of(any::<JoinSplitData<Bctv14Proof>>()),
+        )
+            .prop_map(
+                |(inputs, outputs, lock_time, joinsplit_data)| Transaction::V2 {
+                    inputs,
+                    outputs,
+                    lock_time,
+                    joinsplit_data,
+                },
+            )
+            .boxed()
+    }
+
+    pub fn v3_strategy() -> impl Strategy<Value = Self> {
+        (
+            vec(any::<TransparentInput>(), 0..10),
+            vec(any::<TransparentOutput>(), 0..10),
+            any::<LockTime>(),
+            any::<block::Height>(),
+            option::of(any::<JoinSplitData<Bctv14Proof>>()),
+        )
+            .prop_map(
+                |(inputs, outputs, lock_time, expiry_height, joinsplit_data)| Transaction::V3 {
+                    inputs,
+                    outputs,
+                    lock_time,
+                    expiry_height,
+                    joinsplit_data,
+                },
+            )
+            .boxed()
+    }
+
+    pub fn v4_strategy() -> impl Strategy<Value = Self> {
+        (
+            vec(any::<TransparentInput>(), 0..10),
+            vec(any::<TransparentOutput>(), 0..10),
+            any::<LockTime>(),
+            any::<block::Height>(),
+            any::<Amount>(),
+            option::of(any::<ShieldedData>()),
+            option::of(any::<JoinSplitData<Groth16Proof>>()),
+        )
+            .prop_map(
+                |(
+                    inputs,
+                    outputs,
+                    lock_time,
+                    expiry_height,
+                    value_balance,
+                    shielded_data,
+                    joinsplit_data,
+                )| Transaction::V4 {
+                    inputs,
+                    outputs,
+                    lock_time,
+                    expiry_height,
+                    value_balance,
+                    shielded_data,
+                    joinsplit_data,
+                },
+            )
+            .boxed()
+    }
+
+    pub fn v5_strategy() -> impl Strategy<Value = Self> {
+        (
+            vec(any::<TransparentInput>(), 0..10),
+            vec(any::<TransparentOutput>(), 0..10),
+            any::<LockTime>(),
+            any::<block::Height>(),
+            any::<u32>(),
+            any::<Amount>(),
+            option::of(any::<ShieldedData>()),
+            option::of(any::<orchard::ShieldedData>()),
+        )
+            .prop_map(
+                |(
+                    inputs,
+                    outputs,
+                    lock_time,
+                    expiry_height,
+                    consensus_branch_id,
+                    sapling_value_balance,
+                    sapling_shielded_data,
+                    orchard_shielded_data,
+                )| {
+                    // Version 5 transactions only have one Sapling anchor, and
+                    // only have a value balance if they have shielded data.
+                    let sapling_shielded_data = sapling_shielded_data.map(share_spend_anchor);
+                    let sapling_value_balance = if sapling_shielded_data.is_some() {
+                        sapling_value_balance
+                    } else {
+                        Amount::zero()
+                    };
+                    Transaction::V5 {
+                        inputs,
+                        outputs,
+                        lock_time,
+                        expiry_height,
+                        consensus_branch_id,
+                        sapling_value_balance,
+                        sapling_shielded_data,
+                        orchard_shielded_data,
+                    }
+                },
+            )
+            .boxed()
+    }
+}
+
+/// Give every spend in `shielded_data` the anchor of the first spend.
+fn share_spend_anchor(mut shielded_data: ShieldedData) -> ShieldedData {
+    use futures::future::Either;
+
+    let anchor = match shielded_data.spends().next() {
+        Some(spend) => spend.anchor,
+        None => return shielded_data,
+    };
+    if let Either::Left(ref mut spend) = shielded_data.first {
+        spend.anchor = anchor;
+    }
+    for spend in shielded_data.rest_spends.iter_mut() {
+        spend.anchor = anchor;
+    }
+    shielded_data
+}
+
+impl Arbitrary for Transaction {
+    type Parameters = ();
+
+    fn arbitrary_with(_args: ()) -> Self::Strategy {
+        prop_oneof![
+            Self::v1_strategy(),
+            Self::v2_strategy(),
+            Self::v3_strategy(),
+            Self::v4_strategy(),
+            Self::v5_strategy()
+        ]
+        .boxed()
+    }
+
+    type Strategy = BoxedStrategy<Self>;
+}
+
+impl Arbitrary for TransparentInput {
+    type Parameters = ();
+
+    fn arbitrary_with(_args: ()) -> Self::Strategy {
+        prop_oneof![
+            (any::<OutPoint>(), any::<Script>(), any::<u32>())
+                .prop_map(|(outpoint, script, sequence)| {
+                    TransparentInput::PrevOut {
+                        outpoint,
+                        script,
+                        sequence,
+                    }
+                })
+                .boxed(),
+            (
+                any::<block::Height>(),
+                vec(
+                    any::<u8>(),
+                    0..=(MAX_COINBASE_SCRIPT_LEN - MAX_COINBASE_HEIGHT_LEN)
+                ),
+                any::<u32>()
+            )
+                .prop_map(|(height, data, sequence)| {
+                    TransparentInput::Coinbase {
+                        height,
+                        data: CoinbaseData(data),
+                        sequence,
+                    }
+                })
+                .boxed(),
+        ]
+        .boxed()
+    }
+
+    type Strategy = BoxedStrategy<Self>;
+}
@@ -0,0 +1,94 @@
+#![allow(clippy::unit_arg)]
+use std::fmt;
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest_derive::Arbitrary;
+
+use crate::serialization::{reversed_hex, SerializationError};
+
+use super::Hash;
+
+/// An authorizing data commitment for a transaction.
+///
+/// Transaction IDs for v5 transactions do not commit to the transaction's
+/// authorizing data (signatures and proofs), so ZIP-239 relays them using a
+/// [`WtxId`] that pairs the ID with this digest.
+///
+/// Like transaction IDs, digests are displayed in reversed byte order.
+///
+/// [ZIP-239](https://zips.z.cash/zip-0239)
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
+pub struct AuthDigest(
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::reversed_hex"))] pub [u8; 32],
+);
+
+impl fmt::Display for AuthDigest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&reversed_hex::encode(&self.0))
+    }
+}
+
+impl fmt::Debug for AuthDigest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("AuthDigest")
+            .field(&reversed_hex::encode(&self.0))
+            .finish()
+    }
+}
+
+impl std::str::FromStr for AuthDigest {
+    type Err = SerializationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        reversed_hex::decode(s).map(AuthDigest)
+    }
+}
+
+/// A wide transaction ID, which uniquely identifies a transaction together
+/// with its authorizing data.
+///
+/// [ZIP-239](https://zips.z.cash/zip-0239)
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
+pub struct WtxId {
+    /// The transaction ID.
+    pub id: Hash,
+    /// The digest of the transaction's authorizing data.
+    pub auth_digest: AuthDigest,
+}
+
+/// The ID peers use to relay an unmined transaction.
+///
+/// v5 transactions are relayed by their [`WtxId`], so peers can tell apart
+/// copies with different authorizing data. Earlier versions are relayed by
+/// their transaction ID, which already commits to the whole transaction.
+///
+/// [ZIP-239](https://zips.z.cash/zip-0239)
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
+pub enum UnminedTxId {
+    /// The ID of a transaction before v5.
+    Legacy(Hash),
+    /// The wide ID of a v5 transaction.
+    Witnessed(WtxId),
+}
+
+impl UnminedTxId {
+    /// Returns the transaction ID, which identifies the transaction once it
+    /// is mined.
+    pub fn mined_id(&self) -> Hash {
+        match self {
+            UnminedTxId::Legacy(hash) => *hash,
+            UnminedTxId::Witnessed(wtx_id) => wtx_id.id,
+        }
+    }
+}
+
+impl From<WtxId> for UnminedTxId {
+    fn from(wtx_id: WtxId) -> Self {
+        UnminedTxId::Witnessed(wtx_id)
+    }
+}
@@ -1,26 +1,41 @@
 #![allow(clippy::unit_arg)]
 use std::fmt;
 
-#[cfg(test)]
+#[cfg(any(test, feature = "proptest-impl"))]
 use proptest_derive::Arbitrary;
 
 use crate::{
-    serialization::{SerializationError, ZcashSerialize},
+    serialization::{reversed_hex, SerializationError, ZcashDeserialize, ZcashSerialize},
     sha256d_writer::Sha256dWriter,
 };
 
 use super::Transaction;
 
-/// A hash of a `Transaction`
+/// A SHA-256d hash of a `Transaction`, also called its transaction ID.
 ///
-/// TODO: I'm pretty sure this is also a SHA256d hash but I haven't
-/// confirmed it yet.
-#[derive(Copy, Clone, Eq, PartialEq)]
-#[cfg_attr(test, derive(Arbitrary))]
-pub struct TransactionHash(pub [u8; 32]);
+/// As with [`block::Hash`](crate::block::Hash), the inner bytes are in
+/// internal byte order, and the `Display`, `Debug`, and `FromStr` impls use
+/// the reversed byte order shown by block explorers and RPC methods, as does
+/// Serde in human-readable formats.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, ZcashSerialize, ZcashDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
+pub struct Hash(
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::reversed_hex"))]
+    #[zcash(fixed)]
+    pub [u8; 32],
+);
 
-impl From<Transaction> for TransactionHash {
-    fn from(transaction: Transaction) -> Self {
+impl<'a> From<&'a Transaction> for Hash {
+    /// Compute the ID of `transaction`.
+    ///
+    /// The IDs of v5 transactions don't commit to their signatures and
+    /// proofs, as specified in [ZIP-244](https://zips.z.cash/zip-0244).
+    /// Earlier versions use the SHA-256d hash of the whole transaction.
+    fn from(transaction: &'a Transaction) -> Self {
+        if let Some(txid) = transaction.zip244_txid() {
+            return txid;
+        }
         let mut hash_writer = Sha256dWriter::default();
         transaction
             .zcash_serialize(&mut hash_writer)
@@ -29,24 +44,32 @@ impl From<Transaction> for TransactionHash {
     }
 }
 
-impl fmt::Debug for TransactionHash {
+impl From<Transaction> for Hash {
+    fn from(transaction: Transaction) -> Self {
+        Hash::from(&transaction)
+    }
+}
+
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&reversed_hex::encode(&self.0))
+    }
+}
+
+impl fmt::Debug for Hash {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_tuple("TransactionHash")
-            .field(&hex::encode(&self.0))
+        f.debug_tuple("transaction::Hash")
+            .field(&reversed_hex::encode(&self.0))
             .finish()
     }
 }
 
-impl std::str::FromStr for TransactionHash {
+impl std::str::FromStr for Hash {
     type Err = SerializationError;
 
+    /// Parse a hash in the reversed byte order shown by block explorers.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut bytes = [0; 32];
-        if hex::decode_to_slice(s, &mut bytes[..]).is_err() {
-            Err(SerializationError::Parse("hex decoding error"))
-        } else {
-            Ok(TransactionHash(bytes))
-        }
+        reversed_hex::decode(s).map(Hash)
     }
 }
 
@@ -64,23 +87,22 @@ mod tests {
         let mut sha_writer = Sha256dWriter::default();
         let _ = sha_writer.write_all(preimage);
 
-        let hash = TransactionHash(sha_writer.finish());
+        let hash = Hash(sha_writer.finish());
 
         assert_eq!(
             format!("{:?}", hash),
-            r#"TransactionHash("bf46b4b5030752fedac6f884976162bbfb29a9398f104a280b3e34d51b416631")"#
+            r#"transaction::Hash("3166411bd5343e0b284a108f39a929fbbb62619784f8c6dafe520703b5b446bf")"#
         );
     }
 
     #[test]
     fn transactionhash_from_str() {
-        let hash: TransactionHash =
-            "bf46b4b5030752fedac6f884976162bbfb29a9398f104a280b3e34d51b416631"
-                .parse()
-                .unwrap();
+        let hash: Hash = "3166411bd5343e0b284a108f39a929fbbb62619784f8c6dafe520703b5b446bf"
+            .parse()
+            .unwrap();
         assert_eq!(
             format!("{:?}", hash),
-            r#"TransactionHash("bf46b4b5030752fedac6f884976162bbfb29a9398f104a280b3e34d51b416631")"#
+            r#"transaction::Hash("3166411bd5343e0b284a108f39a929fbbb62619784f8c6dafe520703b5b446bf")"#
         );
     }
 }
@@ -0,0 +1,178 @@
+//! Transaction lock times.
+
+use std::{convert::TryFrom, io};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use chrono::{DateTime, Utc};
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use chrono::TimeZone;
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest::prelude::*;
+
+use crate::{
+    block,
+    serialization::{DateTime32, SerializationError, ZcashDeserialize, ZcashSerialize},
+};
+
+/// A Bitcoin-style `locktime`, representing either a block height or an epoch
+/// time.
+///
+/// Serialized lock times below [`LockTime::MIN_TIMESTAMP`] are block heights,
+/// and larger values are Unix timestamps.
+///
+/// # Invariants
+///
+/// Users should not construct a `LockTime` with a `block::Height` greater than
+/// [`LockTime::MAX_HEIGHT`] or a timestamp before 4 November 1985 (Unix
+/// timestamp less than [`LockTime::MIN_TIMESTAMP`]).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LockTime {
+    /// Unlock at a particular block height.
+    Height(block::Height),
+    /// Unlock at a particular time.
+    Time(DateTime<Utc>),
+}
+
+impl LockTime {
+    /// The smallest serialized lock time that is interpreted as a timestamp.
+    pub const MIN_TIMESTAMP: i64 = 500_000_000;
+
+    /// The largest block height that can be used in a lock time.
+    pub const MAX_HEIGHT: block::Height = block::Height(Self::MIN_TIMESTAMP as u32 - 1);
+
+    /// Returns a lock time that doesn't restrict when its transaction can be
+    /// mined.
+    ///
+    /// Zero lock times are always unlocked, regardless of the input sequence
+    /// numbers.
+    pub fn unlocked() -> Self {
+        LockTime::Height(block::Height(0))
+    }
+
+    /// Returns true if this lock time has passed in a block at `height`,
+    /// with time `block_time`.
+    ///
+    /// Lock times are exclusive: a transaction with a height lock time of
+    /// `n` can be mined at height `n + 1` and above. Zero lock times have
+    /// always passed.
+    pub fn has_passed(&self, height: block::Height, block_time: DateTime<Utc>) -> bool {
+        match *self {
+            LockTime::Height(lock_height) => lock_height.0 == 0 || lock_height < height,
+            LockTime::Time(lock_time) => lock_time < block_time,
+        }
+    }
+}
+
+impl ZcashSerialize for LockTime {
+    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        // This implementation does not check the height and time thresholds on
+        // `LockTime`, so it only fails if the writer does, or if the time doesn't
+        // fit in 32 bits. Deserialized lock times always fit.
+        use LockTime::*;
+        match self {
+            Height(block::Height(n)) => writer.write_u32::<LittleEndian>(*n)?,
+            Time(t) => DateTime32::try_from(t)
+                .map_err(|_| out_of_range("lock time is outside the DateTime32 range"))?
+                .zcash_serialize(&mut writer)?,
+        }
+        Ok(())
+    }
+}
+
+fn out_of_range(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+impl ZcashDeserialize for LockTime {
+    fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let n = reader.read_u32::<LittleEndian>()?;
+        if i64::from(n) < LockTime::MIN_TIMESTAMP {
+            Ok(LockTime::Height(block::Height(n)))
+        } else {
+            Ok(LockTime::Time(DateTime32::from(n).to_chrono()))
+        }
+    }
+}
+
+#[cfg(any(test, feature = "proptest-impl"))]
+impl Arbitrary for LockTime {
+    type Parameters = ();
+
+    fn arbitrary_with(_args: ()) -> Self::Strategy {
+        prop_oneof![
+            (0..=LockTime::MAX_HEIGHT.0).prop_map(|n| LockTime::Height(block::Height(n))),
+            // XXX Setting max to i64::MAX doesn't work, this is 2**32.
+            (LockTime::MIN_TIMESTAMP..4_294_967_296)
+                .prop_map(|n| { LockTime::Time(Utc.timestamp(n, 0)) })
+        ]
+        .boxed()
+    }
+
+    type Strategy = BoxedStrategy<Self>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn out_of_range_times_are_serialization_errors() {
+        let lock_time = LockTime::Time(Utc.timestamp(-1, 0));
+        assert!(lock_time.zcash_serialize(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn lock_time_threshold() {
+        let height = [0xff, 0x64, 0xcd, 0x1d];
+        let time = [0x00, 0x65, 0xcd, 0x1d];
+
+        assert_eq!(
+            LockTime::zcash_deserialize(&height[..]).unwrap(),
+            LockTime::Height(LockTime::MAX_HEIGHT)
+        );
+        assert_eq!(
+            LockTime::zcash_deserialize(&time[..]).unwrap(),
+            LockTime::Time(Utc.timestamp(LockTime::MIN_TIMESTAMP, 0))
+        );
+    }
+
+    #[test]
+    fn lock_time_has_passed() {
+        let block_time = Utc.timestamp(1_600_000_000, 0);
+
+        assert!(LockTime::unlocked().has_passed(block::Height(0), block_time));
+        assert!(LockTime::Height(block::Height(9)).has_passed(block::Height(10), block_time));
+        assert!(!LockTime::Height(block::Height(10)).has_passed(block::Height(10), block_time));
+
+        let earlier = Utc.timestamp(1_599_999_999, 0);
+        assert!(LockTime::Time(earlier).has_passed(block::Height(10), block_time));
+        assert!(!LockTime::Time(block_time).has_passed(block::Height(10), block_time));
+    }
+}
+
+#[cfg(test)]
+mod proptests {
+    use std::io::Cursor;
+
+    use proptest::prelude::*;
+
+    use super::LockTime;
+    use crate::serialization::{ZcashDeserialize, ZcashSerialize};
+
+    proptest! {
+
+        #[test]
+        fn locktime_roundtrip(locktime in any::<LockTime>()) {
+            let mut bytes = Cursor::new(Vec::new());
+            locktime.zcash_serialize(&mut bytes)?;
+
+            bytes.set_position(0);
+            let other_locktime = LockTime::zcash_deserialize(&mut bytes)?;
+
+            prop_assert_eq![locktime, other_locktime];
+        }
+
+    }
+}
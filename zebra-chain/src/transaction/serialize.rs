@@ -1,5 +1,8 @@
 //! Contains impls of `ZcashSerialize`, `ZcashDeserialize` for all of the
 //! transaction types, so that all of the serialization logic is in one place.
+//!
+//! Types that are serialized field by field, like `OutPoint`, derive these
+//! traits instead.
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::{
@@ -7,17 +10,23 @@ use std::{
     sync::Arc,
 };
 
+use crate::amount::Amount;
 use crate::notes;
-use crate::proofs::ZkSnarkProof;
+use crate::orchard::{self, Action};
+use crate::proofs::{Halo2Proof, ZkSnarkProof};
+use crate::sapling;
 use crate::serialization::{
-    ReadZcashExt, SerializationError, WriteZcashExt, ZcashDeserialize, ZcashSerialize,
+    zcash_deserialize_shared_vec, CopyReader, ReadZcashExt, SerializationError, SharedRead,
+    TrustedPreallocate, WriteZcashExt, ZcashDeserialize, ZcashDeserializeShared, ZcashSerialize,
 };
-use crate::types::Script;
+use crate::sprout::{self, JoinSplit};
+use crate::transparent::Script;
 
 use super::*;
 
-const OVERWINTER_VERSION_GROUP_ID: u32 = 0x03C4_8270;
-const SAPLING_VERSION_GROUP_ID: u32 = 0x892F_2085;
+pub(super) const OVERWINTER_VERSION_GROUP_ID: u32 = 0x03C4_8270;
+pub(super) const SAPLING_VERSION_GROUP_ID: u32 = 0x892F_2085;
+pub(super) const NU5_VERSION_GROUP_ID: u32 = 0x26A7_270A;
 
 const GENESIS_COINBASE_DATA: [u8; 77] = [
     4, 255, 255, 7, 31, 1, 4, 69, 90, 99, 97, 115, 104, 48, 98, 57, 99, 52, 101, 101, 102, 56, 98,
@@ -26,23 +35,6 @@ const GENESIS_COINBASE_DATA: [u8; 77] = [
     54, 52, 56, 51, 53, 100, 51, 52,
 ];
 
-impl ZcashSerialize for OutPoint {
-    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
-        writer.write_all(&self.hash.0[..])?;
-        writer.write_u32::<LittleEndian>(self.index)?;
-        Ok(())
-    }
-}
-
-impl ZcashDeserialize for OutPoint {
-    fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
-        Ok(OutPoint {
-            hash: TransactionHash(reader.read_32_bytes()?),
-            index: reader.read_u32::<LittleEndian>()?,
-        })
-    }
-}
-
 // Coinbase inputs include block heights (BIP34). These are not encoded
 // directly, but as a Bitcoin script that pushes the block height to the stack
 // when executed. The script data is otherwise unused. Because we want to
@@ -52,35 +44,36 @@ impl ZcashDeserialize for OutPoint {
 
 fn parse_coinbase_height(
     mut data: Vec<u8>,
-) -> Result<(BlockHeight, CoinbaseData), SerializationError> {
+) -> Result<(block::Height, CoinbaseData), SerializationError> {
     match (data.get(0), data.len()) {
         // Blocks 1 through 16 inclusive encode block height with OP_N opcodes.
         (Some(op_n @ 0x51..=0x60), len) if len >= 1 => Ok((
-            BlockHeight((op_n - 0x50) as u32),
+            block::Height((op_n - 0x50) as u32),
             CoinbaseData(data.split_off(1)),
         )),
         // Blocks 17 through 256 exclusive encode block height with the `0x01` opcode.
-        (Some(0x01), len) if len >= 2 => {
-            Ok((BlockHeight(data[1] as u32), CoinbaseData(data.split_off(2))))
-        }
+        (Some(0x01), len) if len >= 2 => Ok((
+            block::Height(data[1] as u32),
+            CoinbaseData(data.split_off(2)),
+        )),
         // Blocks 256 through 65536 exclusive encode block height with the `0x02` opcode.
         (Some(0x02), len) if len >= 3 => Ok((
-            BlockHeight(data[1] as u32 + ((data[2] as u32) << 8)),
+            block::Height(data[1] as u32 + ((data[2] as u32) << 8)),
             CoinbaseData(data.split_off(3)),
         )),
         // Blocks 65536 through 2**24 exclusive encode block height with the `0x03` opcode.
         (Some(0x03), len) if len >= 4 => Ok((
-            BlockHeight(data[1] as u32 + ((data[2] as u32) << 8) + ((data[3] as u32) << 16)),
+            block::Height(data[1] as u32 + ((data[2] as u32) << 8) + ((data[3] as u32) << 16)),
             CoinbaseData(data.split_off(4)),
         )),
         // The genesis block does not encode the block height by mistake; special case it.
         // The first five bytes are [4, 255, 255, 7, 31], the little-endian encoding of
         // 520_617_983.  This is lucky because it means we can special-case the genesis block
-        // while remaining below the maximum `BlockHeight` of 500_000_000 forced by `LockTime`.
+        // while remaining below the maximum `block::Height` of 500_000_000 forced by `LockTime`.
         // While it's unlikely this code will ever process a block height that high, this means
-        // we don't need to maintain a cascade of different invariants for allowable `BlockHeight`s.
+        // we don't need to maintain a cascade of different invariants for allowable `block::Height`s.
         (Some(0x04), _) if data[..] == GENESIS_COINBASE_DATA[..] => {
-            Ok((BlockHeight(0), CoinbaseData(data)))
+            Ok((block::Height(0), CoinbaseData(data)))
         }
         // As noted above, this is included for completeness.
         (Some(0x04), len) if len >= 5 => {
@@ -89,7 +82,7 @@ fn parse_coinbase_height(
                 + ((data[3] as u32) << 16)
                 + ((data[4] as u32) << 24);
             if h < 500_000_000 {
-                Ok((BlockHeight(h), CoinbaseData(data.split_off(5))))
+                Ok((block::Height(h), CoinbaseData(data.split_off(5))))
             } else {
                 Err(SerializationError::Parse("Invalid block height"))
             }
@@ -100,7 +93,7 @@ fn parse_coinbase_height(
     }
 }
 
-fn coinbase_height_len(height: BlockHeight) -> usize {
+fn coinbase_height_len(height: block::Height) -> usize {
     // We can't write this as a match statement on stable until exclusive range
     // guards are stabilized.
     if let 0 = height.0 {
@@ -120,7 +113,7 @@ fn coinbase_height_len(height: BlockHeight) -> usize {
     }
 }
 
-fn write_coinbase_height<W: io::Write>(height: BlockHeight, mut w: W) -> Result<(), io::Error> {
+fn write_coinbase_height<W: io::Write>(height: block::Height, mut w: W) -> Result<(), io::Error> {
     // We can't write this as a match statement on stable until exclusive range
     // guards are stabilized.
     if let 0 = height.0 {
@@ -147,6 +140,15 @@ fn write_coinbase_height<W: io::Write>(height: BlockHeight, mut w: W) -> Result<
     Ok(())
 }
 
+/// Returns the script of a coinbase input, which is the encoded `height`
+/// followed by the miner's `data`.
+pub(super) fn coinbase_script(height: block::Height, data: &CoinbaseData) -> Script {
+    let mut script = Vec::with_capacity(coinbase_height_len(height) + data.as_ref().len());
+    write_coinbase_height(height, &mut script).expect("writing to a Vec never fails");
+    script.extend_from_slice(data.as_ref());
+    Script(script.into())
+}
+
 impl ZcashSerialize for TransparentInput {
     fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
         match self {
@@ -178,8 +180,8 @@ impl ZcashSerialize for TransparentInput {
     }
 }
 
-impl ZcashDeserialize for TransparentInput {
-    fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
+impl ZcashDeserializeShared for TransparentInput {
+    fn zcash_deserialize_shared<R: SharedRead>(mut reader: R) -> Result<Self, SerializationError> {
         // This inlines the OutPoint deserialization to peek at the hash value
         // and detect whether we have a coinbase input.
         let bytes = reader.read_32_bytes()?;
@@ -188,7 +190,7 @@ impl ZcashDeserialize for TransparentInput {
                 return Err(SerializationError::Parse("wrong index in coinbase"));
             }
             let len = reader.read_compactsize()?;
-            if len > 100 {
+            if len > MAX_COINBASE_SCRIPT_LEN as u64 {
                 return Err(SerializationError::Parse("coinbase has too much data"));
             }
             let mut data = Vec::with_capacity(len as usize);
@@ -203,40 +205,59 @@ impl ZcashDeserialize for TransparentInput {
         } else {
             Ok(TransparentInput::PrevOut {
                 outpoint: OutPoint {
-                    hash: TransactionHash(bytes),
+                    hash: Hash(bytes),
                     index: reader.read_u32::<LittleEndian>()?,
                 },
-                script: Script::zcash_deserialize(&mut reader)?,
+                script: Script::zcash_deserialize_shared(&mut reader)?,
                 sequence: reader.read_u32::<LittleEndian>()?,
             })
         }
     }
 }
 
-impl ZcashSerialize for TransparentOutput {
-    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
-        writer.write_u64::<LittleEndian>(self.value)?;
-        self.pk_script.zcash_serialize(&mut writer)?;
-        Ok(())
+impl ZcashDeserialize for TransparentInput {
+    fn zcash_deserialize<R: io::Read>(reader: R) -> Result<Self, SerializationError> {
+        TransparentInput::zcash_deserialize_shared(CopyReader(reader))
     }
 }
 
-impl ZcashDeserialize for TransparentOutput {
-    fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
+impl ZcashDeserializeShared for TransparentOutput {
+    fn zcash_deserialize_shared<R: SharedRead>(mut reader: R) -> Result<Self, SerializationError> {
         Ok(TransparentOutput {
-            value: reader.read_u64::<LittleEndian>()?,
-            pk_script: Script::zcash_deserialize(&mut reader)?,
+            value: Amount::zcash_deserialize(&mut reader)?,
+            pk_script: Script::zcash_deserialize_shared(&mut reader)?,
         })
     }
 }
 
+impl ZcashDeserialize for TransparentOutput {
+    fn zcash_deserialize<R: io::Read>(reader: R) -> Result<Self, SerializationError> {
+        TransparentOutput::zcash_deserialize_shared(CopyReader(reader))
+    }
+}
+
+/// The smallest transparent input is a previous output reference, an empty
+/// script, and a sequence number.
+impl TrustedPreallocate for TransparentInput {
+    fn max_allocation() -> u64 {
+        (block::MAX_BLOCK_BYTES / (32 + 4 + 1 + 4)) as u64
+    }
+}
+
+/// The smallest transparent output is a value and an empty script.
+impl TrustedPreallocate for TransparentOutput {
+    fn max_allocation() -> u64 {
+        (block::MAX_BLOCK_BYTES / (8 + 1)) as u64
+    }
+}
+
 impl<P: ZkSnarkProof> ZcashSerialize for JoinSplit<P> {
     fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
-        writer.write_u64::<LittleEndian>(self.vpub_old)?;
-        writer.write_u64::<LittleEndian>(self.vpub_new)?;
+        self.vpub_old.zcash_serialize(&mut writer)?;
+        self.vpub_new.zcash_serialize(&mut writer)?;
         writer.write_all(&self.anchor[..])?;
-        writer.write_all(&self.nullifiers[0][..])?;
-        writer.write_all(&self.nullifiers[1][..])?;
+        self.nullifiers[0].zcash_serialize(&mut writer)?;
+        self.nullifiers[1].zcash_serialize(&mut writer)?;
         writer.write_all(&self.commitments[0][..])?;
         writer.write_all(&self.commitments[1][..])?;
         writer.write_all(&self.ephemeral_key.as_bytes()[..])?;
@@ -253,10 +274,13 @@ impl<P: ZkSnarkProof> ZcashSerialize for JoinSplit<P> {
 impl<P: ZkSnarkProof> ZcashDeserialize for JoinSplit<P> {
     fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
         Ok(JoinSplit::<P> {
-            vpub_old: reader.read_u64::<LittleEndian>()?,
-            vpub_new: reader.read_u64::<LittleEndian>()?,
+            vpub_old: Amount::zcash_deserialize(&mut reader)?,
+            vpub_new: Amount::zcash_deserialize(&mut reader)?,
             anchor: reader.read_32_bytes()?,
-            nullifiers: [reader.read_32_bytes()?, reader.read_32_bytes()?],
+            nullifiers: [
+                sprout::Nullifier::zcash_deserialize(&mut reader)?,
+                sprout::Nullifier::zcash_deserialize(&mut reader)?,
+            ],
             commitments: [reader.read_32_bytes()?, reader.read_32_bytes()?],
             ephemeral_key: x25519_dalek::PublicKey::from(reader.read_32_bytes()?),
             random_seed: reader.read_32_bytes()?,
@@ -289,7 +313,8 @@ impl<P: ZkSnarkProof> ZcashDeserialize for Option<JoinSplitData<P>> {
             0 => Ok(None),
             n => {
                 let first = JoinSplit::zcash_deserialize(&mut reader)?;
-                let mut rest = Vec::with_capacity((n - 1) as usize);
+                // Unlike `zcash_deserialize_vec`, we allocate as we read.
+                let mut rest = Vec::new();
                 for _ in 0..(n - 1) {
                     rest.push(JoinSplit::zcash_deserialize(&mut reader)?);
                 }
@@ -310,7 +335,7 @@ impl ZcashSerialize for Spend {
     fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
         writer.write_all(&self.cv[..])?;
         writer.write_all(&self.anchor.0[..])?;
-        writer.write_all(&self.nullifier[..])?;
+        self.nullifier.zcash_serialize(&mut writer)?;
         writer.write_all(&<[u8; 32]>::from(self.rk)[..])?;
         self.zkproof.zcash_serialize(&mut writer)?;
         writer.write_all(&<[u8; 64]>::from(self.spend_auth_sig)[..])?;
@@ -320,11 +345,10 @@ impl ZcashSerialize for Spend {
 
 impl ZcashDeserialize for Spend {
     fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
-        use crate::note_commitment_tree::SaplingNoteTreeRootHash;
         Ok(Spend {
             cv: reader.read_32_bytes()?,
-            anchor: SaplingNoteTreeRootHash(reader.read_32_bytes()?),
-            nullifier: reader.read_32_bytes()?,
+            anchor: sapling::tree::Root(reader.read_32_bytes()?),
+            nullifier: sapling::Nullifier::zcash_deserialize(&mut reader)?,
             rk: reader.read_32_bytes()?.into(),
             zkproof: Groth16Proof::zcash_deserialize(&mut reader)?,
             spend_auth_sig: reader.read_64_bytes()?.into(),
@@ -332,6 +356,13 @@ impl ZcashDeserialize for Spend {
     }
 }
 
+/// Version 4 spends have 4 32-byte fields, a proof, and a signature.
+impl TrustedPreallocate for Spend {
+    fn max_allocation() -> u64 {
+        (block::MAX_BLOCK_BYTES / (4 * 32 + 192 + 64)) as u64
+    }
+}
+
 impl ZcashSerialize for Output {
     fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
         writer.write_all(&self.cv[..])?;
@@ -357,6 +388,259 @@ impl ZcashDeserialize for Output {
     }
 }
 
+/// Version 4 outputs have 3 32-byte fields, the note ciphertexts, and a proof.
+impl TrustedPreallocate for Output {
+    fn max_allocation() -> u64 {
+        (block::MAX_BLOCK_BYTES / (3 * 32 + 580 + 80 + 192)) as u64
+    }
+}
+
+/// Write the Sapling part of a version 5 transaction.
+///
+/// Unlike version 4, the spends and outputs are split up: the descriptions
+/// come first, then the shared anchor, then the proofs and signatures. The
+/// value balance and binding signature are only present if there are any
+/// spends or outputs.
+fn write_v5_sapling<W: io::Write>(
+    value_balance: Amount,
+    shielded_data: Option<&ShieldedData>,
+    mut writer: W,
+) -> Result<(), io::Error> {
+    let spends: Vec<&Spend> = shielded_data
+        .into_iter()
+        .flat_map(|sd| sd.spends())
+        .collect();
+    let outputs: Vec<&Output> = shielded_data
+        .into_iter()
+        .flat_map(|sd| sd.outputs())
+        .collect();
+
+    writer.write_compactsize(spends.len() as u64)?;
+    for spend in &spends {
+        writer.write_all(&spend.cv[..])?;
+        spend.nullifier.zcash_serialize(&mut writer)?;
+        writer.write_all(&<[u8; 32]>::from(spend.rk)[..])?;
+    }
+    writer.write_compactsize(outputs.len() as u64)?;
+    for output in &outputs {
+        writer.write_all(&output.cv[..])?;
+        writer.write_all(&output.cmu[..])?;
+        writer.write_all(&output.ephemeral_key.to_bytes())?;
+        output.enc_ciphertext.zcash_serialize(&mut writer)?;
+        output.out_ciphertext.zcash_serialize(&mut writer)?;
+    }
+
+    let shielded_data = match shielded_data {
+        Some(sd) => sd,
+        None => return Ok(()),
+    };
+    value_balance.zcash_serialize(&mut writer)?;
+    if let Some(spend) = spends.first() {
+        writer.write_all(&spend.anchor.0[..])?;
+    }
+    for spend in &spends {
+        spend.zkproof.zcash_serialize(&mut writer)?;
+    }
+    for spend in &spends {
+        writer.write_all(&<[u8; 64]>::from(spend.spend_auth_sig)[..])?;
+    }
+    for output in &outputs {
+        output.zkproof.zcash_serialize(&mut writer)?;
+    }
+    writer.write_all(&<[u8; 64]>::from(shielded_data.binding_sig)[..])?;
+    Ok(())
+}
+
+/// Read the Sapling part of a version 5 transaction, returning the value
+/// balance and the shielded data, if any.
+fn read_v5_sapling<R: io::Read>(
+    mut reader: R,
+) -> Result<(Amount, Option<ShieldedData>), SerializationError> {
+    // The spends and outputs are split up, so we read their parts first, and
+    // then assemble them. Unlike `Vec<T>`, we allocate as we read.
+    let spend_count = reader.read_compactsize()?;
+    let mut spend_parts = Vec::new();
+    for _ in 0..spend_count {
+        let cv = reader.read_32_bytes()?;
+        let nullifier = sapling::Nullifier::zcash_deserialize(&mut reader)?;
+        let rk = reader.read_32_bytes()?;
+        spend_parts.push((cv, nullifier, rk));
+    }
+    let output_count = reader.read_compactsize()?;
+    let mut output_parts = Vec::new();
+    for _ in 0..output_count {
+        let cv = reader.read_32_bytes()?;
+        let cmu = reader.read_32_bytes()?;
+        let ephemeral_key = jubjub::AffinePoint::from_bytes(reader.read_32_bytes()?);
+        if ephemeral_key.is_none().into() {
+            return Err(SerializationError::Parse("invalid ephemeral key"));
+        }
+        let enc_ciphertext = notes::sapling::EncryptedCiphertext::zcash_deserialize(&mut reader)?;
+        let out_ciphertext = notes::sapling::OutCiphertext::zcash_deserialize(&mut reader)?;
+        output_parts.push((
+            cv,
+            cmu,
+            ephemeral_key.unwrap(),
+            enc_ciphertext,
+            out_ciphertext,
+        ));
+    }
+
+    if spend_parts.is_empty() && output_parts.is_empty() {
+        return Ok((Amount::zero(), None));
+    }
+    let value_balance = Amount::zcash_deserialize(&mut reader)?;
+    let anchor = if spend_parts.is_empty() {
+        sapling::tree::Root([0; 32])
+    } else {
+        sapling::tree::Root(reader.read_32_bytes()?)
+    };
+    let mut proofs = Vec::new();
+    for _ in 0..spend_parts.len() {
+        proofs.push(Groth16Proof::zcash_deserialize(&mut reader)?);
+    }
+    let mut spends = Vec::new();
+    for ((cv, nullifier, rk), zkproof) in spend_parts.into_iter().zip(proofs) {
+        spends.push(Spend {
+            cv,
+            anchor,
+            nullifier,
+            rk: rk.into(),
+            zkproof,
+            spend_auth_sig: reader.read_64_bytes()?.into(),
+        });
+    }
+    let mut outputs = Vec::new();
+    for (cv, cmu, ephemeral_key, enc_ciphertext, out_ciphertext) in output_parts {
+        outputs.push(Output {
+            cv,
+            cmu,
+            ephemeral_key,
+            enc_ciphertext,
+            out_ciphertext,
+            zkproof: Groth16Proof::zcash_deserialize(&mut reader)?,
+        });
+    }
+    let binding_sig = reader.read_64_bytes()?.into();
+
+    use futures::future::Either::*;
+    let first = if !spends.is_empty() {
+        Left(spends.remove(0))
+    } else {
+        Right(outputs.remove(0))
+    };
+    Ok((
+        value_balance,
+        Some(ShieldedData {
+            first,
+            rest_spends: spends,
+            rest_outputs: outputs,
+            binding_sig,
+        }),
+    ))
+}
+
+impl ZcashSerialize for Action {
+    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        // The spend authorization signature is serialized with the other
+        // signatures, after all the actions.
+        writer.write_all(&self.cv[..])?;
+        self.nullifier.zcash_serialize(&mut writer)?;
+        writer.write_all(&self.rk[..])?;
+        writer.write_all(&self.cm_x[..])?;
+        writer.write_all(&self.ephemeral_key[..])?;
+        self.enc_ciphertext.zcash_serialize(&mut writer)?;
+        self.out_ciphertext.zcash_serialize(&mut writer)?;
+        Ok(())
+    }
+}
+
+impl ZcashSerialize for orchard::ShieldedData {
+    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        writer.write_compactsize(self.actions().count() as u64)?;
+        for action in self.actions() {
+            action.zcash_serialize(&mut writer)?;
+        }
+        writer.write_u8(self.flags.to_byte())?;
+        self.value_balance.zcash_serialize(&mut writer)?;
+        writer.write_all(&self.shared_anchor.0[..])?;
+        self.proof.zcash_serialize(&mut writer)?;
+        for action in self.actions() {
+            writer.write_all(&action.spend_auth_sig.0[..])?;
+        }
+        writer.write_all(&self.binding_sig.0[..])?;
+        Ok(())
+    }
+}
+
+impl ZcashDeserializeShared for Option<orchard::ShieldedData> {
+    fn zcash_deserialize_shared<R: SharedRead>(mut reader: R) -> Result<Self, SerializationError> {
+        let num_actions = reader.read_compactsize()?;
+        if num_actions == 0 {
+            return Ok(None);
+        }
+
+        // Each action's signature comes after all the actions, so we read the
+        // other parts first.
+        let mut action_parts = Vec::new();
+        for _ in 0..num_actions {
+            let cv = reader.read_32_bytes()?;
+            let nullifier = orchard::Nullifier::zcash_deserialize(&mut reader)?;
+            let rk = reader.read_32_bytes()?;
+            let cm_x = reader.read_32_bytes()?;
+            let ephemeral_key = reader.read_32_bytes()?;
+            let enc_ciphertext = orchard::EncryptedNote::zcash_deserialize(&mut reader)?;
+            let out_ciphertext = orchard::WrappedNoteKey::zcash_deserialize(&mut reader)?;
+            action_parts.push((
+                cv,
+                nullifier,
+                rk,
+                cm_x,
+                ephemeral_key,
+                enc_ciphertext,
+                out_ciphertext,
+            ));
+        }
+        let flags = orchard::Flags::from_byte(reader.read_u8()?)
+            .ok_or(SerializationError::Parse("reserved Orchard flags were set"))?;
+        let value_balance = Amount::zcash_deserialize(&mut reader)?;
+        let shared_anchor = orchard::tree::Root(reader.read_32_bytes()?);
+        let proof = Halo2Proof::zcash_deserialize_shared(&mut reader)?;
+        let mut actions = Vec::new();
+        for (cv, nullifier, rk, cm_x, ephemeral_key, enc_ciphertext, out_ciphertext) in action_parts
+        {
+            actions.push(Action {
+                cv,
+                nullifier,
+                rk,
+                cm_x,
+                ephemeral_key,
+                enc_ciphertext,
+                out_ciphertext,
+                spend_auth_sig: reader.read_64_bytes()?.into(),
+            });
+        }
+        let binding_sig = reader.read_64_bytes()?.into();
+
+        let first = actions.remove(0);
+        Ok(Some(orchard::ShieldedData {
+            flags,
+            value_balance,
+            shared_anchor,
+            proof,
+            first,
+            rest: actions,
+            binding_sig,
+        }))
+    }
+}
+
+impl ZcashDeserialize for Option<orchard::ShieldedData> {
+    fn zcash_deserialize<R: io::Read>(reader: R) -> Result<Self, SerializationError> {
+        Self::zcash_deserialize_shared(CopyReader(reader))
+    }
+}
+
 impl ZcashSerialize for Transaction {
     fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
         match self {
@@ -422,7 +706,7 @@ impl ZcashSerialize for Transaction {
                 outputs.zcash_serialize(&mut writer)?;
                 lock_time.zcash_serialize(&mut writer)?;
                 writer.write_u32::<LittleEndian>(expiry_height.0)?;
-                writer.write_i64::<LittleEndian>(*value_balance)?;
+                value_balance.zcash_serialize(&mut writer)?;
 
                 // The previous match arms serialize in one go, because the
                 // internal structure happens to nicely line up with the
@@ -460,13 +744,42 @@ impl ZcashSerialize for Transaction {
                     None => {}
                 }
             }
+            Transaction::V5 {
+                inputs,
+                outputs,
+                lock_time,
+                expiry_height,
+                consensus_branch_id,
+                sapling_value_balance,
+                sapling_shielded_data,
+                orchard_shielded_data,
+            } => {
+                // Write version 5 and set the fOverwintered bit.
+                writer.write_u32::<LittleEndian>(5 | (1 << 31))?;
+                writer.write_u32::<LittleEndian>(NU5_VERSION_GROUP_ID)?;
+                writer.write_u32::<LittleEndian>(*consensus_branch_id)?;
+                lock_time.zcash_serialize(&mut writer)?;
+                writer.write_u32::<LittleEndian>(expiry_height.0)?;
+                inputs.zcash_serialize(&mut writer)?;
+                outputs.zcash_serialize(&mut writer)?;
+                write_v5_sapling(
+                    *sapling_value_balance,
+                    sapling_shielded_data.as_ref(),
+                    &mut writer,
+                )?;
+                match orchard_shielded_data {
+                    // Write 0 for nActionsOrchard to signal no Orchard shielded data.
+                    None => writer.write_compactsize(0)?,
+                    Some(od) => od.zcash_serialize(&mut writer)?,
+                }
+            }
         }
         Ok(())
     }
 }
 
-impl ZcashDeserialize for Transaction {
-    fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
+impl ZcashDeserializeShared for Transaction {
+    fn zcash_deserialize_shared<R: SharedRead>(mut reader: R) -> Result<Self, SerializationError> {
         let (version, overwintered) = {
             const LOW_31_BITS: u32 = (1 << 31) - 1;
             let header = reader.read_u32::<LittleEndian>()?;
@@ -476,16 +789,16 @@ impl ZcashDeserialize for Transaction {
         // The overwintered flag MUST NOT be set for version 1 and 2 transactions.
         match (version, overwintered) {
             (1, false) => Ok(Transaction::V1 {
-                inputs: Vec::zcash_deserialize(&mut reader)?,
-                outputs: Vec::zcash_deserialize(&mut reader)?,
+                inputs: zcash_deserialize_shared_vec(&mut reader)?,
+                outputs: zcash_deserialize_shared_vec(&mut reader)?,
                 lock_time: LockTime::zcash_deserialize(&mut reader)?,
             }),
             (2, false) => {
                 // Version 2 transactions use Sprout-on-BCTV14.
                 type OptV2JSD = Option<JoinSplitData<Bctv14Proof>>;
                 Ok(Transaction::V2 {
-                    inputs: Vec::zcash_deserialize(&mut reader)?,
-                    outputs: Vec::zcash_deserialize(&mut reader)?,
+                    inputs: zcash_deserialize_shared_vec(&mut reader)?,
+                    outputs: zcash_deserialize_shared_vec(&mut reader)?,
                     lock_time: LockTime::zcash_deserialize(&mut reader)?,
                     joinsplit_data: OptV2JSD::zcash_deserialize(&mut reader)?,
                 })
@@ -500,10 +813,10 @@ impl ZcashDeserialize for Transaction {
                 // Version 3 transactions use Sprout-on-BCTV14.
                 type OptV3JSD = Option<JoinSplitData<Bctv14Proof>>;
                 Ok(Transaction::V3 {
-                    inputs: Vec::zcash_deserialize(&mut reader)?,
-                    outputs: Vec::zcash_deserialize(&mut reader)?,
+                    inputs: zcash_deserialize_shared_vec(&mut reader)?,
+                    outputs: zcash_deserialize_shared_vec(&mut reader)?,
                     lock_time: LockTime::zcash_deserialize(&mut reader)?,
-                    expiry_height: BlockHeight(reader.read_u32::<LittleEndian>()?),
+                    expiry_height: block::Height(reader.read_u32::<LittleEndian>()?),
                     joinsplit_data: OptV3JSD::zcash_deserialize(&mut reader)?,
                 })
             }
@@ -525,11 +838,11 @@ impl ZcashDeserialize for Transaction {
                 // instead we have to pull the component parts out manually and
                 // then assemble them.
 
-                let inputs = Vec::zcash_deserialize(&mut reader)?;
-                let outputs = Vec::zcash_deserialize(&mut reader)?;
+                let inputs = zcash_deserialize_shared_vec(&mut reader)?;
+                let outputs = zcash_deserialize_shared_vec(&mut reader)?;
                 let lock_time = LockTime::zcash_deserialize(&mut reader)?;
-                let expiry_height = BlockHeight(reader.read_u32::<LittleEndian>()?);
-                let value_balance = reader.read_i64::<LittleEndian>()?;
+                let expiry_height = block::Height(reader.read_u32::<LittleEndian>()?);
+                let value_balance = Amount::zcash_deserialize(&mut reader)?;
                 let mut shielded_spends = Vec::zcash_deserialize(&mut reader)?;
                 let mut shielded_outputs = Vec::zcash_deserialize(&mut reader)?;
                 let joinsplit_data = OptV4JSD::zcash_deserialize(&mut reader)?;
@@ -563,11 +876,48 @@ impl ZcashDeserialize for Transaction {
                     joinsplit_data,
                 })
             }
+            (5, true) => {
+                let id = reader.read_u32::<LittleEndian>()?;
+                if id != NU5_VERSION_GROUP_ID {
+                    return Err(SerializationError::Parse("expected NU5_VERSION_GROUP_ID"));
+                }
+                let consensus_branch_id = reader.read_u32::<LittleEndian>()?;
+                let lock_time = LockTime::zcash_deserialize(&mut reader)?;
+                let expiry_height = block::Height(reader.read_u32::<LittleEndian>()?);
+                let inputs = zcash_deserialize_shared_vec(&mut reader)?;
+                let outputs = zcash_deserialize_shared_vec(&mut reader)?;
+                let (sapling_value_balance, sapling_shielded_data) = read_v5_sapling(&mut reader)?;
+                let orchard_shielded_data =
+                    Option::<orchard::ShieldedData>::zcash_deserialize_shared(&mut reader)?;
+
+                Ok(Transaction::V5 {
+                    inputs,
+                    outputs,
+                    lock_time,
+                    expiry_height,
+                    consensus_branch_id,
+                    sapling_value_balance,
+                    sapling_shielded_data,
+                    orchard_shielded_data,
+                })
+            }
             (_, _) => Err(SerializationError::Parse("bad tx header")),
         }
     }
 }
 
+impl ZcashDeserialize for Transaction {
+    fn zcash_deserialize<R: io::Read>(reader: R) -> Result<Self, SerializationError> {
+        Transaction::zcash_deserialize_shared(CopyReader(reader))
+    }
+}
+
+impl TrustedPreallocate for Transaction {
+    fn max_allocation() -> u64 {
+        block::MAX_BLOCK_TRANSACTIONS
+    }
+}
+
 impl<T> ZcashDeserialize for Arc<T>
 where
     T: ZcashDeserialize,
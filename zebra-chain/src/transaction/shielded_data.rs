@@ -1,56 +1,65 @@
+#[cfg(feature = "serde")]
+use std::convert::TryFrom;
+
 use futures::future::Either;
 
-#[cfg(test)]
+#[cfg(any(test, feature = "proptest-impl"))]
 use proptest::{arbitrary::Arbitrary, array, collection::vec, prelude::*};
 
+use crate::amount::Amount;
+use crate::keys::sapling::find_group_hash;
 // XXX this name seems too long?
-use crate::note_commitment_tree::SaplingNoteTreeRootHash;
 use crate::notes::sapling;
 use crate::proofs::Groth16Proof;
 use crate::redjubjub::{self, Binding, SpendAuth};
+use crate::sapling::tree;
+#[cfg(feature = "serde")]
+use crate::serialization::serde_hex;
 
 /// A _Spend Description_, as described in [protocol specification §7.3][ps].
 ///
 /// [ps]: https://zips.z.cash/protocol/protocol.pdf#spendencoding
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Spend {
     /// A value commitment to the value of the input note.
     ///
     /// XXX refine to a specific type.
+    #[cfg_attr(feature = "serde", serde(with = "serde_hex::bytes32"))]
     pub cv: [u8; 32],
     /// A root of the Sapling note commitment tree at some block height in the past.
-    pub anchor: SaplingNoteTreeRootHash,
+    pub anchor: tree::Root,
     /// The nullifier of the input note.
-    ///
-    /// XXX refine to a specific type.
-    pub nullifier: [u8; 32],
+    pub nullifier: crate::sapling::Nullifier,
     /// The randomized public key for `spend_auth_sig`.
-    pub rk: redjubjub::PublicKeyBytes<SpendAuth>,
+    #[cfg_attr(feature = "serde", serde(with = "serde_hex::bytes32"))]
+    pub rk: redjubjub::VerificationKeyBytes<SpendAuth>,
     /// The ZK spend proof.
     pub zkproof: Groth16Proof,
     /// A signature authorizing this spend.
+    #[cfg_attr(feature = "serde", serde(with = "serde_hex::bytes64"))]
     pub spend_auth_sig: redjubjub::Signature<SpendAuth>,
 }
 
-#[cfg(test)]
+#[cfg(any(test, feature = "proptest-impl"))]
 impl Arbitrary for Spend {
     type Parameters = ();
 
     fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
         (
             array::uniform32(any::<u8>()),
-            any::<SaplingNoteTreeRootHash>(),
-            array::uniform32(any::<u8>()),
+            any::<tree::Root>(),
+            any::<crate::sapling::Nullifier>(),
             array::uniform32(any::<u8>()),
             any::<Groth16Proof>(),
             vec(any::<u8>(), 64),
         )
             .prop_map(
-                |(cv_bytes, anchor, nullifier_bytes, rpk_bytes, proof, sig_bytes)| Self {
+                |(cv_bytes, anchor, nullifier, rpk_bytes, proof, sig_bytes)| Self {
                     anchor,
                     cv: cv_bytes,
-                    nullifier: nullifier_bytes,
-                    rk: redjubjub::PublicKeyBytes::from(rpk_bytes),
+                    nullifier,
+                    rk: redjubjub::VerificationKeyBytes::from(rpk_bytes),
                     zkproof: proof,
                     spend_auth_sig: redjubjub::Signature::from({
                         let mut b = [0u8; 64];
@@ -69,16 +78,20 @@ impl Arbitrary for Spend {
 ///
 /// [ps]: https://zips.z.cash/protocol/protocol.pdf#outputencoding
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Output {
     /// A value commitment to the value of the input note.
     ///
     /// XXX refine to a specific type.
+    #[cfg_attr(feature = "serde", serde(with = "serde_hex::bytes32"))]
     pub cv: [u8; 32],
     /// The u-coordinate of the note commitment for the output note.
     ///
     /// XXX refine to a specific type.
+    #[cfg_attr(feature = "serde", serde(with = "serde_hex::bytes32"))]
     pub cmu: [u8; 32],
     /// An encoding of an ephemeral Jubjub public key.
+    #[cfg_attr(feature = "serde", serde(with = "jubjub_point"))]
     pub ephemeral_key: jubjub::AffinePoint,
     /// A ciphertext component for the encrypted output note.
     pub enc_ciphertext: sapling::EncryptedCiphertext,
@@ -90,7 +103,34 @@ pub struct Output {
 
 impl Eq for Output {}
 
-#[cfg(test)]
+/// Serde `with` functions for Jubjub points, which are serialized using
+/// their 32-byte encoding.
+#[cfg(feature = "serde")]
+mod jubjub_point {
+    use serde::{de, Deserializer, Serializer};
+
+    use crate::serialization::serde_hex;
+
+    pub fn serialize<S: Serializer>(
+        point: &jubjub::AffinePoint,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serde_hex::serialize(&point.to_bytes(), serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<jubjub::AffinePoint, D::Error> {
+        let bytes: [u8; 32] = serde_hex::bytes32::deserialize(deserializer)?;
+        let point = jubjub::AffinePoint::from_bytes(bytes);
+        if point.is_none().into() {
+            return Err(de::Error::custom("invalid Jubjub point encoding"));
+        }
+        Ok(point.unwrap())
+    }
+}
+
+#[cfg(any(test, feature = "proptest-impl"))]
 impl Arbitrary for Output {
     type Parameters = ();
 
@@ -122,7 +162,15 @@ impl Arbitrary for Output {
 }
 
 /// Sapling-on-Groth16 spend and output descriptions.
+///
+/// Serde represents this as separate lists of spends and outputs, like the
+/// [`ShieldedData::spends`] and [`ShieldedData::outputs`] iterators.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(into = "ShieldedDataLists", try_from = "ShieldedDataLists")
+)]
 pub struct ShieldedData {
     /// Either a spend or output description.
     ///
@@ -168,6 +216,49 @@ impl ShieldedData {
         .into_iter()
         .chain(self.rest_outputs.iter())
     }
+
+    /// Compute the binding verification key for `binding_sig`, given the
+    /// transaction's Sapling `value_balance`.
+    ///
+    /// The key is the sum of the spend value commitments, minus the output
+    /// value commitments, minus a commitment to `value_balance` with zero
+    /// randomness. Returns `None` if a value commitment isn't a valid point.
+    ///
+    /// https://zips.z.cash/protocol/protocol.pdf#saplingbalance
+    pub fn binding_verification_key(
+        &self,
+        value_balance: Amount,
+    ) -> Option<redjubjub::VerificationKeyBytes<Binding>> {
+        let value_commitment = |bytes: &[u8; 32]| {
+            Option::<jubjub::AffinePoint>::from(jubjub::AffinePoint::from_bytes(*bytes))
+                .map(jubjub::ExtendedPoint::from)
+        };
+
+        let mut bvk = jubjub::ExtendedPoint::identity();
+        for spend in self.spends() {
+            bvk += value_commitment(&spend.cv)?;
+        }
+        for output in self.outputs() {
+            bvk -= value_commitment(&output.cv)?;
+        }
+
+        let value_balance = i64::from(value_balance);
+        let mut balance = jubjub::Fr::from_raw([value_balance.abs() as u64, 0, 0, 0]);
+        if value_balance < 0 {
+            balance = -balance;
+        }
+        bvk -= value_commitment_base() * balance;
+
+        Some(jubjub::AffinePoint::from(bvk).to_bytes().into())
+    }
+}
+
+/// The value base point for Sapling value commitments, `ValueCommit^{Sapling}`'s
+/// `V` generator.
+///
+/// https://zips.z.cash/protocol/protocol.pdf#concretehomomorphiccommit
+pub(crate) fn value_commitment_base() -> jubjub::ExtendedPoint {
+    find_group_hash(*b"Zcash_cv", b"v")
 }
 
 // Technically, it's possible to construct two equivalent representations
@@ -195,7 +286,54 @@ impl std::cmp::PartialEq for ShieldedData {
 
 impl std::cmp::Eq for ShieldedData {}
 
-#[cfg(test)]
+/// The Serde representation of [`ShieldedData`].
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct ShieldedDataLists {
+    spends: Vec<Spend>,
+    outputs: Vec<Output>,
+    #[serde(with = "serde_hex::bytes64")]
+    binding_sig: redjubjub::Signature<Binding>,
+}
+
+#[cfg(feature = "serde")]
+impl From<ShieldedData> for ShieldedDataLists {
+    fn from(shielded_data: ShieldedData) -> Self {
+        ShieldedDataLists {
+            spends: shielded_data.spends().cloned().collect(),
+            outputs: shielded_data.outputs().cloned().collect(),
+            binding_sig: shielded_data.binding_sig,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<ShieldedDataLists> for ShieldedData {
+    type Error = &'static str;
+
+    fn try_from(lists: ShieldedDataLists) -> Result<Self, Self::Error> {
+        let ShieldedDataLists {
+            mut spends,
+            mut outputs,
+            binding_sig,
+        } = lists;
+        let first = if !spends.is_empty() {
+            Either::Left(spends.remove(0))
+        } else if !outputs.is_empty() {
+            Either::Right(outputs.remove(0))
+        } else {
+            return Err("shielded data must have at least one spend or output");
+        };
+        Ok(ShieldedData {
+            first,
+            rest_spends: spends,
+            rest_outputs: outputs,
+            binding_sig,
+        })
+    }
+}
+
+#[cfg(any(test, feature = "proptest-impl"))]
 impl Arbitrary for ShieldedData {
     type Parameters = ();
 
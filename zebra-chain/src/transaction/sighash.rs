@@ -0,0 +1,465 @@
+//! Signature hashes for Overwinter, Sapling, and NU5 transactions.
+//!
+//! [ZIP-143](https://zips.z.cash/zip-0143) defines the signature hash for
+//! Overwinter transactions, and [ZIP-243](https://zips.z.cash/zip-0243)
+//! extends it to commit to Sapling spends and outputs. Version 5
+//! transactions use the [ZIP-244](https://zips.z.cash/zip-0244) signature
+//! digest, see the `txid` module. Sprout transactions before Overwinter sign
+//! their JoinSplits using a variant of Bitcoin's signature hash.
+
+use std::{fmt, io, ops::BitOr};
+
+use blake2b_simd::{Params, State};
+use byteorder::{LittleEndian, WriteBytesExt};
+use thiserror::Error;
+
+use crate::{
+    proofs::{Bctv14Proof, ZkSnarkProof},
+    serialization::{WriteZcashExt, ZcashSerialize},
+    sha256d_writer::Sha256dWriter,
+    sprout::JoinSplitData,
+};
+
+use super::{
+    serialize::{OVERWINTER_VERSION_GROUP_ID, SAPLING_VERSION_GROUP_ID},
+    LockTime, ShieldedData, Transaction, TransparentInput, TransparentOutput,
+};
+
+const ZCASH_SIGHASH_PERSONALIZATION_PREFIX: &[u8; 12] = b"ZcashSigHash";
+const ZCASH_PREVOUTS_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashPrevoutHash";
+const ZCASH_SEQUENCE_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashSequencHash";
+const ZCASH_OUTPUTS_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashOutputsHash";
+const ZCASH_JOINSPLITS_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashJSplitsHash";
+const ZCASH_SHIELDED_SPENDS_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashSSpendsHash";
+const ZCASH_SHIELDED_OUTPUTS_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashSOutputHash";
+
+/// The hash types allowed by ZIP-244: `ALL`, `NONE` and `SINGLE`, with or
+/// without `ANYONECANPAY`.
+const ZIP244_HASH_TYPES: [u32; 6] = [0x01, 0x02, 0x03, 0x81, 0x82, 0x83];
+
+/// The signature hash types, which choose the parts of a transaction that a
+/// signature commits to.
+///
+/// The base type is one of [`HashType::ALL`], [`HashType::NONE`], or
+/// [`HashType::SINGLE`], and it can be combined with
+/// [`HashType::ANYONECANPAY`] using `|`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct HashType(pub u32);
+
+impl HashType {
+    /// Sign all the inputs and outputs.
+    pub const ALL: HashType = HashType(0x01);
+    /// Sign all the inputs, and none of the outputs.
+    pub const NONE: HashType = HashType(0x02);
+    /// Sign all the inputs, and the output with the same index as the input
+    /// being signed.
+    pub const SINGLE: HashType = HashType(0x03);
+    /// Only sign the input being signed, so that anyone can add more inputs.
+    pub const ANYONECANPAY: HashType = HashType(0x80);
+
+    /// Returns the base type, without the `ANYONECANPAY` flag.
+    pub(super) fn base(self) -> u32 {
+        self.0 & 0x1f
+    }
+
+    /// Returns true if the `ANYONECANPAY` flag is set.
+    pub(super) fn anyone_can_pay(self) -> bool {
+        self.0 & Self::ANYONECANPAY.0 != 0
+    }
+}
+
+impl BitOr for HashType {
+    type Output = HashType;
+
+    fn bitor(self, rhs: HashType) -> HashType {
+        HashType(self.0 | rhs.0)
+    }
+}
+
+/// A signature hash, which is the message signed by transparent input
+/// signatures, and by Sapling spend authorization and binding signatures.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct SigHash(pub [u8; 32]);
+
+impl AsRef<[u8]> for SigHash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for SigHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("SigHash")
+            .field(&hex::encode(&self.0))
+            .finish()
+    }
+}
+
+/// An error computing a signature hash.
+#[derive(Error, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SigHashError {
+    /// Versions 1 and 2 are signed using the Bitcoin algorithm, which isn't
+    /// supported.
+    #[error("transaction version is not signed using ZIP-143, ZIP-243, or ZIP-244")]
+    UnsupportedVersion,
+
+    /// The signed input index is past the end of the inputs.
+    #[error("signed input {0} is not in the transaction")]
+    MissingInput(usize),
+
+    /// The outputs spent by the transaction's inputs weren't all supplied.
+    ///
+    /// ZIP-244 signatures commit to every spent output, and ZIP-143 and
+    /// ZIP-243 signatures commit to the output spent by the signed input.
+    #[error("the outputs spent by the transaction's inputs are missing")]
+    MissingPreviousOutputs,
+
+    /// ZIP-244 shielded signatures always use `SIGHASH_ALL`.
+    #[error("ZIP-244 shielded signature hashes must use SIGHASH_ALL")]
+    ShieldedHashType,
+
+    /// ZIP-244 only defines the `ALL`, `NONE` and `SINGLE` hash types, with or
+    /// without `ANYONECANPAY`.
+    #[error("invalid ZIP-244 hash type {0:#x}")]
+    InvalidHashType(u32),
+}
+
+impl Transaction {
+    /// Compute the signature hash of this transaction for the network upgrade
+    /// with `branch_id`, using `hash_type`.
+    ///
+    /// `previous_outputs` are the outputs spent by each of the transaction's
+    /// inputs, in order. To sign a transparent input, `input` is its index.
+    /// Shielded signatures use `None`, which must be combined with
+    /// [`HashType::ALL`].
+    ///
+    /// Version 5 transactions commit to every spent output, unless they are
+    /// coinbase transactions or have no transparent inputs, and they use
+    /// their own consensus branch ID. Earlier versions only use the output
+    /// spent by the signed input, so shielded signatures can use an empty
+    /// `previous_outputs`.
+    ///
+    /// Returns an error if this transaction version is not signed using
+    /// ZIP-143, ZIP-243, or ZIP-244, or if the signed input or the outputs it
+    /// needs are missing. Versions 1 and 2 use the Bitcoin algorithm.
+    pub fn sighash(
+        &self,
+        branch_id: u32,
+        hash_type: HashType,
+        previous_outputs: &[TransparentOutput],
+        input: Option<usize>,
+    ) -> Result<SigHash, SigHashError> {
+        let (header, group_id) = match self {
+            Transaction::V3 { .. } => (3 | (1 << 31), OVERWINTER_VERSION_GROUP_ID),
+            Transaction::V4 { .. } => (4 | (1 << 31), SAPLING_VERSION_GROUP_ID),
+            Transaction::V5 { .. } => return self.v5_sighash(hash_type, previous_outputs, input),
+            Transaction::V1 { .. } | Transaction::V2 { .. } => {
+                return Err(SigHashError::UnsupportedVersion)
+            }
+        };
+        let input = self.signed_input(previous_outputs, input)?;
+
+        let mut personal = [0; 16];
+        personal[..12].copy_from_slice(ZCASH_SIGHASH_PERSONALIZATION_PREFIX);
+        (&mut personal[12..])
+            .write_u32::<LittleEndian>(branch_id)
+            .expect("the personalization has room for the branch ID");
+        let mut state = Params::new().hash_length(32).personal(&personal).to_state();
+
+        self.write_sighash_preimage(header, group_id, hash_type, input, &mut state)
+            .expect("BLAKE2b state is infallible");
+        Ok(SigHash(finalize(state)))
+    }
+
+    /// Compute the ZIP-244 signature digest of a version 5 transaction.
+    fn v5_sighash(
+        &self,
+        hash_type: HashType,
+        previous_outputs: &[TransparentOutput],
+        input: Option<usize>,
+    ) -> Result<SigHash, SigHashError> {
+        if !ZIP244_HASH_TYPES.contains(&hash_type.0) {
+            return Err(SigHashError::InvalidHashType(hash_type.0));
+        }
+        if input.is_none() && hash_type != HashType::ALL {
+            return Err(SigHashError::ShieldedHashType);
+        }
+        let has_spends = !self.is_coinbase() && self.inputs().next().is_some();
+        if has_spends && previous_outputs.len() != self.inputs().count() {
+            return Err(SigHashError::MissingPreviousOutputs);
+        }
+
+        let input = self.signed_input(previous_outputs, input)?;
+        Ok(self
+            .zip244_sighash(hash_type, previous_outputs, input)
+            .expect("version 5 transactions have a ZIP-244 signature digest"))
+    }
+
+    /// Returns the signed input at `index`, and the output it spends.
+    fn signed_input<'a>(
+        &'a self,
+        previous_outputs: &'a [TransparentOutput],
+        index: Option<usize>,
+    ) -> Result<Option<(usize, &'a TransparentInput, &'a TransparentOutput)>, SigHashError> {
+        let index = match index {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        let input = self
+            .inputs()
+            .nth(index)
+            .ok_or(SigHashError::MissingInput(index))?;
+        let prev_output = previous_outputs
+            .get(index)
+            .ok_or(SigHashError::MissingPreviousOutputs)?;
+        Ok(Some((index, input, prev_output)))
+    }
+
+    /// Compute the hash signed by this transaction's JoinSplit signature,
+    /// `dataToBeSigned`, for the network upgrade with `branch_id`.
+    ///
+    /// From Overwinter, this is the signature hash with [`HashType::ALL`] and
+    /// no transparent input. Returns `None` if there are no JoinSplits.
+    ///
+    /// https://zips.z.cash/protocol/protocol.pdf#sproutnonmalleability
+    pub fn joinsplit_sighash(&self, branch_id: u32) -> Option<SigHash> {
+        match self {
+            Transaction::V2 {
+                inputs,
+                outputs,
+                lock_time,
+                joinsplit_data: Some(joinsplit_data),
+            } => Some(SigHash(
+                legacy_joinsplit_sighash(inputs, outputs, *lock_time, joinsplit_data)
+                    .expect("SHA256d writer is infallible"),
+            )),
+            Transaction::V3 {
+                joinsplit_data: Some(_),
+                ..
+            }
+            | Transaction::V4 {
+                joinsplit_data: Some(_),
+                ..
+            } => Some(
+                self.sighash(branch_id, HashType::ALL, &[], None)
+                    .expect("Overwinter and Sapling shielded signature hashes can't fail"),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Write the ZIP-143 or ZIP-243 preimage to `writer`.
+    ///
+    /// `input` is the index of the signed input, the input itself, and the
+    /// previous output it spends.
+    fn write_sighash_preimage<W: io::Write>(
+        &self,
+        header: u32,
+        group_id: u32,
+        hash_type: HashType,
+        input: Option<(usize, &TransparentInput, &TransparentOutput)>,
+        mut writer: W,
+    ) -> io::Result<()> {
+        writer.write_u32::<LittleEndian>(header)?;
+        writer.write_u32::<LittleEndian>(group_id)?;
+        writer.write_all(&self.hash_prevouts(hash_type)?)?;
+        writer.write_all(&self.hash_sequence(hash_type)?)?;
+        writer.write_all(&self.hash_outputs(hash_type, input.map(|(index, _, _)| index))?)?;
+
+        match self {
+            Transaction::V3 { joinsplit_data, .. } => {
+                writer.write_all(&hash_joinsplits(joinsplit_data.as_ref())?)?;
+            }
+            Transaction::V4 {
+                joinsplit_data,
+                shielded_data,
+                ..
+            } => {
+                writer.write_all(&hash_joinsplits(joinsplit_data.as_ref())?)?;
+                writer.write_all(&hash_shielded_spends(shielded_data.as_ref())?)?;
+                writer.write_all(&hash_shielded_outputs(shielded_data.as_ref())?)?;
+            }
+            _ => unreachable!("only Overwinter and Sapling transactions use this preimage"),
+        }
+
+        self.lock_time().zcash_serialize(&mut writer)?;
+        let expiry_height = self
+            .expiry_height()
+            .expect("Overwinter and Sapling transactions have an expiry height");
+        writer.write_u32::<LittleEndian>(expiry_height.0)?;
+        if let Transaction::V4 { value_balance, .. } = self {
+            value_balance.zcash_serialize(&mut writer)?;
+        }
+        writer.write_u32::<LittleEndian>(hash_type.0)?;
+
+        if let Some((_, input, prev_output)) = input {
+            write_prevout(input, &mut writer)?;
+            prev_output.pk_script.zcash_serialize(&mut writer)?;
+            prev_output.value.zcash_serialize(&mut writer)?;
+            writer.write_u32::<LittleEndian>(sequence(input))?;
+        }
+        Ok(())
+    }
+
+    fn hash_prevouts(&self, hash_type: HashType) -> io::Result<[u8; 32]> {
+        if hash_type.anyone_can_pay() {
+            return Ok([0; 32]);
+        }
+        let mut state = personalized_state(ZCASH_PREVOUTS_HASH_PERSONALIZATION);
+        for input in self.inputs() {
+            write_prevout(input, &mut state)?;
+        }
+        Ok(finalize(state))
+    }
+
+    fn hash_sequence(&self, hash_type: HashType) -> io::Result<[u8; 32]> {
+        if hash_type.anyone_can_pay()
+            || hash_type.base() == HashType::SINGLE.0
+            || hash_type.base() == HashType::NONE.0
+        {
+            return Ok([0; 32]);
+        }
+        let mut state = personalized_state(ZCASH_SEQUENCE_HASH_PERSONALIZATION);
+        for input in self.inputs() {
+            state.write_u32::<LittleEndian>(sequence(input))?;
+        }
+        Ok(finalize(state))
+    }
+
+    fn hash_outputs(&self, hash_type: HashType, index: Option<usize>) -> io::Result<[u8; 32]> {
+        let base = hash_type.base();
+        let outputs: Vec<&TransparentOutput> =
+            if base != HashType::SINGLE.0 && base != HashType::NONE.0 {
+                self.outputs().collect()
+            } else if base == HashType::SINGLE.0 {
+                // Only the output with the same index as the signed input.
+                match index.and_then(|index| self.outputs().nth(index)) {
+                    Some(output) => vec![output],
+                    None => return Ok([0; 32]),
+                }
+            } else {
+                return Ok([0; 32]);
+            };
+        let mut state = personalized_state(ZCASH_OUTPUTS_HASH_PERSONALIZATION);
+        for output in outputs {
+            output.zcash_serialize(&mut state)?;
+        }
+        Ok(finalize(state))
+    }
+}
+
+fn hash_joinsplits<P: ZkSnarkProof>(
+    joinsplit_data: Option<&JoinSplitData<P>>,
+) -> io::Result<[u8; 32]> {
+    let joinsplit_data = match joinsplit_data {
+        Some(jsd) => jsd,
+        None => return Ok([0; 32]),
+    };
+    let mut state = personalized_state(ZCASH_JOINSPLITS_HASH_PERSONALIZATION);
+    for joinsplit in joinsplit_data.joinsplits() {
+        joinsplit.zcash_serialize(&mut state)?;
+    }
+    state.write_all(&<[u8; 32]>::from(joinsplit_data.pub_key)[..])?;
+    Ok(finalize(state))
+}
+
+fn hash_shielded_spends(shielded_data: Option<&ShieldedData>) -> io::Result<[u8; 32]> {
+    let spends: Vec<_> = shielded_data
+        .into_iter()
+        .flat_map(|sd| sd.spends())
+        .collect();
+    if spends.is_empty() {
+        return Ok([0; 32]);
+    }
+    // The spend authorization signatures sign this hash, so they are left out.
+    let mut state = personalized_state(ZCASH_SHIELDED_SPENDS_HASH_PERSONALIZATION);
+    for spend in spends {
+        state.write_all(&spend.cv[..])?;
+        state.write_all(&spend.anchor.0[..])?;
+        state.write_all(&spend.nullifier.0[..])?;
+        state.write_all(&<[u8; 32]>::from(spend.rk)[..])?;
+        spend.zkproof.zcash_serialize(&mut state)?;
+    }
+    Ok(finalize(state))
+}
+
+fn hash_shielded_outputs(shielded_data: Option<&ShieldedData>) -> io::Result<[u8; 32]> {
+    let outputs: Vec<_> = shielded_data
+        .into_iter()
+        .flat_map(|sd| sd.outputs())
+        .collect();
+    if outputs.is_empty() {
+        return Ok([0; 32]);
+    }
+    let mut state = personalized_state(ZCASH_SHIELDED_OUTPUTS_HASH_PERSONALIZATION);
+    for output in outputs {
+        output.zcash_serialize(&mut state)?;
+    }
+    Ok(finalize(state))
+}
+
+/// The signature hash for the JoinSplits in a version 2 transaction.
+///
+/// This is `zcashd`'s legacy signature hash, with `SIGHASH_ALL` and no
+/// signed input: the transaction is serialized with empty input scripts, and
+/// a zero JoinSplit signature, then hashed with SHA256d.
+fn legacy_joinsplit_sighash(
+    inputs: &[TransparentInput],
+    outputs: &[TransparentOutput],
+    lock_time: LockTime,
+    joinsplit_data: &JoinSplitData<Bctv14Proof>,
+) -> io::Result<[u8; 32]> {
+    let mut writer = Sha256dWriter::default();
+
+    writer.write_u32::<LittleEndian>(2)?;
+    writer.write_compactsize(inputs.len() as u64)?;
+    for input in inputs {
+        write_prevout(input, &mut writer)?;
+        // Every input script is replaced by an empty script.
+        writer.write_compactsize(0)?;
+        writer.write_u32::<LittleEndian>(sequence(input))?;
+    }
+    writer.write_compactsize(outputs.len() as u64)?;
+    for output in outputs {
+        output.zcash_serialize(&mut writer)?;
+    }
+    lock_time.zcash_serialize(&mut writer)?;
+
+    writer.write_compactsize(joinsplit_data.joinsplits().count() as u64)?;
+    for joinsplit in joinsplit_data.joinsplits() {
+        joinsplit.zcash_serialize(&mut writer)?;
+    }
+    writer.write_all(&<[u8; 32]>::from(joinsplit_data.pub_key)[..])?;
+    writer.write_all(&[0; 64])?;
+    writer.write_u32::<LittleEndian>(HashType::ALL.0)?;
+
+    Ok(writer.finish())
+}
+
+/// Write the outpoint spent by `input`, which is null for coinbase inputs.
+fn write_prevout<W: io::Write>(input: &TransparentInput, mut writer: W) -> io::Result<()> {
+    match input {
+        TransparentInput::PrevOut { outpoint, .. } => outpoint.zcash_serialize(&mut writer),
+        TransparentInput::Coinbase { .. } => {
+            writer.write_all(&[0; 32])?;
+            writer.write_u32::<LittleEndian>(0xffff_ffff)
+        }
+    }
+}
+
+fn sequence(input: &TransparentInput) -> u32 {
+    match input {
+        TransparentInput::PrevOut { sequence, .. } => *sequence,
+        TransparentInput::Coinbase { sequence, .. } => *sequence,
+    }
+}
+
+pub(super) fn personalized_state(personal: &[u8; 16]) -> State {
+    Params::new().hash_length(32).personal(personal).to_state()
+}
+
+pub(super) fn finalize(state: State) -> [u8; 32] {
+    let mut hash = [0; 32];
+    hash.copy_from_slice(state.finalize().as_bytes());
+    hash
+}
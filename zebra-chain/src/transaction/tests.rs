@@ -1,161 +1,391 @@
-use proptest::{
-    arbitrary::{any, Arbitrary},
-    collection::vec,
-    option,
-    prelude::*,
-};
+use std::convert::TryInto;
+
+use proptest::{arbitrary::any, prelude::*};
 
 use crate::{
+    amount::Amount,
+    network_upgrade::NetworkUpgrade,
+    orchard,
+    proofs::Halo2Proof,
     serialization::{ZcashDeserialize, ZcashSerialize},
-    types::{LockTime, Script},
+    transparent::Script,
 };
 
 use super::*;
 
-#[cfg(test)]
-impl Transaction {
-    pub fn v1_strategy() -> impl Strategy<Value = Self> {
-        (
-            vec(any::<TransparentInput>(), 0..10),
-            vec(any::<TransparentOutput>(), 0..10),
-            any::<LockTime>(),
-        )
-            .prop_map(|(inputs, outputs, lock_time)| Transaction::V1 {
-                inputs,
-                outputs,
-                lock_time,
-            })
-            .boxed()
-    }
+#[test]
+fn librustzcash_tx_deserialize_and_round_trip() {
+    let tx = Transaction::zcash_deserialize(&test_vectors::GENERIC_TESTNET_TX[..])
+        .expect("transaction test vector from librustzcash should deserialize");
 
-    pub fn v2_strategy() -> impl Strategy<Value = Self> {
-        (
-            vec(any::<TransparentInput>(), 0..10),
-            vec(any::<TransparentOutput>(), 0..10),
-            any::<LockTime>(),
-            option::of(any::<JoinSplitData<Bctv14Proof>>()),
-        )
-            .prop_map(
-                |(inputs, outputs, lock_time, joinsplit_data)| Transaction::V2 {
-                    inputs,
-                    outputs,
-                    lock_time,
-                    joinsplit_data,
-                },
-            )
-            .boxed()
-    }
+    let mut data2 = Vec::new();
+    tx.zcash_serialize(&mut data2).expect("tx should serialize");
 
-    pub fn v3_strategy() -> impl Strategy<Value = Self> {
-        (
-            vec(any::<TransparentInput>(), 0..10),
-            vec(any::<TransparentOutput>(), 0..10),
-            any::<LockTime>(),
-            any::<BlockHeight>(),
-            option::of(any::<JoinSplitData<Bctv14Proof>>()),
-        )
-            .prop_map(
-                |(inputs, outputs, lock_time, expiry_height, joinsplit_data)| Transaction::V3 {
-                    inputs,
-                    outputs,
-                    lock_time,
-                    expiry_height,
-                    joinsplit_data,
-                },
-            )
-            .boxed()
+    assert_eq!(&test_vectors::GENERIC_TESTNET_TX[..], &data2[..]);
+}
+
+#[test]
+fn zcashd_block_txs_round_trip() {
+    use crate::block::Block;
+
+    for bytes in &[
+        &zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..],
+        &zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..],
+        &zebra_test_vectors::BLOCK_MAINNET_415000_BYTES[..],
+        &zebra_test_vectors::BLOCK_MAINNET_434873_BYTES[..],
+    ] {
+        let block = Block::zcash_deserialize(*bytes).expect("block test vector should deserialize");
+
+        // The header is followed by the transaction count, then the
+        // transactions, so each transaction must re-serialize to exactly the
+        // bytes it was parsed from.
+        let mut data = Vec::new();
+        block.header.zcash_serialize(&mut data).unwrap();
+        let mut offset = data.len() + 1;
+        for tx in &block.transactions {
+            let mut tx_data = Vec::new();
+            tx.zcash_serialize(&mut tx_data)
+                .expect("tx should serialize");
+            assert_eq!(&bytes[offset..offset + tx_data.len()], &tx_data[..]);
+            offset += tx_data.len();
+        }
+        assert_eq!(offset, bytes.len());
     }
+}
 
-    pub fn v4_strategy() -> impl Strategy<Value = Self> {
-        (
-            vec(any::<TransparentInput>(), 0..10),
-            vec(any::<TransparentOutput>(), 0..10),
-            any::<LockTime>(),
-            any::<BlockHeight>(),
-            any::<i64>(),
-            option::of(any::<ShieldedData>()),
-            option::of(any::<JoinSplitData<Groth16Proof>>()),
-        )
-            .prop_map(
-                |(
-                    inputs,
-                    outputs,
-                    lock_time,
-                    expiry_height,
-                    value_balance,
-                    shielded_data,
-                    joinsplit_data,
-                )| Transaction::V4 {
-                    inputs,
-                    outputs,
-                    lock_time,
-                    expiry_height,
-                    value_balance,
-                    shielded_data,
-                    joinsplit_data,
-                },
-            )
-            .boxed()
+#[test]
+fn v5_reserved_orchard_flags_are_rejected() {
+    for &(byte, valid) in &[(0b00, true), (0b11, true), (0b100, false), (0x80, false)] {
+        assert_eq!(orchard::Flags::from_byte(byte).is_some(), valid);
     }
+    let flags = orchard::Flags {
+        enable_spends: true,
+        enable_outputs: false,
+    };
+    assert_eq!(orchard::Flags::from_byte(flags.to_byte()), Some(flags));
 }
 
-#[cfg(test)]
-impl Arbitrary for Transaction {
-    type Parameters = ();
-
-    fn arbitrary_with(_args: ()) -> Self::Strategy {
-        prop_oneof![
-            Self::v1_strategy(),
-            Self::v2_strategy(),
-            Self::v3_strategy(),
-            Self::v4_strategy()
-        ]
-        .boxed()
+fn sighash_test_tx(first_prevout_index: u32, second_output_value: i64) -> Transaction {
+    let input = |index| TransparentInput::PrevOut {
+        outpoint: OutPoint {
+            hash: Hash([1; 32]),
+            index,
+        },
+        script: Script(vec![].into()),
+        sequence: 0xffff_fffe,
+    };
+    let output = |value: i64| TransparentOutput {
+        value: value.try_into().unwrap(),
+        pk_script: Script(vec![0x51].into()),
+    };
+    Transaction::V4 {
+        inputs: vec![input(first_prevout_index), input(7)],
+        outputs: vec![output(1_000), output(second_output_value)],
+        lock_time: LockTime::Height(block::Height(0)),
+        expiry_height: block::Height(500_000),
+        value_balance: Amount::zero(),
+        shielded_data: None,
+        joinsplit_data: None,
     }
+}
+
+#[test]
+fn coinbase_transactions_have_one_coinbase_input() {
+    let coinbase = |height| TransparentInput::Coinbase {
+        height: block::Height(height),
+        data: CoinbaseData::new(vec![0; 95]).expect("95 bytes fit with any height"),
+        sequence: u32::MAX,
+    };
+    let tx = |inputs| Transaction::V1 {
+        inputs,
+        outputs: vec![],
+        lock_time: LockTime::unlocked(),
+    };
+
+    assert!(CoinbaseData::new(vec![0; 96]).is_none());
+    assert!(tx(vec![coinbase(1)]).is_coinbase());
 
-    type Strategy = BoxedStrategy<Self>;
+    let two_inputs = tx(vec![coinbase(1), coinbase(2)]);
+    assert!(!two_inputs.is_coinbase());
+    assert!(two_inputs.contains_coinbase_input());
+
+    let spend = sighash_test_tx(0, 2_000);
+    assert!(!spend.is_coinbase());
+    assert!(!spend.contains_coinbase_input());
 }
 
-#[cfg(test)]
-impl Arbitrary for TransparentInput {
-    type Parameters = ();
-
-    fn arbitrary_with(_args: ()) -> Self::Strategy {
-        prop_oneof![
-            (any::<OutPoint>(), any::<Script>(), any::<u32>())
-                .prop_map(|(outpoint, script, sequence)| {
-                    TransparentInput::PrevOut {
-                        outpoint,
-                        script,
-                        sequence,
-                    }
-                })
-                .boxed(),
-            (any::<BlockHeight>(), vec(any::<u8>(), 0..95), any::<u32>())
-                .prop_map(|(height, data, sequence)| {
-                    TransparentInput::Coinbase {
-                        height,
-                        data: CoinbaseData(data),
-                        sequence,
-                    }
-                })
-                .boxed(),
-        ]
-        .boxed()
+#[test]
+fn expiry_and_lock_time_rules() {
+    use chrono::{TimeZone, Utc};
+
+    let tx = sighash_test_tx(0, 2_000);
+    assert!(!tx.is_expired_at(block::Height(500_000)));
+    assert!(tx.is_expired_at(block::Height(500_001)));
+
+    let mut no_expiry = tx.clone();
+    if let Transaction::V4 { expiry_height, .. } = &mut no_expiry {
+        *expiry_height = block::Height(0);
     }
+    assert!(!no_expiry.is_expired_at(block::Height::MAX));
 
-    type Strategy = BoxedStrategy<Self>;
+    let block_time = Utc.timestamp(1_600_000_000, 0);
+    let mut locked = tx;
+    if let Transaction::V4 { lock_time, .. } = &mut locked {
+        *lock_time = LockTime::Height(block::Height(10));
+    }
+    assert!(!locked.is_final(block::Height(10), block_time));
+    assert!(locked.is_final(block::Height(11), block_time));
 }
 
 #[test]
-fn librustzcash_tx_deserialize_and_round_trip() {
+fn sighash_commits_to_the_selected_parts() {
+    let sapling_branch_id: u32 = NetworkUpgrade::Sapling.branch_id().unwrap().into();
+    let spent = vec![
+        TransparentOutput {
+            value: 5_000i64.try_into().unwrap(),
+            pk_script: Script(vec![0x76, 0xa9].into()),
+        };
+        2
+    ];
+    let sighash = |tx: &Transaction, hash_type| {
+        tx.sighash(sapling_branch_id, hash_type, &spent, Some(1))
+            .expect("v4 transactions have a ZIP-243 sighash")
+    };
+    let tx = sighash_test_tx(0, 2_000);
+    let other_output = sighash_test_tx(0, 3_000);
+    let other_input = sighash_test_tx(9, 2_000);
+
+    // SIGHASH_ALL commits to every input and output.
+    assert_ne!(
+        sighash(&tx, HashType::ALL),
+        sighash(&other_output, HashType::ALL)
+    );
+    assert_ne!(
+        sighash(&tx, HashType::ALL),
+        sighash(&other_input, HashType::ALL)
+    );
+
+    // SIGHASH_NONE and SIGHASH_SINGLE leave out outputs that aren't signed.
+    assert_eq!(
+        sighash(&tx, HashType::NONE),
+        sighash(&other_output, HashType::NONE)
+    );
+    assert_ne!(
+        sighash(&tx, HashType::SINGLE),
+        sighash(&other_output, HashType::SINGLE)
+    );
+
+    // ANYONECANPAY leaves out the other inputs.
+    let anyone_can_pay = HashType::ALL | HashType::ANYONECANPAY;
+    assert_eq!(
+        sighash(&tx, anyone_can_pay),
+        sighash(&other_input, anyone_can_pay)
+    );
+
+    // The branch ID is part of the personalization.
+    assert_ne!(
+        tx.sighash(sapling_branch_id, HashType::ALL, &[], None),
+        tx.sighash(sapling_branch_id + 1, HashType::ALL, &[], None)
+    );
+
+    let v1 = Transaction::V1 {
+        inputs: vec![],
+        outputs: vec![],
+        lock_time: LockTime::Height(block::Height(0)),
+    };
+    assert_eq!(
+        v1.sighash(sapling_branch_id, HashType::ALL, &[], None),
+        Err(SigHashError::UnsupportedVersion)
+    );
+
+    // The signed input index comes from the caller, so it can be out of
+    // range.
+    assert_eq!(
+        tx.sighash(sapling_branch_id, HashType::ALL, &spent, Some(2)),
+        Err(SigHashError::MissingInput(2))
+    );
+    assert_eq!(
+        tx.sighash(sapling_branch_id, HashType::ALL, &spent[..1], Some(1)),
+        Err(SigHashError::MissingPreviousOutputs)
+    );
+}
+
+#[test]
+fn zip243_sighash_matches_testnet_signature() {
+    use secp256k1::{Message, PublicKey, Secp256k1, Signature};
+
+    // The first input of testnet block 280003's transaction spends a P2PKH
+    // output of 100 ZEC, with SIGHASH_ALL.
     let tx = Transaction::zcash_deserialize(&test_vectors::GENERIC_TESTNET_TX[..])
         .expect("transaction test vector from librustzcash should deserialize");
+    let spent = TransparentOutput {
+        value: 10_000_000_000i64.try_into().unwrap(),
+        pk_script: Script(
+            hex::decode("76a914b526958de90480a754a2c7187cdd00b2a2a6263d88ac")
+                .unwrap()
+                .into(),
+        ),
+    };
+    let sapling_branch_id: u32 = NetworkUpgrade::Sapling.branch_id().unwrap().into();
+    let sighash = tx
+        .sighash(sapling_branch_id, HashType::ALL, &[spent], Some(0))
+        .expect("v4 transactions have a ZIP-243 sighash");
+    assert_eq!(
+        hex::encode(sighash),
+        "07a095a10f043b46cc5390364512a93c1d317a372daab7062dee5830a722a5d4"
+    );
 
-    let mut data2 = Vec::new();
-    tx.zcash_serialize(&mut data2).expect("tx should serialize");
+    // The input script pushes the signature, with its hash type byte, and
+    // then the public key.
+    let script = match tx.inputs().next() {
+        Some(TransparentInput::PrevOut { script, .. }) => script.0.clone(),
+        input => panic!("expected a spend, got {:?}", input),
+    };
+    let sig_len = script[0] as usize;
+    let signature = &script[1..sig_len];
+    assert_eq!(script[sig_len], 0x01);
+    let pub_key = &script[sig_len + 2..];
+    assert_eq!(script[sig_len + 1] as usize, pub_key.len());
 
-    assert_eq!(&test_vectors::GENERIC_TESTNET_TX[..], &data2[..]);
+    let secp = Secp256k1::verification_only();
+    secp.verify(
+        &Message::from_slice(sighash.as_ref()).unwrap(),
+        &Signature::from_der(signature).unwrap(),
+        &PublicKey::from_slice(pub_key).unwrap(),
+    )
+    .expect("the testnet signature is valid for the ZIP-243 sighash");
+}
+
+#[test]
+fn v5_txid_leaves_out_authorizing_data() {
+    let v5 = |spend_auth_sig: [u8; 64]| {
+        let action = orchard::Action {
+            cv: [1; 32],
+            nullifier: orchard::Nullifier([2; 32]),
+            rk: [3; 32],
+            cm_x: [4; 32],
+            ephemeral_key: [5; 32],
+            enc_ciphertext: orchard::EncryptedNote([6; 580]),
+            out_ciphertext: orchard::WrappedNoteKey([7; 80]),
+            spend_auth_sig: spend_auth_sig.into(),
+        };
+        Transaction::V5 {
+            inputs: vec![],
+            outputs: vec![],
+            lock_time: LockTime::Height(block::Height(0)),
+            expiry_height: block::Height(0),
+            consensus_branch_id: 0x37a4_1b06,
+            sapling_value_balance: Amount::zero(),
+            sapling_shielded_data: None,
+            orchard_shielded_data: Some(orchard::ShieldedData {
+                flags: orchard::Flags {
+                    enable_spends: true,
+                    enable_outputs: true,
+                },
+                value_balance: Amount::zero(),
+                shared_anchor: orchard::tree::Root([8; 32]),
+                proof: Halo2Proof(vec![9; 16].into()),
+                first: action,
+                rest: vec![],
+                binding_sig: [10; 64].into(),
+            }),
+        }
+    };
+    let tx = v5([11; 64]);
+    let resigned = v5([12; 64]);
+
+    assert_eq!(
+        tx.orchard_nullifiers().collect::<Vec<_>>(),
+        vec![&orchard::Nullifier([2; 32])]
+    );
+    assert_eq!(tx.sapling_nullifiers().count(), 0);
+    assert_eq!(tx.sprout_nullifiers().count(), 0);
+
+    assert_eq!(Hash::from(&tx), Hash::from(&resigned));
+    assert_ne!(tx.auth_digest(), resigned.auth_digest());
+    assert_ne!(tx.wtx_id(), resigned.wtx_id());
+    assert_eq!(
+        tx.sighash(0x37a4_1b06, HashType::ALL, &[], None),
+        Ok(SigHash(Hash::from(&tx).0))
+    );
+    assert_eq!(
+        tx.sighash(0x37a4_1b06, HashType::NONE, &[], None),
+        Err(SigHashError::ShieldedHashType)
+    );
+
+    // Earlier versions commit to their signatures in the ID, and have no
+    // auth digest.
+    let v4 = sighash_test_tx(0, 2_000);
+    assert_eq!(v4.auth_digest(), AuthDigest([0xff; 32]));
+}
+
+#[test]
+fn v5_sighash_commits_to_the_spent_outputs() {
+    let v4 = sighash_test_tx(0, 2_000);
+    let tx = Transaction::V5 {
+        inputs: v4.inputs().cloned().collect(),
+        outputs: v4.outputs().cloned().collect(),
+        lock_time: LockTime::Height(block::Height(0)),
+        expiry_height: block::Height(0),
+        consensus_branch_id: 0x37a4_1b06,
+        sapling_value_balance: Amount::zero(),
+        sapling_shielded_data: None,
+        orchard_shielded_data: None,
+    };
+    let spent = |value: i64| {
+        vec![
+            TransparentOutput {
+                value: value.try_into().unwrap(),
+                pk_script: Script(vec![0x76, 0xa9].into()),
+            };
+            2
+        ]
+    };
+    let sighash = |spent: &[TransparentOutput], hash_type, input| {
+        tx.sighash(0x37a4_1b06, hash_type, spent, input)
+            .expect("v5 transactions have a ZIP-244 sighash")
+    };
+
+    // With transparent inputs, the signature digest isn't the transaction
+    // ID, even for shielded signatures.
+    assert_ne!(
+        sighash(&spent(5_000), HashType::ALL, None),
+        SigHash(Hash::from(&tx).0)
+    );
+    assert_ne!(
+        sighash(&spent(5_000), HashType::ALL, None),
+        sighash(&spent(5_000), HashType::ALL, Some(0))
+    );
+    assert_ne!(
+        sighash(&spent(5_000), HashType::ALL, Some(0)),
+        sighash(&spent(5_000), HashType::ALL, Some(1))
+    );
+
+    // Every spent output is signed, unless ANYONECANPAY is set.
+    let mut other_spent = spent(5_000);
+    other_spent[1].value = 6_000i64.try_into().unwrap();
+    assert_ne!(
+        sighash(&spent(5_000), HashType::ALL, Some(0)),
+        sighash(&other_spent, HashType::ALL, Some(0))
+    );
+    let anyone_can_pay = HashType::ALL | HashType::ANYONECANPAY;
+    assert_eq!(
+        sighash(&spent(5_000), anyone_can_pay, Some(0)),
+        sighash(&other_spent, anyone_can_pay, Some(0))
+    );
+    assert_ne!(
+        sighash(&spent(5_000), anyone_can_pay, Some(0)),
+        sighash(&spent(6_000), anyone_can_pay, Some(0))
+    );
+
+    assert_eq!(
+        tx.sighash(0x37a4_1b06, HashType::ALL, &[], None),
+        Err(SigHashError::MissingPreviousOutputs)
+    );
+    assert_eq!(
+        tx.sighash(0x37a4_1b06, HashType(0x04), &spent(5_000), Some(0)),
+        Err(SigHashError::InvalidHashType(0x04))
+    );
 }
 
 #[cfg(test)]
@@ -172,4 +402,49 @@ proptest! {
 
         prop_assert_eq![tx, tx2];
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn transaction_serde_roundtrip(tx in any::<Transaction>()) {
+        let json = serde_json::to_string(&tx).expect("tx should serialize to JSON");
+        let tx2: Transaction = serde_json::from_str(&json).expect("JSON should deserialize");
+
+        prop_assert_eq![tx, tx2];
+    }
+}
+
+#[test]
+fn binding_verification_key_balances_value_commitments() {
+    use futures::future::Either;
+
+    use crate::{redjubjub, sapling::tree};
+
+    // A commitment to 5 zatoshis with zero randomness.
+    let cv = jubjub::AffinePoint::from(
+        shielded_data::value_commitment_base() * jubjub::Fr::from_raw([5, 0, 0, 0]),
+    )
+    .to_bytes();
+    let shielded_data = ShieldedData {
+        first: Either::Left(Spend {
+            cv,
+            anchor: tree::Root([0; 32]),
+            nullifier: crate::sapling::Nullifier([0; 32]),
+            rk: redjubjub::VerificationKeyBytes::from([0; 32]),
+            zkproof: Groth16Proof([0; 192]),
+            spend_auth_sig: redjubjub::Signature::from([0; 64]),
+        }),
+        rest_spends: Vec::new(),
+        rest_outputs: Vec::new(),
+        binding_sig: redjubjub::Signature::from([0; 64]),
+    };
+
+    let identity: [u8; 32] = jubjub::AffinePoint::identity().to_bytes();
+    let bvk = |value: i64| {
+        shielded_data
+            .binding_verification_key(value.try_into().unwrap())
+            .map(<[u8; 32]>::from)
+    };
+    assert_eq!(bvk(5), Some(identity));
+    assert_ne!(bvk(4), Some(identity));
+    assert_ne!(bvk(-5), Some(identity));
 }
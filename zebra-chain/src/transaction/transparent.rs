@@ -1,25 +1,53 @@
 //! Transaction types.
 #![allow(clippy::unit_arg)]
 
-#[cfg(test)]
+#[cfg(any(test, feature = "proptest-impl"))]
 use proptest_derive::Arbitrary;
 
-use crate::types::{BlockHeight, Script};
+use crate::amount::{Amount, NonNegative};
+use crate::block;
+use crate::serialization::{ZcashDeserialize, ZcashSerialize};
+use crate::transparent::Script;
 
-use super::TransactionHash;
+use super::Hash;
+
+/// The maximum length of the script in a coinbase input, in bytes.
+///
+/// The script contains the encoded block height, followed by the
+/// [`CoinbaseData`].
+pub const MAX_COINBASE_SCRIPT_LEN: usize = 100;
+
+/// The maximum length of an encoded coinbase height, in bytes.
+///
+/// Heights are encoded as a script that pushes the height to the stack, as in
+/// [BIP-34](https://github.com/bitcoin/bips/blob/master/bip-0034.mediawiki).
+/// Heights below `500_000_000` take at most an opcode and 4 bytes.
+pub const MAX_COINBASE_HEIGHT_LEN: usize = 5;
 
 /// Arbitrary data inserted by miners into a coinbase transaction.
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct CoinbaseData(
     /// Invariant: this vec, together with the coinbase height, must be less than
     /// 100 bytes. We enforce this by only constructing CoinbaseData fields by
-    /// parsing blocks with 100-byte data fields. When we implement block
-    /// creation, we should provide a constructor for the coinbase data field
-    /// that restricts it to 95 = 100 -1 -4 bytes (safe for any block height up
-    /// to 500_000_000).
+    /// parsing blocks with 100-byte data fields, or by using
+    /// [`CoinbaseData::new`], which leaves room for any block height.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::serde_hex"))]
     pub(super) Vec<u8>,
 );
 
+impl CoinbaseData {
+    /// Returns miner data for a new coinbase input, or `None` if `data` is
+    /// too long to fit in the coinbase script with any block height.
+    pub fn new(data: Vec<u8>) -> Option<CoinbaseData> {
+        if data.len() <= MAX_COINBASE_SCRIPT_LEN - MAX_COINBASE_HEIGHT_LEN {
+            Some(CoinbaseData(data))
+        } else {
+            None
+        }
+    }
+}
+
 impl AsRef<[u8]> for CoinbaseData {
     fn as_ref(&self) -> &[u8] {
         self.0.as_ref()
@@ -29,11 +57,12 @@ impl AsRef<[u8]> for CoinbaseData {
 /// OutPoint
 ///
 /// A particular transaction output reference.
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-#[cfg_attr(test, derive(Arbitrary))]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, ZcashSerialize, ZcashDeserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
 pub struct OutPoint {
     /// References the transaction that contains the UTXO being spent.
-    pub hash: TransactionHash,
+    pub hash: Hash,
 
     /// Identifies which UTXO from that transaction is referenced; the
     /// first output is 0, etc.
@@ -42,6 +71,7 @@ pub struct OutPoint {
 
 /// A transparent input to a transaction.
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum TransparentInput {
     /// A reference to an output of a previous transaction.
     PrevOut {
@@ -55,7 +85,7 @@ pub enum TransparentInput {
     /// New coins created by the block reward.
     Coinbase {
         /// The height of this block.
-        height: BlockHeight,
+        height: block::Height,
         /// Free data inserted by miners after the block height.
         data: CoinbaseData,
         /// The sequence number for the output.
@@ -75,13 +105,13 @@ pub enum TransparentInput {
 /// I only own one UTXO worth 2 ZEC, I would construct a transaction
 /// that spends my UTXO and sends 1 ZEC to you and 1 ZEC back to me
 /// (just like receiving change).
-#[derive(Clone, Debug, Eq, PartialEq)]
-#[cfg_attr(test, derive(Arbitrary))]
+#[derive(Clone, Debug, Eq, PartialEq, ZcashSerialize)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
 pub struct TransparentOutput {
     /// Transaction value.
     // At https://en.bitcoin.it/wiki/Protocol_documentation#tx, this is an i64.
-    // XXX refine to Amount ?
-    pub value: u64,
+    pub value: Amount<NonNegative>,
 
     /// Usually contains the public key as a Bitcoin script setting up
     /// conditions to claim this output.
@@ -0,0 +1,457 @@
+//! Transaction IDs and authorizing data digests for v5 transactions.
+//!
+//! [ZIP-244](https://zips.z.cash/zip-0244) defines these digests as a tree
+//! of BLAKE2b hashes, so that the transaction ID doesn't commit to signatures
+//! or proofs, and light clients can check parts of a transaction without
+//! downloading all of it. The signature digest replaces the transparent
+//! part of the transaction ID with a digest that commits to the spent
+//! outputs, and the signed input.
+
+use std::io;
+
+use blake2b_simd::State;
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use crate::{amount::Amount, orchard, serialization::ZcashSerialize};
+
+use super::{
+    serialize::NU5_VERSION_GROUP_ID,
+    sighash::{finalize, personalized_state},
+    AuthDigest, Hash, HashType, ShieldedData, SigHash, Transaction, TransparentInput,
+    TransparentOutput, UnminedTxId, WtxId,
+};
+
+const ZCASH_TX_PERSONALIZATION_PREFIX: &[u8; 12] = b"ZcashTxHash_";
+const ZCASH_AUTH_PERSONALIZATION_PREFIX: &[u8; 12] = b"ZTxAuthHash_";
+
+const ZCASH_HEADERS_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxIdHeadersHash";
+const ZCASH_TRANSPARENT_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxIdTranspaHash";
+const ZCASH_PREVOUTS_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxIdPrevoutHash";
+const ZCASH_SEQUENCE_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxIdSequencHash";
+const ZCASH_OUTPUTS_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxIdOutputsHash";
+const ZCASH_TRANSPARENT_AMOUNTS_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxTrAmountsHash";
+const ZCASH_TRANSPARENT_SCRIPTS_PUBKEYS_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxTrScriptsHash";
+const ZCASH_TRANSPARENT_INPUT_HASH_PERSONALIZATION: &[u8; 16] = b"Zcash___TxInHash";
+
+const ZCASH_SAPLING_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxIdSaplingHash";
+const ZCASH_SAPLING_SPENDS_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxIdSSpendsHash";
+const ZCASH_SAPLING_SPENDS_COMPACT_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxIdSSpendCHash";
+const ZCASH_SAPLING_SPENDS_NONCOMPACT_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxIdSSpendNHash";
+const ZCASH_SAPLING_OUTPUTS_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxIdSOutputHash";
+const ZCASH_SAPLING_OUTPUTS_COMPACT_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxIdSOutC__Hash";
+const ZCASH_SAPLING_OUTPUTS_MEMOS_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxIdSOutM__Hash";
+const ZCASH_SAPLING_OUTPUTS_NONCOMPACT_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxIdSOutN__Hash";
+
+const ZCASH_ORCHARD_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxIdOrchardHash";
+const ZCASH_ORCHARD_ACTIONS_COMPACT_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxIdOrcActCHash";
+const ZCASH_ORCHARD_ACTIONS_MEMOS_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxIdOrcActMHash";
+const ZCASH_ORCHARD_ACTIONS_NONCOMPACT_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxIdOrcActNHash";
+
+const ZCASH_TRANSPARENT_SCRIPTS_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxAuthTransHash";
+const ZCASH_SAPLING_SIGS_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxAuthSapliHash";
+const ZCASH_ORCHARD_SIGS_HASH_PERSONALIZATION: &[u8; 16] = b"ZTxAuthOrchaHash";
+
+/// The auth digest of transactions before v5, which don't have one.
+const LEGACY_AUTH_DIGEST: AuthDigest = AuthDigest([0xff; 32]);
+
+/// The length of the compact part of a note ciphertext, which light clients
+/// use to detect their notes.
+const COMPACT_NOTE_SIZE: usize = 52;
+
+/// The end of the memo in a note ciphertext.
+const MEMO_END: usize = COMPACT_NOTE_SIZE + 512;
+
+impl Transaction {
+    /// Compute the authorizing data digest of this transaction, which commits
+    /// to its signatures and proofs.
+    ///
+    /// Transactions before v5 have no auth digest, so like `zcashd`, we use
+    /// 32 `0xff` bytes in block commitments.
+    pub fn auth_digest(&self) -> AuthDigest {
+        let (branch_id, inputs, sapling, orchard) = match self {
+            Transaction::V5 {
+                consensus_branch_id,
+                inputs,
+                sapling_shielded_data,
+                orchard_shielded_data,
+                ..
+            } => (
+                *consensus_branch_id,
+                inputs,
+                sapling_shielded_data.as_ref(),
+                orchard_shielded_data.as_ref(),
+            ),
+            _ => return LEGACY_AUTH_DIGEST,
+        };
+
+        let mut state = personalized_state(&branch_personalization(
+            ZCASH_AUTH_PERSONALIZATION_PREFIX,
+            branch_id,
+        ));
+        // `State` never returns write errors.
+        state.update(&transparent_scripts_digest(inputs).expect("BLAKE2b state is infallible"));
+        state.update(&sapling_auth_digest(sapling).expect("BLAKE2b state is infallible"));
+        state.update(&orchard_auth_digest(orchard).expect("BLAKE2b state is infallible"));
+        AuthDigest(finalize(state))
+    }
+
+    /// Compute the wide transaction ID of this transaction, which is used to
+    /// relay v5 transactions.
+    pub fn wtx_id(&self) -> WtxId {
+        WtxId {
+            id: Hash::from(self),
+            auth_digest: self.auth_digest(),
+        }
+    }
+
+    /// Returns the ID peers use to relay this transaction while it is unmined.
+    ///
+    /// v5 transactions use their wide ID, and earlier versions use their
+    /// transaction ID.
+    pub fn unmined_id(&self) -> UnminedTxId {
+        match self {
+            Transaction::V5 { .. } => UnminedTxId::Witnessed(self.wtx_id()),
+            _ => UnminedTxId::Legacy(Hash::from(self)),
+        }
+    }
+
+    /// Compute the ZIP-244 transaction ID of a v5 transaction.
+    ///
+    /// Returns `None` for earlier versions, whose IDs are the hash of the
+    /// whole transaction.
+    pub(super) fn zip244_txid(&self) -> Option<Hash> {
+        match self {
+            Transaction::V5 { .. } => {}
+            _ => return None,
+        }
+        // `State` never returns write errors.
+        let transparent_digest = self
+            .transparent_digest()
+            .expect("BLAKE2b state is infallible");
+        self.zip244_digest(transparent_digest).map(Hash)
+    }
+
+    /// Compute the ZIP-244 signature digest of a v5 transaction.
+    ///
+    /// `previous_outputs` are the outputs spent by each input, and `input` is
+    /// the index of the signed transparent input, the input itself, and the
+    /// output it spends. Shielded signatures use `None`, and
+    /// [`HashType::ALL`].
+    ///
+    /// Returns `None` for earlier versions.
+    pub(super) fn zip244_sighash(
+        &self,
+        hash_type: HashType,
+        previous_outputs: &[TransparentOutput],
+        input: Option<(usize, &TransparentInput, &TransparentOutput)>,
+    ) -> Option<SigHash> {
+        // `State` never returns write errors.
+        let transparent_sig_digest = self
+            .transparent_sig_digest(hash_type, previous_outputs, input)
+            .expect("BLAKE2b state is infallible");
+        self.zip244_digest(transparent_sig_digest).map(SigHash)
+    }
+
+    /// Compute the top level ZIP-244 digest of a v5 transaction, with
+    /// `transparent_digest` as its transparent part.
+    fn zip244_digest(&self, transparent_digest: [u8; 32]) -> Option<[u8; 32]> {
+        let (branch_id, sapling_value_balance, sapling, orchard) = match self {
+            Transaction::V5 {
+                consensus_branch_id,
+                sapling_value_balance,
+                sapling_shielded_data,
+                orchard_shielded_data,
+                ..
+            } => (
+                *consensus_branch_id,
+                *sapling_value_balance,
+                sapling_shielded_data.as_ref(),
+                orchard_shielded_data.as_ref(),
+            ),
+            _ => return None,
+        };
+
+        let mut state = personalized_state(&branch_personalization(
+            ZCASH_TX_PERSONALIZATION_PREFIX,
+            branch_id,
+        ));
+        // `State` never returns write errors.
+        state.update(
+            &self
+                .header_digest(branch_id)
+                .expect("BLAKE2b state is infallible"),
+        );
+        state.update(&transparent_digest);
+        state.update(
+            &sapling_digest(sapling_value_balance, sapling).expect("BLAKE2b state is infallible"),
+        );
+        state.update(&orchard_digest(orchard).expect("BLAKE2b state is infallible"));
+        Some(finalize(state))
+    }
+
+    fn header_digest(&self, branch_id: u32) -> io::Result<[u8; 32]> {
+        let mut state = personalized_state(ZCASH_HEADERS_HASH_PERSONALIZATION);
+        state.write_u32::<LittleEndian>(5 | (1 << 31))?;
+        state.write_u32::<LittleEndian>(NU5_VERSION_GROUP_ID)?;
+        state.write_u32::<LittleEndian>(branch_id)?;
+        self.lock_time().zcash_serialize(&mut state)?;
+        let expiry_height = self
+            .expiry_height()
+            .expect("v5 transactions have an expiry height");
+        state.write_u32::<LittleEndian>(expiry_height.0)?;
+        Ok(finalize(state))
+    }
+
+    fn transparent_digest(&self) -> io::Result<[u8; 32]> {
+        let mut state = personalized_state(ZCASH_TRANSPARENT_HASH_PERSONALIZATION);
+        if self.inputs().next().is_none() && self.outputs().next().is_none() {
+            return Ok(finalize(state));
+        }
+
+        let (prevouts, sequences) = self.prevouts_and_sequences()?;
+        state.write_all(&finalize(prevouts))?;
+        state.write_all(&finalize(sequences))?;
+        state.write_all(&outputs_digest(self.outputs())?)?;
+        Ok(finalize(state))
+    }
+
+    /// The transparent part of the ZIP-244 signature digest.
+    ///
+    /// Coinbase transactions, and transactions without transparent inputs,
+    /// use the transparent part of the transaction ID.
+    fn transparent_sig_digest(
+        &self,
+        hash_type: HashType,
+        previous_outputs: &[TransparentOutput],
+        input: Option<(usize, &TransparentInput, &TransparentOutput)>,
+    ) -> io::Result<[u8; 32]> {
+        if self.is_coinbase() || self.inputs().next().is_none() {
+            return self.transparent_digest();
+        }
+
+        // ANYONECANPAY leaves out the other inputs, and the outputs they
+        // spend.
+        let (prevouts, sequences) = if hash_type.anyone_can_pay() {
+            (
+                personalized_state(ZCASH_PREVOUTS_HASH_PERSONALIZATION),
+                personalized_state(ZCASH_SEQUENCE_HASH_PERSONALIZATION),
+            )
+        } else {
+            self.prevouts_and_sequences()?
+        };
+        let mut amounts = personalized_state(ZCASH_TRANSPARENT_AMOUNTS_HASH_PERSONALIZATION);
+        let mut scripts =
+            personalized_state(ZCASH_TRANSPARENT_SCRIPTS_PUBKEYS_HASH_PERSONALIZATION);
+        if !hash_type.anyone_can_pay() {
+            for output in previous_outputs {
+                output.value.zcash_serialize(&mut amounts)?;
+                output.pk_script.zcash_serialize(&mut scripts)?;
+            }
+        }
+
+        let outputs = if hash_type.base() == HashType::SINGLE.0 {
+            // Only the output with the same index as the signed input.
+            let index = input.map(|(index, _, _)| index);
+            outputs_digest(index.and_then(|index| self.outputs().nth(index)))?
+        } else if hash_type.base() == HashType::NONE.0 {
+            outputs_digest(None)?
+        } else {
+            outputs_digest(self.outputs())?
+        };
+
+        let mut txin = personalized_state(ZCASH_TRANSPARENT_INPUT_HASH_PERSONALIZATION);
+        if let Some((_, input, prev_output)) = input {
+            let (prevout, sequence) = split_input(input)?;
+            txin.write_all(&prevout)?;
+            prev_output.value.zcash_serialize(&mut txin)?;
+            prev_output.pk_script.zcash_serialize(&mut txin)?;
+            txin.write_u32::<LittleEndian>(sequence)?;
+        }
+
+        let mut state = personalized_state(ZCASH_TRANSPARENT_HASH_PERSONALIZATION);
+        state.write_u8(hash_type.0 as u8)?;
+        state.write_all(&finalize(prevouts))?;
+        state.write_all(&finalize(amounts))?;
+        state.write_all(&finalize(scripts))?;
+        state.write_all(&finalize(sequences))?;
+        state.write_all(&outputs)?;
+        state.write_all(&finalize(txin))?;
+        Ok(finalize(state))
+    }
+
+    /// Returns the prevout and sequence number digest states for every
+    /// input.
+    fn prevouts_and_sequences(&self) -> io::Result<(State, State)> {
+        let mut prevouts = personalized_state(ZCASH_PREVOUTS_HASH_PERSONALIZATION);
+        let mut sequences = personalized_state(ZCASH_SEQUENCE_HASH_PERSONALIZATION);
+        for input in self.inputs() {
+            let (prevout, sequence) = split_input(input)?;
+            prevouts.write_all(&prevout)?;
+            sequences.write_u32::<LittleEndian>(sequence)?;
+        }
+        Ok((prevouts, sequences))
+    }
+}
+
+fn outputs_digest<'a>(
+    outputs: impl IntoIterator<Item = &'a TransparentOutput>,
+) -> io::Result<[u8; 32]> {
+    let mut state = personalized_state(ZCASH_OUTPUTS_HASH_PERSONALIZATION);
+    for output in outputs {
+        output.zcash_serialize(&mut state)?;
+    }
+    Ok(finalize(state))
+}
+
+/// Returns a personalization with `prefix`, followed by `branch_id`.
+fn branch_personalization(prefix: &[u8; 12], branch_id: u32) -> [u8; 16] {
+    let mut personal = [0; 16];
+    personal[..12].copy_from_slice(prefix);
+    personal[12..].copy_from_slice(&branch_id.to_le_bytes());
+    personal
+}
+
+/// Split the serialized form of `input` into its outpoint and sequence
+/// number.
+///
+/// Coinbase inputs are serialized with a null outpoint.
+fn split_input(input: &TransparentInput) -> io::Result<([u8; 36], u32)> {
+    let bytes = serialize_input(input)?;
+    let mut prevout = [0; 36];
+    prevout.copy_from_slice(&bytes[..36]);
+    let sequence = match input {
+        TransparentInput::PrevOut { sequence, .. } => *sequence,
+        TransparentInput::Coinbase { sequence, .. } => *sequence,
+    };
+    Ok((prevout, sequence))
+}
+
+fn serialize_input(input: &TransparentInput) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    input.zcash_serialize(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn transparent_scripts_digest(inputs: &[TransparentInput]) -> io::Result<[u8; 32]> {
+    let mut state = personalized_state(ZCASH_TRANSPARENT_SCRIPTS_HASH_PERSONALIZATION);
+    for input in inputs {
+        // The script, with its length, is between the outpoint and the
+        // sequence number. Coinbase inputs encode the height in the script.
+        let bytes = serialize_input(input)?;
+        state.write_all(&bytes[36..bytes.len() - 4])?;
+    }
+    Ok(finalize(state))
+}
+
+fn sapling_digest(
+    value_balance: Amount,
+    shielded_data: Option<&ShieldedData>,
+) -> io::Result<[u8; 32]> {
+    let mut state = personalized_state(ZCASH_SAPLING_HASH_PERSONALIZATION);
+    let shielded_data = match shielded_data {
+        Some(sd) => sd,
+        None => return Ok(finalize(state)),
+    };
+
+    let mut spends = personalized_state(ZCASH_SAPLING_SPENDS_HASH_PERSONALIZATION);
+    if shielded_data.spends().next().is_some() {
+        let mut compact = personalized_state(ZCASH_SAPLING_SPENDS_COMPACT_HASH_PERSONALIZATION);
+        let mut noncompact =
+            personalized_state(ZCASH_SAPLING_SPENDS_NONCOMPACT_HASH_PERSONALIZATION);
+        for spend in shielded_data.spends() {
+            compact.write_all(&spend.nullifier.0[..])?;
+            noncompact.write_all(&spend.cv[..])?;
+            noncompact.write_all(&spend.anchor.0[..])?;
+            noncompact.write_all(&<[u8; 32]>::from(spend.rk)[..])?;
+        }
+        spends.write_all(&finalize(compact))?;
+        spends.write_all(&finalize(noncompact))?;
+    }
+
+    let mut outputs = personalized_state(ZCASH_SAPLING_OUTPUTS_HASH_PERSONALIZATION);
+    if shielded_data.outputs().next().is_some() {
+        let mut compact = personalized_state(ZCASH_SAPLING_OUTPUTS_COMPACT_HASH_PERSONALIZATION);
+        let mut memos = personalized_state(ZCASH_SAPLING_OUTPUTS_MEMOS_HASH_PERSONALIZATION);
+        let mut noncompact =
+            personalized_state(ZCASH_SAPLING_OUTPUTS_NONCOMPACT_HASH_PERSONALIZATION);
+        for output in shielded_data.outputs() {
+            let enc_ciphertext = &output.enc_ciphertext.0[..];
+            compact.write_all(&output.cmu[..])?;
+            compact.write_all(&output.ephemeral_key.to_bytes())?;
+            compact.write_all(&enc_ciphertext[..COMPACT_NOTE_SIZE])?;
+            memos.write_all(&enc_ciphertext[COMPACT_NOTE_SIZE..MEMO_END])?;
+            noncompact.write_all(&output.cv[..])?;
+            noncompact.write_all(&enc_ciphertext[MEMO_END..])?;
+            noncompact.write_all(&output.out_ciphertext.0[..])?;
+        }
+        outputs.write_all(&finalize(compact))?;
+        outputs.write_all(&finalize(memos))?;
+        outputs.write_all(&finalize(noncompact))?;
+    }
+
+    state.write_all(&finalize(spends))?;
+    state.write_all(&finalize(outputs))?;
+    value_balance.zcash_serialize(&mut state)?;
+    Ok(finalize(state))
+}
+
+fn sapling_auth_digest(shielded_data: Option<&ShieldedData>) -> io::Result<[u8; 32]> {
+    let mut state = personalized_state(ZCASH_SAPLING_SIGS_HASH_PERSONALIZATION);
+    if let Some(shielded_data) = shielded_data {
+        for spend in shielded_data.spends() {
+            spend.zkproof.zcash_serialize(&mut state)?;
+        }
+        for spend in shielded_data.spends() {
+            state.write_all(&<[u8; 64]>::from(spend.spend_auth_sig)[..])?;
+        }
+        for output in shielded_data.outputs() {
+            output.zkproof.zcash_serialize(&mut state)?;
+        }
+        state.write_all(&<[u8; 64]>::from(shielded_data.binding_sig)[..])?;
+    }
+    Ok(finalize(state))
+}
+
+fn orchard_digest(shielded_data: Option<&orchard::ShieldedData>) -> io::Result<[u8; 32]> {
+    let mut state = personalized_state(ZCASH_ORCHARD_HASH_PERSONALIZATION);
+    let shielded_data = match shielded_data {
+        Some(sd) => sd,
+        None => return Ok(finalize(state)),
+    };
+
+    let mut compact = personalized_state(ZCASH_ORCHARD_ACTIONS_COMPACT_HASH_PERSONALIZATION);
+    let mut memos = personalized_state(ZCASH_ORCHARD_ACTIONS_MEMOS_HASH_PERSONALIZATION);
+    let mut noncompact = personalized_state(ZCASH_ORCHARD_ACTIONS_NONCOMPACT_HASH_PERSONALIZATION);
+    for action in shielded_data.actions() {
+        let enc_ciphertext = &action.enc_ciphertext.0[..];
+        compact.write_all(&action.nullifier.0[..])?;
+        compact.write_all(&action.cm_x[..])?;
+        compact.write_all(&action.ephemeral_key[..])?;
+        compact.write_all(&enc_ciphertext[..COMPACT_NOTE_SIZE])?;
+        memos.write_all(&enc_ciphertext[COMPACT_NOTE_SIZE..MEMO_END])?;
+        noncompact.write_all(&action.cv[..])?;
+        noncompact.write_all(&action.rk[..])?;
+        noncompact.write_all(&enc_ciphertext[MEMO_END..])?;
+        noncompact.write_all(&action.out_ciphertext.0[..])?;
+    }
+
+    state.write_all(&finalize(compact))?;
+    state.write_all(&finalize(memos))?;
+    state.write_all(&finalize(noncompact))?;
+    state.write_u8(shielded_data.flags.to_byte())?;
+    shielded_data.value_balance.zcash_serialize(&mut state)?;
+    state.write_all(&shielded_data.shared_anchor.0[..])?;
+    Ok(finalize(state))
+}
+
+fn orchard_auth_digest(shielded_data: Option<&orchard::ShieldedData>) -> io::Result<[u8; 32]> {
+    let mut state = personalized_state(ZCASH_ORCHARD_SIGS_HASH_PERSONALIZATION);
+    if let Some(shielded_data) = shielded_data {
+        state.write_all(&shielded_data.proof.0[..])?;
+        for action in shielded_data.actions() {
+            state.write_all(&action.spend_auth_sig.0[..])?;
+        }
+        state.write_all(&shielded_data.binding_sig.0[..])?;
+    }
+    Ok(finalize(state))
+}
@@ -0,0 +1,7 @@
+//! Transparent-related (Bitcoin-inherited) functionality.
+
+mod address;
+mod script;
+
+pub use address::Address;
+pub use script::{Instruction, Script, ScriptError, MAX_PUBKEYS_PER_MULTISIG};
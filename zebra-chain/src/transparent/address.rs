@@ -6,15 +6,16 @@ use ripemd160::{Digest, Ripemd160};
 use secp256k1::PublicKey;
 use sha2::Sha256;
 
-#[cfg(test)]
+#[cfg(any(test, feature = "proptest-impl"))]
 use proptest::{arbitrary::Arbitrary, collection::vec, prelude::*};
 
 use crate::{
     serialization::{SerializationError, ZcashDeserialize, ZcashSerialize},
-    types::Script,
     Network,
 };
 
+use super::{script::opcodes::*, Script};
+
 /// Magic numbers used to identify what networks Transparent Addresses
 /// are associated with.
 mod magics {
@@ -41,7 +42,7 @@ mod magics {
 ///
 /// https://zips.z.cash/protocol/protocol.pdf#transparentaddrencoding
 #[derive(Copy, Clone, Eq, PartialEq)]
-pub enum TransparentAddress {
+pub enum Address {
     /// P2SH (Pay to Script Hash) addresses
     PayToScriptHash {
         /// Production, test, or other network
@@ -59,19 +60,19 @@ pub enum TransparentAddress {
     },
 }
 
-impl fmt::Debug for TransparentAddress {
+impl fmt::Debug for Address {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut debug_struct = f.debug_struct("TransparentAddress");
+        let mut debug_struct = f.debug_struct("transparent::Address");
 
         match self {
-            TransparentAddress::PayToScriptHash {
+            Address::PayToScriptHash {
                 network,
                 script_hash,
             } => debug_struct
                 .field("network", network)
                 .field("script_hash", &hex::encode(script_hash))
                 .finish(),
-            TransparentAddress::PayToPublicKeyHash {
+            Address::PayToPublicKeyHash {
                 network,
                 pub_key_hash,
             } => debug_struct
@@ -82,7 +83,7 @@ impl fmt::Debug for TransparentAddress {
     }
 }
 
-impl fmt::Display for TransparentAddress {
+impl fmt::Display for Address {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut bytes = io::Cursor::new(Vec::new());
         let _ = self.zcash_serialize(&mut bytes);
@@ -91,25 +92,25 @@ impl fmt::Display for TransparentAddress {
     }
 }
 
-impl From<Script> for TransparentAddress {
+impl From<Script> for Address {
     fn from(script: Script) -> Self {
-        TransparentAddress::PayToScriptHash {
+        Address::PayToScriptHash {
             network: Network::Mainnet,
             script_hash: Self::hash_payload(&script.0[..]),
         }
     }
 }
 
-impl From<PublicKey> for TransparentAddress {
+impl From<PublicKey> for Address {
     fn from(pub_key: PublicKey) -> Self {
-        TransparentAddress::PayToPublicKeyHash {
+        Address::PayToPublicKeyHash {
             network: Network::Mainnet,
             pub_key_hash: Self::hash_payload(&pub_key.serialize()[..]),
         }
     }
 }
 
-impl std::str::FromStr for TransparentAddress {
+impl std::str::FromStr for Address {
     type Err = SerializationError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -122,10 +123,10 @@ impl std::str::FromStr for TransparentAddress {
     }
 }
 
-impl ZcashSerialize for TransparentAddress {
+impl ZcashSerialize for Address {
     fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
         match self {
-            TransparentAddress::PayToScriptHash {
+            Address::PayToScriptHash {
                 network,
                 script_hash,
             } => {
@@ -137,7 +138,7 @@ impl ZcashSerialize for TransparentAddress {
                 }
                 writer.write_all(script_hash)?
             }
-            TransparentAddress::PayToPublicKeyHash {
+            Address::PayToPublicKeyHash {
                 network,
                 pub_key_hash,
             } => {
@@ -155,7 +156,7 @@ impl ZcashSerialize for TransparentAddress {
     }
 }
 
-impl ZcashDeserialize for TransparentAddress {
+impl ZcashDeserialize for Address {
     fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
         let mut version_bytes = [0; 2];
         reader.read_exact(&mut version_bytes)?;
@@ -164,19 +165,19 @@ impl ZcashDeserialize for TransparentAddress {
         reader.read_exact(&mut hash_bytes)?;
 
         match version_bytes {
-            magics::p2sh::MAINNET => Ok(TransparentAddress::PayToScriptHash {
+            magics::p2sh::MAINNET => Ok(Address::PayToScriptHash {
                 network: Network::Mainnet,
                 script_hash: hash_bytes,
             }),
-            magics::p2sh::TESTNET => Ok(TransparentAddress::PayToScriptHash {
+            magics::p2sh::TESTNET => Ok(Address::PayToScriptHash {
                 network: Network::Testnet,
                 script_hash: hash_bytes,
             }),
-            magics::p2pkh::MAINNET => Ok(TransparentAddress::PayToPublicKeyHash {
+            magics::p2pkh::MAINNET => Ok(Address::PayToPublicKeyHash {
                 network: Network::Mainnet,
                 pub_key_hash: hash_bytes,
             }),
-            magics::p2pkh::TESTNET => Ok(TransparentAddress::PayToPublicKeyHash {
+            magics::p2pkh::TESTNET => Ok(Address::PayToPublicKeyHash {
                 network: Network::Testnet,
                 pub_key_hash: hash_bytes,
             }),
@@ -185,7 +186,28 @@ impl ZcashDeserialize for TransparentAddress {
     }
 }
 
-impl TransparentAddress {
+impl Address {
+    /// The standard lock script that pays to this address, for use as a
+    /// transparent output's `pk_script`.
+    ///
+    /// [`Script::address`] recovers the address from the script.
+    pub fn lock_script(&self) -> Script {
+        let mut script = Vec::with_capacity(25);
+        match self {
+            Address::PayToScriptHash { script_hash, .. } => {
+                script.extend_from_slice(&[OP_HASH160, 20]);
+                script.extend_from_slice(&script_hash[..]);
+                script.push(OP_EQUAL);
+            }
+            Address::PayToPublicKeyHash { pub_key_hash, .. } => {
+                script.extend_from_slice(&[OP_DUP, OP_HASH160, 20]);
+                script.extend_from_slice(&pub_key_hash[..]);
+                script.extend_from_slice(&[OP_EQUALVERIFY, OP_CHECKSIG]);
+            }
+        }
+        Script(script.into())
+    }
+
     /// A hash of a transparent address payload, as used in
     /// transparent pay-to-script-hash and pay-to-publickey-hash
     /// addresses.
@@ -202,8 +224,8 @@ impl TransparentAddress {
     }
 }
 
-#[cfg(test)]
-impl TransparentAddress {
+#[cfg(any(test, feature = "proptest-impl"))]
+impl Address {
     fn p2pkh_strategy() -> impl Strategy<Value = Self> {
         (any::<Network>(), vec(any::<u8>(), 20))
             .prop_map(|(network, payload_bytes)| {
@@ -231,8 +253,8 @@ impl TransparentAddress {
     }
 }
 
-#[cfg(test)]
-impl Arbitrary for TransparentAddress {
+#[cfg(any(test, feature = "proptest-impl"))]
+impl Arbitrary for Address {
     type Parameters = ();
 
     fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
@@ -247,8 +269,6 @@ mod tests {
 
     use secp256k1::PublicKey;
 
-    use crate::types::Script;
-
     use super::*;
 
     #[test]
@@ -259,34 +279,54 @@ mod tests {
         ])
         .expect("A PublicKey from slice");
 
-        let t_addr = TransparentAddress::from(pub_key);
+        let t_addr = Address::from(pub_key);
 
         assert_eq!(format!("{}", t_addr), "t1bmMa1wJDFdbc2TiURQP5BbBz6jHjUBuHq");
     }
 
     #[test]
     fn empty_script() {
-        let script = Script(vec![0; 20]);
+        let script = Script(vec![0; 20].into());
 
-        let t_addr = TransparentAddress::from(script);
+        let t_addr = Address::from(script);
 
         assert_eq!(format!("{}", t_addr), "t3Y5pHwfgHbS6pDjj1HLuMFxhFFip1fcJ6g");
     }
 
     #[test]
     fn from_string() {
-        let t_addr: TransparentAddress = "t3Vz22vK5z2LcKEdg16Yv4FFneEL1zg9ojd".parse().unwrap();
+        let t_addr: Address = "t3Vz22vK5z2LcKEdg16Yv4FFneEL1zg9ojd".parse().unwrap();
 
         assert_eq!(format!("{}", t_addr), "t3Vz22vK5z2LcKEdg16Yv4FFneEL1zg9ojd");
     }
 
+    #[test]
+    fn bad_checksum() {
+        // The last character of a valid address, changed.
+        let result = "t3Vz22vK5z2LcKEdg16Yv4FFneEL1zg9oje".parse::<Address>();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn testnet_p2pkh() {
+        let t_addr = Address::PayToPublicKeyHash {
+            network: Network::Testnet,
+            pub_key_hash: [0; 20],
+        };
+        let encoded = format!("{}", t_addr);
+
+        assert!(encoded.starts_with("tm"));
+        assert_eq!(encoded.parse::<Address>().unwrap(), t_addr);
+    }
+
     #[test]
     fn debug() {
-        let t_addr: TransparentAddress = "t3Vz22vK5z2LcKEdg16Yv4FFneEL1zg9ojd".parse().unwrap();
+        let t_addr: Address = "t3Vz22vK5z2LcKEdg16Yv4FFneEL1zg9ojd".parse().unwrap();
 
         assert_eq!(
             format!("{:?}", t_addr),
-            "TransparentAddress { network: Mainnet, script_hash: \"7d46a730d31f97b1930d3368a967c309bd4d136a\" }"
+            "transparent::Address { network: Mainnet, script_hash: \"7d46a730d31f97b1930d3368a967c309bd4d136a\" }"
         );
     }
 }
@@ -295,14 +335,31 @@ mod tests {
 proptest! {
 
     #[test]
-    fn transparent_address_roundtrip(taddr in any::<TransparentAddress>()) {
+    fn transparent_address_roundtrip(taddr in any::<Address>()) {
 
         let mut data = Vec::new();
 
         taddr.zcash_serialize(&mut data).expect("t-addr should serialize");
 
-        let taddr2 = TransparentAddress::zcash_deserialize(&data[..]).expect("randomized t-addr should deserialize");
+        let taddr2 = Address::zcash_deserialize(&data[..]).expect("randomized t-addr should deserialize");
+
+        prop_assert_eq![taddr, taddr2];
+    }
+
+    #[test]
+    fn transparent_address_string_roundtrip(taddr in any::<Address>()) {
+        let taddr2: Address = taddr.to_string().parse().expect("t-addr should parse");
 
         prop_assert_eq![taddr, taddr2];
     }
+
+    #[test]
+    fn lock_script_roundtrip(taddr in any::<Address>()) {
+        let network = match taddr {
+            Address::PayToScriptHash { network, .. } => network,
+            Address::PayToPublicKeyHash { network, .. } => network,
+        };
+
+        prop_assert_eq![taddr.lock_script().address(network), Some(taddr)];
+    }
 }
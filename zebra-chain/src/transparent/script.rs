@@ -0,0 +1,354 @@
+#![allow(clippy::unit_arg)]
+use std::{fmt, io};
+
+use bytes::Bytes;
+
+use crate::{
+    serialization::{
+        CopyReader, SerializationError, SharedRead, WriteZcashExt, ZcashDeserialize,
+        ZcashDeserializeShared, ZcashSerialize,
+    },
+    Network,
+};
+
+/// The opcodes used by the standard script patterns.
+pub(super) mod opcodes {
+    pub const OP_PUSHDATA1: u8 = 0x4c;
+    pub const OP_PUSHDATA2: u8 = 0x4d;
+    pub const OP_PUSHDATA4: u8 = 0x4e;
+    pub const OP_DUP: u8 = 0x76;
+    pub const OP_EQUAL: u8 = 0x87;
+    pub const OP_EQUALVERIFY: u8 = 0x88;
+    pub const OP_HASH160: u8 = 0xa9;
+    pub const OP_CHECKSIG: u8 = 0xac;
+    pub const OP_CHECKSIGVERIFY: u8 = 0xad;
+    pub const OP_CHECKMULTISIG: u8 = 0xae;
+    pub const OP_CHECKMULTISIGVERIFY: u8 = 0xaf;
+}
+
+/// The largest number of public keys in a multisig, which is also the
+/// number of signature operations that legacy counting assigns to it.
+pub const MAX_PUBKEYS_PER_MULTISIG: u32 = 20;
+
+use opcodes::*;
+
+/// An encoding of a Bitcoin script.
+///
+/// Scripts deserialized from a [`SharedBytes`](crate::serialization::SharedBytes)
+/// reader share their bytes with its buffer.
+#[derive(Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Script(
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::serde_hex"))] pub Bytes,
+);
+
+impl ZcashSerialize for Script {
+    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        writer.write_compact_bytes(&self.0[..])
+    }
+}
+
+impl ZcashDeserializeShared for Script {
+    fn zcash_deserialize_shared<R: SharedRead>(mut reader: R) -> Result<Self, SerializationError> {
+        Ok(Script(reader.read_shared_compact_bytes()?))
+    }
+}
+
+impl ZcashDeserialize for Script {
+    fn zcash_deserialize<R: io::Read>(reader: R) -> Result<Self, SerializationError> {
+        Script::zcash_deserialize_shared(CopyReader(reader))
+    }
+}
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest::{arbitrary::Arbitrary, collection::vec, prelude::*};
+
+#[cfg(any(test, feature = "proptest-impl"))]
+impl Arbitrary for Script {
+    type Parameters = ();
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        vec(any::<u8>(), 0..100)
+            .prop_map(|bytes| Script(bytes.into()))
+            .boxed()
+    }
+
+    type Strategy = BoxedStrategy<Self>;
+}
+
+impl fmt::Debug for Script {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Script")
+            .field(&hex::encode(&self.0))
+            .finish()
+    }
+}
+
+/// A single step of a script: either an opcode, or data pushed by a push
+/// opcode.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Instruction<'a> {
+    /// Push `data` onto the stack.
+    ///
+    /// This includes the direct pushes `0x01..=0x4b`, and the
+    /// `OP_PUSHDATA` opcodes. `OP_0` is a push of empty data.
+    PushBytes(&'a [u8]),
+    /// Any other opcode.
+    Op(u8),
+}
+
+/// An error parsing the instructions in a [`Script`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ScriptError {
+    /// A push opcode claims more data than the rest of the script.
+    TruncatedPush {
+        /// The offset of the push opcode in the script.
+        offset: usize,
+    },
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptError::TruncatedPush { offset } => {
+                write!(
+                    f,
+                    "push at offset {} runs past the end of the script",
+                    offset
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+impl Script {
+    /// Iterate over the instructions in this script, without executing it.
+    ///
+    /// The iterator stops after the first error, because the remaining bytes
+    /// can't be split into instructions.
+    pub fn instructions(&self) -> impl Iterator<Item = Result<Instruction<'_>, ScriptError>> {
+        let bytes = &self.0[..];
+        let mut offset = 0;
+        let mut failed = false;
+        std::iter::from_fn(move || {
+            if failed || offset >= bytes.len() {
+                return None;
+            }
+            let result = next_instruction(bytes, offset);
+            match result {
+                Ok((instruction, next)) => {
+                    offset = next;
+                    Some(Ok(instruction))
+                }
+                Err(e) => {
+                    failed = true;
+                    Some(Err(e))
+                }
+            }
+        })
+    }
+
+    /// Returns the number of signature operations in this script, counted
+    /// without executing it, like `zcashd`'s legacy sigop count.
+    ///
+    /// Each multisig opcode counts as [`MAX_PUBKEYS_PER_MULTISIG`]
+    /// operations. Counting stops at the first push that runs past the end
+    /// of the script.
+    pub fn legacy_sigop_count(&self) -> u32 {
+        self.instructions()
+            .take_while(Result::is_ok)
+            .map(|instruction| match instruction {
+                Ok(Instruction::Op(OP_CHECKSIG)) | Ok(Instruction::Op(OP_CHECKSIGVERIFY)) => 1,
+                Ok(Instruction::Op(OP_CHECKMULTISIG))
+                | Ok(Instruction::Op(OP_CHECKMULTISIGVERIFY)) => MAX_PUBKEYS_PER_MULTISIG,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// Returns true if this is a standard pay-to-public-key-hash script:
+    /// `OP_DUP OP_HASH160 <20 bytes> OP_EQUALVERIFY OP_CHECKSIG`.
+    pub fn is_p2pkh(&self) -> bool {
+        self.p2pkh_hash().is_some()
+    }
+
+    /// Returns true if this is a standard pay-to-script-hash script:
+    /// `OP_HASH160 <20 bytes> OP_EQUAL`.
+    pub fn is_p2sh(&self) -> bool {
+        self.p2sh_hash().is_some()
+    }
+
+    /// Returns the transparent address that receives outputs with this
+    /// lock script on `network`, if it is a standard P2PKH or P2SH script.
+    ///
+    /// Other scripts don't have an address encoding.
+    pub fn address(&self, network: Network) -> Option<Address> {
+        if let Some(pub_key_hash) = self.p2pkh_hash() {
+            Some(Address::PayToPublicKeyHash {
+                network,
+                pub_key_hash,
+            })
+        } else if let Some(script_hash) = self.p2sh_hash() {
+            Some(Address::PayToScriptHash {
+                network,
+                script_hash,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn p2pkh_hash(&self) -> Option<[u8; 20]> {
+        match &self.0[..] {
+            [OP_DUP, OP_HASH160, 0x14, hash @ .., OP_EQUALVERIFY, OP_CHECKSIG]
+                if hash.len() == 20 =>
+            {
+                Some(hash_bytes(hash))
+            }
+            _ => None,
+        }
+    }
+
+    fn p2sh_hash(&self) -> Option<[u8; 20]> {
+        match &self.0[..] {
+            [OP_HASH160, 0x14, hash @ .., OP_EQUAL] if hash.len() == 20 => Some(hash_bytes(hash)),
+            _ => None,
+        }
+    }
+}
+
+fn hash_bytes(hash: &[u8]) -> [u8; 20] {
+    let mut bytes = [0; 20];
+    bytes.copy_from_slice(hash);
+    bytes
+}
+
+/// Parse the instruction at `offset`, returning it with the offset of the
+/// next instruction.
+fn next_instruction(bytes: &[u8], offset: usize) -> Result<(Instruction<'_>, usize), ScriptError> {
+    let truncated = ScriptError::TruncatedPush { offset };
+    let read_len = |start: usize, width: usize| -> Result<usize, ScriptError> {
+        let len_bytes = bytes.get(start..start + width).ok_or(truncated)?;
+        Ok(len_bytes
+            .iter()
+            .rev()
+            .fold(0usize, |len, &byte| (len << 8) | byte as usize))
+    };
+
+    let opcode = bytes[offset];
+    let (data_start, data_len) = match opcode {
+        0x00..=0x4b => (offset + 1, opcode as usize),
+        OP_PUSHDATA1 => (offset + 2, read_len(offset + 1, 1)?),
+        OP_PUSHDATA2 => (offset + 3, read_len(offset + 1, 2)?),
+        OP_PUSHDATA4 => (offset + 5, read_len(offset + 1, 4)?),
+        _ => return Ok((Instruction::Op(opcode), offset + 1)),
+    };
+    let data_end = data_start.checked_add(data_len).ok_or(truncated)?;
+    let data = bytes.get(data_start..data_end).ok_or(truncated)?;
+    Ok((Instruction::PushBytes(data), data_end))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn standard_scripts_have_addresses() {
+        let mut p2pkh = vec![OP_DUP, OP_HASH160, 0x14];
+        p2pkh.extend_from_slice(&[7; 20]);
+        p2pkh.extend_from_slice(&[OP_EQUALVERIFY, OP_CHECKSIG]);
+        let p2pkh = Script(p2pkh.into());
+        assert!(p2pkh.is_p2pkh() && !p2pkh.is_p2sh());
+        assert_eq!(
+            p2pkh.address(Network::Mainnet),
+            Some(Address::PayToPublicKeyHash {
+                network: Network::Mainnet,
+                pub_key_hash: [7; 20],
+            })
+        );
+
+        let mut p2sh = vec![OP_HASH160, 0x14];
+        p2sh.extend_from_slice(&[9; 20]);
+        p2sh.push(OP_EQUAL);
+        let p2sh = Script(p2sh.into());
+        assert!(p2sh.is_p2sh() && !p2sh.is_p2pkh());
+        assert_eq!(
+            p2sh.address(Network::Testnet),
+            Some(Address::PayToScriptHash {
+                network: Network::Testnet,
+                script_hash: [9; 20],
+            })
+        );
+
+        // A P2SH script with a short hash isn't standard.
+        let short = Script(vec![OP_HASH160, 0x13, 0, OP_EQUAL].into());
+        assert_eq!(short.address(Network::Mainnet), None);
+    }
+
+    #[test]
+    fn instructions_split_pushes_and_opcodes() {
+        let script = Script(vec![0x00, 0x02, 1, 2, OP_PUSHDATA1, 0x01, 3, OP_CHECKSIG].into());
+        let instructions: Vec<_> = script.instructions().collect();
+        assert_eq!(
+            instructions,
+            vec![
+                Ok(Instruction::PushBytes(&[])),
+                Ok(Instruction::PushBytes(&[1, 2])),
+                Ok(Instruction::PushBytes(&[3])),
+                Ok(Instruction::Op(OP_CHECKSIG)),
+            ]
+        );
+
+        let truncated = Script(vec![OP_CHECKSIG, OP_PUSHDATA2, 0x05, 0x00, 1].into());
+        let instructions: Vec<_> = truncated.instructions().collect();
+        assert_eq!(
+            instructions,
+            vec![
+                Ok(Instruction::Op(OP_CHECKSIG)),
+                Err(ScriptError::TruncatedPush { offset: 1 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn legacy_sigops_are_counted() {
+        let script = Script(
+            vec![
+                OP_CHECKSIG,
+                0x01,
+                OP_CHECKSIG,
+                OP_CHECKSIGVERIFY,
+                OP_CHECKMULTISIG,
+                OP_CHECKMULTISIGVERIFY,
+            ]
+            .into(),
+        );
+        // The pushed byte is data, not an opcode.
+        assert_eq!(
+            script.legacy_sigop_count(),
+            2 + 2 * MAX_PUBKEYS_PER_MULTISIG
+        );
+
+        let truncated = Script(vec![OP_CHECKSIG, OP_PUSHDATA1, 0x02, OP_CHECKSIG].into());
+        assert_eq!(truncated.legacy_sigop_count(), 1);
+    }
+
+    proptest! {
+        #[test]
+        fn script_roundtrip(script in any::<Script>()) {
+            let mut bytes = Cursor::new(Vec::new());
+            script.zcash_serialize(&mut bytes)?;
+
+            bytes.set_position(0);
+            let other_script = Script::zcash_deserialize(&mut bytes)?;
+
+            prop_assert_eq![script, other_script];
+        }
+    }
+}
@@ -0,0 +1,391 @@
+//! Balances of the transparent and shielded value pools.
+//!
+//! A transaction's value balance is the value it removes from each pool, so
+//! removing value is positive, and adding value is negative. The chain value
+//! pools are the total value in each pool, which must never be negative.
+
+use std::{collections::HashMap, io};
+
+use thiserror::Error;
+
+use crate::{
+    amount::{self, Amount, Constraint, NegativeAllowed, NonNegative},
+    serialization::{SerializationError, ZcashDeserialize, ZcashSerialize},
+    transaction::{OutPoint, Transaction, TransparentInput, TransparentOutput},
+};
+
+/// The value in, or the value flowing out of, each value pool.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct ValueBalance<C> {
+    transparent: Amount<C>,
+    sprout: Amount<C>,
+    sapling: Amount<C>,
+    orchard: Amount<C>,
+}
+
+/// An error in a [`ValueBalance`] calculation, with the pool it happened in.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ValueBalanceError {
+    /// The transparent pool calculation failed.
+    #[error("transparent value balance error: {0}")]
+    Transparent(amount::Error),
+    /// The Sprout pool calculation failed.
+    #[error("sprout value balance error: {0}")]
+    Sprout(amount::Error),
+    /// The Sapling pool calculation failed.
+    #[error("sapling value balance error: {0}")]
+    Sapling(amount::Error),
+    /// The Orchard pool calculation failed.
+    #[error("orchard value balance error: {0}")]
+    Orchard(amount::Error),
+    /// A transparent output spent by the transaction wasn't supplied.
+    #[error("spent output {0:?} is missing")]
+    MissingUtxo(OutPoint),
+}
+
+impl<C: Constraint + Copy> ValueBalance<C> {
+    /// A value balance with no value in any pool.
+    pub fn zero() -> Self {
+        ValueBalance {
+            transparent: Amount::zero(),
+            sprout: Amount::zero(),
+            sapling: Amount::zero(),
+            orchard: Amount::zero(),
+        }
+    }
+
+    /// A value balance with only a transparent `amount`.
+    pub fn from_transparent_amount(amount: Amount<C>) -> Self {
+        ValueBalance {
+            transparent: amount,
+            ..ValueBalance::zero()
+        }
+    }
+
+    /// A value balance with only a Sprout `amount`.
+    pub fn from_sprout_amount(amount: Amount<C>) -> Self {
+        ValueBalance {
+            sprout: amount,
+            ..ValueBalance::zero()
+        }
+    }
+
+    /// A value balance with only a Sapling `amount`.
+    pub fn from_sapling_amount(amount: Amount<C>) -> Self {
+        ValueBalance {
+            sapling: amount,
+            ..ValueBalance::zero()
+        }
+    }
+
+    /// A value balance with only an Orchard `amount`.
+    pub fn from_orchard_amount(amount: Amount<C>) -> Self {
+        ValueBalance {
+            orchard: amount,
+            ..ValueBalance::zero()
+        }
+    }
+
+    /// Get the transparent amount.
+    pub fn transparent_amount(&self) -> Amount<C> {
+        self.transparent
+    }
+
+    /// Get the Sprout amount.
+    pub fn sprout_amount(&self) -> Amount<C> {
+        self.sprout
+    }
+
+    /// Get the Sapling amount.
+    pub fn sapling_amount(&self) -> Amount<C> {
+        self.sapling
+    }
+
+    /// Get the Orchard amount.
+    pub fn orchard_amount(&self) -> Amount<C> {
+        self.orchard
+    }
+
+    /// Convert every amount in this balance to a different constraint.
+    pub fn constrain<C2: Constraint>(self) -> Result<ValueBalance<C2>, ValueBalanceError> {
+        use ValueBalanceError::*;
+        Ok(ValueBalance {
+            transparent: self.transparent.constrain().map_err(Transparent)?,
+            sprout: self.sprout.constrain().map_err(Sprout)?,
+            sapling: self.sapling.constrain().map_err(Sapling)?,
+            orchard: self.orchard.constrain().map_err(Orchard)?,
+        })
+    }
+}
+
+impl<C: Constraint + Copy> Default for ValueBalance<C> {
+    fn default() -> Self {
+        ValueBalance::zero()
+    }
+}
+
+impl ValueBalance<NonNegative> {
+    /// Update these chain value pools with the value balance of a
+    /// transaction, which removes its `tx_balance` from each pool.
+    ///
+    /// Returns an error if any pool would become negative, or overflow.
+    pub fn add_transaction(
+        self,
+        tx_balance: ValueBalance<NegativeAllowed>,
+    ) -> Result<ValueBalance<NonNegative>, ValueBalanceError> {
+        (self.constrain::<NegativeAllowed>()? - tx_balance)?.constrain()
+    }
+}
+
+/// Chain value pools are serialized as each pool's amount, in the order
+/// transparent, Sprout, Sapling, Orchard, so they can be stored with each
+/// block.
+impl ZcashSerialize for ValueBalance<NonNegative> {
+    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        self.transparent.zcash_serialize(&mut writer)?;
+        self.sprout.zcash_serialize(&mut writer)?;
+        self.sapling.zcash_serialize(&mut writer)?;
+        self.orchard.zcash_serialize(&mut writer)
+    }
+}
+
+impl ZcashDeserialize for ValueBalance<NonNegative> {
+    fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
+        Ok(ValueBalance {
+            transparent: Amount::zcash_deserialize(&mut reader)?,
+            sprout: Amount::zcash_deserialize(&mut reader)?,
+            sapling: Amount::zcash_deserialize(&mut reader)?,
+            orchard: Amount::zcash_deserialize(&mut reader)?,
+        })
+    }
+}
+
+impl<C: Constraint + Copy> std::ops::Add for ValueBalance<C> {
+    type Output = Result<ValueBalance<C>, ValueBalanceError>;
+
+    fn add(self, rhs: ValueBalance<C>) -> Self::Output {
+        use ValueBalanceError::*;
+        Ok(ValueBalance {
+            transparent: (self.transparent + rhs.transparent).map_err(Transparent)?,
+            sprout: (self.sprout + rhs.sprout).map_err(Sprout)?,
+            sapling: (self.sapling + rhs.sapling).map_err(Sapling)?,
+            orchard: (self.orchard + rhs.orchard).map_err(Orchard)?,
+        })
+    }
+}
+
+impl<C: Constraint + Copy> std::ops::Sub for ValueBalance<C> {
+    type Output = Result<ValueBalance<C>, ValueBalanceError>;
+
+    fn sub(self, rhs: ValueBalance<C>) -> Self::Output {
+        use ValueBalanceError::*;
+        Ok(ValueBalance {
+            transparent: (self.transparent - rhs.transparent).map_err(Transparent)?,
+            sprout: (self.sprout - rhs.sprout).map_err(Sprout)?,
+            sapling: (self.sapling - rhs.sapling).map_err(Sapling)?,
+            orchard: (self.orchard - rhs.orchard).map_err(Orchard)?,
+        })
+    }
+}
+
+impl Transaction {
+    /// Compute the value balance of this transaction, which is the value it
+    /// removes from each pool.
+    ///
+    /// `utxos` must contain every transparent output spent by this
+    /// transaction, otherwise the balance is an error.
+    ///
+    /// The sum of the pools' balances is the transaction fee, except for
+    /// coinbase transactions, which create new value.
+    pub fn value_balance(
+        &self,
+        utxos: &HashMap<OutPoint, TransparentOutput>,
+    ) -> Result<ValueBalance<NegativeAllowed>, ValueBalanceError> {
+        use ValueBalanceError::*;
+
+        let spent_values = self
+            .inputs()
+            .filter_map(|input| match input {
+                TransparentInput::PrevOut { outpoint, .. } => Some(
+                    utxos
+                        .get(outpoint)
+                        .map(|output| output.value)
+                        .ok_or(MissingUtxo(*outpoint)),
+                ),
+                TransparentInput::Coinbase { .. } => None,
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let spent: amount::Result<Amount<NonNegative>> = spent_values.into_iter().sum();
+        let created: amount::Result<Amount<NonNegative>> =
+            self.outputs().map(|output| output.value).sum();
+        let transparent = spent
+            .and_then(|spent| spent.constrain::<NegativeAllowed>())
+            .and_then(|spent| spent - created?.constrain()?)
+            .map_err(Transparent)?;
+
+        Ok(ValueBalance {
+            transparent,
+            ..self.shielded_value_balance()?
+        })
+    }
+
+    /// Compute the value this transaction removes from each shielded pool,
+    /// which doesn't depend on the transparent outputs it spends.
+    ///
+    /// The transparent amount is zero.
+    pub fn shielded_value_balance(
+        &self,
+    ) -> Result<ValueBalance<NegativeAllowed>, ValueBalanceError> {
+        use ValueBalanceError::*;
+
+        let sprout = self.sprout_value_balance().map_err(Sprout)?;
+
+        let (sapling, orchard) = match self {
+            Transaction::V4 { value_balance, .. } => (*value_balance, Amount::zero()),
+            Transaction::V5 {
+                sapling_value_balance,
+                orchard_shielded_data,
+                ..
+            } => (
+                *sapling_value_balance,
+                orchard_shielded_data
+                    .as_ref()
+                    .map(|sd| sd.value_balance)
+                    .unwrap_or_else(Amount::zero),
+            ),
+            _ => (Amount::zero(), Amount::zero()),
+        };
+
+        Ok(ValueBalance {
+            transparent: Amount::zero(),
+            sprout,
+            sapling,
+            orchard,
+        })
+    }
+
+    /// The value this transaction's JoinSplits remove from the Sprout pool.
+    ///
+    /// Returns an error if the JoinSplit values are out of range.
+    pub fn sprout_value_balance(&self) -> amount::Result<Amount> {
+        let vpubs: Vec<(Amount<NonNegative>, Amount<NonNegative>)> = match self {
+            Transaction::V2 { joinsplit_data, .. } | Transaction::V3 { joinsplit_data, .. } => {
+                joinsplit_data
+                    .iter()
+                    .flat_map(|jsd| jsd.joinsplits())
+                    .map(|js| (js.vpub_old, js.vpub_new))
+                    .collect()
+            }
+            Transaction::V4 { joinsplit_data, .. } => joinsplit_data
+                .iter()
+                .flat_map(|jsd| jsd.joinsplits())
+                .map(|js| (js.vpub_old, js.vpub_new))
+                .collect(),
+            Transaction::V1 { .. } | Transaction::V5 { .. } => Vec::new(),
+        };
+
+        vpubs
+            .into_iter()
+            .try_fold(Amount::zero(), |balance, (vpub_old, vpub_new)| {
+                let vpub_new: Amount = vpub_new.constrain()?;
+                let vpub_old: Amount = vpub_old.constrain()?;
+                (balance + vpub_new) - vpub_old
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::*;
+
+    fn amount<C: Constraint>(value: i64) -> Amount<C> {
+        value.try_into().unwrap()
+    }
+
+    #[test]
+    fn chain_pools_cannot_go_negative() {
+        let pools: ValueBalance<NonNegative> = ValueBalance::from_sapling_amount(amount(10));
+
+        // Removing value from the Sapling pool is fine, until it runs out.
+        let spend = ValueBalance::from_sapling_amount(amount(4));
+        let pools = pools.add_transaction(spend).unwrap();
+        assert_eq!(pools.sapling_amount(), amount(6));
+        assert_eq!(
+            pools
+                .add_transaction(ValueBalance::from_sapling_amount(amount(7)))
+                .map_err(|e| match e {
+                    ValueBalanceError::Sapling(_) => "sapling",
+                    _ => "other pool",
+                }),
+            Err("sapling")
+        );
+
+        // Adding value to a pool is a negative balance.
+        let shield = ValueBalance::from_orchard_amount(amount(-3));
+        assert_eq!(
+            pools.add_transaction(shield).unwrap().orchard_amount(),
+            amount(3)
+        );
+    }
+
+    #[test]
+    fn chain_pools_round_trip() {
+        let pools = (ValueBalance::from_transparent_amount(amount(1))
+            + ValueBalance::from_orchard_amount(amount(4)))
+        .unwrap();
+        let mut bytes = Vec::new();
+        pools.zcash_serialize(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 32);
+        assert_eq!(
+            ValueBalance::<NonNegative>::zcash_deserialize(&bytes[..]).unwrap(),
+            pools
+        );
+    }
+
+    #[test]
+    fn transaction_value_balance_sums_to_the_fee() {
+        use crate::{block, transaction};
+        use crate::{transaction::LockTime, transparent::Script};
+
+        let outpoint = OutPoint {
+            hash: transaction::Hash([1; 32]),
+            index: 0,
+        };
+        let output = |value| TransparentOutput {
+            value: amount(value),
+            pk_script: Script(vec![].into()),
+        };
+        let mut utxos = HashMap::new();
+        utxos.insert(outpoint, output(1_000));
+
+        // Spend 1000 transparently, shield 300 into Sapling, and pay a fee of
+        // 100.
+        let tx = Transaction::V4 {
+            inputs: vec![TransparentInput::PrevOut {
+                outpoint,
+                script: Script(vec![].into()),
+                sequence: 0xffff_ffff,
+            }],
+            outputs: vec![output(600)],
+            lock_time: LockTime::Height(block::Height(0)),
+            expiry_height: block::Height(0),
+            value_balance: amount(-300),
+            shielded_data: None,
+            joinsplit_data: None,
+        };
+        let balance = tx.value_balance(&utxos).unwrap();
+        assert_eq!(balance.transparent_amount(), amount(400));
+        assert_eq!(balance.sapling_amount(), amount(-300));
+        assert_eq!(
+            balance.transparent_amount() + balance.sapling_amount(),
+            Ok(amount(100))
+        );
+
+        assert_eq!(
+            tx.value_balance(&HashMap::new()),
+            Err(ValueBalanceError::MissingUtxo(outpoint))
+        );
+    }
+}
@@ -0,0 +1,4 @@
+//! Proof-of-work implementation.
+
+pub mod difficulty;
+pub mod equihash;
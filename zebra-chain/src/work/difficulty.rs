@@ -0,0 +1,588 @@
+//! Block difficulty data structures and calculations.
+//!
+//! The block difficulty "target threshold" is stored in the block header as a
+//! 32-bit [`CompactDifficulty`]. The `block::Hash` must be less than or equal
+//! to the [`ExpandedDifficulty`] threshold, when represented as a 256-bit
+//! integer in little-endian order.
+//!
+//! The block work is used to find the chain with the greatest total work. Each
+//! block's work value depends on the fixed threshold in the block header, not
+//! the actual work represented by the block header hash.
+#![allow(clippy::unit_arg)]
+
+use std::{cmp::Ordering, fmt};
+
+use primitive_types::U256;
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest_derive::Arbitrary;
+
+use crate::{block, Network};
+
+/// A 32-bit "compact bits" value, which represents the difficulty threshold
+/// for a block header.
+///
+/// Used for:
+///   - checking the `bits` value in the block header,
+///   - calculating the 256-bit `ExpandedDifficulty` threshold, for comparison
+///     with the block header hash, and
+///   - calculating the block work.
+///
+/// This is a floating-point encoding, with a 24-bit signed mantissa, an 8-bit
+/// exponent, an offset of 3, and a radix of 256:
+/// `mantissa * 256^(exponent - 3)`.
+///
+/// [Bitcoin-nBits](https://bitcoin.org/en/developer-reference#target-nbits)
+#[derive(Clone, Copy, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(any(test, feature = "proptest-impl"), derive(Arbitrary))]
+pub struct CompactDifficulty(pub u32);
+
+impl fmt::Debug for CompactDifficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CompactDifficulty")
+            .field(&format_args!("{:#010x}", self.0))
+            .finish()
+    }
+}
+
+/// A 256-bit unsigned "expanded difficulty" value.
+///
+/// Used as a target threshold for the difficulty of a `block::Hash`.
+///
+/// Details:
+///
+/// The precise bit pattern of an `ExpandedDifficulty` value is
+/// consensus-critical, because it is compared with the `block::Hash`.
+///
+/// Note that each `CompactDifficulty` value represents a range of
+/// `ExpandedDifficulty` values, because the precision of the
+/// floating-point format requires rounding on conversion.
+///
+/// Therefore, consensus-critical code must perform the specified
+/// conversions to `CompactDifficulty`, even if the original
+/// `ExpandedDifficulty` values are known.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct ExpandedDifficulty(U256);
+
+impl fmt::Debug for ExpandedDifficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut bytes = [0; 32];
+        self.0.to_big_endian(&mut bytes);
+        f.debug_tuple("ExpandedDifficulty")
+            .field(&hex::encode(&bytes))
+            .finish()
+    }
+}
+
+/// A 128-bit unsigned "Work" value.
+///
+/// Used to calculate the total work for each chain of blocks.
+///
+/// Details:
+///
+/// The relative value of `Work` is consensus-critical, because it is used to
+/// choose the best chain. But its precise value and bit pattern are not
+/// consensus-critical.
+///
+/// We calculate work values according to the Zcash specification, but store
+/// them as u128, rather than the implied u256. We don't expect the total chain
+/// work to ever exceed 2^128, but sums of work saturate, rather than
+/// overflowing.
+#[derive(Clone, Copy, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Work(u128);
+
+impl Work {
+    /// Returns the inner `u128` value.
+    pub fn as_u128(self) -> u128 {
+        self.0
+    }
+}
+
+impl fmt::Debug for Work {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Work")
+            .field(&format_args!("{:#x}", self.0))
+            .field(&self.0)
+            .finish()
+    }
+}
+
+impl CompactDifficulty {
+    /// CompactDifficulty exponent offset.
+    const OFFSET: i32 = 3;
+
+    /// CompactDifficulty floating-point precision.
+    const PRECISION: u32 = 24;
+
+    /// CompactDifficulty sign bit, part of the signed mantissa.
+    const SIGN_BIT: u32 = 1 << (CompactDifficulty::PRECISION - 1);
+
+    /// CompactDifficulty unsigned mantissa mask.
+    ///
+    /// Also the maximum unsigned mantissa value.
+    const UNSIGNED_MANTISSA_MASK: u32 = CompactDifficulty::SIGN_BIT - 1;
+
+    /// Calculate the ExpandedDifficulty for a compact representation.
+    ///
+    /// See `ToTarget()` in the Zcash Specification, and `CheckProofOfWork()` in
+    /// zcashd.
+    ///
+    /// Returns None for negative, zero, and overflow values. (zcashd rejects
+    /// these values, before comparing the hash.)
+    pub fn to_expanded(&self) -> Option<ExpandedDifficulty> {
+        // The constants for this floating-point representation.
+        // Alias the struct constants here, so the code is easier to read.
+        const OFFSET: i32 = CompactDifficulty::OFFSET;
+        const PRECISION: u32 = CompactDifficulty::PRECISION;
+        const SIGN_BIT: u32 = CompactDifficulty::SIGN_BIT;
+        const UNSIGNED_MANTISSA_MASK: u32 = CompactDifficulty::UNSIGNED_MANTISSA_MASK;
+
+        // Negative values in this floating-point representation.
+        // 0 if (x & 2^23 == 2^23)
+        // zcashd rejects negative values without comparing the hash.
+        if self.0 & SIGN_BIT == SIGN_BIT {
+            return None;
+        }
+
+        // The components of the result
+        // The fractional part of the floating-point number
+        // x & (2^23 - 1)
+        let mantissa = self.0 & UNSIGNED_MANTISSA_MASK;
+
+        // The exponent for the multiplier in the floating-point number
+        // 256^(floor(x/(2^24)) - 3)
+        // The i32 conversion is safe, because we've just divided self by 2^24.
+        let exponent = ((self.0 >> PRECISION) as i32) - OFFSET;
+
+        // Normalise the mantissa and exponent before multiplying.
+        //
+        // zcashd rejects overflows, but accepts underflows, as long as the
+        // result is not zero.
+        let (mantissa, exponent) = match (mantissa, exponent) {
+            // Avoid overflows and underflows
+            (0, _) => return None,
+            (mantissa, exponent) if exponent < 0 => {
+                // Shift the mantissa right, rather than shifting the result
+                // left by a negative amount.
+                let shift = (-exponent) as u32 * 8;
+                if shift >= PRECISION {
+                    return None;
+                }
+                (mantissa >> shift, 0)
+            }
+            (mantissa, exponent) => (mantissa, exponent as u32),
+        };
+
+        // The result is zero after an underflow
+        if mantissa == 0 {
+            return None;
+        }
+
+        // The result overflows if the mantissa's highest bit is shifted past
+        // bit 255.
+        let mantissa_bits = 32 - mantissa.leading_zeros();
+        if mantissa_bits + exponent * 8 > 256 {
+            return None;
+        }
+
+        // The exponent has a radix of 256, so each step is 8 bits.
+        let result = U256::from(mantissa) << (exponent as usize * 8);
+        Some(ExpandedDifficulty(result))
+    }
+
+    /// Calculate the Work for a compact representation.
+    ///
+    /// See `Definition of Work` in the Zcash Specification, and
+    /// `GetBlockProof()` in zcashd.
+    ///
+    /// Returns None if the corresponding ExpandedDifficulty is None.
+    /// Also returns None on Work overflow, which should be impossible on a
+    /// valid chain.
+    pub fn to_work(&self) -> Option<Work> {
+        let expanded = self.to_expanded()?;
+        Work::try_from_expanded(expanded)
+    }
+}
+
+impl Work {
+    /// Calculate the Work for an ExpandedDifficulty.
+    ///
+    /// Returns None on Work overflow.
+    fn try_from_expanded(expanded: ExpandedDifficulty) -> Option<Work> {
+        // Work is calculated as 2^256 / (target + 1), but 2^256 doesn't fit
+        // in a U256. Since target + 1 is at least 2, this is equal to
+        // (2^256 - target - 1) / (target + 1) + 1.
+        //
+        // `to_expanded` never returns U256::MAX, so `+ 1` can't overflow.
+        let target = expanded.0;
+        let result = (!target / (target + 1)) + 1;
+
+        if result <= U256::from(u128::max_value()) {
+            Some(Work(result.as_u128()))
+        } else {
+            None
+        }
+    }
+}
+
+impl ExpandedDifficulty {
+    /// Returns the easiest target difficulty allowed on `network`.
+    ///
+    /// See `PoWLimit` in the Zcash specification.
+    pub fn target_difficulty_limit(network: Network) -> ExpandedDifficulty {
+        let limit = match network {
+            // 2^243 - 1
+            Network::Mainnet => (U256::one() << 243) - 1,
+            // 2^251 - 1
+            Network::Testnet => (U256::one() << 251) - 1,
+            // 0x0f0f...0f
+            Network::Regtest => U256::from_big_endian(&[0x0f; 32]),
+        };
+
+        ExpandedDifficulty(limit)
+    }
+
+    /// Returns the value of `hash`, as a 256-bit little-endian integer.
+    ///
+    /// Zcash interprets block hashes in internal byte order as little-endian
+    /// integers for their difficulty comparisons.
+    pub fn from_hash(hash: &block::Hash) -> ExpandedDifficulty {
+        ExpandedDifficulty(U256::from_little_endian(&hash.0))
+    }
+
+    /// Returns the threshold as big-endian bytes, which is the order that
+    /// `zcashd` displays targets in.
+    pub fn bytes_in_display_order(&self) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        self.0.to_big_endian(&mut bytes);
+        bytes
+    }
+
+    /// Calculate the CompactDifficulty for an expanded difficulty.
+    ///
+    /// See `ToCompact()` in the Zcash Specification, and `GetCompact()` in
+    /// zcashd.
+    ///
+    /// The compact encoding only has 23 bits of precision, so the conversion
+    /// rounds the threshold down. The difficulty adjustment always converts
+    /// its result to a `CompactDifficulty`, so this rounding is
+    /// consensus-critical.
+    pub fn to_compact(&self) -> CompactDifficulty {
+        const OFFSET: u32 = CompactDifficulty::OFFSET as u32;
+        const PRECISION: u32 = CompactDifficulty::PRECISION;
+        const SIGN_BIT: u32 = CompactDifficulty::SIGN_BIT;
+
+        // The number of bytes in the threshold, which becomes the exponent.
+        let mut size = (self.0.bits() as u32 + 7) / 8;
+
+        // Keep the most significant 3 bytes as the mantissa.
+        let mut mantissa = if size <= OFFSET {
+            (self.0.low_u64() << (8 * (OFFSET - size))) as u32
+        } else {
+            (self.0 >> (8 * (size - OFFSET) as usize)).low_u64() as u32
+        };
+
+        // The mantissa is signed, so a set high bit needs an extra byte.
+        if mantissa & SIGN_BIT == SIGN_BIT {
+            mantissa >>= 8;
+            size += 1;
+        }
+
+        CompactDifficulty(mantissa | (size << PRECISION))
+    }
+}
+
+impl PartialEq<ExpandedDifficulty> for block::Hash {
+    fn eq(&self, other: &ExpandedDifficulty) -> bool {
+        ExpandedDifficulty::from_hash(self) == *other
+    }
+}
+
+impl PartialOrd<ExpandedDifficulty> for block::Hash {
+    /// `block::Hash`es are compared with `ExpandedDifficulty` thresholds by
+    /// converting the hash to a 256-bit integer in little-endian order.
+    ///
+    /// A block's proof of work is valid if its hash is less than or equal to
+    /// its difficulty threshold.
+    fn partial_cmp(&self, other: &ExpandedDifficulty) -> Option<Ordering> {
+        ExpandedDifficulty::from_hash(self).partial_cmp(other)
+    }
+}
+
+impl PartialEq<block::Hash> for ExpandedDifficulty {
+    fn eq(&self, other: &block::Hash) -> bool {
+        other.eq(self)
+    }
+}
+
+impl PartialOrd<block::Hash> for ExpandedDifficulty {
+    fn partial_cmp(&self, other: &block::Hash) -> Option<Ordering> {
+        other.partial_cmp(self).map(Ordering::reverse)
+    }
+}
+
+impl std::ops::Add for Work {
+    type Output = Work;
+
+    fn add(self, rhs: Work) -> Work {
+        Work(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl std::ops::AddAssign for Work {
+    fn add_assign(&mut self, rhs: Work) {
+        *self = *self + rhs;
+    }
+}
+
+impl std::iter::Sum for Work {
+    fn sum<I: Iterator<Item = Work>>(iter: I) -> Self {
+        iter.fold(Work::default(), |acc, work| acc + work)
+    }
+}
+
+// Arithmetic for the difficulty adjustment, which averages and scales
+// thresholds. Like `Work`, these operations saturate rather than overflowing.
+
+impl std::ops::Add for ExpandedDifficulty {
+    type Output = ExpandedDifficulty;
+
+    fn add(self, rhs: ExpandedDifficulty) -> ExpandedDifficulty {
+        ExpandedDifficulty(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl std::iter::Sum for ExpandedDifficulty {
+    fn sum<I: Iterator<Item = ExpandedDifficulty>>(iter: I) -> Self {
+        iter.fold(ExpandedDifficulty(U256::zero()), |acc, threshold| {
+            acc + threshold
+        })
+    }
+}
+
+impl std::ops::Mul<u64> for ExpandedDifficulty {
+    type Output = ExpandedDifficulty;
+
+    fn mul(self, rhs: u64) -> ExpandedDifficulty {
+        ExpandedDifficulty(self.0.saturating_mul(U256::from(rhs)))
+    }
+}
+
+impl std::ops::Div<u64> for ExpandedDifficulty {
+    type Output = ExpandedDifficulty;
+
+    /// Integer division, rounding down.
+    ///
+    /// # Panics
+    ///
+    /// If `rhs` is zero.
+    fn div(self, rhs: u64) -> ExpandedDifficulty {
+        ExpandedDifficulty(self.0 / U256::from(rhs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Returns the expanded difficulty of `mantissa * 256^exponent`.
+    fn expanded(mantissa: u32, exponent: usize) -> ExpandedDifficulty {
+        ExpandedDifficulty(U256::from(mantissa) << (8 * exponent))
+    }
+
+    #[test]
+    fn compact_invalid_values() {
+        // Zero, positive and negative
+        assert_eq!(CompactDifficulty(0).to_expanded(), None);
+        assert_eq!(CompactDifficulty(0x0080_0000).to_expanded(), None);
+        assert_eq!(CompactDifficulty(0x2000_0000).to_expanded(), None);
+
+        // Negative
+        assert_eq!(CompactDifficulty(0x0492_3456).to_expanded(), None);
+        assert_eq!(CompactDifficulty(0x01fe_dcba).to_expanded(), None);
+
+        // Underflow to zero
+        assert_eq!(CompactDifficulty(0x0100_3456).to_expanded(), None);
+        assert_eq!(CompactDifficulty(0x0001_2345).to_expanded(), None);
+
+        // Overflow
+        assert_eq!(CompactDifficulty(0x2112_3456).to_expanded(), None);
+        assert_eq!(CompactDifficulty(0xff12_3456).to_expanded(), None);
+        assert_eq!(CompactDifficulty(0x2112_3456).to_work(), None);
+    }
+
+    #[test]
+    fn compact_expansion() {
+        // Test vectors from Bitcoin's arith_uint256_tests.cpp
+        assert_eq!(
+            CompactDifficulty(0x0112_3456).to_expanded(),
+            Some(expanded(0x12, 0))
+        );
+        assert_eq!(
+            CompactDifficulty(0x0200_8000).to_expanded(),
+            Some(expanded(0x80, 0))
+        );
+        assert_eq!(
+            CompactDifficulty(0x0500_9234).to_expanded(),
+            Some(expanded(0x9234, 2))
+        );
+        assert_eq!(
+            CompactDifficulty(0x0412_3456).to_expanded(),
+            Some(expanded(0x12_3456, 1))
+        );
+
+        // The largest exponent that doesn't overflow
+        assert_eq!(
+            CompactDifficulty(0x2012_3456).to_expanded(),
+            Some(expanded(0x12_3456, 29))
+        );
+
+        // The Bitcoin and Zcash genesis blocks
+        assert_eq!(
+            CompactDifficulty(0x1d00_ffff).to_expanded(),
+            Some(expanded(0xffff, 26))
+        );
+        assert_eq!(
+            CompactDifficulty(0x1f07_ffff).to_expanded(),
+            Some(expanded(0x07_ffff, 28))
+        );
+    }
+
+    #[test]
+    fn compact_work() {
+        assert_eq!(
+            CompactDifficulty(0x1d00_ffff).to_work(),
+            Some(Work(0x1_0001_0001))
+        );
+        assert_eq!(CompactDifficulty(0x1f07_ffff).to_work(), Some(Work(8192)));
+
+        // The easiest possible target has the least work.
+        assert_eq!(
+            CompactDifficulty(0x2000_0001).to_work(),
+            Some(Work(16_777_215))
+        );
+
+        // The hardest possible target has too much work for a u128.
+        assert_eq!(
+            CompactDifficulty(0x0300_0001).to_expanded(),
+            Some(expanded(1, 0))
+        );
+        assert_eq!(CompactDifficulty(0x0300_0001).to_work(), None);
+    }
+
+    #[test]
+    fn work_saturates() {
+        let max = Work(u128::max_value());
+        assert_eq!(max + Work(1), max);
+        assert_eq!(vec![Work(1), Work(2)].into_iter().sum::<Work>(), Work(3));
+        assert_eq!(vec![max, max].into_iter().sum::<Work>(), max);
+
+        let mut total = Work::default();
+        total += Work(7);
+        assert_eq!(total.as_u128(), 7);
+    }
+
+    #[test]
+    fn compact_rounding() {
+        // Test vectors from Bitcoin's arith_uint256_tests.cpp
+        assert_eq!(expanded(0, 0).to_compact(), CompactDifficulty(0));
+        assert_eq!(
+            expanded(0x12, 0).to_compact(),
+            CompactDifficulty(0x0112_0000)
+        );
+        assert_eq!(
+            expanded(0x80, 0).to_compact(),
+            CompactDifficulty(0x0200_8000)
+        );
+        assert_eq!(
+            expanded(0x9234, 2).to_compact(),
+            CompactDifficulty(0x0500_9234)
+        );
+        assert_eq!(
+            expanded(0x12_3456, 1).to_compact(),
+            CompactDifficulty(0x0412_3456)
+        );
+
+        // Extra precision is rounded down.
+        assert_eq!(
+            expanded(0x12_3456_78, 1).to_compact(),
+            CompactDifficulty(0x0512_3456)
+        );
+
+        // The network limits round down to the genesis thresholds.
+        assert_eq!(
+            ExpandedDifficulty::target_difficulty_limit(Network::Mainnet).to_compact(),
+            CompactDifficulty(0x1f07_ffff)
+        );
+        assert_eq!(
+            ExpandedDifficulty::target_difficulty_limit(Network::Testnet).to_compact(),
+            CompactDifficulty(0x2007_ffff)
+        );
+    }
+
+    #[test]
+    fn expanded_arithmetic() {
+        let ten = expanded(10, 0);
+        assert_eq!(ten * 3 / 4, expanded(7, 0));
+        assert_eq!(
+            vec![ten, ten].into_iter().sum::<ExpandedDifficulty>(),
+            expanded(20, 0)
+        );
+
+        let max = ExpandedDifficulty(U256::max_value());
+        assert_eq!(max + ten, max);
+        assert_eq!(max * 2, max);
+    }
+
+    #[test]
+    fn hash_comparison() {
+        let limit = ExpandedDifficulty::target_difficulty_limit(Network::Mainnet);
+
+        // Hashes are little-endian, so the last byte is the most significant.
+        let mut bytes = [0xff; 32];
+        bytes[31] = 0x00;
+        bytes[30] = 0x07;
+        assert!(block::Hash(bytes) <= limit);
+        assert!(block::Hash(bytes) == limit);
+        assert!(limit >= block::Hash(bytes));
+
+        bytes[30] = 0x08;
+        assert!(block::Hash(bytes) > limit);
+        assert!(limit < block::Hash(bytes));
+
+        assert!(block::Hash([0; 32]) < limit);
+    }
+
+    #[test]
+    fn difficulty_limits_are_ordered() {
+        let mainnet = ExpandedDifficulty::target_difficulty_limit(Network::Mainnet);
+        let testnet = ExpandedDifficulty::target_difficulty_limit(Network::Testnet);
+        let regtest = ExpandedDifficulty::target_difficulty_limit(Network::Regtest);
+
+        assert!(mainnet < testnet);
+        assert!(testnet < regtest);
+
+        // The genesis block uses the easiest target that fits in the compact
+        // encoding.
+        let genesis = CompactDifficulty(0x1f07_ffff).to_expanded().unwrap();
+        assert!(genesis <= mainnet);
+        assert!(CompactDifficulty(0x1f08_0000).to_expanded().unwrap() > mainnet);
+    }
+
+    proptest! {
+        #[test]
+        fn expanded_work_is_ordered(a in any::<CompactDifficulty>(), b in any::<CompactDifficulty>()) {
+            // Easier targets have less work.
+            if let (Some(ea), Some(eb), Some(wa), Some(wb)) =
+                (a.to_expanded(), b.to_expanded(), a.to_work(), b.to_work())
+            {
+                if ea < eb {
+                    prop_assert!(wa >= wb);
+                }
+            }
+        }
+    }
+}
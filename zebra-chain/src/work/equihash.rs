@@ -0,0 +1,352 @@
+//! Equihash Solution and related items.
+
+use std::{fmt, io};
+
+use thiserror::Error;
+
+#[cfg(any(test, feature = "proptest-impl"))]
+use proptest::{arbitrary::Arbitrary, collection::vec, prelude::*};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[cfg(feature = "serde")]
+use crate::serialization::serde_hex;
+use crate::{
+    block::Header,
+    serialization::{
+        ReadZcashExt, SerializationError, WriteZcashExt, ZcashDeserialize, ZcashSerialize,
+    },
+};
+
+/// The size of a mainnet or testnet Equihash solution in bytes (always 1344).
+pub(crate) const SOLUTION_SIZE: usize = 1344;
+
+/// The size of a regtest Equihash solution in bytes (always 36).
+pub(crate) const REGTEST_SOLUTION_SIZE: usize = 36;
+
+/// The error returned when an Equihash solution is invalid.
+#[derive(Error, Debug)]
+#[error("invalid equihash solution for block header")]
+pub struct Error(#[from] equihash::Error);
+
+/// Equihash Solution.
+///
+/// Mainnet and testnet use Equihash (200, 9), which has 1344-byte
+/// solutions. Regtest uses Equihash (48, 5), which has 36-byte solutions.
+///
+/// The arrays are wrapped because Rust doesn't implement common traits like
+/// `Debug`, `Clone`, etc for arrays beyond lengths 0 to 32.
+pub enum Solution {
+    /// A mainnet or testnet Equihash (200, 9) solution.
+    Common([u8; SOLUTION_SIZE]),
+    /// A regtest Equihash (48, 5) solution.
+    Regtest([u8; REGTEST_SOLUTION_SIZE]),
+}
+
+impl Solution {
+    /// The length of the header prefix that is hashed with the nonce: every
+    /// field before the nonce.
+    const INPUT_LENGTH: usize = 4 + 32 * 3 + 4 * 2;
+
+    /// Returns the solution's bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Solution::Common(bytes) => &bytes[..],
+            Solution::Regtest(bytes) => &bytes[..],
+        }
+    }
+
+    /// Returns the solution in `bytes`, which must be a mainnet, testnet, or
+    /// regtest solution length.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Solution, SerializationError> {
+        match bytes.len() {
+            SOLUTION_SIZE => {
+                let mut solution = [0; SOLUTION_SIZE];
+                solution.copy_from_slice(bytes);
+                Ok(Solution::Common(solution))
+            }
+            REGTEST_SOLUTION_SIZE => {
+                let mut solution = [0; REGTEST_SOLUTION_SIZE];
+                solution.copy_from_slice(bytes);
+                Ok(Solution::Regtest(solution))
+            }
+            _ => Err(SerializationError::Parse(
+                "incorrect equihash solution length",
+            )),
+        }
+    }
+
+    /// Check that this is a valid Equihash solution for `header`, using the
+    /// parameters for its length.
+    ///
+    /// The solution is checked against the header fields before the nonce,
+    /// and the nonce itself. It doesn't check the header hash against the
+    /// difficulty threshold.
+    pub fn check(&self, header: &Header) -> Result<(), Error> {
+        let (n, k) = match self {
+            Solution::Common(_) => (200, 9),
+            Solution::Regtest(_) => (48, 5),
+        };
+
+        let input = Solution::input(header);
+        equihash::is_valid_solution(n, k, &input, &header.nonce, self.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Returns the header fields that are hashed before the nonce.
+    fn input(header: &Header) -> Vec<u8> {
+        let mut input = Vec::new();
+        header
+            .zcash_serialize(&mut input)
+            .expect("serialization into a vec can't fail");
+        input.truncate(Solution::INPUT_LENGTH);
+        input
+    }
+
+    /// Returns a regtest Equihash (48, 5) solution for `header` and its
+    /// nonce, or `None` if there isn't one for this nonce.
+    ///
+    /// This is a simple version of Wagner's algorithm, which is fast enough
+    /// for the regtest parameters. Tests use it to mine regtest blocks.
+    #[cfg(any(test, feature = "proptest-impl"))]
+    pub fn solve_regtest(header: &Header) -> Option<Solution> {
+        const N: u32 = 48;
+        const K: u32 = 5;
+        // Each index has `N / (K + 1) + 1` bits.
+        const INDEX_BITS: usize = 9;
+        const HASH_LENGTH: usize = N as usize / 8;
+        const HASHES_PER_OUTPUT: usize = 512 / N as usize;
+
+        let mut personal = [0; 16];
+        personal[..8].copy_from_slice(b"ZcashPoW");
+        personal[8..12].copy_from_slice(&N.to_le_bytes());
+        personal[12..].copy_from_slice(&K.to_le_bytes());
+        let mut state = blake2b_simd::Params::new()
+            .hash_length(HASHES_PER_OUTPUT * HASH_LENGTH)
+            .personal(&personal)
+            .to_state();
+        state.update(&Solution::input(header));
+        state.update(&header.nonce);
+
+        // Each row is a partial XOR of hashes, and the indices of those hashes.
+        let index_count = 1 << INDEX_BITS;
+        let mut rows = Vec::with_capacity(index_count);
+        for index in 0..index_count {
+            let output = state
+                .clone()
+                .update(&((index / HASHES_PER_OUTPUT) as u32).to_le_bytes())
+                .finalize();
+            let start = (index % HASHES_PER_OUTPUT) * HASH_LENGTH;
+            let hash = output.as_bytes()[start..start + HASH_LENGTH].to_vec();
+            rows.push((hash, vec![index as u32]));
+        }
+
+        for round in 0..K as usize {
+            // The last round collides on both remaining bytes, so the XOR of
+            // all the hashes is zero.
+            let collision = if round + 1 == K as usize { 2 } else { 1 };
+            rows.sort_by(|a, b| a.0[..collision].cmp(&b.0[..collision]));
+
+            let mut next = Vec::new();
+            let mut start = 0;
+            while start < rows.len() {
+                let mut end = start + 1;
+                while end < rows.len() && rows[end].0[..collision] == rows[start].0[..collision] {
+                    end += 1;
+                }
+                for (i, (a_hash, a_indices)) in rows[start..end].iter().enumerate() {
+                    for (b_hash, b_indices) in &rows[start + i + 1..end] {
+                        if a_indices.iter().any(|index| b_indices.contains(index)) {
+                            continue;
+                        }
+                        // The colliding byte is trimmed, like the verifier does.
+                        let hash = a_hash[1..]
+                            .iter()
+                            .zip(&b_hash[1..])
+                            .map(|(a, b)| a ^ b)
+                            .collect();
+                        // The subtree with the lowest first index goes first.
+                        let indices = if a_indices[0] < b_indices[0] {
+                            [&a_indices[..], &b_indices[..]].concat()
+                        } else {
+                            [&b_indices[..], &a_indices[..]].concat()
+                        };
+                        next.push((hash, indices));
+                    }
+                }
+                start = end;
+            }
+            rows = next;
+        }
+
+        // Pack the indices as big-endian `INDEX_BITS`-bit values.
+        let (_, indices) = rows.into_iter().next()?;
+        let mut solution = [0; REGTEST_SOLUTION_SIZE];
+        for (position, index) in indices.iter().enumerate() {
+            for bit in 0..INDEX_BITS {
+                if (index >> (INDEX_BITS - 1 - bit)) & 1 == 1 {
+                    let offset = position * INDEX_BITS + bit;
+                    solution[offset / 8] |= 0x80 >> (offset % 8);
+                }
+            }
+        }
+
+        Some(Solution::Regtest(solution))
+    }
+}
+
+impl PartialEq<Solution> for Solution {
+    fn eq(&self, other: &Solution) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl fmt::Debug for Solution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("equihash::Solution")
+            .field(&hex::encode(self.as_bytes()))
+            .finish()
+    }
+}
+
+// These impls all only exist because of array length restrictions.
+
+impl Copy for Solution {}
+
+impl Clone for Solution {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl Eq for Solution {}
+
+#[cfg(feature = "serde")]
+impl Serialize for Solution {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde_hex::serialize(self.as_bytes(), serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Solution {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes: Vec<u8> = serde_hex::deserialize(deserializer)?;
+        Solution::from_bytes(&bytes).map_err(serde::de::Error::custom)
+    }
+}
+
+impl ZcashSerialize for Solution {
+    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        writer.write_compact_bytes(self.as_bytes())
+    }
+}
+
+impl ZcashDeserialize for Solution {
+    fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let len = reader.read_compactsize()?;
+        if len != SOLUTION_SIZE as u64 && len != REGTEST_SOLUTION_SIZE as u64 {
+            return Err(SerializationError::Parse(
+                "incorrect equihash solution length",
+            ));
+        }
+        let mut bytes = vec![0; len as usize];
+        reader.read_exact(&mut bytes[..])?;
+        Solution::from_bytes(&bytes)
+    }
+}
+
+#[cfg(any(test, feature = "proptest-impl"))]
+impl Arbitrary for Solution {
+    type Parameters = ();
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        (vec(any::<u8>(), SOLUTION_SIZE))
+            .prop_map(|v| Solution::from_bytes(&v).expect("vector has the solution length"))
+            .boxed()
+    }
+
+    type Strategy = BoxedStrategy<Self>;
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    use crate::block::Block;
+
+    #[test]
+    fn equihash_solutions_match_block_vectors() {
+        for bytes in &[
+            &zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..],
+            &zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..],
+            &zebra_test_vectors::BLOCK_MAINNET_415000_BYTES[..],
+        ] {
+            let header = Block::zcash_deserialize(*bytes)
+                .expect("block test vector should deserialize")
+                .header;
+            header
+                .solution
+                .check(&header)
+                .expect("mainnet blocks have valid solutions");
+
+            let mut changed_nonce = header;
+            changed_nonce.nonce[0] ^= 1;
+            assert!(header.solution.check(&changed_nonce).is_err());
+
+            let mut changed_solution = header;
+            if let Solution::Common(bytes) = &mut changed_solution.solution {
+                bytes[100] ^= 1;
+            }
+            assert!(changed_solution.solution.check(&changed_solution).is_err());
+        }
+    }
+
+    #[test]
+    fn regtest_solutions_are_valid() {
+        let mut header = crate::parameters::genesis::genesis_block(crate::Network::Regtest).header;
+        header
+            .solution
+            .check(&header)
+            .expect("the regtest genesis block has a valid solution");
+
+        let mut solved = 0;
+        for nonce in 0..16u8 {
+            header.nonce[0] = nonce;
+            if let Some(solution) = Solution::solve_regtest(&header) {
+                header.solution = solution;
+                header
+                    .solution
+                    .check(&header)
+                    .expect("solved regtest headers have valid solutions");
+                solved += 1;
+            }
+        }
+        assert!(solved > 0, "some regtest nonces have solutions");
+    }
+
+    proptest! {
+
+        #[test]
+        fn equihash_solution_roundtrip(solution in any::<Solution>()) {
+
+            let mut data = Vec::new();
+
+            solution.zcash_serialize(&mut data).expect("Solution should serialize");
+
+            let solution2 = Solution::zcash_deserialize(&data[..])
+                .expect("randomized Solution should deserialize");
+
+            prop_assert_eq![solution, solution2];
+        }
+
+        #[test]
+        fn equihash_random_solutions_are_rejected(header in any::<Header>()) {
+            prop_assert!(header.solution.check(&header).is_err());
+        }
+
+    }
+}
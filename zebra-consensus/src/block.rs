@@ -0,0 +1,613 @@
+//! Block verification.
+//!
+//! The [`BlockVerifier`] checks the structure and consensus rules of each
+//! block on its own, then checks its time and difficulty adjustment against
+//! the previous blocks in the state. It checks that the coinbase claims at
+//! most the block subsidy and the transaction fees, verifies each
+//! transaction using the [`TransactionVerifier`], and adds valid blocks to
+//! the state.
+//! Other checks that need earlier blocks, like nullifier double-spends, are
+//! left to the state service.
+
+pub mod check;
+pub mod difficulty;
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::HashMap,
+    error, fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use chrono::Utc;
+use futures::{
+    channel::oneshot,
+    future::{FutureExt, Shared, TryFutureExt},
+};
+use thiserror::Error;
+use tokio::sync::Semaphore;
+use tower::{buffer::Buffer, Service, ServiceExt};
+
+use zebra_chain::{
+    amount::{Amount, NonNegative},
+    block::{
+        self, merkle, Block, ChainHistoryBlockTxAuthCommitmentHash, ChainHistoryMmrRootHash,
+        Commitment, CommitmentError, Header,
+    },
+    history_tree::HistoryTree,
+    parameters::{genesis::GENESIS_PREVIOUS_BLOCK_HASH, subsidy::FundingStreamReceiver},
+    sapling::tree::{self as sapling_tree, NoteCommitmentTree, NoteCommitmentTreeError},
+    transaction::OutPoint,
+    work::difficulty::CompactDifficulty,
+    Network,
+};
+
+use crate::{
+    transaction::{self, AsyncChecks, TransactionVerifier},
+    Config, VerificationError,
+};
+
+/// How long a checked block can wait for the state to add it.
+///
+/// The state queues blocks until their parents are added, and stops taking
+/// requests while its queue is full, so blocks whose parents never arrive
+/// have to give up their place in the queue.
+const STATE_ADD_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// The error type for block verification.
+pub type Error = Box<dyn error::Error + Send + Sync + 'static>;
+
+/// A consensus rule violation in a block.
+#[derive(Error, Debug)]
+pub enum BlockError {
+    /// The first transaction isn't a coinbase transaction.
+    #[error("block has no coinbase transaction")]
+    NoCoinbase,
+    /// A transaction other than the first has a coinbase input.
+    #[error("block has a coinbase input outside the first transaction")]
+    CoinbaseNotFirst,
+    /// The header's merkle root doesn't commit to the block's transactions.
+    #[error("block merkle root {actual:?} does not match header {expected:?}")]
+    BadMerkleRoot {
+        /// The merkle root in the header.
+        expected: merkle::Root,
+        /// The merkle root of the transactions.
+        actual: merkle::Root,
+    },
+    /// The block time isn't after the median time of the previous blocks.
+    #[error("block time is not after the median time past")]
+    TimeTooEarly,
+    /// The block time is too far ahead of our clock.
+    #[error("block time is more than two hours in the future")]
+    TimeTooFarInFuture,
+    /// The Equihash solution is invalid.
+    #[error("invalid equihash solution")]
+    Equihash(#[from] zebra_chain::work::equihash::Error),
+    /// The Equihash solution has the wrong size for the network.
+    #[error("{0}-byte equihash solution is the wrong size for this network")]
+    WrongEquihashSolutionSize(usize),
+    /// The difficulty threshold isn't a positive 256-bit number.
+    #[error("invalid difficulty threshold {0:?}")]
+    InvalidDifficulty(CompactDifficulty),
+    /// The difficulty threshold is easier than the network's limit.
+    #[error("difficulty threshold {0:?} is easier than the proof of work limit")]
+    TargetDifficultyLimit(CompactDifficulty),
+    /// The difficulty threshold doesn't match the difficulty adjustment.
+    #[error("difficulty threshold {actual:?} does not match adjusted threshold {expected:?}")]
+    BadDifficultyThreshold {
+        /// The threshold from the difficulty adjustment.
+        expected: CompactDifficulty,
+        /// The threshold in the header.
+        actual: CompactDifficulty,
+    },
+    /// The block hash is greater than the difficulty threshold.
+    #[error("block hash {0:?} does not meet difficulty threshold {1:?}")]
+    DifficultyFilter(block::Hash, CompactDifficulty),
+    /// A transaction's lock time hasn't passed at this height and time.
+    #[error("block contains a transaction whose lock time has not passed")]
+    LockedTransaction,
+    /// The header's commitment field isn't valid for the network upgrade.
+    #[error("invalid block commitment")]
+    Commitment(#[from] CommitmentError),
+    /// The final Sapling root in the header doesn't match the note
+    /// commitment tree after the block.
+    #[error("final Sapling root {actual:?} does not match header {expected:?}")]
+    BadFinalSaplingRoot {
+        /// The final Sapling root in the header.
+        expected: sapling_tree::Root,
+        /// The root of the note commitment tree after the block.
+        actual: sapling_tree::Root,
+    },
+    /// The block's note commitments don't fit in the note commitment tree.
+    #[error("note commitment tree error")]
+    NoteCommitmentTree(#[from] NoteCommitmentTreeError),
+    /// The chain history root in the header doesn't match the chain history
+    /// tree before the block.
+    #[error("chain history root {actual:?} does not match header {expected:?}")]
+    BadChainHistoryRoot {
+        /// The chain history root in the header.
+        expected: ChainHistoryMmrRootHash,
+        /// The root of the chain history tree before the block.
+        actual: ChainHistoryMmrRootHash,
+    },
+    /// The block commitments hash in the header doesn't match the chain
+    /// history tree before the block and the block's authorizing data.
+    #[error("block commitments {actual:?} do not match header {expected:?}")]
+    BadBlockCommitments {
+        /// The block commitments hash in the header.
+        expected: ChainHistoryBlockTxAuthCommitmentHash,
+        /// The hash of the chain history root and authorizing data root.
+        actual: ChainHistoryBlockTxAuthCommitmentHash,
+    },
+    /// The block has two copies of the same transaction.
+    ///
+    /// Repeating the last transactions doesn't change the merkle root, so
+    /// the root doesn't catch these blocks (CVE-2012-2459).
+    #[error("block has transaction {0:?} more than once")]
+    DuplicateTransaction(zebra_chain::transaction::Hash),
+    /// Two inputs in the block spend the same transparent output.
+    #[error("block spends {0:?} more than once")]
+    DuplicateTransparentSpend(OutPoint),
+    /// Two shielded spends in the block reveal the same nullifier.
+    #[error("block reveals the same nullifier more than once")]
+    DuplicateNullifier,
+    /// The block has more legacy signature operations than the limit.
+    #[error("block has {0} signature operations, more than the limit")]
+    TooManySigops(u32),
+    /// A transaction is too small, or larger than the limit at its height.
+    #[error("transaction size {size} is not in the valid range, up to {max}")]
+    BadTransactionSize {
+        /// The serialized size of the transaction.
+        size: usize,
+        /// The largest valid size at this height.
+        max: usize,
+    },
+    /// The coinbase doesn't pay the founders' reward to the right address.
+    #[error("coinbase does not pay the founders' reward")]
+    FoundersRewardNotFound,
+    /// The coinbase doesn't pay a funding stream to the right address.
+    #[error("coinbase does not pay the {0:?} funding stream")]
+    FundingStreamNotFound(FundingStreamReceiver),
+    /// The coinbase's values don't fit in the valid range of amounts.
+    #[error("coinbase value is out of range")]
+    CoinbaseValueOutOfRange,
+    /// A transaction's fee is negative, or out of range.
+    #[error("transaction {0:?} has a negative or out of range fee")]
+    BadTransactionFee(zebra_chain::transaction::Hash),
+    /// The coinbase creates more than the block subsidy and fees.
+    #[error("coinbase creates {created:?}, more than the subsidy and fees {max:?}")]
+    CoinbaseValueTooLarge {
+        /// The value created by the coinbase.
+        created: Amount<NonNegative>,
+        /// The block subsidy plus the transaction fees.
+        max: Amount<NonNegative>,
+    },
+}
+
+/// A block that wasn't verified, because its parent was being verified, but
+/// wasn't added to the state.
+#[derive(Error, Copy, Clone, Debug, Eq, PartialEq)]
+#[error("parent block {parent:?} wasn't added")]
+pub struct ParentNotAdded {
+    /// The hash of the block's parent.
+    pub parent: block::Hash,
+}
+
+/// The blocks that a [`BlockVerifier`] is checking, so that their children
+/// can wait for them.
+///
+/// Each receiver finishes with true if its block was added to the state.
+#[derive(Clone, Default)]
+struct PendingBlocks(Arc<Mutex<HashMap<block::Hash, Shared<oneshot::Receiver<bool>>>>>);
+
+impl fmt::Debug for PendingBlocks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pending = self.0.lock().expect("mutex should be unpoisoned");
+        f.debug_set().entries(pending.keys()).finish()
+    }
+}
+
+/// Removes a block from the [`PendingBlocks`] when its verification
+/// finishes, or is dropped.
+///
+/// If the block was already pending, the first request keeps its entry.
+struct PendingBlock {
+    blocks: PendingBlocks,
+    hash: block::Hash,
+    inserted: bool,
+}
+
+impl Drop for PendingBlock {
+    fn drop(&mut self) {
+        if !self.inserted {
+            return;
+        }
+        self.blocks
+            .0
+            .lock()
+            .expect("mutex should be unpoisoned")
+            .remove(&self.hash);
+    }
+}
+
+/// Checks blocks, and adds valid blocks to the state.
+///
+/// Responds with the hash of each block that was added.
+///
+/// Blocks can be sent before their parent has been added. Their
+/// context-free checks run straight away, then they wait for their parent
+/// before the checks that use the previous blocks.
+#[derive(Clone, Debug)]
+pub struct BlockVerifier<S> {
+    /// The network that blocks are verified for.
+    network: Network,
+    /// The state service, which stores valid blocks.
+    state_service: S,
+    /// The verifier for the transactions in each block.
+    transaction_verifier: TransactionVerifier<S>,
+    /// Limits the number of blocks that are verified at the same time.
+    permits: Arc<Semaphore>,
+    /// The blocks that are being verified.
+    pending: PendingBlocks,
+}
+
+impl<S> BlockVerifier<S>
+where
+    S: Clone,
+{
+    /// Returns a verifier for blocks on `network`, which adds valid blocks to
+    /// `state_service`.
+    pub fn new(network: Network, state_service: S) -> Self {
+        BlockVerifier::from_config(&Config::default(), network, state_service)
+    }
+
+    /// Returns a verifier for blocks on `network`, using the batch limits in
+    /// `config`.
+    pub fn from_config(config: &Config, network: Network, state_service: S) -> Self {
+        BlockVerifier {
+            network,
+            transaction_verifier: TransactionVerifier::from_config(
+                config,
+                network,
+                state_service.clone(),
+            ),
+            state_service,
+            permits: Arc::new(Semaphore::new(config.max_concurrent_blocks)),
+            pending: PendingBlocks::default(),
+        }
+    }
+}
+
+impl<S> Service<Arc<Block>> for BlockVerifier<S>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = Error>
+        + Send
+        + Clone
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = block::Hash;
+    type Error = Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // The state service is only used after checks, so we check its
+        // readiness in the response future.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, block: Arc<Block>) -> Self::Future {
+        let network = self.network;
+        let mut state_service = self.state_service.clone();
+        let mut transaction_verifier = self.transaction_verifier.clone();
+        let permits = self.permits.clone();
+
+        let hash = block.hash();
+        let parent_hash = block.header.previous_block_hash;
+        let (added_tx, added_rx) = oneshot::channel();
+        let (parent, inserted) = {
+            let mut pending = self.pending.0.lock().expect("mutex should be unpoisoned");
+            let inserted = !pending.contains_key(&hash);
+            if inserted {
+                pending.insert(hash, added_rx.shared());
+            }
+            (pending.get(&parent_hash).cloned(), inserted)
+        };
+        let pending = PendingBlock {
+            blocks: self.pending.clone(),
+            hash,
+            inserted,
+        };
+
+        let verified = async move {
+            let invalid = move |error: BlockError| VerificationError::block(hash, error);
+
+            // The Equihash solution and merkle root checks are CPU-bound, so
+            // they run on a blocking thread. This lets blocks from the
+            // download pipeline verify on multiple cores, without stalling
+            // the async executor.
+            let height = {
+                let block = block.clone();
+                tokio::task::spawn_blocking(move || check_block(network, &block))
+                    .await?
+                    .map_err(invalid)?
+            };
+
+            // The other checks use the previous blocks, so they wait until
+            // the parent has been added to the state.
+            if let Some(parent) = parent {
+                if parent.await != Ok(true) {
+                    return Err(ParentNotAdded {
+                        parent: parent_hash,
+                    }
+                    .into());
+                }
+            }
+
+            // Each block holds a permit until it is added to the state, so
+            // the blocks waiting for their batches can't use too much memory.
+            let _permit = permits.acquire().await;
+
+            // The coinbase can claim the fees of the other transactions, which
+            // depend on the outputs they spend. The transaction verifier also
+            // uses these outputs, so it doesn't look them up again.
+            let mut known_utxos = check::block_outputs(&block);
+            let mut fees = Amount::<NonNegative>::zero();
+            for transaction in block.transactions.iter().skip(1) {
+                let utxos =
+                    transaction::spent_utxos(&mut state_service, &known_utxos, transaction).await?;
+                let fee = check::transaction_fee(transaction, &utxos).map_err(invalid)?;
+                fees = (fees + fee).map_err(|_| invalid(BlockError::CoinbaseValueOutOfRange))?;
+                known_utxos.extend(utxos);
+            }
+            check::coinbase_value_is_valid(&block, height, network, fees).map_err(invalid)?;
+            let known_utxos = Arc::new(known_utxos);
+
+            let context = previous_headers(
+                &mut state_service,
+                &block.header,
+                difficulty::POW_ADJUSTMENT_BLOCK_SPAN,
+            )
+            .await?;
+            check_contextual(network, &block, height, &context).map_err(invalid)?;
+
+            match block.commitment(network).map_err(|e| invalid(e.into()))? {
+                Commitment::PreSaplingReserved(_) => {}
+                Commitment::FinalSaplingRoot(root) => {
+                    let tree = parent_sapling_tree(&mut state_service, &block.header).await?;
+                    check::final_sapling_root_is_valid(&block, tree, root).map_err(invalid)?;
+                }
+                // `commitment` has already checked that it is all zeroes.
+                Commitment::ChainHistoryActivationReserved => {}
+                Commitment::ChainHistoryRoot(root) => {
+                    let tree = parent_history_tree(&mut state_service, &block.header).await?;
+                    check::chain_history_root_is_valid(&tree, root).map_err(invalid)?;
+                }
+                Commitment::ChainHistoryBlockTxAuthCommitment(commitment) => {
+                    let tree = parent_history_tree(&mut state_service, &block.header).await?;
+                    check::block_commitments_are_valid(&block, &tree, commitment)
+                        .map_err(invalid)?;
+                }
+            }
+
+            let mut async_checks = AsyncChecks::default();
+            for transaction in &block.transactions {
+                let request = transaction::Request::Block {
+                    transaction: transaction.clone(),
+                    height,
+                    previous_block_hash: block.header.previous_block_hash,
+                    known_utxos: known_utxos.clone(),
+                };
+                let verified = transaction_verifier.ready_and().await?.call(request);
+                async_checks.push(verified.map(|result| result.map(|_hash| ())));
+            }
+            // The first poll queues every proof and signature in the block in
+            // the verifier batches. There won't be any more items from this
+            // block, so we flush the batches, rather than waiting for them to
+            // fill up or time out.
+            let mut checks = async_checks.check().boxed();
+            match futures::poll!(&mut checks) {
+                Poll::Ready(result) => result?,
+                Poll::Pending => {
+                    transaction_verifier.flush().await?;
+                    checks.await?;
+                }
+            }
+
+            let added = state_service
+                .ready_and()
+                .and_then(|state| state.call(zebra_state::Request::AddBlock { block }));
+            let response = tokio::time::timeout(STATE_ADD_TIMEOUT, added)
+                .await
+                .map_err(|_| "timed out waiting for the state to add the block's parent")??;
+            let result: Result<block::Hash, Error> = match response {
+                zebra_state::Response::Added => Ok(hash),
+                response => Err(format!("unexpected state response: {:?}", response).into()),
+            };
+            result
+        };
+
+        async move {
+            let result = verified.await;
+            // Children that are sent after this point find the block in the
+            // state instead.
+            std::mem::drop(pending);
+            let _ = added_tx.send(result.is_ok());
+            result
+        }
+        .boxed()
+    }
+}
+
+/// Check the consensus rules that only depend on `block` itself, and return
+/// its height.
+///
+/// Regtest blocks use the smaller Equihash (48, 5) parameters, and their
+/// difficulty threshold must be no easier than the Regtest limit.
+fn check_block(network: Network, block: &Block) -> Result<block::Height, BlockError> {
+    let height = check::coinbase_is_first(block)?;
+
+    check::time_is_valid_at(&block.header, Utc::now())?;
+    check::equihash_solution_is_valid(&block.header, network)?;
+    check::difficulty_is_valid(&block.header, network, &block.hash())?;
+
+    check::merkle_root_is_valid(block)?;
+    check::transaction_hashes_are_unique(block)?;
+    check::spends_are_unique(block)?;
+    check::transaction_sizes_are_valid(block, height, network)?;
+    check::sigops_are_valid(block)?;
+    check::lock_times_have_passed(block, height)?;
+
+    Ok(height)
+}
+
+/// Check the consensus rules that depend on the previous blocks, and the
+/// subsidy rules for the block's height.
+///
+/// `context` is the headers of the previous blocks, most recent first. It
+/// must contain [`difficulty::POW_ADJUSTMENT_BLOCK_SPAN`] headers, unless
+/// the chain is shorter.
+///
+/// Regtest doesn't adjust its difficulty, so Regtest blocks must have the
+/// threshold of the previous block, or the Regtest limit.
+fn check_contextual(
+    network: Network,
+    block: &Block,
+    height: block::Height,
+    context: &[Header],
+) -> Result<(), BlockError> {
+    check::time_is_after_median_time_past(&block.header, context)?;
+    check::difficulty_threshold_is_adjusted(&block.header, height, network, context)?;
+    check::subsidy_is_valid(block, height, network)?;
+
+    Ok(())
+}
+
+/// Returns up to `count` headers before `header` from `state_service`, most
+/// recent first.
+///
+/// Returns fewer headers if the genesis block is reached, and an error if a
+/// previous block is missing from the state. Pruned blocks still have their
+/// headers, so this works on pruned states.
+async fn previous_headers<S>(
+    state_service: &mut S,
+    header: &Header,
+    count: usize,
+) -> Result<Vec<Header>, Error>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = Error>,
+{
+    let mut headers = Vec::with_capacity(count);
+    let mut hash = header.previous_block_hash;
+
+    while headers.len() < count && hash != GENESIS_PREVIOUS_BLOCK_HASH {
+        let response = state_service
+            .ready_and()
+            .await?
+            .call(zebra_state::Request::BlockHeader {
+                hash_or_height: hash.into(),
+            })
+            .await?;
+        let header = match response {
+            zebra_state::Response::BlockHeader { header } => header,
+            response => return Err(format!("unexpected state response: {:?}", response).into()),
+        };
+
+        hash = header.previous_block_hash;
+        headers.push(header);
+    }
+
+    Ok(headers)
+}
+
+/// Returns the Sapling note commitment tree after the block before `header`,
+/// from `state_service`.
+///
+/// The genesis block has an empty tree before it.
+async fn parent_sapling_tree<S>(
+    state_service: &mut S,
+    header: &Header,
+) -> Result<NoteCommitmentTree, Error>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = Error>,
+{
+    let hash = header.previous_block_hash;
+    if hash == GENESIS_PREVIOUS_BLOCK_HASH {
+        return Ok(NoteCommitmentTree::default());
+    }
+
+    let response = state_service
+        .ready_and()
+        .await?
+        .call(zebra_state::Request::GetSaplingTree { hash })
+        .await?;
+    match response {
+        zebra_state::Response::SaplingTree { tree: Some(tree) } => Ok(tree),
+        zebra_state::Response::SaplingTree { tree: None } => {
+            Err(format!("missing Sapling note commitment tree for {:?}", hash).into())
+        }
+        response => Err(format!("unexpected state response: {:?}", response).into()),
+    }
+}
+
+/// Returns the chain history tree after the block before `header`, from
+/// `state_service`.
+///
+/// Only blocks after Heartwood activation commit to the tree, so their
+/// parent is never the genesis block.
+async fn parent_history_tree<S>(
+    state_service: &mut S,
+    header: &Header,
+) -> Result<HistoryTree, Error>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = Error>,
+{
+    let hash = header.previous_block_hash;
+    let response = state_service
+        .ready_and()
+        .await?
+        .call(zebra_state::Request::GetHistoryTree { hash })
+        .await?;
+    match response {
+        zebra_state::Response::HistoryTree { tree: Some(tree) } => Ok(tree),
+        zebra_state::Response::HistoryTree { tree: None } => {
+            Err(format!("missing chain history tree for {:?}", hash).into())
+        }
+        response => Err(format!("unexpected state response: {:?}", response).into()),
+    }
+}
+
+/// Returns a block verifier for `network`, which adds valid blocks to
+/// `state_service`.
+///
+/// The verifier is buffered, so it can be cloned and shared between tasks.
+pub fn init<S>(
+    config: &Config,
+    network: Network,
+    state_service: S,
+) -> impl Service<
+    Arc<Block>,
+    Response = block::Hash,
+    Error = Error,
+    Future = impl Future<Output = Result<block::Hash, Error>>,
+> + Send
+       + Clone
+       + 'static
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = Error>
+        + Send
+        + Clone
+        + 'static,
+    S::Future: Send + 'static,
+{
+    Buffer::new(
+        BlockVerifier::from_config(config, network, state_service),
+        config.max_concurrent_blocks,
+    )
+}
@@ -0,0 +1,469 @@
+//! Consensus checks for individual blocks and headers.
+
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+use chrono::{DateTime, Duration, Utc};
+
+use zebra_chain::{
+    amount::{Amount, NonNegative},
+    block::{
+        self, merkle, Block, ChainHistoryBlockTxAuthCommitmentHash, ChainHistoryMmrRootHash,
+        Header, MAX_BLOCK_BYTES,
+    },
+    history_tree::HistoryTree,
+    network_upgrade::NetworkUpgrade,
+    parameters::subsidy::{self, FundingStreamReceiver},
+    sapling::tree::{NoteCommitmentTree, Root},
+    serialization::ZcashSerialize,
+    transaction::{
+        self, OutPoint, Transaction, TransparentInput, TransparentOutput, MIN_TRANSACTION_BYTES,
+    },
+    transparent,
+    work::{difficulty::ExpandedDifficulty, equihash},
+    Network,
+};
+
+use super::{
+    difficulty::{self, AdjustedDifficulty, POW_MEDIAN_BLOCK_SPAN},
+    BlockError,
+};
+
+/// How far a block's time can be ahead of our clock.
+///
+/// Like `zcashd`, we allow some clock skew between nodes.
+const MAX_FUTURE_BLOCK_TIME_SECONDS: i64 = 2 * 60 * 60;
+
+/// The largest number of legacy signature operations in a block.
+pub const MAX_BLOCK_SIGOPS: u32 = 20_000;
+
+/// The largest transaction before Sapling, in bytes.
+///
+/// From Sapling, transactions are only limited by [`MAX_BLOCK_BYTES`].
+pub const MAX_TX_SIZE_BEFORE_SAPLING: usize = 100_000;
+
+/// Returns `Ok(height)` if the first transaction in `block` is its only
+/// coinbase transaction, where `height` is the coinbase height.
+pub fn coinbase_is_first(block: &Block) -> Result<block::Height, BlockError> {
+    let height = block.coinbase_height().ok_or(BlockError::NoCoinbase)?;
+    if !block.has_coinbase_only_first() {
+        return Err(BlockError::CoinbaseNotFirst);
+    }
+    Ok(height)
+}
+
+/// Returns `Ok(())` if `header`'s time is at most two hours after `now`.
+pub fn time_is_valid_at(header: &Header, now: DateTime<Utc>) -> Result<(), BlockError> {
+    if header.time > now + Duration::seconds(MAX_FUTURE_BLOCK_TIME_SECONDS) {
+        return Err(BlockError::TimeTooFarInFuture);
+    }
+    Ok(())
+}
+
+/// Returns `Ok(())` if `header`'s time is after the median time past of the
+/// previous blocks.
+///
+/// `context` is the headers of the previous blocks, most recent first. The
+/// median time past is the median time of the previous
+/// [`POW_MEDIAN_BLOCK_SPAN`] blocks, or all the previous blocks if the chain
+/// is shorter. The genesis block has no previous blocks, so its time is
+/// always valid.
+pub fn time_is_after_median_time_past(
+    header: &Header,
+    context: &[Header],
+) -> Result<(), BlockError> {
+    let times: Vec<_> = context
+        .iter()
+        .take(POW_MEDIAN_BLOCK_SPAN)
+        .map(|header| header.time)
+        .collect();
+    if times.is_empty() {
+        return Ok(());
+    }
+
+    if header.time <= difficulty::median_time(&times) {
+        return Err(BlockError::TimeTooEarly);
+    }
+    Ok(())
+}
+
+/// Returns `Ok(())` if `hash` meets the difficulty threshold in `header`,
+/// and that threshold is valid on `network`.
+///
+/// The threshold is the expanded `nBits` field of the header. It must be a
+/// positive number that fits in 256 bits, and be no easier than the
+/// network's proof of work limit. The hash passes if it is less than or
+/// equal to the threshold, as a 256-bit little-endian integer.
+///
+/// This doesn't check that the threshold matches the difficulty adjustment,
+/// because that needs the previous blocks. See
+/// [`difficulty_threshold_is_adjusted`].
+pub fn difficulty_is_valid(
+    header: &Header,
+    network: Network,
+    hash: &block::Hash,
+) -> Result<(), BlockError> {
+    let threshold = header
+        .bits
+        .to_expanded()
+        .ok_or(BlockError::InvalidDifficulty(header.bits))?;
+
+    if threshold > ExpandedDifficulty::target_difficulty_limit(network) {
+        return Err(BlockError::TargetDifficultyLimit(header.bits));
+    }
+    if *hash > threshold {
+        return Err(BlockError::DifficultyFilter(*hash, header.bits));
+    }
+    Ok(())
+}
+
+/// Returns `Ok(())` if the difficulty threshold in `header` is the adjusted
+/// threshold for a block at `height` on `network`.
+///
+/// `context` is the headers of the previous blocks, most recent first. It
+/// should contain [`POW_ADJUSTMENT_BLOCK_SPAN`] headers, or all the previous
+/// headers if the chain is shorter.
+///
+/// [`POW_ADJUSTMENT_BLOCK_SPAN`]: super::difficulty::POW_ADJUSTMENT_BLOCK_SPAN
+pub fn difficulty_threshold_is_adjusted(
+    header: &Header,
+    height: block::Height,
+    network: Network,
+    context: &[Header],
+) -> Result<(), BlockError> {
+    let adjusted = AdjustedDifficulty::new(header, height, network, context.iter().copied())
+        .expected_difficulty_threshold();
+
+    if header.bits != adjusted {
+        return Err(BlockError::BadDifficultyThreshold {
+            expected: adjusted,
+            actual: header.bits,
+        });
+    }
+    Ok(())
+}
+
+/// Returns `Ok(())` if the Equihash solution in `header` is valid on
+/// `network`.
+///
+/// Regtest uses Equihash (48, 5), and the other networks use Equihash
+/// (200, 9), so the solution must also have the network's size.
+pub fn equihash_solution_is_valid(header: &Header, network: Network) -> Result<(), BlockError> {
+    let is_regtest_solution = matches!(header.solution, equihash::Solution::Regtest(_));
+    if is_regtest_solution != (network == Network::Regtest) {
+        return Err(BlockError::WrongEquihashSolutionSize(
+            header.solution.as_bytes().len(),
+        ));
+    }
+
+    Ok(header.solution.check(header)?)
+}
+
+/// Returns `Ok(())` if the merkle root in the header of `block` commits to its
+/// transactions.
+pub fn merkle_root_is_valid(block: &Block) -> Result<(), BlockError> {
+    let merkle_root = merkle::Root::from_transactions(&block.transactions);
+    if merkle_root != block.header.merkle_root {
+        return Err(BlockError::BadMerkleRoot {
+            expected: block.header.merkle_root,
+            actual: merkle_root,
+        });
+    }
+    Ok(())
+}
+
+/// Returns `Ok(())` if `final_sapling_root`, from the header of `block`, is
+/// the root of `parent_tree` after appending the note commitments in
+/// `block`.
+///
+/// `parent_tree` is the Sapling note commitment tree after the previous
+/// block.
+pub fn final_sapling_root_is_valid(
+    block: &Block,
+    mut parent_tree: NoteCommitmentTree,
+    final_sapling_root: Root,
+) -> Result<(), BlockError> {
+    for output in block
+        .transactions
+        .iter()
+        .flat_map(|tx| tx.sapling_outputs())
+    {
+        parent_tree.append(output.cmu)?;
+    }
+
+    let root = parent_tree.root();
+    if root != final_sapling_root {
+        return Err(BlockError::BadFinalSaplingRoot {
+            expected: final_sapling_root,
+            actual: root,
+        });
+    }
+    Ok(())
+}
+
+/// Returns `Ok(())` if `chain_history_root`, from the header of a block, is
+/// the root of `parent_tree`.
+///
+/// `parent_tree` is the chain history tree after the previous block.
+pub fn chain_history_root_is_valid(
+    parent_tree: &HistoryTree,
+    chain_history_root: ChainHistoryMmrRootHash,
+) -> Result<(), BlockError> {
+    // The tree is only empty before Heartwood activation, when blocks don't
+    // commit to it. Like the activation block, its root is all zeroes.
+    let root = parent_tree
+        .hash()
+        .unwrap_or(ChainHistoryMmrRootHash([0; 32]));
+    if root != chain_history_root {
+        return Err(BlockError::BadChainHistoryRoot {
+            expected: chain_history_root,
+            actual: root,
+        });
+    }
+    Ok(())
+}
+
+/// Returns `Ok(())` if `block_commitments`, from the header of `block`,
+/// commits to the root of `parent_tree`, and the authorizing data of
+/// `block`'s transactions.
+///
+/// `parent_tree` is the chain history tree after the previous block.
+pub fn block_commitments_are_valid(
+    block: &Block,
+    parent_tree: &HistoryTree,
+    block_commitments: ChainHistoryBlockTxAuthCommitmentHash,
+) -> Result<(), BlockError> {
+    let history_root = parent_tree
+        .hash()
+        .unwrap_or(ChainHistoryMmrRootHash([0; 32]));
+    let actual = ChainHistoryBlockTxAuthCommitmentHash::from_commitments(
+        &history_root,
+        &block.auth_data_root(),
+    );
+    if actual != block_commitments {
+        return Err(BlockError::BadBlockCommitments {
+            expected: block_commitments,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Returns `Ok(())` if the lock times of every transaction in `block` have
+/// passed at `height`.
+///
+/// The other transaction rules are checked by the
+/// [`TransactionVerifier`](crate::transaction::TransactionVerifier).
+pub fn lock_times_have_passed(block: &Block, height: block::Height) -> Result<(), BlockError> {
+    for transaction in &block.transactions {
+        if !transaction.is_final(height, block.header.time) {
+            return Err(BlockError::LockedTransaction);
+        }
+    }
+    Ok(())
+}
+
+/// Returns `Ok(())` if every transaction in `block` has a different hash.
+///
+/// A block with its last transactions repeated has the same merkle root as
+/// the original block, so an invalid copy of a valid block could otherwise
+/// make us reject the valid block by its hash.
+pub fn transaction_hashes_are_unique(block: &Block) -> Result<(), BlockError> {
+    let mut hashes = HashSet::new();
+    for transaction in &block.transactions {
+        let hash = transaction::Hash::from(transaction.as_ref());
+        if !hashes.insert(hash) {
+            return Err(BlockError::DuplicateTransaction(hash));
+        }
+    }
+    Ok(())
+}
+
+/// Returns `Ok(())` if no transparent output or nullifier is spent more than
+/// once in `block`.
+///
+/// This covers spends in different transactions, and repeated spends in a
+/// single transaction. Spends of outputs and notes from earlier blocks are
+/// checked by the state.
+pub fn spends_are_unique(block: &Block) -> Result<(), BlockError> {
+    let mut outpoints = HashSet::new();
+    for transaction in &block.transactions {
+        for input in transaction.inputs() {
+            if let TransparentInput::PrevOut { outpoint, .. } = input {
+                if !outpoints.insert(outpoint) {
+                    return Err(BlockError::DuplicateTransparentSpend(*outpoint));
+                }
+            }
+        }
+    }
+
+    let transactions = || block.transactions.iter();
+    if !all_unique(transactions().flat_map(|tx| tx.sprout_nullifiers()))
+        || !all_unique(transactions().flat_map(|tx| tx.sapling_nullifiers()))
+        || !all_unique(transactions().flat_map(|tx| tx.orchard_nullifiers()))
+    {
+        return Err(BlockError::DuplicateNullifier);
+    }
+
+    Ok(())
+}
+
+/// Returns true if `items` has no duplicates.
+fn all_unique<T: Eq + Hash>(items: impl IntoIterator<Item = T>) -> bool {
+    let mut seen = HashSet::new();
+    items.into_iter().all(|item| seen.insert(item))
+}
+
+/// Returns `Ok(())` if the transparent scripts in `block` have at most
+/// [`MAX_BLOCK_SIGOPS`] legacy signature operations.
+pub fn sigops_are_valid(block: &Block) -> Result<(), BlockError> {
+    let sigops: u32 = block
+        .transactions
+        .iter()
+        .map(|transaction| transaction.legacy_sigop_count())
+        .sum();
+    if sigops > MAX_BLOCK_SIGOPS {
+        return Err(BlockError::TooManySigops(sigops));
+    }
+    Ok(())
+}
+
+/// Returns `Ok(())` if every transaction in `block` has a valid serialized
+/// size for a block at `height` on `network`.
+///
+/// Transactions must be at least [`MIN_TRANSACTION_BYTES`] long. Before
+/// Sapling, they can be at most [`MAX_TX_SIZE_BEFORE_SAPLING`] long.
+pub fn transaction_sizes_are_valid(
+    block: &Block,
+    height: block::Height,
+    network: Network,
+) -> Result<(), BlockError> {
+    let sapling = NetworkUpgrade::Sapling.activation_height(network);
+    let max = if sapling.map(|sapling| height >= sapling).unwrap_or(false) {
+        MAX_BLOCK_BYTES
+    } else {
+        MAX_TX_SIZE_BEFORE_SAPLING
+    };
+
+    for transaction in &block.transactions {
+        let size = transaction.zcash_serialized_size();
+        if size < MIN_TRANSACTION_BYTES || size > max {
+            return Err(BlockError::BadTransactionSize { size, max });
+        }
+    }
+    Ok(())
+}
+
+/// Returns `Ok(())` if the coinbase transaction in `block` pays the
+/// founders' reward or funding streams that are required at `height` on
+/// `network`.
+///
+/// Each required payment must be a single transparent output with the exact
+/// amount, and the standard lock script for the receiver's address. Every
+/// funding stream is checked, including the ECC stream, whose address
+/// changes with each address period.
+///
+/// Regtest doesn't have funding stream addresses, so its funding streams
+/// aren't checked.
+pub fn subsidy_is_valid(
+    block: &Block,
+    height: block::Height,
+    network: Network,
+) -> Result<(), BlockError> {
+    let coinbase = block.transactions.get(0).ok_or(BlockError::NoCoinbase)?;
+    let pays = |address: transparent::Address, value: Amount<NonNegative>| {
+        let lock_script = address.lock_script();
+        coinbase
+            .outputs()
+            .any(|output| output.value == value && output.pk_script == lock_script)
+    };
+
+    if let Some(address) = subsidy::founders_reward_address(height, network) {
+        if !pays(address, subsidy::founders_reward(height, network)) {
+            return Err(BlockError::FoundersRewardNotFound);
+        }
+    }
+
+    for (receiver, value) in subsidy::funding_stream_values(height, network) {
+        if let Some(address) = subsidy::funding_stream_address(height, network, receiver) {
+            if !pays(address, value) {
+                return Err(BlockError::FundingStreamNotFound(receiver));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the outputs created by the transactions in `block`, by outpoint.
+///
+/// Transactions can spend the outputs of earlier transactions in the same
+/// block, before they are in the state.
+pub fn block_outputs(block: &Block) -> HashMap<OutPoint, TransparentOutput> {
+    let mut outputs = HashMap::new();
+    for transaction in &block.transactions {
+        let hash = transaction::Hash::from(transaction.as_ref());
+        for (index, output) in transaction.outputs().enumerate() {
+            let outpoint = OutPoint {
+                hash,
+                index: index as u32,
+            };
+            outputs.insert(outpoint, output.clone());
+        }
+    }
+    outputs
+}
+
+/// Returns the fee paid by `transaction`, which spends the outputs in
+/// `utxos`.
+///
+/// The fee is the sum of the value that the transaction removes from each
+/// pool, which can't be negative.
+pub fn transaction_fee(
+    transaction: &Transaction,
+    utxos: &HashMap<OutPoint, TransparentOutput>,
+) -> Result<Amount<NonNegative>, BlockError> {
+    let bad_fee = || BlockError::BadTransactionFee(transaction::Hash::from(transaction));
+    let balance = transaction.value_balance(utxos).map_err(|_| bad_fee())?;
+    let fee = ((balance.transparent_amount() + balance.sprout_amount()) + balance.sapling_amount())
+        + balance.orchard_amount();
+    fee.and_then(|fee| fee.constrain()).map_err(|_| bad_fee())
+}
+
+/// Returns `Ok(())` if the coinbase transaction in `block` creates at most
+/// the block subsidy at `height` on `network`, plus the transaction `fees`.
+///
+/// The created value includes the coinbase's transparent outputs, and any
+/// value it moves into the shielded pools. Like `zcashd`, miners may claim
+/// less than the full amount.
+///
+/// The fees are the sum of the value balances of the other transactions in
+/// the block, so the caller needs the outputs that they spend.
+pub fn coinbase_value_is_valid(
+    block: &Block,
+    height: block::Height,
+    network: Network,
+    fees: Amount<NonNegative>,
+) -> Result<(), BlockError> {
+    let coinbase = block.transactions.get(0).ok_or(BlockError::NoCoinbase)?;
+
+    // Coinbase transactions don't spend any transparent outputs.
+    let balance = coinbase
+        .value_balance(&HashMap::new())
+        .map_err(|_| BlockError::CoinbaseValueOutOfRange)?;
+    let removed = ((balance.transparent_amount() + balance.sprout_amount())
+        + balance.sapling_amount())
+        + balance.orchard_amount();
+    let created: Amount<NonNegative> = removed
+        .and_then(|removed| (-removed).constrain())
+        .map_err(|_| BlockError::CoinbaseValueOutOfRange)?;
+
+    let max = (subsidy::block_subsidy(height, network) + fees)
+        .map_err(|_| BlockError::CoinbaseValueOutOfRange)?;
+    if created > max {
+        return Err(BlockError::CoinbaseValueTooLarge { created, max });
+    }
+    Ok(())
+}
@@ -0,0 +1,241 @@
+//! Contextual difficulty adjustment.
+//!
+//! Each block's difficulty threshold is calculated from the thresholds and
+//! times of the previous [`POW_ADJUSTMENT_BLOCK_SPAN`] blocks. See
+//! "Difficulty adjustment" in the Zcash specification, and
+//! `GetNextWorkRequired()` in zcashd.
+//!
+//! Like zcashd's regtest, Regtest doesn't adjust its difficulty, but it has
+//! minimum difficulty blocks after the genesis block.
+
+use std::cmp::{max, min};
+
+use chrono::{DateTime, Utc};
+
+use zebra_chain::{
+    block::{self, Header},
+    network_upgrade::NetworkUpgrade,
+    work::difficulty::{CompactDifficulty, ExpandedDifficulty},
+    Network,
+};
+
+/// The number of previous blocks whose thresholds are averaged.
+///
+/// `PoWAveragingWindow` in the Zcash specification.
+pub const POW_AVERAGING_WINDOW: usize = 17;
+
+/// The number of block times used to calculate a median time past.
+///
+/// `PoWMedianBlockSpan` in the Zcash specification.
+pub const POW_MEDIAN_BLOCK_SPAN: usize = 11;
+
+/// The number of previous blocks used by the difficulty adjustment.
+///
+/// The oldest median time past ends at the block just before the averaging
+/// window, so we need both spans.
+pub const POW_ADJUSTMENT_BLOCK_SPAN: usize = POW_AVERAGING_WINDOW + POW_MEDIAN_BLOCK_SPAN;
+
+/// Only a quarter of the difference between the actual and target timespans
+/// is used to adjust the threshold.
+///
+/// `PoWDampingFactor` in the Zcash specification.
+pub const POW_DAMPING_FACTOR: i64 = 4;
+
+/// The largest percentage decrease in the damped timespan.
+///
+/// `PoWMaxAdjustUp` in the Zcash specification. A shorter timespan makes
+/// the difficulty go up.
+pub const POW_MAX_ADJUST_UP_PERCENT: i64 = 16;
+
+/// The largest percentage increase in the damped timespan.
+///
+/// `PoWMaxAdjustDown` in the Zcash specification.
+pub const POW_MAX_ADJUST_DOWN_PERCENT: i64 = 32;
+
+/// The first testnet height where blocks can use the minimum difficulty.
+///
+/// zcashd allows minimum difficulty blocks after height 299187.
+pub const TESTNET_MINIMUM_DIFFICULTY_START_HEIGHT: block::Height = block::Height(299_188);
+
+/// The first regtest height where blocks can use the minimum difficulty.
+///
+/// zcashd allows minimum difficulty blocks after the regtest genesis block.
+pub const REGTEST_MINIMUM_DIFFICULTY_START_HEIGHT: block::Height = block::Height(1);
+
+/// Testnet and regtest blocks can use the minimum difficulty if they are
+/// more than this many target spacings after the previous block.
+pub const TESTNET_MINIMUM_DIFFICULTY_GAP_MULTIPLIER: i32 = 6;
+
+/// The difficulty adjustment inputs for a candidate block.
+#[derive(Clone, Debug)]
+pub struct AdjustedDifficulty {
+    /// The time of the candidate block.
+    candidate_time: DateTime<Utc>,
+    /// The height of the candidate block.
+    candidate_height: block::Height,
+    /// The network of the candidate block.
+    network: Network,
+    /// The thresholds of the previous blocks, most recent first.
+    relevant_difficulty_thresholds: Vec<CompactDifficulty>,
+    /// The times of the previous blocks, most recent first.
+    relevant_times: Vec<DateTime<Utc>>,
+}
+
+impl AdjustedDifficulty {
+    /// Returns the difficulty adjustment for `candidate_header` at
+    /// `candidate_height` on `network`.
+    ///
+    /// `context` is the headers of the previous blocks, most recent first.
+    /// Only the first [`POW_ADJUSTMENT_BLOCK_SPAN`] headers are used. Near
+    /// the genesis block, `context` can be shorter.
+    pub fn new<C>(
+        candidate_header: &Header,
+        candidate_height: block::Height,
+        network: Network,
+        context: C,
+    ) -> AdjustedDifficulty
+    where
+        C: IntoIterator<Item = Header>,
+    {
+        AdjustedDifficulty::new_from_time(candidate_header.time, candidate_height, network, context)
+    }
+
+    /// Like [`AdjustedDifficulty::new`], but for a candidate block that
+    /// only has a time, like a block template.
+    pub fn new_from_time<C>(
+        candidate_time: DateTime<Utc>,
+        candidate_height: block::Height,
+        network: Network,
+        context: C,
+    ) -> AdjustedDifficulty
+    where
+        C: IntoIterator<Item = Header>,
+    {
+        let (relevant_difficulty_thresholds, relevant_times): (Vec<_>, Vec<_>) = context
+            .into_iter()
+            .take(POW_ADJUSTMENT_BLOCK_SPAN)
+            .map(|header| (header.bits, header.time))
+            .unzip();
+
+        AdjustedDifficulty {
+            candidate_time,
+            candidate_height,
+            network,
+            relevant_difficulty_thresholds,
+            relevant_times,
+        }
+    }
+
+    /// Returns the difficulty threshold that the candidate block must have.
+    pub fn expected_difficulty_threshold(&self) -> CompactDifficulty {
+        let limit = ExpandedDifficulty::target_difficulty_limit(self.network);
+
+        if self.is_minimum_difficulty_block() {
+            return limit.to_compact();
+        }
+
+        // zcashd uses the limit until the block before the averaging window
+        // exists.
+        if self.relevant_times.len() <= POW_AVERAGING_WINDOW {
+            return limit.to_compact();
+        }
+
+        // zcashd's regtest sets `fPowNoRetargeting`, so each block has the
+        // threshold of the previous block.
+        if self.network == Network::Regtest {
+            return self.relevant_difficulty_thresholds[0];
+        }
+
+        let averaging_window_timespan = self.averaging_window_timespan();
+        let threshold = (self.mean_target_difficulty() / averaging_window_timespan as u64)
+            * self.median_timespan_bounded() as u64;
+
+        min(threshold, limit).to_compact()
+    }
+
+    /// Returns true if the candidate is a testnet or regtest block that is
+    /// allowed to use the minimum difficulty, because it is long after the
+    /// previous block.
+    fn is_minimum_difficulty_block(&self) -> bool {
+        let start_height = match self.network {
+            Network::Mainnet => return false,
+            Network::Testnet => TESTNET_MINIMUM_DIFFICULTY_START_HEIGHT,
+            Network::Regtest => REGTEST_MINIMUM_DIFFICULTY_START_HEIGHT,
+        };
+        if self.candidate_height < start_height {
+            return false;
+        }
+
+        let previous_time = match self.relevant_times.first() {
+            Some(time) => *time,
+            None => return false,
+        };
+        let spacing =
+            NetworkUpgrade::target_spacing_for_height(self.network, self.candidate_height);
+
+        self.candidate_time > previous_time + spacing * TESTNET_MINIMUM_DIFFICULTY_GAP_MULTIPLIER
+    }
+
+    /// Returns the target timespan of the averaging window, in seconds.
+    ///
+    /// Uses the target spacing at the candidate height, so the timespan
+    /// halves at Blossom activation.
+    fn averaging_window_timespan(&self) -> i64 {
+        let spacing =
+            NetworkUpgrade::target_spacing_for_height(self.network, self.candidate_height);
+        spacing.num_seconds() * POW_AVERAGING_WINDOW as i64
+    }
+
+    /// Returns the mean of the thresholds in the averaging window, rounded
+    /// down.
+    fn mean_target_difficulty(&self) -> ExpandedDifficulty {
+        let total: ExpandedDifficulty = self.relevant_difficulty_thresholds[..POW_AVERAGING_WINDOW]
+            .iter()
+            .map(|bits| {
+                bits.to_expanded()
+                    .expect("previous blocks in the state have valid thresholds")
+            })
+            .sum();
+
+        total / POW_AVERAGING_WINDOW as u64
+    }
+
+    /// Returns the damped and bounded time between the median times past at
+    /// the start and end of the averaging window, in seconds.
+    fn median_timespan_bounded(&self) -> i64 {
+        let averaging_window_timespan = self.averaging_window_timespan();
+
+        // Like zcashd, we use integer division, which truncates towards zero.
+        let damped = averaging_window_timespan
+            + (self.median_timespan() - averaging_window_timespan) / POW_DAMPING_FACTOR;
+
+        let min_timespan = averaging_window_timespan * (100 - POW_MAX_ADJUST_UP_PERCENT) / 100;
+        let max_timespan = averaging_window_timespan * (100 + POW_MAX_ADJUST_DOWN_PERCENT) / 100;
+
+        min(max(damped, min_timespan), max_timespan)
+    }
+
+    /// Returns the time between the median time past of the previous block,
+    /// and the median time past of the block before the averaging window,
+    /// in seconds.
+    fn median_timespan(&self) -> i64 {
+        let newer_median = median_time(&self.relevant_times[..POW_MEDIAN_BLOCK_SPAN]);
+        let older_median = median_time(&self.relevant_times[POW_AVERAGING_WINDOW..]);
+
+        (newer_median - older_median).num_seconds()
+    }
+}
+
+/// Returns the median of `times`.
+///
+/// If there are an even number of times, returns the later of the two middle
+/// times, like zcashd.
+///
+/// # Panics
+///
+/// If `times` is empty.
+pub fn median_time(times: &[DateTime<Utc>]) -> DateTime<Utc> {
+    let mut times = times.to_vec();
+    times.sort_unstable();
+    times[times.len() / 2]
+}
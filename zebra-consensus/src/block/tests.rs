@@ -0,0 +1,1127 @@
+//! Tests for block verification.
+
+use std::sync::Arc;
+
+use color_eyre::Report;
+use eyre::{ensure, eyre};
+use tower::{Service, ServiceExt};
+
+use zebra_chain::{
+    block::{self, Block},
+    serialization::ZcashDeserialize,
+    Network,
+};
+
+use super::*;
+
+fn block(bytes: &[u8]) -> Result<Block, Report> {
+    Ok(Block::zcash_deserialize(bytes)?)
+}
+
+/// Changes the nonce of `block`, starting at `nonce`, until it has a regtest
+/// Equihash solution. Returns the next nonce to try.
+fn solve_regtest_equihash(block: &mut Block, mut nonce: u64) -> u64 {
+    use zebra_chain::work::equihash::Solution;
+
+    loop {
+        block.header.nonce[..8].copy_from_slice(&nonce.to_le_bytes());
+        nonce += 1;
+        if let Some(solution) = Solution::solve_regtest(&block.header) {
+            block.header.solution = solution;
+            return nonce;
+        }
+    }
+}
+
+/// Sets the difficulty threshold of `block` to the Regtest limit, then changes
+/// its nonce until it has a regtest Equihash solution and its hash meets the
+/// threshold.
+///
+/// This is enough to verify a changed header on Regtest.
+fn solve_for_regtest(block: &mut Block) {
+    use zebra_chain::work::difficulty::ExpandedDifficulty;
+
+    block.header.bits = ExpandedDifficulty::target_difficulty_limit(Network::Regtest).to_compact();
+    let threshold = block
+        .header
+        .bits
+        .to_expanded()
+        .expect("the Regtest limit is a valid threshold");
+    let mut nonce = 0;
+    loop {
+        nonce = solve_regtest_equihash(block, nonce);
+        if block.hash() <= threshold {
+            return;
+        }
+    }
+}
+
+/// Returns the `BlockError` from a verification failure, if there is one.
+fn block_error(error: &Error) -> Option<&BlockError> {
+    error
+        .downcast_ref::<VerificationError>()
+        .and_then(|error| error.inner().downcast_ref::<BlockError>())
+}
+
+#[tokio::test]
+async fn verify_mainnet_blocks() -> Result<(), Report> {
+    let mut verifier = BlockVerifier::new(
+        Network::Mainnet,
+        zebra_state::in_memory::init(Network::Mainnet),
+    );
+
+    for bytes in &[
+        &zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..],
+        &zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..],
+    ] {
+        let block = Arc::new(block(bytes)?);
+        let hash = verifier
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(block.clone())
+            .await
+            .map_err(|e| eyre!(e))?;
+        ensure!(hash == block.hash(), "verifier returned the wrong hash");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn children_wait_for_their_pending_parent() -> Result<(), Report> {
+    let mut verifier = BlockVerifier::new(
+        Network::Mainnet,
+        zebra_state::in_memory::init(Network::Mainnet),
+    );
+
+    let genesis = Arc::new(block(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?);
+    let block1 = Arc::new(block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?);
+    let genesis_verified = verifier
+        .ready_and()
+        .await
+        .map_err(|e| eyre!(e))?
+        .call(genesis.clone());
+    let block1_verified = verifier
+        .ready_and()
+        .await
+        .map_err(|e| eyre!(e))?
+        .call(block1.clone());
+
+    // The child is polled first, so it has to wait for its parent.
+    let (hash1, genesis_hash) = futures::future::join(block1_verified, genesis_verified).await;
+    ensure!(
+        hash1.map_err(|e| eyre!(e))? == block1.hash(),
+        "verifier returned the wrong hash"
+    );
+    ensure!(
+        genesis_hash.map_err(|e| eyre!(e))? == genesis.hash(),
+        "verifier returned the wrong hash"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn bad_equihash_solution_is_rejected() -> Result<(), Report> {
+    let mut verifier = BlockVerifier::new(
+        Network::Mainnet,
+        zebra_state::in_memory::init(Network::Mainnet),
+    );
+
+    let mut block = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    block.header.nonce[0] ^= 0xff;
+
+    let error = verifier
+        .call(Arc::new(block))
+        .await
+        .expect_err("the solution doesn't match the nonce");
+    ensure!(
+        matches!(block_error(&error), Some(BlockError::Equihash(_))),
+        "unexpected error: {:?}",
+        error
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn regtest_checks_regtest_equihash_solutions() -> Result<(), Report> {
+    let mut verifier = BlockVerifier::new(
+        Network::Regtest,
+        zebra_state::in_memory::init(Network::Regtest),
+    );
+
+    // Mainnet block 1 has a valid Equihash (200, 9) solution, but Regtest
+    // uses Equihash (48, 5).
+    let mut block = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    let error = verifier
+        .call(Arc::new(block.clone()))
+        .await
+        .expect_err("mainnet solutions are the wrong size for Regtest");
+    ensure!(
+        matches!(
+            block_error(&error),
+            Some(BlockError::WrongEquihashSolutionSize(1344))
+        ),
+        "unexpected error: {:?}",
+        error
+    );
+
+    solve_for_regtest(&mut block);
+    block.header.nonce[31] ^= 0xff;
+    let error = verifier
+        .call(Arc::new(block))
+        .await
+        .expect_err("the solution doesn't match the nonce");
+    ensure!(
+        matches!(block_error(&error), Some(BlockError::Equihash(_))),
+        "unexpected error: {:?}",
+        error
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn bad_merkle_root_is_rejected() -> Result<(), Report> {
+    // Regtest solutions are cheap, so we can change the header.
+    let mut verifier = BlockVerifier::new(
+        Network::Regtest,
+        zebra_state::in_memory::init(Network::Regtest),
+    );
+
+    let mut block = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    block.header.merkle_root = merkle::Root([0; 32]);
+    solve_for_regtest(&mut block);
+
+    let error = verifier
+        .call(Arc::new(block))
+        .await
+        .expect_err("the merkle root doesn't match the transactions");
+    ensure!(
+        matches!(block_error(&error), Some(BlockError::BadMerkleRoot { .. })),
+        "unexpected error: {:?}",
+        error
+    );
+
+    let error = error
+        .downcast_ref::<VerificationError>()
+        .expect("rule violations are verification errors");
+    ensure!(
+        matches!(error, VerificationError::Header { .. }) && error.is_misbehavior(),
+        "the merkle root is a header rule"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn second_coinbase_is_rejected() -> Result<(), Report> {
+    let mut verifier = BlockVerifier::new(
+        Network::Regtest,
+        zebra_state::in_memory::init(Network::Regtest),
+    );
+
+    let mut block = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    let coinbase = block.transactions[0].clone();
+    block.transactions.push(coinbase);
+
+    let error = verifier
+        .call(Arc::new(block))
+        .await
+        .expect_err("only the first transaction can be a coinbase transaction");
+    ensure!(
+        matches!(block_error(&error), Some(BlockError::CoinbaseNotFirst)),
+        "unexpected error: {:?}",
+        error
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn regtest_checks_the_difficulty_filter() -> Result<(), Report> {
+    use zebra_chain::work::difficulty::CompactDifficulty;
+
+    let mut verifier = BlockVerifier::new(
+        Network::Regtest,
+        zebra_state::in_memory::init(Network::Regtest),
+    );
+
+    // The Equihash solution is valid, but the hash must also meet the
+    // threshold.
+    let mut block = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    block.header.bits = CompactDifficulty(0x0300_0001);
+    solve_regtest_equihash(&mut block, 0);
+
+    let error = verifier
+        .call(Arc::new(block))
+        .await
+        .expect_err("the block hash is greater than 1");
+    ensure!(
+        matches!(block_error(&error), Some(BlockError::DifficultyFilter(..))),
+        "unexpected error: {:?}",
+        error
+    );
+
+    Ok(())
+}
+
+#[test]
+fn difficulty_threshold_checks() -> Result<(), Report> {
+    use zebra_chain::work::difficulty::CompactDifficulty;
+
+    let genesis = block(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?;
+    let hash = genesis.hash();
+    check::difficulty_is_valid(&genesis.header, Network::Mainnet, &hash)?;
+
+    let with_bits = |bits| {
+        let mut header = genesis.header;
+        header.bits = CompactDifficulty(bits);
+        check::difficulty_is_valid(&header, Network::Mainnet, &hash)
+    };
+    ensure!(
+        matches!(
+            with_bits(0x1f80_0001),
+            Err(BlockError::InvalidDifficulty(_))
+        ),
+        "negative thresholds are invalid"
+    );
+    ensure!(
+        matches!(
+            with_bits(0x1f08_0000),
+            Err(BlockError::TargetDifficultyLimit(_))
+        ),
+        "thresholds must be below the mainnet limit"
+    );
+    ensure!(
+        matches!(
+            with_bits(0x0300_0001),
+            Err(BlockError::DifficultyFilter(..))
+        ),
+        "the genesis hash is greater than 1"
+    );
+
+    // The testnet limit is easier than the mainnet limit.
+    let mut header = genesis.header;
+    header.bits = CompactDifficulty(0x1f08_0000);
+    check::difficulty_is_valid(&header, Network::Testnet, &hash)?;
+
+    Ok(())
+}
+
+#[test]
+fn future_block_times_are_rejected() -> Result<(), Report> {
+    use chrono::Duration;
+
+    let header = block(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.header;
+
+    check::time_is_valid_at(&header, header.time)?;
+    check::time_is_valid_at(&header, header.time - Duration::hours(2))?;
+    ensure!(
+        check::time_is_valid_at(&header, header.time - Duration::hours(3)).is_err(),
+        "blocks more than two hours in the future are invalid"
+    );
+
+    Ok(())
+}
+
+/// Returns `count` headers before `candidate`, most recent first.
+///
+/// The headers are `spacing` seconds apart, and have difficulty threshold
+/// `bits`.
+fn previous_headers(
+    candidate: &block::Header,
+    spacing: i64,
+    bits: u32,
+    count: usize,
+) -> Vec<block::Header> {
+    use chrono::Duration;
+    use zebra_chain::work::difficulty::CompactDifficulty;
+
+    (1..=count)
+        .map(|i| {
+            let mut header = *candidate;
+            header.time = candidate.time - Duration::seconds(spacing * i as i64);
+            header.bits = CompactDifficulty(bits);
+            header
+        })
+        .collect()
+}
+
+#[test]
+fn difficulty_adjustment() -> Result<(), Report> {
+    use difficulty::{AdjustedDifficulty, POW_ADJUSTMENT_BLOCK_SPAN, POW_AVERAGING_WINDOW};
+    use zebra_chain::work::difficulty::CompactDifficulty;
+
+    let candidate = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.header;
+    let expected = |height, spacing, bits, count| {
+        let context = previous_headers(&candidate, spacing, bits, count);
+        AdjustedDifficulty::new(&candidate, block::Height(height), Network::Mainnet, context)
+            .expected_difficulty_threshold()
+    };
+
+    // The limit is used until there is a block before the averaging window.
+    let limit = CompactDifficulty(0x1f07_ffff);
+    ensure!(
+        expected(1, 150, 0x1c09_f600, 0) == limit,
+        "no previous blocks"
+    );
+    ensure!(
+        expected(17, 150, 0x1c09_f600, POW_AVERAGING_WINDOW) == limit,
+        "not enough previous blocks"
+    );
+
+    // Blocks at the target spacing keep the same threshold. The mantissa is
+    // a multiple of the pre-Blossom and post-Blossom averaging window
+    // timespans, so there is no rounding.
+    let steady = CompactDifficulty(0x1c09_f600);
+    ensure!(
+        expected(100_000, 150, steady.0, POW_ADJUSTMENT_BLOCK_SPAN) == steady,
+        "pre-Blossom target spacing"
+    );
+    ensure!(
+        expected(700_000, 75, steady.0, POW_ADJUSTMENT_BLOCK_SPAN) == steady,
+        "post-Blossom target spacing"
+    );
+
+    // Fast and slow blocks are limited by the maximum adjustments:
+    // 2550 * 84% = 2142 = 0x85e, and 2550 * 132% = 3366 = 0xd26.
+    ensure!(
+        expected(100_000, 1, steady.0, POW_ADJUSTMENT_BLOCK_SPAN) == CompactDifficulty(0x1c08_5e00),
+        "fast blocks make the difficulty go up"
+    );
+    ensure!(
+        expected(100_000, 1000, steady.0, POW_ADJUSTMENT_BLOCK_SPAN)
+            == CompactDifficulty(0x1c0d_2600),
+        "slow blocks make the difficulty go down"
+    );
+
+    // Extra headers are ignored.
+    ensure!(
+        expected(100_000, 150, steady.0, POW_ADJUSTMENT_BLOCK_SPAN + 10) == steady,
+        "only the adjustment span is used"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn testnet_minimum_difficulty_blocks() -> Result<(), Report> {
+    use difficulty::{AdjustedDifficulty, POW_ADJUSTMENT_BLOCK_SPAN};
+    use zebra_chain::work::difficulty::CompactDifficulty;
+
+    let mut candidate = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.header;
+    let steady = CompactDifficulty(0x1c09_f600);
+    let limit = CompactDifficulty(0x2007_ffff);
+    let context = previous_headers(&candidate, 150, steady.0, POW_ADJUSTMENT_BLOCK_SPAN);
+
+    let expected = |candidate: &block::Header, network, height| {
+        AdjustedDifficulty::new(
+            candidate,
+            block::Height(height),
+            network,
+            context.iter().copied(),
+        )
+        .expected_difficulty_threshold()
+    };
+
+    // Six target spacings after the previous block isn't enough.
+    candidate.time = context[0].time + chrono::Duration::seconds(6 * 150);
+    ensure!(
+        expected(&candidate, Network::Testnet, 300_000) == steady,
+        "gap is not long enough"
+    );
+
+    candidate.time = context[0].time + chrono::Duration::seconds(6 * 150 + 1);
+    ensure!(
+        expected(&candidate, Network::Testnet, 300_000) == limit,
+        "long gaps allow the minimum difficulty"
+    );
+    ensure!(
+        expected(&candidate, Network::Testnet, 299_187) != limit,
+        "minimum difficulty blocks start at 299188"
+    );
+    ensure!(
+        expected(&candidate, Network::Mainnet, 300_000) != CompactDifficulty(0x1f07_ffff),
+        "mainnet doesn't have minimum difficulty blocks"
+    );
+
+    // The block is still checked against the adjusted threshold.
+    candidate.bits = steady;
+    ensure!(
+        matches!(
+            check::difficulty_threshold_is_adjusted(
+                &candidate,
+                block::Height(300_000),
+                Network::Testnet,
+                &context
+            ),
+            Err(BlockError::BadDifficultyThreshold { .. })
+        ),
+        "the threshold must be the minimum difficulty"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn regtest_difficulty_is_not_adjusted() -> Result<(), Report> {
+    use difficulty::{AdjustedDifficulty, POW_ADJUSTMENT_BLOCK_SPAN};
+    use zebra_chain::work::difficulty::CompactDifficulty;
+
+    let mut candidate = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.header;
+    let limit = CompactDifficulty(0x200f_0f0f);
+    let harder = CompactDifficulty(0x1f0f_0f0f);
+
+    let expected = |candidate: &block::Header, bits, count| {
+        let context = previous_headers(candidate, 1, bits, count);
+        AdjustedDifficulty::new(candidate, block::Height(100), Network::Regtest, context)
+            .expected_difficulty_threshold()
+    };
+
+    // Fast blocks don't make the difficulty go up.
+    ensure!(
+        expected(&candidate, limit.0, POW_ADJUSTMENT_BLOCK_SPAN) == limit,
+        "regtest blocks keep the limit"
+    );
+    ensure!(
+        expected(&candidate, harder.0, POW_ADJUSTMENT_BLOCK_SPAN) == harder,
+        "regtest blocks keep the previous threshold"
+    );
+    ensure!(
+        expected(&candidate, harder.0, 3) == limit,
+        "the limit is used until there is a block before the averaging window"
+    );
+
+    // Regtest has minimum difficulty blocks, using the post-Blossom spacing.
+    let context = previous_headers(&candidate, 1, harder.0, POW_ADJUSTMENT_BLOCK_SPAN);
+    candidate.time = context[0].time + chrono::Duration::seconds(6 * 75 + 1);
+    ensure!(
+        AdjustedDifficulty::new(&candidate, block::Height(100), Network::Regtest, context)
+            .expected_difficulty_threshold()
+            == limit,
+        "long gaps allow the minimum difficulty"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn regtest_thresholds_are_checked() -> Result<(), Report> {
+    use zebra_chain::work::difficulty::CompactDifficulty;
+
+    let mut block = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    let context = previous_headers(&block.header, 150, 0x200f_0f0f, 3);
+
+    // Near the genesis block, Regtest blocks must use the limit, even if
+    // they meet a harder threshold.
+    block.header.bits = CompactDifficulty(0x1f0f_0f0f);
+    let result = check_contextual(Network::Regtest, &block, block::Height(1), &context);
+    ensure!(
+        matches!(
+            result,
+            Err(BlockError::BadDifficultyThreshold {
+                expected: CompactDifficulty(0x200f_0f0f),
+                ..
+            })
+        ),
+        "unexpected result: {:?}",
+        result
+    );
+
+    Ok(())
+}
+
+#[test]
+fn median_time_past_checks() -> Result<(), Report> {
+    use chrono::Duration;
+
+    let mut header = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.header;
+
+    // The genesis block has no previous blocks.
+    check::time_is_after_median_time_past(&header, &[])?;
+
+    // The median of 11 blocks spaced 150 seconds apart is 6 blocks back, and
+    // older blocks are ignored.
+    let context = previous_headers(&header, 150, header.bits.0, 20);
+    check::time_is_after_median_time_past(&header, &context)?;
+
+    header.time = context[5].time + Duration::seconds(1);
+    check::time_is_after_median_time_past(&header, &context)?;
+
+    header.time = context[5].time;
+    ensure!(
+        matches!(
+            check::time_is_after_median_time_past(&header, &context),
+            Err(BlockError::TimeTooEarly)
+        ),
+        "block times must be after the median time past"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn early_block_time_is_rejected() -> Result<(), Report> {
+    let mut verifier = BlockVerifier::new(
+        Network::Regtest,
+        zebra_state::in_memory::init(Network::Regtest),
+    );
+
+    let genesis = zebra_chain::parameters::genesis::genesis_block(Network::Regtest);
+    verifier.call(genesis.clone()).await.map_err(|e| eyre!(e))?;
+
+    // Regtest solutions are cheap, so we can change the header.
+    let mut block = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    block.header.previous_block_hash = genesis.hash();
+    block.header.time = genesis.header.time;
+    solve_for_regtest(&mut block);
+
+    let error = verifier
+        .call(Arc::new(block))
+        .await
+        .expect_err("the block time is the same as the previous block");
+    ensure!(
+        matches!(block_error(&error), Some(BlockError::TimeTooEarly)),
+        "unexpected error: {:?}",
+        error
+    );
+
+    let error = error
+        .downcast_ref::<VerificationError>()
+        .expect("rule violations are verification errors");
+    ensure!(
+        matches!(error, VerificationError::Contextual { .. }) && !error.is_misbehavior(),
+        "the median time past depends on the chain"
+    );
+
+    Ok(())
+}
+
+/// Returns mainnet block 1, with `f` applied to its coinbase outputs.
+fn block1_with_coinbase_outputs(
+    f: impl FnOnce(&mut Vec<zebra_chain::transaction::TransparentOutput>),
+) -> Result<Block, Report> {
+    use zebra_chain::transaction::Transaction;
+
+    let mut block = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    let mut coinbase = block.transactions[0].as_ref().clone();
+    match &mut coinbase {
+        Transaction::V1 { outputs, .. } => f(outputs),
+        _ => return Err(eyre!("mainnet block 1 has a version 1 coinbase")),
+    }
+    block.transactions[0] = Arc::new(coinbase);
+    Ok(block)
+}
+
+#[test]
+fn founders_reward_is_required() -> Result<(), Report> {
+    let height = block::Height(1);
+    let block1 = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    check::subsidy_is_valid(&block1, height, Network::Mainnet)?;
+
+    ensure!(
+        matches!(
+            check::subsidy_is_valid(&block1, height, Network::Regtest),
+            Err(BlockError::FoundersRewardNotFound)
+        ),
+        "the founders' reward address is different on Regtest"
+    );
+
+    let founders_reward =
+        zebra_chain::parameters::subsidy::founders_reward(height, Network::Mainnet);
+    let unpaid = block1_with_coinbase_outputs(|outputs| {
+        outputs.retain(|output| output.value != founders_reward)
+    })?;
+    ensure!(
+        matches!(
+            check::subsidy_is_valid(&unpaid, height, Network::Mainnet),
+            Err(BlockError::FoundersRewardNotFound)
+        ),
+        "the founders' reward must be paid"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn coinbase_value_is_bounded() -> Result<(), Report> {
+    use std::convert::TryFrom;
+    use zebra_chain::amount::Amount;
+
+    let height = block::Height(1);
+    let block1 = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    check::coinbase_value_is_valid(&block1, height, Network::Mainnet, Amount::zero())?;
+
+    let overpaid = block1_with_coinbase_outputs(|outputs| {
+        outputs[0].value = (outputs[0].value + Amount::try_from(1u64).unwrap()).unwrap()
+    })?;
+    ensure!(
+        matches!(
+            check::coinbase_value_is_valid(&overpaid, height, Network::Mainnet, Amount::zero()),
+            Err(BlockError::CoinbaseValueTooLarge { .. })
+        ),
+        "the coinbase can't create more than the block subsidy"
+    );
+
+    // The miner can also claim the transaction fees.
+    let fees = Amount::try_from(1u64)?;
+    check::coinbase_value_is_valid(&overpaid, height, Network::Mainnet, fees)?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn overpaid_coinbase_is_rejected() -> Result<(), Report> {
+    use std::convert::TryFrom;
+    use zebra_chain::amount::{Amount, COIN};
+
+    // Regtest solutions are cheap, so we can change the header.
+    let mut verifier = BlockVerifier::new(
+        Network::Regtest,
+        zebra_state::in_memory::init(Network::Regtest),
+    );
+
+    // More than the full block subsidy on any network.
+    let mut block = block1_with_coinbase_outputs(|outputs| {
+        outputs[0].value = Amount::try_from(100 * COIN).unwrap()
+    })?;
+    block.header.merkle_root = merkle::Root::from_transactions(&block.transactions);
+    solve_for_regtest(&mut block);
+
+    let error = verifier
+        .call(Arc::new(block))
+        .await
+        .expect_err("the coinbase can't create more than the subsidy and fees");
+    ensure!(
+        matches!(
+            block_error(&error),
+            Some(BlockError::CoinbaseValueTooLarge { .. })
+        ),
+        "unexpected error: {:?}",
+        error
+    );
+
+    Ok(())
+}
+
+#[test]
+fn transaction_fees_are_spent_minus_created_value() -> Result<(), Report> {
+    use std::{collections::HashMap, convert::TryFrom};
+    use zebra_chain::{
+        amount::Amount,
+        transaction::{LockTime, OutPoint, Transaction, TransparentInput, TransparentOutput},
+        transparent::Script,
+    };
+
+    let block1 = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    let outputs = check::block_outputs(&block1);
+    let coinbase_outputs: Vec<_> = block1.transactions[0].outputs().cloned().collect();
+    ensure!(
+        outputs.len() == coinbase_outputs.len(),
+        "every output is indexed"
+    );
+
+    let outpoint = OutPoint {
+        hash: zebra_chain::transaction::Hash::from(block1.transactions[0].as_ref()),
+        index: 0,
+    };
+    let spent = outputs[&outpoint].value;
+    let spend = |value| Transaction::V1 {
+        inputs: vec![TransparentInput::PrevOut {
+            outpoint,
+            script: Script(vec![].into()),
+            sequence: u32::MAX,
+        }],
+        outputs: vec![TransparentOutput {
+            value,
+            pk_script: Script(vec![].into()),
+        }],
+        lock_time: LockTime::unlocked(),
+    };
+
+    let fee = Amount::try_from(1_000u64)?;
+    let transaction = spend((spent - fee)?);
+    ensure!(
+        check::transaction_fee(&transaction, &outputs)? == fee,
+        "the fee is the spent value minus the created value"
+    );
+
+    let transaction = spend((spent + Amount::try_from(1u64)?)?);
+    ensure!(
+        matches!(
+            check::transaction_fee(&transaction, &outputs),
+            Err(BlockError::BadTransactionFee(_))
+        ),
+        "transactions can't create more than they spend"
+    );
+    ensure!(
+        matches!(
+            check::transaction_fee(&transaction, &HashMap::new()),
+            Err(BlockError::BadTransactionFee(_))
+        ),
+        "the spent outputs are needed to calculate the fee"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn sigop_limit_is_checked() -> Result<(), Report> {
+    use zebra_chain::{amount::Amount, transaction::TransparentOutput, transparent::Script};
+
+    let block1 = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    check::sigops_are_valid(&block1)?;
+
+    // Each OP_CHECKMULTISIG counts as 20 signature operations.
+    let too_many = block1_with_coinbase_outputs(|outputs| {
+        outputs.push(TransparentOutput {
+            value: Amount::zero(),
+            pk_script: Script(vec![0xae; 1_001].into()),
+        })
+    })?;
+    ensure!(
+        matches!(
+            check::sigops_are_valid(&too_many),
+            Err(BlockError::TooManySigops(_))
+        ),
+        "blocks can have at most 20,000 signature operations"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn pre_sapling_transaction_size_is_limited() -> Result<(), Report> {
+    use zebra_chain::{amount::Amount, transaction::TransparentOutput, transparent::Script};
+
+    let block1 = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    check::transaction_sizes_are_valid(&block1, block::Height(1), Network::Mainnet)?;
+
+    let large = block1_with_coinbase_outputs(|outputs| {
+        outputs.push(TransparentOutput {
+            value: Amount::zero(),
+            pk_script: Script(vec![0; check::MAX_TX_SIZE_BEFORE_SAPLING].into()),
+        })
+    })?;
+    ensure!(
+        matches!(
+            check::transaction_sizes_are_valid(&large, block::Height(1), Network::Mainnet),
+            Err(BlockError::BadTransactionSize { .. })
+        ),
+        "transactions are limited to 100 kB before Sapling"
+    );
+    check::transaction_sizes_are_valid(&large, block::Height(419_200), Network::Mainnet)?;
+
+    Ok(())
+}
+
+#[test]
+fn duplicate_spends_are_rejected() -> Result<(), Report> {
+    use zebra_chain::{
+        transaction::{self, LockTime, OutPoint, Transaction, TransparentInput},
+        transparent::Script,
+    };
+
+    let mut block = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    check::spends_are_unique(&block)?;
+
+    let spend = |index| {
+        Arc::new(Transaction::V1 {
+            inputs: vec![TransparentInput::PrevOut {
+                outpoint: OutPoint {
+                    hash: transaction::Hash([0x22; 32]),
+                    index,
+                },
+                script: Script(vec![].into()),
+                sequence: u32::MAX,
+            }],
+            outputs: vec![],
+            lock_time: LockTime::unlocked(),
+        })
+    };
+    block.transactions.push(spend(0));
+    block.transactions.push(spend(1));
+    check::spends_are_unique(&block)?;
+
+    block.transactions.push(spend(0));
+    ensure!(
+        matches!(
+            check::spends_are_unique(&block),
+            Err(BlockError::DuplicateTransparentSpend(OutPoint {
+                index: 0,
+                ..
+            }))
+        ),
+        "each output can only be spent once in a block"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn duplicate_transactions_are_rejected() -> Result<(), Report> {
+    use zebra_chain::{
+        block::merkle,
+        transaction::{self, LockTime, OutPoint, Transaction, TransparentInput},
+        transparent::Script,
+    };
+
+    let spend = |index| {
+        Arc::new(Transaction::V1 {
+            inputs: vec![TransparentInput::PrevOut {
+                outpoint: OutPoint {
+                    hash: transaction::Hash([0x22; 32]),
+                    index,
+                },
+                script: Script(vec![].into()),
+                sequence: u32::MAX,
+            }],
+            outputs: vec![],
+            lock_time: LockTime::unlocked(),
+        })
+    };
+
+    let mut block = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    block.transactions.push(spend(0));
+    block.transactions.push(spend(1));
+    block.header.merkle_root = merkle::Root::from_transactions(&block.transactions);
+    check::merkle_root_is_valid(&block)?;
+    check::transaction_hashes_are_unique(&block)?;
+
+    // Repeating the last transaction doesn't change the merkle root.
+    let last = spend(1);
+    block.transactions.push(last.clone());
+    check::merkle_root_is_valid(&block)?;
+    ensure!(
+        matches!(
+            check::transaction_hashes_are_unique(&block),
+            Err(BlockError::DuplicateTransaction(hash))
+                if hash == transaction::Hash::from(last.as_ref())
+        ),
+        "each transaction can only be in a block once"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn final_sapling_root_is_checked() -> Result<(), Report> {
+    use zebra_chain::sapling::tree::{NoteCommitmentTree, Root};
+
+    // Block 1 doesn't have any Sapling outputs, so the tree stays empty.
+    let block = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    let empty_root = NoteCommitmentTree::default().root();
+    check::final_sapling_root_is_valid(&block, NoteCommitmentTree::default(), empty_root)?;
+
+    ensure!(
+        matches!(
+            check::final_sapling_root_is_valid(
+                &block,
+                NoteCommitmentTree::default(),
+                Root([0xff; 32])
+            ),
+            Err(BlockError::BadFinalSaplingRoot { .. })
+        ),
+        "the final Sapling root must match the note commitment tree"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn chain_history_commitments_are_checked() -> Result<(), Report> {
+    use zebra_chain::{
+        block::{ChainHistoryBlockTxAuthCommitmentHash, ChainHistoryMmrRootHash},
+        history_tree::HistoryTree,
+        orchard, sapling,
+        transaction::{Transaction, TransparentInput},
+    };
+
+    // Regtest activates Heartwood at height 5, so block 1 at that height
+    // starts the tree.
+    let mut block = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    let mut coinbase = block.transactions[0].as_ref().clone();
+    if let Transaction::V1 { inputs, .. } = &mut coinbase {
+        if let TransparentInput::Coinbase { height, .. } = &mut inputs[0] {
+            *height = block::Height(5);
+        }
+    }
+    block.transactions[0] = Arc::new(coinbase);
+
+    let mut tree = HistoryTree::default();
+    tree.push(
+        Network::Regtest,
+        &block,
+        sapling::tree::Root::default(),
+        orchard::tree::Root::default(),
+    )?;
+    let root = tree.hash().ok_or_else(|| eyre!("the tree has a block"))?;
+    check::chain_history_root_is_valid(&tree, root)?;
+    ensure!(
+        matches!(
+            check::chain_history_root_is_valid(&tree, ChainHistoryMmrRootHash([0; 32])),
+            Err(BlockError::BadChainHistoryRoot { .. })
+        ),
+        "the chain history root must match the tree"
+    );
+
+    // From NU5, the commitment also covers the block's authorizing data.
+    let commitments =
+        ChainHistoryBlockTxAuthCommitmentHash::from_commitments(&root, &block.auth_data_root());
+    check::block_commitments_are_valid(&block, &tree, commitments)?;
+    ensure!(
+        matches!(
+            check::block_commitments_are_valid(&block, &HistoryTree::default(), commitments),
+            Err(BlockError::BadBlockCommitments { .. })
+        ),
+        "the block commitments must match the chain history tree"
+    );
+
+    Ok(())
+}
+
+/// Returns a Regtest block at `height` on top of `parent`, with a coinbase
+/// that pays the founders' reward.
+///
+/// Blocks with different `tag`s have different coinbase transactions, so
+/// they can be on competing forks. Only heights before Sapling are
+/// supported.
+fn regtest_child(parent: &Block, height: u32, tag: u8) -> Result<Arc<Block>, Report> {
+    use zebra_chain::{
+        network_upgrade::NetworkUpgrade,
+        parameters::subsidy,
+        transaction::{CoinbaseData, LockTime, Transaction, TransparentInput, TransparentOutput},
+    };
+
+    let height = block::Height(height);
+    let founders_address = subsidy::founders_reward_address(height, Network::Regtest)
+        .ok_or_else(|| eyre!("early Regtest blocks pay the founders' reward"))?;
+    let inputs = vec![TransparentInput::Coinbase {
+        height,
+        data: CoinbaseData::new(vec![tag]).expect("the tag is short"),
+        sequence: u32::MAX,
+    }];
+    let outputs = vec![TransparentOutput {
+        value: subsidy::founders_reward(height, Network::Regtest),
+        pk_script: founders_address.lock_script(),
+    }];
+    let lock_time = LockTime::unlocked();
+    let coinbase = match NetworkUpgrade::current(Network::Regtest, height) {
+        NetworkUpgrade::BeforeOverwinter => Transaction::V1 {
+            inputs,
+            outputs,
+            lock_time,
+        },
+        NetworkUpgrade::Overwinter => Transaction::V3 {
+            inputs,
+            outputs,
+            lock_time,
+            expiry_height: height,
+            joinsplit_data: None,
+        },
+        upgrade => return Err(eyre!("unsupported network upgrade {:?}", upgrade)),
+    };
+
+    let transactions = vec![Arc::new(coinbase)];
+    let mut block = Block {
+        header: parent.header,
+        transactions,
+    };
+    block.header.previous_block_hash = parent.hash();
+    block.header.merkle_root = merkle::Root::from_transactions(&block.transactions);
+    block.header.time = parent.header.time + chrono::Duration::minutes(1);
+    solve_for_regtest(&mut block);
+
+    Ok(Arc::new(block))
+}
+
+#[tokio::test]
+async fn side_chain_blocks_are_verified_on_disk() -> Result<(), Report> {
+    let cache_dir = tempdir::TempDir::new("zebra_consensus_side_chain")?;
+    let config = zebra_state::Config {
+        cache_dir: cache_dir.path().to_owned(),
+        ..zebra_state::Config::default()
+    };
+    let mut state = zebra_state::on_disk::init(config, Network::Regtest).map_err(|e| eyre!(e))?;
+    let mut verifier = BlockVerifier::new(Network::Regtest, state.clone());
+
+    // Block 1 is the first chain, then a competing block 1 and block 2 are
+    // a longer fork. Verifying fork block 2 looks up its parent, which is on
+    // a side chain until block 2 is added.
+    let genesis = zebra_chain::parameters::genesis::genesis_block(Network::Regtest);
+    let block1 = regtest_child(&genesis, 1, 0)?;
+    let fork1 = regtest_child(&genesis, 1, 1)?;
+    let fork2 = regtest_child(&fork1, 2, 1)?;
+
+    for block in vec![genesis, block1, fork1, fork2.clone()] {
+        let hash = verifier
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(block.clone())
+            .await
+            .map_err(|e| eyre!(e))?;
+        ensure!(hash == block.hash(), "verifier returned the wrong hash");
+    }
+
+    let response = state
+        .ready_and()
+        .await
+        .map_err(|e| eyre!(e))?
+        .call(zebra_state::Request::Tip)
+        .await
+        .map_err(|e| eyre!(e))?;
+    ensure!(
+        matches!(
+            response,
+            zebra_state::Response::BestTip { tip: Some((block::Height(2), hash)) }
+                if hash == fork2.hash()
+        ),
+        "the state should reorg to the fork: {:?}",
+        response
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn blocks_are_verified_on_pruned_states() -> Result<(), Report> {
+    let cache_dir = tempdir::TempDir::new("zebra_consensus_pruned")?;
+    let config = zebra_state::Config {
+        cache_dir: cache_dir.path().to_owned(),
+        prune_depth: Some(1),
+        ..zebra_state::Config::default()
+    };
+    let mut state = zebra_state::on_disk::init(config, Network::Regtest).map_err(|e| eyre!(e))?;
+
+    // Checkpoint sync commits finalized blocks, which prunes the genesis
+    // block body.
+    let genesis = zebra_chain::parameters::genesis::genesis_block(Network::Regtest);
+    let block1 = regtest_child(&genesis, 1, 0)?;
+    let block2 = regtest_child(&block1, 2, 0)?;
+    for block in vec![genesis, block1] {
+        state
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(zebra_state::Request::CommitFinalizedBlock { block })
+            .await
+            .map_err(|e| eyre!(e))?;
+    }
+
+    // The contextual checks use the header of the pruned block.
+    let mut verifier = BlockVerifier::new(Network::Regtest, state);
+    let hash = verifier
+        .ready_and()
+        .await
+        .map_err(|e| eyre!(e))?
+        .call(block2.clone())
+        .await
+        .map_err(|e| eyre!(e))?;
+    ensure!(hash == block2.hash(), "verifier returned the wrong hash");
+
+    Ok(())
+}
@@ -0,0 +1,210 @@
+//! Chain verification.
+//!
+//! The [`ChainVerifier`] sends blocks up to the final checkpoint to the
+//! [`CheckpointVerifier`], and later blocks to the [`BlockVerifier`], so
+//! callers like the syncer can verify the whole chain using a single service.
+//!
+//! The chain verifier can also reject every block above a maximum height, so
+//! a node can stop following the chain at a height it no longer trusts.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::FutureExt;
+use thiserror::Error;
+use tower::{buffer::Buffer, Service, ServiceExt};
+
+use zebra_chain::{
+    block::{self, Block},
+    Network,
+};
+
+use crate::{
+    block::{BlockVerifier, Error},
+    checkpoint::{CheckpointError, CheckpointList, CheckpointVerifier},
+    Config,
+};
+
+/// A block is above the maximum height that the chain verifier accepts.
+#[derive(Error, Debug, Clone, Copy, Eq, PartialEq)]
+#[error("block height {height:?} is above the maximum height {max_height:?}")]
+pub struct AboveMaxHeight {
+    /// The height of the block.
+    pub height: block::Height,
+    /// The maximum height.
+    pub max_height: block::Height,
+}
+
+/// Verifies blocks using the checkpoint verifier `C`, or the block verifier
+/// `B` above the final checkpoint.
+///
+/// Responds with the hash of each block that was added.
+#[derive(Clone, Debug)]
+pub struct ChainVerifier<C, B> {
+    /// The height of the final checkpoint.
+    max_checkpoint_height: block::Height,
+    /// The highest block that can be verified, if there is one.
+    max_height: Option<block::Height>,
+    /// The verifier for blocks up to the final checkpoint.
+    checkpoint_verifier: C,
+    /// The verifier for blocks above the final checkpoint.
+    block_verifier: B,
+}
+
+impl<C, B> Service<Arc<Block>> for ChainVerifier<C, B>
+where
+    C: Service<Arc<Block>, Response = block::Hash, Error = Error> + Send + Clone + 'static,
+    C::Future: Send + 'static,
+    B: Service<Arc<Block>, Response = block::Hash, Error = Error> + Send + Clone + 'static,
+    B::Future: Send + 'static,
+{
+    type Response = block::Hash;
+    type Error = Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Each verifier's readiness is checked in the response future.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, block: Arc<Block>) -> Self::Future {
+        match (block.coinbase_height(), self.max_height) {
+            (Some(height), Some(max_height)) if height > max_height => {
+                async move { Err(AboveMaxHeight { height, max_height }.into()) }.boxed()
+            }
+            (Some(height), _) if height <= self.max_checkpoint_height => {
+                self.checkpoint_verifier.clone().oneshot(block).boxed()
+            }
+            (Some(_), _) => self.block_verifier.clone().oneshot(block).boxed(),
+            (None, _) => async { Err(CheckpointError::NoCoinbaseHeight.into()) }.boxed(),
+        }
+    }
+}
+
+/// Returns a chain verifier for `network`, which adds verified blocks to
+/// `state_service`.
+///
+/// Checkpoint verification resumes after the current tip of
+/// `state_service`, so blocks that are already in the state aren't needed.
+///
+/// Blocks above `max_height` are rejected with [`AboveMaxHeight`], without
+/// being verified. Checkpoints are verified in ranges, so if `max_height` is
+/// between checkpoints, it is lowered to the checkpoint below it. Otherwise
+/// the blocks up to `max_height` could never be verified.
+///
+/// Returns an error if `config` or its checkpoint list is invalid, or the tip
+/// can't be read.
+pub async fn init<S>(
+    config: &Config,
+    network: Network,
+    state_service: S,
+    max_height: Option<block::Height>,
+) -> Result<
+    impl Service<
+            Arc<Block>,
+            Response = block::Hash,
+            Error = Error,
+            Future = impl Future<Output = Result<block::Hash, Error>>,
+        > + Send
+        + Clone
+        + 'static,
+    Error,
+>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = Error>
+        + Send
+        + Clone
+        + 'static,
+    S::Future: Send + 'static,
+{
+    config.validate()?;
+
+    let tip = match state_service
+        .clone()
+        .oneshot(zebra_state::Request::Tip)
+        .await?
+    {
+        zebra_state::Response::BestTip { tip } => tip,
+        response => return Err(format!("unexpected state response: {:?}", response).into()),
+    };
+
+    let checkpoint_list = CheckpointList::from_config(config, network)?;
+    let max_checkpoint_height = checkpoint_list.max_height();
+    let max_height = max_height.map(|max_height| {
+        if max_height < max_checkpoint_height {
+            checkpoint_list.previous_checkpoint(max_height)
+        } else {
+            max_height
+        }
+    });
+    let checkpoint_verifier =
+        CheckpointVerifier::from_checkpoint_list(checkpoint_list, state_service.clone())
+            .resume_from(tip);
+
+    Ok(Buffer::new(
+        ChainVerifier {
+            max_checkpoint_height,
+            max_height,
+            checkpoint_verifier: Buffer::new(checkpoint_verifier, 1),
+            block_verifier: BlockVerifier::from_config(config, network, state_service),
+        },
+        config.max_concurrent_blocks,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use zebra_chain::serialization::ZcashDeserialize;
+
+    #[tokio::test]
+    async fn blocks_above_the_max_height_are_rejected() -> Result<(), Error> {
+        let block1 = Arc::new(Block::zcash_deserialize(
+            &zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..],
+        )?);
+        let verifier = init(
+            &Config::default(),
+            Network::Mainnet,
+            zebra_state::in_memory::init(Network::Mainnet),
+            Some(block::Height(0)),
+        )
+        .await?;
+
+        let error = verifier
+            .oneshot(block1)
+            .await
+            .expect_err("block 1 is above the maximum height");
+        assert_eq!(
+            error.downcast_ref::<AboveMaxHeight>(),
+            Some(&AboveMaxHeight {
+                height: block::Height(1),
+                max_height: block::Height(0),
+            })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn zero_concurrent_blocks_is_rejected() {
+        let config = Config {
+            max_concurrent_blocks: 0,
+            ..Config::default()
+        };
+        let result = init(
+            &config,
+            Network::Mainnet,
+            zebra_state::in_memory::init(Network::Mainnet),
+            None,
+        )
+        .await;
+
+        assert!(result.is_err(), "the verifier would never get a permit");
+    }
+}
@@ -0,0 +1,445 @@
+//! Checkpoint-based block verification.
+//!
+//! During the initial sync, blocks up to the final checkpoint are verified by
+//! checking that their hashes chain back from a hard-coded checkpoint hash to
+//! the previous checkpoint. Each block hash commits to the previous header,
+//! and the merkle root commits to the transactions, so a valid chain of hashes
+//! verifies every block in the range. This is much faster than full semantic
+//! verification.
+//!
+//! Blocks above the final checkpoint must be verified by the
+//! [`BlockVerifier`](crate::block::BlockVerifier).
+
+pub mod list;
+#[cfg(test)]
+mod tests;
+
+pub use list::CheckpointList;
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::{
+    channel::{mpsc, oneshot},
+    FutureExt,
+};
+use thiserror::Error;
+use tower::{buffer::Buffer, Service, ServiceExt};
+
+use zebra_chain::{
+    block::{self, Block},
+    parameters::genesis::GENESIS_PREVIOUS_BLOCK_HASH,
+    Network,
+};
+
+use crate::{
+    block::{check, BlockError, Error},
+    Config,
+};
+
+/// The maximum number of blocks at each height that can wait for
+/// verification.
+///
+/// Peers can send us blocks from other chains, but only one of them can be
+/// on the checkpoint chain.
+pub const MAX_QUEUED_BLOCKS_PER_HEIGHT: usize = 4;
+
+/// The maximum number of blocks that can wait for verification.
+///
+/// The syncer keeps up to its lookahead limit of blocks in flight, so this
+/// must be larger than the lookahead limit.
+pub const MAX_QUEUED_BLOCKS: usize = 4_000;
+
+/// A checkpoint verification failure.
+#[derive(Error, Debug)]
+pub enum CheckpointError {
+    /// The block doesn't have a coinbase height.
+    #[error("block has no coinbase height")]
+    NoCoinbaseHeight,
+    /// The block is above the final checkpoint.
+    #[error("block height {0:?} is above the final checkpoint")]
+    AboveFinalCheckpoint(block::Height),
+    /// The checkpoint range containing the block has already been verified.
+    #[error("block height {0:?} has already been verified")]
+    AlreadyVerified(block::Height),
+    /// The same block is already waiting for verification.
+    #[error("block {0:?} is already queued for verification")]
+    DuplicateBlock(block::Hash),
+    /// There are already [`MAX_QUEUED_BLOCKS_PER_HEIGHT`] blocks waiting at
+    /// the block's height.
+    #[error("too many blocks are queued for verification at height {0:?}")]
+    TooManyBlocksAtHeight(block::Height),
+    /// There are already [`MAX_QUEUED_BLOCKS`] blocks waiting for
+    /// verification.
+    #[error("the checkpoint verification queue is full")]
+    QueueFull,
+    /// The block isn't in the chain of hashes ending at a checkpoint.
+    #[error("block {0:?} is not on the checkpoint chain")]
+    NotOnCheckpointChain(block::Hash),
+    /// The block failed a consensus check.
+    #[error("block is invalid")]
+    Block(#[from] BlockError),
+    /// The verifier was dropped before the block was verified.
+    #[error("checkpoint verifier was dropped")]
+    Dropped,
+}
+
+/// A block waiting for the rest of its checkpoint range.
+#[derive(Debug)]
+struct QueuedBlock {
+    /// The block.
+    block: Arc<Block>,
+    /// The hash of the block.
+    hash: block::Hash,
+    /// The channel for the verification result.
+    tx: oneshot::Sender<Result<block::Hash, Error>>,
+}
+
+/// Verifies blocks up to the final checkpoint, and adds them to the state in
+/// height order.
+///
+/// Blocks are queued until every block from the previous checkpoint to the
+/// next checkpoint has arrived, so responses can be delayed until the rest of
+/// the range is downloaded. Responds with the hash of each block that was
+/// added.
+#[derive(Debug)]
+pub struct CheckpointVerifier<S> {
+    /// The checkpoints that blocks are verified against.
+    checkpoint_list: CheckpointList,
+    /// The height and hash of the most recently verified checkpoint, or the
+    /// state tip that verification resumed from. `None` if the genesis block
+    /// hasn't been verified.
+    ///
+    /// Verified ranges are committed in the background, so this is rolled
+    /// back if a commit fails.
+    previous_checkpoint: Option<(block::Height, block::Hash)>,
+    /// Blocks waiting for verification, by height.
+    ///
+    /// There can be more than one block at each height, because peers can
+    /// send us blocks from other chains.
+    queued: BTreeMap<block::Height, Vec<QueuedBlock>>,
+    /// The number of blocks in `queued`.
+    queued_count: usize,
+    /// The state service, which stores verified blocks.
+    state_service: S,
+    /// Completes when the previous verified range has been added to the
+    /// state, with `true` if every block in it was added.
+    previous_commit: Option<oneshot::Receiver<bool>>,
+    /// Sends the last committed block when a range fails to commit.
+    failed_commits_tx: mpsc::UnboundedSender<Option<(block::Height, block::Hash)>>,
+    /// Receives the last committed block when a range fails to commit.
+    failed_commits: mpsc::UnboundedReceiver<Option<(block::Height, block::Hash)>>,
+}
+
+impl<S> CheckpointVerifier<S> {
+    /// Returns a verifier for blocks on `network`, using its hard-coded
+    /// checkpoints, which adds verified blocks to `state_service`.
+    pub fn new(network: Network, state_service: S) -> Self {
+        Self::from_checkpoint_list(CheckpointList::new(network), state_service)
+    }
+
+    /// Returns a verifier for blocks on `network`, using the checkpoints in
+    /// `config`, which adds verified blocks to `state_service`.
+    ///
+    /// Returns an error if the configured checkpoint list is invalid.
+    pub fn from_config(config: &Config, network: Network, state_service: S) -> Result<Self, Error> {
+        let checkpoint_list = CheckpointList::from_config(config, network)?;
+        Ok(Self::from_checkpoint_list(checkpoint_list, state_service))
+    }
+
+    /// Returns a verifier that uses `checkpoint_list`, and adds verified
+    /// blocks to `state_service`.
+    pub fn from_checkpoint_list(checkpoint_list: CheckpointList, state_service: S) -> Self {
+        let (failed_commits_tx, failed_commits) = mpsc::unbounded();
+        CheckpointVerifier {
+            checkpoint_list,
+            previous_checkpoint: None,
+            queued: BTreeMap::new(),
+            queued_count: 0,
+            state_service,
+            previous_commit: None,
+            failed_commits_tx,
+            failed_commits,
+        }
+    }
+
+    /// Resumes verification after `tip`, which must already be in the state.
+    ///
+    /// The next range is checked from the block after `tip` up to the next
+    /// checkpoint, so `tip` doesn't need to be a checkpoint.
+    pub fn resume_from(mut self, tip: Option<(block::Height, block::Hash)>) -> Self {
+        self.previous_checkpoint = tip;
+        self
+    }
+
+    /// Rolls back to the last committed block if a range has failed to
+    /// commit, so the blocks after it can be verified again.
+    ///
+    /// The ranges after a failed range also fail, without adding any blocks
+    /// to the state, so new ranges don't wait for them.
+    fn roll_back_failed_commits(&mut self) {
+        while let Ok(Some(committed)) = self.failed_commits.try_next() {
+            self.previous_checkpoint = committed;
+            self.previous_commit = None;
+        }
+    }
+
+    /// Checks `block`, and queues it for verification.
+    ///
+    /// Returns an error if the block can't be verified using the checkpoints.
+    fn queue_block(
+        &mut self,
+        block: Arc<Block>,
+        tx: oneshot::Sender<Result<block::Hash, Error>>,
+    ) -> Result<(), CheckpointError> {
+        let height = block
+            .coinbase_height()
+            .ok_or(CheckpointError::NoCoinbaseHeight)?;
+        if height > self.checkpoint_list.max_height() {
+            return Err(CheckpointError::AboveFinalCheckpoint(height));
+        }
+        if let Some((verified_height, _)) = self.previous_checkpoint {
+            if height <= verified_height {
+                return Err(CheckpointError::AlreadyVerified(height));
+            }
+        }
+
+        // The hash chain doesn't cover the transactions. Repeated
+        // transactions don't change the merkle root, so they are checked
+        // separately (CVE-2012-2459).
+        check::merkle_root_is_valid(&block)?;
+        check::transaction_hashes_are_unique(&block)?;
+
+        let hash = block.hash();
+        if let Some(checkpoint_hash) = self.checkpoint_list.hash(height) {
+            if hash != checkpoint_hash {
+                return Err(CheckpointError::NotOnCheckpointChain(hash));
+            }
+        }
+
+        // If a request was dropped, its block can be replaced by a new
+        // download of the same block.
+        let queued = self.queued.entry(height).or_default();
+        let queued_at_height = queued.len();
+        queued.retain(|queued_block| !queued_block.tx.is_canceled());
+        self.queued_count -= queued_at_height - queued.len();
+
+        if self.queued_count >= MAX_QUEUED_BLOCKS {
+            return Err(CheckpointError::QueueFull);
+        }
+        if queued.iter().any(|queued_block| queued_block.hash == hash) {
+            return Err(CheckpointError::DuplicateBlock(hash));
+        }
+        if queued.len() >= MAX_QUEUED_BLOCKS_PER_HEIGHT {
+            return Err(CheckpointError::TooManyBlocksAtHeight(height));
+        }
+        queued.push(QueuedBlock { block, hash, tx });
+        self.queued_count += 1;
+
+        Ok(())
+    }
+
+    /// Removes and returns the blocks after the previous checkpoint, up to
+    /// and including the next checkpoint, if they are all queued and their
+    /// hashes chain together.
+    ///
+    /// Other queued blocks at those heights are rejected.
+    fn next_verified_range(&mut self) -> Option<Vec<QueuedBlock>> {
+        let (start_height, previous_hash) = match self.previous_checkpoint {
+            Some((height, hash)) => (height.next()?, hash),
+            None => (block::Height(0), GENESIS_PREVIOUS_BLOCK_HASH),
+        };
+        let (checkpoint_height, checkpoint_hash) =
+            self.checkpoint_list.next_checkpoint(start_height)?;
+
+        // Walk back from the checkpoint, choosing the block at each height
+        // that has the expected hash.
+        let mut expected_hash = checkpoint_hash;
+        let mut chain = Vec::new();
+        for height in (start_height.0..=checkpoint_height.0).rev() {
+            let height = block::Height(height);
+            let blocks = self.queued.get(&height)?;
+            let index = blocks
+                .iter()
+                .position(|queued_block| queued_block.hash == expected_hash)?;
+
+            expected_hash = blocks[index].block.header.previous_block_hash;
+            chain.push((height, index));
+        }
+
+        // The first block must be the child of the previous checkpoint.
+        if expected_hash != previous_hash {
+            return None;
+        }
+
+        let mut range = Vec::with_capacity(chain.len());
+        for (height, index) in chain.into_iter().rev() {
+            let mut blocks = self
+                .queued
+                .remove(&height)
+                .expect("heights in the chain are queued");
+            self.queued_count -= blocks.len();
+            range.push(blocks.swap_remove(index));
+
+            for rejected in blocks {
+                let error = CheckpointError::NotOnCheckpointChain(rejected.hash);
+                let _ = rejected.tx.send(Err(error.into()));
+            }
+        }
+
+        self.previous_checkpoint = Some((checkpoint_height, checkpoint_hash));
+        Some(range)
+    }
+}
+
+impl<S> CheckpointVerifier<S>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = Error>
+        + Send
+        + Clone
+        + 'static,
+    S::Future: Send + 'static,
+{
+    /// Adds every fully verified range to the state.
+    ///
+    /// Each range is added by a separate task, which waits for the previous
+    /// range, so blocks are added to the state in height order.
+    fn commit_verified_ranges(&mut self) {
+        loop {
+            // The range starts after the previous checkpoint.
+            let mut committed = self.previous_checkpoint;
+            let range = match self.next_verified_range() {
+                Some(range) => range,
+                None => break,
+            };
+
+            let previous_commit = self.previous_commit.take();
+            let (done_tx, done_rx) = oneshot::channel();
+            self.previous_commit = Some(done_rx);
+
+            let mut state_service = self.state_service.clone();
+            let failed_commits_tx = self.failed_commits_tx.clone();
+            tokio::spawn(async move {
+                // If the previous task panicked, its blocks weren't added.
+                let previous_added = match previous_commit {
+                    Some(previous_commit) => previous_commit.await.unwrap_or(false),
+                    None => true,
+                };
+
+                let mut failed = !previous_added;
+                for QueuedBlock { block, hash, tx } in range {
+                    let height = block
+                        .coinbase_height()
+                        .expect("queued blocks have a coinbase height");
+                    let result = if failed {
+                        Err("a previous block in the checkpoint range was not added".into())
+                    } else {
+                        add_block(&mut state_service, block, hash).await
+                    };
+
+                    match &result {
+                        Ok(_) => committed = Some((height, hash)),
+                        // Only the first failed block rolls back the verifier.
+                        // It rolls back before responding, so the block can
+                        // be sent again straight away.
+                        Err(_) if !failed => {
+                            let _ = failed_commits_tx.unbounded_send(committed);
+                        }
+                        Err(_) => {}
+                    }
+
+                    failed = result.is_err();
+                    let _ = tx.send(result);
+                }
+
+                let _ = done_tx.send(!failed);
+            });
+        }
+    }
+}
+
+impl<S> Service<Arc<Block>> for CheckpointVerifier<S>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = Error>
+        + Send
+        + Clone
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = block::Hash;
+    type Error = Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Blocks are queued without using the state service.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, block: Arc<Block>) -> Self::Future {
+        self.roll_back_failed_commits();
+
+        let (tx, rx) = oneshot::channel();
+        let queued = self.queue_block(block, tx);
+        if queued.is_ok() {
+            self.commit_verified_ranges();
+        }
+
+        async move {
+            queued?;
+            rx.await
+                .unwrap_or_else(|_| Err(CheckpointError::Dropped.into()))
+        }
+        .boxed()
+    }
+}
+
+/// Adds `block` to `state_service`, and returns its `hash`.
+async fn add_block<S>(
+    state_service: &mut S,
+    block: Arc<Block>,
+    hash: block::Hash,
+) -> Result<block::Hash, Error>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = Error>,
+{
+    let response = state_service
+        .ready_and()
+        .await?
+        .call(zebra_state::Request::AddBlock { block })
+        .await?;
+    match response {
+        zebra_state::Response::Added => Ok(hash),
+        response => Err(format!("unexpected state response: {:?}", response).into()),
+    }
+}
+
+/// Returns a checkpoint verifier for `network`, which adds verified blocks to
+/// `state_service`.
+///
+/// The verifier is buffered, so it can be cloned and shared between tasks.
+pub fn init<S>(
+    network: Network,
+    state_service: S,
+) -> impl Service<
+    Arc<Block>,
+    Response = block::Hash,
+    Error = Error,
+    Future = impl Future<Output = Result<block::Hash, Error>>,
+> + Send
+       + Clone
+       + 'static
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = Error>
+        + Send
+        + Clone
+        + 'static,
+    S::Future: Send + 'static,
+{
+    Buffer::new(CheckpointVerifier::new(network, state_service), 1)
+}
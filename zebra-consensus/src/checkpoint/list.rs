@@ -0,0 +1,206 @@
+//! Hard-coded checkpoint lists.
+//!
+//! Each list is a text file with one checkpoint per line, as a block height
+//! and a block hash separated by whitespace. Hashes use the byte order shown
+//! by block explorers. Every list starts at the genesis block.
+//!
+//! The hard-coded lists can be replaced by a list file in the [`Config`].
+//! Both kinds of list can be generated from a synced `zcashd` with the
+//! `zebra-checkpoints` tool in `zebra-utils`.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    convert::TryFrom,
+    fs,
+    path::Path,
+    str::FromStr,
+};
+
+use zebra_chain::{block, parameters::genesis::genesis_hash, Network};
+
+use crate::{block::Error, Config};
+
+/// The maximum number of blocks between consecutive checkpoints.
+///
+/// The checkpoint verifier queues every block in a range until it can verify
+/// the range, so large gaps use a lot of memory.
+pub const MAX_CHECKPOINT_HEIGHT_GAP: u32 = 400;
+
+/// The mainnet checkpoints.
+const MAINNET_CHECKPOINTS: &str = include_str!("main-checkpoints.txt");
+
+/// The testnet checkpoints.
+const TESTNET_CHECKPOINTS: &str = include_str!("test-checkpoints.txt");
+
+/// An ordered list of checkpoint heights and hashes.
+///
+/// # Invariants
+///
+/// The list contains the genesis block, and its heights and hashes are
+/// unique.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CheckpointList(BTreeMap<block::Height, block::Hash>);
+
+impl FromStr for CheckpointList {
+    type Err = Error;
+
+    /// Parse a checkpoint list, with one `height hash` pair per line.
+    ///
+    /// The lines can be in any order. Blank lines are ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut checkpoints = Vec::new();
+
+        for line in s.lines().filter(|line| !line.trim().is_empty()) {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let (height, hash) = match fields.as_slice() {
+                [height, hash] => (height, hash),
+                _ => return Err(format!("invalid checkpoint line: {:?}", line).into()),
+            };
+
+            let height = block::Height::try_from(height.parse::<u32>()?)?;
+            let hash = hash.parse::<block::Hash>()?;
+            checkpoints.push((height, hash));
+        }
+
+        CheckpointList::from_list(checkpoints)
+    }
+}
+
+impl CheckpointList {
+    /// Returns the hard-coded checkpoint list for `network`.
+    ///
+    /// Regtest chains are created locally, so the regtest list only contains
+    /// the genesis block.
+    pub fn new(network: Network) -> CheckpointList {
+        let list = match network {
+            Network::Mainnet => MAINNET_CHECKPOINTS
+                .parse()
+                .expect("hard-coded mainnet checkpoint list parses"),
+            Network::Testnet => TESTNET_CHECKPOINTS
+                .parse()
+                .expect("hard-coded testnet checkpoint list parses"),
+            Network::Regtest => {
+                CheckpointList::from_list(vec![(block::Height(0), genesis_hash(network))])
+                    .expect("a genesis checkpoint is a valid list")
+            }
+        };
+
+        list.verify_genesis(network)
+            .expect("hard-coded checkpoint lists start at the genesis block for their network");
+        list
+    }
+
+    /// Returns the checkpoint list for `network`, using the list file in
+    /// `config` if there is one.
+    ///
+    /// Returns an error if the file can't be loaded, or if its genesis
+    /// checkpoint doesn't match `network`.
+    pub fn from_config(config: &Config, network: Network) -> Result<CheckpointList, Error> {
+        match &config.checkpoint_list {
+            Some(path) => {
+                let list = CheckpointList::load(path)?;
+                list.verify_genesis(network)?;
+                Ok(list)
+            }
+            None => Ok(CheckpointList::new(network)),
+        }
+    }
+
+    /// Loads and parses the checkpoint list file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<CheckpointList, Error> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("could not read checkpoint list {:?}: {}", path, e))?;
+        contents.parse()
+    }
+
+    /// Returns an error if the genesis checkpoint in this list isn't the
+    /// genesis block for `network`.
+    ///
+    /// Use this to check that a list file matches the compiled-in genesis
+    /// block before verifying blocks with it.
+    pub fn verify_genesis(&self, network: Network) -> Result<(), Error> {
+        let expected = genesis_hash(network);
+        match self.hash(block::Height(0)) {
+            Some(hash) if hash == expected => Ok(()),
+            hash => Err(format!(
+                "genesis checkpoint {:?} does not match the {:?} genesis block {:?}",
+                hash, network, expected
+            )
+            .into()),
+        }
+    }
+
+    /// Returns a checkpoint list containing `list`, sorted by height.
+    ///
+    /// Returns an error if the list doesn't contain the genesis block, has
+    /// duplicate heights or hashes, or has a gap larger than
+    /// [`MAX_CHECKPOINT_HEIGHT_GAP`].
+    pub fn from_list(
+        list: impl IntoIterator<Item = (block::Height, block::Hash)>,
+    ) -> Result<CheckpointList, Error> {
+        let mut checkpoints = BTreeMap::new();
+        let mut hashes = HashSet::new();
+
+        for (height, hash) in list {
+            if checkpoints.insert(height, hash).is_some() {
+                return Err(format!("duplicate checkpoint height: {:?}", height).into());
+            }
+            if !hashes.insert(hash) {
+                return Err(format!("duplicate checkpoint hash: {:?}", hash).into());
+            }
+        }
+
+        if !checkpoints.contains_key(&block::Height(0)) {
+            return Err("checkpoint lists must contain the genesis block".into());
+        }
+
+        let heights: Vec<_> = checkpoints.keys().collect();
+        for pair in heights.windows(2) {
+            let (previous, next) = (pair[0], pair[1]);
+            if next.0 - previous.0 > MAX_CHECKPOINT_HEIGHT_GAP {
+                return Err(format!(
+                    "checkpoint gap from {:?} to {:?} is larger than {}",
+                    previous, next, MAX_CHECKPOINT_HEIGHT_GAP
+                )
+                .into());
+            }
+        }
+
+        Ok(CheckpointList(checkpoints))
+    }
+
+    /// Returns the checkpoint hash at `height`, if there is a checkpoint at
+    /// that height.
+    pub fn hash(&self, height: block::Height) -> Option<block::Hash> {
+        self.0.get(&height).cloned()
+    }
+
+    /// Returns the height of the final checkpoint.
+    pub fn max_height(&self) -> block::Height {
+        *self
+            .0
+            .keys()
+            .next_back()
+            .expect("checkpoint lists are not empty")
+    }
+
+    /// Returns the first checkpoint at or after `height`, or `None` if
+    /// `height` is above the final checkpoint.
+    pub fn next_checkpoint(&self, height: block::Height) -> Option<(block::Height, block::Hash)> {
+        self.0
+            .range(height..)
+            .next()
+            .map(|(height, hash)| (*height, *hash))
+    }
+
+    /// Returns the height of the last checkpoint at or before `height`.
+    pub fn previous_checkpoint(&self, height: block::Height) -> block::Height {
+        *self
+            .0
+            .range(..=height)
+            .next_back()
+            .expect("checkpoint lists contain the genesis block")
+            .0
+    }
+}
@@ -0,0 +1,443 @@
+//! Tests for checkpoint-based block verification.
+
+use std::sync::Arc;
+
+use color_eyre::Report;
+use eyre::{ensure, eyre};
+use tower::{Service, ServiceExt};
+
+use zebra_chain::{
+    block::{self, Block},
+    parameters::genesis::genesis_hash,
+    serialization::ZcashDeserialize,
+    Network,
+};
+
+use super::*;
+
+fn block(bytes: &[u8]) -> Result<Arc<Block>, Report> {
+    Ok(Arc::new(Block::zcash_deserialize(bytes)?))
+}
+
+/// Returns the `CheckpointError` from a verification failure, if there is
+/// one.
+fn checkpoint_error(error: &Error) -> Option<&CheckpointError> {
+    error.downcast_ref::<CheckpointError>()
+}
+
+#[test]
+fn hard_coded_lists_start_at_genesis() {
+    for network in &[Network::Mainnet, Network::Testnet, Network::Regtest] {
+        let list = CheckpointList::new(*network);
+        assert_eq!(list.hash(block::Height(0)), Some(genesis_hash(*network)));
+    }
+}
+
+#[test]
+fn checkpoint_list_parsing() -> Result<(), Report> {
+    let genesis = genesis_hash(Network::Mainnet);
+
+    let list: CheckpointList = format!("\n0 {}\n\n", genesis)
+        .parse()
+        .map_err(|e| eyre!(e))?;
+    ensure!(list.max_height() == block::Height(0), "one checkpoint");
+    ensure!(
+        list.next_checkpoint(block::Height(0)) == Some((block::Height(0), genesis)),
+        "the next checkpoint includes the current height"
+    );
+    ensure!(
+        list.next_checkpoint(block::Height(1)).is_none(),
+        "no checkpoints above the final checkpoint"
+    );
+    ensure!(
+        list.previous_checkpoint(block::Height(1)) == block::Height(0),
+        "the previous checkpoint can be the final checkpoint"
+    );
+
+    for invalid in &[
+        // No genesis block
+        format!("1 {}", genesis),
+        // Duplicate heights and hashes
+        format!("0 {}\n0 {}", genesis, genesis_hash(Network::Testnet)),
+        format!("0 {}\n1 {}", genesis, genesis),
+        // Missing, extra, and invalid fields
+        "0".to_string(),
+        format!("0 {} 1", genesis),
+        format!("-1 {}", genesis),
+        "0 00".to_string(),
+    ] {
+        ensure!(
+            invalid.parse::<CheckpointList>().is_err(),
+            "invalid list {:?} should fail to parse",
+            invalid
+        );
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn verify_mainnet_blocks_in_any_order() -> Result<(), Report> {
+    let state_service = zebra_state::in_memory::init(Network::Mainnet);
+    let mut verifier = CheckpointVerifier::new(Network::Mainnet, state_service.clone());
+
+    let genesis = block(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?;
+    let block1 = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+
+    // Block 1 waits for the genesis block.
+    let block1_verified = verifier.call(block1.clone());
+    let genesis_verified = verifier.call(genesis.clone());
+
+    ensure!(
+        genesis_verified.await.map_err(|e| eyre!(e))? == genesis.hash(),
+        "verifier returned the wrong genesis hash"
+    );
+    ensure!(
+        block1_verified.await.map_err(|e| eyre!(e))? == block1.hash(),
+        "verifier returned the wrong block 1 hash"
+    );
+
+    let tip = state_service
+        .clone()
+        .ready_and()
+        .await
+        .map_err(|e| eyre!(e))?
+        .call(zebra_state::Request::GetTip)
+        .await
+        .map_err(|e| eyre!(e))?;
+    ensure!(
+        matches!(tip, zebra_state::Response::Tip { hash } if hash == block1.hash()),
+        "unexpected tip: {:?}",
+        tip
+    );
+
+    // Verified blocks can't be verified again.
+    let error = verifier
+        .call(genesis)
+        .await
+        .expect_err("the genesis block has already been verified");
+    ensure!(
+        matches!(
+            checkpoint_error(&error),
+            Some(CheckpointError::AlreadyVerified(_))
+        ),
+        "unexpected error: {:?}",
+        error
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn blocks_off_the_checkpoint_chain_are_rejected() -> Result<(), Report> {
+    let mut verifier = CheckpointVerifier::new(
+        Network::Mainnet,
+        zebra_state::in_memory::init(Network::Mainnet),
+    );
+
+    // Changing the nonce changes the hash, but not the merkle root.
+    let mut block1 = Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    block1.header.nonce[0] ^= 0xff;
+
+    let error = verifier
+        .call(Arc::new(block1))
+        .await
+        .expect_err("the block hash doesn't match the checkpoint");
+    ensure!(
+        matches!(
+            checkpoint_error(&error),
+            Some(CheckpointError::NotOnCheckpointChain(_))
+        ),
+        "unexpected error: {:?}",
+        error
+    );
+
+    let block = block(&zebra_test_vectors::BLOCK_MAINNET_415000_BYTES[..])?;
+    let error = verifier
+        .call(block)
+        .await
+        .expect_err("the block is above the final checkpoint");
+    ensure!(
+        matches!(
+            checkpoint_error(&error),
+            Some(CheckpointError::AboveFinalCheckpoint(_))
+        ),
+        "unexpected error: {:?}",
+        error
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn malleated_blocks_are_rejected() -> Result<(), Report> {
+    use zebra_chain::{
+        block::merkle,
+        transaction::{self, LockTime, OutPoint, Transaction, TransparentInput},
+        transparent::Script,
+    };
+
+    let spend = |index| {
+        Arc::new(Transaction::V1 {
+            inputs: vec![TransparentInput::PrevOut {
+                outpoint: OutPoint {
+                    hash: transaction::Hash([0x22; 32]),
+                    index,
+                },
+                script: Script(vec![].into()),
+                sequence: u32::MAX,
+            }],
+            outputs: vec![],
+            lock_time: LockTime::unlocked(),
+        })
+    };
+
+    // A block with an odd number of transactions, and a checkpoint for it.
+    let mut block1 = Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    block1.transactions.push(spend(0));
+    block1.transactions.push(spend(1));
+    block1.header.merkle_root = merkle::Root::from_transactions(&block1.transactions);
+    let list = CheckpointList::from_list(vec![
+        (block::Height(0), genesis_hash(Network::Mainnet)),
+        (block::Height(1), block1.hash()),
+    ])
+    .map_err(|e| eyre!(e))?;
+    let mut verifier = CheckpointVerifier::from_checkpoint_list(
+        list,
+        zebra_state::in_memory::init(Network::Mainnet),
+    );
+
+    // Repeating the last transaction keeps the hash and the merkle root.
+    let mut malleated = block1.clone();
+    malleated.transactions.push(spend(1));
+    let error = verifier
+        .call(Arc::new(malleated))
+        .await
+        .expect_err("the block repeats a transaction");
+    ensure!(
+        matches!(
+            checkpoint_error(&error),
+            Some(CheckpointError::Block(BlockError::DuplicateTransaction(_)))
+        ),
+        "unexpected error: {:?}",
+        error
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn duplicate_queued_blocks_are_rejected() -> Result<(), Report> {
+    let mut verifier = CheckpointVerifier::new(
+        Network::Mainnet,
+        zebra_state::in_memory::init(Network::Mainnet),
+    );
+
+    let block1 = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    let _waiting = verifier.call(block1.clone());
+
+    let error = verifier
+        .call(block1)
+        .await
+        .expect_err("block 1 is already waiting for the genesis block");
+    ensure!(
+        matches!(
+            checkpoint_error(&error),
+            Some(CheckpointError::DuplicateBlock(_))
+        ),
+        "unexpected error: {:?}",
+        error
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn dropped_queued_blocks_can_be_sent_again() -> Result<(), Report> {
+    let genesis = block(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?;
+    let block1 = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    let list = CheckpointList::from_list(vec![
+        (block::Height(0), genesis.hash()),
+        (block::Height(1), block1.hash()),
+    ])
+    .map_err(|e| eyre!(e))?;
+    let mut verifier = CheckpointVerifier::from_checkpoint_list(
+        list,
+        zebra_state::in_memory::init(Network::Mainnet),
+    );
+
+    // A failed sync round drops its requests, then downloads the block again.
+    std::mem::drop(verifier.call(block1.clone()));
+    let block1_verified = verifier.call(block1.clone());
+    ensure!(verifier.queued_count == 1, "the dropped block is replaced");
+
+    verifier.call(genesis).await.map_err(|e| eyre!(e))?;
+    ensure!(
+        block1_verified.await.map_err(|e| eyre!(e))? == block1.hash(),
+        "verifier returned the wrong block 1 hash"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn queued_blocks_per_height_are_limited() -> Result<(), Report> {
+    // Without a checkpoint at height 1, blocks from other chains can be
+    // queued there.
+    let list = CheckpointList::from_list(vec![
+        (block::Height(0), genesis_hash(Network::Mainnet)),
+        (block::Height(2), block::Hash([2; 32])),
+    ])
+    .map_err(|e| eyre!(e))?;
+    let mut verifier = CheckpointVerifier::from_checkpoint_list(
+        list,
+        zebra_state::in_memory::init(Network::Mainnet),
+    );
+
+    let block1 = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    let mut waiting = Vec::new();
+    for nonce in 0..=MAX_QUEUED_BLOCKS_PER_HEIGHT {
+        let mut other = (*block1).clone();
+        other.header.nonce[0] = nonce as u8;
+        waiting.push(verifier.call(Arc::new(other)));
+    }
+
+    let error = waiting
+        .pop()
+        .expect("blocks were queued")
+        .await
+        .expect_err("there are already too many blocks at height 1");
+    ensure!(
+        matches!(
+            checkpoint_error(&error),
+            Some(CheckpointError::TooManyBlocksAtHeight(block::Height(1)))
+        ),
+        "unexpected error: {:?}",
+        error
+    );
+    ensure!(
+        verifier.queued_count == MAX_QUEUED_BLOCKS_PER_HEIGHT,
+        "rejected blocks aren't counted"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn checkpoint_list_gaps_and_order() -> Result<(), Report> {
+    use list::MAX_CHECKPOINT_HEIGHT_GAP;
+
+    let genesis = genesis_hash(Network::Mainnet);
+    let block1 = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.hash();
+
+    // Lines can be in any order.
+    let list: CheckpointList = format!("1 {}\n0 {}", block1, genesis)
+        .parse()
+        .map_err(|e| eyre!(e))?;
+    ensure!(
+        list.next_checkpoint(block::Height(0)) == Some((block::Height(0), genesis)),
+        "checkpoints are sorted by height"
+    );
+    ensure!(list.max_height() == block::Height(1), "two checkpoints");
+
+    let at_height = |height| vec![(block::Height(0), genesis), (block::Height(height), block1)];
+    CheckpointList::from_list(at_height(MAX_CHECKPOINT_HEIGHT_GAP)).map_err(|e| eyre!(e))?;
+    ensure!(
+        CheckpointList::from_list(at_height(MAX_CHECKPOINT_HEIGHT_GAP + 1)).is_err(),
+        "gaps must be at most MAX_CHECKPOINT_HEIGHT_GAP"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn checkpoint_list_from_config() -> Result<(), Report> {
+    // Without a file, the hard-coded list is used.
+    let list =
+        CheckpointList::from_config(&Config::default(), Network::Mainnet).map_err(|e| eyre!(e))?;
+    ensure!(
+        list == CheckpointList::new(Network::Mainnet),
+        "the default config uses the hard-coded list"
+    );
+
+    let path = std::env::temp_dir().join(format!(
+        "zebra-consensus-checkpoints-{}.txt",
+        std::process::id()
+    ));
+    std::fs::write(&path, format!("0 {}\n", genesis_hash(Network::Testnet)))?;
+    let config = Config {
+        checkpoint_list: Some(path.clone()),
+        ..Config::default()
+    };
+
+    let testnet =
+        CheckpointList::from_config(&config, Network::Testnet).map(|list| list.max_height());
+    let mainnet = CheckpointList::from_config(&config, Network::Mainnet);
+    std::fs::remove_file(&path)?;
+
+    ensure!(
+        testnet.map_err(|e| eyre!(e))? == block::Height(0),
+        "the list file is loaded"
+    );
+    ensure!(
+        mainnet.is_err(),
+        "list files must match the network's genesis block"
+    );
+
+    let missing = Config {
+        checkpoint_list: Some(path),
+        ..Config::default()
+    };
+    ensure!(
+        CheckpointList::from_config(&missing, Network::Testnet).is_err(),
+        "missing list files are an error"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn failed_commits_can_be_retried() -> Result<(), Report> {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    // A state that fails to add block 1 once.
+    let inner = zebra_state::in_memory::init(Network::Mainnet);
+    let fail_block1 = Arc::new(AtomicBool::new(true));
+    let state_service = tower::service_fn(move |request: zebra_state::Request| {
+        let mut inner = inner.clone();
+        let fail = match &request {
+            zebra_state::Request::AddBlock { block } => {
+                block.coinbase_height() == Some(block::Height(1))
+                    && fail_block1.swap(false, Ordering::SeqCst)
+            }
+            _ => false,
+        };
+        async move {
+            if fail {
+                return Err::<_, Error>("the state is unavailable".into());
+            }
+            inner.ready_and().await?.call(request).await
+        }
+    });
+
+    // Each block is its own range.
+    let genesis = block(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?;
+    let block1 = block(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    let list = CheckpointList::from_list(vec![
+        (block::Height(0), genesis.hash()),
+        (block::Height(1), block1.hash()),
+    ])
+    .map_err(|e| eyre!(e))?;
+    let mut verifier = CheckpointVerifier::from_checkpoint_list(list, state_service);
+    verifier.call(genesis).await.map_err(|e| eyre!(e))?;
+    verifier
+        .call(block1.clone())
+        .await
+        .expect_err("the state fails to add block 1");
+
+    // The range wasn't committed, so block 1 can be verified again.
+    let hash = verifier.call(block1.clone()).await.map_err(|e| eyre!(e))?;
+    ensure!(hash == block1.hash(), "verifier returned the wrong hash");
+
+    Ok(())
+}
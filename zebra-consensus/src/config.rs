@@ -0,0 +1,70 @@
+//! Configuration for consensus verification.
+
+use std::{path::PathBuf, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::primitives::{DEFAULT_MAX_BATCH_LATENCY, DEFAULT_MAX_BATCH_SIZE};
+
+/// The default maximum number of blocks that are verified at the same time.
+const DEFAULT_MAX_CONCURRENT_BLOCKS: usize = 16;
+
+/// Configuration for block verification.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Config {
+    /// A checkpoint list file, which replaces the hard-coded checkpoints for
+    /// the network.
+    ///
+    /// The file has one `height hash` pair per line, like the hard-coded
+    /// lists. Its genesis checkpoint must match the configured network.
+    pub checkpoint_list: Option<PathBuf>,
+
+    /// The maximum number of proofs or signatures in each verification
+    /// batch.
+    ///
+    /// Larger batches are cheaper to verify, but use more memory.
+    pub max_batch_size: usize,
+
+    /// The maximum number of blocks that are verified at the same time.
+    ///
+    /// Blocks are verified using multiple threads, but each block waiting
+    /// for verification holds all its proofs and signatures in memory.
+    pub max_concurrent_blocks: usize,
+
+    // Note: due to the way this is rendered by the toml
+    // serializer, the Duration fields should come last.
+    /// The longest time a proof or signature waits for the rest of its
+    /// batch.
+    ///
+    /// Batches are also flushed at the end of each block, so this only
+    /// delays mempool transactions.
+    pub max_batch_latency: Duration,
+}
+
+impl Config {
+    /// Returns an error if the config can't be used to verify blocks.
+    ///
+    /// Empty batches or verification limits would stop verification.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_batch_size == 0 {
+            return Err("consensus.max_batch_size must be at least 1".to_owned());
+        }
+        if self.max_concurrent_blocks == 0 {
+            return Err("consensus.max_concurrent_blocks must be at least 1".to_owned());
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            checkpoint_list: None,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            max_concurrent_blocks: DEFAULT_MAX_CONCURRENT_BLOCKS,
+            max_batch_latency: DEFAULT_MAX_BATCH_LATENCY,
+        }
+    }
+}
@@ -0,0 +1,385 @@
+//! Structured errors for consensus rule violations.
+//!
+//! Verifiers return a [`VerificationError`] when a block or transaction
+//! breaks a consensus rule. Other errors, like a missing previous block, an
+//! unexpected state response, or a failed batch verifier, mean that the data
+//! couldn't be checked, so they aren't the fault of the peer that sent it.
+//!
+//! Each error carries the hash of the invalid block or transaction, and a
+//! reference to the rule in the Zcash protocol specification or a ZIP, so
+//! callers can relay precise reject reasons.
+
+use std::fmt;
+
+use thiserror::Error;
+
+use zebra_chain::{block, transaction};
+
+use crate::{
+    block::{BlockError, Error},
+    primitives,
+    transaction::TransactionError,
+};
+
+/// The hash of the block or transaction that broke a rule.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ObjectHash {
+    /// A block, or its header.
+    Block(block::Hash),
+    /// A transaction.
+    Transaction(transaction::Hash),
+}
+
+impl fmt::Display for ObjectHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjectHash::Block(hash) => write!(f, "block {}", hash),
+            ObjectHash::Transaction(hash) => write!(f, "transaction {}", hash),
+        }
+    }
+}
+
+/// A consensus rule violation, categorized by the kind of rule.
+#[derive(Error, Debug)]
+pub enum VerificationError {
+    /// The block header is invalid on its own.
+    #[error("invalid header in {hash}, see {rule}")]
+    Header {
+        /// The invalid block.
+        hash: ObjectHash,
+        /// The broken rule.
+        rule: &'static str,
+        /// The rule violation.
+        source: Error,
+    },
+    /// The block's transactions break a block-wide rule.
+    #[error("invalid transactions in {hash}, see {rule}")]
+    Block {
+        /// The invalid block.
+        hash: ObjectHash,
+        /// The broken rule.
+        rule: &'static str,
+        /// The rule violation.
+        source: Error,
+    },
+    /// The coinbase transaction, or its outputs, are invalid.
+    #[error("invalid coinbase in {hash}, see {rule}")]
+    Coinbase {
+        /// The invalid block or coinbase transaction.
+        hash: ObjectHash,
+        /// The broken rule.
+        rule: &'static str,
+        /// The rule violation.
+        source: Error,
+    },
+    /// The transaction is invalid on its own.
+    #[error("invalid {hash}, see {rule}")]
+    Transaction {
+        /// The invalid transaction.
+        hash: ObjectHash,
+        /// The broken rule.
+        rule: &'static str,
+        /// The rule violation.
+        source: Error,
+    },
+    /// A transparent input doesn't satisfy the script of the output it
+    /// spends.
+    #[error("invalid script in {hash}, see {rule}")]
+    Script {
+        /// The invalid transaction.
+        hash: ObjectHash,
+        /// The broken rule.
+        rule: &'static str,
+        /// The rule violation.
+        source: Error,
+    },
+    /// A zero-knowledge proof is invalid.
+    #[error("invalid proof in {hash}, see {rule}")]
+    Proof {
+        /// The invalid transaction.
+        hash: ObjectHash,
+        /// The broken rule.
+        rule: &'static str,
+        /// The rule violation.
+        source: Error,
+    },
+    /// A signature is invalid.
+    #[error("invalid signature in {hash}, see {rule}")]
+    Signature {
+        /// The invalid transaction.
+        hash: ObjectHash,
+        /// The broken rule.
+        rule: &'static str,
+        /// The rule violation.
+        source: Error,
+    },
+    /// The block or transaction is invalid in the context of the chain, or
+    /// of our clock.
+    ///
+    /// These rules can depend on our view of the chain, so breaking them
+    /// isn't necessarily misbehaviour.
+    #[error("{hash} is invalid in the current chain, see {rule}")]
+    Contextual {
+        /// The invalid block or transaction.
+        hash: ObjectHash,
+        /// The broken rule.
+        rule: &'static str,
+        /// The rule violation.
+        source: Error,
+    },
+}
+
+/// The protocol specification section for block header rules.
+const HEADER_RULES: &str = "protocol specification §7.6";
+
+/// The protocol specification section for block rules.
+const BLOCK_RULES: &str = "protocol specification §7.7";
+
+/// The protocol specification section for transaction rules.
+const TRANSACTION_RULES: &str = "protocol specification §7.1";
+
+impl VerificationError {
+    /// Returns the error for `error` in the block with `hash`.
+    pub(crate) fn block(hash: block::Hash, error: BlockError) -> VerificationError {
+        use BlockError::*;
+
+        let hash = ObjectHash::Block(hash);
+        match error {
+            BadMerkleRoot { .. } => VerificationError::Header {
+                hash,
+                rule: BLOCK_RULES,
+                source: error.into(),
+            },
+            Equihash(_) | WrongEquihashSolutionSize(_) => VerificationError::Header {
+                hash,
+                rule: "protocol specification §7.6.1",
+                source: error.into(),
+            },
+            InvalidDifficulty(_) | TargetDifficultyLimit(_) | DifficultyFilter(..) => {
+                VerificationError::Header {
+                    hash,
+                    rule: "protocol specification §7.6.2",
+                    source: error.into(),
+                }
+            }
+            Commitment(_) => VerificationError::Header {
+                hash,
+                rule: HEADER_RULES,
+                source: error.into(),
+            },
+            DuplicateTransaction(_)
+            | DuplicateTransparentSpend(_)
+            | DuplicateNullifier
+            | TooManySigops(_) => VerificationError::Block {
+                hash,
+                rule: BLOCK_RULES,
+                source: error.into(),
+            },
+            BadTransactionSize { .. } => VerificationError::Block {
+                hash,
+                rule: TRANSACTION_RULES,
+                source: error.into(),
+            },
+            NoCoinbase | CoinbaseNotFirst => VerificationError::Coinbase {
+                hash,
+                rule: BLOCK_RULES,
+                source: error.into(),
+            },
+            FoundersRewardNotFound => VerificationError::Coinbase {
+                hash,
+                rule: "protocol specification §7.8",
+                source: error.into(),
+            },
+            FundingStreamNotFound(_) => VerificationError::Coinbase {
+                hash,
+                rule: "ZIP-207",
+                source: error.into(),
+            },
+            CoinbaseValueOutOfRange | CoinbaseValueTooLarge { .. } => VerificationError::Coinbase {
+                hash,
+                rule: BLOCK_RULES,
+                source: error.into(),
+            },
+            BadTransactionFee(_) => VerificationError::Block {
+                hash,
+                rule: TRANSACTION_RULES,
+                source: error.into(),
+            },
+            TimeTooEarly | TimeTooFarInFuture => VerificationError::Contextual {
+                hash,
+                rule: HEADER_RULES,
+                source: error.into(),
+            },
+            BadDifficultyThreshold { .. } => VerificationError::Contextual {
+                hash,
+                rule: "protocol specification §7.6.3",
+                source: error.into(),
+            },
+            LockedTransaction => VerificationError::Contextual {
+                hash,
+                rule: TRANSACTION_RULES,
+                source: error.into(),
+            },
+            BadFinalSaplingRoot { .. }
+            | NoteCommitmentTree(_)
+            | BadChainHistoryRoot { .. }
+            | BadBlockCommitments { .. } => VerificationError::Contextual {
+                hash,
+                rule: HEADER_RULES,
+                source: error.into(),
+            },
+        }
+    }
+
+    /// Returns the error for `error` in the transaction with `hash`.
+    pub(crate) fn transaction(
+        hash: transaction::Hash,
+        error: TransactionError,
+    ) -> VerificationError {
+        use TransactionError::*;
+
+        let hash = ObjectHash::Transaction(hash);
+        match error {
+            WrongVersion(_)
+            | NoInputs
+            | NoOutputs
+            | ValueOutOfRange(_)
+            | MaximumExpiryHeight(_)
+            | DuplicateInput
+            | DuplicateNullifier => VerificationError::Transaction {
+                hash,
+                rule: TRANSACTION_RULES,
+                source: error.into(),
+            },
+            WrongConsensusBranchId { .. } => VerificationError::Transaction {
+                hash,
+                rule: "ZIP-225",
+                source: error.into(),
+            },
+            CoinbaseInMempool
+            | CoinbaseInputFound
+            | CoinbaseHasJoinSplit
+            | CoinbaseHasSpend
+            | CoinbaseHasEnableSpendsOrchard
+            | CoinbaseHasOutputPreHeartwood => VerificationError::Coinbase {
+                hash,
+                rule: TRANSACTION_RULES,
+                source: error.into(),
+            },
+            CoinbaseExpiryHeight { .. } => VerificationError::Coinbase {
+                hash,
+                rule: "ZIP-203",
+                source: error.into(),
+            },
+            InvalidValueCommitment => VerificationError::Signature {
+                hash,
+                rule: "protocol specification §4.13",
+                source: error.into(),
+            },
+            SigHash(_) => VerificationError::Signature {
+                hash,
+                rule: "ZIP-244",
+                source: error.into(),
+            },
+            Expired { .. } => VerificationError::Contextual {
+                hash,
+                rule: "ZIP-203",
+                source: error.into(),
+            },
+            UnknownSproutAnchor(_)
+            | UnknownSaplingAnchor(_)
+            | UnknownOrchardAnchor(_)
+            | SproutTree(_) => VerificationError::Contextual {
+                hash,
+                rule: TRANSACTION_RULES,
+                source: error.into(),
+            },
+        }
+    }
+
+    /// Returns the error for a failed proof check in the transaction with
+    /// `hash`, using the proof `rule`.
+    ///
+    /// Failures of the batch verifier service are returned unchanged,
+    /// because the proof wasn't checked.
+    pub(crate) fn proof(hash: transaction::Hash, rule: &'static str, source: Error) -> Error {
+        if primitives::is_service_failure(&source) {
+            return source;
+        }
+        VerificationError::Proof {
+            hash: ObjectHash::Transaction(hash),
+            rule,
+            source,
+        }
+        .into()
+    }
+
+    /// Returns the error for a failed signature check in the transaction
+    /// with `hash`, using the signature `rule`.
+    ///
+    /// Failures of the batch verifier service are returned unchanged,
+    /// because the signature wasn't checked.
+    pub(crate) fn signature(hash: transaction::Hash, rule: &'static str, source: Error) -> Error {
+        if primitives::is_service_failure(&source) {
+            return source;
+        }
+        VerificationError::Signature {
+            hash: ObjectHash::Transaction(hash),
+            rule,
+            source,
+        }
+        .into()
+    }
+
+    /// Returns the error for a transparent input in the transaction with
+    /// `hash` that doesn't satisfy the script of the output it spends.
+    pub(crate) fn script(hash: transaction::Hash, source: Error) -> VerificationError {
+        VerificationError::Script {
+            hash: ObjectHash::Transaction(hash),
+            rule: TRANSACTION_RULES,
+            source,
+        }
+    }
+
+    /// Returns the hash of the invalid block or transaction.
+    pub fn hash(&self) -> ObjectHash {
+        *self.parts().0
+    }
+
+    /// Returns a reference to the broken rule, in the Zcash protocol
+    /// specification or a ZIP.
+    pub fn rule(&self) -> &'static str {
+        self.parts().1
+    }
+
+    /// Returns the underlying rule violation, which is usually a
+    /// [`BlockError`] or [`TransactionError`].
+    pub fn inner(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+        self.parts().2.as_ref()
+    }
+
+    /// Returns true if the peer that sent the invalid data should be
+    /// treated as misbehaving.
+    ///
+    /// Contextual rules depend on our own chain and clock, so a peer with a
+    /// different view of the chain can send data that breaks them.
+    pub fn is_misbehavior(&self) -> bool {
+        !matches!(self, VerificationError::Contextual { .. })
+    }
+
+    /// Returns the fields shared by every category.
+    fn parts(&self) -> (&ObjectHash, &'static str, &Error) {
+        use VerificationError::*;
+
+        match self {
+            Header { hash, rule, source }
+            | Block { hash, rule, source }
+            | Coinbase { hash, rule, source }
+            | Transaction { hash, rule, source }
+            | Script { hash, rule, source }
+            | Proof { hash, rule, source }
+            | Signature { hash, rule, source }
+            | Contextual { hash, rule, source } => (hash, rule, source),
+        }
+    }
+}
@@ -1,10 +1,20 @@
+//! Consensus rule checks for Zebra.
+//!
+//! Verifiers are `tower` services, which check consensus rules, then pass
+//! valid data on to the state service.
+
 #![doc(html_logo_url = "https://www.zfnd.org/images/zebra-icon.png")]
 #![doc(html_root_url = "https://doc.zebra.zfnd.org/zebra_consensus")]
+#![deny(missing_docs)]
+
+mod config;
+
+pub mod block;
+pub mod chain;
+pub mod checkpoint;
+pub mod error;
+pub mod primitives;
+pub mod transaction;
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
-    }
-}
+pub use config::Config;
+pub use error::VerificationError;
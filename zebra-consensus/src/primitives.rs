@@ -0,0 +1,51 @@
+//! Asynchronous verification of cryptographic primitives.
+//!
+//! Each verifier is a [`tower_batch::Batch`] service, which collects
+//! verification requests from every transaction, and verifies them together
+//! on a blocking thread.
+//!
+//! A batch is flushed when it has `max_items` items, when its first item has
+//! waited for `max_latency`, or when the caller flushes it at the end of a
+//! block.
+
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::block;
+
+pub mod ed25519;
+pub mod groth16;
+pub mod halo2;
+pub mod redjubjub;
+pub mod redpallas;
+
+/// The default maximum number of items in a verification batch.
+pub(crate) const DEFAULT_MAX_BATCH_SIZE: usize = 64;
+
+/// The default maximum time an item waits for the rest of its batch.
+pub(crate) const DEFAULT_MAX_BATCH_LATENCY: Duration = Duration::from_millis(100);
+
+/// A batch verifier was dropped before it verified an item.
+#[derive(Error, Debug, Clone, Copy, Eq, PartialEq)]
+#[error("{0} verifier was dropped")]
+pub struct VerifierDropped(pub &'static str);
+
+/// Returns true if `error` is a failure of a batch verifier service, rather
+/// than of the item it was asked to verify.
+///
+/// These errors mean the item couldn't be checked, so they aren't the fault
+/// of the peer that sent it.
+pub(crate) fn is_service_failure(error: &block::Error) -> bool {
+    error.is::<tower_batch::error::ServiceError>()
+        || error.is::<tower_batch::error::Closed>()
+        || error.is::<VerifierDropped>()
+        || matches!(
+            error.downcast_ref::<groth16::VerificationError>(),
+            Some(groth16::VerificationError::Dropped)
+        )
+        || matches!(
+            error.downcast_ref::<halo2::VerificationError>(),
+            Some(halo2::VerificationError::Dropped)
+        )
+}
@@ -0,0 +1,44 @@
+//! Tests for Ed25519 verification.
+
+use tower::ServiceExt;
+
+use zebra_chain::ed25519_zebra::{SigningKey, VerificationKey, VerificationKeyBytes};
+
+use crate::primitives::{DEFAULT_MAX_BATCH_LATENCY, DEFAULT_MAX_BATCH_SIZE};
+
+use super::*;
+
+/// Returns an item for a signature on `msg`, which is verified against
+/// `verified_msg`.
+fn item(msg: &[u8], verified_msg: &[u8]) -> Item {
+    let sk = SigningKey::new(OsRng);
+    let vk = VerificationKeyBytes::from(VerificationKey::from(&sk));
+    Item::from((vk, sk.sign(msg), &verified_msg))
+}
+
+#[tokio::test]
+async fn invalid_signatures_fail_in_batches() {
+    let mut verifier = verifier(DEFAULT_MAX_BATCH_SIZE, DEFAULT_MAX_BATCH_LATENCY);
+
+    let items = vec![
+        item(b"joinsplit", b"joinsplit"),
+        item(b"joinsplit", b"other message"),
+        item(b"another joinsplit", b"another joinsplit"),
+    ];
+
+    let mut responses = Vec::new();
+    for item in items {
+        let verifier = verifier
+            .ready_and()
+            .await
+            .expect("the verifier is always ready");
+        responses.push(verifier.call(item));
+    }
+
+    let results: Vec<bool> = futures::future::join_all(responses)
+        .await
+        .into_iter()
+        .map(|result| result.is_ok())
+        .collect();
+    assert_eq!(results, vec![true, false, true]);
+}
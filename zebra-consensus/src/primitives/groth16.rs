@@ -0,0 +1,375 @@
+//! Async Groth16 batch verification for Sapling proofs.
+//!
+//! Proofs are checked using a random linear combination of their
+//! verification equations, so a batch of `n` proofs needs `n + 2` Miller
+//! loops and a single final exponentiation, instead of `3n` of each. If a
+//! batch fails, each proof in it is checked on its own, so that only the
+//! invalid proofs are rejected.
+
+pub mod params;
+#[cfg(test)]
+mod tests;
+
+pub use params::{Groth16Params, VerifyingKey, PARAMS};
+
+use std::{
+    convert::TryFrom,
+    future::Future,
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use bls12_381::{multi_miller_loop, G1Affine, G1Projective, G2Affine, G2Prepared, Scalar};
+use futures::{channel::oneshot, FutureExt};
+use rand_core::{OsRng, RngCore};
+use thiserror::Error;
+use tower::Service;
+use tower_batch::{Batch, BatchControl};
+
+use zebra_chain::{
+    proofs::Groth16Proof,
+    transaction::{Output, Spend},
+};
+
+/// The number of bits that fit in a BLS12-381 scalar.
+const SCALAR_CAPACITY: usize = 254;
+
+/// A Groth16 verification failure.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum VerificationError {
+    /// The proof isn't made of valid, non-identity curve points.
+    #[error("malformed Groth16 proof")]
+    MalformedProof,
+    /// A public input isn't a valid curve point or field element.
+    #[error("malformed Groth16 public input")]
+    MalformedInput,
+    /// A public input is a Jubjub point of small order.
+    #[error("small-order Jubjub point in the Groth16 public inputs")]
+    SmallOrderInput,
+    /// The proof has the wrong number of public inputs for its key.
+    #[error("expected {expected} Groth16 public inputs, found {actual}")]
+    WrongInputCount {
+        /// The number of inputs for the verifying key.
+        expected: usize,
+        /// The number of inputs in the item.
+        actual: usize,
+    },
+    /// The proof doesn't verify.
+    #[error("invalid Groth16 proof")]
+    InvalidProof,
+    /// The verifier was dropped before the proof was verified.
+    #[error("Groth16 verifier was dropped")]
+    Dropped,
+}
+
+/// A decoded Groth16 proof.
+#[derive(Clone, Debug)]
+struct Proof {
+    a: G1Affine,
+    b: G2Affine,
+    c: G1Affine,
+}
+
+impl TryFrom<&Groth16Proof> for Proof {
+    type Error = VerificationError;
+
+    fn try_from(proof: &Groth16Proof) -> Result<Proof, VerificationError> {
+        let mut a = [0u8; 48];
+        let mut b = [0u8; 96];
+        let mut c = [0u8; 48];
+        a.copy_from_slice(&proof.0[..48]);
+        b.copy_from_slice(&proof.0[48..144]);
+        c.copy_from_slice(&proof.0[144..]);
+
+        let a = Option::<G1Affine>::from(G1Affine::from_compressed(&a));
+        let b = Option::<G2Affine>::from(G2Affine::from_compressed(&b));
+        let c = Option::<G1Affine>::from(G1Affine::from_compressed(&c));
+
+        match (a, b, c) {
+            (Some(a), Some(b), Some(c))
+                if !bool::from(a.is_identity() | b.is_identity() | c.is_identity()) =>
+            {
+                Ok(Proof { a, b, c })
+            }
+            _ => Err(VerificationError::MalformedProof),
+        }
+    }
+}
+
+/// A Groth16 proof and its public inputs, waiting for verification.
+#[derive(Clone, Debug)]
+pub struct Item {
+    proof: Proof,
+    inputs: Vec<Scalar>,
+}
+
+impl Item {
+    /// Returns an item for `proof` with the primary `inputs`.
+    pub fn new(proof: &Groth16Proof, inputs: Vec<Scalar>) -> Result<Item, VerificationError> {
+        Ok(Item {
+            proof: Proof::try_from(proof)?,
+            inputs,
+        })
+    }
+}
+
+impl TryFrom<&Spend> for Item {
+    type Error = VerificationError;
+
+    /// The spend circuit's inputs are `rk`, `cv`, the anchor, and the
+    /// multipacked nullifier.
+    fn try_from(spend: &Spend) -> Result<Item, VerificationError> {
+        let rk = jubjub_point(spend.rk.into())?;
+        let cv = jubjub_point(spend.cv)?;
+
+        let mut inputs = vec![
+            rk.get_u(),
+            rk.get_v(),
+            cv.get_u(),
+            cv.get_v(),
+            field_element(spend.anchor.0)?,
+        ];
+        inputs.extend(multipack(&spend.nullifier.0));
+
+        Item::new(&spend.zkproof, inputs)
+    }
+}
+
+impl TryFrom<&Output> for Item {
+    type Error = VerificationError;
+
+    /// The output circuit's inputs are `cv`, the ephemeral key, and `cmu`.
+    fn try_from(output: &Output) -> Result<Item, VerificationError> {
+        let cv = jubjub_point(output.cv)?;
+        let epk = large_order(output.ephemeral_key)?;
+
+        let inputs = vec![
+            cv.get_u(),
+            cv.get_v(),
+            epk.get_u(),
+            epk.get_v(),
+            field_element(output.cmu)?,
+        ];
+
+        Item::new(&output.zkproof, inputs)
+    }
+}
+
+/// Decodes a Jubjub point, which is a public input to a Sapling circuit.
+fn jubjub_point(bytes: [u8; 32]) -> Result<jubjub::AffinePoint, VerificationError> {
+    let point = Option::from(jubjub::AffinePoint::from_bytes(bytes))
+        .ok_or(VerificationError::MalformedInput)?;
+    large_order(point)
+}
+
+/// Rejects Jubjub points of small order.
+///
+/// The circuits don't check the order of `cv`, `rk`, or `epk`, so `zcashd`
+/// rejects small-order points before verifying the proof.
+fn large_order(point: jubjub::AffinePoint) -> Result<jubjub::AffinePoint, VerificationError> {
+    if bool::from(jubjub::ExtendedPoint::from(point).is_small_order()) {
+        return Err(VerificationError::SmallOrderInput);
+    }
+    Ok(point)
+}
+
+/// Decodes a canonical BLS12-381 scalar.
+fn field_element(bytes: [u8; 32]) -> Result<Scalar, VerificationError> {
+    Option::from(Scalar::from_bytes(&bytes)).ok_or(VerificationError::MalformedInput)
+}
+
+/// Packs the little-endian bits of `bytes` into as few scalars as possible,
+/// like `bellman`'s `multipack` gadget.
+fn multipack(bytes: &[u8; 32]) -> Vec<Scalar> {
+    let bits: Vec<bool> = (0..256)
+        .map(|i| (bytes[i / 8] >> (i % 8)) & 1 == 1)
+        .collect();
+
+    bits.chunks(SCALAR_CAPACITY)
+        .map(|chunk| {
+            let mut repr = [0u8; 32];
+            for (i, _) in chunk.iter().enumerate().filter(|(_, bit)| **bit) {
+                repr[i / 8] |= 1 << (i % 8);
+            }
+            Option::<Scalar>::from(Scalar::from_bytes(&repr))
+                .expect("chunks are smaller than the field modulus")
+        })
+        .collect()
+}
+
+impl VerifyingKey {
+    /// Returns true if every proof in `items` is valid, using a random
+    /// `weight` for each item.
+    ///
+    /// Each proof satisfies `e(A, B) = e(alpha, beta) * e(I, gamma) *
+    /// e(C, delta)`, where `I` combines the public inputs. The weighted
+    /// product of these equations is checked instead of each equation.
+    fn verify_weighted(&self, items: &[(&Item, Scalar)]) -> bool {
+        let mut g1 = Vec::with_capacity(items.len() + 2);
+        let mut g2 = Vec::with_capacity(items.len() + 2);
+        let mut inputs_acc = G1Projective::identity();
+        let mut c_acc = G1Projective::identity();
+        let mut weight_sum = Scalar::zero();
+
+        for (item, weight) in items {
+            let mut inputs = G1Projective::from(self.ic[0]);
+            for (input, base) in item.inputs.iter().zip(&self.ic[1..]) {
+                inputs += base * input;
+            }
+
+            inputs_acc += inputs * weight;
+            c_acc += item.proof.c * weight;
+            g1.push(G1Affine::from(item.proof.a * weight));
+            g2.push(G2Prepared::from(item.proof.b));
+            weight_sum += weight;
+        }
+
+        g1.push(G1Affine::from(-inputs_acc));
+        g2.push(self.gamma_g2.clone());
+        g1.push(G1Affine::from(-c_acc));
+        g2.push(self.delta_g2.clone());
+
+        let terms: Vec<_> = g1.iter().zip(g2.iter()).collect();
+        multi_miller_loop(&terms).final_exponentiation() == self.alpha_beta * weight_sum
+    }
+
+    /// Checks that `item` has the right number of inputs for this key.
+    fn check_inputs_len(&self, item: &Item) -> Result<(), VerificationError> {
+        if item.inputs.len() != self.inputs_len() {
+            return Err(VerificationError::WrongInputCount {
+                expected: self.inputs_len(),
+                actual: item.inputs.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Verifies a single proof.
+    pub fn verify(&self, item: &Item) -> Result<(), VerificationError> {
+        self.check_inputs_len(item)?;
+        if !self.verify_weighted(&[(item, Scalar::one())]) {
+            return Err(VerificationError::InvalidProof);
+        }
+        Ok(())
+    }
+
+    /// Verifies a batch of proofs, and returns the result for each proof.
+    fn verify_batch(&self, items: &[Item]) -> Vec<Result<(), VerificationError>> {
+        let input_lens: Vec<_> = items
+            .iter()
+            .map(|item| self.check_inputs_len(item))
+            .collect();
+
+        // 128-bit weights make a batch with an invalid proof pass with
+        // negligible probability.
+        let weighted: Vec<_> = items
+            .iter()
+            .zip(&input_lens)
+            .filter(|(_, len)| len.is_ok())
+            .map(|(item, _)| {
+                let mut bytes = [0u8; 64];
+                OsRng.fill_bytes(&mut bytes[..16]);
+                (item, Scalar::from_bytes_wide(&bytes))
+            })
+            .collect();
+
+        if self.verify_weighted(&weighted) {
+            input_lens
+        } else {
+            items
+                .iter()
+                .zip(input_lens)
+                .map(|(item, len)| len.and_then(|()| self.verify(item)))
+                .collect()
+        }
+    }
+}
+
+/// A Groth16 verification result, sent to the caller.
+type Tx = oneshot::Sender<Result<(), VerificationError>>;
+
+/// Collects Groth16 proofs for one verifying key, and verifies them in
+/// batches.
+///
+/// This service should be wrapped in a [`Batch`], using [`spend_verifier`]
+/// or [`output_verifier`].
+#[derive(Debug)]
+pub struct Verifier {
+    /// The key that proofs are verified with.
+    key: &'static VerifyingKey,
+    /// Items waiting for the next flush.
+    pending: Vec<(Item, Tx)>,
+}
+
+impl Verifier {
+    /// Returns a verifier for proofs using `key`.
+    pub fn new(key: &'static VerifyingKey) -> Verifier {
+        Verifier {
+            key,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Verifies the pending items on a blocking thread, and sends each
+    /// result to its caller.
+    fn flush(&mut self) {
+        let pending = mem::take(&mut self.pending);
+        if pending.is_empty() {
+            return;
+        }
+
+        let key = self.key;
+        tokio::task::spawn_blocking(move || {
+            let (items, txs): (Vec<_>, Vec<_>) = pending.into_iter().unzip();
+            for (result, tx) in key.verify_batch(&items).into_iter().zip(txs) {
+                let _ = tx.send(result);
+            }
+        });
+    }
+}
+
+impl Service<BatchControl<Item>> for Verifier {
+    type Response = ();
+    type Error = VerificationError;
+    type Future = Pin<Box<dyn Future<Output = Result<(), VerificationError>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: BatchControl<Item>) -> Self::Future {
+        match request {
+            BatchControl::Item(item) => {
+                let (tx, rx) = oneshot::channel();
+                self.pending.push((item, tx));
+
+                async move { rx.await.unwrap_or(Err(VerificationError::Dropped)) }.boxed()
+            }
+            BatchControl::Flush => {
+                self.flush();
+                async { Ok(()) }.boxed()
+            }
+        }
+    }
+}
+
+/// A batched Groth16 verifier for a single verifying key.
+pub type BatchVerifier = Batch<Verifier, Item>;
+
+/// Returns a batch verifier for Sapling spend proofs.
+///
+/// Batches are flushed when they have `max_items` items, or after
+/// `max_latency`. Must be called from within a Tokio runtime.
+pub fn spend_verifier(max_items: usize, max_latency: Duration) -> BatchVerifier {
+    Batch::new(Verifier::new(&PARAMS.spend), max_items, max_latency)
+}
+
+/// Returns a batch verifier for Sapling output proofs.
+///
+/// See [`spend_verifier`] for the batch limits. Must be called from within
+/// a Tokio runtime.
+pub fn output_verifier(max_items: usize, max_latency: Duration) -> BatchVerifier {
+    Batch::new(Verifier::new(&PARAMS.output), max_items, max_latency)
+}
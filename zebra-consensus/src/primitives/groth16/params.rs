@@ -0,0 +1,94 @@
+//! The Sapling Groth16 verifying keys.
+
+use std::io::{self, Read};
+
+use bls12_381::{pairing, G1Affine, G2Affine, G2Prepared, Gt};
+use lazy_static::lazy_static;
+
+lazy_static! {
+    /// The Sapling verifying keys, parsed from the embedded parameters.
+    pub static ref PARAMS: Groth16Params = Groth16Params::new();
+}
+
+/// The Groth16 verifying keys used by Zebra.
+#[derive(Debug)]
+pub struct Groth16Params {
+    /// The verifying key for Sapling spend proofs.
+    pub spend: VerifyingKey,
+    /// The verifying key for Sapling output proofs.
+    pub output: VerifyingKey,
+}
+
+impl Groth16Params {
+    fn new() -> Groth16Params {
+        let (spend_bytes, output_bytes) = wagyu_zcash_parameters::load_sapling_parameters();
+
+        Groth16Params {
+            spend: VerifyingKey::read(&spend_bytes[..])
+                .expect("the embedded Sapling spend parameters are valid"),
+            output: VerifyingKey::read(&output_bytes[..])
+                .expect("the embedded Sapling output parameters are valid"),
+        }
+    }
+}
+
+/// A Groth16 verifying key, prepared for verification.
+#[derive(Debug)]
+pub struct VerifyingKey {
+    /// The pairing of `alpha_g1` and `beta_g2`.
+    pub(super) alpha_beta: Gt,
+    /// The prepared `gamma_g2` element.
+    pub(super) gamma_g2: G2Prepared,
+    /// The prepared `delta_g2` element.
+    pub(super) delta_g2: G2Prepared,
+    /// The bases for the public inputs, starting with the constant term.
+    pub(super) ic: Vec<G1Affine>,
+}
+
+impl VerifyingKey {
+    /// Reads a verifying key from the start of a `bellman` parameters file.
+    ///
+    /// The rest of the file is only needed for proving.
+    pub fn read<R: Read>(mut reader: R) -> io::Result<VerifyingKey> {
+        let alpha_g1 = read_g1(&mut reader)?;
+        let _beta_g1 = read_g1(&mut reader)?;
+        let beta_g2 = read_g2(&mut reader)?;
+        let gamma_g2 = read_g2(&mut reader)?;
+        let _delta_g1 = read_g1(&mut reader)?;
+        let delta_g2 = read_g2(&mut reader)?;
+
+        let mut len = [0u8; 4];
+        reader.read_exact(&mut len)?;
+        let ic = (0..u32::from_be_bytes(len))
+            .map(|_| read_g1(&mut reader))
+            .collect::<io::Result<_>>()?;
+
+        Ok(VerifyingKey {
+            alpha_beta: pairing(&alpha_g1, &beta_g2),
+            gamma_g2: G2Prepared::from(gamma_g2),
+            delta_g2: G2Prepared::from(delta_g2),
+            ic,
+        })
+    }
+
+    /// Returns the number of public inputs for proofs using this key.
+    pub fn inputs_len(&self) -> usize {
+        self.ic.len() - 1
+    }
+}
+
+/// Reads an uncompressed G1 point.
+fn read_g1<R: Read>(mut reader: R) -> io::Result<G1Affine> {
+    let mut bytes = [0u8; 96];
+    reader.read_exact(&mut bytes)?;
+    Option::from(G1Affine::from_uncompressed(&bytes))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid G1 point"))
+}
+
+/// Reads an uncompressed G2 point.
+fn read_g2<R: Read>(mut reader: R) -> io::Result<G2Affine> {
+    let mut bytes = [0u8; 192];
+    reader.read_exact(&mut bytes)?;
+    Option::from(G2Affine::from_uncompressed(&bytes))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid G2 point"))
+}
@@ -0,0 +1,186 @@
+//! Tests for Groth16 verification.
+
+use bls12_381::{pairing, G2Affine};
+use tower::ServiceExt;
+
+use zebra_chain::notes::sapling::{EncryptedCiphertext, OutCiphertext};
+
+use crate::primitives::{DEFAULT_MAX_BATCH_LATENCY, DEFAULT_MAX_BATCH_SIZE};
+
+use super::*;
+
+/// Returns the encoding of a proof made of the curve generators, which is
+/// well-formed, but doesn't verify.
+fn generator_proof() -> Groth16Proof {
+    let mut bytes = [0u8; 192];
+    bytes[..48].copy_from_slice(&G1Affine::generator().to_compressed());
+    bytes[48..144].copy_from_slice(&G2Affine::generator().to_compressed());
+    bytes[144..].copy_from_slice(&G1Affine::generator().to_compressed());
+    Groth16Proof(bytes)
+}
+
+/// Returns a verifying key with known trapdoors, and a valid proof for
+/// `inputs` using that key.
+///
+/// Making a real Sapling proof needs the proving parameters, so the proof is
+/// made from the trapdoors instead. It satisfies the same equation.
+fn synthetic_proof(inputs: &[Scalar]) -> (VerifyingKey, Groth16Proof) {
+    let g1 = G1Affine::generator();
+    let g2 = G2Affine::generator();
+    let alpha = Scalar::from(2u64);
+    let beta = Scalar::from(3u64);
+    let gamma = Scalar::from(5u64);
+    let delta = Scalar::from(7u64);
+    let ic: Vec<Scalar> = (0..=inputs.len())
+        .map(|i| Scalar::from(11 + i as u64))
+        .collect();
+
+    let key = VerifyingKey {
+        alpha_beta: pairing(&G1Affine::from(g1 * alpha), &G2Affine::from(g2 * beta)),
+        gamma_g2: G2Prepared::from(G2Affine::from(g2 * gamma)),
+        delta_g2: G2Prepared::from(G2Affine::from(g2 * delta)),
+        ic: ic.iter().map(|base| G1Affine::from(g1 * base)).collect(),
+    };
+
+    // `k` is the discrete log of the inputs term `I`, so choosing `A` and
+    // `B` fixes the `C` that satisfies `ab = alpha * beta + k * gamma + c * delta`.
+    let k = inputs
+        .iter()
+        .zip(&ic[1..])
+        .fold(ic[0], |k, (input, base)| k + input * base);
+    let a = Scalar::from(17u64);
+    let b = Scalar::from(19u64);
+    let c = (a * b - alpha * beta - k * gamma) * delta.invert().unwrap();
+
+    let mut bytes = [0u8; 192];
+    bytes[..48].copy_from_slice(&G1Affine::from(g1 * a).to_compressed());
+    bytes[48..144].copy_from_slice(&G2Affine::from(g2 * b).to_compressed());
+    bytes[144..].copy_from_slice(&G1Affine::from(g1 * c).to_compressed());
+    (key, Groth16Proof(bytes))
+}
+
+#[test]
+fn sapling_verifying_keys_parse() {
+    // rk, cv, the anchor, and the two nullifier scalars
+    assert_eq!(PARAMS.spend.inputs_len(), 7);
+    // cv, epk, and cmu
+    assert_eq!(PARAMS.output.inputs_len(), 5);
+}
+
+#[test]
+fn nullifiers_are_multipacked() {
+    let mut one = [0u8; 32];
+    one[0] = 1;
+    assert_eq!(multipack(&one), vec![Scalar::one(), Scalar::zero()]);
+
+    // The top two bits are packed into the second scalar.
+    let mut top = [0u8; 32];
+    top[31] = 0xc0;
+    assert_eq!(multipack(&top), vec![Scalar::zero(), Scalar::from(3u64)]);
+}
+
+#[test]
+fn malformed_proofs_are_rejected() {
+    assert_eq!(
+        Item::new(&Groth16Proof([0; 192]), vec![]).map(|_| ()),
+        Err(VerificationError::MalformedProof)
+    );
+    assert!(Item::new(&generator_proof(), vec![]).is_ok());
+}
+
+#[test]
+fn valid_proofs_verify() {
+    let inputs = vec![
+        Scalar::from(23u64),
+        Scalar::one(),
+        Scalar::zero(),
+        Scalar::from(29u64),
+        Scalar::from(31u64),
+    ];
+    let (key, proof) = synthetic_proof(&inputs);
+
+    let valid = Item::new(&proof, inputs.clone()).expect("synthetic proofs are well-formed");
+    assert_eq!(key.verify(&valid), Ok(()));
+
+    let mut changed = inputs;
+    changed[3] += Scalar::one();
+    let invalid = Item::new(&proof, changed).expect("synthetic proofs are well-formed");
+    assert_eq!(key.verify(&invalid), Err(VerificationError::InvalidProof));
+
+    assert_eq!(
+        key.verify_batch(&[valid.clone(), valid.clone()]),
+        vec![Ok(()), Ok(())]
+    );
+    assert_eq!(
+        key.verify_batch(&[valid.clone(), invalid, valid]),
+        vec![Ok(()), Err(VerificationError::InvalidProof), Ok(())]
+    );
+}
+
+#[test]
+fn small_order_inputs_are_rejected() {
+    let identity = jubjub::AffinePoint::identity();
+    assert_eq!(
+        jubjub_point(identity.to_bytes()).map(|_| ()),
+        Err(VerificationError::SmallOrderInput)
+    );
+    assert_eq!(
+        large_order(identity).map(|_| ()),
+        Err(VerificationError::SmallOrderInput)
+    );
+
+    let output = Output {
+        cv: identity.to_bytes(),
+        cmu: [0; 32],
+        ephemeral_key: identity,
+        enc_ciphertext: EncryptedCiphertext([0; 580]),
+        out_ciphertext: OutCiphertext([0; 80]),
+        zkproof: generator_proof(),
+    };
+    assert_eq!(
+        Item::try_from(&output).map(|_| ()),
+        Err(VerificationError::SmallOrderInput)
+    );
+}
+
+#[tokio::test]
+async fn invalid_proofs_fail_in_batches() {
+    let mut verifier = output_verifier(DEFAULT_MAX_BATCH_SIZE, DEFAULT_MAX_BATCH_LATENCY);
+
+    let valid_len = Item::new(&generator_proof(), vec![Scalar::one(); 5])
+        .expect("generator proofs are well-formed");
+    let wrong_len = Item::new(&generator_proof(), vec![Scalar::one(); 7])
+        .expect("generator proofs are well-formed");
+
+    let mut responses = Vec::new();
+    for item in [&valid_len, &wrong_len, &valid_len].iter() {
+        let verifier = verifier
+            .ready_and()
+            .await
+            .expect("the verifier is always ready");
+        responses.push(verifier.call((*item).clone()));
+    }
+
+    let results: Vec<_> = futures::future::join_all(responses)
+        .await
+        .into_iter()
+        .map(|result| {
+            result
+                .expect_err("generator proofs don't verify")
+                .downcast_ref::<VerificationError>()
+                .cloned()
+        })
+        .collect();
+
+    assert_eq!(
+        results,
+        vec![
+            Some(VerificationError::InvalidProof),
+            Some(VerificationError::WrongInputCount {
+                expected: 5,
+                actual: 7
+            }),
+            Some(VerificationError::InvalidProof),
+        ]
+    );
+}
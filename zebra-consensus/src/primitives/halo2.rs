@@ -0,0 +1,286 @@
+//! Async Halo2 batch verification for Orchard proofs.
+//!
+//! Each Orchard bundle has a single proof for all of its actions. The public
+//! inputs for each action are derived from the action's fields, and the
+//! bundle's anchor and flags.
+//!
+//! Batches use the `halo2` batch verifier, which combines the final checks
+//! of every proof into a single multiscalar multiplication. If a batch fails,
+//! each proof in it is checked on its own.
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+    convert::TryFrom,
+    fmt,
+    future::Future,
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{channel::oneshot, FutureExt};
+use halo2::{
+    plonk::{self, BatchVerifier as ProofBatch},
+    poly::commitment::Params,
+};
+use lazy_static::lazy_static;
+use pasta_curves::{
+    arithmetic::CurveAffine,
+    group::{
+        ff::{Field, PrimeField},
+        GroupEncoding,
+    },
+    pallas, vesta,
+};
+use thiserror::Error;
+use tower::Service;
+use tower_batch::{Batch, BatchControl};
+
+use zebra_chain::orchard::{Action, ShieldedData};
+
+/// The size of the Orchard circuit, as a power of two.
+const ORCHARD_CIRCUIT_K: u32 = 11;
+
+lazy_static! {
+    /// The Orchard verifying key, which is generated from the circuit.
+    pub static ref VERIFYING_KEY: VerifyingKey = VerifyingKey::build();
+}
+
+/// A Halo2 verification failure.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum VerificationError {
+    /// A public input isn't a valid Pallas point or field element.
+    #[error("malformed Halo2 public input")]
+    MalformedInput,
+    /// The proof doesn't verify.
+    #[error("invalid Halo2 proof")]
+    InvalidProof,
+    /// The verifier was dropped before the proof was verified.
+    #[error("Halo2 verifier was dropped")]
+    Dropped,
+}
+
+/// The verifying key for the Orchard action circuit.
+pub struct VerifyingKey {
+    params: Params<vesta::Affine>,
+    vk: plonk::VerifyingKey<vesta::Affine>,
+}
+
+impl VerifyingKey {
+    /// Generates the verifying key for the Orchard circuit.
+    ///
+    /// Halo2 doesn't have a trusted setup, so Zebra can build the key itself,
+    /// rather than loading parameters.
+    fn build() -> VerifyingKey {
+        let params = Params::new(ORCHARD_CIRCUIT_K);
+        let circuit = orchard::circuit::Circuit::default();
+        let vk = plonk::keygen_vk(&params, &circuit)
+            .expect("the Orchard circuit fits in its parameters");
+
+        VerifyingKey { params, vk }
+    }
+
+    /// Returns true if every proof in `batch` is valid.
+    fn verify_batch(&self, batch: ProofBatch<vesta::Affine>) -> bool {
+        batch.finalize(&self.params, &self.vk)
+    }
+
+    /// Verifies a single proof.
+    pub fn verify(&self, item: &Item) -> Result<(), VerificationError> {
+        let mut batch = ProofBatch::new();
+        item.queue(&mut batch);
+        if !self.verify_batch(batch) {
+            return Err(VerificationError::InvalidProof);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for VerifyingKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VerifyingKey")
+            .field("k", &ORCHARD_CIRCUIT_K)
+            .finish()
+    }
+}
+
+/// An Orchard proof and the public inputs for each of its actions, waiting
+/// for verification.
+#[derive(Clone, Debug)]
+pub struct Item {
+    /// The public inputs for each action, in circuit order.
+    instances: Vec<Vec<pallas::Base>>,
+    proof: Vec<u8>,
+}
+
+impl Item {
+    /// Adds this item to `batch`.
+    fn queue(&self, batch: &mut ProofBatch<vesta::Affine>) {
+        let instances = self
+            .instances
+            .iter()
+            .map(|instance| vec![instance.clone()])
+            .collect();
+        batch.add_proof(instances, self.proof.clone());
+    }
+}
+
+impl TryFrom<&ShieldedData> for Item {
+    type Error = VerificationError;
+
+    /// The action circuit's inputs are the anchor, `cv_net`, the nullifier,
+    /// `rk`, `cmx`, and the spend and output flags.
+    fn try_from(shielded_data: &ShieldedData) -> Result<Item, VerificationError> {
+        let anchor = field_element(shielded_data.shared_anchor.0)?;
+        let enable_spends = flag(shielded_data.flags.enable_spends);
+        let enable_outputs = flag(shielded_data.flags.enable_outputs);
+
+        let instances = shielded_data
+            .actions()
+            .map(|action| action_instance(action, anchor, enable_spends, enable_outputs))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Item {
+            instances,
+            proof: shielded_data.proof.0.to_vec(),
+        })
+    }
+}
+
+/// Returns the public inputs for `action`.
+fn action_instance(
+    action: &Action,
+    anchor: pallas::Base,
+    enable_spends: pallas::Base,
+    enable_outputs: pallas::Base,
+) -> Result<Vec<pallas::Base>, VerificationError> {
+    let (cv_x, cv_y) = point_coordinates(action.cv)?;
+    let (rk_x, rk_y) = point_coordinates(action.rk)?;
+
+    Ok(vec![
+        anchor,
+        cv_x,
+        cv_y,
+        field_element(action.nullifier.0)?,
+        rk_x,
+        rk_y,
+        field_element(action.cm_x)?,
+        enable_spends,
+        enable_outputs,
+    ])
+}
+
+/// Decodes a canonical Pallas base field element.
+fn field_element(bytes: [u8; 32]) -> Result<pallas::Base, VerificationError> {
+    Option::from(pallas::Base::from_repr(bytes)).ok_or(VerificationError::MalformedInput)
+}
+
+/// Decodes a Pallas point, and returns its affine coordinates.
+///
+/// The identity doesn't have affine coordinates, so it isn't a valid input.
+fn point_coordinates(bytes: [u8; 32]) -> Result<(pallas::Base, pallas::Base), VerificationError> {
+    let point: Option<pallas::Affine> = pallas::Affine::from_bytes(&bytes).into();
+    let coordinates = point
+        .and_then(|point| Option::from(point.coordinates()))
+        .ok_or(VerificationError::MalformedInput)?;
+    Ok((*coordinates.x(), *coordinates.y()))
+}
+
+/// Encodes a flag as a field element.
+fn flag(enabled: bool) -> pallas::Base {
+    if enabled {
+        pallas::Base::one()
+    } else {
+        pallas::Base::zero()
+    }
+}
+
+/// A Halo2 verification result, sent to the caller.
+type Tx = oneshot::Sender<Result<(), VerificationError>>;
+
+/// Collects Orchard proofs, and verifies them in batches.
+///
+/// This service should be wrapped in a [`Batch`], using [`verifier`].
+#[derive(Debug)]
+pub struct Verifier {
+    /// The key that proofs are verified with.
+    key: &'static VerifyingKey,
+    /// Items waiting for the next flush.
+    pending: Vec<(Item, Tx)>,
+}
+
+impl Verifier {
+    /// Returns a verifier for proofs using `key`.
+    pub fn new(key: &'static VerifyingKey) -> Verifier {
+        Verifier {
+            key,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Verifies the pending items on a blocking thread, and sends each
+    /// result to its caller.
+    fn flush(&mut self) {
+        let pending = mem::take(&mut self.pending);
+        if pending.is_empty() {
+            return;
+        }
+
+        let key = self.key;
+        tokio::task::spawn_blocking(move || {
+            let mut batch = ProofBatch::new();
+            for (item, _) in &pending {
+                item.queue(&mut batch);
+            }
+
+            if key.verify_batch(batch) {
+                for (_, tx) in pending {
+                    let _ = tx.send(Ok(()));
+                }
+            } else {
+                for (item, tx) in pending {
+                    let _ = tx.send(key.verify(&item));
+                }
+            }
+        });
+    }
+}
+
+impl Service<BatchControl<Item>> for Verifier {
+    type Response = ();
+    type Error = VerificationError;
+    type Future = Pin<Box<dyn Future<Output = Result<(), VerificationError>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: BatchControl<Item>) -> Self::Future {
+        match request {
+            BatchControl::Item(item) => {
+                let (tx, rx) = oneshot::channel();
+                self.pending.push((item, tx));
+
+                async move { rx.await.unwrap_or(Err(VerificationError::Dropped)) }.boxed()
+            }
+            BatchControl::Flush => {
+                self.flush();
+                async { Ok(()) }.boxed()
+            }
+        }
+    }
+}
+
+/// A batched Halo2 verifier for Orchard proofs.
+pub type BatchVerifier = Batch<Verifier, Item>;
+
+/// Returns a batch verifier for Orchard proofs.
+///
+/// Batches are flushed when they have `max_items` items, or after
+/// `max_latency`. Must be called from within a Tokio runtime.
+pub fn verifier(max_items: usize, max_latency: Duration) -> BatchVerifier {
+    Batch::new(Verifier::new(&VERIFYING_KEY), max_items, max_latency)
+}
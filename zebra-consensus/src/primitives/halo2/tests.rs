@@ -0,0 +1,83 @@
+//! Tests for Halo2 verification.
+
+use pasta_curves::group::prime::PrimeCurveAffine;
+use tower::ServiceExt;
+
+use zebra_chain::{
+    amount::Amount,
+    orchard::{tree, EncryptedNote, Flags, Nullifier, RedPallasSignature, WrappedNoteKey},
+    proofs::Halo2Proof,
+};
+
+use crate::primitives::{DEFAULT_MAX_BATCH_LATENCY, DEFAULT_MAX_BATCH_SIZE};
+
+use super::*;
+
+/// Returns Orchard shielded data with two actions, which have well-formed
+/// public inputs, and an empty proof.
+fn shielded_data() -> ShieldedData {
+    let generator = pallas::Affine::generator().to_bytes();
+    let action = Action {
+        cv: generator,
+        nullifier: Nullifier([0; 32]),
+        rk: generator,
+        cm_x: [0; 32],
+        ephemeral_key: [0; 32],
+        enc_ciphertext: EncryptedNote([0; 580]),
+        out_ciphertext: WrappedNoteKey([0; 80]),
+        spend_auth_sig: RedPallasSignature([0; 64]),
+    };
+
+    ShieldedData {
+        flags: Flags {
+            enable_spends: true,
+            enable_outputs: true,
+        },
+        value_balance: Amount::zero(),
+        shared_anchor: tree::Root([0; 32]),
+        proof: Halo2Proof(vec![].into()),
+        first: action.clone(),
+        rest: vec![action],
+        binding_sig: RedPallasSignature([0; 64]),
+    }
+}
+
+#[test]
+fn each_action_has_an_instance() {
+    let item = Item::try_from(&shielded_data()).expect("the inputs are well-formed");
+    assert_eq!(item.instances.len(), 2);
+    assert!(item.instances.iter().all(|instance| instance.len() == 9));
+}
+
+#[test]
+fn malformed_inputs_are_rejected() {
+    let mut data = shielded_data();
+    data.shared_anchor = tree::Root([0xff; 32]);
+    assert_eq!(
+        Item::try_from(&data).map(|_| ()),
+        Err(VerificationError::MalformedInput)
+    );
+
+    // The identity doesn't have affine coordinates.
+    let mut data = shielded_data();
+    data.first.cv = pallas::Affine::identity().to_bytes();
+    assert_eq!(
+        Item::try_from(&data).map(|_| ()),
+        Err(VerificationError::MalformedInput)
+    );
+}
+
+#[tokio::test]
+async fn invalid_proofs_are_rejected() {
+    let verifier = verifier(DEFAULT_MAX_BATCH_SIZE, DEFAULT_MAX_BATCH_LATENCY);
+    let item = Item::try_from(&shielded_data()).expect("the inputs are well-formed");
+
+    let error = verifier
+        .oneshot(item)
+        .await
+        .expect_err("an empty proof doesn't verify");
+    assert_eq!(
+        error.downcast_ref::<VerificationError>(),
+        Some(&VerificationError::InvalidProof)
+    );
+}
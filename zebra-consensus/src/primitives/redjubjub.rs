@@ -0,0 +1,111 @@
+//! Async RedJubjub batch verification for Sapling signatures.
+//!
+//! Spend authorization signatures and binding signatures are verified in the
+//! same batch. A failed batch falls back to checking each of its signatures
+//! separately.
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+    future::Future,
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{channel::oneshot, FutureExt};
+use rand_core::OsRng;
+use tower::Service;
+use tower_batch::{Batch, BatchControl};
+
+use zebra_chain::redjubjub::{batch, Error};
+
+use crate::{block, primitives::VerifierDropped};
+
+/// A RedJubjub signature, its verification key, and the signed message,
+/// waiting for verification.
+pub type Item = batch::Item;
+
+/// A RedJubjub verification result, sent to the caller.
+type Tx = oneshot::Sender<Result<(), Error>>;
+
+/// Collects RedJubjub signatures, and verifies them in batches.
+///
+/// This service should be wrapped in a [`Batch`], using [`verifier`].
+#[derive(Debug, Default)]
+pub struct Verifier {
+    /// Items waiting for the next flush.
+    pending: Vec<(Item, Tx)>,
+}
+
+impl Verifier {
+    /// Verifies the pending items on a blocking thread, and sends each
+    /// result to its caller.
+    fn flush(&mut self) {
+        let pending = mem::take(&mut self.pending);
+        if pending.is_empty() {
+            return;
+        }
+
+        tokio::task::spawn_blocking(move || {
+            let mut batch = batch::Verifier::new();
+            for (item, _) in &pending {
+                batch.queue(item.clone());
+            }
+
+            if batch.verify(OsRng).is_ok() {
+                for (_, tx) in pending {
+                    let _ = tx.send(Ok(()));
+                }
+            } else {
+                for (item, tx) in pending {
+                    let _ = tx.send(item.verify_single());
+                }
+            }
+        });
+    }
+}
+
+impl Service<BatchControl<Item>> for Verifier {
+    type Response = ();
+    type Error = block::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<(), block::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: BatchControl<Item>) -> Self::Future {
+        match request {
+            BatchControl::Item(item) => {
+                let (tx, rx) = oneshot::channel();
+                self.pending.push((item, tx));
+
+                async move {
+                    match rx.await {
+                        Ok(result) => result.map_err(Into::into),
+                        Err(_) => Err(VerifierDropped("RedJubjub").into()),
+                    }
+                }
+                .boxed()
+            }
+            BatchControl::Flush => {
+                self.flush();
+                async { Ok(()) }.boxed()
+            }
+        }
+    }
+}
+
+/// A batched RedJubjub verifier.
+pub type BatchVerifier = Batch<Verifier, Item>;
+
+/// Returns a batch verifier for spend authorization and binding signatures.
+///
+/// Batches are flushed when they have `max_items` items, or after
+/// `max_latency`. Must be called from within a Tokio runtime.
+pub fn verifier(max_items: usize, max_latency: Duration) -> BatchVerifier {
+    Batch::new(Verifier::default(), max_items, max_latency)
+}
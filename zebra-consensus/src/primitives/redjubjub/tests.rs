@@ -0,0 +1,54 @@
+//! Tests for RedJubjub verification.
+
+use tower::ServiceExt;
+
+use zebra_chain::redjubjub::{
+    Binding, SigningKey, SpendAuth, VerificationKey, VerificationKeyBytes,
+};
+
+use crate::primitives::{DEFAULT_MAX_BATCH_LATENCY, DEFAULT_MAX_BATCH_SIZE};
+
+use super::*;
+
+/// Returns an item for a spend authorization signature on `msg`, which is
+/// verified against `verified_msg`.
+fn spend_auth_item(msg: &[u8], verified_msg: &[u8]) -> Item {
+    let sk = SigningKey::<SpendAuth>::new(OsRng);
+    let vk = VerificationKeyBytes::from(VerificationKey::from(&sk));
+    Item::from((vk, sk.sign(OsRng, msg), &verified_msg))
+}
+
+/// Returns an item for a binding signature on `msg`.
+fn binding_item(msg: &[u8]) -> Item {
+    let sk = SigningKey::<Binding>::new(OsRng);
+    let vk = VerificationKeyBytes::from(VerificationKey::from(&sk));
+    Item::from((vk, sk.sign(OsRng, msg), &msg))
+}
+
+#[tokio::test]
+async fn invalid_signatures_fail_in_batches() {
+    let mut verifier = verifier(DEFAULT_MAX_BATCH_SIZE, DEFAULT_MAX_BATCH_LATENCY);
+
+    let items = vec![
+        spend_auth_item(b"spend", b"spend"),
+        binding_item(b"binding"),
+        spend_auth_item(b"spend", b"other message"),
+        binding_item(b"binding"),
+    ];
+
+    let mut responses = Vec::new();
+    for item in items {
+        let verifier = verifier
+            .ready_and()
+            .await
+            .expect("the verifier is always ready");
+        responses.push(verifier.call(item));
+    }
+
+    let results: Vec<bool> = futures::future::join_all(responses)
+        .await
+        .into_iter()
+        .map(|result| result.is_ok())
+        .collect();
+    assert_eq!(results, vec![true, true, false, true]);
+}
@@ -0,0 +1,153 @@
+//! Async RedPallas batch verification for Orchard signatures.
+//!
+//! Spend authorization signatures and binding signatures are verified in the
+//! same batch. A failed batch falls back to checking each of its signatures
+//! separately.
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+    future::Future,
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{channel::oneshot, FutureExt};
+use pasta_curves::{
+    arithmetic::CurveExt,
+    group::{Group, GroupEncoding},
+    pallas,
+};
+use rand::rngs::OsRng;
+use reddsa::{batch, Error, VerificationKeyBytes};
+use tower::Service;
+use tower_batch::{Batch, BatchControl};
+
+use zebra_chain::orchard::ShieldedData;
+
+use crate::{block, primitives::VerifierDropped};
+
+pub use reddsa::orchard::{Binding, SpendAuth};
+
+/// A RedPallas signature, its verification key, and the signed message,
+/// waiting for verification.
+pub type Item = batch::Item<SpendAuth, Binding>;
+
+/// A RedPallas verification result, sent to the caller.
+type Tx = oneshot::Sender<Result<(), Error>>;
+
+/// Collects RedPallas signatures, and verifies them in batches.
+///
+/// This service should be wrapped in a [`Batch`], using [`verifier`].
+#[derive(Debug, Default)]
+pub struct Verifier {
+    /// Items waiting for the next flush.
+    pending: Vec<(Item, Tx)>,
+}
+
+impl Verifier {
+    /// Verifies the pending items on a blocking thread, and sends each
+    /// result to its caller.
+    fn flush(&mut self) {
+        let pending = mem::take(&mut self.pending);
+        if pending.is_empty() {
+            return;
+        }
+
+        tokio::task::spawn_blocking(move || {
+            let mut batch = batch::Verifier::new();
+            for (item, _) in &pending {
+                batch.queue(item.clone());
+            }
+
+            if batch.verify(OsRng).is_ok() {
+                for (_, tx) in pending {
+                    let _ = tx.send(Ok(()));
+                }
+            } else {
+                for (item, tx) in pending {
+                    let _ = tx.send(item.verify_single());
+                }
+            }
+        });
+    }
+}
+
+impl Service<BatchControl<Item>> for Verifier {
+    type Response = ();
+    type Error = block::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<(), block::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: BatchControl<Item>) -> Self::Future {
+        match request {
+            BatchControl::Item(item) => {
+                let (tx, rx) = oneshot::channel();
+                self.pending.push((item, tx));
+
+                async move {
+                    match rx.await {
+                        Ok(result) => result.map_err(Into::into),
+                        Err(_) => Err(VerifierDropped("RedPallas").into()),
+                    }
+                }
+                .boxed()
+            }
+            BatchControl::Flush => {
+                self.flush();
+                async { Ok(()) }.boxed()
+            }
+        }
+    }
+}
+
+/// A batched RedPallas verifier.
+pub type BatchVerifier = Batch<Verifier, Item>;
+
+/// Returns a batch verifier for Orchard spend authorization and binding
+/// signatures.
+///
+/// Batches are flushed when they have `max_items` items, or after
+/// `max_latency`. Must be called from within a Tokio runtime.
+pub fn verifier(max_items: usize, max_latency: Duration) -> BatchVerifier {
+    Batch::new(Verifier::default(), max_items, max_latency)
+}
+
+/// Returns the binding verification key for the actions in `shielded_data`.
+///
+/// The key is the sum of the action value commitments, minus a commitment to
+/// the bundle's `value_balance` with zero randomness. Returns `None` if a
+/// value commitment isn't a valid point.
+///
+/// https://zips.z.cash/protocol/protocol.pdf#orchardbalance
+pub fn binding_verification_key(
+    shielded_data: &ShieldedData,
+) -> Option<VerificationKeyBytes<Binding>> {
+    let mut bvk = pallas::Point::identity();
+    for action in shielded_data.actions() {
+        bvk += Option::<pallas::Point>::from(pallas::Point::from_bytes(&action.cv))?;
+    }
+
+    let value_balance = i64::from(shielded_data.value_balance);
+    let mut balance = pallas::Scalar::from(value_balance.abs() as u64);
+    if value_balance < 0 {
+        balance = -balance;
+    }
+    bvk -= value_commitment_base() * balance;
+
+    Some(bvk.to_bytes().into())
+}
+
+/// The value base point for Orchard value commitments, `ValueCommit^{Orchard}`'s
+/// `V` generator.
+///
+/// https://zips.z.cash/protocol/protocol.pdf#concretehomomorphiccommit
+fn value_commitment_base() -> pallas::Point {
+    pallas::Point::hash_to_curve("z.cash:Orchard-cv")(b"v")
+}
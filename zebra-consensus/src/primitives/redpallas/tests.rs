@@ -0,0 +1,100 @@
+//! Tests for RedPallas verification.
+
+use std::convert::TryInto;
+
+use pasta_curves::group::prime::PrimeCurveAffine;
+use reddsa::{SigningKey, VerificationKey};
+use tower::ServiceExt;
+
+use zebra_chain::{
+    amount::Amount,
+    orchard::{tree, Action, EncryptedNote, Flags, Nullifier, RedPallasSignature, WrappedNoteKey},
+    proofs::Halo2Proof,
+};
+
+use crate::primitives::{DEFAULT_MAX_BATCH_LATENCY, DEFAULT_MAX_BATCH_SIZE};
+
+use super::*;
+
+/// Returns an item for a spend authorization signature on `msg`, which is
+/// verified against `verified_msg`.
+fn spend_auth_item(msg: &[u8], verified_msg: &[u8]) -> Item {
+    let sk = SigningKey::<SpendAuth>::new(OsRng);
+    let vk = VerificationKeyBytes::from(VerificationKey::from(&sk));
+    Item::from_spendauth(vk, sk.sign(OsRng, msg), &verified_msg)
+}
+
+/// Returns an item for a binding signature on `msg`.
+fn binding_item(msg: &[u8]) -> Item {
+    let sk = SigningKey::<Binding>::new(OsRng);
+    let vk = VerificationKeyBytes::from(VerificationKey::from(&sk));
+    Item::from_binding(vk, sk.sign(OsRng, msg), &msg)
+}
+
+#[tokio::test]
+async fn invalid_signatures_fail_in_batches() {
+    let mut verifier = verifier(DEFAULT_MAX_BATCH_SIZE, DEFAULT_MAX_BATCH_LATENCY);
+
+    let items = vec![
+        spend_auth_item(b"spend", b"spend"),
+        binding_item(b"binding"),
+        spend_auth_item(b"spend", b"other message"),
+        binding_item(b"binding"),
+    ];
+
+    let mut responses = Vec::new();
+    for item in items {
+        let verifier = verifier
+            .ready_and()
+            .await
+            .expect("the verifier is always ready");
+        responses.push(verifier.call(item));
+    }
+
+    let results: Vec<bool> = futures::future::join_all(responses)
+        .await
+        .into_iter()
+        .map(|result| result.is_ok())
+        .collect();
+    assert_eq!(results, vec![true, true, false, true]);
+}
+
+#[test]
+fn binding_verification_key_balances_value_commitments() {
+    // A commitment to 5 zatoshis with zero randomness.
+    let cv = (value_commitment_base() * pallas::Scalar::from(5u64)).to_bytes();
+    let action = Action {
+        cv,
+        nullifier: Nullifier([0; 32]),
+        rk: pallas::Affine::generator().to_bytes(),
+        cm_x: [0; 32],
+        ephemeral_key: [0; 32],
+        enc_ciphertext: EncryptedNote([0; 580]),
+        out_ciphertext: WrappedNoteKey([0; 80]),
+        spend_auth_sig: RedPallasSignature([0; 64]),
+    };
+    let mut shielded_data = ShieldedData {
+        flags: Flags {
+            enable_spends: true,
+            enable_outputs: true,
+        },
+        value_balance: Amount::zero(),
+        shared_anchor: tree::Root([0; 32]),
+        proof: Halo2Proof(vec![].into()),
+        first: action,
+        rest: Vec::new(),
+        binding_sig: RedPallasSignature([0; 64]),
+    };
+
+    let identity = pallas::Point::identity().to_bytes();
+    let mut bvk = |value: i64| {
+        shielded_data.value_balance = value.try_into().unwrap();
+        binding_verification_key(&shielded_data).map(<[u8; 32]>::from)
+    };
+    assert_eq!(bvk(5), Some(identity));
+    assert_ne!(bvk(4), Some(identity));
+    assert_ne!(bvk(-5), Some(identity));
+
+    shielded_data.first.cv = [0xff; 32];
+    assert!(binding_verification_key(&shielded_data).is_none());
+}
@@ -0,0 +1,747 @@
+//! Transaction verification.
+//!
+//! The [`TransactionVerifier`] checks the consensus rules for each
+//! transaction, either as part of a block, or on its own for the mempool.
+//!
+//! Transparent inputs are checked against the scripts of the outputs they
+//! spend, which are awaited from the state if they aren't in the request.
+//!
+//! Anchors are checked against the note commitment tree roots in the chain
+//! the transaction is verified on: the chain before its block, or the best
+//! chain for the mempool. JoinSplits can also use the Sprout root after an
+//! earlier JoinSplit in the same transaction.
+
+pub mod check;
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::{
+    stream::{FuturesUnordered, StreamExt},
+    FutureExt, TryFutureExt,
+};
+use thiserror::Error;
+use tower::{Service, ServiceExt};
+
+use zebra_chain::{
+    amount, block, ed25519_zebra,
+    network_upgrade::{ConsensusBranchId, NetworkUpgrade},
+    orchard, sapling, sprout,
+    transaction::{self, HashType, OutPoint, Transaction, TransparentInput, TransparentOutput},
+    Network,
+};
+
+use crate::{
+    block::Error,
+    primitives::{ed25519, groth16, halo2, redjubjub, redpallas},
+    Config, VerificationError,
+};
+
+/// The protocol specification section for Sapling spend and output proofs.
+const SAPLING_PROOF_RULES: &str = "protocol specification §4.15";
+
+/// The protocol specification section for Orchard action proofs.
+const ORCHARD_PROOF_RULES: &str = "protocol specification §4.17";
+
+/// The protocol specification section for JoinSplit signatures.
+const JOINSPLIT_SIGNATURE_RULES: &str = "protocol specification §4.11";
+
+/// The protocol specification section for Sapling and Orchard binding
+/// signatures.
+const BINDING_SIGNATURE_RULES: &str = "protocol specification §4.13";
+
+/// The protocol specification section for Sapling and Orchard spend
+/// authorization signatures.
+const SPEND_AUTH_SIGNATURE_RULES: &str = "protocol specification §4.14";
+
+/// A transaction verification request.
+#[derive(Clone, Debug)]
+pub enum Request {
+    /// Verify a transaction in a block at `height`.
+    Block {
+        /// The transaction.
+        transaction: Arc<Transaction>,
+        /// The height of the block containing the transaction.
+        height: block::Height,
+        /// The hash of the block before the block containing the
+        /// transaction, which ends the chain that anchors are checked on.
+        previous_block_hash: block::Hash,
+        /// Transparent outputs that the transaction can spend without
+        /// waiting for the state, like the outputs created by earlier
+        /// transactions in the block.
+        known_utxos: Arc<HashMap<OutPoint, TransparentOutput>>,
+    },
+    /// Verify a transaction for the mempool, which could be mined in the
+    /// next block at `height`.
+    Mempool {
+        /// The transaction.
+        transaction: Arc<Transaction>,
+        /// The height of the next block.
+        height: block::Height,
+        /// Transparent outputs that the transaction can spend without
+        /// waiting for the state, like the outputs created by other mempool
+        /// transactions.
+        known_utxos: Arc<HashMap<OutPoint, TransparentOutput>>,
+    },
+}
+
+impl Request {
+    /// Returns the transaction in this request.
+    pub fn transaction(&self) -> Arc<Transaction> {
+        match self {
+            Request::Block { transaction, .. } | Request::Mempool { transaction, .. } => {
+                transaction.clone()
+            }
+        }
+    }
+
+    /// Returns the height that the transaction is verified at.
+    pub fn height(&self) -> block::Height {
+        match self {
+            Request::Block { height, .. } | Request::Mempool { height, .. } => *height,
+        }
+    }
+
+    /// Returns the outputs that the transaction can spend without waiting
+    /// for the state.
+    pub fn known_utxos(&self) -> Arc<HashMap<OutPoint, TransparentOutput>> {
+        match self {
+            Request::Block { known_utxos, .. } | Request::Mempool { known_utxos, .. } => {
+                known_utxos.clone()
+            }
+        }
+    }
+
+    /// Returns the last block of the chain that the transaction's anchors
+    /// must be in, or `None` for the best chain.
+    pub fn anchor_tip(&self) -> Option<block::Hash> {
+        match self {
+            Request::Block {
+                previous_block_hash,
+                ..
+            } => Some(*previous_block_hash),
+            Request::Mempool { .. } => None,
+        }
+    }
+
+    /// Returns true if this is a mempool request.
+    pub fn is_mempool(&self) -> bool {
+        matches!(self, Request::Mempool { .. })
+    }
+}
+
+/// A consensus rule violation in a transaction.
+#[derive(Error, Debug)]
+pub enum TransactionError {
+    /// The transaction version isn't valid in the current network upgrade.
+    #[error("transaction version is not valid in network upgrade {0:?}")]
+    WrongVersion(NetworkUpgrade),
+    /// The transaction has no transparent inputs, JoinSplits, Sapling spends,
+    /// or Orchard actions.
+    #[error("transaction has no inputs")]
+    NoInputs,
+    /// The transaction has no transparent outputs, JoinSplits, Sapling
+    /// outputs, or Orchard actions.
+    #[error("transaction has no outputs")]
+    NoOutputs,
+    /// The transaction's values don't fit in the valid range of amounts.
+    #[error("transaction value is out of range")]
+    ValueOutOfRange(#[from] amount::Error),
+    /// The expiry height is above the maximum expiry height.
+    #[error("expiry height {0:?} is greater than the maximum expiry height")]
+    MaximumExpiryHeight(block::Height),
+    /// The transaction has expired at this height.
+    #[error("transaction expired at {expiry_height:?}, before height {height:?}")]
+    Expired {
+        /// The expiry height of the transaction.
+        expiry_height: block::Height,
+        /// The height the transaction was verified at.
+        height: block::Height,
+    },
+    /// Coinbase transactions can only be mined by the block producer.
+    #[error("coinbase transactions are not accepted into the mempool")]
+    CoinbaseInMempool,
+    /// A Sapling or Orchard value commitment isn't a valid point, so the
+    /// binding signature can't be checked.
+    #[error("invalid Sapling or Orchard value commitment")]
+    InvalidValueCommitment,
+    /// A transaction that isn't a coinbase transaction has a coinbase input.
+    #[error("coinbase input found in a non-coinbase transaction")]
+    CoinbaseInputFound,
+    /// A coinbase transaction has a JoinSplit.
+    #[error("coinbase transaction has a JoinSplit")]
+    CoinbaseHasJoinSplit,
+    /// A coinbase transaction spends a Sapling note.
+    #[error("coinbase transaction has a Sapling spend")]
+    CoinbaseHasSpend,
+    /// A coinbase transaction enables Orchard spends.
+    #[error("coinbase transaction enables Orchard spends")]
+    CoinbaseHasEnableSpendsOrchard,
+    /// A coinbase transaction has a Sapling output before Heartwood.
+    #[error("coinbase transaction has a Sapling output before Heartwood")]
+    CoinbaseHasOutputPreHeartwood,
+    /// From NU5, a coinbase transaction's expiry height must be its block
+    /// height.
+    #[error("coinbase expiry height {expiry_height:?} is not the block height {height:?}")]
+    CoinbaseExpiryHeight {
+        /// The expiry height of the coinbase transaction.
+        expiry_height: Option<block::Height>,
+        /// The height of the block.
+        height: block::Height,
+    },
+    /// The transaction spends the same transparent output more than once.
+    #[error("transaction spends the same output more than once")]
+    DuplicateInput,
+    /// The transaction reveals the same nullifier more than once.
+    #[error("transaction reveals the same nullifier more than once")]
+    DuplicateNullifier,
+    /// A version 5 transaction has the wrong consensus branch ID.
+    #[error("transaction has consensus branch ID {actual:#x}, expected {expected:x?}")]
+    WrongConsensusBranchId {
+        /// The branch ID of the network upgrade, if it has one.
+        expected: Option<u32>,
+        /// The branch ID in the transaction.
+        actual: u32,
+    },
+    /// A JoinSplit's anchor isn't the note commitment tree root after a
+    /// block in the chain, or after an earlier JoinSplit in the transaction.
+    #[error("unknown Sprout anchor {0:?}")]
+    UnknownSproutAnchor(sprout::tree::Root),
+    /// A Sapling spend's anchor isn't the note commitment tree root after a
+    /// block in the chain.
+    #[error("unknown Sapling anchor {0:?}")]
+    UnknownSaplingAnchor(sapling::tree::Root),
+    /// The Orchard anchor isn't the note commitment tree root after a block
+    /// in the chain.
+    #[error("unknown Orchard anchor {0:?}")]
+    UnknownOrchardAnchor(orchard::tree::Root),
+    /// The JoinSplits fill up the Sprout note commitment tree.
+    #[error("JoinSplit note commitments overflow the Sprout tree: {0}")]
+    SproutTree(#[from] sprout::tree::NoteCommitmentTreeError),
+    /// The transaction's signature hash can't be computed, for example
+    /// because it has an invalid ZIP-244 hash type.
+    #[error("transaction has no signature hash: {0}")]
+    SigHash(#[from] transaction::SigHashError),
+}
+
+/// Checks transactions in blocks and the mempool.
+///
+/// Responds with the hash of each valid transaction.
+#[derive(Clone, Debug)]
+pub struct TransactionVerifier<S> {
+    /// The network that transactions are verified for.
+    network: Network,
+    /// The state service, which is used to look up anchors.
+    state_service: S,
+    /// Verifies Sapling spend proofs.
+    spend_verifier: groth16::BatchVerifier,
+    /// Verifies Sapling output proofs.
+    output_verifier: groth16::BatchVerifier,
+    /// Verifies Orchard action proofs.
+    halo2_verifier: halo2::BatchVerifier,
+    /// Verifies Sapling spend authorization and binding signatures.
+    redjubjub_verifier: redjubjub::BatchVerifier,
+    /// Verifies Orchard spend authorization and binding signatures.
+    redpallas_verifier: redpallas::BatchVerifier,
+    /// Verifies JoinSplit signatures.
+    ed25519_verifier: ed25519::BatchVerifier,
+}
+
+impl<S> TransactionVerifier<S> {
+    /// Returns a verifier for transactions on `network`, with the default
+    /// batch limits.
+    ///
+    /// Must be called from within a Tokio runtime, because it spawns the
+    /// batch verification tasks.
+    pub fn new(network: Network, state_service: S) -> Self {
+        TransactionVerifier::from_config(&Config::default(), network, state_service)
+    }
+
+    /// Returns a verifier for transactions on `network`, with the batch
+    /// limits in `config`.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn from_config(config: &Config, network: Network, state_service: S) -> Self {
+        let max_items = config.max_batch_size;
+        let max_latency = config.max_batch_latency;
+        TransactionVerifier {
+            network,
+            state_service,
+            spend_verifier: groth16::spend_verifier(max_items, max_latency),
+            output_verifier: groth16::output_verifier(max_items, max_latency),
+            halo2_verifier: halo2::verifier(max_items, max_latency),
+            redjubjub_verifier: redjubjub::verifier(max_items, max_latency),
+            redpallas_verifier: redpallas::verifier(max_items, max_latency),
+            ed25519_verifier: ed25519::verifier(max_items, max_latency),
+        }
+    }
+
+    /// Flushes every proof and signature batch, so the queued items are
+    /// verified without waiting for their batches to fill up.
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        futures::try_join!(
+            self.spend_verifier.flush(),
+            self.output_verifier.flush(),
+            self.halo2_verifier.flush(),
+            self.redjubjub_verifier.flush(),
+            self.redpallas_verifier.flush(),
+            self.ed25519_verifier.flush(),
+        )?;
+        Ok(())
+    }
+}
+
+impl<S> Service<Request> for TransactionVerifier<S>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = Error>
+        + Send
+        + Clone
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = transaction::Hash;
+    type Error = Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let network = self.network;
+        let mut state_service = self.state_service.clone();
+        let spend_verifier = self.spend_verifier.clone();
+        let output_verifier = self.output_verifier.clone();
+        let halo2_verifier = self.halo2_verifier.clone();
+        let redjubjub_verifier = self.redjubjub_verifier.clone();
+        let redpallas_verifier = self.redpallas_verifier.clone();
+        let ed25519_verifier = self.ed25519_verifier.clone();
+
+        async move {
+            let transaction = request.transaction();
+            let hash = transaction::Hash::from(transaction.as_ref());
+            check_transaction(network, &request)
+                .map_err(|error| VerificationError::transaction(hash, error))?;
+
+            let tip = request.anchor_tip();
+            let joinsplits: Vec<_> = transaction.sprout_anchors_and_commitments().collect();
+            sprout_anchors_are_valid(&mut state_service, hash, &joinsplits, tip).await?;
+            let anchors: HashSet<_> = transaction
+                .sapling_spends()
+                .map(|spend| spend.anchor)
+                .collect();
+            for anchor in anchors {
+                sapling_anchor_is_valid(&mut state_service, hash, anchor, tip).await?;
+            }
+            if let Some(shielded_data) = transaction.orchard_shielded_data() {
+                let anchor = shielded_data.shared_anchor;
+                orchard_anchor_is_valid(&mut state_service, hash, anchor, tip).await?;
+            }
+
+            // Coinbase inputs don't spend any outputs, so coinbase
+            // transactions have no spent outputs or scripts to verify.
+            let previous_outputs =
+                spent_outputs(&mut state_service, &request.known_utxos(), &transaction).await?;
+
+            // Queue the proofs and signatures in each transaction's batch, so
+            // they are verified alongside those from other transactions.
+            let invalid_proof =
+                move |error: Error| VerificationError::proof(hash, SAPLING_PROOF_RULES, error);
+            let mut async_checks = AsyncChecks::default();
+            if !previous_outputs.is_empty() {
+                let transaction = transaction.clone();
+                let previous_outputs = previous_outputs.clone();
+                let branch_id = ConsensusBranchId(branch_id(network, &request));
+                // Script verification is CPU-bound, so it runs on a blocking
+                // thread instead of the async executor.
+                async_checks.push(
+                    tokio::task::spawn_blocking(move || {
+                        zebra_script::inputs_are_valid(&transaction, &previous_outputs, branch_id)
+                    })
+                    .map(move |result| -> Result<(), Error> {
+                        result?.map_err(|error| VerificationError::script(hash, error.into()))?;
+                        Ok(())
+                    }),
+                );
+            }
+            for spend in transaction.sapling_spends() {
+                let item = groth16::Item::try_from(spend).map_err(|e| invalid_proof(e.into()))?;
+                async_checks.push(spend_verifier.clone().oneshot(item).map_err(invalid_proof));
+            }
+            for output in transaction.sapling_outputs() {
+                let item = groth16::Item::try_from(output).map_err(|e| invalid_proof(e.into()))?;
+                async_checks.push(output_verifier.clone().oneshot(item).map_err(invalid_proof));
+            }
+
+            if let Some(shielded_data) = transaction.orchard_shielded_data() {
+                let invalid_proof =
+                    move |error: Error| VerificationError::proof(hash, ORCHARD_PROOF_RULES, error);
+                let item =
+                    halo2::Item::try_from(shielded_data).map_err(|e| invalid_proof(e.into()))?;
+                async_checks.push(halo2_verifier.oneshot(item).map_err(invalid_proof));
+            }
+
+            if let Some((pub_key, sig)) = joinsplit_signature(&transaction) {
+                let sighash = transaction
+                    .joinsplit_sighash(branch_id(network, &request))
+                    .expect("transactions with JoinSplits have a JoinSplit sighash");
+                let item = ed25519::Item::from((pub_key, sig, &sighash));
+                async_checks.push(ed25519_verifier.oneshot(item).map_err(move |error| {
+                    VerificationError::signature(hash, JOINSPLIT_SIGNATURE_RULES, error)
+                }));
+            }
+
+            // Sapling and Orchard signatures all sign the same hash.
+            let shielded_sighash = if transaction.sapling_shielded_data().is_some()
+                || transaction.orchard_shielded_data().is_some()
+            {
+                // ZIP-244 shielded signatures commit to the outputs spent by
+                // the transparent inputs.
+                let previous_outputs: &[TransparentOutput] = match transaction.as_ref() {
+                    Transaction::V5 { .. } => &previous_outputs,
+                    _ => &[],
+                };
+                let sighash = transaction
+                    .sighash(
+                        branch_id(network, &request),
+                        HashType::ALL,
+                        previous_outputs,
+                        None,
+                    )
+                    .map_err(|error| VerificationError::transaction(hash, error.into()))?;
+                Some(sighash)
+            } else {
+                None
+            };
+
+            if let (Some(shielded_data), Some(sighash)) =
+                (transaction.sapling_shielded_data(), &shielded_sighash)
+            {
+                for spend in shielded_data.spends() {
+                    let item = redjubjub::Item::from((spend.rk, spend.spend_auth_sig, sighash));
+                    async_checks.push(redjubjub_verifier.clone().oneshot(item).map_err(
+                        move |error| {
+                            VerificationError::signature(hash, SPEND_AUTH_SIGNATURE_RULES, error)
+                        },
+                    ));
+                }
+
+                let bvk = shielded_data
+                    .binding_verification_key(transaction.sapling_value_balance())
+                    .ok_or_else(|| {
+                        VerificationError::transaction(
+                            hash,
+                            TransactionError::InvalidValueCommitment,
+                        )
+                    })?;
+                let item = redjubjub::Item::from((bvk, shielded_data.binding_sig, sighash));
+                async_checks.push(
+                    redjubjub_verifier
+                        .clone()
+                        .oneshot(item)
+                        .map_err(move |error| {
+                            VerificationError::signature(hash, BINDING_SIGNATURE_RULES, error)
+                        }),
+                );
+            }
+
+            if let (Some(shielded_data), Some(sighash)) =
+                (transaction.orchard_shielded_data(), &shielded_sighash)
+            {
+                for action in shielded_data.actions() {
+                    let item = redpallas::Item::from_spendauth(
+                        action.rk.into(),
+                        action.spend_auth_sig.0.into(),
+                        sighash,
+                    );
+                    async_checks.push(redpallas_verifier.clone().oneshot(item).map_err(
+                        move |error| {
+                            VerificationError::signature(hash, SPEND_AUTH_SIGNATURE_RULES, error)
+                        },
+                    ));
+                }
+
+                let bvk = redpallas::binding_verification_key(shielded_data).ok_or_else(|| {
+                    VerificationError::transaction(hash, TransactionError::InvalidValueCommitment)
+                })?;
+                let item =
+                    redpallas::Item::from_binding(bvk, shielded_data.binding_sig.0.into(), sighash);
+                async_checks.push(redpallas_verifier.oneshot(item).map_err(move |error| {
+                    VerificationError::signature(hash, BINDING_SIGNATURE_RULES, error)
+                }));
+            }
+
+            async_checks.check().await?;
+
+            let hash: Result<transaction::Hash, Error> = Ok(hash);
+            hash
+        }
+        .boxed()
+    }
+}
+
+/// Check the consensus rules that only depend on the transaction, and the
+/// height it is verified at.
+fn check_transaction(network: Network, request: &Request) -> Result<(), TransactionError> {
+    let transaction = request.transaction();
+    let height = request.height();
+
+    if request.is_mempool() && transaction.is_coinbase() {
+        return Err(TransactionError::CoinbaseInMempool);
+    }
+
+    check::network_upgrade_is_valid(&transaction, network, height)?;
+    check::consensus_branch_id_is_valid(&transaction, network, height)?;
+    check::has_inputs_and_outputs(&transaction)?;
+    check::coinbase_is_valid(&transaction, network, height)?;
+    check::inputs_are_unique(&transaction)?;
+    check::values_are_in_range(&transaction)?;
+    check::expiry_height_is_valid(&transaction, height)?;
+
+    Ok(())
+}
+
+/// Returns an error if the anchor of one of the `joinsplits` in the
+/// transaction with `hash` isn't a Sprout note commitment tree root in the
+/// chain ending at `tip` in `state_service`, or the root after an earlier
+/// JoinSplit in the transaction.
+///
+/// Each JoinSplit is its anchor, and the note commitments it creates.
+async fn sprout_anchors_are_valid<S>(
+    state_service: &mut S,
+    hash: transaction::Hash,
+    joinsplits: &[([u8; 32], [[u8; 32]; 2])],
+    tip: Option<block::Hash>,
+) -> Result<(), Error>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = Error>,
+{
+    let invalid = move |error: TransactionError| VerificationError::transaction(hash, error);
+
+    // The trees after each earlier JoinSplit, by their root.
+    let mut interstitial_trees: HashMap<sprout::tree::Root, sprout::tree::NoteCommitmentTree> =
+        HashMap::new();
+    for (anchor, commitments) in joinsplits {
+        let anchor = sprout::tree::Root(*anchor);
+        let mut tree = match interstitial_trees.get(&anchor) {
+            Some(tree) => tree.clone(),
+            None => {
+                let response = state_service
+                    .ready_and()
+                    .await?
+                    .call(zebra_state::Request::GetSproutTree { anchor, tip })
+                    .await?;
+                match response {
+                    zebra_state::Response::SproutTree { tree: Some(tree) } => tree,
+                    zebra_state::Response::SproutTree { tree: None } => {
+                        return Err(invalid(TransactionError::UnknownSproutAnchor(anchor)).into())
+                    }
+                    response => {
+                        return Err(format!("unexpected state response: {:?}", response).into())
+                    }
+                }
+            }
+        };
+        for cm in commitments.iter() {
+            tree.append(*cm).map_err(|error| invalid(error.into()))?;
+        }
+        let _ = interstitial_trees.insert(tree.root(), tree);
+    }
+    Ok(())
+}
+
+/// Returns an error if `anchor`, from the transaction with `hash`, isn't a
+/// Sapling note commitment tree root in the chain ending at `tip` in
+/// `state_service`.
+async fn sapling_anchor_is_valid<S>(
+    state_service: &mut S,
+    hash: transaction::Hash,
+    anchor: sapling::tree::Root,
+    tip: Option<block::Hash>,
+) -> Result<(), Error>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = Error>,
+{
+    anchor_is_valid(
+        state_service,
+        hash,
+        zebra_state::Request::ContainsSaplingAnchor { anchor, tip },
+        TransactionError::UnknownSaplingAnchor(anchor),
+    )
+    .await
+}
+
+/// Returns an error if `anchor`, from the transaction with `hash`, isn't an
+/// Orchard note commitment tree root in the chain ending at `tip` in
+/// `state_service`.
+async fn orchard_anchor_is_valid<S>(
+    state_service: &mut S,
+    hash: transaction::Hash,
+    anchor: orchard::tree::Root,
+    tip: Option<block::Hash>,
+) -> Result<(), Error>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = Error>,
+{
+    anchor_is_valid(
+        state_service,
+        hash,
+        zebra_state::Request::ContainsOrchardAnchor { anchor, tip },
+        TransactionError::UnknownOrchardAnchor(anchor),
+    )
+    .await
+}
+
+/// Sends the anchor `request` to `state_service`, and returns `unknown` for
+/// the transaction with `hash` if the state doesn't contain the anchor.
+async fn anchor_is_valid<S>(
+    state_service: &mut S,
+    hash: transaction::Hash,
+    request: zebra_state::Request,
+    unknown: TransactionError,
+) -> Result<(), Error>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = Error>,
+{
+    let response = state_service.ready_and().await?.call(request).await?;
+    match response {
+        zebra_state::Response::ContainsAnchor { contains: true } => Ok(()),
+        zebra_state::Response::ContainsAnchor { contains: false } => {
+            Err(VerificationError::transaction(hash, unknown).into())
+        }
+        response => Err(format!("unexpected state response: {:?}", response).into()),
+    }
+}
+
+/// Returns the outputs spent by the transparent inputs of `transaction`, by
+/// outpoint.
+///
+/// Outputs in `known_utxos` are used directly, and the others are awaited
+/// until they are committed to `state_service`.
+pub(crate) async fn spent_utxos<S>(
+    state_service: &mut S,
+    known_utxos: &HashMap<OutPoint, TransparentOutput>,
+    transaction: &Transaction,
+) -> Result<HashMap<OutPoint, TransparentOutput>, Error>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = Error>,
+{
+    let mut utxos = HashMap::new();
+    for input in transaction.inputs() {
+        let outpoint = match input {
+            TransparentInput::PrevOut { outpoint, .. } => *outpoint,
+            TransparentInput::Coinbase { .. } => continue,
+        };
+        if let Some(output) = known_utxos.get(&outpoint) {
+            utxos.insert(outpoint, output.clone());
+            continue;
+        }
+        let response = state_service
+            .ready_and()
+            .await?
+            .call(zebra_state::Request::AwaitUtxo { outpoint })
+            .await?;
+        match response {
+            zebra_state::Response::Utxo {
+                output: Some(output),
+            } => {
+                utxos.insert(outpoint, output);
+            }
+            response => return Err(format!("unexpected state response: {:?}", response).into()),
+        }
+    }
+    Ok(utxos)
+}
+
+/// Returns the outputs spent by the transparent inputs of `transaction`, in
+/// order, like [`spent_utxos`].
+///
+/// Coinbase inputs don't spend an output, so they are skipped.
+async fn spent_outputs<S>(
+    state_service: &mut S,
+    known_utxos: &HashMap<OutPoint, TransparentOutput>,
+    transaction: &Transaction,
+) -> Result<Vec<TransparentOutput>, Error>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = Error>,
+{
+    let mut utxos = spent_utxos(state_service, known_utxos, transaction).await?;
+    let outputs = transaction
+        .inputs()
+        .filter_map(|input| match input {
+            TransparentInput::PrevOut { outpoint, .. } => utxos.remove(outpoint),
+            TransparentInput::Coinbase { .. } => None,
+        })
+        .collect();
+    Ok(outputs)
+}
+
+/// Returns the consensus branch ID at the request's height.
+///
+/// Transactions before Overwinter don't commit to a branch ID, so it is zero.
+fn branch_id(network: Network, request: &Request) -> u32 {
+    NetworkUpgrade::current(network, request.height())
+        .branch_id()
+        .map(u32::from)
+        .unwrap_or(0)
+}
+
+/// Returns the JoinSplit verification key and signature in `transaction`, if
+/// it has any JoinSplits.
+fn joinsplit_signature(
+    transaction: &Transaction,
+) -> Option<(
+    ed25519_zebra::VerificationKeyBytes,
+    ed25519_zebra::Signature,
+)> {
+    match transaction {
+        Transaction::V2 {
+            joinsplit_data: Some(jsd),
+            ..
+        }
+        | Transaction::V3 {
+            joinsplit_data: Some(jsd),
+            ..
+        } => Some((jsd.pub_key, jsd.sig)),
+        Transaction::V4 {
+            joinsplit_data: Some(jsd),
+            ..
+        } => Some((jsd.pub_key, jsd.sig)),
+        _ => None,
+    }
+}
+
+/// A set of verification futures, which run concurrently.
+#[derive(Default)]
+pub(crate) struct AsyncChecks(
+    FuturesUnordered<Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'static>>>,
+);
+
+impl AsyncChecks {
+    /// Adds `check` to the set.
+    pub(crate) fn push<E>(&mut self, check: impl Future<Output = Result<(), E>> + Send + 'static)
+    where
+        E: Into<Error>,
+    {
+        self.0.push(check.map_err(Into::into).boxed());
+    }
+
+    /// Waits for every check to finish, and returns the first error.
+    ///
+    /// The remaining checks are dropped after an error.
+    pub(crate) async fn check(mut self) -> Result<(), Error> {
+        while let Some(result) = self.0.next().await {
+            result?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+//! Consensus checks for individual transactions.
+
+use std::{collections::HashSet, hash::Hash};
+
+use zebra_chain::{
+    amount::{self, Amount, NonNegative},
+    block,
+    network_upgrade::NetworkUpgrade,
+    transaction::{Transaction, TransparentInput, MAX_EXPIRY_HEIGHT},
+    Network,
+};
+
+use super::TransactionError;
+
+/// Returns `Ok(())` if `transaction`'s version is valid in the network
+/// upgrade at `height` on `network`.
+///
+/// Overwinter requires version 3 transactions, and Sapling requires version
+/// 4 or later. Version 5 transactions are only valid from NU5.
+pub fn network_upgrade_is_valid(
+    transaction: &Transaction,
+    network: Network,
+    height: block::Height,
+) -> Result<(), TransactionError> {
+    use NetworkUpgrade::*;
+
+    let upgrade = NetworkUpgrade::current(network, height);
+    let is_valid = match transaction {
+        Transaction::V1 { .. } | Transaction::V2 { .. } => {
+            matches!(upgrade, Genesis | BeforeOverwinter)
+        }
+        Transaction::V3 { .. } => upgrade == Overwinter,
+        Transaction::V4 { .. } => !matches!(upgrade, Genesis | BeforeOverwinter | Overwinter),
+        Transaction::V5 { .. } => upgrade == Nu5,
+    };
+
+    if !is_valid {
+        return Err(TransactionError::WrongVersion(upgrade));
+    }
+    Ok(())
+}
+
+/// Returns `Ok(())` if `transaction` has at least one input and one output.
+///
+/// Shielded transfers count as inputs and outputs: JoinSplits and Orchard
+/// actions are both, Sapling spends are inputs, and Sapling outputs are
+/// outputs.
+pub fn has_inputs_and_outputs(transaction: &Transaction) -> Result<(), TransactionError> {
+    let has_joinsplits = transaction.sprout_nullifiers().next().is_some();
+    let has_actions = transaction.orchard_nullifiers().next().is_some();
+
+    let has_inputs = transaction.inputs().next().is_some()
+        || has_joinsplits
+        || transaction.sapling_nullifiers().next().is_some()
+        || has_actions;
+    let has_outputs = transaction.outputs().next().is_some()
+        || has_joinsplits
+        || transaction.sapling_outputs().next().is_some()
+        || has_actions;
+
+    if !has_inputs {
+        return Err(TransactionError::NoInputs);
+    }
+    if !has_outputs {
+        return Err(TransactionError::NoOutputs);
+    }
+    Ok(())
+}
+
+/// Returns `Ok(())` if the total transparent output value, and the JoinSplit
+/// values, are in the valid range of amounts.
+///
+/// Each individual amount is range-checked when it is deserialized.
+pub fn values_are_in_range(transaction: &Transaction) -> Result<(), TransactionError> {
+    let _: Amount<NonNegative> = transaction
+        .outputs()
+        .map(|output| output.value)
+        .sum::<amount::Result<_>>()?;
+    transaction.sprout_value_balance()?;
+
+    Ok(())
+}
+
+/// Returns `Ok(())` if `transaction`'s expiry height is at most
+/// [`MAX_EXPIRY_HEIGHT`], and the transaction hasn't expired at `height`.
+pub fn expiry_height_is_valid(
+    transaction: &Transaction,
+    height: block::Height,
+) -> Result<(), TransactionError> {
+    if let Some(expiry_height) = transaction.expiry_height() {
+        if expiry_height > MAX_EXPIRY_HEIGHT {
+            return Err(TransactionError::MaximumExpiryHeight(expiry_height));
+        }
+        if transaction.is_expired_at(height) {
+            return Err(TransactionError::Expired {
+                expiry_height,
+                height,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Returns `Ok(())` if `transaction` follows the coinbase rules for a block
+/// at `height` on `network`.
+///
+/// Coinbase transactions create new value, so they can't also spend
+/// shielded value: they can't have JoinSplits or Sapling spends, and their
+/// Orchard spends must be disabled. Before Heartwood, they also can't have
+/// Sapling outputs. From NU5, their expiry height must be the block height.
+///
+/// Other transactions can't have coinbase inputs.
+pub fn coinbase_is_valid(
+    transaction: &Transaction,
+    network: Network,
+    height: block::Height,
+) -> Result<(), TransactionError> {
+    if !transaction.is_coinbase() {
+        if transaction.contains_coinbase_input() {
+            return Err(TransactionError::CoinbaseInputFound);
+        }
+        return Ok(());
+    }
+
+    if transaction.sprout_nullifiers().next().is_some() {
+        return Err(TransactionError::CoinbaseHasJoinSplit);
+    }
+    if transaction.sapling_spends().next().is_some() {
+        return Err(TransactionError::CoinbaseHasSpend);
+    }
+    if let Transaction::V5 {
+        orchard_shielded_data: Some(shielded_data),
+        ..
+    } = transaction
+    {
+        if shielded_data.flags.enable_spends {
+            return Err(TransactionError::CoinbaseHasEnableSpendsOrchard);
+        }
+    }
+
+    let heartwood = NetworkUpgrade::Heartwood.activation_height(network);
+    let is_pre_heartwood = heartwood.map(|h| height < h).unwrap_or(true);
+    if is_pre_heartwood && transaction.sapling_outputs().next().is_some() {
+        return Err(TransactionError::CoinbaseHasOutputPreHeartwood);
+    }
+
+    let nu5 = NetworkUpgrade::Nu5.activation_height(network);
+    if nu5.map(|h| height >= h).unwrap_or(false) && transaction.expiry_height() != Some(height) {
+        return Err(TransactionError::CoinbaseExpiryHeight {
+            expiry_height: transaction.expiry_height(),
+            height,
+        });
+    }
+
+    Ok(())
+}
+
+/// Returns `Ok(())` if `transaction` doesn't spend the same transparent
+/// output, or reveal the same nullifier, more than once.
+///
+/// Double-spends between transactions are checked by the state.
+pub fn inputs_are_unique(transaction: &Transaction) -> Result<(), TransactionError> {
+    let outpoints = transaction.inputs().filter_map(|input| match input {
+        TransparentInput::PrevOut { outpoint, .. } => Some(outpoint),
+        TransparentInput::Coinbase { .. } => None,
+    });
+    if !all_unique(outpoints) {
+        return Err(TransactionError::DuplicateInput);
+    }
+
+    if !all_unique(transaction.sprout_nullifiers())
+        || !all_unique(transaction.sapling_nullifiers())
+        || !all_unique(transaction.orchard_nullifiers())
+    {
+        return Err(TransactionError::DuplicateNullifier);
+    }
+
+    Ok(())
+}
+
+/// Returns true if `items` has no duplicates.
+fn all_unique<T: Eq + Hash>(items: impl IntoIterator<Item = T>) -> bool {
+    let mut seen = HashSet::new();
+    items.into_iter().all(|item| seen.insert(item))
+}
+
+/// Returns `Ok(())` if a version 5 `transaction` commits to the consensus
+/// branch ID of the network upgrade at `height` on `network`.
+///
+/// Earlier transaction versions don't have a consensus branch ID field.
+pub fn consensus_branch_id_is_valid(
+    transaction: &Transaction,
+    network: Network,
+    height: block::Height,
+) -> Result<(), TransactionError> {
+    if let Transaction::V5 {
+        consensus_branch_id,
+        ..
+    } = transaction
+    {
+        let expected = NetworkUpgrade::current(network, height)
+            .branch_id()
+            .map(u32::from);
+        if expected != Some(*consensus_branch_id) {
+            return Err(TransactionError::WrongConsensusBranchId {
+                expected,
+                actual: *consensus_branch_id,
+            });
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,687 @@
+//! Tests for transaction verification.
+
+use std::{convert::TryFrom, sync::Arc};
+
+use color_eyre::Report;
+use eyre::{ensure, eyre};
+use tower::{Service, ServiceExt};
+
+use zebra_chain::{
+    amount::Amount,
+    block::{self, Block},
+    serialization::ZcashDeserialize,
+    transaction::{LockTime, OutPoint, TransparentInput, TransparentOutput},
+    transparent::Script,
+    Network,
+};
+
+use super::*;
+
+/// Returns the coinbase transaction from mainnet block 1.
+fn block1_coinbase() -> Result<Arc<Transaction>, Report> {
+    let block = Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?;
+    Ok(block.transactions[0].clone())
+}
+
+/// Returns a version 4 transaction with one transparent input and output.
+fn v4_transaction(expiry_height: block::Height) -> Transaction {
+    Transaction::V4 {
+        inputs: vec![TransparentInput::PrevOut {
+            outpoint: OutPoint {
+                hash: transaction::Hash([0x11; 32]),
+                index: 0,
+            },
+            script: Script(vec![].into()),
+            sequence: u32::MAX,
+        }],
+        outputs: vec![TransparentOutput {
+            value: Amount::try_from(1i64).expect("1 is a valid amount"),
+            pk_script: Script(vec![].into()),
+        }],
+        lock_time: LockTime::unlocked(),
+        expiry_height,
+        value_balance: Amount::zero(),
+        shielded_data: None,
+        joinsplit_data: None,
+    }
+}
+
+/// Returns the `TransactionError` from a verification failure, if there is
+/// one.
+fn transaction_error(error: &Error) -> Option<&TransactionError> {
+    error
+        .downcast_ref::<VerificationError>()
+        .and_then(|error| error.inner().downcast_ref::<TransactionError>())
+}
+
+#[tokio::test]
+async fn verify_block_and_mempool_transactions() -> Result<(), Report> {
+    let mut verifier = TransactionVerifier::new(
+        Network::Mainnet,
+        zebra_state::in_memory::init(Network::Mainnet),
+    );
+
+    let coinbase = block1_coinbase()?;
+    let hash = verifier
+        .ready_and()
+        .await
+        .map_err(|e| eyre!(e))?
+        .call(Request::Block {
+            transaction: coinbase.clone(),
+            height: block::Height(1),
+            // The coinbase has no anchors, so the parent isn't looked up.
+            previous_block_hash: block::Hash([0; 32]),
+            known_utxos: Arc::new(HashMap::new()),
+        })
+        .await
+        .map_err(|e| eyre!(e))?;
+    ensure!(
+        hash == transaction::Hash::from(coinbase.as_ref()),
+        "verifier returned the wrong hash"
+    );
+
+    let error = verifier
+        .call(Request::Mempool {
+            transaction: coinbase,
+            height: block::Height(2),
+            known_utxos: Arc::new(HashMap::new()),
+        })
+        .await
+        .expect_err("coinbase transactions are only valid in blocks");
+    ensure!(
+        matches!(
+            transaction_error(&error),
+            Some(TransactionError::CoinbaseInMempool)
+        ),
+        "unexpected error: {:?}",
+        error
+    );
+
+    let transaction = Arc::new(v4_transaction(block::Height(0)));
+    verifier
+        .call(Request::Mempool {
+            transaction,
+            height: block::Height(500_000),
+            known_utxos: spent_output(vec![OP_1]),
+        })
+        .await
+        .map_err(|e| eyre!(e))?;
+
+    Ok(())
+}
+
+/// The `OP_1` opcode, which pushes a true value.
+const OP_1: u8 = 0x51;
+
+/// The `OP_CHECKSIG` opcode.
+const OP_CHECKSIG: u8 = 0xac;
+
+/// Returns the output spent by [`v4_transaction`], with `pk_script`.
+fn spent_output(pk_script: Vec<u8>) -> Arc<HashMap<OutPoint, TransparentOutput>> {
+    let outpoint = OutPoint {
+        hash: transaction::Hash([0x11; 32]),
+        index: 0,
+    };
+    let output = TransparentOutput {
+        value: Amount::try_from(2i64).expect("2 is a valid amount"),
+        pk_script: Script(pk_script.into()),
+    };
+    Arc::new(vec![(outpoint, output)].into_iter().collect())
+}
+
+#[tokio::test]
+async fn bad_signatures_are_rejected() -> Result<(), Report> {
+    let mut verifier = TransactionVerifier::new(
+        Network::Mainnet,
+        zebra_state::in_memory::init(Network::Mainnet),
+    );
+
+    // A pay-to-public-key output, for the secp256k1 generator point.
+    let mut pk_script = vec![33, 0x02];
+    pk_script.extend_from_slice(&[
+        0x79, 0xbe, 0x66, 0x7e, 0xf9, 0xdc, 0xbb, 0xac, 0x55, 0xa0, 0x62, 0x95, 0xce, 0x87, 0x0b,
+        0x07, 0x02, 0x9b, 0xfc, 0xdb, 0x2d, 0xce, 0x28, 0xd9, 0x59, 0xf2, 0x81, 0x5b, 0x16, 0xf8,
+        0x17, 0x98,
+    ]);
+    pk_script.push(OP_CHECKSIG);
+
+    // A well-formed signature, which doesn't sign the transaction.
+    let mut signature = vec![0x30, 0x44, 0x02, 0x20];
+    signature.extend_from_slice(&[0x01; 32]);
+    signature.extend_from_slice(&[0x02, 0x20]);
+    signature.extend_from_slice(&[0x01; 32]);
+    signature.push(HashType::ALL.0 as u8);
+    let mut script_sig = vec![signature.len() as u8];
+    script_sig.extend_from_slice(&signature);
+
+    let mut transaction = v4_transaction(block::Height(0));
+    if let Transaction::V4 { inputs, .. } = &mut transaction {
+        if let TransparentInput::PrevOut { script, .. } = &mut inputs[0] {
+            *script = Script(script_sig.into());
+        }
+    }
+
+    let error = verifier
+        .ready_and()
+        .await
+        .map_err(|e| eyre!(e))?
+        .call(Request::Mempool {
+            transaction: Arc::new(transaction),
+            height: block::Height(500_000),
+            known_utxos: spent_output(pk_script),
+        })
+        .await
+        .expect_err("the signature doesn't sign the transaction");
+    ensure!(
+        matches!(
+            error.downcast_ref::<VerificationError>(),
+            Some(VerificationError::Script { .. })
+        ),
+        "unexpected error: {:?}",
+        error
+    );
+
+    Ok(())
+}
+
+#[test]
+fn transaction_versions_match_network_upgrades() -> Result<(), Report> {
+    let v1 = block1_coinbase()?;
+    let v4 = v4_transaction(block::Height(0));
+
+    check::network_upgrade_is_valid(&v1, Network::Mainnet, block::Height(1))?;
+    ensure!(
+        matches!(
+            check::network_upgrade_is_valid(&v1, Network::Mainnet, block::Height(419_200)),
+            Err(TransactionError::WrongVersion(NetworkUpgrade::Sapling))
+        ),
+        "v1 transactions are invalid after Overwinter"
+    );
+
+    check::network_upgrade_is_valid(&v4, Network::Mainnet, block::Height(419_200))?;
+    check::network_upgrade_is_valid(&v4, Network::Testnet, block::Height(1_842_420))?;
+    ensure!(
+        check::network_upgrade_is_valid(&v4, Network::Mainnet, block::Height(419_199)).is_err(),
+        "v4 transactions are invalid before Sapling"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn transactions_need_inputs_and_outputs() {
+    let mut transaction = v4_transaction(block::Height(0));
+    assert!(check::has_inputs_and_outputs(&transaction).is_ok());
+
+    if let Transaction::V4 { outputs, .. } = &mut transaction {
+        outputs.clear();
+    }
+    assert!(matches!(
+        check::has_inputs_and_outputs(&transaction),
+        Err(TransactionError::NoOutputs)
+    ));
+
+    if let Transaction::V4 { inputs, .. } = &mut transaction {
+        inputs.clear();
+    }
+    assert!(matches!(
+        check::has_inputs_and_outputs(&transaction),
+        Err(TransactionError::NoInputs)
+    ));
+}
+
+#[test]
+fn transaction_values_are_bounded() {
+    use zebra_chain::amount::MAX_MONEY;
+
+    let mut transaction = v4_transaction(block::Height(0));
+    assert!(check::values_are_in_range(&transaction).is_ok());
+
+    let max = Amount::try_from(MAX_MONEY).expect("MAX_MONEY is a valid amount");
+    if let Transaction::V4 { outputs, .. } = &mut transaction {
+        outputs[0].value = max;
+    }
+    assert!(check::values_are_in_range(&transaction).is_ok());
+
+    if let Transaction::V4 { outputs, .. } = &mut transaction {
+        let output = outputs[0].clone();
+        outputs.push(output);
+    }
+    assert!(matches!(
+        check::values_are_in_range(&transaction),
+        Err(TransactionError::ValueOutOfRange(_))
+    ));
+}
+
+#[test]
+fn expiry_heights_are_checked() {
+    use zebra_chain::transaction::MAX_EXPIRY_HEIGHT;
+
+    let height = block::Height(500_000);
+
+    for valid in &[0, 500_000, MAX_EXPIRY_HEIGHT.0] {
+        let transaction = v4_transaction(block::Height(*valid));
+        assert!(check::expiry_height_is_valid(&transaction, height).is_ok());
+    }
+
+    assert!(matches!(
+        check::expiry_height_is_valid(&v4_transaction(block::Height(499_999)), height),
+        Err(TransactionError::Expired { .. })
+    ));
+    assert!(matches!(
+        check::expiry_height_is_valid(
+            &v4_transaction(block::Height(MAX_EXPIRY_HEIGHT.0 + 1)),
+            height
+        ),
+        Err(TransactionError::MaximumExpiryHeight(_))
+    ));
+}
+
+/// Returns a version 5 coinbase transaction, copying the coinbase input from
+/// mainnet block 1.
+fn v5_coinbase(
+    expiry_height: block::Height,
+    consensus_branch_id: u32,
+) -> Result<Transaction, Report> {
+    let coinbase = block1_coinbase()?;
+    Ok(Transaction::V5 {
+        inputs: coinbase.inputs().cloned().collect(),
+        outputs: coinbase.outputs().cloned().collect(),
+        lock_time: LockTime::unlocked(),
+        expiry_height,
+        consensus_branch_id,
+        sapling_value_balance: Amount::zero(),
+        sapling_shielded_data: None,
+        orchard_shielded_data: None,
+    })
+}
+
+#[test]
+fn coinbase_inputs_are_checked() -> Result<(), Report> {
+    let coinbase = block1_coinbase()?;
+    check::coinbase_is_valid(&coinbase, Network::Mainnet, block::Height(1))?;
+
+    let mut transaction = v4_transaction(block::Height(0));
+    check::coinbase_is_valid(&transaction, Network::Mainnet, block::Height(500_000))?;
+
+    if let Transaction::V4 { inputs, .. } = &mut transaction {
+        inputs.extend(coinbase.inputs().cloned());
+    }
+    ensure!(
+        matches!(
+            check::coinbase_is_valid(&transaction, Network::Mainnet, block::Height(500_000)),
+            Err(TransactionError::CoinbaseInputFound)
+        ),
+        "only coinbase transactions can have coinbase inputs"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn nu5_coinbase_expiry_height_is_the_block_height() -> Result<(), Report> {
+    let height = block::Height(1_687_104);
+    let branch_id = 0xc2d6_d0b4;
+
+    check::coinbase_is_valid(&v5_coinbase(height, branch_id)?, Network::Mainnet, height)?;
+    ensure!(
+        matches!(
+            check::coinbase_is_valid(
+                &v5_coinbase(block::Height(0), branch_id)?,
+                Network::Mainnet,
+                height
+            ),
+            Err(TransactionError::CoinbaseExpiryHeight { .. })
+        ),
+        "NU5 coinbase transactions must expire at their block height"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn duplicate_inputs_are_rejected() {
+    let mut transaction = v4_transaction(block::Height(0));
+    assert!(check::inputs_are_unique(&transaction).is_ok());
+
+    if let Transaction::V4 { inputs, .. } = &mut transaction {
+        let input = inputs[0].clone();
+        inputs.push(input);
+    }
+    assert!(matches!(
+        check::inputs_are_unique(&transaction),
+        Err(TransactionError::DuplicateInput)
+    ));
+}
+
+#[test]
+fn batch_verifier_failures_are_not_misbehavior() {
+    let hash = transaction::Hash([0x22; 32]);
+
+    let invalid = VerificationError::proof(
+        hash,
+        SAPLING_PROOF_RULES,
+        groth16::VerificationError::InvalidProof.into(),
+    );
+    assert!(matches!(
+        invalid.downcast_ref::<VerificationError>(),
+        Some(error) if matches!(error, VerificationError::Proof { .. }) && error.is_misbehavior()
+    ));
+
+    let dropped = VerificationError::proof(
+        hash,
+        SAPLING_PROOF_RULES,
+        groth16::VerificationError::Dropped.into(),
+    );
+    assert!(!dropped.is::<VerificationError>());
+
+    let dropped = VerificationError::signature(
+        hash,
+        JOINSPLIT_SIGNATURE_RULES,
+        crate::primitives::VerifierDropped("Ed25519").into(),
+    );
+    assert!(!dropped.is::<VerificationError>());
+}
+
+#[test]
+fn v5_consensus_branch_id_is_checked() -> Result<(), Report> {
+    let height = block::Height(1_687_104);
+    let nu5_branch_id = 0xc2d6_d0b4;
+    let canopy_branch_id = 0xe9ff_75a6;
+
+    let transaction = v5_coinbase(height, nu5_branch_id)?;
+    check::consensus_branch_id_is_valid(&transaction, Network::Mainnet, height)?;
+
+    let transaction = v5_coinbase(height, canopy_branch_id)?;
+    ensure!(
+        matches!(
+            check::consensus_branch_id_is_valid(&transaction, Network::Mainnet, height),
+            Err(TransactionError::WrongConsensusBranchId {
+                expected: Some(0xc2d6_d0b4),
+                actual: 0xe9ff_75a6,
+            })
+        ),
+        "version 5 transactions must use the current branch ID"
+    );
+
+    // Earlier versions don't have a branch ID.
+    check::consensus_branch_id_is_valid(&v4_transaction(height), Network::Mainnet, height)?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn sapling_anchors_must_be_in_the_state() -> Result<(), Report> {
+    use zebra_chain::sapling::tree::{NoteCommitmentTree, Root};
+
+    let mut state_service = zebra_state::in_memory::init(Network::Mainnet);
+    let genesis: Arc<_> =
+        Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.into();
+    state_service
+        .ready_and()
+        .await
+        .map_err(|e| eyre!(e))?
+        .call(zebra_state::Request::AddBlock { block: genesis })
+        .await
+        .map_err(|e| eyre!(e))?;
+
+    let hash = transaction::Hash([0x11; 32]);
+    let empty_root = NoteCommitmentTree::default().root();
+    sapling_anchor_is_valid(&mut state_service, hash, empty_root, None)
+        .await
+        .map_err(|e| eyre!(e))?;
+
+    let error = sapling_anchor_is_valid(&mut state_service, hash, Root([0xff; 32]), None)
+        .await
+        .expect_err("anchors must be tree roots in the state");
+    ensure!(
+        matches!(
+            transaction_error(&error),
+            Some(TransactionError::UnknownSaplingAnchor(_))
+        ),
+        "unexpected error: {:?}",
+        error
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn sprout_anchors_can_be_earlier_joinsplit_roots() -> Result<(), Report> {
+    use zebra_chain::sprout::tree::NoteCommitmentTree;
+
+    let mut state_service = zebra_state::in_memory::init(Network::Mainnet);
+    let genesis: Arc<_> =
+        Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.into();
+    state_service
+        .ready_and()
+        .await
+        .map_err(|e| eyre!(e))?
+        .call(zebra_state::Request::AddBlock { block: genesis })
+        .await
+        .map_err(|e| eyre!(e))?;
+
+    let hash = transaction::Hash([0x11; 32]);
+    let mut tree = NoteCommitmentTree::default();
+    let empty_root = tree.root().0;
+    tree.append([1; 32])?;
+    tree.append([2; 32])?;
+    let interstitial_root = tree.root().0;
+
+    // The second JoinSplit uses the root after the first one.
+    let joinsplits = [
+        (empty_root, [[1; 32], [2; 32]]),
+        (interstitial_root, [[3; 32], [4; 32]]),
+    ];
+    sprout_anchors_are_valid(&mut state_service, hash, &joinsplits, None)
+        .await
+        .map_err(|e| eyre!(e))?;
+
+    // Interstitial roots can't be used by other transactions.
+    for joinsplits in &[
+        [(interstitial_root, [[3; 32], [4; 32]])],
+        [([0xff; 32], [[3; 32], [4; 32]])],
+    ] {
+        let error = sprout_anchors_are_valid(&mut state_service, hash, joinsplits, None)
+            .await
+            .expect_err("anchors must be tree roots in the state");
+        ensure!(
+            matches!(
+                transaction_error(&error),
+                Some(TransactionError::UnknownSproutAnchor(_))
+            ),
+            "unexpected error: {:?}",
+            error
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns a version 5 transaction at the NU5 activation height, which
+/// spends the output from [`spent_output`] into a new Orchard note.
+///
+/// The Orchard bundle is made by the `orchard` crate's builder, so it has a
+/// real proof, and real signatures on the transaction's signature hash.
+fn v5_orchard_transaction() -> Result<Transaction, Report> {
+    use ::orchard::{
+        builder::Builder,
+        bundle::Flags,
+        circuit::ProvingKey,
+        keys::{FullViewingKey, SpendingKey},
+        value::NoteValue,
+        Anchor,
+    };
+    use rand::rngs::OsRng;
+    use zebra_chain::{
+        orchard::{self, EncryptedNote, Nullifier, RedPallasSignature, WrappedNoteKey},
+        proofs::Halo2Proof,
+    };
+
+    let sk = Option::<SpendingKey>::from(SpendingKey::from_bytes([7; 32]))
+        .ok_or_else(|| eyre!("invalid spending key"))?;
+    let recipient = FullViewingKey::from(&sk).default_address();
+    // The verifier's state has the genesis block, so the anchor is the root
+    // of the empty tree.
+    let empty_root = orchard::tree::NoteCommitmentTree::default().root();
+    let anchor = Option::<Anchor>::from(Anchor::from_bytes(empty_root.0))
+        .ok_or_else(|| eyre!("invalid anchor"))?;
+
+    let mut builder = Builder::new(Flags::from_parts(true, true), anchor);
+    builder
+        .add_recipient(None, recipient, NoteValue::from_raw(1), None)
+        .map_err(|e| eyre!(e))?;
+    let bundle = builder.build::<i64>(OsRng).map_err(|e| eyre!("{:?}", e))?;
+
+    // Proofs and signatures aren't part of the signature hash, so the
+    // transaction is hashed with placeholders, which are replaced once the
+    // bundle is signed.
+    let mut actions = bundle.actions().iter().map(|action| {
+        let note = action.encrypted_note();
+        orchard::Action {
+            cv: action.cv_net().to_bytes(),
+            nullifier: Nullifier(action.nullifier().to_bytes()),
+            rk: action.rk().into(),
+            cm_x: action.cmx().to_bytes(),
+            ephemeral_key: note.epk_bytes,
+            enc_ciphertext: EncryptedNote(note.enc_ciphertext),
+            out_ciphertext: WrappedNoteKey(note.out_ciphertext),
+            spend_auth_sig: RedPallasSignature([0; 64]),
+        }
+    });
+    let mut shielded_data = orchard::ShieldedData {
+        flags: orchard::Flags {
+            enable_spends: true,
+            enable_outputs: true,
+        },
+        value_balance: Amount::try_from(*bundle.value_balance())?,
+        shared_anchor: orchard::tree::Root(bundle.anchor().to_bytes()),
+        proof: Halo2Proof(vec![].into()),
+        first: actions.next().expect("bundles have at least one action"),
+        rest: actions.collect(),
+        binding_sig: RedPallasSignature([0; 64]),
+    };
+
+    let mut transaction = match v4_transaction(block::Height(0)) {
+        Transaction::V4 { inputs, .. } => Transaction::V5 {
+            inputs,
+            outputs: Vec::new(),
+            lock_time: LockTime::unlocked(),
+            expiry_height: block::Height(0),
+            consensus_branch_id: 0xc2d6_d0b4,
+            sapling_value_balance: Amount::zero(),
+            sapling_shielded_data: None,
+            orchard_shielded_data: Some(shielded_data.clone()),
+        },
+        _ => unreachable!("v4_transaction returns a version 4 transaction"),
+    };
+    let previous_outputs: Vec<_> = spent_output(vec![OP_1]).values().cloned().collect();
+    let sighash = transaction.sighash(0xc2d6_d0b4, HashType::ALL, &previous_outputs, None)?;
+
+    let bundle = bundle
+        .create_proof(&ProvingKey::build())
+        .map_err(|e| eyre!("{:?}", e))?
+        .prepare(OsRng, sighash.0)
+        .finalize()
+        .map_err(|e| eyre!("{:?}", e))?;
+    let signatures = bundle.actions().iter().map(|action| action.authorization());
+    let actions = std::iter::once(&mut shielded_data.first).chain(shielded_data.rest.iter_mut());
+    for (action, signature) in actions.zip(signatures) {
+        action.spend_auth_sig = RedPallasSignature(signature.into());
+    }
+    shielded_data.proof = Halo2Proof(bundle.authorization().proof().as_ref().to_vec().into());
+    shielded_data.binding_sig =
+        RedPallasSignature(bundle.authorization().binding_signature().into());
+    if let Transaction::V5 {
+        orchard_shielded_data,
+        ..
+    } = &mut transaction
+    {
+        *orchard_shielded_data = Some(shielded_data);
+    }
+
+    Ok(transaction)
+}
+
+#[tokio::test]
+async fn orchard_bundles_are_verified() -> Result<(), Report> {
+    let mut state_service = zebra_state::in_memory::init(Network::Mainnet);
+    let genesis: Arc<_> =
+        Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.into();
+    state_service
+        .ready_and()
+        .await
+        .map_err(|e| eyre!(e))?
+        .call(zebra_state::Request::AddBlock { block: genesis })
+        .await
+        .map_err(|e| eyre!(e))?;
+    let mut verifier = TransactionVerifier::new(Network::Mainnet, state_service);
+    let height = block::Height(1_687_104);
+    let transaction = v5_orchard_transaction()?;
+
+    verifier
+        .ready_and()
+        .await
+        .map_err(|e| eyre!(e))?
+        .call(Request::Mempool {
+            transaction: Arc::new(transaction.clone()),
+            height,
+            known_utxos: spent_output(vec![OP_1]),
+        })
+        .await
+        .map_err(|e| eyre!(e))?;
+
+    // A spend authorization signature isn't a valid binding signature.
+    let mut bad_signature = transaction.clone();
+    if let Transaction::V5 {
+        orchard_shielded_data: Some(shielded_data),
+        ..
+    } = &mut bad_signature
+    {
+        shielded_data.binding_sig = shielded_data.first.spend_auth_sig;
+    }
+    let error = verifier
+        .call(Request::Mempool {
+            transaction: Arc::new(bad_signature),
+            height,
+            known_utxos: spent_output(vec![OP_1]),
+        })
+        .await
+        .expect_err("the binding signature doesn't sign the transaction");
+    ensure!(
+        matches!(
+            error.downcast_ref::<VerificationError>(),
+            Some(VerificationError::Signature { .. })
+        ),
+        "unexpected error: {:?}",
+        error
+    );
+
+    // The anchor must be a tree root in the state.
+    let mut bad_anchor = transaction;
+    if let Transaction::V5 {
+        orchard_shielded_data: Some(shielded_data),
+        ..
+    } = &mut bad_anchor
+    {
+        shielded_data.shared_anchor = zebra_chain::orchard::tree::Root([1; 32]);
+    }
+    let error = verifier
+        .call(Request::Mempool {
+            transaction: Arc::new(bad_anchor),
+            height,
+            known_utxos: spent_output(vec![OP_1]),
+        })
+        .await
+        .expect_err("the anchor isn't in the state");
+    ensure!(
+        matches!(
+            transaction_error(&error),
+            Some(TransactionError::UnknownOrchardAnchor(_))
+        ),
+        "unexpected error: {:?}",
+        error
+    );
+
+    Ok(())
+}
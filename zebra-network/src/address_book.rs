@@ -5,11 +5,13 @@ use std::{
     collections::{BTreeSet, HashMap},
     iter::Extend,
     net::SocketAddr,
+    time::Duration,
 };
 
-use chrono::{DateTime, Utc};
 use tracing::Span;
 
+use zebra_chain::serialization::DateTime32;
+
 use crate::{
     constants,
     types::{MetaAddr, PeerServices},
@@ -17,10 +19,15 @@ use crate::{
 
 /// A database of peers, their advertised services, and information on when they
 /// were last seen.
+///
+/// The address book also tracks the round-trip time of peers we have
+/// connected to, so that we can prefer low-latency peers when reconnecting.
 #[derive(Debug)]
 pub struct AddressBook {
-    by_addr: HashMap<SocketAddr, (DateTime<Utc>, PeerServices)>,
+    by_addr: HashMap<SocketAddr, (DateTime32, PeerServices)>,
     by_time: BTreeSet<MetaAddr>,
+    /// The smoothed heartbeat round-trip time of each peer.
+    rtts: HashMap<SocketAddr, Duration>,
     span: Span,
 }
 
@@ -31,6 +38,7 @@ impl AddressBook {
         AddressBook {
             by_addr: HashMap::default(),
             by_time: BTreeSet::default(),
+            rtts: HashMap::default(),
             span,
         }
     }
@@ -93,6 +101,30 @@ impl AddressBook {
         self.assert_consistency();
     }
 
+    /// Record a heartbeat round-trip time of `rtt` to the peer at `addr`.
+    ///
+    /// Like TCP's [smoothed RTT], each sample moves the peer's round-trip
+    /// time an eighth of the way towards it, so one slow pong doesn't make a
+    /// good peer look distant.
+    ///
+    /// [smoothed RTT]: https://tools.ietf.org/html/rfc6298#section-2
+    pub fn record_rtt(&mut self, addr: SocketAddr, rtt: Duration) {
+        let _guard = self.span.enter();
+        let smoothed = match self.rtts.get(&addr) {
+            Some(&prev) => (prev * 7 + rtt) / 8,
+            None => rtt,
+        };
+        trace!(?addr, ?rtt, ?smoothed, "recording peer round-trip time");
+        self.rtts.insert(addr, smoothed);
+    }
+
+    /// Returns the smoothed round-trip time to the peer at `addr`, or `None`
+    /// if we have never measured it.
+    pub fn rtt(&self, addr: &SocketAddr) -> Option<Duration> {
+        let _guard = self.span.enter();
+        self.rtts.get(addr).copied()
+    }
+
     /// Compute a cutoff time that can determine whether an entry
     /// in an address book being updated with peer message timestamps
     /// represents a known-disconnected peer or a potentially-connected peer.
@@ -102,10 +134,8 @@ impl AddressBook {
     /// the connection. Therefore, if the last-seen timestamp is older than
     /// [`constants::LIVE_PEER_DURATION`] ago, we know we must have disconnected
     /// from it. Otherwise, we could potentially be connected to it.
-    fn cutoff_time() -> DateTime<Utc> {
-        // chrono uses signed durations while stdlib uses unsigned durations
-        use chrono::Duration as CD;
-        Utc::now() - CD::from_std(constants::LIVE_PEER_DURATION).unwrap()
+    fn cutoff_time() -> DateTime32 {
+        DateTime32::now().saturating_sub(constants::LIVE_PEER_DURATION)
     }
 
     /// Returns true if the given [`SocketAddr`] could potentially be connected
@@ -202,3 +232,22 @@ impl<'a> Iterator for Drain<'a> {
         Some(next_item)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_times_are_smoothed() {
+        let addr: SocketAddr = "192.0.2.1:8233".parse().unwrap();
+        let mut address_book = AddressBook::new(Span::none());
+        assert_eq!(address_book.rtt(&addr), None);
+
+        address_book.record_rtt(addr, Duration::from_millis(80));
+        assert_eq!(address_book.rtt(&addr), Some(Duration::from_millis(80)));
+
+        // One slow pong only moves the round-trip time an eighth of the way.
+        address_book.record_rtt(addr, Duration::from_millis(880));
+        assert_eq!(address_book.rtt(&addr), Some(Duration::from_millis(180)));
+    }
+}
@@ -0,0 +1,25 @@
+//! A shared handle to the height of this node's best chain tip.
+
+use std::sync::{Arc, Mutex};
+
+use zebra_chain::block;
+
+/// A cloneable handle to the height of this node's best chain tip.
+///
+/// The networking code uses the tip height to decide which network upgrade is
+/// active, and therefore which peer protocol versions are obsolete. The
+/// component that commits blocks should update it as the tip advances.
+#[derive(Clone, Debug, Default)]
+pub struct BestTipHeight(Arc<Mutex<Option<block::Height>>>);
+
+impl BestTipHeight {
+    /// Update the best tip height.
+    pub fn set(&self, height: block::Height) {
+        *self.0.lock().expect("mutex should be unpoisoned") = Some(height);
+    }
+
+    /// Returns the best tip height, or `None` if it is not yet known.
+    pub fn get(&self) -> Option<block::Height> {
+        *self.0.lock().expect("mutex should be unpoisoned")
+    }
+}
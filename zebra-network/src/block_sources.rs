@@ -0,0 +1,80 @@
+//! The peers that sent recently downloaded blocks.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use zebra_chain::block;
+
+use crate::constants;
+
+/// A cloneable record of the peer that sent each recently downloaded block.
+///
+/// The peer set records the peer when a block download succeeds, so blocks
+/// that fail verification can be reported against the peer that sent them.
+/// Only the most recent [`constants::BLOCK_SOURCES_SIZE`] blocks are kept.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct BlockSources(Arc<Mutex<Sources>>);
+
+#[derive(Debug, Default)]
+struct Sources {
+    peers: HashMap<block::Hash, SocketAddr>,
+    /// The hashes in `peers`, oldest first.
+    order: VecDeque<block::Hash>,
+}
+
+impl BlockSources {
+    /// Record that the peer at `addr` sent the block with `hash`.
+    pub(crate) fn insert(&self, hash: block::Hash, addr: SocketAddr) {
+        let mut sources = self.0.lock().expect("mutex should be unpoisoned");
+        if sources.peers.insert(hash, addr).is_none() {
+            sources.order.push_back(hash);
+        }
+        while sources.order.len() > constants::BLOCK_SOURCES_SIZE {
+            let oldest = sources
+                .order
+                .pop_front()
+                .expect("the queue is longer than the limit");
+            sources.peers.remove(&oldest);
+        }
+    }
+
+    /// Returns the peer that most recently sent the block with `hash`, if
+    /// it is still recorded.
+    pub(crate) fn get(&self, hash: &block::Hash) -> Option<SocketAddr> {
+        self.0
+            .lock()
+            .expect("mutex should be unpoisoned")
+            .peers
+            .get(hash)
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oldest_sources_are_forgotten() {
+        let sources = BlockSources::default();
+        let addr: SocketAddr = "127.0.0.1:8233".parse().unwrap();
+        let hash = |i: usize| {
+            let mut bytes = [0; 32];
+            bytes[..8].copy_from_slice(&(i as u64).to_le_bytes());
+            block::Hash(bytes)
+        };
+
+        for i in 0..=constants::BLOCK_SOURCES_SIZE {
+            sources.insert(hash(i), addr);
+        }
+        assert_eq!(sources.get(&hash(0)), None);
+        assert_eq!(sources.get(&hash(1)), Some(addr));
+        assert_eq!(
+            sources.get(&hash(constants::BLOCK_SOURCES_SIZE)),
+            Some(addr)
+        );
+    }
+}
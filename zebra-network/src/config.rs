@@ -1,5 +1,5 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     net::{SocketAddr, ToSocketAddrs},
     string::String,
     time::Duration,
@@ -7,12 +7,44 @@ use std::{
 
 use zebra_chain::Network;
 
+use crate::{
+    ip_filter::{BanList, IpFilter},
+    types::PeerServices,
+    IpNetwork,
+};
+
 /// Configuration for networking code.
 #[derive(Clone, Debug, Deserialize, Serialize)]
-#[serde(deny_unknown_fields)]
+#[serde(deny_unknown_fields, default)]
 pub struct Config {
-    /// The address on which this node should listen for connections.
-    pub listen_addr: SocketAddr,
+    /// The addresses on which this node should listen for connections.
+    ///
+    /// To accept both IPv4 and IPv6 connections, list an address of each
+    /// kind, like `0.0.0.0:8233` and `[::]:8233`.
+    ///
+    /// The old `listen_addr` key, with a single address, is also accepted.
+    #[serde(alias = "listen_addr", deserialize_with = "one_or_many_addrs")]
+    pub listen_addrs: Vec<SocketAddr>,
+
+    /// The address of a SOCKS5 proxy, such as a local Tor daemon, to use for
+    /// outbound connections.
+    ///
+    /// If this is set, initial peer hostnames are also resolved through the
+    /// proxy, so DNS seeder lookups don't leak to the local resolver. See
+    /// `proxy_dns_server`.
+    pub proxy: Option<SocketAddr>,
+
+    /// A public DNS server to look up hostnames on, through the `proxy`.
+    ///
+    /// By default, hostnames are resolved by the proxy, using Tor's
+    /// `RESOLVE` extension, which only returns one address per lookup. If
+    /// this is set, queries go to this server over TCP through the proxy
+    /// instead, so they return every address of each DNS seeder, and fall
+    /// back to `RESOLVE` if the server can't be reached. The server sees
+    /// which seeders we look up, but not our IP address.
+    ///
+    /// This is only used if `proxy` is set.
+    pub proxy_dns_server: Option<SocketAddr>,
 
     /// The network to connect to.
     pub network: Network,
@@ -20,17 +52,130 @@ pub struct Config {
     /// The user-agent to advertise.
     pub user_agent: String,
 
+    /// The services to advertise to peers, as a bitmask of service bits.
+    pub advertised_services: PeerServices,
+
+    /// Whether to ask peers to relay unconfirmed transactions to us.
+    ///
+    /// This is the BIP37 `relay` flag in our `version` message. If it is
+    /// false, peers should not announce transactions until we send them a
+    /// bloom filter, which we never do.
+    pub relay: bool,
+
+    /// Whether to serve BIP157 compact block filters to peers.
+    ///
+    /// If this is true, we advertise `NODE_COMPACT_FILTERS`, and pass filter
+    /// requests to the inbound service. `zebrad` answers them from the
+    /// state's compact filter index, so it refuses to start if
+    /// `state.index_compact_filters` is false.
+    pub serve_compact_filters: bool,
+
     /// A list of initial peers for the peerset when operating on
     /// mainnet.
+    ///
+    /// These are usually DNS seeders. Failed lookups are retried, and the
+    /// seeders are resolved again if we know about too few peers.
     pub initial_mainnet_peers: HashSet<String>,
 
     /// A list of initial peers for the peerset when operating on
     /// testnet.
     pub initial_testnet_peers: HashSet<String>,
 
+    /// A list of initial peers for the peerset when operating on
+    /// regtest.
+    ///
+    /// Regtest has no DNS seeders, so this is empty by default.
+    pub initial_regtest_peers: HashSet<String>,
+
+    /// Fixed peer addresses to add to the address book at startup, as
+    /// `host:port` strings.
+    ///
+    /// Unlike the initial peers for each network, we don't connect to these
+    /// peers straight away. The crawler treats them like gossiped peers.
+    pub initial_peers: HashSet<String>,
+
+    /// If this is not empty, only connect to these peers, as `host:port`
+    /// strings.
+    ///
+    /// This disables the initial peers for each network, `initial_peers`,
+    /// and address gossip. Inbound connections from other IP addresses are
+    /// closed.
+    pub only_connect_to: HashSet<String>,
+
+    /// If this is not empty, only connect to, and accept connections from,
+    /// peers in these IP ranges.
+    ///
+    /// Ranges are in CIDR notation, like `"10.0.0.0/8"`.
+    pub allowed_ranges: Vec<IpNetwork>,
+
+    /// Never connect to, or accept connections from, peers in these IP
+    /// ranges, even if they are in `allowed_ranges`.
+    pub denied_ranges: Vec<IpNetwork>,
+
     /// The outgoing request buffer size for the peer set.
     pub peerset_request_buffer_size: usize,
 
+    /// The maximum number of inbound connections we accept, across all of
+    /// the `listen_addrs`.
+    ///
+    /// When the limit is reached, new connections replace an unprotected
+    /// inbound peer, or are closed as soon as they are accepted.
+    pub max_inbound_connections: usize,
+
+    /// The number of outbound connections the crawler tries to keep open.
+    ///
+    /// This is also the number of peers we try to connect to at startup.
+    ///
+    /// The old `peerset_initial_target_size` key is also accepted.
+    #[serde(alias = "peerset_initial_target_size")]
+    pub target_outbound_peers: usize,
+
+    /// The maximum number of new outbound connections the crawler opens
+    /// each second, or zero for no limit.
+    ///
+    /// This stops a node with a large address book from flooding the network
+    /// or local connection tracking with connection attempts.
+    pub max_outbound_connections_per_second: u32,
+
+    /// The maximum number of outbound handshakes the crawler runs at once.
+    pub max_concurrent_outbound_handshakes: usize,
+
+    /// The maximum payload length accepted from a peer, in bytes.
+    ///
+    /// Peers that send larger messages are disconnected.
+    pub max_message_len: usize,
+
+    /// The maximum payload length accepted from a peer for `block`
+    /// messages, in bytes.
+    pub max_block_message_len: usize,
+
+    /// The number of consecutive heartbeat pings a peer may fail to answer
+    /// before we close the connection.
+    ///
+    /// Values greater than one let a silent connection outlive
+    /// [`LIVE_PEER_DURATION`](crate::constants::LIVE_PEER_DURATION), so the
+    /// address book may list the peer as disconnected before it is closed.
+    pub max_missed_heartbeats: usize,
+
+    /// The maximum number of blocks or transactions requested in each
+    /// `getdata` message.
+    ///
+    /// Larger requests are split into batches of this size, which are sent
+    /// to the peer together.
+    pub getdata_batch_size: usize,
+
+    /// Whether to log the command, length, checksum, and a hex dump of the
+    /// start of every message sent to or received from a peer.
+    ///
+    /// The events are emitted at `debug` level under the
+    /// `zebra_network::wire` target, so they also need to be enabled in the
+    /// tracing filter.
+    pub trace_wire_format: bool,
+
+    /// The number of inbound messages a peer may send over its rate limits
+    /// in each minute, before we close the connection.
+    pub max_rate_limit_violations: usize,
+
     // Note: due to the way this is rendered by the toml
     // serializer, the Duration fields should come last.
     /// The default RTT estimate for peer responses, used in load-balancing.
@@ -39,14 +184,60 @@ pub struct Config {
     /// The decay time for the exponentially-weighted moving average response time.
     pub ewma_decay_time: Duration,
 
-    /// The timeout for peer handshakes.
+    /// The timeout for opening a TCP connection to a peer, including any
+    /// proxy negotiation.
+    pub connect_timeout: Duration,
+
+    /// The timeout for the `version` and `verack` exchange with a peer, after
+    /// the TCP connection is open.
     pub handshake_timeout: Duration,
 
-    /// How frequently we attempt to connect to a new peer.
+    /// The time a peer has to answer each of our requests.
+    ///
+    /// Requests that time out fail immediately, and are counted in the
+    /// peer's [`PeerInfo`](crate::PeerInfo). Block and transaction
+    /// downloads get this much time for each batch of items.
+    pub request_timeout: Duration,
+
+    /// How frequently we ask peers for new addresses, and connect to new
+    /// peers if we have fewer than `target_outbound_peers`.
     pub new_peer_interval: Duration,
 
-    /// The initial target size for the peer set.
-    pub peerset_initial_target_size: usize,
+    /// Per-connection rate limits for inbound messages, keyed by message
+    /// command, such as `"addr"` or `"inv"`.
+    ///
+    /// Messages over the limit are dropped. Commands without a limit are
+    /// never dropped.
+    pub inbound_rate_limits: HashMap<String, RateLimit>,
+}
+
+/// Deserializes a list of addresses, or a single address, for configs that
+/// use the old `listen_addr` key.
+fn one_or_many_addrs<'de, D>(deserializer: D) -> Result<Vec<SocketAddr>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(SocketAddr),
+        Many(Vec<SocketAddr>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(addr) => vec![addr],
+        OneOrMany::Many(addrs) => addrs,
+    })
+}
+
+/// A token bucket rate limit for one inbound message type.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    /// The number of messages a peer can send at once, after being idle.
+    pub burst: u32,
+    /// The sustained number of messages per second.
+    pub per_second: f64,
 }
 
 impl Config {
@@ -59,10 +250,26 @@ impl Config {
     }
 
     /// Get the initial seed peers based on the configured network.
-    pub fn initial_peers(&self) -> HashSet<SocketAddr> {
+    pub fn initial_seed_peers(&self) -> HashSet<SocketAddr> {
+        Config::parse_peers(self.initial_seed_peer_names().clone())
+    }
+
+    /// Get the filter for peer IP addresses, from the allowed and denied
+    /// ranges.
+    pub(crate) fn ip_filter(&self, bans: BanList) -> IpFilter {
+        IpFilter::new(
+            self.allowed_ranges.clone(),
+            self.denied_ranges.clone(),
+            bans,
+        )
+    }
+
+    /// Get the unresolved initial seed peers for the configured network.
+    pub(crate) fn initial_seed_peer_names(&self) -> &HashSet<String> {
         match self.network {
-            Network::Mainnet => Config::parse_peers(self.initial_mainnet_peers.clone()),
-            Network::Testnet => Config::parse_peers(self.initial_testnet_peers.clone()),
+            Network::Mainnet => &self.initial_mainnet_peers,
+            Network::Testnet => &self.initial_testnet_peers,
+            Network::Regtest => &self.initial_regtest_peers,
         }
     }
 }
@@ -89,19 +296,82 @@ impl Default for Config {
         .collect();
 
         Config {
-            listen_addr: "127.0.0.1:8233"
+            listen_addrs: vec!["127.0.0.1:8233"
                 .parse()
-                .expect("Hardcoded address should be parseable"),
+                .expect("Hardcoded address should be parseable")],
+            proxy: None,
+            proxy_dns_server: None,
             user_agent: crate::constants::USER_AGENT.to_owned(),
+            advertised_services: PeerServices::NODE_NETWORK,
+            relay: false,
+            serve_compact_filters: false,
             network: Network::Mainnet,
             initial_mainnet_peers: mainnet_peers,
             initial_testnet_peers: testnet_peers,
+            initial_regtest_peers: HashSet::new(),
+            initial_peers: HashSet::new(),
+            only_connect_to: HashSet::new(),
+            allowed_ranges: Vec::new(),
+            denied_ranges: Vec::new(),
             ewma_default_rtt: Duration::from_secs(1),
             ewma_decay_time: Duration::from_secs(60),
             peerset_request_buffer_size: 10,
+            max_inbound_connections: 100,
+            target_outbound_peers: 50,
+            max_outbound_connections_per_second: 10,
+            max_concurrent_outbound_handshakes: 50,
+            max_message_len: crate::constants::MAX_PROTOCOL_MESSAGE_LEN,
+            max_block_message_len: crate::constants::MAX_BLOCK_MESSAGE_LEN,
+            max_missed_heartbeats: 1,
+            getdata_batch_size: crate::constants::GETDATA_BATCH_SIZE,
+            trace_wire_format: false,
+            max_rate_limit_violations: 100,
+            connect_timeout: Duration::from_secs(4),
             handshake_timeout: Duration::from_secs(4),
+            request_timeout: crate::constants::REQUEST_TIMEOUT,
             new_peer_interval: Duration::from_secs(60),
-            peerset_initial_target_size: 50,
+            inbound_rate_limits: [
+                // Answers to our own getaddr requests aren't limited, so
+                // unsolicited addr messages should be rare.
+                (
+                    "addr",
+                    RateLimit {
+                        burst: 10,
+                        per_second: 0.1,
+                    },
+                ),
+                (
+                    "getaddr",
+                    RateLimit {
+                        burst: 2,
+                        per_second: 0.01,
+                    },
+                ),
+                (
+                    "inv",
+                    RateLimit {
+                        burst: 100,
+                        per_second: 10.0,
+                    },
+                ),
+                (
+                    "getdata",
+                    RateLimit {
+                        burst: 100,
+                        per_second: 10.0,
+                    },
+                ),
+                (
+                    "tx",
+                    RateLimit {
+                        burst: 100,
+                        per_second: 10.0,
+                    },
+                ),
+            ]
+            .iter()
+            .map(|(command, limit)| (command.to_string(), *limit))
+            .collect(),
         }
     }
 }
@@ -0,0 +1,348 @@
+//! Information about the peers we are currently connected to, for
+//! introspection by operators and RPCs.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use futures::channel::oneshot;
+
+use zebra_chain::{block, network_upgrade::NetworkUpgrade, Network};
+
+use crate::protocol::external::types::{PeerServices, Version};
+
+/// Whether a connection was opened by us or by the remote peer.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Direction {
+    /// The remote peer connected to our listener.
+    Inbound {
+        /// The local address of the listener that accepted the connection.
+        listen_addr: SocketAddr,
+    },
+    /// We connected to the remote peer.
+    Outbound,
+}
+
+/// Information about a connected peer.
+#[derive(Clone, Debug)]
+pub struct PeerInfo {
+    /// The peer's address.
+    pub addr: SocketAddr,
+    /// Whether we connected to the peer, or the peer connected to us.
+    pub direction: Direction,
+    /// The protocol version the peer sent in its `version` message.
+    pub version: Version,
+    /// The protocol version used for the connection, which is the lower of
+    /// ours and the peer's.
+    pub negotiated_version: Version,
+    /// The services advertised by the peer.
+    pub services: PeerServices,
+    /// The peer's user agent.
+    pub user_agent: String,
+    /// The height of the peer's best chain when it connected.
+    pub start_height: block::Height,
+    /// Whether the peer asked us to relay unconfirmed transactions.
+    pub relay: bool,
+    /// When the handshake finished.
+    pub connected_at: DateTime<Utc>,
+    /// The number of our requests that the peer has not answered yet.
+    ///
+    /// Each connection handles one request at a time, so this is at most one.
+    pub in_flight_requests: usize,
+    /// When we last sent the peer one of our requests.
+    pub last_request: Option<DateTime<Utc>>,
+    /// When the peer last finished answering one of our requests.
+    pub last_response: Option<DateTime<Utc>>,
+    /// The number of our requests that the peer failed to answer in time.
+    pub timed_out_requests: usize,
+    /// The lowest round-trip time of the peer's heartbeat pings, if it has
+    /// answered any.
+    pub min_ping: Option<Duration>,
+}
+
+/// The total bytes sent to and received from all peers, since the node
+/// started.
+///
+/// Clones share the same totals, so connections can count their bytes
+/// without locking [`ConnectedPeers`].
+#[derive(Clone, Debug, Default)]
+pub struct ByteCounts {
+    sent: Arc<AtomicU64>,
+    received: Arc<AtomicU64>,
+}
+
+impl ByteCounts {
+    /// Returns the total bytes sent, including message headers.
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    /// Returns the total bytes received, including message headers.
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn add_sent(&self, bytes: usize) {
+        self.sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_received(&self, bytes: usize) {
+        self.received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+/// Identifies one connection, so a closing connection can't change the
+/// entry of a newer connection to the same address.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct ConnectionId(u64);
+
+/// The peers we are currently connected to.
+///
+/// Peers are added when their handshake finishes, and removed when their
+/// connection closes.
+#[derive(Debug, Default)]
+pub struct ConnectedPeers {
+    by_addr: HashMap<SocketAddr, PeerInfo>,
+    /// The connection that owns each entry.
+    ids: HashMap<SocketAddr, ConnectionId>,
+    next_id: u64,
+    /// Signals that close each connection, taken when it is evicted.
+    evict_txs: HashMap<SocketAddr, oneshot::Sender<()>>,
+    /// The bytes sent and received by every connection, including closed
+    /// ones.
+    byte_counts: ByteCounts,
+}
+
+impl ConnectedPeers {
+    /// Construct an empty `ConnectedPeers`.
+    pub fn new() -> ConnectedPeers {
+        ConnectedPeers::default()
+    }
+
+    /// Returns the total bytes sent to and received from all peers.
+    pub fn byte_counts(&self) -> ByteCounts {
+        self.byte_counts.clone()
+    }
+
+    /// Return an iterator over the connected peers, in arbitrary order.
+    pub fn peers(&self) -> impl Iterator<Item = &PeerInfo> {
+        self.by_addr.values()
+    }
+
+    /// Get the information for the peer at `addr`, if we are connected to it.
+    pub fn get(&self, addr: &SocketAddr) -> Option<&PeerInfo> {
+        self.by_addr.get(addr)
+    }
+
+    /// Return the number of connected peers.
+    pub fn len(&self) -> usize {
+        self.by_addr.len()
+    }
+
+    /// Return true if we are not connected to any peers.
+    pub fn is_empty(&self) -> bool {
+        self.by_addr.is_empty()
+    }
+
+    /// Return the number of peers that connected to us.
+    pub fn inbound_count(&self) -> usize {
+        self.len() - self.outbound_count()
+    }
+
+    /// Return the number of peers that we connected to.
+    pub fn outbound_count(&self) -> usize {
+        self.by_addr
+            .values()
+            .filter(|info| info.direction == Direction::Outbound)
+            .count()
+    }
+
+    /// Returns an estimate of the height of the network's best chain at
+    /// `now`, or `None` if we aren't connected to any peers.
+    ///
+    /// Each peer's start height is advanced by the number of `network`
+    /// blocks that should have been mined since it connected. The median
+    /// estimate is used, so a few peers with wrong heights don't move it
+    /// much.
+    pub fn estimated_tip_height(
+        &self,
+        network: Network,
+        now: DateTime<Utc>,
+    ) -> Option<block::Height> {
+        let mut estimates: Vec<u32> = self
+            .by_addr
+            .values()
+            .map(|info| {
+                let spacing = NetworkUpgrade::target_spacing_for_height(network, info.start_height);
+                let elapsed = now.signed_duration_since(info.connected_at);
+                let mined = (elapsed.num_seconds() / spacing.num_seconds()).max(0);
+                info.start_height.0.saturating_add(mined as u32)
+            })
+            .collect();
+        if estimates.is_empty() {
+            return None;
+        }
+
+        estimates.sort_unstable();
+        Some(block::Height(estimates[estimates.len() / 2]))
+    }
+
+    /// Add a connected peer, whose connection closes when `evict_tx` fires,
+    /// and return the ID that the connection uses to update its entry.
+    ///
+    /// Replaces any older connection to the same address.
+    pub(crate) fn insert(&mut self, info: PeerInfo, evict_tx: oneshot::Sender<()>) -> ConnectionId {
+        let id = ConnectionId(self.next_id);
+        self.next_id += 1;
+
+        self.ids.insert(info.addr, id);
+        self.evict_txs.insert(info.addr, evict_tx);
+        self.by_addr.insert(info.addr, info);
+        id
+    }
+
+    /// Remove the peer at `addr`, if its entry belongs to connection `id`.
+    pub(crate) fn remove(&mut self, addr: &SocketAddr, id: ConnectionId) {
+        if self.ids.get(addr) == Some(&id) {
+            self.ids.remove(addr);
+            self.by_addr.remove(addr);
+            self.evict_txs.remove(addr);
+        }
+    }
+
+    /// Return the inbound peers that have not already been evicted.
+    pub(crate) fn evictable_inbound(&self) -> impl Iterator<Item = &PeerInfo> {
+        let evict_txs = &self.evict_txs;
+        self.by_addr.values().filter(move |info| {
+            info.direction != Direction::Outbound && evict_txs.contains_key(&info.addr)
+        })
+    }
+
+    /// Close the connection to the peer at `addr`.
+    ///
+    /// Returns false if the peer is not connected, or was already evicted.
+    pub(crate) fn evict(&mut self, addr: &SocketAddr) -> bool {
+        match self.evict_txs.remove(addr) {
+            Some(evict_tx) => evict_tx.send(()).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Returns the entry for `addr`, if it belongs to connection `id`.
+    pub(crate) fn get_mut(&mut self, addr: &SocketAddr, id: ConnectionId) -> Option<&mut PeerInfo> {
+        if self.ids.get(addr) != Some(&id) {
+            return None;
+        }
+        self.by_addr.get_mut(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::{Duration as ChronoDuration, TimeZone};
+
+    /// Returns an outbound peer at `addr`, which was at `start_height` when it
+    /// connected at `connected_at`.
+    fn peer(addr: &str, start_height: u32, connected_at: DateTime<Utc>) -> PeerInfo {
+        PeerInfo {
+            addr: addr.parse().unwrap(),
+            direction: Direction::Outbound,
+            version: Version(170_100),
+            negotiated_version: Version(170_100),
+            services: PeerServices::NODE_NETWORK,
+            user_agent: "/test/".to_owned(),
+            start_height: block::Height(start_height),
+            relay: true,
+            connected_at,
+            in_flight_requests: 0,
+            last_request: None,
+            last_response: None,
+            timed_out_requests: 0,
+            min_ping: None,
+        }
+    }
+
+    #[test]
+    fn closed_connections_only_remove_their_own_entry() {
+        let now = Utc::now();
+        let mut peers = ConnectedPeers::new();
+        let (old_tx, _old_rx) = oneshot::channel();
+        let old = peers.insert(peer("192.0.2.1:8233", 1, now), old_tx);
+
+        // The peer reconnects before the old connection finishes closing.
+        let (new_tx, _new_rx) = oneshot::channel();
+        let new = peers.insert(peer("192.0.2.1:8233", 2, now), new_tx);
+        let addr = "192.0.2.1:8233".parse().unwrap();
+
+        assert!(peers.get_mut(&addr, old).is_none());
+        peers.remove(&addr, old);
+        assert_eq!(peers.get(&addr).unwrap().start_height, block::Height(2));
+
+        peers.get_mut(&addr, new).unwrap().in_flight_requests = 1;
+        peers.remove(&addr, new);
+        assert!(peers.is_empty());
+    }
+
+    #[test]
+    fn estimated_tip_height_is_none_without_peers() {
+        let peers = ConnectedPeers::new();
+        assert_eq!(
+            peers.estimated_tip_height(Network::Mainnet, Utc::now()),
+            None
+        );
+    }
+
+    #[test]
+    fn estimated_tip_height_is_the_median() {
+        let now = Utc.timestamp(1_600_000_000, 0);
+        let mut peers = ConnectedPeers::new();
+        // One peer far ahead, and one far behind, don't move the estimate.
+        for (i, &height) in [1_000_000, 1_000_010, 1_000_020, 5_000_000, 10]
+            .iter()
+            .enumerate()
+        {
+            let (evict_tx, _evict_rx) = oneshot::channel();
+            peers.insert(peer(&format!("192.0.2.{}:8233", i), height, now), evict_tx);
+        }
+
+        assert_eq!(
+            peers.estimated_tip_height(Network::Mainnet, now),
+            Some(block::Height(1_000_010))
+        );
+    }
+
+    #[test]
+    fn estimated_tip_height_includes_blocks_since_connecting() {
+        let connected_at = Utc.timestamp(1_600_000_000, 0);
+        let mut peers = ConnectedPeers::new();
+        let (evict_tx, _evict_rx) = oneshot::channel();
+        peers.insert(peer("192.0.2.1:8233", 1_000_000, connected_at), evict_tx);
+
+        // Blocks are mined every 75 seconds after Blossom.
+        let spacing =
+            NetworkUpgrade::target_spacing_for_height(Network::Mainnet, block::Height(1_000_000));
+        assert_eq!(spacing, ChronoDuration::seconds(75));
+
+        let now = connected_at + ChronoDuration::seconds(75 * 10 + 30);
+        assert_eq!(
+            peers.estimated_tip_height(Network::Mainnet, now),
+            Some(block::Height(1_000_010))
+        );
+
+        // Clock skew doesn't make the estimate go backwards.
+        let before = connected_at - ChronoDuration::seconds(75 * 10);
+        assert_eq!(
+            peers.estimated_tip_height(Network::Mainnet, before),
+            Some(block::Height(1_000_000))
+        );
+    }
+}
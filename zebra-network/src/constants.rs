@@ -5,7 +5,7 @@ use std::time::Duration;
 // XXX should these constants be split into protocol also?
 use crate::protocol::external::types::*;
 
-/// The timeout for requests made to a remote peer.
+/// The default timeout for requests made to a remote peer.
 pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// We expect to receive a message from a live peer at least once in this time duration.
@@ -29,13 +29,97 @@ pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
 ///
 /// This is intended to prevent a peer from learning exactly when we recieved
 /// messages from each of our peers.
-pub const TIMESTAMP_TRUNCATION_SECONDS: i64 = 30 * 60;
+pub const TIMESTAMP_TRUNCATION_SECONDS: u32 = 30 * 60;
+
+/// The default maximum payload length for network messages, in bytes.
+///
+/// Frames whose header declares a longer payload are rejected before the
+/// payload is buffered.
+pub const MAX_PROTOCOL_MESSAGE_LEN: usize = zebra_chain::serialization::MAX_PROTOCOL_MESSAGE_LEN;
+
+/// The default maximum payload length for `block` messages, in bytes.
+///
+/// Blocks are the only messages that may legitimately exceed
+/// [`MAX_PROTOCOL_MESSAGE_LEN`], so they get a separate limit.
+pub const MAX_BLOCK_MESSAGE_LEN: usize = 4 * 1024 * 1024;
+
+/// The default maximum number of inventory items in each outbound `getdata`
+/// message.
+///
+/// This matches zcashd's limit on blocks in flight from a single peer.
+pub const GETDATA_BATCH_SIZE: usize = 16;
+
+/// The number of recently sent and received inventory hashes remembered for
+/// each peer.
+///
+/// This is enough for several blocks' worth of transaction advertisements.
+pub const RECENT_INVENTORY_CACHE_SIZE: usize = 5000;
+
+/// The minimum time between connection attempts to a peer that we failed to
+/// connect to.
+pub const MIN_PEER_RECONNECTION_DELAY: Duration = Duration::from_secs(2 * 60);
+
+/// How often the peer set's inventory registry starts a new interval.
+///
+/// Advertisements are remembered for between one and two intervals.
+pub const INVENTORY_ROTATION_INTERVAL: Duration = Duration::from_secs(53);
+
+/// The number of inventory advertisements that can be queued for the peer
+/// set's inventory registry, across all peers.
+pub const INVENTORY_CHANNEL_SIZE: usize = 1000;
+
+/// The number of peer lifecycle events kept for slow subscribers.
+///
+/// Subscribers that fall further behind miss the oldest events.
+pub const PEER_EVENT_CHANNEL_SIZE: usize = 1000;
+
+/// The number of times a failed DNS seeder lookup is retried.
+pub const DNS_SEED_RETRIES: u32 = 3;
+
+/// The delay before the first retry of a failed DNS seeder lookup, which
+/// doubles after each retry.
+pub const DNS_SEED_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// How often we check whether we know about enough peers, and resolve the
+/// DNS seeders again if we don't.
+pub const DNS_SEED_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// The number of network groups whose longest-connected inbound peer is
+/// protected from eviction.
+pub const EVICTION_PROTECTED_NETGROUPS: usize = 4;
+
+/// The number of inbound peers with the lowest ping that are protected from
+/// eviction.
+pub const EVICTION_PROTECTED_LOW_PING: usize = 8;
+
+/// How long the listener waits for an evicted inbound peer's connection to
+/// close, before it gives up and closes the new connection instead.
+pub const EVICTION_CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The number of peers that each `Peers` request is sent to.
+pub const GETADDR_FANOUT: usize = 3;
+
+/// The number of downloaded blocks whose peer is remembered, so invalid
+/// blocks can be reported against the peer that sent them.
+///
+/// This is more than the syncer's maximum lookahead.
+pub const BLOCK_SOURCES_SIZE: usize = 5000;
+
+/// How long a peer's inbound rate limit violations are counted for, before
+/// the count is reset.
+///
+/// A well-behaved peer that occasionally bursts over its limits is dropped
+/// only if it exceeds them too often within one window.
+pub const RATE_LIMIT_VIOLATION_WINDOW: Duration = Duration::from_secs(60);
 
 /// The User-Agent string provided by the node.
 pub const USER_AGENT: &str = "🦓Zebra v2.0.0-alpha.0🦓";
 
 /// The Zcash network protocol version used on mainnet.
-pub const CURRENT_VERSION: Version = Version(170_009);
+///
+/// This must be at least the minimum version for the latest network upgrade
+/// we support, see [`Version::min_for_upgrade`].
+pub const CURRENT_VERSION: Version = Version(170_100);
 
 /// The minimum version supported for peer connections.
 pub const MIN_VERSION: Version = Version(170_009);
@@ -47,6 +131,8 @@ pub mod magics {
     pub const MAINNET: Magic = Magic([0x24, 0xe9, 0x27, 0x64]);
     /// The testnet.
     pub const TESTNET: Magic = Magic([0xfa, 0x1a, 0xf9, 0xbf]);
+    /// The regtest network.
+    pub const REGTEST: Magic = Magic([0xaa, 0xe8, 0x3f, 0x5f]);
 }
 
 #[cfg(test)]
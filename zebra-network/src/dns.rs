@@ -0,0 +1,227 @@
+//! A minimal DNS client, for looking up every address of a DNS seeder
+//! through a SOCKS5 proxy.
+//!
+//! Tor's `RESOLVE` extension only returns one address for each lookup, but
+//! DNS seeders answer with many peers. Instead, we send `A` and `AAAA`
+//! queries over TCP, through the proxy, to a DNS server, as defined in
+//! [RFC 1035]. The local resolver never sees the query.
+//!
+//! [RFC 1035]: https://tools.ietf.org/html/rfc1035
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use rand::Rng;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::socks5;
+
+/// The length of a DNS message header.
+const HEADER_LEN: usize = 12;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+/// Returns every IPv4 and IPv6 address of `host`, by querying `dns_server`
+/// through the SOCKS5 proxy at `proxy`.
+pub(crate) async fn resolve(
+    proxy: SocketAddr,
+    dns_server: SocketAddr,
+    host: &str,
+) -> io::Result<Vec<IpAddr>> {
+    let mut stream = socks5::connect(proxy, dns_server).await?;
+
+    let mut addrs = Vec::new();
+    for &qtype in &[TYPE_A, TYPE_AAAA] {
+        let id = rand::thread_rng().gen();
+        let query = query_message(id, host, qtype)?;
+        // DNS over TCP prefixes each message with its length.
+        stream.write_u16(query.len() as u16).await?;
+        stream.write_all(&query).await?;
+
+        let len = stream.read_u16().await?;
+        let mut response = vec![0u8; len as usize];
+        stream.read_exact(&mut response).await?;
+        addrs.extend(parse_answers(id, &response)?);
+    }
+    Ok(addrs)
+}
+
+/// Returns a recursive query for the `qtype` records of `host`.
+fn query_message(id: u16, host: &str, qtype: u16) -> io::Result<Vec<u8>> {
+    // Recursion desired.
+    let mut message = Vec::new();
+    for field in &[id, 0x0100, 1, 0, 0, 0] {
+        message.extend_from_slice(&field.to_be_bytes());
+    }
+    for label in host.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format_error("hostname has an invalid label"));
+        }
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+    message.extend_from_slice(&qtype.to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(message)
+}
+
+/// Returns the addresses in the `A` and `AAAA` answers of `response`, which
+/// must be the response to the query with `id`.
+///
+/// Other answers, like the `CNAME` records before the addresses, are
+/// skipped.
+fn parse_answers(id: u16, response: &[u8]) -> io::Result<Vec<IpAddr>> {
+    let read_u16 = |at: usize| -> io::Result<u16> {
+        match response.get(at..at + 2) {
+            Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+            None => Err(format_error("response is truncated")),
+        }
+    };
+
+    if read_u16(0)? != id {
+        return Err(format_error("response is for a different query"));
+    }
+    let flags = read_u16(2)?;
+    if flags & 0x8000 == 0 {
+        return Err(format_error("response is a query"));
+    }
+    match flags & 0xf {
+        0 => {}
+        // The name doesn't exist.
+        3 => return Ok(Vec::new()),
+        _ => return Err(format_error("DNS server failed to answer")),
+    }
+    let questions = read_u16(4)?;
+    let answers = read_u16(6)?;
+
+    let mut at = HEADER_LEN;
+    for _ in 0..questions {
+        at = skip_name(response, at)? + 4;
+    }
+
+    let mut addrs = Vec::new();
+    for _ in 0..answers {
+        at = skip_name(response, at)?;
+        let rtype = read_u16(at)?;
+        let rclass = read_u16(at + 2)?;
+        let rdlength = read_u16(at + 8)? as usize;
+        at += 10;
+        let rdata = response
+            .get(at..at + rdlength)
+            .ok_or_else(|| format_error("response is truncated"))?;
+        at += rdlength;
+
+        match (rtype, rclass, rdata.len()) {
+            (TYPE_A, CLASS_IN, 4) => {
+                let mut octets = [0u8; 4];
+                octets.copy_from_slice(rdata);
+                addrs.push(IpAddr::V4(Ipv4Addr::from(octets)));
+            }
+            (TYPE_AAAA, CLASS_IN, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(rdata);
+                addrs.push(IpAddr::V6(Ipv6Addr::from(octets)));
+            }
+            _ => {}
+        }
+    }
+    Ok(addrs)
+}
+
+/// Returns the position after the name at `at` in `message`.
+///
+/// Names end with an empty label, or with a pointer to an earlier name.
+fn skip_name(message: &[u8], mut at: usize) -> io::Result<usize> {
+    loop {
+        let len = *message
+            .get(at)
+            .ok_or_else(|| format_error("response is truncated"))?;
+        match len {
+            0 => return Ok(at + 1),
+            len if len & 0xc0 == 0xc0 => return Ok(at + 2),
+            len if len <= 63 => at += 1 + len as usize,
+            _ => return Err(format_error("response has an invalid label")),
+        }
+    }
+}
+
+fn format_error(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a response to `query` with an answer for each of `rdatas`,
+    /// with their record type.
+    fn response_message(query: &[u8], rdatas: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut response = query.to_vec();
+        // A response, with recursion available.
+        response[2] = 0x81;
+        response[3] = 0x80;
+        response[7] = rdatas.len() as u8;
+        for (rtype, rdata) in rdatas {
+            // A pointer to the question's name.
+            response.extend_from_slice(&[0xc0, HEADER_LEN as u8]);
+            response.extend_from_slice(&rtype.to_be_bytes());
+            response.extend_from_slice(&CLASS_IN.to_be_bytes());
+            response.extend_from_slice(&60u32.to_be_bytes());
+            response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            response.extend_from_slice(rdata);
+        }
+        response
+    }
+
+    #[test]
+    fn every_address_is_returned() {
+        let query = query_message(0x1234, "dnsseed.example.com.", TYPE_A).unwrap();
+        assert_eq!(&query[HEADER_LEN..HEADER_LEN + 8], b"\x07dnsseed");
+
+        // The seeder's name is an alias, which is skipped.
+        let alias: &[u8] = &[4, b's', b'e', b'e', b'd', 0xc0, HEADER_LEN as u8];
+        let response = response_message(
+            &query,
+            &[
+                (5, alias),
+                (TYPE_A, &[192, 0, 2, 1]),
+                (TYPE_A, &[192, 0, 2, 2]),
+                (TYPE_A, &[192, 0, 2, 3]),
+            ],
+        );
+        assert_eq!(
+            parse_answers(0x1234, &response).unwrap(),
+            vec![
+                IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+                IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)),
+                IpAddr::V4(Ipv4Addr::new(192, 0, 2, 3)),
+            ]
+        );
+
+        let query = query_message(0x4321, "dnsseed.example.com", TYPE_AAAA).unwrap();
+        let response = response_message(&query, &[(TYPE_AAAA, &Ipv6Addr::LOCALHOST.octets())]);
+        assert_eq!(
+            parse_answers(0x4321, &response).unwrap(),
+            vec![IpAddr::V6(Ipv6Addr::LOCALHOST)]
+        );
+    }
+
+    #[test]
+    fn bad_responses_are_errors() {
+        let query = query_message(0x1234, "dnsseed.example.com", TYPE_A).unwrap();
+        let response = response_message(&query, &[(TYPE_A, &[192, 0, 2, 1])]);
+
+        assert!(parse_answers(0x9999, &response).is_err());
+        assert!(parse_answers(0x1234, &query).is_err());
+        assert!(parse_answers(0x1234, &response[..response.len() - 1]).is_err());
+        assert!(query_message(0x1234, "dnsseed..example.com", TYPE_A).is_err());
+    }
+}
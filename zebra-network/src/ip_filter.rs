@@ -0,0 +1,260 @@
+//! Allow and deny lists of IP address ranges, for filtering peers.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, Ipv4Addr},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+/// A range of IP addresses in CIDR notation, like `10.0.0.0/8` or
+/// `2001:db8::/32`.
+///
+/// A bare IP address is parsed as a range containing only that address.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+/// An error parsing an [`IpNetwork`].
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+#[error("invalid IP network {0:?}: expected an IP address or CIDR range")]
+pub struct IpNetworkParseError(String);
+
+impl IpNetwork {
+    /// Returns true if `ip` is in this range.
+    ///
+    /// IPv4-mapped IPv6 addresses are treated as IPv4 addresses.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, normalize(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_matches(&net.octets(), &ip.octets(), self.prefix_len)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_matches(&net.octets(), &ip.octets(), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Converts IPv4-mapped IPv6 addresses, in `::ffff:0:0/96`, to IPv4.
+///
+/// `Ipv6Addr::to_ipv4` also converts IPv4-compatible addresses, like `::1`,
+/// so it isn't used here.
+fn normalize(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.octets() {
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a, b, c, d] => {
+                IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+            }
+            _ => ip,
+        },
+        ip => ip,
+    }
+}
+
+/// Returns true if the first `prefix_len` bits of `a` and `b` are equal.
+fn prefix_matches(a: &[u8], b: &[u8], prefix_len: u8) -> bool {
+    let whole_bytes = usize::from(prefix_len / 8);
+    if a[..whole_bytes] != b[..whole_bytes] {
+        return false;
+    }
+    let rem_bits = prefix_len % 8;
+    if rem_bits == 0 {
+        return true;
+    }
+    let mask = 0xffu8 << (8 - rem_bits);
+    a[whole_bytes] & mask == b[whole_bytes] & mask
+}
+
+impl FromStr for IpNetwork {
+    type Err = IpNetworkParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || IpNetworkParseError(s.to_owned());
+        let mut parts = s.splitn(2, '/');
+        let addr = parts
+            .next()
+            .and_then(|addr| addr.parse::<IpAddr>().ok())
+            .ok_or_else(err)?;
+        let max_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix_len = match parts.next() {
+            Some(len) => len.parse::<u8>().map_err(|_| err())?,
+            None => max_len,
+        };
+        if prefix_len > max_len {
+            return Err(err());
+        }
+        Ok(IpNetwork { addr, prefix_len })
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl Serialize for IpNetwork {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for IpNetwork {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// IP address ranges that operators have banned at runtime, and when each
+/// ban expires.
+///
+/// Unlike the configured deny list, bans don't persist across restarts.
+#[derive(Clone, Debug, Default)]
+pub struct BanList {
+    bans: Arc<Mutex<HashMap<IpNetwork, DateTime<Utc>>>>,
+}
+
+impl BanList {
+    /// Ban `net` until `until`, replacing any existing ban.
+    pub fn ban(&self, net: IpNetwork, until: DateTime<Utc>) {
+        self.bans.lock().unwrap().insert(net, until);
+    }
+
+    /// Remove the ban on `net`, returning false if it wasn't banned.
+    pub fn unban(&self, net: &IpNetwork) -> bool {
+        self.bans.lock().unwrap().remove(net).is_some()
+    }
+
+    /// Remove every ban.
+    pub fn clear(&self) {
+        self.bans.lock().unwrap().clear();
+    }
+
+    /// Returns the unexpired bans, and when they expire.
+    pub fn bans(&self) -> Vec<(IpNetwork, DateTime<Utc>)> {
+        let mut bans = self.bans.lock().unwrap();
+        let now = Utc::now();
+        bans.retain(|_, until| *until > now);
+        bans.iter().map(|(net, until)| (*net, *until)).collect()
+    }
+
+    /// Returns true if `ip` is in an unexpired ban.
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        let now = Utc::now();
+        self.bans
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(net, until)| *until > now && net.contains(ip))
+    }
+}
+
+/// Decides whether we may connect to, or accept connections from, a peer's
+/// IP address.
+///
+/// Addresses in a denied range, or a banned range, are always rejected. If
+/// there are any allowed ranges, addresses outside them are also rejected.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct IpFilter {
+    allowed: Vec<IpNetwork>,
+    denied: Vec<IpNetwork>,
+    bans: BanList,
+}
+
+impl IpFilter {
+    pub(crate) fn new(allowed: Vec<IpNetwork>, denied: Vec<IpNetwork>, bans: BanList) -> Self {
+        IpFilter {
+            allowed,
+            denied,
+            bans,
+        }
+    }
+
+    /// Returns true if peers at `ip` are allowed.
+    pub(crate) fn is_allowed(&self, ip: IpAddr) -> bool {
+        if self.denied.iter().any(|net| net.contains(ip)) || self.bans.is_banned(ip) {
+            return false;
+        }
+        self.allowed.is_empty() || self.allowed.iter().any(|net| net.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_and_contains() {
+        let net: IpNetwork = "10.1.0.0/15".parse().unwrap();
+        assert!(net.contains(ip("10.0.255.1")));
+        assert!(net.contains(ip("10.1.2.3")));
+        assert!(!net.contains(ip("10.2.0.0")));
+        assert!(net.contains(IpAddr::V6(Ipv4Addr::new(10, 1, 0, 1).to_ipv6_mapped())));
+        assert!(!net.contains(ip("2001:db8::1")));
+
+        let host: IpNetwork = "2001:db8::1".parse().unwrap();
+        assert!(host.contains(ip("2001:db8::1")));
+        assert!(!host.contains(ip("2001:db8::2")));
+        assert_eq!(host.to_string(), "2001:db8::1/128");
+
+        let all: IpNetwork = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains(ip("203.0.113.6")));
+        // IPv4-compatible addresses are IPv6, so `::1` isn't `0.0.0.1`.
+        assert!(!all.contains(ip("::1")));
+
+        let loopback: IpNetwork = "::1".parse().unwrap();
+        assert!(loopback.contains(ip("::1")));
+
+        assert!("10.0.0.0/33".parse::<IpNetwork>().is_err());
+        assert!("example.com/8".parse::<IpNetwork>().is_err());
+    }
+
+    #[test]
+    fn deny_overrides_allow() {
+        let filter = IpFilter::new(
+            vec!["10.0.0.0/8".parse().unwrap()],
+            vec!["10.66.0.0/16".parse().unwrap()],
+            BanList::default(),
+        );
+        assert!(filter.is_allowed(ip("10.1.1.1")));
+        assert!(!filter.is_allowed(ip("10.66.1.1")));
+        assert!(!filter.is_allowed(ip("192.0.2.1")));
+
+        assert!(IpFilter::default().is_allowed(ip("192.0.2.1")));
+    }
+
+    #[test]
+    fn bans_expire() {
+        let bans = BanList::default();
+        let filter = IpFilter::new(Vec::new(), Vec::new(), bans.clone());
+        let net: IpNetwork = "192.0.2.0/24".parse().unwrap();
+
+        bans.ban(net, Utc::now() + chrono::Duration::hours(1));
+        assert!(!filter.is_allowed(ip("192.0.2.1")));
+        assert!(filter.is_allowed(ip("198.51.100.1")));
+        assert_eq!(bans.bans().len(), 1);
+
+        bans.ban(net, Utc::now() - chrono::Duration::seconds(1));
+        assert!(filter.is_allowed(ip("192.0.2.1")));
+        assert!(bans.bans().is_empty());
+        assert!(!bans.unban(&net));
+    }
+}
@@ -0,0 +1,141 @@
+//! Opening a single connection that is isolated from the rest of the node.
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use futures::{channel::mpsc, future};
+use tokio::sync::broadcast;
+use tower::{discover::Change, Service, ServiceExt};
+
+use zebra_chain::Network;
+
+use crate::{
+    peer, types::PeerServices, BestTipHeight, BoxedStdError, Config, ConnectedPeers, Request,
+    Response,
+};
+
+/// Open a peer connection to `addr` on `network` that shares no state with
+/// the rest of the node, and return a client for sending it requests.
+///
+/// If `proxy` is set, the connection is opened through that SOCKS5 proxy,
+/// such as a local Tor daemon.
+///
+/// The connection is meant for one-off tasks, like a wallet submitting a
+/// transaction, that should not be linkable to this node. So it advertises no
+/// services, an empty user agent, and a start height of zero, and it uses a
+/// fresh handshake nonce. Its address is not added to any address book, and
+/// it ignores requests from the remote peer.
+pub async fn connect_isolated(
+    network: Network,
+    addr: SocketAddr,
+    proxy: Option<SocketAddr>,
+) -> Result<peer::Client, BoxedStdError> {
+    let config = Config {
+        network,
+        proxy,
+        user_agent: String::new(),
+        advertised_services: PeerServices::empty(),
+        relay: false,
+        serve_compact_filters: false,
+        ..Config::default()
+    };
+
+    // The receivers are dropped, so the connection's address book and
+    // inventory updates go nowhere.
+    let (timestamp_collector, _) = mpsc::channel(1);
+    let (inv_collector, _) = mpsc::channel(1);
+    let handshake = peer::Handshake::new(
+        config.clone(),
+        tower::service_fn(|_req: Request| future::ok::<_, BoxedStdError>(Response::Nil)),
+        timestamp_collector,
+        BestTipHeight::default(),
+        inv_collector,
+        Arc::new(Mutex::new(ConnectedPeers::new())),
+        broadcast::channel(1).0,
+    );
+
+    let mut connector = peer::Connector::new(handshake, proxy, config.connect_timeout);
+    connector.ready_and().await?;
+    match connector.call(addr).await? {
+        Change::Insert(_, client) => Ok(client),
+        Change::Remove(_) => unreachable!("the connector only inserts peers"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use futures::{SinkExt, StreamExt};
+    use tokio::{net::TcpListener, runtime::Runtime};
+    use tokio_util::codec::Framed;
+
+    use zebra_chain::block;
+
+    use crate::{
+        constants,
+        protocol::external::{types::Nonce, Codec, Message},
+    };
+
+    use super::*;
+
+    #[test]
+    fn isolated_handshake_advertises_nothing() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            let fake_peer = tokio::spawn(async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = Framed::new(
+                    stream,
+                    Codec::builder().for_network(Network::Mainnet).finish(),
+                );
+
+                match stream.next().await.unwrap().unwrap() {
+                    Message::Version {
+                        services,
+                        user_agent,
+                        start_height,
+                        relay,
+                        ..
+                    } => {
+                        assert_eq!(services, PeerServices::empty());
+                        assert_eq!(user_agent, "");
+                        assert_eq!(start_height, block::Height(0));
+                        assert!(!relay);
+                    }
+                    msg => panic!("expected a version message, got {:?}", msg),
+                }
+
+                stream
+                    .send(Message::Version {
+                        version: constants::CURRENT_VERSION,
+                        services: PeerServices::NODE_NETWORK,
+                        timestamp: Utc::now(),
+                        address_recv: (PeerServices::empty(), addr),
+                        address_from: (PeerServices::NODE_NETWORK, addr),
+                        nonce: Nonce::default(),
+                        user_agent: "/fake-peer/".to_owned(),
+                        start_height: block::Height(0),
+                        relay: true,
+                    })
+                    .await
+                    .unwrap();
+                match stream.next().await.unwrap().unwrap() {
+                    Message::Verack => {}
+                    msg => panic!("expected a verack message, got {:?}", msg),
+                }
+                stream.send(Message::Verack).await.unwrap();
+                stream
+            });
+
+            connect_isolated(Network::Mainnet, addr, None)
+                .await
+                .expect("handshake with the fake peer succeeds");
+            let _stream = fake_peer.await.unwrap();
+        });
+    }
+}
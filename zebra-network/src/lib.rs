@@ -50,25 +50,44 @@ extern crate bitflags;
 pub type BoxedStdError = Box<dyn std::error::Error + Send + Sync + 'static>;
 
 mod address_book;
+mod best_tip_height;
+mod block_sources;
 mod config;
+mod connected_peers;
 mod constants;
+mod dns;
+mod ip_filter;
+mod isolated;
 mod meta_addr;
 mod peer;
+mod peer_control;
+mod peer_event;
 mod peer_set;
 mod policies;
 mod protocol;
+mod socks5;
 mod timestamp_collector;
 
 pub use crate::{
     address_book::AddressBook,
-    config::Config,
-    peer_set::init,
-    policies::{RetryErrors, RetryLimit},
+    best_tip_height::BestTipHeight,
+    config::{Config, RateLimit},
+    connected_peers::{ByteCounts, ConnectedPeers, Direction, PeerInfo},
+    ip_filter::{BanList, IpNetwork, IpNetworkParseError},
+    isolated::connect_isolated,
+    peer::{Client, Misbehavior},
+    peer_control::PeerControl,
+    peer_event::PeerEvent,
+    peer_set::{init, RoutingError, Shutdown},
+    policies::{RetryErrors, RetryLimit, RetryPeerErrors},
     protocol::external::codec::Builder,
     protocol::internal::{Request, Response},
 };
 
 /// Types used in the definition of [`Request`] and [`Response`] messages.
 pub mod types {
-    pub use crate::{meta_addr::MetaAddr, protocol::types::PeerServices};
+    pub use crate::{
+        meta_addr::MetaAddr,
+        protocol::types::{PeerServices, Version},
+    };
 }
@@ -2,30 +2,28 @@
 
 use std::{
     cmp::{Ord, Ordering},
-    io::{Read, Write},
     net::SocketAddr,
 };
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use chrono::{DateTime, TimeZone, Utc};
-
 use zebra_chain::serialization::{
-    ReadZcashExt, SerializationError, WriteZcashExt, ZcashDeserialize, ZcashSerialize,
+    DateTime32, TrustedPreallocate, ZcashDeserialize, ZcashSerialize, MAX_PROTOCOL_MESSAGE_LEN,
 };
 
 use crate::protocol::types::PeerServices;
 
 /// An address with metadata on its advertised services and last-seen time.
 ///
+/// The fields are in serialization order.
+///
 /// [Bitcoin reference](https://en.bitcoin.it/wiki/Protocol_documentation#Network_address)
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ZcashSerialize, ZcashDeserialize)]
 pub struct MetaAddr {
-    /// The peer's address.
-    pub addr: SocketAddr,
+    /// When the peer was last seen.
+    pub last_seen: DateTime32,
     /// The services advertised by the peer.
     pub services: PeerServices,
-    /// When the peer was last seen.
-    pub last_seen: DateTime<Utc>,
+    /// The peer's address.
+    pub addr: SocketAddr,
 }
 
 impl MetaAddr {
@@ -33,11 +31,20 @@ impl MetaAddr {
     pub fn sanitize(mut self) -> MetaAddr {
         let interval = crate::constants::TIMESTAMP_TRUNCATION_SECONDS;
         let ts = self.last_seen.timestamp();
-        self.last_seen = Utc.timestamp(ts - ts.rem_euclid(interval), 0);
+        self.last_seen = DateTime32::from(ts - ts % interval);
         self
     }
 }
 
+/// The size of a serialized `MetaAddr`, in bytes.
+const META_ADDR_SIZE: usize = 4 + 8 + 16 + 2;
+
+impl TrustedPreallocate for MetaAddr {
+    fn max_allocation() -> u64 {
+        (MAX_PROTOCOL_MESSAGE_LEN / META_ADDR_SIZE) as u64
+    }
+}
+
 impl Ord for MetaAddr {
     /// `MetaAddr`s are sorted newest-first, and then in an arbitrary
     /// but determinate total order.
@@ -66,26 +73,6 @@ impl PartialOrd for MetaAddr {
     }
 }
 
-impl ZcashSerialize for MetaAddr {
-    fn zcash_serialize<W: Write>(&self, mut writer: W) -> Result<(), std::io::Error> {
-        writer.write_u32::<LittleEndian>(self.last_seen.timestamp() as u32)?;
-        writer.write_u64::<LittleEndian>(self.services.bits())?;
-        writer.write_socket_addr(self.addr)?;
-        Ok(())
-    }
-}
-
-impl ZcashDeserialize for MetaAddr {
-    fn zcash_deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
-        Ok(MetaAddr {
-            last_seen: Utc.timestamp(reader.read_u32::<LittleEndian>()? as i64, 0),
-            // Discard unknown service bits.
-            services: PeerServices::from_bits_truncate(reader.read_u64::<LittleEndian>()?),
-            addr: reader.read_socket_addr()?,
-        })
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -95,7 +82,7 @@ mod tests {
         let entry = MetaAddr {
             services: PeerServices::default(),
             addr: "127.0.0.1:8233".parse().unwrap(),
-            last_seen: Utc.timestamp(1_573_680_222, 0),
+            last_seen: DateTime32::from(1_573_680_222),
         }
         .sanitize();
         // We want the sanitized timestamp to be a multiple of the truncation interval.
@@ -10,6 +10,10 @@ mod connector;
 mod error;
 /// Performs peer handshakes.
 mod handshake;
+/// A cache of recently exchanged inventory hashes.
+mod inventory_cache;
+/// Rate limits for inbound peer messages.
+mod rate_limit;
 
 use client::ClientRequest;
 use error::ErrorSlot;
@@ -17,5 +21,5 @@ use error::ErrorSlot;
 pub use client::Client;
 pub use connection::Connection;
 pub use connector::Connector;
-pub use error::{HandshakeError, PeerError, SharedPeerError};
+pub use error::{HandshakeError, Misbehavior, PeerError, SharedPeerError};
 pub use handshake::Handshake;
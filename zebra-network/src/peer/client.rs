@@ -10,7 +10,10 @@ use futures::{
 };
 use tower::Service;
 
-use crate::protocol::internal::{Request, Response};
+use crate::{
+    peer_set::ConnectionTracker,
+    protocol::internal::{Request, Response},
+};
 
 use super::{ErrorSlot, SharedPeerError};
 
@@ -19,6 +22,9 @@ pub struct Client {
     pub(super) span: tracing::Span,
     pub(super) server_tx: mpsc::Sender<ClientRequest>,
     pub(super) error_slot: ErrorSlot,
+    /// Counts this connection against a connection limit until the client
+    /// is dropped.
+    pub(super) connection_tracker: Option<ConnectionTracker>,
 }
 
 /// A message from the `peer::Client` to the `peer::Server`, containing both a
@@ -32,6 +38,15 @@ pub(super) struct ClientRequest(
     pub(super) oneshot::Sender<Result<Response, SharedPeerError>>,
 );
 
+impl Client {
+    /// Count this connection with `tracker` for as long as the client is
+    /// alive.
+    pub(crate) fn with_connection_tracker(mut self, tracker: ConnectionTracker) -> Self {
+        self.connection_tracker = Some(tracker);
+        self
+    }
+}
+
 impl Service<Request> for Client {
     type Response = Response;
     type Error = SharedPeerError;
@@ -1,41 +1,83 @@
-use std::collections::HashSet;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use chrono::Utc;
 use futures::{
     channel::{mpsc, oneshot},
     future::{self, Either},
     prelude::*,
     stream::Stream,
 };
-use tokio::time::{delay_for, Delay};
+use tokio::{
+    sync::broadcast,
+    time::{delay_for, Delay},
+};
 use tower::Service;
 
 use zebra_chain::{
-    block::{Block, BlockHeaderHash},
+    block::{
+        self,
+        filter::{BlockFilter, BASIC_FILTER_TYPE},
+        Block,
+    },
     serialization::SerializationError,
+    transaction::{Transaction, UnminedTxId},
+    Network,
 };
 
 use crate::{
-    constants,
+    connected_peers::ConnectionId,
     protocol::{
-        external::{types::Nonce, InventoryHash, Message},
+        external::{
+            types::{Nonce, Version},
+            InventoryHash, Message,
+        },
         internal::{Request, Response},
     },
-    BoxedStdError,
+    BestTipHeight, BoxedStdError, ConnectedPeers, PeerEvent, PeerInfo,
 };
 
-use super::{ClientRequest, ErrorSlot, PeerError, SharedPeerError};
+use super::{
+    inventory_cache::RecentInventory,
+    rate_limit::{self, InboundRateLimiter},
+    ClientRequest, ErrorSlot, Misbehavior, PeerError, SharedPeerError,
+};
 
 pub(super) enum Handler {
     /// Indicates that the handler has finished processing the request.
     Finished(Result<Response, SharedPeerError>),
-    Ping(Nonce),
+    /// Waits for the `Pong` matching the nonce of a `Ping` sent at the
+    /// given time.
+    Ping(Nonce, Instant),
     GetPeers,
+    /// Collects blocks from one or more pipelined `getdata` batches.
+    ///
+    /// Blocks can arrive in any order, so they are stored by hash until
+    /// every block has arrived, then returned in the order they were
+    /// requested.
     GetBlocksByHash {
-        hashes: HashSet<BlockHeaderHash>,
-        blocks: Vec<Arc<Block>>,
+        order: Vec<block::Hash>,
+        pending: HashSet<block::Hash>,
+        blocks: HashMap<block::Hash, Arc<Block>>,
     },
     FindBlocks,
+    FindHeaders,
+    /// Like `GetBlocksByHash`, but for transactions.
+    TransactionsByHash {
+        order: Vec<UnminedTxId>,
+        pending: HashSet<UnminedTxId>,
+        transactions: HashMap<UnminedTxId, Arc<Transaction>>,
+    },
+    MempoolTransactions,
+    /// Collects `cfilter` messages until the filter for `stop` arrives.
+    CompactFilters {
+        stop: block::Hash,
+        filters: Vec<(block::Hash, BlockFilter)>,
+    },
+    CompactFilterHeaders,
+    CompactFilterCheckpoints,
 }
 
 impl Handler {
@@ -56,33 +98,69 @@ impl Handler {
         // XXX can this be avoided?
         let tmp_state = std::mem::replace(self, Finished(Ok(Response::Nil)));
         *self = match (tmp_state, msg) {
-            (Ping(req_nonce), Message::Pong(rsp_nonce)) => {
+            (Ping(req_nonce, sent_at), Message::Pong(rsp_nonce)) => {
                 if req_nonce == rsp_nonce {
-                    Finished(Ok(Response::Nil))
+                    Finished(Ok(Response::Pong(sent_at.elapsed())))
                 } else {
-                    Ping(req_nonce)
+                    Ping(req_nonce, sent_at)
                 }
             }
             (GetPeers, Message::Addr(addrs)) => Finished(Ok(Response::Peers(addrs))),
             (
                 GetBlocksByHash {
-                    mut hashes,
+                    order,
+                    mut pending,
                     mut blocks,
                 },
                 Message::Block(block),
             ) => {
-                if hashes.remove(&BlockHeaderHash::from(block.as_ref())) {
-                    blocks.push(block);
-                    if hashes.is_empty() {
-                        Finished(Ok(Response::Blocks(blocks)))
+                let hash = block::Hash::from(block.as_ref());
+                if pending.remove(&hash) {
+                    blocks.insert(hash, block);
+                    if pending.is_empty() {
+                        Finished(Ok(Response::Blocks(
+                            order
+                                .iter()
+                                .filter_map(|hash| blocks.remove(hash))
+                                .collect(),
+                        )))
                     } else {
-                        GetBlocksByHash { hashes, blocks }
+                        GetBlocksByHash {
+                            order,
+                            pending,
+                            blocks,
+                        }
                     }
                 } else {
                     Finished(Err(Arc::new(PeerError::WrongBlock).into()))
                 }
             }
-            (FindBlocks, Message::Inv(inv_hashes)) => Finished(Ok(Response::BlockHeaderHashes(
+            (
+                GetBlocksByHash {
+                    order,
+                    pending,
+                    blocks,
+                },
+                Message::NotFound(items),
+            ) => {
+                let missing: Vec<InventoryHash> = items
+                    .into_iter()
+                    .filter(|item| match item {
+                        InventoryHash::Block(hash) => pending.contains(hash),
+                        _ => false,
+                    })
+                    .collect();
+                if missing.is_empty() {
+                    GetBlocksByHash {
+                        order,
+                        pending,
+                        blocks,
+                    }
+                } else {
+                    Finished(Err(Arc::new(PeerError::NotFound(missing)).into()))
+                }
+            }
+            (FindBlocks, Message::Inv(inv_hashes)) => Finished(Ok(Response::BlockHashes(
                 inv_hashes
                     .into_iter()
                     .filter_map(|inv| match inv {
@@ -91,6 +169,110 @@ impl Handler {
                     })
                     .collect(),
             ))),
+            (FindHeaders, Message::Headers(headers)) => {
+                Finished(Ok(Response::BlockHeaders(headers)))
+            }
+            (
+                TransactionsByHash {
+                    order,
+                    mut pending,
+                    mut transactions,
+                },
+                Message::Tx(transaction),
+            ) => {
+                let id = transaction.unmined_id();
+                if pending.remove(&id) {
+                    transactions.insert(id, transaction);
+                    if pending.is_empty() {
+                        Finished(Ok(Response::Transactions(
+                            order
+                                .iter()
+                                .filter_map(|id| transactions.remove(id))
+                                .collect(),
+                        )))
+                    } else {
+                        TransactionsByHash {
+                            order,
+                            pending,
+                            transactions,
+                        }
+                    }
+                } else {
+                    Finished(Err(Arc::new(PeerError::WrongTransaction).into()))
+                }
+            }
+            (
+                TransactionsByHash {
+                    order,
+                    pending,
+                    transactions,
+                },
+                Message::NotFound(items),
+            ) => {
+                let missing: Vec<InventoryHash> = items
+                    .into_iter()
+                    .filter(|item| match item.unmined_tx_id() {
+                        Some(id) => pending.contains(&id),
+                        None => false,
+                    })
+                    .collect();
+                if missing.is_empty() {
+                    TransactionsByHash {
+                        order,
+                        pending,
+                        transactions,
+                    }
+                } else {
+                    Finished(Err(Arc::new(PeerError::NotFound(missing)).into()))
+                }
+            }
+            (MempoolTransactions, Message::Inv(inv_hashes)) => {
+                Finished(Ok(Response::TransactionHashes(
+                    inv_hashes
+                        .iter()
+                        .filter_map(|inv| inv.unmined_tx_id())
+                        .collect(),
+                )))
+            }
+            (
+                CompactFilters { stop, mut filters },
+                Message::CFilter {
+                    filter_type: BASIC_FILTER_TYPE,
+                    block_hash,
+                    filter,
+                },
+            ) => {
+                filters.push((block_hash, filter));
+                if block_hash == stop {
+                    Finished(Ok(Response::CompactFilters(filters)))
+                } else {
+                    CompactFilters { stop, filters }
+                }
+            }
+            (
+                CompactFilterHeaders,
+                Message::CFHeaders {
+                    filter_type: BASIC_FILTER_TYPE,
+                    stop_hash,
+                    previous_filter_header,
+                    filter_hashes,
+                },
+            ) => Finished(Ok(Response::CompactFilterHeaders {
+                stop: stop_hash,
+                previous: previous_filter_header,
+                hashes: filter_hashes,
+            })),
+            (
+                CompactFilterCheckpoints,
+                Message::CFCheckpt {
+                    filter_type: BASIC_FILTER_TYPE,
+                    stop_hash,
+                    filter_headers,
+                },
+            ) => Finished(Ok(Response::CompactFilterCheckpoints {
+                stop: stop_hash,
+                headers: filter_headers,
+            })),
             // By default, messages are not responses.
             (state, msg) => {
                 ignored_msg = Some(msg);
@@ -118,6 +300,39 @@ pub struct Connection<S, Tx> {
     /// State so that we can move the future out of it independently of
     /// other state handling.
     pub(super) request_timer: Option<Delay>,
+    /// The time the peer has to answer each request, or each batch of a
+    /// large inventory request.
+    pub(super) request_timeout: Duration,
+    /// The number of consecutive heartbeat pings that timed out.
+    pub(super) missed_pings: usize,
+    /// The number of consecutive missed pings that fails the connection.
+    pub(super) max_missed_pings: usize,
+    /// The maximum number of inventory items sent in each `getdata` message.
+    pub(super) getdata_batch_size: usize,
+    /// Whether to pass BIP157 filter requests from the peer to the inbound
+    /// service.
+    pub(super) serve_compact_filters: bool,
+    /// Rate limits for messages the peer sends us unprompted.
+    pub(super) rate_limiter: InboundRateLimiter,
+    /// The inventory hashes recently sent to or received from the peer.
+    pub(super) recent_inventory: RecentInventory,
+    /// The network this connection is on.
+    pub(super) network: Network,
+    /// The protocol version the remote peer sent in its `version` message.
+    pub(super) remote_version: Version,
+    /// The height of our best chain tip, used to detect obsolete peers
+    /// after a network upgrade activates.
+    pub(super) best_tip_height: BestTipHeight,
+    /// The remote peer's address.
+    pub(super) addr: SocketAddr,
+    /// The connected peers, where this connection's request state is shown.
+    pub(super) connected_peers: Arc<Mutex<ConnectedPeers>>,
+    /// This connection's entry in `connected_peers`.
+    pub(super) connection_id: ConnectionId,
+    /// Fires when the connection is evicted to make room for another peer.
+    pub(super) evict_rx: future::Fuse<oneshot::Receiver<()>>,
+    /// The channel for this connection's lifecycle events.
+    pub(super) events: broadcast::Sender<PeerEvent>,
     pub(super) svc: S,
     pub(super) client_rx: mpsc::Receiver<ClientRequest>,
     /// A slot for an error shared between the Connection and the Client that uses it.
@@ -157,21 +372,40 @@ where
         // If there is a pending request, we wait only on an incoming peer message, and
         // check whether it can be interpreted as a response to the pending request.
         loop {
+            let in_flight_requests = matches!(self.state, State::AwaitingResponse(..)) as usize;
+            update_peer_info(
+                &self.connected_peers,
+                &self.addr,
+                self.connection_id,
+                |info| {
+                    info.in_flight_requests = in_flight_requests;
+                },
+            );
+            if !matches!(self.state, State::Failed) && self.is_obsolete() {
+                self.fail_with(PeerError::ObsoleteVersion(self.remote_version));
+            }
+
             match self.state {
                 State::AwaitingRequest => {
                     trace!("awaiting client request or peer message");
-                    match future::select(peer_rx.next(), self.client_rx.next()).await {
-                        Either::Left((None, _)) => {
+                    let next = future::select(peer_rx.next(), self.client_rx.next());
+                    match future::select(next, &mut self.evict_rx).await {
+                        Either::Left((Either::Left((None, _)), _)) => {
                             self.fail_with(PeerError::ConnectionClosed);
                         }
-                        Either::Left((Some(Err(e)), _)) => self.fail_with(e.into()),
-                        Either::Left((Some(Ok(msg)), _)) => {
+                        Either::Left((Either::Left((Some(Err(e)), _)), _)) => {
+                            self.fail_with(e.into())
+                        }
+                        Either::Left((Either::Left((Some(Ok(msg)), _)), _)) => {
                             self.handle_message_as_request(msg).await
                         }
-                        Either::Right((None, _)) => {
+                        Either::Left((Either::Right((None, _)), _)) => {
                             self.fail_with(PeerError::DeadClient);
                         }
-                        Either::Right((Some(req), _)) => self.handle_client_request(req).await,
+                        Either::Left((Either::Right((Some(req), _)), _)) => {
+                            self.handle_client_request(req).await
+                        }
+                        Either::Right(_) => self.fail_with(PeerError::Evicted),
                     }
                 }
                 // We're awaiting a response to a client request,
@@ -182,7 +416,15 @@ where
                         .request_timer
                         .as_mut()
                         .expect("timeout must be set while awaiting response");
-                    match future::select(peer_rx.next(), timer_ref).await {
+                    let next = future::select(peer_rx.next(), timer_ref);
+                    let next = match future::select(next, &mut self.evict_rx).await {
+                        Either::Left((next, _)) => next,
+                        Either::Right(_) => {
+                            self.fail_with(PeerError::Evicted);
+                            continue;
+                        }
+                    };
+                    match next {
                         Either::Left((None, _)) => self.fail_with(PeerError::ConnectionClosed),
                         Either::Left((Some(Err(e)), _)) => self.fail_with(e.into()),
                         Either::Left((Some(Ok(peer_msg)), _timer)) => {
@@ -193,6 +435,8 @@ where
                             // &mut self. This is a sign that we don't properly
                             // factor the state required for inbound and
                             // outbound requests.
+                            let awaiting_ping =
+                                matches!(self.state, State::AwaitingResponse(Handler::Ping(..), _));
                             let request_msg = match self.state {
                                 State::AwaitingResponse(ref mut handler, _) => {
                                     handler.process_message(peer_msg)
@@ -208,7 +452,17 @@ where
                                 // processing messages and update the state.
                                 self.state = match self.state {
                                     State::AwaitingResponse(Handler::Finished(response), tx) => {
+                                        if awaiting_ping {
+                                            // The peer answered, so it is live again.
+                                            self.missed_pings = 0;
+                                        }
                                         let _ = tx.send(response);
+                                        update_peer_info(
+                                            &self.connected_peers,
+                                            &self.addr,
+                                            self.connection_id,
+                                            |info| info.last_response = Some(Utc::now()),
+                                        );
                                         State::AwaitingRequest
                                     }
                                     pending @ State::AwaitingResponse(_, _) => pending,
@@ -219,14 +473,30 @@ where
                         Either::Right(((), _peer_fut)) => {
                             trace!("client request timed out");
                             let e = PeerError::ClientRequestTimeout;
+                            update_peer_info(
+                                &self.connected_peers,
+                                &self.addr,
+                                self.connection_id,
+                                |info| info.timed_out_requests += 1,
+                            );
+                            if let State::AwaitingResponse(Handler::Ping(..), _) = self.state {
+                                self.missed_pings += 1;
+                            }
                             self.state = match self.state {
-                                // Special case: ping timeouts fail the connection.
-                                State::AwaitingResponse(Handler::Ping(_), _) => {
+                                // Special case: too many consecutive ping
+                                // timeouts fail the connection.
+                                State::AwaitingResponse(Handler::Ping(..), _)
+                                    if self.missed_pings >= self.max_missed_pings =>
+                                {
                                     self.fail_with(e);
                                     State::Failed
                                 }
                                 // Other request timeouts fail the request.
                                 State::AwaitingResponse(_, tx) => {
+                                    let _ = self.events.send(PeerEvent::RequestFailed {
+                                        addr: self.addr,
+                                        error: e.to_string(),
+                                    });
                                     let _ = tx.send(Err(Arc::new(e).into()));
                                     State::AwaitingRequest
                                 }
@@ -248,13 +518,39 @@ where
                             // Continue until we've errored all queued reqs
                             continue;
                         }
-                        None => return,
+                        None => {
+                            self.connected_peers
+                                .lock()
+                                .expect("mutex should be unpoisoned")
+                                .remove(&self.addr, self.connection_id);
+                            let reason = self
+                                .error_slot
+                                .try_get_error()
+                                .map(|e| e.to_string())
+                                .unwrap_or_default();
+                            let _ = self.events.send(PeerEvent::Disconnected {
+                                addr: self.addr,
+                                reason,
+                            });
+                            return;
+                        }
                     }
                 }
             }
         }
     }
 
+    /// Returns true if the remote peer's version is below the minimum for the
+    /// network upgrade at our best chain tip.
+    ///
+    /// This is checked on every connection event, so peers are disconnected
+    /// shortly after an upgrade activates, at the latest when the next
+    /// heartbeat ping is sent.
+    fn is_obsolete(&self) -> bool {
+        let min_version = Version::min_remote_for_height(self.network, self.best_tip_height.get());
+        self.remote_version < min_version
+    }
+
     /// Marks the peer as having failed with error `e`.
     fn fail_with(&mut self, e: PeerError) {
         debug!(%e, "failing peer service with error");
@@ -293,6 +589,22 @@ where
         use Request::*;
         use State::*;
         let ClientRequest(req, tx) = msg;
+        update_peer_info(
+            &self.connected_peers,
+            &self.addr,
+            self.connection_id,
+            |info| info.last_request = Some(Utc::now()),
+        );
+
+        // Large inventory requests are split into pipelined batches, so give
+        // the peer time to answer each batch.
+        let batch_size = std::cmp::max(self.getdata_batch_size, 1);
+        let batches = match &req {
+            BlocksByHash(hashes) => (hashes.len() + batch_size - 1) / batch_size,
+            TransactionsByHash(hashes) => (hashes.len() + batch_size - 1) / batch_size,
+            _ => 1,
+        };
+        let timeout = self.request_timeout * std::cmp::max(batches, 1) as u32;
 
         // Inner match returns Result with the new state or an error.
         // Outer match updates state or fails.
@@ -305,41 +617,152 @@ where
                 .await
                 .map_err(|e| e.into())
                 .map(|()| AwaitingResponse(Handler::GetPeers, tx)),
-            (AwaitingRequest, Ping(nonce)) => self
-                .peer_tx
-                .send(Message::Ping(nonce))
-                .await
-                .map_err(|e| e.into())
-                .map(|()| AwaitingResponse(Handler::Ping(nonce), tx)),
-            (AwaitingRequest, BlocksByHash(hashes)) => self
-                .peer_tx
-                .send(Message::GetData(
-                    hashes.iter().map(|h| (*h).into()).collect(),
-                ))
+            (AwaitingRequest, Ping(nonce)) => {
+                // Start timing when the ping is sent, rather than when it
+                // was queued behind other requests to this peer.
+                let sent_at = Instant::now();
+                self.peer_tx
+                    .send(Message::Ping(nonce))
+                    .await
+                    .map_err(|e| e.into())
+                    .map(|()| AwaitingResponse(Handler::Ping(nonce, sent_at), tx))
+            }
+            (AwaitingRequest, BlocksByHash(hashes)) => {
+                let mut pending = HashSet::with_capacity(hashes.len());
+                let order: Vec<_> = hashes
+                    .into_iter()
+                    .filter(|hash| pending.insert(*hash))
+                    .collect();
+                send_getdata(
+                    &mut self.peer_tx,
+                    batch_size,
+                    order.iter().map(|h| (*h).into()).collect(),
+                )
                 .await
                 .map_err(|e| e.into())
                 .map(|()| {
                     AwaitingResponse(
                         Handler::GetBlocksByHash {
-                            blocks: Vec::with_capacity(hashes.len()),
-                            hashes,
+                            pending,
+                            blocks: HashMap::with_capacity(order.len()),
+                            order,
                         },
                         tx,
                     )
-                }),
+                })
+            }
             (AwaitingRequest, FindBlocks { known_blocks, stop }) => self
                 .peer_tx
                 .send(Message::GetBlocks {
                     block_locator_hashes: known_blocks,
-                    hash_stop: stop.unwrap_or(BlockHeaderHash([0; 32])),
+                    hash_stop: stop.unwrap_or(block::Hash([0; 32])),
                 })
                 .await
                 .map_err(|e| e.into())
                 .map(|()| AwaitingResponse(Handler::FindBlocks, tx)),
+            (AwaitingRequest, FindHeaders { known_blocks, stop }) => self
+                .peer_tx
+                .send(Message::GetHeaders {
+                    block_locator_hashes: known_blocks,
+                    hash_stop: stop.unwrap_or(block::Hash([0; 32])),
+                })
+                .await
+                .map_err(|e| e.into())
+                .map(|()| AwaitingResponse(Handler::FindHeaders, tx)),
+            (AwaitingRequest, TransactionsByHash(hashes)) => {
+                let order: Vec<_> = hashes.into_iter().collect();
+                send_getdata(
+                    &mut self.peer_tx,
+                    batch_size,
+                    order.iter().map(|h| (*h).into()).collect(),
+                )
+                .await
+                .map_err(|e| e.into())
+                .map(|()| {
+                    AwaitingResponse(
+                        Handler::TransactionsByHash {
+                            pending: order.iter().cloned().collect(),
+                            transactions: HashMap::with_capacity(order.len()),
+                            order,
+                        },
+                        tx,
+                    )
+                })
+            }
+            (AwaitingRequest, PushTransaction(transaction)) => self
+                .peer_tx
+                .send(Message::Tx(transaction))
+                .await
+                .map_err(|e| e.into())
+                .map(|()| AwaitingResponse(Handler::Finished(Ok(Response::Nil)), tx)),
+            (AwaitingRequest, AdvertiseTransactionIds(hashes)) => {
+                let items = hashes.into_iter().map(InventoryHash::from).collect();
+                send_inv(&mut self.peer_tx, &mut self.recent_inventory, items)
+                    .await
+                    .map_err(|e| e.into())
+                    .map(|()| AwaitingResponse(Handler::Finished(Ok(Response::Nil)), tx))
+            }
+            (AwaitingRequest, AdvertiseBlock(hash)) => send_inv(
+                &mut self.peer_tx,
+                &mut self.recent_inventory,
+                vec![hash.into()],
+            )
+            .await
+            .map_err(|e| e.into())
+            .map(|()| AwaitingResponse(Handler::Finished(Ok(Response::Nil)), tx)),
+            (AwaitingRequest, MempoolTransactions) => self
+                .peer_tx
+                .send(Message::Mempool)
+                .await
+                .map_err(|e| e.into())
+                .map(|()| AwaitingResponse(Handler::MempoolTransactions, tx)),
+            (AwaitingRequest, CompactFilters { start_height, stop }) => self
+                .peer_tx
+                .send(Message::GetCFilters {
+                    filter_type: BASIC_FILTER_TYPE,
+                    start_height,
+                    stop_hash: stop,
+                })
+                .await
+                .map_err(|e| e.into())
+                .map(|()| {
+                    AwaitingResponse(
+                        Handler::CompactFilters {
+                            stop,
+                            filters: Vec::new(),
+                        },
+                        tx,
+                    )
+                }),
+            (AwaitingRequest, CompactFilterHeaders { start_height, stop }) => self
+                .peer_tx
+                .send(Message::GetCFHeaders {
+                    filter_type: BASIC_FILTER_TYPE,
+                    start_height,
+                    stop_hash: stop,
+                })
+                .await
+                .map_err(|e| e.into())
+                .map(|()| AwaitingResponse(Handler::CompactFilterHeaders, tx)),
+            (AwaitingRequest, CompactFilterCheckpoints { stop }) => self
+                .peer_tx
+                .send(Message::GetCFCheckpt {
+                    filter_type: BASIC_FILTER_TYPE,
+                    stop_hash: stop,
+                })
+                .await
+                .map_err(|e| e.into())
+                .map(|()| AwaitingResponse(Handler::CompactFilterCheckpoints, tx)),
         } {
+            // Requests that don't expect a response are finished as soon
+            // as their message is sent.
+            Ok(AwaitingResponse(Handler::Finished(response), tx)) => {
+                let _ = tx.send(response);
+                self.state = AwaitingRequest;
+            }
             Ok(new_state) => {
                 self.state = new_state;
-                self.request_timer = Some(delay_for(constants::REQUEST_TIMEOUT));
+                self.request_timer = Some(delay_for(timeout));
             }
             Err(e) => self.fail_with(e),
         }
@@ -347,6 +770,22 @@ where
 
     async fn handle_message_as_request(&mut self, msg: Message) {
         trace!(?msg);
+
+        // Responses to our requests are checked by the handler, so only
+        // messages that reach this point count against the rate limits.
+        match self.rate_limiter.check(&msg, Instant::now()) {
+            rate_limit::Decision::Allow => {}
+            rate_limit::Decision::Drop => {
+                debug!(command = msg.command(), "dropping rate-limited message");
+                metrics::counter!("peer.rate_limited", 1, "command" => msg.command());
+                return;
+            }
+            rate_limit::Decision::Disconnect => {
+                metrics::counter!("peer.misbehavior", 1, "command" => msg.command());
+                self.fail_with(PeerError::RateLimited(msg.command()));
+                return;
+            }
+        }
         // These messages are transport-related, handle them separately:
         match msg {
             Message::Version { .. } => {
@@ -387,6 +826,109 @@ where
                 None
             }
             Message::GetAddr => Some(Request::Peers),
+            Message::GetData(items) => {
+                if items
+                    .iter()
+                    .all(|item| matches!(item, InventoryHash::Block(_)))
+                {
+                    Some(Request::BlocksByHash(
+                        items
+                            .into_iter()
+                            .filter_map(|item| match item {
+                                InventoryHash::Block(hash) => Some(hash),
+                                _ => None,
+                            })
+                            .collect(),
+                    ))
+                } else if items.iter().all(|item| item.unmined_tx_id().is_some()) {
+                    Some(Request::TransactionsByHash(
+                        items
+                            .iter()
+                            .filter_map(|item| item.unmined_tx_id())
+                            .collect(),
+                    ))
+                } else {
+                    debug!("ignoring getdata with mixed or unsupported inventory");
+                    None
+                }
+            }
+            Message::Inv(items) => {
+                // Drop hashes we've already exchanged with this peer, so
+                // repeated floods of the same inventory are cheap to ignore.
+                let recent_inventory = &mut self.recent_inventory;
+                let items: Vec<_> = items
+                    .into_iter()
+                    .filter(|item| recent_inventory.insert(*item))
+                    .collect();
+                match &items[..] {
+                    // A single block hash is how peers announce new blocks.
+                    [InventoryHash::Block(hash)] => Some(Request::AdvertiseBlock(*hash)),
+                    _ if !items.is_empty()
+                        && items.iter().all(|item| item.unmined_tx_id().is_some()) =>
+                    {
+                        Some(Request::AdvertiseTransactionIds(
+                            items
+                                .iter()
+                                .filter_map(|item| item.unmined_tx_id())
+                                .collect(),
+                        ))
+                    }
+                    [] => {
+                        trace!("ignoring inv message with only recently seen hashes");
+                        None
+                    }
+                    _ => {
+                        debug!("ignoring unsolicited inv message");
+                        None
+                    }
+                }
+            }
+            Message::GetBlocks {
+                block_locator_hashes,
+                hash_stop,
+            } => Some(Request::FindBlocks {
+                known_blocks: block_locator_hashes,
+                stop: stop_hash(hash_stop),
+            }),
+            Message::GetHeaders {
+                block_locator_hashes,
+                hash_stop,
+            } => Some(Request::FindHeaders {
+                known_blocks: block_locator_hashes,
+                stop: stop_hash(hash_stop),
+            }),
+            Message::Tx(transaction) => Some(Request::PushTransaction(transaction)),
+            Message::Mempool => Some(Request::MempoolTransactions),
+            // We only answer filter requests if we advertised
+            // NODE_COMPACT_FILTERS, and we only build basic filters.
+            Message::GetCFilters {
+                filter_type: BASIC_FILTER_TYPE,
+                start_height,
+                stop_hash,
+            } if self.serve_compact_filters => Some(Request::CompactFilters {
+                start_height,
+                stop: stop_hash,
+            }),
+            Message::GetCFHeaders {
+                filter_type: BASIC_FILTER_TYPE,
+                start_height,
+                stop_hash,
+            } if self.serve_compact_filters => Some(Request::CompactFilterHeaders {
+                start_height,
+                stop: stop_hash,
+            }),
+            Message::GetCFCheckpt {
+                filter_type: BASIC_FILTER_TYPE,
+                stop_hash,
+            } if self.serve_compact_filters => {
+                Some(Request::CompactFilterCheckpoints { stop: stop_hash })
+            }
+            Message::GetCFilters { .. }
+            | Message::GetCFHeaders { .. }
+            | Message::GetCFCheckpt { .. } => {
+                debug!("ignoring unsupported compact filter request");
+                None
+            }
             _ => {
                 debug!("unhandled message type");
                 None
@@ -416,9 +958,15 @@ where
             Err(e) => {
                 if e.is::<Overloaded>() {
                     self.fail_with(PeerError::Overloaded);
+                } else if let Some(Misbehavior(reason)) = e.downcast_ref::<Misbehavior>() {
+                    self.fail_with(PeerError::Misbehavior(reason.clone()));
                 } else {
                     // We could send a reject to the remote peer.
-                    error!(%e);
+                    debug!(%e, "inbound service failed to answer a peer request");
+                    let _ = self.events.send(PeerEvent::InboundRequestFailed {
+                        addr: self.addr,
+                        error: e.to_string(),
+                    });
                 }
                 return;
             }
@@ -427,6 +975,7 @@ where
 
         match rsp {
             Response::Nil => { /* generic success, do nothing */ }
+            Response::Pong(_) => { /* only used for outbound pings, do nothing */ }
             Response::Peers(addrs) => {
                 if let Err(e) = self.peer_tx.send(Message::Addr(addrs)).await {
                     self.fail_with(e.into());
@@ -440,7 +989,29 @@ where
                     }
                 }
             }
-            Response::BlockHeaderHashes(hashes) => {
+            Response::BlockHashes(hashes) => {
+                if let Err(e) = self
+                    .peer_tx
+                    .send(Message::Inv(hashes.into_iter().map(Into::into).collect()))
+                    .await
+                {
+                    self.fail_with(e.into())
+                }
+            }
+            Response::BlockHeaders(headers) => {
+                if let Err(e) = self.peer_tx.send(Message::Headers(headers)).await {
+                    self.fail_with(e.into())
+                }
+            }
+            Response::Transactions(transactions) => {
+                // Generate one tx message per transaction.
+                for transaction in transactions.into_iter() {
+                    if let Err(e) = self.peer_tx.send(Message::Tx(transaction)).await {
+                        self.fail_with(e.into());
+                    }
+                }
+            }
+            Response::TransactionHashes(hashes) => {
                 if let Err(e) = self
                     .peer_tx
                     .send(Message::Inv(hashes.into_iter().map(Into::into).collect()))
@@ -449,6 +1020,196 @@ where
                     self.fail_with(e.into())
                 }
             }
+            Response::CompactFilters(filters) => {
+                // Generate one cfilter message per block.
+                for (block_hash, filter) in filters.into_iter() {
+                    let msg = Message::CFilter {
+                        filter_type: BASIC_FILTER_TYPE,
+                        block_hash,
+                        filter,
+                    };
+                    if let Err(e) = self.peer_tx.send(msg).await {
+                        self.fail_with(e.into());
+                    }
+                }
+            }
+            Response::CompactFilterHeaders {
+                stop,
+                previous,
+                hashes,
+            } => {
+                let msg = Message::CFHeaders {
+                    filter_type: BASIC_FILTER_TYPE,
+                    stop_hash: stop,
+                    previous_filter_header: previous,
+                    filter_hashes: hashes,
+                };
+                if let Err(e) = self.peer_tx.send(msg).await {
+                    self.fail_with(e.into())
+                }
+            }
+            Response::CompactFilterCheckpoints { stop, headers } => {
+                let msg = Message::CFCheckpt {
+                    filter_type: BASIC_FILTER_TYPE,
+                    stop_hash: stop,
+                    filter_headers: headers,
+                };
+                if let Err(e) = self.peer_tx.send(msg).await {
+                    self.fail_with(e.into())
+                }
+            }
+        }
+    }
+}
+
+/// Converts a wire `hash_stop` into an optional stop hash, treating the
+/// all-zeroes hash as "no stop".
+fn stop_hash(hash_stop: block::Hash) -> Option<block::Hash> {
+    if hash_stop == block::Hash([0; 32]) {
+        None
+    } else {
+        Some(hash_stop)
+    }
+}
+
+/// Update the entry for `addr` in `connected_peers` using `f`, if it still
+/// belongs to connection `id`.
+pub(super) fn update_peer_info(
+    connected_peers: &Mutex<ConnectedPeers>,
+    addr: &SocketAddr,
+    id: ConnectionId,
+    f: impl FnOnce(&mut PeerInfo),
+) {
+    let mut connected_peers = connected_peers.lock().expect("mutex should be unpoisoned");
+    if let Some(info) = connected_peers.get_mut(addr, id) {
+        f(info);
+    }
+}
+
+/// Advertise `items` to the peer in an `inv` message, skipping any hashes
+/// that the peer recently sent us or that we recently sent the peer.
+///
+/// Nothing is sent if all the hashes were skipped.
+async fn send_inv<Tx>(
+    peer_tx: &mut Tx,
+    recent_inventory: &mut RecentInventory,
+    items: Vec<InventoryHash>,
+) -> Result<(), SerializationError>
+where
+    Tx: Sink<Message, Error = SerializationError> + Unpin,
+{
+    let items: Vec<_> = items
+        .into_iter()
+        .filter(|item| recent_inventory.insert(*item))
+        .collect();
+    if items.is_empty() {
+        return Ok(());
+    }
+    peer_tx.send(Message::Inv(items)).await
+}
+
+/// Send `items` to the peer as a sequence of `getdata` messages, each
+/// containing at most `batch_size` items.
+///
+/// All the batches are sent before waiting for any responses, so the peer can
+/// start answering the first batch while it receives the rest.
+async fn send_getdata<Tx>(
+    peer_tx: &mut Tx,
+    batch_size: usize,
+    items: Vec<InventoryHash>,
+) -> Result<(), SerializationError>
+where
+    Tx: Sink<Message, Error = SerializationError> + Unpin,
+{
+    for batch in items.chunks(batch_size) {
+        peer_tx.send(Message::GetData(batch.to_vec())).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use zebra_chain::serialization::ZcashDeserialize;
+
+    fn blocks() -> Vec<Arc<Block>> {
+        [
+            &zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..],
+            &zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..],
+            &zebra_test_vectors::BLOCK_MAINNET_2_BYTES[..],
+        ]
+        .iter()
+        .map(|bytes| Block::zcash_deserialize(*bytes).unwrap().into())
+        .collect()
+    }
+
+    #[test]
+    fn getdata_is_sent_in_batches() {
+        let (tx, rx) = mpsc::unbounded();
+        let mut peer_tx = tx.sink_map_err(|_| SerializationError::Parse("peer channel closed"));
+        let items: Vec<InventoryHash> = (0..5u8)
+            .map(|i| InventoryHash::Block(block::Hash([i; 32])))
+            .collect();
+
+        futures::executor::block_on(send_getdata(&mut peer_tx, 2, items.clone())).unwrap();
+        std::mem::drop(peer_tx);
+
+        let batches: Vec<Vec<InventoryHash>> = futures::executor::block_on(rx.collect::<Vec<_>>())
+            .into_iter()
+            .map(|msg| match msg {
+                Message::GetData(batch) => batch,
+                _ => panic!("only getdata messages should be sent"),
+            })
+            .collect();
+        assert_eq!(
+            batches.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![2, 2, 1]
+        );
+        assert_eq!(batches.concat(), items);
+    }
+
+    #[test]
+    fn blocks_are_returned_in_request_order() {
+        let blocks = blocks();
+        let order: Vec<block::Hash> = blocks.iter().map(|block| block.hash()).collect();
+        let mut handler = Handler::GetBlocksByHash {
+            pending: order.iter().cloned().collect(),
+            blocks: HashMap::new(),
+            order: order.clone(),
+        };
+
+        // Later batches can be answered first.
+        for block in blocks.iter().rev() {
+            assert!(handler
+                .process_message(Message::Block(block.clone()))
+                .is_none());
+        }
+        match handler {
+            Handler::Finished(Ok(Response::Blocks(received))) => assert_eq!(
+                received
+                    .iter()
+                    .map(|block| block.hash())
+                    .collect::<Vec<_>>(),
+                order
+            ),
+            _ => panic!("every requested block should finish the request"),
+        }
+    }
+
+    #[test]
+    fn unrequested_blocks_fail_the_request() {
+        let blocks = blocks();
+        let mut handler = Handler::GetBlocksByHash {
+            pending: vec![blocks[0].hash()].into_iter().collect(),
+            blocks: HashMap::new(),
+            order: vec![blocks[0].hash()],
+        };
+
+        handler.process_message(Message::Block(blocks[1].clone()));
+        match handler {
+            Handler::Finished(Err(_)) => {}
+            _ => panic!("an unrequested block should fail the request"),
         }
     }
 }
@@ -3,34 +3,50 @@ use std::{
     net::SocketAddr,
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
 
 use futures::prelude::*;
 use tokio::net::TcpStream;
 use tower::{discover::Change, Service, ServiceExt};
 
-use crate::{BoxedStdError, Request, Response};
+use crate::{socks5, BoxedStdError, Direction, Request, Response};
 
 use super::{Client, Handshake};
 
 /// A wrapper around [`peer::Handshake`] that opens a TCP connection before
 /// forwarding to the inner handshake service. Writing this as its own
 /// [`tower::Service`] lets us apply unified timeout policies, etc.
+///
+/// If a SOCKS5 `proxy` is set, connections are opened through it. Opening
+/// the connection fails if it takes longer than `connect_timeout`.
 pub struct Connector<S> {
     handshaker: Handshake<S>,
+    proxy: Option<SocketAddr>,
+    connect_timeout: Duration,
 }
 
 impl<S: Clone> Clone for Connector<S> {
     fn clone(&self) -> Self {
         Connector {
             handshaker: self.handshaker.clone(),
+            proxy: self.proxy,
+            connect_timeout: self.connect_timeout,
         }
     }
 }
 
 impl<S> Connector<S> {
-    pub fn new(handshaker: Handshake<S>) -> Self {
-        Connector { handshaker }
+    pub fn new(
+        handshaker: Handshake<S>,
+        proxy: Option<SocketAddr>,
+        connect_timeout: Duration,
+    ) -> Self {
+        Connector {
+            handshaker,
+            proxy,
+            connect_timeout,
+        }
     }
 }
 
@@ -50,10 +66,18 @@ where
 
     fn call(&mut self, addr: SocketAddr) -> Self::Future {
         let mut hs = self.handshaker.clone();
+        let proxy = self.proxy;
+        let connect_timeout = self.connect_timeout;
         async move {
-            let stream = TcpStream::connect(addr).await?;
+            let connect = async move {
+                match proxy {
+                    Some(proxy) => socks5::connect(proxy, addr).await,
+                    None => TcpStream::connect(addr).await,
+                }
+            };
+            let stream = tokio::time::timeout(connect_timeout, connect).await??;
             hs.ready_and().await?;
-            let client = hs.call((stream, addr)).await?;
+            let client = hs.call((stream, addr, Direction::Outbound)).await?;
             Ok(Change::Insert(addr, client))
         }
         .boxed()
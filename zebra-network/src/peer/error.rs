@@ -49,8 +49,38 @@ pub enum PeerError {
     /// The remote peer responded with a block we didn't ask for.
     #[error("Remote peer responded with a block we didn't ask for.")]
     WrongBlock,
+    /// The remote peer's protocol version became obsolete when a network
+    /// upgrade activated.
+    #[error("Peer version {0:?} is obsolete at the current network upgrade")]
+    ObsoleteVersion(crate::protocol::external::types::Version),
+    /// The remote peer responded with a transaction we didn't ask for.
+    #[error("Remote peer responded with a transaction we didn't ask for.")]
+    WrongTransaction,
+    /// The remote peer kept sending messages after exceeding their rate
+    /// limits.
+    #[error("Peer repeatedly exceeded the rate limit for {0} messages")]
+    RateLimited(&'static str),
+    /// The remote peer sent a `notfound` message for some of the blocks or
+    /// transactions we asked for.
+    #[error("Remote peer doesn't have {} of the requested items", .0.len())]
+    NotFound(Vec<crate::protocol::external::InventoryHash>),
+    /// We closed an inbound connection to make room for a new one.
+    #[error("Peer was evicted to make room for a new inbound connection")]
+    Evicted,
+    /// The remote peer sent a block or transaction that breaks a consensus
+    /// rule.
+    #[error("Peer sent invalid data: {0}")]
+    Misbehavior(String),
 }
 
+/// An inbound service error, which means that the peer sent invalid data.
+///
+/// When the inbound service fails a request with this error, the connection
+/// to the peer is closed.
+#[derive(Error, Clone, Debug, Eq, PartialEq)]
+#[error("{0}")]
+pub struct Misbehavior(pub String);
+
 #[derive(Default, Clone)]
 pub(super) struct ErrorSlot(pub(super) Arc<Mutex<Option<SharedPeerError>>>);
 
@@ -85,4 +115,7 @@ pub enum HandshakeError {
     /// The remote peer offered a version older than our minimum version.
     #[error("Peer offered obsolete version: {0:?}")]
     ObsoleteVersion(crate::protocol::external::types::Version),
+    /// The remote peer didn't finish the handshake in time.
+    #[error("Timed out waiting for the peer to finish the handshake")]
+    Timeout,
 }
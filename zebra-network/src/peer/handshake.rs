@@ -1,5 +1,5 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     future::Future,
     net::SocketAddr,
     pin::Pin,
@@ -8,34 +8,53 @@ use std::{
 };
 
 use chrono::Utc;
-use futures::{channel::mpsc, prelude::*};
-use tokio::net::TcpStream;
+use futures::{
+    channel::{mpsc, oneshot},
+    prelude::*,
+};
+use tokio::{net::TcpStream, sync::broadcast};
 use tokio_util::codec::Framed;
 use tower::Service;
 use tracing::{span, Level};
 use tracing_futures::Instrument;
 
-use zebra_chain::types::BlockHeight;
+use zebra_chain::{
+    block,
+    serialization::{DateTime32, SerializationError},
+};
 
 use crate::{
+    connected_peers::ConnectionId,
     constants,
     protocol::{
-        external::{types::*, Codec, Message},
+        external::{types::*, Codec, InventoryHash, Message},
         internal::{Request, Response},
     },
+    timestamp_collector::AddressBookUpdate,
     types::MetaAddr,
-    BoxedStdError, Config,
+    BestTipHeight, BoxedStdError, Config, ConnectedPeers, Direction, PeerEvent, PeerInfo,
 };
 
-use super::{Client, Connection, ErrorSlot, HandshakeError};
+use super::{
+    connection, inventory_cache::RecentInventory, rate_limit::InboundRateLimiter, Client,
+    ClientRequest, Connection, ErrorSlot, HandshakeError,
+};
 
 /// A [`Service`] that handshakes with a remote peer and constructs a
 /// client/server pair.
 pub struct Handshake<S> {
     config: Config,
     internal_service: S,
-    timestamp_collector: mpsc::Sender<MetaAddr>,
-    nonces: Arc<Mutex<HashSet<Nonce>>>,
+    timestamp_collector: mpsc::Sender<AddressBookUpdate>,
+    /// The nonces of handshakes in progress, with the address and direction
+    /// of each connection.
+    nonces: Arc<Mutex<HashMap<Nonce, (SocketAddr, Direction)>>>,
+    /// The addresses where we found ourselves, instead of a remote peer.
+    self_addrs: Arc<Mutex<HashSet<SocketAddr>>>,
+    best_tip_height: BestTipHeight,
+    inv_collector: mpsc::Sender<(InventoryHash, SocketAddr)>,
+    connected_peers: Arc<Mutex<ConnectedPeers>>,
+    events: broadcast::Sender<PeerEvent>,
 }
 
 impl<S: Clone> Clone for Handshake<S> {
@@ -45,6 +64,11 @@ impl<S: Clone> Clone for Handshake<S> {
             internal_service: self.internal_service.clone(),
             timestamp_collector: self.timestamp_collector.clone(),
             nonces: self.nonces.clone(),
+            self_addrs: self.self_addrs.clone(),
+            best_tip_height: self.best_tip_height.clone(),
+            inv_collector: self.inv_collector.clone(),
+            connected_peers: self.connected_peers.clone(),
+            events: self.events.clone(),
         }
     }
 }
@@ -58,7 +82,11 @@ where
     pub fn new(
         config: Config,
         internal_service: S,
-        timestamp_collector: mpsc::Sender<MetaAddr>,
+        timestamp_collector: mpsc::Sender<AddressBookUpdate>,
+        best_tip_height: BestTipHeight,
+        inv_collector: mpsc::Sender<(InventoryHash, SocketAddr)>,
+        connected_peers: Arc<Mutex<ConnectedPeers>>,
+        events: broadcast::Sender<PeerEvent>,
     ) -> Self {
         // XXX this function has too many parameters, but it's not clear how to
         // do a nice builder as all fields are mandatory. Could have Builder1,
@@ -69,12 +97,25 @@ where
             config,
             internal_service,
             timestamp_collector,
-            nonces: Arc::new(Mutex::new(HashSet::new())),
+            nonces: Arc::new(Mutex::new(HashMap::new())),
+            self_addrs: Arc::new(Mutex::new(HashSet::new())),
+            best_tip_height,
+            inv_collector,
+            connected_peers,
+            events,
         }
     }
+
+    /// Returns the addresses where a handshake connected to ourselves.
+    ///
+    /// These are usually our own listener, reached through a loopback or NAT
+    /// address that a peer gossiped to us.
+    pub fn self_addrs(&self) -> Arc<Mutex<HashSet<SocketAddr>>> {
+        self.self_addrs.clone()
+    }
 }
 
-impl<S> Service<(TcpStream, SocketAddr)> for Handshake<S>
+impl<S> Service<(TcpStream, SocketAddr, Direction)> for Handshake<S>
 where
     S: Service<Request, Response = Response, Error = BoxedStdError> + Clone + Send + 'static,
     S::Future: Send,
@@ -88,8 +129,8 @@ where
         Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, req: (TcpStream, SocketAddr)) -> Self::Future {
-        let (tcp_stream, addr) = req;
+    fn call(&mut self, req: (TcpStream, SocketAddr, Direction)) -> Self::Future {
+        let (tcp_stream, addr, direction) = req;
 
         let connector_span = span!(Level::INFO, "connector", addr = ?addr);
         // set parent: None for the peer connection span, as it should exist
@@ -99,40 +140,72 @@ where
 
         // Clone these upfront, so they can be moved into the future.
         let nonces = self.nonces.clone();
+        let self_addrs = self.self_addrs.clone();
         let internal_service = self.internal_service.clone();
         let timestamp_collector = self.timestamp_collector.clone();
         let user_agent = self.config.user_agent.clone();
+        let serve_compact_filters = self.config.serve_compact_filters;
+        let our_services = if serve_compact_filters {
+            self.config.advertised_services | PeerServices::NODE_COMPACT_FILTERS
+        } else {
+            self.config.advertised_services
+        };
+        let relay = self.config.relay;
         let network = self.config.network;
+        let max_message_len = self.config.max_message_len;
+        let max_block_message_len = self.config.max_block_message_len;
+        let max_missed_pings = self.config.max_missed_heartbeats;
+        let trace_wire_format = self.config.trace_wire_format;
+        let getdata_batch_size = self.config.getdata_batch_size;
+        let inbound_rate_limits = self.config.inbound_rate_limits.clone();
+        let max_rate_limit_violations = self.config.max_rate_limit_violations;
+        let best_tip_height = self.best_tip_height.clone();
+        let inv_collector = self.inv_collector.clone();
+        let connected_peers = self.connected_peers.clone();
+        let byte_counts = connected_peers
+            .lock()
+            .expect("mutex should be unpoisoned")
+            .byte_counts();
+        let events = self.events.clone();
+        let handshake_events = self.events.clone();
+        let handshake_timeout = self.config.handshake_timeout;
+        let request_timeout = self.config.request_timeout;
+
+        let local_nonce = Nonce::default();
+        let handshake_nonces = nonces.clone();
+
+        // Sending only fails if there are no subscribers.
+        let _ = self.events.send(PeerEvent::Connecting { addr, direction });
 
         let fut = async move {
             debug!("connecting to remote peer");
 
-            let mut stream =
-                Framed::new(tcp_stream, Codec::builder().for_network(network).finish());
+            let mut stream = Framed::new(
+                tcp_stream,
+                Codec::builder()
+                    .for_network(network)
+                    .with_max_body_len(max_message_len)
+                    .with_max_block_body_len(max_block_message_len)
+                    .with_wire_tracing(trace_wire_format)
+                    .with_byte_counts(byte_counts)
+                    .finish(),
+            );
 
-            let local_nonce = Nonce::default();
             nonces
                 .lock()
                 .expect("mutex should be unpoisoned")
-                .insert(local_nonce);
+                .insert(local_nonce, (addr, direction));
 
             let version = Message::Version {
                 version: constants::CURRENT_VERSION,
-                services: PeerServices::NODE_NETWORK,
+                services: our_services,
                 timestamp: Utc::now(),
                 address_recv: (PeerServices::NODE_NETWORK, addr),
-                address_from: (
-                    PeerServices::NODE_NETWORK,
-                    "127.0.0.1:9000".parse().unwrap(),
-                ),
+                address_from: (our_services, "127.0.0.1:9000".parse().unwrap()),
                 nonce: local_nonce,
                 user_agent,
-                // XXX eventually the `PeerConnector` will need to have a handle
-                // for a service that gets the current block height. Among other
-                // things we need it to reject peers who don't know about the
-                // current protocol epoch.
-                start_height: BlockHeight(0),
-                relay: false,
+                start_height: best_tip_height.get().unwrap_or(block::Height(0)),
+                relay,
             };
 
             debug!(?version, "sending initial version message");
@@ -145,27 +218,48 @@ where
 
             // Check that we got a Version and destructure its fields into the local scope.
             debug!(?remote_msg, "got message from remote peer");
-            let (remote_nonce, remote_services, remote_version) = if let Message::Version {
+            let (
+                remote_nonce,
+                remote_services,
+                remote_version,
+                remote_user_agent,
+                remote_start_height,
+                remote_relay,
+            ) = if let Message::Version {
                 nonce,
                 services,
                 version,
+                user_agent,
+                start_height,
+                relay,
                 ..
             } = remote_msg
             {
-                (nonce, services, version)
+                (nonce, services, version, user_agent, start_height, relay)
             } else {
                 return Err(HandshakeError::UnexpectedMessage(Box::new(remote_msg)));
             };
 
             // Check for nonce reuse, indicating self-connection.
-            let nonce_reuse = {
+            let nonce_owner = {
                 let mut locked_nonces = nonces.lock().expect("mutex should be unpoisoned");
-                let nonce_reuse = locked_nonces.contains(&remote_nonce);
+                let nonce_owner = locked_nonces.get(&remote_nonce).copied();
                 // Regardless of whether we observed nonce reuse, clean up the nonce set.
                 locked_nonces.remove(&local_nonce);
-                nonce_reuse
+                nonce_owner
             };
-            if nonce_reuse {
+            if let Some(owner) = nonce_owner {
+                if let Some(self_addr) = self_connection_addr((addr, direction), owner) {
+                    info!(
+                        ?self_addr,
+                        "detected self-connection, marking address as our own"
+                    );
+                    self_addrs
+                        .lock()
+                        .expect("mutex should be unpoisoned")
+                        .insert(self_addr);
+                }
+                metrics::counter!("peer.self_connections", 1);
                 return Err(HandshakeError::NonceReuse);
             }
 
@@ -185,17 +279,14 @@ where
             // we would disconnect here if it received a second one. Is it even possible
             // for that to happen to us here?
 
-            if remote_version < constants::MIN_VERSION {
-                // Disconnect if peer is using an obsolete version.
+            // Disconnect if peer is using a version that is obsolete at the
+            // current network upgrade. Like zcashd, we do this for both
+            // inbound and outbound connections.
+            let min_version = Version::min_remote_for_height(network, best_tip_height.get());
+            if remote_version < min_version {
                 return Err(HandshakeError::ObsoleteVersion(remote_version));
             }
 
-            // TODO: Reject incoming connections from nodes that don't know about the current epoch.
-            // zcashd does this:
-            //  const Consensus::Params& consensusParams = chainparams.GetConsensus();
-            //  auto currentEpoch = CurrentEpoch(GetHeight(), consensusParams);
-            //  if (pfrom->nVersion < consensusParams.vUpgrades[currentEpoch].nProtocolVersion)
-
             // Set the connection's version to the minimum of the received version or our own.
             let negotiated_version = std::cmp::min(remote_version, constants::CURRENT_VERSION);
 
@@ -218,6 +309,7 @@ where
                 span: connection_span.clone(),
                 server_tx: server_tx.clone(),
                 error_slot: slot.clone(),
+                connection_tracker: None,
             };
 
             let (peer_tx, peer_rx) = stream.split();
@@ -235,11 +327,23 @@ where
                 future::ready(Ok(msg))
             });
 
+            let heartbeat_timestamp_collector = timestamp_collector.clone();
             let peer_rx = peer_rx
                 .then(move |msg| {
                     // Add a metric for inbound messages and fire a timestamp event.
                     let mut timestamp_collector = timestamp_collector.clone();
+                    let mut inv_collector = inv_collector.clone();
                     async move {
+                        // Register the peer's inventory, whether it's an
+                        // advertisement or a response to our request, so the
+                        // peer set can route requests for it to this peer.
+                        if let Ok(Message::Inv(hashes)) = &msg {
+                            for hash in hashes {
+                                // Dropping registrations when the registry is
+                                // busy only affects routing.
+                                let _ = inv_collector.try_send((*hash, addr));
+                            }
+                        }
                         if msg.is_ok() {
                             // XXX add a dimension tagging message metrics by type
                             metrics::counter!(
@@ -249,19 +353,52 @@ where
                             );
                             use futures::sink::SinkExt;
                             let _ = timestamp_collector
-                                .send(MetaAddr {
+                                .send(AddressBookUpdate::Seen(MetaAddr {
                                     addr,
                                     services: remote_services,
-                                    last_seen: Utc::now(),
-                                })
+                                    last_seen: DateTime32::now(),
+                                }))
                                 .await;
+                        } else if let Err(SerializationError::Parse(_)) = &msg {
+                            // Malformed or oversized messages fail the
+                            // connection, so record them as misbehavior.
+                            // IO errors are usually just a closed connection.
+                            metrics::counter!(
+                                "peer.misbehavior",
+                                1,
+                                "addr" => addr.to_string(),
+                            );
                         }
                         msg
                     }
                 })
                 .boxed();
 
-            use super::connection;
+            let (evict_tx, evict_rx) = oneshot::channel();
+
+            let connection_id = connected_peers
+                .lock()
+                .expect("mutex should be unpoisoned")
+                .insert(
+                    PeerInfo {
+                        addr,
+                        direction,
+                        version: remote_version,
+                        negotiated_version,
+                        services: remote_services,
+                        user_agent: remote_user_agent.clone(),
+                        start_height: remote_start_height,
+                        relay: remote_relay,
+                        connected_at: Utc::now(),
+                        in_flight_requests: 0,
+                        last_request: None,
+                        last_response: None,
+                        timed_out_requests: 0,
+                        min_ping: None,
+                    },
+                    evict_tx,
+                );
+
             let server = Connection {
                 state: connection::State::AwaitingRequest,
                 svc: internal_service,
@@ -269,35 +406,74 @@ where
                 error_slot: slot,
                 peer_tx,
                 request_timer: None,
+                request_timeout,
+                missed_pings: 0,
+                max_missed_pings,
+                getdata_batch_size,
+                serve_compact_filters,
+                rate_limiter: InboundRateLimiter::new(
+                    inbound_rate_limits,
+                    max_rate_limit_violations,
+                ),
+                recent_inventory: RecentInventory::new(constants::RECENT_INVENTORY_CACHE_SIZE),
+                network,
+                remote_version,
+                best_tip_height,
+                addr,
+                connected_peers: connected_peers.clone(),
+                connection_id,
+                evict_rx: evict_rx.fuse(),
+                events: events.clone(),
             };
 
-            tokio::spawn(server.run(peer_rx).instrument(connection_span).boxed());
-
-            tokio::spawn(async move {
-                use futures::channel::oneshot;
-
-                use super::client::ClientRequest;
-
-                let mut server_tx = server_tx;
-
-                let mut interval_stream = tokio::time::interval(constants::HEARTBEAT_INTERVAL);
+            let _ = events.send(PeerEvent::HandshakeCompleted {
+                addr,
+                version: remote_version,
+                user_agent: remote_user_agent,
+            });
 
-                loop {
-                    interval_stream.tick().await;
+            tokio::spawn(
+                server
+                    .run(peer_rx)
+                    .instrument(connection_span.clone())
+                    .boxed(),
+            );
+
+            tokio::spawn(
+                heartbeat(
+                    addr,
+                    remote_services,
+                    server_tx,
+                    heartbeat_timestamp_collector,
+                    connected_peers,
+                    connection_id,
+                )
+                .instrument(connection_span),
+            );
 
-                    // We discard the server handle because our
-                    // heartbeat `Ping`s are a special case, and we
-                    // don't actually care about the response here.
-                    let (request_tx, _) = oneshot::channel();
-                    let msg = ClientRequest(Request::Ping(Nonce::default()), request_tx);
+            Ok(client)
+        };
 
-                    if server_tx.send(msg).await.is_err() {
-                        return;
-                    }
+        // Time out slow handshakes, cleaning up the nonce so it doesn't
+        // outlive the connection attempt.
+        let fut = async move {
+            let result = match tokio::time::timeout(handshake_timeout, fut).await {
+                Ok(result) => result,
+                Err(_) => {
+                    handshake_nonces
+                        .lock()
+                        .expect("mutex should be unpoisoned")
+                        .remove(&local_nonce);
+                    Err(HandshakeError::Timeout)
                 }
-            });
-
-            Ok(client)
+            };
+            if let Err(e) = &result {
+                let _ = handshake_events.send(PeerEvent::Disconnected {
+                    addr,
+                    reason: e.to_string(),
+                });
+            }
+            result
         };
 
         // Spawn a new task to drive this handshake.
@@ -312,3 +488,166 @@ where
             .boxed()
     }
 }
+
+/// Returns the address that led back to us, when the handshake for
+/// `connection` received the nonce that we sent on `owner`.
+///
+/// Either side of a self-connection can notice the reused nonce first, but
+/// only the outbound side's address leads back to us. If both sides are
+/// inbound, a peer relayed our own nonce, and there is no address to avoid.
+fn self_connection_addr(
+    connection: (SocketAddr, Direction),
+    owner: (SocketAddr, Direction),
+) -> Option<SocketAddr> {
+    match (connection, owner) {
+        ((addr, Direction::Outbound), _) => Some(addr),
+        (_, (owner_addr, Direction::Outbound)) => Some(owner_addr),
+        _ => None,
+    }
+}
+
+/// Send a `Ping` to the peer every [`constants::HEARTBEAT_INTERVAL`], recording
+/// the round-trip time of each answered ping in the address book, and the
+/// lowest round-trip time in the peer's `connected_peers` entry.
+///
+/// The [`Connection`] matches `Pong` nonces and closes the connection after too
+/// many missed pings, which also ends this task.
+async fn heartbeat(
+    addr: SocketAddr,
+    services: PeerServices,
+    mut server_tx: mpsc::Sender<ClientRequest>,
+    mut timestamp_collector: mpsc::Sender<AddressBookUpdate>,
+    connected_peers: Arc<Mutex<ConnectedPeers>>,
+    connection_id: ConnectionId,
+) {
+    let mut interval_stream = tokio::time::interval(constants::HEARTBEAT_INTERVAL);
+
+    loop {
+        interval_stream.tick().await;
+
+        let (request_tx, response_rx) = oneshot::channel();
+        let msg = ClientRequest(Request::Ping(Nonce::default()), request_tx);
+
+        if server_tx.send(msg).await.is_err() {
+            return;
+        }
+
+        match response_rx.await {
+            Ok(Ok(Response::Pong(rtt))) => {
+                trace!(?rtt, "got heartbeat pong");
+                metrics::histogram!(
+                    "peer.heartbeat_rtt_ms",
+                    rtt.as_millis() as u64,
+                    "addr" => addr.to_string(),
+                );
+                connection::update_peer_info(&connected_peers, &addr, connection_id, |info| {
+                    info.min_ping = Some(info.min_ping.map_or(rtt, |min| min.min(rtt)));
+                });
+                let _ = timestamp_collector
+                    .send(AddressBookUpdate::Rtt { addr, rtt })
+                    .await;
+                // Pongs already update the last-seen time as inbound
+                // messages, but record it here too, so that liveness doesn't
+                // depend on the order of the two updates.
+                let _ = timestamp_collector
+                    .send(AddressBookUpdate::Seen(MetaAddr {
+                        addr,
+                        services,
+                        last_seen: DateTime32::now(),
+                    }))
+                    .await;
+            }
+            Ok(Ok(rsp)) => unreachable!("pings are answered with pongs, got {:?}", rsp),
+            Ok(Err(e)) => {
+                debug!(%e, "heartbeat ping failed");
+                metrics::counter!("peer.heartbeat_missed", 1, "addr" => addr.to_string());
+            }
+            // The connection dropped the request without answering it.
+            Err(_) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::net::TcpListener;
+    use tower::ServiceExt;
+
+    #[test]
+    fn self_connections_avoid_the_outbound_address() {
+        let dialed: SocketAddr = "203.0.113.1:8233".parse().unwrap();
+        let accepted: SocketAddr = "198.51.100.1:51234".parse().unwrap();
+
+        // Whichever side notices first, we stop dialing the address that
+        // reached our listener.
+        assert_eq!(
+            self_connection_addr(
+                (dialed, Direction::Outbound),
+                (accepted, Direction::Inbound)
+            ),
+            Some(dialed)
+        );
+        assert_eq!(
+            self_connection_addr(
+                (accepted, Direction::Inbound),
+                (dialed, Direction::Outbound)
+            ),
+            Some(dialed)
+        );
+        assert_eq!(
+            self_connection_addr(
+                (accepted, Direction::Inbound),
+                (accepted, Direction::Inbound)
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn failed_handshakes_are_disconnected_events() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let (events, mut events_rx) = broadcast::channel(10);
+            let handshake = Handshake::new(
+                Config::default(),
+                tower::service_fn(|_req: Request| future::ok::<_, BoxedStdError>(Response::Nil)),
+                mpsc::channel(1).0,
+                BestTipHeight::default(),
+                mpsc::channel(1).0,
+                Arc::new(Mutex::new(ConnectedPeers::new())),
+                events,
+            );
+
+            // The remote peer closes the connection without sending a
+            // `version` message.
+            let (stream, accepted) =
+                future::join(TcpStream::connect(addr), listener.accept()).await;
+            std::mem::drop(accepted.unwrap());
+            let result = handshake
+                .oneshot((stream.unwrap(), addr, Direction::Outbound))
+                .await;
+            assert!(result.is_err());
+
+            assert_eq!(
+                events_rx.recv().await.unwrap(),
+                PeerEvent::Connecting {
+                    addr,
+                    direction: Direction::Outbound,
+                }
+            );
+            match events_rx.recv().await.unwrap() {
+                PeerEvent::Disconnected {
+                    addr: closed_addr,
+                    reason,
+                } => {
+                    assert_eq!(closed_addr, addr);
+                    assert!(!reason.is_empty());
+                }
+                event => panic!("expected a disconnection, got {:?}", event),
+            }
+        });
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::protocol::external::InventoryHash;
+
+/// A bounded set of the inventory hashes most recently exchanged with a peer.
+///
+/// Once the cache is full, inserting a new hash evicts the oldest one.
+pub(super) struct RecentInventory {
+    hashes: HashSet<InventoryHash>,
+    order: VecDeque<InventoryHash>,
+    capacity: usize,
+}
+
+impl RecentInventory {
+    pub(super) fn new(capacity: usize) -> Self {
+        RecentInventory {
+            hashes: HashSet::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Record `hash` as exchanged with the peer.
+    ///
+    /// Returns true if `hash` was not already in the cache.
+    pub(super) fn insert(&mut self, hash: InventoryHash) -> bool {
+        if self.capacity == 0 || !self.hashes.insert(hash) {
+            return false;
+        }
+        self.order.push_back(hash);
+        if self.order.len() > self.capacity {
+            let oldest = self.order.pop_front().expect("order is not empty");
+            self.hashes.remove(&oldest);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zebra_chain::{
+        block,
+        transaction::{self, AuthDigest, WtxId},
+    };
+
+    use super::*;
+
+    #[test]
+    fn evicts_oldest_hash() {
+        let mut cache = RecentInventory::new(2);
+        let hashes: Vec<InventoryHash> = (0..3)
+            .map(|i| InventoryHash::Block(block::Hash([i; 32])))
+            .collect();
+
+        assert!(cache.insert(hashes[0]));
+        assert!(!cache.insert(hashes[0]));
+        assert!(cache.insert(hashes[1]));
+        assert!(cache.insert(hashes[2]));
+
+        assert!(!cache.insert(hashes[2]));
+        // The first hash was evicted, so it is new again.
+        assert!(cache.insert(hashes[0]));
+    }
+
+    #[test]
+    fn wide_transaction_ids_include_authorizing_data() {
+        let mut cache = RecentInventory::new(10);
+        let id = transaction::Hash([7; 32]);
+        let wtx_id = |digest| {
+            InventoryHash::Wtx(WtxId {
+                id,
+                auth_digest: AuthDigest([digest; 32]),
+            })
+        };
+
+        assert!(cache.insert(wtx_id(1)));
+        assert!(!cache.insert(wtx_id(1)));
+        // The same transaction with different authorizing data is new.
+        assert!(cache.insert(wtx_id(2)));
+    }
+}
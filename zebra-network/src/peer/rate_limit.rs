@@ -0,0 +1,162 @@
+use std::{collections::HashMap, time::Instant};
+
+use crate::{config::RateLimit, constants, protocol::external::Message};
+
+/// What to do with an inbound message after checking the rate limits.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(super) enum Decision {
+    /// The message is within its limit.
+    Allow,
+    /// The message exceeded its limit and should be dropped.
+    Drop,
+    /// The peer has exceeded its limits too many times, and should be
+    /// disconnected.
+    Disconnect,
+}
+
+/// A token bucket that refills continuously, up to its burst size.
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        TokenBucket {
+            limit,
+            tokens: f64::from(limit.burst),
+            last_refill: now,
+        }
+    }
+
+    fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.limit.per_second)
+            .min(f64::from(self.limit.burst));
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Per-connection rate limits for inbound messages, keyed by command.
+///
+/// Messages without a configured limit are always allowed. Violations are
+/// counted over a [`constants::RATE_LIMIT_VIOLATION_WINDOW`] that starts at
+/// the first violation, and the count is reset when the window ends.
+pub(super) struct InboundRateLimiter {
+    buckets: HashMap<&'static str, TokenBucket>,
+    limits: HashMap<String, RateLimit>,
+    violations: usize,
+    window_start: Option<Instant>,
+    max_violations: usize,
+}
+
+impl InboundRateLimiter {
+    pub(super) fn new(limits: HashMap<String, RateLimit>, max_violations: usize) -> Self {
+        InboundRateLimiter {
+            buckets: HashMap::new(),
+            limits,
+            violations: 0,
+            window_start: None,
+            max_violations,
+        }
+    }
+
+    /// Check `msg` against its rate limit, at time `now`.
+    pub(super) fn check(&mut self, msg: &Message, now: Instant) -> Decision {
+        let command = msg.command();
+        if !self.buckets.contains_key(command) {
+            match self.limits.get(command) {
+                Some(limit) => {
+                    self.buckets.insert(command, TokenBucket::new(*limit, now));
+                }
+                None => return Decision::Allow,
+            }
+        }
+        let bucket = self
+            .buckets
+            .get_mut(command)
+            .expect("bucket was inserted above");
+
+        if bucket.try_take(now) {
+            Decision::Allow
+        } else {
+            let window_expired = self.window_start.map_or(true, |start| {
+                now.saturating_duration_since(start) >= constants::RATE_LIMIT_VIOLATION_WINDOW
+            });
+            if window_expired {
+                self.window_start = Some(now);
+                self.violations = 0;
+            }
+
+            self.violations += 1;
+            if self.violations > self.max_violations {
+                Decision::Disconnect
+            } else {
+                Decision::Drop
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn limiter(burst: u32, per_second: f64, max_violations: usize) -> InboundRateLimiter {
+        let mut limits = HashMap::new();
+        limits.insert("getaddr".to_owned(), RateLimit { burst, per_second });
+        InboundRateLimiter::new(limits, max_violations)
+    }
+
+    #[test]
+    fn burst_then_drop_then_disconnect() {
+        let now = Instant::now();
+        let mut limiter = limiter(2, 1.0, 1);
+
+        assert_eq!(limiter.check(&Message::GetAddr, now), Decision::Allow);
+        assert_eq!(limiter.check(&Message::GetAddr, now), Decision::Allow);
+        assert_eq!(limiter.check(&Message::GetAddr, now), Decision::Drop);
+        assert_eq!(limiter.check(&Message::GetAddr, now), Decision::Disconnect);
+
+        // Messages without a limit are unaffected.
+        assert_eq!(limiter.check(&Message::Mempool, now), Decision::Allow);
+    }
+
+    #[test]
+    fn tokens_refill_over_time() {
+        let now = Instant::now();
+        let mut limiter = limiter(1, 2.0, 10);
+
+        assert_eq!(limiter.check(&Message::GetAddr, now), Decision::Allow);
+        assert_eq!(limiter.check(&Message::GetAddr, now), Decision::Drop);
+
+        let later = now + Duration::from_millis(500);
+        assert_eq!(limiter.check(&Message::GetAddr, later), Decision::Allow);
+    }
+
+    #[test]
+    fn violations_reset_after_window() {
+        let now = Instant::now();
+        let mut limiter = limiter(1, 0.0, 1);
+
+        assert_eq!(limiter.check(&Message::GetAddr, now), Decision::Allow);
+        assert_eq!(limiter.check(&Message::GetAddr, now), Decision::Drop);
+
+        let later = now + constants::RATE_LIMIT_VIOLATION_WINDOW;
+        assert_eq!(limiter.check(&Message::GetAddr, later), Decision::Drop);
+        assert_eq!(
+            limiter.check(&Message::GetAddr, later),
+            Decision::Disconnect
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! Runtime control of the peer set, for operators.
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use futures::channel::mpsc;
+
+use zebra_chain::block;
+
+use crate::{
+    block_sources::BlockSources, ip_filter::BanList, BoxedStdError, ConnectedPeers, IpNetwork,
+};
+
+/// A handle that adds, disconnects, and bans the peers of a peer set.
+///
+/// Changes only last until the node restarts. Use the network config to
+/// change peers permanently.
+#[derive(Clone, Debug)]
+pub struct PeerControl {
+    connected_peers: Arc<Mutex<ConnectedPeers>>,
+    ban_list: BanList,
+    /// The peers that sent each downloaded block.
+    block_sources: BlockSources,
+    /// Adds addresses to the crawler's candidates, or `None` in connect-only
+    /// mode.
+    new_peer_tx: Option<mpsc::Sender<SocketAddr>>,
+}
+
+impl PeerControl {
+    /// Returns a handle for `connected_peers` that can't add new peers.
+    pub fn new(connected_peers: Arc<Mutex<ConnectedPeers>>) -> Self {
+        PeerControl::with_crawler(
+            connected_peers,
+            BanList::default(),
+            BlockSources::default(),
+            None,
+        )
+    }
+
+    pub(crate) fn with_crawler(
+        connected_peers: Arc<Mutex<ConnectedPeers>>,
+        ban_list: BanList,
+        block_sources: BlockSources,
+        new_peer_tx: Option<mpsc::Sender<SocketAddr>>,
+    ) -> Self {
+        PeerControl {
+            connected_peers,
+            ban_list,
+            block_sources,
+            new_peer_tx,
+        }
+    }
+
+    /// Returns the currently connected peers.
+    pub fn connected_peers(&self) -> &Arc<Mutex<ConnectedPeers>> {
+        &self.connected_peers
+    }
+
+    /// Ask the crawler to connect to `addr`.
+    ///
+    /// The crawler connects when it next needs an outbound peer, so this
+    /// returns before the connection is made.
+    pub fn add_peer(&self, addr: SocketAddr) -> Result<(), BoxedStdError> {
+        if self.ban_list.is_banned(addr.ip()) {
+            return Err(format!("{} is banned", addr).into());
+        }
+        let mut new_peer_tx = self
+            .new_peer_tx
+            .clone()
+            .ok_or("peers can't be added in connect-only mode")?;
+        new_peer_tx
+            .try_send(addr)
+            .map_err(|_| "too many peers are waiting to be added")?;
+        Ok(())
+    }
+
+    /// Close the connection to the peer at `addr`.
+    ///
+    /// Returns false if the peer is not connected.
+    pub fn disconnect(&self, addr: &SocketAddr) -> bool {
+        self.connected_peers
+            .lock()
+            .expect("mutex should be unpoisoned")
+            .evict(addr)
+    }
+
+    /// Disconnect the peer that sent the block with `hash`, because the
+    /// block is invalid.
+    ///
+    /// Returns the peer's address, or `None` if the block wasn't downloaded
+    /// by the peer set recently.
+    pub fn report_invalid_block(&self, hash: &block::Hash) -> Option<SocketAddr> {
+        let addr = self.block_sources.get(hash)?;
+        self.disconnect(&addr);
+        Some(addr)
+    }
+
+    /// Ban `net` until `until`, and disconnect any connected peers in it.
+    pub fn ban(&self, net: IpNetwork, until: DateTime<Utc>) {
+        self.ban_list.ban(net, until);
+
+        let mut connected_peers = self
+            .connected_peers
+            .lock()
+            .expect("mutex should be unpoisoned");
+        let banned: Vec<SocketAddr> = connected_peers
+            .peers()
+            .map(|info| info.addr)
+            .filter(|addr| net.contains(addr.ip()))
+            .collect();
+        for addr in banned {
+            connected_peers.evict(&addr);
+        }
+    }
+
+    /// Remove the ban on `net`, returning false if it wasn't banned.
+    pub fn unban(&self, net: &IpNetwork) -> bool {
+        self.ban_list.unban(net)
+    }
+
+    /// Returns the current bans, and when they expire.
+    pub fn bans(&self) -> Vec<(IpNetwork, DateTime<Utc>)> {
+        self.ban_list.bans()
+    }
+
+    /// Remove every ban.
+    pub fn clear_bans(&self) {
+        self.ban_list.clear();
+    }
+}
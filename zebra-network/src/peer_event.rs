@@ -0,0 +1,53 @@
+//! Events describing the lifecycle of peer connections.
+
+use std::net::SocketAddr;
+
+use crate::{protocol::external::types::Version, Direction};
+
+/// An event in the lifecycle of a peer connection.
+///
+/// Events are sent on the broadcast channel returned by [`init`](crate::init),
+/// so that other components can observe connection churn. Each `Connecting`
+/// event is eventually followed by a `Disconnected` event for the same
+/// address, whether or not the handshake completed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PeerEvent {
+    /// A TCP connection is open, and the handshake is starting.
+    Connecting {
+        /// The peer's address.
+        addr: SocketAddr,
+        /// Whether we connected to the peer, or the peer connected to us.
+        direction: Direction,
+    },
+    /// The handshake finished, and the peer is ready for requests.
+    HandshakeCompleted {
+        /// The peer's address.
+        addr: SocketAddr,
+        /// The protocol version the peer sent in its `version` message.
+        version: Version,
+        /// The peer's user agent.
+        user_agent: String,
+    },
+    /// One of our requests to the peer failed, but the connection is still
+    /// open.
+    RequestFailed {
+        /// The peer's address.
+        addr: SocketAddr,
+        /// Why the request failed.
+        error: String,
+    },
+    /// Our inbound service failed to answer one of the peer's requests.
+    InboundRequestFailed {
+        /// The peer's address.
+        addr: SocketAddr,
+        /// Why the request failed.
+        error: String,
+    },
+    /// The connection closed, or the handshake failed.
+    Disconnected {
+        /// The peer's address.
+        addr: SocketAddr,
+        /// Why the connection closed.
+        reason: String,
+    },
+}
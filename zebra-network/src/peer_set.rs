@@ -1,9 +1,18 @@
 mod candidate_set;
+mod eviction;
 mod initialize;
+mod inventory_registry;
+mod limit;
+mod netgroup;
+mod seeder;
 mod set;
+mod shutdown;
 mod unready_service;
 
 use candidate_set::CandidateSet;
+pub(crate) use limit::ConnectionTracker;
 use set::PeerSet;
 
 pub use initialize::init;
+pub use set::RoutingError;
+pub use shutdown::Shutdown;
@@ -1,11 +1,22 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
 
-use chrono::Utc;
-use futures::stream::{FuturesUnordered, StreamExt};
 use tower::{Service, ServiceExt};
 use tracing::Level;
 
-use crate::{types::MetaAddr, AddressBook, BoxedStdError, Request, Response};
+use zebra_chain::serialization::DateTime32;
+
+use crate::{
+    constants,
+    ip_filter::IpFilter,
+    types::{MetaAddr, PeerServices},
+    AddressBook, BoxedStdError, Request, Response,
+};
+
+use super::netgroup::NetGroup;
 
 /// The `CandidateSet` maintains a pool of candidate peers.
 ///
@@ -74,12 +85,20 @@ use crate::{types::MetaAddr, AddressBook, BoxedStdError, Request, Response};
 ///    │to Discover │
 ///    └────────────┘
 /// ```
+///
+/// If `only_connect_to` is set, we don't ask peers for addresses, and the
+/// only candidates are those fixed peers. Candidates that `ip_filter` rejects
+/// are dropped, as are `self_addrs`, where we found ourselves during a
+/// handshake.
 pub(super) struct CandidateSet<S> {
     pub(super) disconnected: AddressBook,
     pub(super) gossiped: AddressBook,
     pub(super) failed: AddressBook,
     pub(super) peer_set: Arc<Mutex<AddressBook>>,
     pub(super) peer_service: S,
+    pub(super) only_connect_to: Option<HashSet<SocketAddr>>,
+    pub(super) ip_filter: IpFilter,
+    pub(super) self_addrs: Arc<Mutex<HashSet<SocketAddr>>>,
 }
 
 impl<S> CandidateSet<S>
@@ -87,38 +106,82 @@ where
     S: Service<Request, Response = Response, Error = BoxedStdError>,
     S::Future: Send + 'static,
 {
-    pub fn new(peer_set: Arc<Mutex<AddressBook>>, peer_service: S) -> CandidateSet<S> {
-        CandidateSet {
+    pub fn new(
+        peer_set: Arc<Mutex<AddressBook>>,
+        peer_service: S,
+        only_connect_to: Option<HashSet<SocketAddr>>,
+        ip_filter: IpFilter,
+        self_addrs: Arc<Mutex<HashSet<SocketAddr>>>,
+    ) -> CandidateSet<S> {
+        let mut candidates = CandidateSet {
             disconnected: AddressBook::new(span!(Level::TRACE, "disconnected peers")),
             gossiped: AddressBook::new(span!(Level::TRACE, "gossiped peers")),
             failed: AddressBook::new(span!(Level::TRACE, "failed peers")),
             peer_set,
             peer_service,
+            only_connect_to,
+            ip_filter,
+            self_addrs,
+        };
+        if let Some(peers) = candidates.only_connect_to.clone() {
+            candidates.add_fixed_peers(peers);
         }
+        candidates
+    }
+
+    /// Add `addrs` to the gossiped peers, as if a peer had just told us about
+    /// them.
+    ///
+    /// Addresses that have failed, or are already in the peer set, are skipped.
+    pub fn add_fixed_peers(&mut self, addrs: impl IntoIterator<Item = SocketAddr>) {
+        let now = DateTime32::now();
+        let failed = &self.failed;
+        let peer_set = self.peer_set.lock().expect("mutex must be unpoisoned");
+        self.gossiped.extend(
+            addrs
+                .into_iter()
+                .filter(|addr| !failed.contains_addr(addr))
+                .filter(|addr| !peer_set.contains_addr(addr))
+                .map(|addr| MetaAddr {
+                    addr,
+                    services: PeerServices::NODE_NETWORK,
+                    last_seen: now,
+                }),
+        );
     }
 
     pub async fn update(&mut self) -> Result<(), BoxedStdError> {
+        if let Some(peers) = self.only_connect_to.clone() {
+            // Don't crawl the network. Instead, make sure any fixed peers that
+            // have disconnected are candidates again.
+            let peer_set = self.peer_set.lock().expect("mutex must be unpoisoned");
+            let disconnected: Vec<MetaAddr> = peer_set
+                .disconnected_peers()
+                .filter(|meta| peers.contains(&meta.addr))
+                .filter(|meta| !self.failed.contains_addr(&meta.addr))
+                .collect();
+            std::mem::drop(peer_set);
+            self.disconnected.extend(disconnected);
+            return Ok(());
+        }
+
         // Opportunistically crawl the network on every update call to ensure
         // we're actively fetching peers. Continue independently of whether we
         // actually receive any peers, but always ask the network for more.
-        // Because requests are load-balanced across existing peers, we can make
-        // multiple requests concurrently, which will be randomly assigned to
-        // existing peers, but we don't make too many because update may be
-        // called while the peer set is already loaded.
-        let mut responses = FuturesUnordered::new();
-        for _ in 0..2usize {
-            self.peer_service.ready_and().await?;
-            responses.push(self.peer_service.call(Request::Peers));
-        }
-        while let Some(rsp) = responses.next().await {
-            if let Ok(Response::Peers(addrs)) = rsp {
+        // The peer set sends the request to several peers and merges their
+        // answers, so one peer can't fill the address book on its own.
+        self.peer_service.ready_and().await?;
+        match self.peer_service.call(Request::Peers).await {
+            Ok(Response::Peers(addrs)) => {
                 let addr_len = addrs.len();
                 let prev_len = self.gossiped.len();
                 // Filter new addresses to ensure that gossiped
                 let failed = &self.failed;
                 let peer_set = &self.peer_set;
+                let ip_filter = &self.ip_filter;
                 let new_addrs = addrs
                     .into_iter()
+                    .filter(|meta| ip_filter.is_allowed(meta.addr.ip()))
                     .filter(|meta| !failed.contains_addr(&meta.addr))
                     .filter(|meta| !peer_set.lock().unwrap().contains_addr(&meta.addr));
                 self.gossiped.extend(new_addrs);
@@ -127,9 +190,8 @@ where
                     new_addrs = self.gossiped.len() - prev_len,
                     "got response to GetPeers"
                 );
-            } else {
-                trace!("got error in GetPeers request");
             }
+            _ => trace!("got error in GetPeers request"),
         }
 
         // Determine whether any known peers have recently disconnected.
@@ -146,20 +208,261 @@ where
         Ok(())
     }
 
+    /// Returns the next peer to connect to, or `None` if there are no
+    /// suitable candidates.
+    ///
+    /// Disconnected peers are tried first, starting with the peers that had
+    /// the lowest round-trip times. Candidates in the same network group as
+    /// a connected peer are kept for later, so that our connections are
+    /// spread across network operators.
+    /// Failed peers are only retried after
+    /// [`constants::MIN_PEER_RECONNECTION_DELAY`].
     pub fn next(&mut self) -> Option<MetaAddr> {
         metrics::gauge!("candidate_set.disconnected", self.disconnected.len() as i64);
         metrics::gauge!("candidate_set.gossiped", self.gossiped.len() as i64);
         metrics::gauge!("candidate_set.failed", self.failed.len() as i64);
         let guard = self.peer_set.lock().unwrap();
-        self.disconnected
-            .drain_oldest()
-            .chain(self.gossiped.drain_newest())
-            .chain(self.failed.drain_oldest())
-            .find(|meta| !guard.is_potentially_connected(&meta.addr))
+        let is_connected = |meta: &MetaAddr| guard.is_potentially_connected(&meta.addr);
+        // Fixed peers were chosen by the user, so we connect to all of them,
+        // even if they share a network group.
+        let connected_groups: HashSet<NetGroup> = if self.only_connect_to.is_some() {
+            HashSet::new()
+        } else {
+            guard
+                .peers()
+                .filter(|meta| is_connected(meta))
+                .map(|meta| NetGroup::from(meta.addr.ip()))
+                .collect()
+        };
+
+        let self_addrs = self.self_addrs.lock().expect("mutex must be unpoisoned");
+        let ip_filter = &self.ip_filter;
+        let is_unusable = |meta: &MetaAddr| {
+            is_connected(meta)
+                || self_addrs.contains(&meta.addr)
+                || !ip_filter.is_allowed(meta.addr.ip())
+        };
+
+        let mut deferred = Vec::new();
+
+        // Reconnect to the peers with the lowest round-trip times first, then
+        // to the peers we never measured.
+        let mut disconnected: Vec<MetaAddr> = self.disconnected.drain_oldest().collect();
+        disconnected.sort_by_key(|meta| {
+            let rtt = guard.rtt(&meta.addr);
+            (rtt.is_none(), rtt)
+        });
+        let mut disconnected = disconnected.into_iter();
+        let candidate = find_candidate(
+            &mut disconnected,
+            |_| true,
+            is_unusable,
+            &connected_groups,
+            &mut deferred,
+        );
+        self.disconnected.extend(disconnected);
+        self.disconnected.extend(deferred.drain(..));
+        if candidate.is_some() {
+            return candidate;
+        }
+
+        let candidate = find_candidate(
+            self.gossiped.drain_newest(),
+            |_| true,
+            is_unusable,
+            &connected_groups,
+            &mut deferred,
+        );
+        self.gossiped.extend(deferred.drain(..));
+        if candidate.is_some() {
+            return candidate;
+        }
+
+        let retry_cutoff = DateTime32::now().saturating_sub(constants::MIN_PEER_RECONNECTION_DELAY);
+        let candidate = find_candidate(
+            self.failed.drain_oldest(),
+            |meta| meta.last_seen <= retry_cutoff,
+            is_unusable,
+            &connected_groups,
+            &mut deferred,
+        );
+        self.failed.extend(deferred.drain(..));
+        candidate
     }
 
     pub fn report_failed(&mut self, mut addr: MetaAddr) {
-        addr.last_seen = Utc::now();
+        addr.last_seen = DateTime32::now();
         self.failed.update(addr);
     }
 }
+
+/// Returns the first candidate in `candidates` that we can connect to.
+///
+/// Peers that are `unusable`, because they are connected, filtered, or our
+/// own address, are dropped. Peers in `connected_groups` are moved to
+/// `deferred`. The search stops at the first peer that is not `ready`, which
+/// is also moved to `deferred`.
+fn find_candidate(
+    candidates: impl Iterator<Item = MetaAddr>,
+    ready: impl Fn(&MetaAddr) -> bool,
+    unusable: impl Fn(&MetaAddr) -> bool,
+    connected_groups: &HashSet<NetGroup>,
+    deferred: &mut Vec<MetaAddr>,
+) -> Option<MetaAddr> {
+    for meta in candidates {
+        if !ready(&meta) {
+            deferred.push(meta);
+            return None;
+        }
+        if unusable(&meta) {
+            continue;
+        }
+        if connected_groups.contains(&NetGroup::from(meta.addr.ip())) {
+            deferred.push(meta);
+            continue;
+        }
+        return Some(meta);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use futures::future;
+
+    use crate::ip_filter::BanList;
+
+    type Ready = future::Ready<Result<Response, BoxedStdError>>;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    /// Returns a candidate set with `peer_set`, which panics if it asks the
+    /// network for more peers.
+    fn candidate_set(
+        peer_set: Arc<Mutex<AddressBook>>,
+        only_connect_to: Option<HashSet<SocketAddr>>,
+    ) -> CandidateSet<
+        impl Service<Request, Response = Response, Error = BoxedStdError, Future = Ready>,
+    > {
+        let peer_service = tower::service_fn(|_req: Request| -> Ready {
+            panic!("the candidate set should not crawl the network")
+        });
+        CandidateSet::new(
+            peer_set,
+            peer_service,
+            only_connect_to,
+            IpFilter::new(Vec::new(), Vec::new(), BanList::default()),
+            Arc::new(Mutex::new(HashSet::new())),
+        )
+    }
+
+    fn peer_set(peers: &[(SocketAddr, DateTime32)]) -> Arc<Mutex<AddressBook>> {
+        let mut address_book = AddressBook::new(span!(Level::TRACE, "test peers"));
+        for &(addr, last_seen) in peers {
+            address_book.update(MetaAddr {
+                addr,
+                services: PeerServices::NODE_NETWORK,
+                last_seen,
+            });
+        }
+        Arc::new(Mutex::new(address_book))
+    }
+
+    #[test]
+    fn only_connect_to_peers_are_candidates() {
+        let connected = addr("192.0.2.3:8233");
+        let fixed: HashSet<SocketAddr> =
+            vec![addr("192.0.2.1:8233"), addr("192.0.2.2:8233"), connected]
+                .into_iter()
+                .collect();
+        let peer_set = peer_set(&[(connected, DateTime32::now())]);
+        let mut candidates = candidate_set(peer_set, Some(fixed.clone()));
+
+        // Fixed peers are all candidates, even though they share a network
+        // group, except for the peer we're already connected to.
+        let mut next = HashSet::new();
+        while let Some(meta) = candidates.next() {
+            assert!(next.insert(meta.addr));
+        }
+        let mut expected = fixed;
+        expected.remove(&connected);
+        assert_eq!(next, expected);
+    }
+
+    #[test]
+    fn connect_only_updates_reconnect_to_fixed_peers() {
+        let fixed = addr("192.0.2.1:8233");
+        let other = addr("198.51.100.1:8233");
+        let long_ago = DateTime32::now().saturating_sub(2 * constants::LIVE_PEER_DURATION);
+        let peer_set = peer_set(&[(fixed, long_ago), (other, long_ago)]);
+        let mut candidates = candidate_set(peer_set, Some(vec![fixed].into_iter().collect()));
+        assert!(candidates.next().is_none());
+
+        // Updates don't crawl, and only add disconnected fixed peers.
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(candidates.update())
+            .unwrap();
+        assert_eq!(candidates.next().map(|meta| meta.addr), Some(fixed));
+        assert!(candidates.next().is_none());
+    }
+
+    #[test]
+    fn closer_peers_are_reconnected_first() {
+        let long_ago = DateTime32::now().saturating_sub(2 * constants::LIVE_PEER_DURATION);
+        let far = addr("192.0.2.1:8233");
+        let unmeasured = addr("198.51.100.1:8233");
+        let near = addr("203.0.113.1:8233");
+        let peer_set = peer_set(&[(far, long_ago), (unmeasured, long_ago), (near, long_ago)]);
+        {
+            let mut peer_set = peer_set.lock().unwrap();
+            peer_set.record_rtt(far, Duration::from_millis(900));
+            peer_set.record_rtt(near, Duration::from_millis(30));
+        }
+        let mut candidates = candidate_set(peer_set.clone(), None);
+        candidates
+            .disconnected
+            .extend(peer_set.lock().unwrap().disconnected_peers());
+
+        let order: Vec<SocketAddr> = std::iter::from_fn(|| candidates.next())
+            .map(|meta| meta.addr)
+            .collect();
+        assert_eq!(order, vec![near, far, unmeasured]);
+    }
+
+    #[test]
+    fn self_addrs_are_not_candidates() {
+        let own = addr("192.0.2.1:8233");
+        let other = addr("198.51.100.1:8233");
+        let mut candidates = candidate_set(peer_set(&[]), None);
+        candidates.add_fixed_peers(vec![own, other]);
+        candidates.self_addrs.lock().unwrap().insert(own);
+
+        assert_eq!(candidates.next().map(|meta| meta.addr), Some(other));
+        assert!(candidates.next().is_none());
+    }
+
+    #[test]
+    fn fixed_peers_skip_known_addresses() {
+        let connected = addr("192.0.2.1:8233");
+        let failed = addr("198.51.100.1:8233");
+        let new = addr("203.0.113.1:8233");
+        let peer_set = peer_set(&[(connected, DateTime32::now())]);
+        let mut candidates = candidate_set(peer_set, None);
+        candidates.report_failed(MetaAddr {
+            addr: failed,
+            services: PeerServices::NODE_NETWORK,
+            last_seen: DateTime32::now(),
+        });
+
+        candidates.add_fixed_peers(vec![connected, failed, new]);
+        assert_eq!(candidates.gossiped.len(), 1);
+        assert!(candidates.gossiped.contains_addr(&new));
+    }
+}
@@ -0,0 +1,168 @@
+//! Choosing an inbound peer to evict when the inbound connection limit is
+//! reached.
+//!
+//! Like `bitcoind`, we protect the inbound peers that would be hardest for an
+//! attacker to replace, then evict the newest peer from the network group
+//! with the most remaining connections. This makes it expensive for an
+//! attacker to take over our inbound slots just by opening many connections.
+
+use std::{collections::HashMap, net::SocketAddr};
+
+use rand::{seq::SliceRandom, Rng};
+
+use crate::{constants, PeerInfo};
+
+use super::netgroup::NetGroup;
+
+/// Choose one of the `candidates` to evict, so that a new inbound peer can
+/// connect, using `rng` to choose which network groups are protected.
+///
+/// These peers are protected from eviction, in order:
+/// - the longest-connected peer in each of
+///   [`constants::EVICTION_PROTECTED_NETGROUPS`] random network groups,
+/// - the [`constants::EVICTION_PROTECTED_LOW_PING`] peers with the lowest
+///   heartbeat ping,
+/// - half of the remaining peers, choosing the longest-connected.
+///
+/// Returns `None` if every candidate is protected.
+pub(super) fn select_peer_to_evict<'a, R: Rng>(
+    candidates: impl IntoIterator<Item = &'a PeerInfo>,
+    rng: &mut R,
+) -> Option<SocketAddr> {
+    let mut candidates: Vec<&PeerInfo> = candidates.into_iter().collect();
+
+    // Protect network diversity. The groups are random, so an attacker can't
+    // predict which of their addresses would be protected.
+    let mut groups: Vec<NetGroup> = candidates
+        .iter()
+        .map(|info| NetGroup::from(info.addr.ip()))
+        .collect();
+    groups.sort_by_key(group_order);
+    groups.dedup();
+    groups.shuffle(rng);
+    for group in groups
+        .into_iter()
+        .take(constants::EVICTION_PROTECTED_NETGROUPS)
+    {
+        if let Some(oldest) = candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, info)| NetGroup::from(info.addr.ip()) == group)
+            .min_by_key(|(_, info)| info.connected_at)
+            .map(|(i, _)| i)
+        {
+            candidates.swap_remove(oldest);
+        }
+    }
+
+    // Protect fast peers. Peers that haven't answered a ping sort last.
+    candidates.sort_by_key(|info| (info.min_ping.is_none(), info.min_ping));
+    let protected = constants::EVICTION_PROTECTED_LOW_PING.min(candidates.len());
+    candidates.drain(..protected);
+
+    // Protect long-lived peers.
+    candidates.sort_by_key(|info| info.connected_at);
+    let protected = candidates.len() / 2;
+    candidates.drain(..protected);
+
+    // Evict the newest peer from the group with the most connections.
+    let mut by_group: HashMap<NetGroup, Vec<&PeerInfo>> = HashMap::new();
+    for info in candidates {
+        by_group
+            .entry(NetGroup::from(info.addr.ip()))
+            .or_default()
+            .push(info);
+    }
+    by_group
+        .values()
+        .max_by_key(|peers| {
+            let newest = peers.iter().map(|info| info.connected_at).max();
+            (peers.len(), newest)
+        })
+        .and_then(|peers| peers.iter().max_by_key(|info| info.connected_at))
+        .map(|info| info.addr)
+}
+
+/// A total order on network groups, so that duplicates can be removed before
+/// the groups are shuffled.
+fn group_order(group: &NetGroup) -> (u8, [u8; 4]) {
+    match *group {
+        NetGroup::V4([a, b]) => (4, [a, b, 0, 0]),
+        NetGroup::V6(prefix) => (6, prefix),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use zebra_chain::block;
+
+    use crate::{protocol::external::types::*, Direction};
+
+    use super::*;
+
+    fn inbound_peer(addr: &str, connected_at: DateTime<Utc>, ping_ms: Option<u64>) -> PeerInfo {
+        PeerInfo {
+            addr: addr.parse().unwrap(),
+            direction: Direction::Inbound {
+                listen_addr: "0.0.0.0:8233".parse().unwrap(),
+            },
+            version: Version(170_013),
+            negotiated_version: Version(170_013),
+            services: PeerServices::NODE_NETWORK,
+            user_agent: "/test/".to_owned(),
+            start_height: block::Height(0),
+            relay: true,
+            connected_at,
+            in_flight_requests: 0,
+            last_request: None,
+            last_response: None,
+            timed_out_requests: 0,
+            min_ping: ping_ms.map(Duration::from_millis),
+        }
+    }
+
+    #[test]
+    fn evicts_newest_peer_in_busiest_group() {
+        let mut peers = Vec::new();
+        // One slow peer in each of many groups, connected long ago.
+        for i in 0..20 {
+            peers.push(inbound_peer(
+                &format!("10.{}.0.1:8233", i),
+                Utc.timestamp(1_000 + i, 0),
+                Some(500),
+            ));
+        }
+        // Fast peers, which are all protected.
+        for i in 0..constants::EVICTION_PROTECTED_LOW_PING as i64 {
+            peers.push(inbound_peer(
+                &format!("172.16.{}.1:8233", i),
+                Utc.timestamp(5_000 + i, 0),
+                Some(10),
+            ));
+        }
+        // Many recent connections from a single group.
+        for i in 0..10 {
+            peers.push(inbound_peer(
+                &format!("192.0.2.{}:8233", i),
+                Utc.timestamp(10_000 + i, 0),
+                None,
+            ));
+        }
+
+        let evicted = select_peer_to_evict(&peers, &mut rand::thread_rng());
+        assert_eq!(evicted, Some("192.0.2.9:8233".parse().unwrap()));
+    }
+
+    #[test]
+    fn small_peer_sets_are_protected() {
+        let peers: Vec<PeerInfo> = (0..constants::EVICTION_PROTECTED_NETGROUPS as i64)
+            .map(|i| inbound_peer(&format!("10.{}.0.1:8233", i), Utc.timestamp(i, 0), None))
+            .collect();
+
+        assert_eq!(select_peer_to_evict(&peers, &mut rand::thread_rng()), None);
+    }
+}
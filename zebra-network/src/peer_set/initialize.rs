@@ -4,7 +4,8 @@
 // which is (c) 2019 Tower Contributors (MIT licensed).
 
 use std::{
-    net::SocketAddr,
+    collections::HashSet,
+    net::{IpAddr, SocketAddr},
     sync::{Arc, Mutex},
 };
 
@@ -14,7 +15,10 @@ use futures::{
     sink::SinkExt,
     stream::{FuturesUnordered, StreamExt},
 };
-use tokio::net::{TcpListener, TcpStream};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::broadcast,
+};
 use tower::{
     buffer::Buffer,
     discover::{Change, ServiceStream},
@@ -24,19 +28,39 @@ use tower::{
 use tower_load::{peak_ewma::PeakEwmaDiscover, NoInstrument};
 
 use crate::{
-    peer, timestamp_collector::TimestampCollector, AddressBook, BoxedStdError, Config, Request,
-    Response,
+    block_sources::BlockSources,
+    constants,
+    ip_filter::{BanList, IpFilter},
+    peer,
+    peer_control::PeerControl,
+    timestamp_collector::TimestampCollector,
+    AddressBook, BestTipHeight, BoxedStdError, Config, ConnectedPeers, Direction, PeerEvent,
+    Request, Response,
 };
 
-use super::CandidateSet;
 use super::PeerSet;
+use super::{
+    eviction::select_peer_to_evict,
+    limit::ActiveConnectionCounter,
+    seeder::{reseed_when_low, resolve_peers, resolve_seeds, Proxy},
+    CandidateSet, Shutdown,
+};
 
 type PeerChange = Result<Change<SocketAddr, peer::Client>, BoxedStdError>;
 
 /// Initialize a peer set with the given `config`, forwarding peer requests to the `inbound_service`.
+///
+/// Peers whose protocol version is obsolete at `best_tip_height` are rejected
+/// during the handshake, and disconnected when a network upgrade activates.
+///
+/// Returns the peer set, its address book, its currently connected peers, a
+/// sender for the [`PeerEvent`]s of its connections, which can be subscribed
+/// to, a handle that stops its listeners and crawler, and a [`PeerControl`]
+/// that adds, disconnects, and bans peers.
 pub async fn init<S>(
     config: Config,
     inbound_service: S,
+    best_tip_height: BestTipHeight,
 ) -> (
     impl Service<
             Request,
@@ -47,24 +71,40 @@ pub async fn init<S>(
         + Clone
         + 'static,
     Arc<Mutex<AddressBook>>,
+    Arc<Mutex<ConnectedPeers>>,
+    broadcast::Sender<PeerEvent>,
+    Shutdown,
+    PeerControl,
 )
 where
     S: Service<Request, Response = Response, Error = BoxedStdError> + Clone + Send + 'static,
     S::Future: Send + 'static,
 {
     let (address_book, timestamp_collector) = TimestampCollector::spawn();
+    let (inv_sender, inv_receiver) = mpsc::channel(constants::INVENTORY_CHANNEL_SIZE);
+    let connected_peers = Arc::new(Mutex::new(ConnectedPeers::new()));
+    let (events, _) = broadcast::channel(constants::PEER_EVENT_CHANNEL_SIZE);
+    let shutdown = Shutdown::default();
+    let block_sources = BlockSources::default();
 
     // Construct services that handle inbound handshakes and perform outbound
     // handshakes. These use the same handshake service internally to detect
-    // self-connection attempts. Both are decorated with a tower TimeoutLayer to
-    // enforce timeouts as specified in the Config.
-    let (listener, connector) = {
-        use tower::timeout::TimeoutLayer;
-        let hs_timeout = TimeoutLayer::new(config.handshake_timeout);
-        let hs = peer::Handshake::new(config.clone(), inbound_service, timestamp_collector);
+    // self-connection attempts. The connect and handshake timeouts from the
+    // Config are enforced by the services themselves.
+    let (listener, connector, self_addrs) = {
+        let hs = peer::Handshake::new(
+            config.clone(),
+            inbound_service,
+            timestamp_collector,
+            best_tip_height,
+            inv_sender,
+            connected_peers.clone(),
+            events.clone(),
+        );
         (
-            hs_timeout.layer(hs.clone()),
-            hs_timeout.layer(peer::Connector::new(hs)),
+            hs.clone(),
+            peer::Connector::new(hs.clone(), config.proxy, config.connect_timeout),
+            hs.self_addrs(),
         )
     };
 
@@ -88,23 +128,99 @@ where
         ),
         demand_tx.clone(),
         handle_rx,
+        inv_receiver,
+        connected_peers.clone(),
+        block_sources.clone(),
     );
     let peer_set = Buffer::new(peer_set, config.peerset_request_buffer_size);
 
     // Connect the tx end to the 3 peer sources:
 
-    // 1. Initial peers, specified in the config.
-    let add_guard = tokio::spawn(add_initial_peers(
-        config.initial_peers(),
+    // Outbound connections from the initial peers and the crawler count
+    // towards the target outbound peer count.
+    let outbound_connections = ActiveConnectionCounter::new(config.target_outbound_peers);
+
+    // In connect-only mode, the crawler dials the fixed peers, and we don't
+    // use any other peer sources.
+    let proxy = Proxy::from_config(&config);
+    let only_connect_to = if config.only_connect_to.is_empty() {
+        None
+    } else {
+        Some(resolve_peers(proxy, &config.only_connect_to).await)
+    };
+
+    let ban_list = BanList::default();
+    let ip_filter = config.ip_filter(ban_list.clone());
+
+    // 1. Initial peers, specified in the config. If the network is low on
+    //    peers later, we resolve these DNS seeders again.
+    let mut initial_peers = if only_connect_to.is_some() {
+        HashSet::new()
+    } else {
+        resolve_seeds(proxy, config.initial_seed_peer_names()).await
+    };
+    initial_peers.retain(|addr| ip_filter.is_allowed(addr.ip()));
+    let add_guard = shutdown.spawn(add_initial_peers(
+        initial_peers,
         connector.clone(),
+        outbound_connections.clone(),
         peerset_tx.clone(),
     ));
 
-    // 2. Incoming peer connections, via a listener.
-    let listen_guard = tokio::spawn(listen(config.listen_addr, listener, peerset_tx.clone()));
+    // 2. Incoming peer connections, via a listener on each listen address.
+    // The listeners share the inbound connection limit.
+    let inbound_connections = ActiveConnectionCounter::new(config.max_inbound_connections);
+    let allowed_inbound_ips: Option<HashSet<IpAddr>> = only_connect_to
+        .as_ref()
+        .map(|peers| peers.iter().map(SocketAddr::ip).collect());
+    let listen_guards = config.listen_addrs.iter().map(|&listen_addr| {
+        shutdown.spawn(listen(
+            listen_addr,
+            inbound_connections.clone(),
+            allowed_inbound_ips.clone(),
+            ip_filter.clone(),
+            connected_peers.clone(),
+            listener.clone(),
+            peerset_tx.clone(),
+        ))
+    });
+    let mut guards = vec![add_guard];
+    guards.extend(listen_guards);
+
+    let (seed_tx, seed_rx) = mpsc::channel(100);
+    // Peers added at runtime go to the crawler with the seeded peers.
+    let new_peer_tx = if only_connect_to.is_none() {
+        Some(seed_tx.clone())
+    } else {
+        None
+    };
+    let peer_control = PeerControl::with_crawler(
+        connected_peers.clone(),
+        ban_list,
+        block_sources,
+        new_peer_tx,
+    );
+    if only_connect_to.is_none() {
+        guards.push(shutdown.spawn(reseed_when_low(
+            proxy,
+            config.initial_seed_peer_names().clone(),
+            address_book.clone(),
+            config.target_outbound_peers,
+            seed_tx,
+        )));
+    }
 
     // 3. Outgoing peers we connect to in response to load.
-    let mut candidates = CandidateSet::new(address_book.clone(), peer_set.clone());
+    let mut candidates = CandidateSet::new(
+        address_book.clone(),
+        peer_set.clone(),
+        only_connect_to,
+        ip_filter,
+        self_addrs,
+    );
+    if candidates.only_connect_to.is_none() {
+        candidates.add_fixed_peers(resolve_peers(proxy, &config.initial_peers).await);
+    }
 
     // We need to await candidates.update() here, because Zcashd only sends one
     // `addr` message per connection, and if we only have one initial peer we
@@ -114,32 +230,47 @@ where
 
     info!("Sending initial request for peers");
 
-    for _ in 0..config.peerset_initial_target_size {
+    for _ in 0..config.target_outbound_peers {
         let _ = demand_tx.try_send(());
     }
 
-    let crawl_guard = tokio::spawn(crawl_and_dial(
+    let crawl_guard = shutdown.spawn(crawl_and_dial(
         config.new_peer_interval,
+        config.target_outbound_peers,
+        config.max_outbound_connections_per_second,
+        config.max_concurrent_outbound_handshakes,
+        outbound_connections,
         demand_tx,
         demand_rx,
+        seed_rx,
         candidates,
         connector,
         peerset_tx,
     ));
 
-    handle_tx
-        .send(vec![add_guard, listen_guard, crawl_guard])
-        .unwrap();
+    guards.push(crawl_guard);
+    handle_tx.send(guards).unwrap();
 
-    (peer_set, address_book)
+    (
+        peer_set,
+        address_book,
+        connected_peers,
+        events,
+        shutdown,
+        peer_control,
+    )
 }
 
 /// Use the provided `handshaker` to connect to `initial_peers`, then send
 /// the results over `tx`.
-#[instrument(skip(initial_peers, connector, tx))]
+///
+/// Initial peers are counted by `outbound_connections` while there is room,
+/// but we connect to all of them regardless.
+#[instrument(skip(initial_peers, connector, outbound_connections, tx))]
 async fn add_initial_peers<S>(
-    initial_peers: std::collections::HashSet<SocketAddr>,
+    initial_peers: HashSet<SocketAddr>,
     connector: S,
+    outbound_connections: ActiveConnectionCounter,
     mut tx: mpsc::Sender<PeerChange>,
 ) -> Result<(), BoxedStdError>
 where
@@ -153,35 +284,105 @@ where
     let mut handshakes = CallAllUnordered::new(connector, addr_stream);
 
     while let Some(handshake_result) = handshakes.next().await {
+        let handshake_result = handshake_result.map(|change| match change {
+            Change::Insert(addr, client) => match outbound_connections.try_track() {
+                Some(tracker) => Change::Insert(addr, client.with_connection_tracker(tracker)),
+                None => Change::Insert(addr, client),
+            },
+            change => change,
+        });
         tx.send(handshake_result).await?;
     }
 
     Ok(())
 }
 
-/// Bind to `addr`, listen for peers using `handshaker`, then send the
+/// Bind to `listen_addr`, listen for peers using `handshaker`, then send the
 /// results over `tx`.
-#[instrument(skip(tx, handshaker))]
+///
+/// Accepted peers are counted by `inbound_connections`. If it is at its limit,
+/// we evict one of the inbound peers in `connected_peers` to make room, or
+/// close the new connection if they are all protected. If `allowed_ips` is
+/// set, connections from other IP addresses are closed, as are connections
+/// that `ip_filter` rejects. Like outbound peers, accepted peers are added to
+/// the address book by their connection's timestamp collector.
+#[instrument(skip(
+    inbound_connections,
+    allowed_ips,
+    ip_filter,
+    connected_peers,
+    tx,
+    handshaker
+))]
 async fn listen<S>(
-    addr: SocketAddr,
+    listen_addr: SocketAddr,
+    inbound_connections: ActiveConnectionCounter,
+    allowed_ips: Option<HashSet<IpAddr>>,
+    ip_filter: IpFilter,
+    connected_peers: Arc<Mutex<ConnectedPeers>>,
     mut handshaker: S,
     tx: mpsc::Sender<PeerChange>,
 ) -> Result<(), BoxedStdError>
 where
-    S: Service<(TcpStream, SocketAddr), Response = peer::Client, Error = BoxedStdError> + Clone,
+    S: Service<(TcpStream, SocketAddr, Direction), Response = peer::Client, Error = BoxedStdError>
+        + Clone,
     S::Future: Send + 'static,
 {
-    let mut listener = TcpListener::bind(addr).await?;
+    let mut listener = bind_listener(listen_addr)?;
+    info!(local_addr = ?listener.local_addr(), "listening for inbound peer connections");
     loop {
         if let Ok((tcp_stream, addr)) = listener.accept().await {
+            if let Some(allowed_ips) = &allowed_ips {
+                if !allowed_ips.contains(&addr.ip()) {
+                    debug!(?addr, "peer is not in only_connect_to, closing connection");
+                    continue;
+                }
+            }
+            if !ip_filter.is_allowed(addr.ip()) {
+                debug!(?addr, "peer address is filtered, closing connection");
+                metrics::counter!("pool.inbound_connections_filtered", 1);
+                continue;
+            }
+            let tracker = match inbound_connections.try_track() {
+                Some(tracker) => Some(tracker),
+                // Wait for the evicted peer to close, so the new connection
+                // doesn't go over the limit.
+                None if evict_inbound_peer(&connected_peers) => {
+                    inbound_connections
+                        .track_within(constants::EVICTION_CLOSE_TIMEOUT)
+                        .await
+                }
+                None => None,
+            };
+            let tracker = match tracker {
+                Some(tracker) => tracker,
+                None => {
+                    debug!(
+                        ?addr,
+                        max_inbound_connections = inbound_connections.limit(),
+                        "too many inbound connections, closing connection"
+                    );
+                    metrics::counter!("pool.inbound_connections_rejected", 1);
+                    // Dropping the stream closes the connection.
+                    std::mem::drop(tcp_stream);
+                    continue;
+                }
+            };
             debug!(?addr, "got incoming connection");
+            metrics::gauge!(
+                "pool.inbound_connections",
+                inbound_connections.count() as i64
+            );
             handshaker.ready_and().await?;
             // Construct a handshake future but do not drive it yet....
-            let handshake = handshaker.call((tcp_stream, addr));
+            let handshake = handshaker.call((tcp_stream, addr, Direction::Inbound { listen_addr }));
             // ... instead, spawn a new task to handle this connection
             let mut tx2 = tx.clone();
             tokio::spawn(async move {
+                // If the handshake fails, the tracker is dropped here,
+                // freeing its slot.
                 if let Ok(client) = handshake.await {
+                    let client = client.with_connection_tracker(tracker);
                     let _ = tx2.send(Ok(Change::Insert(addr, client))).await;
                 }
             });
@@ -189,20 +390,88 @@ where
     }
 }
 
+/// Evict an unprotected inbound peer from `connected_peers`, to make room
+/// for a new connection.
+///
+/// Returns false if all the inbound peers are protected.
+fn evict_inbound_peer(connected_peers: &Mutex<ConnectedPeers>) -> bool {
+    let mut connected_peers = connected_peers.lock().expect("mutex should be unpoisoned");
+    let addr =
+        match select_peer_to_evict(connected_peers.evictable_inbound(), &mut rand::thread_rng()) {
+            Some(addr) => addr,
+            None => return false,
+        };
+    if !connected_peers.evict(&addr) {
+        return false;
+    }
+    debug!(
+        ?addr,
+        "evicted inbound peer to make room for a new connection"
+    );
+    metrics::counter!("pool.inbound_connections_evicted", 1);
+    true
+}
+
+/// Bind a listener to `addr`.
+///
+/// IPv6 listeners only accept IPv6 connections, so that they can share a port
+/// with an IPv4 listener, like `0.0.0.0:8233` and `[::]:8233`.
+fn bind_listener(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let domain = if addr.is_ipv4() {
+        Domain::ipv4()
+    } else {
+        Domain::ipv6()
+    };
+    let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+
+    let listener = socket.into_tcp_listener();
+    listener.set_nonblocking(true)?;
+    TcpListener::from_std(listener)
+}
+
 /// Given a channel that signals a need for new peers, try to connect to a peer
 /// and send the resulting `peer::Client` through a channel.
+///
+/// Every `new_peer_interval`, ask our peers for more addresses, and signal
+/// demand for enough new peers to reach `target_outbound_peers`. Demand
+/// signals are dropped while `outbound_connections` is at the target.
+///
+/// Addresses from DNS seeders arrive on `seed_rx`, and are added to the
+/// candidates before each update.
+///
+/// Connection attempts are spaced out to at most `max_connections_per_second`,
+/// and demand signals are dropped while there are `max_concurrent_handshakes`
+/// handshakes in flight.
 #[instrument(skip(
     new_peer_interval,
+    target_outbound_peers,
+    max_connections_per_second,
+    max_concurrent_handshakes,
+    outbound_connections,
     demand_tx,
     demand_rx,
+    seed_rx,
     candidates,
     connector,
     success_tx
 ))]
 async fn crawl_and_dial<C, S>(
     new_peer_interval: std::time::Duration,
+    target_outbound_peers: usize,
+    max_connections_per_second: u32,
+    max_concurrent_handshakes: usize,
+    outbound_connections: ActiveConnectionCounter,
     mut demand_tx: mpsc::Sender<()>,
     mut demand_rx: mpsc::Receiver<()>,
+    mut seed_rx: mpsc::Receiver<SocketAddr>,
     mut candidates: CandidateSet<S>,
     mut connector: C,
     mut success_tx: mpsc::Sender<PeerChange>,
@@ -230,6 +499,13 @@ where
 
     let mut crawl_timer = tokio::time::interval(new_peer_interval);
 
+    let dial_interval = if max_connections_per_second == 0 {
+        None
+    } else {
+        Some(std::time::Duration::from_secs(1) / max_connections_per_second)
+    };
+    let mut next_dial = tokio::time::Instant::now();
+
     loop {
         metrics::gauge!("crawler.in_flight_handshakes", handshakes.len() as i64 - 1);
         // This is a little awkward because there's no select3.
@@ -240,34 +516,70 @@ where
         .await
         {
             Left((Left((Some(_demand), _)), _)) => {
-                if handshakes.len() > 50 {
+                // `handshakes` also holds the pending future, so this allows
+                // `max_concurrent_handshakes` real handshakes.
+                if handshakes.len() > max_concurrent_handshakes {
                     // This is set to trace level because when the peerset is
                     // congested it can generate a lot of demand signal very rapidly.
                     trace!("too many in-flight handshakes, dropping demand signal");
                     continue;
                 }
+                let tracker = match outbound_connections.try_track() {
+                    Some(tracker) => tracker,
+                    None => {
+                        trace!("at target outbound peer count, dropping demand signal");
+                        continue;
+                    }
+                };
                 if let Some(candidate) = candidates.next() {
+                    if let Some(dial_interval) = dial_interval {
+                        // Wait for our turn, so a burst of demand becomes a
+                        // steady stream of connection attempts.
+                        tokio::time::delay_until(next_dial).await;
+                        next_dial = tokio::time::Instant::now() + dial_interval;
+                    }
                     debug!(?candidate.addr, "attempting outbound connection in response to demand");
                     connector.ready_and().await?;
                     handshakes.push(
                         connector
                             .call(candidate.addr)
+                            // If the handshake fails, the tracker is dropped,
+                            // freeing its slot.
+                            .map_ok(move |change| match change {
+                                Change::Insert(addr, client) => {
+                                    Change::Insert(addr, client.with_connection_tracker(tracker))
+                                }
+                                change => change,
+                            })
                             .map_err(move |_| candidate)
                             .boxed(),
                     );
                 } else {
                     debug!("demand for peers but no available candidates");
+                    add_seeded_peers(&mut seed_rx, &mut candidates);
                     candidates.update().await?;
-                    // Try to connect to a new peer.
-                    let _ = demand_tx.try_send(());
+                    // Try to connect to a new peer. Updates can't find new
+                    // peers in connect-only mode, so we wait for the crawl
+                    // timer instead of spinning here.
+                    if candidates.only_connect_to.is_none() {
+                        let _ = demand_tx.try_send(());
+                    }
                 }
             }
             // did a drill sergeant write this? no there's just no Either3
             Left((Right((Some(_timer), _)), _)) => {
-                debug!("crawling for more peers");
+                let outbound_peers = outbound_connections.count();
+                metrics::gauge!("crawler.outbound_connections", outbound_peers as i64);
+                debug!(
+                    outbound_peers,
+                    target_outbound_peers, "crawling for more peers"
+                );
+                add_seeded_peers(&mut seed_rx, &mut candidates);
                 candidates.update().await?;
-                // Try to connect to a new peer.
-                let _ = demand_tx.try_send(());
+                // Try to connect to enough new peers to reach the target.
+                for _ in outbound_peers..target_outbound_peers {
+                    let _ = demand_tx.try_send(());
+                }
             }
             Right((Some(Ok(change)), _)) => {
                 // in fact all changes are Insert so this branch is always taken
@@ -290,3 +602,19 @@ where
     }
     Ok(())
 }
+
+/// Add any addresses waiting in `seed_rx` to `candidates`.
+fn add_seeded_peers<S>(seed_rx: &mut mpsc::Receiver<SocketAddr>, candidates: &mut CandidateSet<S>)
+where
+    S: Service<Request, Response = Response, Error = BoxedStdError>,
+    S::Future: Send + 'static,
+{
+    let mut addrs = Vec::new();
+    while let Ok(Some(addr)) = seed_rx.try_next() {
+        addrs.push(addr);
+    }
+    if !addrs.is_empty() {
+        debug!(count = addrs.len(), "adding peers from DNS seeders");
+        candidates.add_fixed_peers(addrs);
+    }
+}
@@ -0,0 +1,93 @@
+//! Tracks which peers have advertised which inventory hashes.
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{channel::mpsc, Stream};
+use tokio::time::Interval;
+
+use crate::{constants, protocol::external::InventoryHash};
+
+/// A rolling registry of the peers that advertised each inventory hash.
+///
+/// Advertisements are kept for between one and two
+/// [`constants::INVENTORY_ROTATION_INTERVAL`]s, because the registry only
+/// stores the current and previous intervals.
+pub(super) struct InventoryRegistry {
+    current: HashMap<InventoryHash, HashSet<SocketAddr>>,
+    prev: HashMap<InventoryHash, HashSet<SocketAddr>>,
+    inv_stream: mpsc::Receiver<(InventoryHash, SocketAddr)>,
+    interval: Interval,
+}
+
+impl InventoryRegistry {
+    /// Returns a registry that is updated from `inv_stream`.
+    pub(super) fn new(inv_stream: mpsc::Receiver<(InventoryHash, SocketAddr)>) -> Self {
+        InventoryRegistry {
+            current: HashMap::new(),
+            prev: HashMap::new(),
+            inv_stream,
+            interval: tokio::time::interval(constants::INVENTORY_ROTATION_INTERVAL),
+        }
+    }
+
+    /// Returns the peers that recently advertised `hash`.
+    pub(super) fn peers(&self, hash: &InventoryHash) -> impl Iterator<Item = &SocketAddr> {
+        let current = self.current.get(hash).into_iter().flatten();
+        let prev = self.prev.get(hash).into_iter().flatten();
+        current.chain(prev)
+    }
+
+    /// Process any queued advertisements, and rotate the registry if the
+    /// interval has elapsed.
+    pub(super) fn poll_inventory(&mut self, cx: &mut Context<'_>) {
+        while Pin::new(&mut self.interval).poll_next(cx).is_ready() {
+            self.rotate();
+        }
+
+        // The handshake services own the senders, so the stream only ends
+        // when the peer set is shutting down.
+        while let Poll::Ready(Some((hash, addr))) = Pin::new(&mut self.inv_stream).poll_next(cx) {
+            self.register(hash, addr);
+        }
+    }
+
+    fn register(&mut self, hash: InventoryHash, addr: SocketAddr) {
+        self.current.entry(hash).or_default().insert(addr);
+    }
+
+    fn rotate(&mut self) {
+        self.prev = std::mem::take(&mut self.current);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zebra_chain::block;
+
+    use super::*;
+
+    #[test]
+    fn advertisements_expire_after_two_rotations() {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let (_tx, rx) = mpsc::channel(1);
+            let mut registry = InventoryRegistry::new(rx);
+
+            let hash = InventoryHash::Block(block::Hash([1; 32]));
+            let addr: SocketAddr = "203.0.113.6:8233".parse().unwrap();
+            registry.register(hash, addr);
+            assert_eq!(registry.peers(&hash).collect::<Vec<_>>(), vec![&addr]);
+
+            registry.rotate();
+            assert_eq!(registry.peers(&hash).collect::<Vec<_>>(), vec![&addr]);
+
+            registry.rotate();
+            assert_eq!(registry.peers(&hash).count(), 0);
+        });
+    }
+}
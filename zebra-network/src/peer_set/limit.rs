@@ -0,0 +1,141 @@
+//! Limits on the number of open peer connections.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+/// Counts the open connections of one kind, up to a limit.
+///
+/// Each open connection holds a [`ConnectionTracker`], which decrements the
+/// count when it is dropped.
+#[derive(Clone, Debug)]
+pub(crate) struct ActiveConnectionCounter {
+    count: Arc<AtomicUsize>,
+    limit: usize,
+}
+
+impl ActiveConnectionCounter {
+    pub(crate) fn new(limit: usize) -> Self {
+        ActiveConnectionCounter {
+            count: Arc::new(AtomicUsize::new(0)),
+            limit,
+        }
+    }
+
+    /// Returns the maximum number of open connections.
+    pub(crate) fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Returns the number of open connections.
+    pub(crate) fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Returns a tracker for a new connection, or `None` if the limit has
+    /// been reached.
+    pub(crate) fn try_track(&self) -> Option<ConnectionTracker> {
+        let mut count = self.count();
+        loop {
+            if count >= self.limit {
+                return None;
+            }
+            match self
+                .count
+                .compare_exchange(count, count + 1, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => {
+                    return Some(ConnectionTracker {
+                        count: self.count.clone(),
+                    })
+                }
+                Err(actual) => count = actual,
+            }
+        }
+    }
+
+    /// Returns a tracker for a new connection, waiting up to `timeout` for a
+    /// connection to close if the limit has been reached.
+    ///
+    /// This is used when an existing connection is closing to make room, so
+    /// the count never exceeds the limit.
+    pub(crate) async fn track_within(&self, timeout: Duration) -> Option<ConnectionTracker> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(tracker) = self.try_track() {
+                return Some(tracker);
+            }
+            if Instant::now() >= deadline {
+                return None;
+            }
+            tokio::time::delay_for(CLOSE_POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// How often [`ActiveConnectionCounter::track_within`] checks for a closed
+/// connection.
+const CLOSE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Keeps a connection counted by an [`ActiveConnectionCounter`] until it is
+/// dropped.
+#[derive(Debug)]
+pub(crate) struct ConnectionTracker {
+    count: Arc<AtomicUsize>,
+}
+
+impl Drop for ConnectionTracker {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trackers_release_their_slot_on_drop() {
+        let counter = ActiveConnectionCounter::new(2);
+
+        let first = counter.try_track().expect("below the limit");
+        let _second = counter.try_track().expect("below the limit");
+        assert!(counter.try_track().is_none());
+        assert_eq!(counter.count(), 2);
+
+        drop(first);
+        assert_eq!(counter.count(), 1);
+        assert!(counter.try_track().is_some());
+    }
+
+    #[test]
+    fn waiting_trackers_never_exceed_the_limit() {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let counter = ActiveConnectionCounter::new(1);
+            let first = counter.try_track().expect("below the limit");
+
+            // Nothing closes, so the wait times out.
+            assert!(counter
+                .track_within(Duration::from_millis(50))
+                .await
+                .is_none());
+
+            tokio::spawn(async move {
+                tokio::time::delay_for(Duration::from_millis(20)).await;
+                drop(first);
+            });
+            let _second = counter
+                .track_within(Duration::from_secs(5))
+                .await
+                .expect("the first connection closes");
+            assert_eq!(counter.count(), 1);
+        });
+    }
+}
@@ -0,0 +1,56 @@
+//! Network groups, which approximate the network operator of a peer.
+
+use std::net::IpAddr;
+
+/// The network group of an IP address.
+///
+/// Peers in the same group are likely to be run by the same operator, so we
+/// avoid making several outbound connections to one group. Like `zcashd`, we
+/// group IPv4 addresses by `/16`, and IPv6 addresses by `/32`.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub(super) enum NetGroup {
+    V4([u8; 2]),
+    V6([u8; 4]),
+}
+
+impl From<IpAddr> for NetGroup {
+    fn from(ip: IpAddr) -> Self {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4().map(IpAddr::V4).unwrap_or(ip),
+            ip => ip,
+        };
+        match ip {
+            IpAddr::V4(v4) => {
+                let octets = v4.octets();
+                NetGroup::V4([octets[0], octets[1]])
+            }
+            IpAddr::V6(v6) => {
+                let octets = v6.octets();
+                NetGroup::V6([octets[0], octets[1], octets[2], octets[3]])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    #[test]
+    fn groups_by_prefix() {
+        let a = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 6));
+        let b = IpAddr::V4(Ipv4Addr::new(203, 0, 7, 1));
+        let c = IpAddr::V4(Ipv4Addr::new(203, 1, 113, 6));
+        assert_eq!(NetGroup::from(a), NetGroup::from(b));
+        assert_ne!(NetGroup::from(a), NetGroup::from(c));
+
+        let mapped = IpAddr::V6(Ipv4Addr::new(203, 0, 1, 1).to_ipv6_mapped());
+        assert_eq!(NetGroup::from(a), NetGroup::from(mapped));
+
+        let d: IpAddr = "2001:db8:1::1".parse::<Ipv6Addr>().unwrap().into();
+        let e: IpAddr = "2001:db8:2::1".parse::<Ipv6Addr>().unwrap().into();
+        assert_eq!(NetGroup::from(d), NetGroup::from(e));
+    }
+}
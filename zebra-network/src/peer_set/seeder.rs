@@ -0,0 +1,257 @@
+//! Resolving configured peers and DNS seeders.
+
+use std::{
+    collections::HashSet,
+    future::Future,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{channel::mpsc, future, sink::SinkExt};
+
+use crate::{constants, dns, socks5, AddressBook, BoxedStdError, Config};
+
+/// A SOCKS5 proxy, and the DNS server that hostnames are looked up on
+/// through it, if there is one.
+#[derive(Copy, Clone, Debug)]
+pub(super) struct Proxy {
+    addr: SocketAddr,
+    dns_server: Option<SocketAddr>,
+}
+
+impl Proxy {
+    /// Returns the proxy in `config`, if there is one.
+    pub(super) fn from_config(config: &Config) -> Option<Proxy> {
+        config.proxy.map(|addr| Proxy {
+            addr,
+            dns_server: config.proxy_dns_server,
+        })
+    }
+}
+
+/// Resolve `peers`, which are `host:port` strings, through `proxy` if it is
+/// set, or the local resolver otherwise.
+///
+/// Peers that fail to resolve are skipped.
+pub(super) async fn resolve_peers(
+    proxy: Option<Proxy>,
+    peers: &HashSet<String>,
+) -> HashSet<SocketAddr> {
+    let mut addrs = HashSet::new();
+    for peer in peers {
+        match resolve_peer(proxy, peer).await {
+            Ok(resolved) => addrs.extend(resolved),
+            Err(e) => warn!(%e, ?peer, "could not resolve peer"),
+        }
+    }
+    addrs
+}
+
+/// Resolve the DNS `seeds`, which are `host:port` strings, retrying each
+/// failed lookup with exponential backoff.
+///
+/// Seeds are resolved concurrently, and seeds that still fail after
+/// [`constants::DNS_SEED_RETRIES`] retries are skipped, so one broken seeder
+/// doesn't stop us using the others.
+pub(super) async fn resolve_seeds(
+    proxy: Option<Proxy>,
+    seeds: &HashSet<String>,
+) -> HashSet<SocketAddr> {
+    let lookups = seeds.iter().map(|seed| resolve_seed(proxy, seed));
+    future::join_all(lookups)
+        .await
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// Every [`constants::DNS_SEED_INTERVAL`], re-resolve the DNS `seeds` if we
+/// know about fewer than `target_peers` peers, and send new addresses to the
+/// crawler over `tx`.
+#[instrument(skip(seeds, address_book, tx))]
+pub(super) async fn reseed_when_low(
+    proxy: Option<Proxy>,
+    seeds: HashSet<String>,
+    address_book: Arc<Mutex<AddressBook>>,
+    target_peers: usize,
+    mut tx: mpsc::Sender<SocketAddr>,
+) -> Result<(), BoxedStdError> {
+    let mut interval = tokio::time::interval(constants::DNS_SEED_INTERVAL);
+    // The first tick finishes immediately, but we just resolved the seeds.
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        let known_peers = address_book.lock().expect("mutex must be unpoisoned").len();
+        if known_peers >= target_peers {
+            continue;
+        }
+
+        info!(known_peers, target_peers, "re-resolving DNS seeders");
+        let addrs = resolve_seeds(proxy, &seeds).await;
+        metrics::counter!("seeder.resolved_addresses", addrs.len() as u64);
+        for addr in addrs {
+            tx.send(addr).await?;
+        }
+    }
+}
+
+/// Resolve `seed`, retrying with exponential backoff if it fails.
+async fn resolve_seed(proxy: Option<Proxy>, seed: &str) -> Vec<SocketAddr> {
+    let lookup = || resolve_peer(proxy, seed);
+    match with_retries(
+        seed,
+        constants::DNS_SEED_RETRIES,
+        constants::DNS_SEED_RETRY_DELAY,
+        lookup,
+    )
+    .await
+    {
+        Some(addrs) => addrs,
+        None => {
+            metrics::counter!("seeder.failed_lookups", 1);
+            Vec::new()
+        }
+    }
+}
+
+/// Run `lookup` for `seed`, retrying up to `retries` times if it fails.
+///
+/// The delay before each retry starts at `delay`, and doubles after each
+/// retry. Returns `None` if every attempt fails.
+async fn with_retries<F, Fut, T>(
+    seed: &str,
+    retries: u32,
+    mut delay: Duration,
+    mut lookup: F,
+) -> Option<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = io::Result<T>>,
+{
+    for retry in 0..=retries {
+        match lookup().await {
+            Ok(result) => return Some(result),
+            Err(e) if retry < retries => {
+                debug!(%e, ?seed, ?delay, "DNS seed lookup failed, retrying");
+                tokio::time::delay_for(delay).await;
+                delay *= 2;
+            }
+            Err(e) => warn!(%e, ?seed, "DNS seed lookup failed, giving up"),
+        }
+    }
+    None
+}
+
+/// Resolve `peer`, a `host:port` string, through `proxy` if it is set, or the
+/// local resolver otherwise.
+///
+/// Peers that are already IP addresses are returned as-is.
+async fn resolve_peer(proxy: Option<Proxy>, peer: &str) -> io::Result<Vec<SocketAddr>> {
+    if let Ok(addr) = peer.parse() {
+        return Ok(vec![addr]);
+    }
+    match proxy {
+        Some(proxy) => {
+            let mut parts = peer.rsplitn(2, ':');
+            let port = parts.next().and_then(|port| port.parse().ok());
+            let host = parts.next();
+            match (host, port) {
+                (Some(host), Some(port)) => {
+                    let ips = match proxy.dns_server {
+                        Some(dns_server) => {
+                            match dns::resolve(proxy.addr, dns_server, host).await {
+                                Ok(ips) if !ips.is_empty() => ips,
+                                result => {
+                                    debug!(
+                                    ?result,
+                                    ?host,
+                                    "DNS lookup through the proxy failed, using RESOLVE instead"
+                                );
+                                    vec![socks5::resolve(proxy.addr, host).await?]
+                                }
+                            }
+                        }
+                        None => vec![socks5::resolve(proxy.addr, host).await?],
+                    };
+                    Ok(ips
+                        .into_iter()
+                        .map(|ip| SocketAddr::new(ip, port))
+                        .collect())
+                }
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "peer is not a host:port pair",
+                )),
+            }
+        }
+        None => Ok(tokio::net::lookup_host(peer).await?.collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::Cell;
+
+    const SEED: &str = "dnsseed.example.com:8233";
+
+    fn lookup_error() -> io::Error {
+        io::Error::new(io::ErrorKind::Other, "seeder is down")
+    }
+
+    #[test]
+    fn failed_lookups_are_retried() {
+        let attempts = Cell::new(0);
+        let lookup = || {
+            attempts.set(attempts.get() + 1);
+            let result = if attempts.get() < 3 {
+                Err(lookup_error())
+            } else {
+                Ok(attempts.get())
+            };
+            future::ready(result)
+        };
+
+        let result = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(with_retries(SEED, 3, Duration::from_millis(1), lookup));
+        assert_eq!(result, Some(3));
+        assert_eq!(attempts.get(), 3);
+    }
+
+    #[test]
+    fn lookups_give_up_after_the_last_retry() {
+        let attempts = Cell::new(0);
+        let lookup = || {
+            attempts.set(attempts.get() + 1);
+            future::ready(Err::<(), _>(lookup_error()))
+        };
+
+        let result = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(with_retries(SEED, 2, Duration::from_millis(1), lookup));
+        assert_eq!(result, None);
+        assert_eq!(attempts.get(), 3);
+    }
+
+    #[test]
+    fn every_seed_is_resolved() {
+        let seeds: HashSet<String> = vec![
+            "192.0.2.1:8233".to_string(),
+            "[2001:db8::1]:8233".to_string(),
+        ]
+        .into_iter()
+        .collect();
+        let addrs = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(resolve_seeds(None, &seeds));
+
+        let expected: HashSet<SocketAddr> =
+            seeds.iter().map(|seed| seed.parse().unwrap()).collect();
+        assert_eq!(addrs, expected);
+    }
+}
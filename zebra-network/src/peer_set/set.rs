@@ -4,7 +4,9 @@ use std::{
     fmt::Debug,
     future::Future,
     marker::PhantomData,
+    net::SocketAddr,
     pin::Pin,
+    sync::{Arc, Mutex},
     task::{Context, Poll},
 };
 
@@ -14,6 +16,7 @@ use futures::{
     stream::FuturesUnordered,
 };
 use indexmap::IndexMap;
+use thiserror::Error;
 use tokio::sync::oneshot::error::TryRecvError;
 use tokio::task::JoinHandle;
 use tower::{
@@ -22,12 +25,35 @@ use tower::{
 };
 use tower_load::Load;
 
+use zebra_chain::serialization::DateTime32;
+
 use crate::{
-    protocol::internal::{Request, Response},
-    BoxedStdError,
+    block_sources::BlockSources,
+    constants,
+    meta_addr::MetaAddr,
+    protocol::{
+        external::{types::PeerServices, InventoryHash},
+        internal::{Request, Response},
+    },
+    BoxedStdError, ConnectedPeers,
+};
+
+use super::{
+    inventory_registry::InventoryRegistry,
+    unready_service::{Error as UnreadyError, UnreadyService},
 };
 
-use super::unready_service::{Error as UnreadyError, UnreadyService};
+/// An error routing a request to one of the peers in the peer set.
+#[derive(Error, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RoutingError {
+    /// None of the ready peers advertise the services that the request
+    /// needs.
+    #[error("no ready peers advertise the {required:?} services")]
+    MissingServices {
+        /// The services that the request needs.
+        required: PeerServices,
+    },
+}
 
 /// A [`tower::Service`] that abstractly represents "the rest of the network".
 ///
@@ -88,11 +114,19 @@ where
     /// These guards are checked for errors as part of `poll_ready` which lets
     /// the `PeerSet` propagate errors from background tasks back to the user
     guards: futures::stream::FuturesUnordered<JoinHandle<Result<(), BoxedStdError>>>,
+    /// The peers that recently advertised each inventory hash, used to route
+    /// inventory requests.
+    inventory_registry: InventoryRegistry,
+    /// The connected peers, used to look up the services each peer
+    /// advertised.
+    connected_peers: Arc<Mutex<ConnectedPeers>>,
+    /// The peers that sent each downloaded block.
+    block_sources: BlockSources,
 }
 
 impl<D> PeerSet<D>
 where
-    D: Discover + Unpin,
+    D: Discover<Key = SocketAddr> + Unpin,
     D::Key: Clone + Debug,
     D::Service: Service<Request, Response = Response> + Load,
     D::Error: Into<BoxedStdError>,
@@ -105,6 +139,9 @@ where
         discover: D,
         demand_signal: mpsc::Sender<()>,
         handle_rx: tokio::sync::oneshot::Receiver<Vec<JoinHandle<Result<(), BoxedStdError>>>>,
+        inv_stream: mpsc::Receiver<(InventoryHash, SocketAddr)>,
+        connected_peers: Arc<Mutex<ConnectedPeers>>,
+        block_sources: BlockSources,
     ) -> Self {
         Self {
             discover,
@@ -115,6 +152,9 @@ where
             demand_signal,
             guards: futures::stream::FuturesUnordered::new(),
             handle_rx,
+            inventory_registry: InventoryRegistry::new(inv_stream),
+            connected_peers,
+            block_sources,
         }
     }
 
@@ -219,14 +259,25 @@ where
 
     /// Performs P2C on inner services to select a ready service.
     fn select_next_ready_index(&mut self) -> Option<usize> {
-        match self.ready_services.len() {
+        let indexes: Vec<usize> = (0..self.ready_services.len()).collect();
+        self.select_p2c_index(&indexes)
+    }
+
+    /// Performs P2C on the ready services at `indexes`, selecting the less
+    /// loaded of two random services.
+    ///
+    /// The load of each peer is its peak EWMA response time, multiplied by
+    /// the number of requests in flight to it, so slow and busy peers are
+    /// both avoided.
+    fn select_p2c_index(&self, indexes: &[usize]) -> Option<usize> {
+        match indexes.len() {
             0 => None,
-            1 => Some(0),
+            1 => Some(indexes[0]),
             len => {
                 // XXX avoid relying on rand complexity
                 let (a, b) = {
                     let idxs = rand::seq::index::sample(&mut rand::thread_rng(), len, 2);
-                    (idxs.index(0), idxs.index(1))
+                    (indexes[idxs.index(0)], indexes[idxs.index(1)])
                 };
 
                 let a_load = self.ready_index_load(a);
@@ -241,6 +292,153 @@ where
         }
     }
 
+    /// Returns the indexes of the ready services whose peers advertise all
+    /// the `required` services.
+    fn ready_indexes_with(&self, required: PeerServices) -> Vec<usize> {
+        if required.is_empty() {
+            return (0..self.ready_services.len()).collect();
+        }
+        let connected_peers = self
+            .connected_peers
+            .lock()
+            .expect("mutex should be unpoisoned");
+        self.ready_services
+            .keys()
+            .enumerate()
+            .filter(|(_, addr)| {
+                connected_peers
+                    .get(addr)
+                    .map_or(false, |info| info.services.contains(required))
+            })
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Returns the index of a ready service that recently advertised the
+    /// inventory in `req`, if there is one among the `eligible` indexes.
+    ///
+    /// Prefers the services that advertised the most requested items, using
+    /// P2C to choose between them.
+    fn select_inventory_index(&self, req: &Request, eligible: &[usize]) -> Option<usize> {
+        let hashes: Vec<InventoryHash> = match req {
+            Request::BlocksByHash(hashes) => hashes.iter().map(|&hash| hash.into()).collect(),
+            Request::TransactionsByHash(hashes) => hashes.iter().map(|&hash| hash.into()).collect(),
+            _ => return None,
+        };
+
+        let mut advertised = HashMap::<SocketAddr, usize>::new();
+        for hash in &hashes {
+            for addr in self.inventory_registry.peers(hash) {
+                *advertised.entry(*addr).or_default() += 1;
+            }
+        }
+
+        let ready: Vec<(usize, usize)> = advertised
+            .into_iter()
+            .filter_map(|(addr, count)| {
+                self.ready_services
+                    .get_full(&addr)
+                    .map(|(index, _, _)| (count, index))
+            })
+            .filter(|(_, index)| eligible.contains(index))
+            .collect();
+        let max_count = ready.iter().map(|&(count, _)| count).max()?;
+        let best: Vec<usize> = ready
+            .into_iter()
+            .filter(|&(count, _)| count == max_count)
+            .map(|(_, index)| index)
+            .collect();
+
+        self.select_p2c_index(&best)
+    }
+
+    /// Sends `req` to a random subset of the ready services, returning a
+    /// future that finishes once every service has handled it.
+    ///
+    /// We advertise to the square root of the number of ready peers, so new
+    /// inventory spreads through the network without flooding any one peer.
+    /// Peers that fail to handle the advertisement are ignored.
+    fn broadcast(
+        &mut self,
+        req: Request,
+    ) -> Pin<Box<dyn Future<Output = Result<Response, BoxedStdError>> + Send + 'static>> {
+        let ready = self.ready_services.len();
+        let fanout = ((ready as f64).sqrt().ceil() as usize).max(1).min(ready);
+        metrics::counter!("pool.advertised_peers", fanout as u64);
+
+        futures::future::join_all(self.call_random_ready(req, fanout))
+            .map(|_| Ok(Response::Nil))
+            .boxed()
+    }
+
+    /// Sends a `Peers` request to several random ready services, returning a
+    /// future that merges their responses.
+    ///
+    /// Asking [`constants::GETADDR_FANOUT`] peers at once means a single
+    /// peer can't control our view of the network. Failed requests are
+    /// ignored, unless every request fails.
+    fn fan_out_peers(
+        &mut self,
+        req: Request,
+    ) -> Pin<Box<dyn Future<Output = Result<Response, BoxedStdError>> + Send + 'static>> {
+        let fanout = constants::GETADDR_FANOUT.min(self.ready_services.len());
+        metrics::counter!("pool.getaddr_peers", fanout as u64);
+
+        futures::future::join_all(self.call_random_ready(req, fanout))
+            .map(|responses| {
+                let mut addr_lists = Vec::new();
+                let mut last_error: Option<BoxedStdError> = None;
+                for response in responses {
+                    match response {
+                        Ok(Response::Peers(addrs)) => addr_lists.push(addrs),
+                        Ok(_) => {}
+                        Err(e) => last_error = Some(e.into()),
+                    }
+                }
+                match (addr_lists.is_empty(), last_error) {
+                    (true, Some(e)) => Err(e),
+                    _ => Ok(Response::Peers(merge_peer_addrs(
+                        addr_lists,
+                        DateTime32::now(),
+                    ))),
+                }
+            })
+            .boxed()
+    }
+
+    /// Sends `req` to `count` randomly chosen ready services, and moves them
+    /// to the unready set.
+    fn call_random_ready(
+        &mut self,
+        req: Request,
+        count: usize,
+    ) -> Vec<<D::Service as Service<Request>>::Future> {
+        let keys: Vec<D::Key> =
+            rand::seq::index::sample(&mut rand::thread_rng(), self.ready_services.len(), count)
+                .iter()
+                .map(|index| {
+                    let (key, _) = self
+                        .ready_services
+                        .get_index(index)
+                        .expect("sampled index must be valid");
+                    key.clone()
+                })
+                .collect();
+
+        // Removing services perturbs the preselected index, but `call` has
+        // already taken it.
+        let mut responses = Vec::with_capacity(count);
+        for key in keys {
+            let mut svc = self
+                .ready_services
+                .swap_remove(&key)
+                .expect("sampled key must be ready");
+            responses.push(svc.call(req.clone()));
+            self.push_unready(key, svc);
+        }
+        responses
+    }
+
     /// Accesses a ready endpoint by index and returns its current load.
     fn ready_index_load(&self, index: usize) -> <D::Service as Load>::Metric {
         let (_, svc) = self.ready_services.get_index(index).expect("invalid index");
@@ -250,7 +448,7 @@ where
 
 impl<D> Service<Request> for PeerSet<D>
 where
-    D: Discover + Unpin,
+    D: Discover<Key = SocketAddr> + Unpin,
     D::Key: Clone + Debug + ToString,
     D::Service: Service<Request, Response = Response> + Load,
     D::Error: Into<BoxedStdError>,
@@ -265,6 +463,7 @@ where
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         self.check_for_background_errors(cx)?;
+        self.inventory_registry.poll_inventory(cx);
         // Process peer discovery updates.
         let _ = self.poll_discover(cx)?;
 
@@ -321,10 +520,40 @@ where
     }
 
     fn call(&mut self, req: Request) -> Self::Future {
-        let index = self
+        let preselected = self
             .next_idx
             .take()
             .expect("ready service must have valid preselected index");
+        match req {
+            Request::AdvertiseBlock(_) | Request::AdvertiseTransactionIds(_) => {
+                return self.broadcast(req)
+            }
+            Request::Peers => return self.fan_out_peers(req),
+            _ => {}
+        }
+        // Only send requests to peers that advertise the services they
+        // need, like NODE_NETWORK for block downloads.
+        let required = req.required_services();
+        let eligible = self.ready_indexes_with(required);
+
+        // Send inventory requests to a peer that advertised the inventory,
+        // if one is ready. Otherwise, use the service selected by p2c, or
+        // choose again from the services that can handle the request.
+        let index = match self.select_inventory_index(&req, &eligible) {
+            Some(index) => {
+                metrics::counter!("pool.inventory_routed", 1);
+                index
+            }
+            None if eligible.contains(&preselected) => preselected,
+            None => match self.select_p2c_index(&eligible) {
+                Some(index) => index,
+                None => {
+                    metrics::counter!("pool.requests_missing_services", 1);
+                    let error: BoxedStdError = RoutingError::MissingServices { required }.into();
+                    return futures::future::err(error).boxed();
+                }
+            },
+        };
         let (key, mut svc) = self
             .ready_services
             .swap_remove_index(index)
@@ -337,10 +566,98 @@ where
             "key" => key.to_string(),
         );
 
+        let is_block_download = matches!(req, Request::BlocksByHash(_));
         let fut = svc.call(req);
         self.push_unready(key, svc);
 
-        use futures::future::TryFutureExt;
-        fut.map_err(Into::into).boxed()
+        let block_sources = self.block_sources.clone();
+        async move {
+            let response = fut.await.map_err(Into::into)?;
+            if let (true, Response::Blocks(blocks)) = (is_block_download, &response) {
+                for block in blocks {
+                    block_sources.insert(block.hash(), key);
+                }
+            }
+            Ok(response)
+        }
+        .boxed()
+    }
+}
+
+/// Merge the addresses that several peers sent us into one list.
+///
+/// Unusable addresses are dropped, and timestamps in the future are clamped
+/// to `now`, so a peer can't make its addresses look fresher than they are.
+/// If more than one peer sent an address, we keep its latest timestamp, and
+/// the services from that entry. The merged addresses are then sanitized.
+fn merge_peer_addrs(
+    addr_lists: impl IntoIterator<Item = Vec<MetaAddr>>,
+    now: DateTime32,
+) -> Vec<MetaAddr> {
+    let mut merged: HashMap<SocketAddr, MetaAddr> = HashMap::new();
+    for mut meta in addr_lists.into_iter().flatten() {
+        if meta.addr.ip().is_unspecified() || meta.addr.port() == 0 {
+            continue;
+        }
+        meta.last_seen = meta.last_seen.min(now);
+        merged
+            .entry(meta.addr)
+            .and_modify(|prev| {
+                if meta.last_seen > prev.last_seen {
+                    *prev = meta;
+                }
+            })
+            .or_insert(meta);
+    }
+    let mut merged: Vec<MetaAddr> = merged
+        .into_iter()
+        .map(|(_, meta)| meta.sanitize())
+        .collect();
+    merged.sort();
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn meta(addr: &str, last_seen: DateTime32) -> MetaAddr {
+        MetaAddr {
+            addr: addr.parse().unwrap(),
+            services: PeerServices::NODE_NETWORK,
+            last_seen,
+        }
+    }
+
+    #[test]
+    fn merge_peer_addrs_dedups_and_clamps() {
+        let hour = Duration::from_secs(60 * 60);
+        let now = DateTime32::from(1_000_000_000);
+        let old = now.saturating_sub(3 * hour);
+        let older = now.saturating_sub(5 * hour);
+        let merged = merge_peer_addrs(
+            vec![
+                vec![
+                    meta("192.0.2.1:8233", older),
+                    meta("0.0.0.0:8233", now),
+                    meta("192.0.2.3:0", now),
+                ],
+                vec![
+                    meta("192.0.2.1:8233", old),
+                    meta("192.0.2.2:8233", now.saturating_add(24 * hour)),
+                ],
+            ],
+            now,
+        );
+
+        assert_eq!(
+            merged,
+            vec![
+                meta("192.0.2.2:8233", now).sanitize(),
+                meta("192.0.2.1:8233", old).sanitize(),
+            ]
+        );
     }
 }
@@ -0,0 +1,47 @@
+//! Stopping the peer set's background tasks.
+
+use std::sync::{Arc, Mutex};
+
+use futures::future::{abortable, AbortHandle, Future, FutureExt};
+use tokio::task::JoinHandle;
+
+use crate::BoxedStdError;
+
+/// A handle that stops the peer set's listeners, crawler, and reseeder.
+///
+/// Once stopped, the peer set doesn't accept inbound connections or dial
+/// new peers. Existing connections stay open, so requests that are already
+/// running can finish.
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown {
+    handles: Arc<Mutex<Vec<AbortHandle>>>,
+}
+
+impl Shutdown {
+    /// Stops every background task spawned by this handle.
+    pub fn stop(&self) {
+        for handle in self
+            .handles
+            .lock()
+            .expect("mutex should be unpoisoned")
+            .iter()
+        {
+            handle.abort();
+        }
+    }
+
+    /// Spawns `task`, so that it can be stopped using this handle.
+    ///
+    /// Stopped tasks return `Ok(())`.
+    pub(crate) fn spawn<F>(&self, task: F) -> JoinHandle<Result<(), BoxedStdError>>
+    where
+        F: Future<Output = Result<(), BoxedStdError>> + Send + 'static,
+    {
+        let (task, handle) = abortable(task);
+        self.handles
+            .lock()
+            .expect("mutex should be unpoisoned")
+            .push(handle);
+        tokio::spawn(task.map(|result| result.unwrap_or(Ok(()))))
+    }
+}
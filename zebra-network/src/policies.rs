@@ -1,6 +1,10 @@
+use std::error::Error;
+
 use futures::future;
 use tower::retry::Policy;
 
+use crate::{peer::SharedPeerError, BoxedStdError};
+
 /// A very basic retry policy with a limited number of retry attempts.
 ///
 /// XXX Remove this when https://github.com/tower-rs/tower/pull/414 lands.
@@ -59,3 +63,50 @@ impl<Req: Clone, Res, E> Policy<Req, Res, E> for RetryErrors {
         Some(req.clone())
     }
 }
+
+/// A retry policy for peer set requests, which retries requests that failed
+/// because of the peer that handled them, up to `retry_attempts` times.
+///
+/// Requests fail this way when the peer times out, doesn't have the data we
+/// asked for, or disconnects. The peer set chooses peers at random, so each
+/// retry usually goes to a different peer. Errors from the peer set itself,
+/// such as having no peers with the services a request needs, are returned
+/// immediately.
+#[derive(Clone, Debug)]
+pub struct RetryPeerErrors {
+    remaining_tries: usize,
+}
+
+impl RetryPeerErrors {
+    /// Create a policy with the given number of retry attempts.
+    pub fn new(retry_attempts: usize) -> Self {
+        RetryPeerErrors {
+            remaining_tries: retry_attempts,
+        }
+    }
+}
+
+impl<Req: Clone, Res> Policy<Req, Res, BoxedStdError> for RetryPeerErrors {
+    type Future = future::Ready<Self>;
+    fn retry(&self, _: &Req, result: Result<&Res, &BoxedStdError>) -> Option<Self::Future> {
+        match result {
+            Err(e) if self.remaining_tries > 0 && is_peer_error(&**e) => {
+                metrics::counter!("pool.retried_requests", 1);
+                Some(future::ready(RetryPeerErrors {
+                    remaining_tries: self.remaining_tries - 1,
+                }))
+            }
+            _ => None,
+        }
+    }
+
+    fn clone_request(&self, req: &Req) -> Option<Req> {
+        Some(req.clone())
+    }
+}
+
+/// Returns true if `error`, or any of its sources, is an error from a peer
+/// connection.
+fn is_peer_error(error: &(dyn Error + 'static)) -> bool {
+    std::iter::successors(Some(error), |e| e.source()).any(|e| e.is::<SharedPeerError>())
+}
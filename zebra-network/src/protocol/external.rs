@@ -1,3 +1,6 @@
+/// Proptest strategies for wire messages.
+#[cfg(test)]
+mod arbitrary;
 /// A Tokio codec that transforms an `AsyncRead` into a `Stream` of `Message`s.
 pub mod codec;
 /// Inventory items.
@@ -0,0 +1,311 @@
+//! Proptest strategies for wire messages.
+//!
+//! Hashes use the `zebra-chain` `proptest-impl` strategies. Blocks and
+//! transactions are sampled from the test vectors, so that messages carrying
+//! them stay a reasonable size.
+
+use std::{
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+
+use bytes::Bytes;
+use chrono::{DateTime, TimeZone, Utc};
+use proptest::{collection::vec, prelude::*, sample::select, strategy::Union};
+
+use zebra_chain::{
+    block::{
+        self,
+        filter::{BlockFilter, FilterHash, FilterHeader},
+        Block,
+    },
+    serialization::{DateTime32, ZcashDeserialize},
+    transaction::{self, Transaction, WtxId},
+};
+
+use crate::meta_addr::MetaAddr;
+
+use super::{
+    message::{Message, RejectReason},
+    types::{self, Filter, Nonce, PeerServices, Tweak},
+    InventoryHash,
+};
+
+impl Arbitrary for InventoryHash {
+    type Parameters = ();
+
+    fn arbitrary_with(_args: ()) -> Self::Strategy {
+        prop_oneof![
+            Just(InventoryHash::Error),
+            any::<transaction::Hash>().prop_map(InventoryHash::Tx),
+            any::<block::Hash>().prop_map(InventoryHash::Block),
+            any::<block::Hash>().prop_map(InventoryHash::FilteredBlock),
+            any::<WtxId>().prop_map(InventoryHash::Wtx),
+        ]
+        .boxed()
+    }
+
+    type Strategy = BoxedStrategy<Self>;
+}
+
+impl Arbitrary for Message {
+    type Parameters = ();
+
+    fn arbitrary_with(_args: ()) -> Self::Strategy {
+        Union::new(vec![
+            version_strategy(),
+            Just(Message::Verack).boxed(),
+            any::<u64>().prop_map(|n| Message::Ping(Nonce(n))).boxed(),
+            any::<u64>().prop_map(|n| Message::Pong(Nonce(n))).boxed(),
+            reject_strategy(),
+            vec(meta_addr_strategy(), 0..10)
+                .prop_map(Message::Addr)
+                .boxed(),
+            Just(Message::GetAddr).boxed(),
+            select(test_blocks())
+                .prop_map(|block| Message::Block(Arc::new(block)))
+                .boxed(),
+            (vec(block_hash_strategy(), 0..10), block_hash_strategy())
+                .prop_map(|(block_locator_hashes, hash_stop)| Message::GetBlocks {
+                    block_locator_hashes,
+                    hash_stop,
+                })
+                .boxed(),
+            vec(select(test_headers()), 0..4)
+                .prop_map(Message::Headers)
+                .boxed(),
+            (vec(block_hash_strategy(), 0..10), block_hash_strategy())
+                .prop_map(|(block_locator_hashes, hash_stop)| Message::GetHeaders {
+                    block_locator_hashes,
+                    hash_stop,
+                })
+                .boxed(),
+            vec(any::<InventoryHash>(), 0..10)
+                .prop_map(Message::Inv)
+                .boxed(),
+            vec(any::<InventoryHash>(), 0..10)
+                .prop_map(Message::GetData)
+                .boxed(),
+            vec(any::<InventoryHash>(), 0..10)
+                .prop_map(Message::NotFound)
+                .boxed(),
+            select(test_transactions())
+                .prop_map(|transaction| Message::Tx(Arc::new(transaction)))
+                .boxed(),
+            Just(Message::Mempool).boxed(),
+            (
+                vec(any::<u8>(), 0..=36000),
+                any::<u32>(),
+                any::<u32>(),
+                any::<u8>(),
+            )
+                .prop_map(
+                    |(filter, hash_functions_count, tweak, flags)| Message::FilterLoad {
+                        filter: Filter(Bytes::from(filter)),
+                        hash_functions_count,
+                        tweak: Tweak(tweak),
+                        flags,
+                    },
+                )
+                .boxed(),
+            vec(any::<u8>(), 0..=520)
+                .prop_map(|data| Message::FilterAdd {
+                    data: Bytes::from(data),
+                })
+                .boxed(),
+            Just(Message::FilterClear).boxed(),
+            (any::<u8>(), any::<u32>(), block_hash_strategy())
+                .prop_map(
+                    |(filter_type, start_height, stop_hash)| Message::GetCFilters {
+                        filter_type,
+                        start_height: block::Height(start_height),
+                        stop_hash,
+                    },
+                )
+                .boxed(),
+            (any::<u8>(), block_hash_strategy(), vec(any::<u8>(), 0..100))
+                .prop_map(|(filter_type, block_hash, filter)| Message::CFilter {
+                    filter_type,
+                    block_hash,
+                    filter: BlockFilter(filter),
+                })
+                .boxed(),
+            (any::<u8>(), any::<u32>(), block_hash_strategy())
+                .prop_map(
+                    |(filter_type, start_height, stop_hash)| Message::GetCFHeaders {
+                        filter_type,
+                        start_height: block::Height(start_height),
+                        stop_hash,
+                    },
+                )
+                .boxed(),
+            (
+                any::<u8>(),
+                block_hash_strategy(),
+                any::<[u8; 32]>(),
+                vec(any::<[u8; 32]>(), 0..10),
+            )
+                .prop_map(
+                    |(filter_type, stop_hash, previous, hashes)| Message::CFHeaders {
+                        filter_type,
+                        stop_hash,
+                        previous_filter_header: FilterHeader(previous),
+                        filter_hashes: hashes.into_iter().map(FilterHash).collect(),
+                    },
+                )
+                .boxed(),
+            (any::<u8>(), block_hash_strategy())
+                .prop_map(|(filter_type, stop_hash)| Message::GetCFCheckpt {
+                    filter_type,
+                    stop_hash,
+                })
+                .boxed(),
+            (
+                any::<u8>(),
+                block_hash_strategy(),
+                vec(any::<[u8; 32]>(), 0..10),
+            )
+                .prop_map(|(filter_type, stop_hash, headers)| Message::CFCheckpt {
+                    filter_type,
+                    stop_hash,
+                    filter_headers: headers.into_iter().map(FilterHeader).collect(),
+                })
+                .boxed(),
+        ])
+        .boxed()
+    }
+
+    type Strategy = BoxedStrategy<Self>;
+}
+
+fn version_strategy() -> BoxedStrategy<Message> {
+    (
+        any::<u32>(),
+        services_strategy(),
+        timestamp_strategy(),
+        (services_strategy(), socket_addr_strategy()),
+        (services_strategy(), socket_addr_strategy()),
+        any::<u64>(),
+        ".{0,32}",
+        any::<u32>(),
+        any::<bool>(),
+    )
+        .prop_map(
+            |(
+                version,
+                services,
+                timestamp,
+                address_recv,
+                address_from,
+                nonce,
+                user_agent,
+                start_height,
+                relay,
+            )| Message::Version {
+                version: types::Version(version),
+                services,
+                timestamp,
+                address_recv,
+                address_from,
+                nonce: Nonce(nonce),
+                user_agent,
+                start_height: block::Height(start_height),
+                relay,
+            },
+        )
+        .boxed()
+}
+
+fn reject_strategy() -> BoxedStrategy<Message> {
+    use RejectReason::*;
+
+    (
+        ".{0,12}",
+        select(vec![
+            Malformed,
+            Invalid,
+            Obsolete,
+            Duplicate,
+            Nonstandard,
+            Dust,
+            InsufficientFee,
+            Checkpoint,
+            Other,
+        ]),
+        ".{0,32}",
+        any::<[u8; 32]>(),
+    )
+        .prop_map(|(message, ccode, reason, data)| Message::Reject {
+            message,
+            ccode,
+            reason,
+            // The codec always reads the data field.
+            data: Some(data),
+        })
+        .boxed()
+}
+
+fn meta_addr_strategy() -> impl Strategy<Value = MetaAddr> {
+    (socket_addr_strategy(), services_strategy(), any::<u32>()).prop_map(
+        |(addr, services, last_seen)| MetaAddr {
+            addr,
+            services,
+            last_seen: DateTime32::from(last_seen),
+        },
+    )
+}
+
+/// Services with only known bits set, because the codec discards unknown
+/// bits.
+fn services_strategy() -> impl Strategy<Value = PeerServices> {
+    any::<u64>().prop_map(PeerServices::from_bits_truncate)
+}
+
+/// Timestamps with whole seconds, because the codec discards fractional
+/// seconds.
+fn timestamp_strategy() -> impl Strategy<Value = DateTime<Utc>> {
+    (0..=i64::from(u32::MAX)).prop_map(|secs| Utc.timestamp(secs, 0))
+}
+
+/// IPv4 and IPv6 addresses, excluding IPv6 addresses that decode as IPv4.
+fn socket_addr_strategy() -> impl Strategy<Value = SocketAddr> {
+    let v4 = any::<[u8; 4]>().prop_map(IpAddr::from);
+    let v6 = any::<[u8; 16]>()
+        .prop_map(Ipv6Addr::from)
+        .prop_filter("IPv4-compatible addresses decode as IPv4", |ip| {
+            ip.to_ipv4().is_none()
+        })
+        .prop_map(IpAddr::V6);
+    (prop_oneof![v4, v6], any::<u16>()).prop_map(|(ip, port)| SocketAddr::new(ip, port))
+}
+
+fn block_hash_strategy() -> impl Strategy<Value = block::Hash> {
+    any::<block::Hash>()
+}
+
+fn test_blocks() -> Vec<Block> {
+    [
+        &zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..],
+        &zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..],
+        &zebra_test_vectors::BLOCK_MAINNET_415000_BYTES[..],
+        &zebra_test_vectors::BLOCK_MAINNET_434873_BYTES[..],
+    ]
+    .iter()
+    .map(|bytes| Block::zcash_deserialize(*bytes).expect("block test vectors deserialize"))
+    .collect()
+}
+
+fn test_headers() -> Vec<block::Header> {
+    test_blocks()
+        .into_iter()
+        .map(|block| block.header)
+        .collect()
+}
+
+fn test_transactions() -> Vec<Transaction> {
+    test_blocks()
+        .into_iter()
+        .flat_map(|block| block.transactions)
+        .map(|transaction| (*transaction).clone())
+        .collect()
+}
@@ -4,21 +4,26 @@ use std::fmt;
 use std::io::{Cursor, Read, Write};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use bytes::BytesMut;
+use bytes::{BufMut, Bytes, BytesMut};
 use chrono::{TimeZone, Utc};
 use tokio_util::codec::{Decoder, Encoder};
 
 use zebra_chain::{
-    block::{Block, BlockHeaderHash},
+    block::{
+        self,
+        filter::{BlockFilter, FilterHeader},
+        Block,
+    },
     serialization::{
-        ReadZcashExt, SerializationError as Error, WriteZcashExt, ZcashDeserialize, ZcashSerialize,
+        ReadZcashExt, SerializationError as Error, SharedBytes, WriteZcashExt, ZcashDeserialize,
+        ZcashDeserializeShared, ZcashSerialize,
     },
     transaction::Transaction,
-    types::{BlockHeight, Sha256dChecksum},
+    types::Sha256dChecksum,
     Network,
 };
 
-use crate::constants;
+use crate::{constants, ByteCounts};
 
 use super::{
     message::{Message, RejectReason},
@@ -28,6 +33,9 @@ use super::{
 /// The length of a Bitcoin message header.
 const HEADER_LEN: usize = 24usize;
 
+/// The number of frame bytes included in wire tracing hex dumps.
+const WIRE_TRACE_DUMP_LEN: usize = 64;
+
 /// A codec which produces Bitcoin messages from byte streams and vice versa.
 pub struct Codec {
     builder: Builder,
@@ -42,6 +50,12 @@ pub struct Builder {
     version: Version,
     /// The maximum allowable message length.
     max_len: usize,
+    /// The maximum allowable length for `block` messages.
+    max_block_len: usize,
+    /// Whether to emit wire tracing events for each frame.
+    trace_wire: bool,
+    /// The totals to add the bytes of each frame to, if any.
+    byte_counts: Option<ByteCounts>,
 }
 
 impl Codec {
@@ -50,7 +64,10 @@ impl Codec {
         Builder {
             network: Network::Mainnet,
             version: constants::CURRENT_VERSION,
-            max_len: 4_000_000,
+            max_len: constants::MAX_PROTOCOL_MESSAGE_LEN,
+            max_block_len: constants::MAX_BLOCK_MESSAGE_LEN,
+            trace_wire: false,
+            byte_counts: None,
         }
     }
 
@@ -86,6 +103,67 @@ impl Builder {
         self.max_len = len;
         self
     }
+
+    /// Configure the codec's maximum accepted payload size for `block`
+    /// messages, in bytes.
+    pub fn with_max_block_body_len(mut self, len: usize) -> Self {
+        self.max_block_len = len;
+        self
+    }
+
+    /// Configure whether the codec emits a `debug`-level event for every
+    /// frame it sends or receives, under the `zebra_network::wire` target.
+    ///
+    /// Each event contains the command, body length, checksum, and a hex dump
+    /// of the start of the frame, for diagnosing interoperability bugs.
+    pub fn with_wire_tracing(mut self, enabled: bool) -> Self {
+        self.trace_wire = enabled;
+        self
+    }
+
+    /// Configure the codec to add the size of every frame it sends or
+    /// receives to `byte_counts`.
+    pub(crate) fn with_byte_counts(mut self, byte_counts: ByteCounts) -> Self {
+        self.byte_counts = Some(byte_counts);
+        self
+    }
+}
+
+impl Codec {
+    /// Emit a wire tracing event for `frame`, if wire tracing is enabled.
+    ///
+    /// `frame` starts with the message header, and may omit the end of the
+    /// body.
+    fn trace_frame(
+        &self,
+        direction: &'static str,
+        command: &[u8; 12],
+        body_len: usize,
+        checksum: Sha256dChecksum,
+        frame: &[u8],
+    ) {
+        if !self.builder.trace_wire {
+            return;
+        }
+        debug!(
+            target: "zebra_network::wire",
+            direction,
+            command = %String::from_utf8_lossy(command).trim_end_matches('\0'),
+            body_len,
+            ?checksum,
+            dump = %hex_prefix(frame, WIRE_TRACE_DUMP_LEN),
+        );
+    }
+}
+
+/// Hex-encode at most `max_len` bytes of `bytes`, marking truncated output
+/// with a trailing `..`.
+fn hex_prefix(bytes: &[u8], max_len: usize) -> String {
+    if bytes.len() > max_len {
+        format!("{}..", hex::encode(&bytes[..max_len]))
+    } else {
+        hex::encode(bytes)
+    }
 }
 
 // ======== Encoding =========
@@ -95,14 +173,6 @@ impl Encoder for Codec {
     type Error = Error;
 
     fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        // XXX(HACK): this is inefficient and does an extra allocation.
-        // instead, we should have a size estimator for the message, reserve
-        // that much space, write the header (with zeroed checksum), then the body,
-        // then write the computed checksum in-place.  for now, just do an extra alloc.
-
-        let mut body = Vec::new();
-        self.write_body(&item, &mut body)?;
-
         use Message::*;
         // Note: because all match arms must have
         // the same type, and the array length is
@@ -129,21 +199,40 @@ impl Encoder for Codec {
             FilterLoad { .. } => b"filterload\0\0",
             FilterAdd { .. } => b"filteradd\0\0\0",
             FilterClear { .. } => b"filterclear\0",
+            GetCFilters { .. } => b"getcfilters\0",
+            CFilter { .. } => b"cfilter\0\0\0\0\0",
+            GetCFHeaders { .. } => b"getcfheaders",
+            CFHeaders { .. } => b"cfheaders\0\0\0",
+            GetCFCheckpt { .. } => b"getcfcheckpt",
+            CFCheckpt { .. } => b"cfcheckpt\0\0\0",
         };
-        trace!(?item, len = body.len());
+        // Write a zeroed header, then serialize the body directly into the
+        // buffer, and fill in the header once the body length and checksum
+        // are known. This avoids serializing into a temporary buffer and
+        // copying it, which matters when relaying large blocks.
+        let start = dst.len();
+        dst.reserve(HEADER_LEN);
+        dst.extend_from_slice(&[0u8; HEADER_LEN]);
+        if let Err(e) = self.write_body(&item, (&mut *dst).writer()) {
+            dst.truncate(start);
+            return Err(e);
+        }
 
-        // XXX this should write directly into the buffer,
-        // but leave it for now until we fix the issue above.
-        let mut header = [0u8; HEADER_LEN];
-        let mut header_writer = Cursor::new(&mut header[..]);
+        let body = &dst[start + HEADER_LEN..];
+        let body_len = body.len();
+        let checksum = Sha256dChecksum::from(body);
+        trace!(?item, len = body_len);
+
+        let mut header_writer = Cursor::new(&mut dst[start..start + HEADER_LEN]);
         header_writer.write_all(&Magic::from(self.builder.network).0[..])?;
         header_writer.write_all(command)?;
-        header_writer.write_u32::<LittleEndian>(body.len() as u32)?;
-        header_writer.write_all(&Sha256dChecksum::from(&body[..]).0)?;
+        header_writer.write_u32::<LittleEndian>(body_len as u32)?;
+        header_writer.write_all(&checksum.0)?;
 
-        dst.reserve(HEADER_LEN + body.len());
-        dst.extend_from_slice(&header);
-        dst.extend_from_slice(&body);
+        self.trace_frame("send", command, body_len, checksum, &dst[start..]);
+        if let Some(byte_counts) = &self.builder.byte_counts {
+            byte_counts.add_sent(HEADER_LEN + body_len);
+        }
 
         Ok(())
     }
@@ -241,6 +330,56 @@ impl Codec {
                 writer.write_all(data)?;
             }
             Message::FilterClear => { /* Empty payload -- no-op */ }
+            Message::GetCFilters {
+                filter_type,
+                start_height,
+                stop_hash,
+            }
+            | Message::GetCFHeaders {
+                filter_type,
+                start_height,
+                stop_hash,
+            } => {
+                writer.write_u8(*filter_type)?;
+                writer.write_u32::<LittleEndian>(start_height.0)?;
+                stop_hash.zcash_serialize(&mut writer)?;
+            }
+            Message::CFilter {
+                filter_type,
+                block_hash,
+                filter,
+            } => {
+                writer.write_u8(*filter_type)?;
+                block_hash.zcash_serialize(&mut writer)?;
+                filter.zcash_serialize(&mut writer)?;
+            }
+            Message::CFHeaders {
+                filter_type,
+                stop_hash,
+                previous_filter_header,
+                filter_hashes,
+            } => {
+                writer.write_u8(*filter_type)?;
+                stop_hash.zcash_serialize(&mut writer)?;
+                previous_filter_header.zcash_serialize(&mut writer)?;
+                filter_hashes.zcash_serialize(&mut writer)?;
+            }
+            Message::GetCFCheckpt {
+                filter_type,
+                stop_hash,
+            } => {
+                writer.write_u8(*filter_type)?;
+                stop_hash.zcash_serialize(&mut writer)?;
+            }
+            Message::CFCheckpt {
+                filter_type,
+                stop_hash,
+                filter_headers,
+            } => {
+                writer.write_u8(*filter_type)?;
+                stop_hash.zcash_serialize(&mut writer)?;
+                filter_headers.zcash_serialize(&mut writer)?;
+            }
         }
         Ok(())
     }
@@ -275,6 +414,41 @@ impl fmt::Debug for DecodeState {
     }
 }
 
+/// Returns the largest valid body length for messages with `command`, or
+/// `None` if the command is only limited by the overall frame size.
+///
+/// These limits are generous upper bounds on the encoded size of each
+/// message, so that a peer can't make us buffer a pathological payload for a
+/// message type that is always small.
+fn max_command_body_len(command: &[u8; 12]) -> Option<usize> {
+    // A compactsize length prefix for any count we accept.
+    const COUNT_LEN: usize = 3;
+    const HASH_LEN: usize = 32;
+
+    match command {
+        b"verack\0\0\0\0\0\0" | b"getaddr\0\0\0\0\0" | b"mempool\0\0\0\0\0" | b"filterclear\0" => {
+            Some(0)
+        }
+        b"ping\0\0\0\0\0\0\0\0" | b"pong\0\0\0\0\0\0\0\0" => Some(8),
+        // The fixed fields, plus a 256-byte user agent.
+        b"version\0\0\0\0\0" => Some(80 + COUNT_LEN + 256 + 5),
+        // A command, code, reason of up to 256 bytes, and data hash.
+        b"reject\0\0\0\0\0\0" => Some(1 + 12 + 1 + COUNT_LEN + 256 + HASH_LEN),
+        // Up to 1000 addresses, each with a timestamp, services, and address.
+        b"addr\0\0\0\0\0\0\0\0" => Some(COUNT_LEN + 1000 * 30),
+        // A version, up to 101 locator hashes, and a stop hash.
+        b"getblocks\0\0\0" | b"getheaders\0\0" => Some(4 + COUNT_LEN + 102 * HASH_LEN),
+        // BIP 37 limits filters to 36000 bytes and data elements to 520 bytes.
+        b"filterload\0\0" => Some(COUNT_LEN + 36_000 + 9),
+        b"filteradd\0\0\0" => Some(COUNT_LEN + 520),
+        b"getcfilters\0" | b"getcfheaders" => Some(1 + 4 + HASH_LEN),
+        b"getcfcheckpt" => Some(1 + HASH_LEN),
+        // Up to 2000 filter hashes, plus the stop hash and previous header.
+        b"cfheaders\0\0\0" => Some(1 + 2 * HASH_LEN + COUNT_LEN + 2000 * HASH_LEN),
+        _ => None,
+    }
+}
+
 impl Decoder for Codec {
     type Item = Message;
     type Error = Error;
@@ -311,9 +485,26 @@ impl Decoder for Codec {
                 if magic != Magic::from(self.builder.network) {
                     return Err(Parse("supplied magic did not meet expectations"));
                 }
-                if body_len >= self.builder.max_len {
+                // Check the declared length before reserving any space for
+                // the body, so that a peer can't make us allocate by lying.
+                let max_len = if &command == b"block\0\0\0\0\0\0\0" {
+                    self.builder.max_block_len
+                } else {
+                    self.builder.max_len
+                };
+                if body_len > max_len {
                     return Err(Parse("body length exceeded maximum size"));
                 }
+                if let Some(command_max_len) = max_command_body_len(&command) {
+                    if body_len > command_max_len {
+                        let command = String::from_utf8_lossy(&command)
+                            .trim_end_matches('\0')
+                            .to_owned();
+                        debug!(%command, body_len, command_max_len, "rejecting oversized message");
+                        metrics::counter!("peer.misbehavior", 1, "command" => command);
+                        return Err(Parse("body length exceeded maximum size for command"));
+                    }
+                }
 
                 // Reserve buffer space for the expected body and the following header.
                 src.reserve(body_len + HEADER_LEN);
@@ -341,8 +532,14 @@ impl Decoder for Codec {
                 // Now that we know we have the full body, split off the body,
                 // and reset the decoder state for the next message. Otherwise
                 // we will attempt to read the next header as the current body.
-                let body = src.split_to(body_len);
+                //
+                // Freezing the body lets messages that carry opaque byte
+                // strings hold slices of the receive buffer without copying.
+                let body = src.split_to(body_len).freeze();
                 self.state = DecodeState::Head;
+                if let Some(byte_counts) = &self.builder.byte_counts {
+                    byte_counts.add_received(HEADER_LEN + body_len);
+                }
 
                 if checksum != Sha256dChecksum::from(&body[..]) {
                     return Err(Parse(
@@ -350,7 +547,19 @@ impl Decoder for Codec {
                     ));
                 }
 
-                let body_reader = Cursor::new(&body);
+                if self.builder.trace_wire {
+                    // The header has already been split off the buffer, so
+                    // reassemble the frame for the dump.
+                    let mut frame = Vec::with_capacity(HEADER_LEN + body_len);
+                    frame.extend_from_slice(&Magic::from(self.builder.network).0[..]);
+                    frame.extend_from_slice(&command);
+                    frame.extend_from_slice(&(body_len as u32).to_le_bytes());
+                    frame.extend_from_slice(&checksum.0);
+                    frame.extend_from_slice(&body[..std::cmp::min(body_len, WIRE_TRACE_DUMP_LEN)]);
+                    self.trace_frame("recv", &command, body_len, checksum, &frame);
+                }
+
+                let body_reader = &body[..];
                 match &command {
                     b"version\0\0\0\0\0" => self.read_version(body_reader),
                     b"verack\0\0\0\0\0\0" => self.read_verack(body_reader),
@@ -359,18 +568,24 @@ impl Decoder for Codec {
                     b"reject\0\0\0\0\0\0" => self.read_reject(body_reader),
                     b"addr\0\0\0\0\0\0\0\0" => self.read_addr(body_reader),
                     b"getaddr\0\0\0\0\0" => self.read_getaddr(body_reader),
-                    b"block\0\0\0\0\0\0\0" => self.read_block(body_reader),
+                    b"block\0\0\0\0\0\0\0" => self.read_block(body.clone()),
                     b"getblocks\0\0\0" => self.read_getblocks(body_reader),
                     b"headers\0\0\0\0\0" => self.read_headers(body_reader),
                     b"getheaders\0\0" => self.read_getheaders(body_reader),
                     b"inv\0\0\0\0\0\0\0\0\0" => self.read_inv(body_reader),
                     b"getdata\0\0\0\0\0" => self.read_getdata(body_reader),
                     b"notfound\0\0\0\0" => self.read_notfound(body_reader),
-                    b"tx\0\0\0\0\0\0\0\0\0\0" => self.read_tx(body_reader),
+                    b"tx\0\0\0\0\0\0\0\0\0\0" => self.read_tx(body.clone()),
                     b"mempool\0\0\0\0\0" => self.read_mempool(body_reader),
-                    b"filterload\0\0" => self.read_filterload(body_reader, body_len),
-                    b"filteradd\0\0\0" => self.read_filteradd(body_reader),
+                    b"filterload\0\0" => self.read_filterload(body.clone()),
+                    b"filteradd\0\0\0" => self.read_filteradd(body.clone()),
                     b"filterclear\0" => self.read_filterclear(body_reader),
+                    b"getcfilters\0" => self.read_getcfilters(body_reader),
+                    b"cfilter\0\0\0\0\0" => self.read_cfilter(body_reader),
+                    b"getcfheaders" => self.read_getcfheaders(body_reader),
+                    b"cfheaders\0\0\0" => self.read_cfheaders(body_reader),
+                    b"getcfcheckpt" => self.read_getcfcheckpt(body_reader),
+                    b"cfcheckpt\0\0\0" => self.read_cfcheckpt(body_reader),
                     _ => return Err(Parse("unknown command")),
                 }
                 // We need Ok(Some(msg)) to signal that we're done decoding.
@@ -401,7 +616,7 @@ impl Codec {
             ),
             nonce: Nonce(reader.read_u64::<LittleEndian>()?),
             user_agent: reader.read_string()?,
-            start_height: BlockHeight(reader.read_u32::<LittleEndian>()?),
+            start_height: block::Height(reader.read_u32::<LittleEndian>()?),
             relay: match reader.read_u8()? {
                 0 => false,
                 1 => true,
@@ -450,15 +665,19 @@ impl Codec {
         Ok(Message::GetAddr)
     }
 
-    fn read_block<R: Read>(&self, reader: R) -> Result<Message, Error> {
-        Ok(Message::Block(Block::zcash_deserialize(reader)?.into()))
+    /// Blocks share their scripts and proofs with `body`, rather than
+    /// copying them.
+    fn read_block(&self, body: Bytes) -> Result<Message, Error> {
+        Ok(Message::Block(
+            Block::zcash_deserialize_shared(SharedBytes::new(body))?.into(),
+        ))
     }
 
     fn read_getblocks<R: Read>(&self, mut reader: R) -> Result<Message, Error> {
         if self.builder.version == Version(reader.read_u32::<LittleEndian>()?) {
             Ok(Message::GetBlocks {
                 block_locator_hashes: Vec::zcash_deserialize(&mut reader)?,
-                hash_stop: BlockHeaderHash::zcash_deserialize(&mut reader)?,
+                hash_stop: block::Hash::zcash_deserialize(&mut reader)?,
             })
         } else {
             Err(Error::Parse("getblocks version did not match negotiation"))
@@ -478,7 +697,7 @@ impl Codec {
         if self.builder.version == Version(reader.read_u32::<LittleEndian>()?) {
             Ok(Message::GetHeaders {
                 block_locator_hashes: Vec::zcash_deserialize(&mut reader)?,
-                hash_stop: BlockHeaderHash::zcash_deserialize(&mut reader)?,
+                hash_stop: block::Hash::zcash_deserialize(&mut reader)?,
             })
         } else {
             Err(Error::Parse("getblocks version did not match negotiation"))
@@ -497,49 +716,98 @@ impl Codec {
         Ok(Message::NotFound(Vec::zcash_deserialize(reader)?))
     }
 
-    fn read_tx<R: Read>(&self, rdr: R) -> Result<Message, Error> {
-        Ok(Message::Tx(Transaction::zcash_deserialize(rdr)?.into()))
+    fn read_tx(&self, body: Bytes) -> Result<Message, Error> {
+        Ok(Message::Tx(
+            Transaction::zcash_deserialize_shared(SharedBytes::new(body))?.into(),
+        ))
     }
 
     fn read_mempool<R: Read>(&self, mut _reader: R) -> Result<Message, Error> {
         Ok(Message::Mempool)
     }
 
-    fn read_filterload<R: Read>(&self, mut reader: R, body_len: usize) -> Result<Message, Error> {
-        if !(FILTERLOAD_REMAINDER_LENGTH <= body_len
-            && body_len <= FILTERLOAD_REMAINDER_LENGTH + MAX_FILTER_LENGTH)
-        {
-            return Err(Error::Parse("Invalid filterload message body length."));
-        }
-
+    fn read_filterload(&self, body: Bytes) -> Result<Message, Error> {
         const MAX_FILTER_LENGTH: usize = 36000;
         const FILTERLOAD_REMAINDER_LENGTH: usize = 4 + 4 + 1;
 
-        let filter_length: usize = body_len - FILTERLOAD_REMAINDER_LENGTH;
+        if !(FILTERLOAD_REMAINDER_LENGTH <= body.len()
+            && body.len() <= FILTERLOAD_REMAINDER_LENGTH + MAX_FILTER_LENGTH)
+        {
+            return Err(Error::Parse("Invalid filterload message body length."));
+        }
 
-        let mut filter_bytes = vec![0; filter_length];
-        reader.read_exact(&mut filter_bytes)?;
+        let filter_length: usize = body.len() - FILTERLOAD_REMAINDER_LENGTH;
+        let mut reader = &body[filter_length..];
 
         Ok(Message::FilterLoad {
-            filter: Filter(filter_bytes),
+            filter: Filter(body.slice(..filter_length)),
             hash_functions_count: reader.read_u32::<LittleEndian>()?,
             tweak: Tweak(reader.read_u32::<LittleEndian>()?),
             flags: reader.read_u8()?,
         })
     }
 
-    fn read_filteradd<R: Read>(&self, reader: R) -> Result<Message, Error> {
-        let mut bytes = Vec::new();
+    fn read_filteradd(&self, body: Bytes) -> Result<Message, Error> {
+        const MAX_FILTERADD_LENGTH: usize = 520;
 
-        // Maximum size of data is 520 bytes.
-        reader.take(520).read_exact(&mut bytes)?;
+        if body.len() > MAX_FILTERADD_LENGTH {
+            return Err(Error::Parse("Invalid filteradd message body length."));
+        }
 
-        Ok(Message::FilterAdd { data: bytes })
+        Ok(Message::FilterAdd { data: body })
     }
 
     fn read_filterclear<R: Read>(&self, mut _reader: R) -> Result<Message, Error> {
         Ok(Message::FilterClear)
     }
+
+    fn read_getcfilters<R: Read>(&self, mut reader: R) -> Result<Message, Error> {
+        Ok(Message::GetCFilters {
+            filter_type: reader.read_u8()?,
+            start_height: block::Height(reader.read_u32::<LittleEndian>()?),
+            stop_hash: block::Hash::zcash_deserialize(&mut reader)?,
+        })
+    }
+
+    fn read_cfilter<R: Read>(&self, mut reader: R) -> Result<Message, Error> {
+        Ok(Message::CFilter {
+            filter_type: reader.read_u8()?,
+            block_hash: block::Hash::zcash_deserialize(&mut reader)?,
+            filter: BlockFilter::zcash_deserialize(&mut reader)?,
+        })
+    }
+
+    fn read_getcfheaders<R: Read>(&self, mut reader: R) -> Result<Message, Error> {
+        Ok(Message::GetCFHeaders {
+            filter_type: reader.read_u8()?,
+            start_height: block::Height(reader.read_u32::<LittleEndian>()?),
+            stop_hash: block::Hash::zcash_deserialize(&mut reader)?,
+        })
+    }
+
+    fn read_cfheaders<R: Read>(&self, mut reader: R) -> Result<Message, Error> {
+        Ok(Message::CFHeaders {
+            filter_type: reader.read_u8()?,
+            stop_hash: block::Hash::zcash_deserialize(&mut reader)?,
+            previous_filter_header: FilterHeader::zcash_deserialize(&mut reader)?,
+            filter_hashes: Vec::zcash_deserialize(&mut reader)?,
+        })
+    }
+
+    fn read_getcfcheckpt<R: Read>(&self, mut reader: R) -> Result<Message, Error> {
+        Ok(Message::GetCFCheckpt {
+            filter_type: reader.read_u8()?,
+            stop_hash: block::Hash::zcash_deserialize(&mut reader)?,
+        })
+    }
+
+    fn read_cfcheckpt<R: Read>(&self, mut reader: R) -> Result<Message, Error> {
+        Ok(Message::CFCheckpt {
+            filter_type: reader.read_u8()?,
+            stop_hash: block::Hash::zcash_deserialize(&mut reader)?,
+            filter_headers: Vec::zcash_deserialize(&mut reader)?,
+        })
+    }
 }
 
 // XXX replace these interior unit tests with exterior integration tests + proptest
@@ -547,8 +815,11 @@ impl Codec {
 mod tests {
     use super::*;
     use futures::prelude::*;
+    use proptest::{arbitrary::any, prop_assert_eq, proptest};
     use tokio::runtime::Runtime;
 
+    use super::super::InventoryHash;
+
     #[test]
     fn version_message_round_trip() {
         use std::net::{IpAddr, Ipv4Addr, SocketAddr};
@@ -571,7 +842,7 @@ mod tests {
             ),
             nonce: Nonce(0x9082_4908_8927_9238),
             user_agent: "Zebra".to_owned(),
-            start_height: BlockHeight(540_000),
+            start_height: block::Height(540_000),
             relay: true,
         };
 
@@ -598,12 +869,40 @@ mod tests {
         assert_eq!(v, v_parsed);
     }
 
+    #[test]
+    fn block_scripts_share_the_message_buffer() {
+        let block = Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_415000_BYTES[..])
+            .expect("block test vector should deserialize");
+        let mut codec = Codec::builder().finish();
+        let mut buffer = BytesMut::new();
+        codec
+            .encode(Message::Block(block.clone().into()), &mut buffer)
+            .expect("message should be serialized");
+        let frame = buffer.as_ptr() as usize..buffer.as_ptr() as usize + buffer.len();
+
+        let parsed = match codec.decode(&mut buffer) {
+            Ok(Some(Message::Block(parsed))) => parsed,
+            result => panic!("expected a block message, got {:?}", result),
+        };
+        assert_eq!(*parsed, block);
+
+        let scripts = parsed
+            .transactions
+            .iter()
+            .flat_map(|tx| tx.outputs())
+            .map(|output| &output.pk_script.0)
+            .filter(|script| !script.is_empty());
+        for script in scripts {
+            assert!(frame.contains(&(script.as_ptr() as usize)));
+        }
+    }
+
     #[test]
     fn filterload_message_round_trip() {
         let mut rt = Runtime::new().unwrap();
 
         let v = Message::FilterLoad {
-            filter: Filter(vec![0; 35999]),
+            filter: Filter(vec![0; 35999].into()),
             hash_functions_count: 0,
             tweak: Tweak(0),
             flags: 0,
@@ -637,7 +936,7 @@ mod tests {
         let mut rt = Runtime::new().unwrap();
 
         let v = Message::FilterLoad {
-            filter: Filter(vec![0; 40000]),
+            filter: Filter(vec![0; 40000].into()),
             hash_functions_count: 0,
             tweak: Tweak(0),
             flags: 0,
@@ -664,6 +963,211 @@ mod tests {
         });
     }
 
+    #[test]
+    fn oversized_command_rejected() {
+        use crate::meta_addr::MetaAddr;
+        use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+        let mut rt = Runtime::new().unwrap();
+
+        let addr = MetaAddr {
+            addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 6)), 8233),
+            services: PeerServices::NODE_NETWORK,
+            last_seen: zebra_chain::serialization::DateTime32::from(1_568_000_000),
+        };
+
+        use tokio_util::codec::{FramedRead, FramedWrite};
+        for &(count, valid) in &[(1000, true), (1001, false)] {
+            let v = Message::Addr(vec![addr; count]);
+            let v_bytes = rt.block_on(async {
+                let mut bytes = Vec::new();
+                {
+                    let mut fw = FramedWrite::new(&mut bytes, Codec::builder().finish());
+                    fw.send(v.clone())
+                        .await
+                        .expect("message should be serialized");
+                }
+                bytes
+            });
+
+            let result = rt.block_on(async {
+                let mut fr = FramedRead::new(Cursor::new(&v_bytes), Codec::builder().finish());
+                fr.next().await.expect("a next message should be available")
+            });
+            assert_eq!(result.is_ok(), valid, "addr message with {} entries", count);
+        }
+    }
+
+    #[test]
+    fn oversized_message_rejected() {
+        let mut rt = Runtime::new().unwrap();
+
+        let v = Message::Ping(Nonce(0x9082_4908_8927_9238));
+
+        use tokio_util::codec::{FramedRead, FramedWrite};
+        let v_bytes = rt.block_on(async {
+            let mut bytes = Vec::new();
+            {
+                let mut fw = FramedWrite::new(&mut bytes, Codec::builder().finish());
+                fw.send(v.clone())
+                    .await
+                    .expect("message should be serialized");
+            }
+            bytes
+        });
+
+        rt.block_on(async {
+            let mut fr = FramedRead::new(
+                Cursor::new(&v_bytes),
+                Codec::builder().with_max_body_len(7).finish(),
+            );
+            fr.next()
+                .await
+                .expect("a next message should be available")
+                .expect_err("that message should exceed the maximum length")
+        });
+
+        let v_parsed = rt.block_on(async {
+            let mut fr = FramedRead::new(
+                Cursor::new(&v_bytes),
+                Codec::builder().with_max_body_len(8).finish(),
+            );
+            fr.next()
+                .await
+                .expect("a next message should be available")
+                .expect("a message at the maximum length should deserialize")
+        });
+
+        assert_eq!(v, v_parsed);
+    }
+
+    #[test]
+    fn wire_tracing_round_trip() {
+        let mut rt = Runtime::new().unwrap();
+
+        let v = Message::Ping(Nonce(0x9082_4908_8927_9238));
+
+        use tokio_util::codec::{FramedRead, FramedWrite};
+        let v_parsed = rt.block_on(async {
+            let mut bytes = Vec::new();
+            {
+                let mut fw = FramedWrite::new(
+                    &mut bytes,
+                    Codec::builder().with_wire_tracing(true).finish(),
+                );
+                fw.send(v.clone())
+                    .await
+                    .expect("message should be serialized");
+            }
+            let mut fr = FramedRead::new(
+                Cursor::new(&bytes),
+                Codec::builder().with_wire_tracing(true).finish(),
+            );
+            fr.next()
+                .await
+                .expect("a next message should be available")
+                .expect("that message should deserialize")
+        });
+
+        assert_eq!(v, v_parsed);
+    }
+
+    #[test]
+    fn compact_filter_messages_round_trip() {
+        use zebra_chain::block::filter::{FilterHash, BASIC_FILTER_TYPE};
+
+        let mut rt = Runtime::new().unwrap();
+
+        let stop_hash = block::Hash([7; 32]);
+        let messages = vec![
+            Message::GetCFilters {
+                filter_type: BASIC_FILTER_TYPE,
+                start_height: block::Height(1000),
+                stop_hash,
+            },
+            Message::CFilter {
+                filter_type: BASIC_FILTER_TYPE,
+                block_hash: stop_hash,
+                filter: BlockFilter(vec![0x01, 0x8a, 0x40]),
+            },
+            Message::CFHeaders {
+                filter_type: BASIC_FILTER_TYPE,
+                stop_hash,
+                previous_filter_header: FilterHeader([1; 32]),
+                filter_hashes: vec![FilterHash([2; 32]), FilterHash([3; 32])],
+            },
+            Message::GetCFCheckpt {
+                filter_type: BASIC_FILTER_TYPE,
+                stop_hash,
+            },
+            Message::CFCheckpt {
+                filter_type: BASIC_FILTER_TYPE,
+                stop_hash,
+                filter_headers: vec![FilterHeader([4; 32])],
+            },
+        ];
+
+        use tokio_util::codec::{FramedRead, FramedWrite};
+        for v in messages {
+            let v_parsed = rt.block_on(async {
+                let mut bytes = Vec::new();
+                {
+                    let mut fw = FramedWrite::new(&mut bytes, Codec::builder().finish());
+                    fw.send(v.clone())
+                        .await
+                        .expect("message should be serialized");
+                }
+                let mut fr = FramedRead::new(Cursor::new(&bytes), Codec::builder().finish());
+                fr.next()
+                    .await
+                    .expect("a next message should be available")
+                    .expect("that message should deserialize")
+            });
+
+            assert_eq!(v, v_parsed);
+        }
+    }
+
+    #[test]
+    fn hex_prefix_truncates() {
+        assert_eq!(hex_prefix(&[0xab, 0xcd], 2), "abcd");
+        assert_eq!(hex_prefix(&[0xab, 0xcd, 0xef], 2), "abcd..");
+    }
+
+    /// Serialize `msg` with a default codec, then deserialize it again.
+    fn codec_round_trip(msg: Message) -> Result<Message, Error> {
+        let mut codec = Codec::builder().finish();
+        let mut bytes = BytesMut::new();
+        codec.encode(msg, &mut bytes)?;
+
+        let msg = codec
+            .decode(&mut bytes)?
+            .expect("the buffer contains a whole message");
+        assert!(bytes.is_empty(), "the message should use the whole buffer");
+        Ok(msg)
+    }
+
+    /// Serialize `value`, then deserialize it again.
+    fn zcash_round_trip<T: ZcashSerialize + ZcashDeserialize>(value: &T) -> Result<T, Error> {
+        let mut bytes = Vec::new();
+        value.zcash_serialize(&mut bytes)?;
+        T::zcash_deserialize(&bytes[..])
+    }
+
+    proptest! {
+        #[test]
+        fn message_round_trip(msg in any::<Message>()) {
+            let other_msg = codec_round_trip(msg.clone())?;
+            prop_assert_eq!(msg, other_msg);
+        }
+
+        #[test]
+        fn inventory_hash_round_trip(inv in any::<InventoryHash>()) {
+            let other_inv = zcash_round_trip(&inv)?;
+            prop_assert_eq!(inv, other_inv);
+        }
+    }
+
     #[test]
     fn decode_state_debug() {
         assert_eq!(format!("{:?}", DecodeState::Head), "DecodeState::Head");
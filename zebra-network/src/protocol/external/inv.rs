@@ -8,11 +8,12 @@ use std::io::{Read, Write};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
-use zebra_chain::block::BlockHeaderHash;
+use zebra_chain::block;
 use zebra_chain::serialization::{
-    ReadZcashExt, SerializationError, ZcashDeserialize, ZcashSerialize,
+    ReadZcashExt, SerializationError, TrustedPreallocate, ZcashDeserialize, ZcashSerialize,
+    MAX_PROTOCOL_MESSAGE_LEN,
 };
-use zebra_chain::transaction::TransactionHash;
+use zebra_chain::transaction::{self, AuthDigest, UnminedTxId, WtxId};
 
 /// An inventory hash which refers to some advertised or requested data.
 ///
@@ -20,7 +21,7 @@ use zebra_chain::transaction::TransactionHash;
 /// container, so we do not use that term to avoid confusion with `Vec<T>`.
 ///
 /// [Bitcoin·reference](https://en.bitcoin.it/wiki/Protocol_documentation#Inventory_Vectors)
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub enum InventoryHash {
     /// An error.
     ///
@@ -28,26 +29,60 @@ pub enum InventoryHash {
     /// so we don't include a typed hash.
     Error,
     /// A hash of a transaction.
-    Tx(TransactionHash),
+    Tx(transaction::Hash),
     /// A hash of a block.
-    Block(BlockHeaderHash),
+    Block(block::Hash),
     /// A hash of a filtered block.
     ///
     /// The Bitcoin wiki says: Hash of a block header, but only to be used in
     /// getdata message. Indicates the reply should be a merkleblock message
     /// rather than a block message; this only works if a bloom filter has been
     /// set.
-    FilteredBlock(BlockHeaderHash),
+    FilteredBlock(block::Hash),
+    /// A pair of a transaction ID and its authorizing data digest.
+    ///
+    /// v5 transaction IDs don't commit to authorizing data, so ZIP-239 uses
+    /// this wide ID to advertise and request v5 transactions.
+    ///
+    /// [ZIP-239](https://zips.z.cash/zip-0239)
+    Wtx(WtxId),
+}
+
+impl InventoryHash {
+    /// Returns the unmined transaction ID for transaction inventory, or `None`
+    /// for other kinds of inventory.
+    pub fn unmined_tx_id(&self) -> Option<UnminedTxId> {
+        match self {
+            InventoryHash::Tx(hash) => Some(UnminedTxId::Legacy(*hash)),
+            InventoryHash::Wtx(wtx_id) => Some(UnminedTxId::Witnessed(*wtx_id)),
+            _ => None,
+        }
+    }
 }
 
-impl From<TransactionHash> for InventoryHash {
-    fn from(tx: TransactionHash) -> InventoryHash {
+impl From<UnminedTxId> for InventoryHash {
+    fn from(tx: UnminedTxId) -> InventoryHash {
+        match tx {
+            UnminedTxId::Legacy(hash) => InventoryHash::Tx(hash),
+            UnminedTxId::Witnessed(wtx_id) => InventoryHash::Wtx(wtx_id),
+        }
+    }
+}
+
+impl From<transaction::Hash> for InventoryHash {
+    fn from(tx: transaction::Hash) -> InventoryHash {
         InventoryHash::Tx(tx)
     }
 }
 
-impl From<BlockHeaderHash> for InventoryHash {
-    fn from(block: BlockHeaderHash) -> InventoryHash {
+impl From<WtxId> for InventoryHash {
+    fn from(wtx_id: WtxId) -> InventoryHash {
+        InventoryHash::Wtx(wtx_id)
+    }
+}
+
+impl From<block::Hash> for InventoryHash {
+    fn from(block: block::Hash) -> InventoryHash {
         // Auto-convert to Block rather than FilteredBlock because filtered
         // blocks aren't useful for Zcash.
         InventoryHash::Block(block)
@@ -61,6 +96,13 @@ impl ZcashSerialize for InventoryHash {
             InventoryHash::Tx(hash) => (1, hash.0),
             InventoryHash::Block(hash) => (2, hash.0),
             InventoryHash::FilteredBlock(hash) => (3, hash.0),
+            InventoryHash::Wtx(wtx_id) => {
+                // Wide IDs are the only inventory with a 64-byte payload.
+                writer.write_u32::<LittleEndian>(5)?;
+                writer.write_all(&wtx_id.id.0)?;
+                writer.write_all(&wtx_id.auth_digest.0)?;
+                return Ok(());
+            }
         };
         writer.write_u32::<LittleEndian>(code)?;
         writer.write_all(&bytes)?;
@@ -74,10 +116,45 @@ impl ZcashDeserialize for InventoryHash {
         let bytes = reader.read_32_bytes()?;
         match code {
             0 => Ok(InventoryHash::Error),
-            1 => Ok(InventoryHash::Tx(TransactionHash(bytes))),
-            2 => Ok(InventoryHash::Block(BlockHeaderHash(bytes))),
-            3 => Ok(InventoryHash::FilteredBlock(BlockHeaderHash(bytes))),
+            1 => Ok(InventoryHash::Tx(transaction::Hash(bytes))),
+            2 => Ok(InventoryHash::Block(block::Hash(bytes))),
+            3 => Ok(InventoryHash::FilteredBlock(block::Hash(bytes))),
+            5 => Ok(InventoryHash::Wtx(WtxId {
+                id: transaction::Hash(bytes),
+                auth_digest: AuthDigest(reader.read_32_bytes()?),
+            })),
             _ => Err(SerializationError::Parse("invalid inventory code")),
         }
     }
 }
+
+/// The smallest inventory hashes are a 4-byte code and a 32-byte hash.
+impl TrustedPreallocate for InventoryHash {
+    fn max_allocation() -> u64 {
+        (MAX_PROTOCOL_MESSAGE_LEN / (4 + 32)) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wtx_inventory_round_trip() {
+        let wtx_id = WtxId {
+            id: transaction::Hash([0x11; 32]),
+            auth_digest: AuthDigest([0x22; 32]),
+        };
+        let inv = InventoryHash::from(UnminedTxId::from(wtx_id));
+
+        let mut bytes = Vec::new();
+        inv.zcash_serialize(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 4 + 64);
+        assert_eq!(&bytes[..4], &[5, 0, 0, 0]);
+
+        let inv2 = InventoryHash::zcash_deserialize(&bytes[..]).unwrap();
+        assert_eq!(inv, inv2);
+        assert_eq!(inv2, InventoryHash::Wtx(wtx_id));
+        assert_eq!(inv2.unmined_tx_id(), Some(UnminedTxId::Witnessed(wtx_id)));
+    }
+}
@@ -3,10 +3,15 @@
 use std::error::Error;
 use std::{net, sync::Arc};
 
+use bytes::Bytes;
 use chrono::{DateTime, Utc};
 
-use zebra_chain::block::{Block, BlockHeader, BlockHeaderHash};
-use zebra_chain::{transaction::Transaction, types::BlockHeight};
+use zebra_chain::block::{
+    self,
+    filter::{BlockFilter, FilterHash, FilterHeader},
+    Block,
+};
+use zebra_chain::transaction::Transaction;
 
 use super::inv::InventoryHash;
 use super::types::*;
@@ -66,7 +71,7 @@ pub enum Message {
         user_agent: String,
 
         /// The last block received by the emitting node.
-        start_height: BlockHeight,
+        start_height: block::Height,
 
         /// Whether the remote peer should announce relayed
         /// transactions or not, see [BIP 0037](https://github.com/bitcoin/bips/blob/master/bip-0037.mediawiki)
@@ -161,12 +166,12 @@ pub enum Message {
     // many results.
     GetBlocks {
         /// Block locators, from newest back to genesis block.
-        block_locator_hashes: Vec<BlockHeaderHash>,
+        block_locator_hashes: Vec<block::Hash>,
 
-        /// `BlockHeaderHash` of the last desired block.
+        /// `block::Hash` of the last desired block.
         ///
         /// Set to zero to get as many blocks as possible (500).
-        hash_stop: BlockHeaderHash,
+        hash_stop: block::Hash,
     },
 
     /// A `headers` message.
@@ -178,7 +183,7 @@ pub enum Message {
     // transaction count (a var_int, so there can be more than 81
     // bytes per header) as opposed to the block headers that are
     // hashed by miners.
-    Headers(Vec<BlockHeader>),
+    Headers(Vec<block::Header>),
 
     /// A `getheaders` message.
     ///
@@ -201,12 +206,12 @@ pub enum Message {
     // many results.
     GetHeaders {
         /// Block locators, from newest back to genesis block.
-        block_locator_hashes: Vec<BlockHeaderHash>,
+        block_locator_hashes: Vec<block::Hash>,
 
-        /// `BlockHeaderHash` of the last desired block header.
+        /// `block::Hash` of the last desired block header.
         ///
         /// Set to zero to get as many block headers as possible (2000).
-        hash_stop: BlockHeaderHash,
+        hash_stop: block::Hash,
     },
 
     /// An `inv` message.
@@ -281,8 +286,9 @@ pub enum Message {
         // in size (the maximum size of any potentially matched
         // object).
         //
-        // A Vec instead of [u8; 520] because of needed traits.
-        data: Vec<u8>,
+        // Bytes instead of [u8; 520] because of needed traits, and so
+        // that the data can borrow from the receive buffer.
+        data: Bytes,
     },
 
     /// A `filterclear` message.
@@ -292,6 +298,134 @@ pub enum Message {
     /// [Bitcoin reference](https://en.bitcoin.it/wiki/Protocol_documentation#filterload.2C_filteradd.2C_filterclear.2C_merkleblock)
     /// [BIP37]: https://github.com/bitcoin/bips/blob/master/bip-0037.mediawiki
     FilterClear,
+
+    /// A `getcfilters` message.
+    ///
+    /// Requests compact filters for a range of blocks, from `start_height`
+    /// up to and including the block with hash `stop_hash`.
+    ///
+    /// This was defined in [BIP157].
+    ///
+    /// [BIP157]: https://github.com/bitcoin/bips/blob/master/bip-0157.mediawiki
+    GetCFilters {
+        /// The type of filter requested.
+        filter_type: u8,
+        /// The height of the first block in the range.
+        start_height: block::Height,
+        /// The hash of the last block in the range.
+        stop_hash: block::Hash,
+    },
+
+    /// A `cfilter` message, sent in response to `getcfilters`, once for
+    /// each block in the requested range.
+    ///
+    /// This was defined in [BIP157].
+    ///
+    /// [BIP157]: https://github.com/bitcoin/bips/blob/master/bip-0157.mediawiki
+    CFilter {
+        /// The type of filter.
+        filter_type: u8,
+        /// The hash of the block the filter was built for.
+        block_hash: block::Hash,
+        /// The encoded filter.
+        filter: BlockFilter,
+    },
+
+    /// A `getcfheaders` message.
+    ///
+    /// Requests filter hashes for a range of blocks, along with the filter
+    /// header that precedes them, so the client can verify the header chain.
+    ///
+    /// This was defined in [BIP157].
+    ///
+    /// [BIP157]: https://github.com/bitcoin/bips/blob/master/bip-0157.mediawiki
+    GetCFHeaders {
+        /// The type of filter requested.
+        filter_type: u8,
+        /// The height of the first block in the range.
+        start_height: block::Height,
+        /// The hash of the last block in the range.
+        stop_hash: block::Hash,
+    },
+
+    /// A `cfheaders` message, sent in response to `getcfheaders`.
+    ///
+    /// This was defined in [BIP157].
+    ///
+    /// [BIP157]: https://github.com/bitcoin/bips/blob/master/bip-0157.mediawiki
+    CFHeaders {
+        /// The type of filter.
+        filter_type: u8,
+        /// The hash of the last block in the range.
+        stop_hash: block::Hash,
+        /// The filter header of the block before the range.
+        previous_filter_header: FilterHeader,
+        /// The filter hashes of each block in the range.
+        filter_hashes: Vec<FilterHash>,
+    },
+
+    /// A `getcfcheckpt` message.
+    ///
+    /// Requests the filter headers at every 1000th block, up to the block
+    /// with hash `stop_hash`.
+    ///
+    /// This was defined in [BIP157].
+    ///
+    /// [BIP157]: https://github.com/bitcoin/bips/blob/master/bip-0157.mediawiki
+    GetCFCheckpt {
+        /// The type of filter requested.
+        filter_type: u8,
+        /// The hash of the last block to consider.
+        stop_hash: block::Hash,
+    },
+
+    /// A `cfcheckpt` message, sent in response to `getcfcheckpt`.
+    ///
+    /// This was defined in [BIP157].
+    ///
+    /// [BIP157]: https://github.com/bitcoin/bips/blob/master/bip-0157.mediawiki
+    CFCheckpt {
+        /// The type of filter.
+        filter_type: u8,
+        /// The hash of the last block considered.
+        stop_hash: block::Hash,
+        /// The filter headers at heights 1000, 2000, ..., in order.
+        filter_headers: Vec<FilterHeader>,
+    },
+}
+
+impl Message {
+    /// Returns the wire command name of this message, without padding.
+    pub fn command(&self) -> &'static str {
+        use Message::*;
+        match self {
+            Version { .. } => "version",
+            Verack => "verack",
+            Ping(_) => "ping",
+            Pong(_) => "pong",
+            Reject { .. } => "reject",
+            Addr(_) => "addr",
+            GetAddr => "getaddr",
+            Block(_) => "block",
+            GetBlocks { .. } => "getblocks",
+            Headers(_) => "headers",
+            GetHeaders { .. } => "getheaders",
+            Inv(_) => "inv",
+            GetData(_) => "getdata",
+            NotFound(_) => "notfound",
+            Tx(_) => "tx",
+            Mempool => "mempool",
+            FilterLoad { .. } => "filterload",
+            FilterAdd { .. } => "filteradd",
+            FilterClear => "filterclear",
+            GetCFilters { .. } => "getcfilters",
+            CFilter { .. } => "cfilter",
+            GetCFHeaders { .. } => "getcfheaders",
+            CFHeaders { .. } => "cfheaders",
+            GetCFCheckpt { .. } => "getcfcheckpt",
+            CFCheckpt { .. } => "cfcheckpt",
+        }
+    }
 }
 
 impl<E> From<E> for Message
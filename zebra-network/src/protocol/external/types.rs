@@ -1,10 +1,18 @@
 #![allow(clippy::unit_arg)]
-use std::fmt;
+use std::{fmt, io};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use bytes::Bytes;
 
 #[cfg(test)]
 use proptest_derive::Arbitrary;
 
-use zebra_chain::Network;
+use zebra_chain::{
+    block,
+    network_upgrade::NetworkUpgrade,
+    serialization::{SerializationError, ZcashDeserialize, ZcashSerialize},
+    Network,
+};
 
 use crate::constants::magics;
 
@@ -25,6 +33,7 @@ impl From<Network> for Magic {
         match network {
             Network::Mainnet => magics::MAINNET,
             Network::Testnet => magics::TESTNET,
+            Network::Regtest => magics::REGTEST,
         }
     }
 }
@@ -33,6 +42,50 @@ impl From<Network> for Magic {
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
 pub struct Version(pub u32);
 
+impl Version {
+    /// Returns the minimum protocol version that nodes must support once
+    /// `network_upgrade` has activated on `network`.
+    ///
+    /// Returns `None` for upgrades before Overwinter, which didn't set a
+    /// minimum version.
+    pub fn min_for_upgrade(network: Network, network_upgrade: NetworkUpgrade) -> Option<Version> {
+        use NetworkUpgrade::*;
+        // Version numbers from the zcashd chain parameters.
+        let version = match (network, network_upgrade) {
+            (_, Genesis) | (_, BeforeOverwinter) => return None,
+            // Regtest uses the testnet versions.
+            (Network::Mainnet, Overwinter) => 170_005,
+            (_, Overwinter) => 170_003,
+            (_, Sapling) => 170_007,
+            (Network::Mainnet, Blossom) => 170_009,
+            (_, Blossom) => 170_008,
+            (Network::Mainnet, Heartwood) => 170_011,
+            (_, Heartwood) => 170_010,
+            (Network::Mainnet, Canopy) => 170_013,
+            (_, Canopy) => 170_012,
+            (Network::Mainnet, Nu5) => 170_100,
+            (_, Nu5) => 170_050,
+        };
+        Some(Version(version))
+    }
+
+    /// Returns the minimum protocol version that remote peers must support,
+    /// when our best chain tip is at `tip_height` on `network`.
+    ///
+    /// This is at least [`constants::MIN_VERSION`](crate::constants::MIN_VERSION),
+    /// even if the tip height is unknown.
+    pub fn min_remote_for_height(network: Network, tip_height: Option<block::Height>) -> Version {
+        let upgrade_version = tip_height.and_then(|height| {
+            Version::min_for_upgrade(network, NetworkUpgrade::current(network, height))
+        });
+
+        match upgrade_version {
+            Some(version) => std::cmp::max(version, crate::constants::MIN_VERSION),
+            None => crate::constants::MIN_VERSION,
+        }
+    }
+}
+
 bitflags! {
     /// A bitflag describing services advertised by a node in the network.
     ///
@@ -44,6 +97,48 @@ bitflags! {
         /// blocks, as opposed to a light client that makes network requests but
         /// does not provide network services.
         const NODE_NETWORK = 1;
+        /// NODE_GETUTXO means that the node answers BIP64 `getutxo`
+        /// requests. Zcash nodes don't implement it.
+        const NODE_GETUTXO = 1 << 1;
+        /// NODE_BLOOM means that the node serves BIP37 bloom-filtered
+        /// blocks and transactions.
+        const NODE_BLOOM = 1 << 2;
+        /// NODE_COMPACT_FILTERS means that the node serves BIP157 compact
+        /// block filters.
+        const NODE_COMPACT_FILTERS = 1 << 6;
+        /// NODE_NETWORK_LIMITED means that the node serves recent blocks,
+        /// as defined in BIP159, but may have pruned older ones.
+        const NODE_NETWORK_LIMITED = 1 << 10;
+    }
+}
+
+impl serde::Serialize for PeerServices {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.bits().serialize(serializer)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for PeerServices {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Discard unknown service bits, so we never advertise them.
+        Ok(PeerServices::from_bits_truncate(u64::deserialize(
+            deserializer,
+        )?))
+    }
+}
+
+impl ZcashSerialize for PeerServices {
+    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        writer.write_u64::<LittleEndian>(self.bits())
+    }
+}
+
+impl ZcashDeserialize for PeerServices {
+    fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
+        // Discard unknown service bits.
+        Ok(PeerServices::from_bits_truncate(
+            reader.read_u64::<LittleEndian>()?,
+        ))
     }
 }
 
@@ -72,14 +167,14 @@ impl Default for Tweak {
 /// A Bloom filter consisting of a bit field of arbitrary byte-aligned
 /// size, maximum size is 36,000 bytes.
 #[derive(Clone, Debug, Eq, PartialEq)]
-pub struct Filter(pub Vec<u8>);
+pub struct Filter(pub Bytes);
 
 #[cfg(test)]
 mod proptest {
 
     use proptest::prelude::*;
 
-    use super::Magic;
+    use super::{Magic, Network, NetworkUpgrade, Version};
 
     use crate::constants::magics;
 
@@ -87,6 +182,50 @@ mod proptest {
     fn magic_debug() {
         assert_eq!(format!("{:?}", magics::MAINNET), "Magic(\"24e92764\")");
         assert_eq!(format!("{:?}", magics::TESTNET), "Magic(\"fa1af9bf\")");
+        assert_eq!(format!("{:?}", magics::REGTEST), "Magic(\"aae83f5f\")");
+    }
+
+    #[test]
+    fn min_remote_version_increases_at_upgrades() {
+        for &network in &[Network::Mainnet, Network::Testnet, Network::Regtest] {
+            let mut last = Version::min_remote_for_height(network, None);
+            for (height, _) in NetworkUpgrade::activation_list(network) {
+                let version = Version::min_remote_for_height(network, Some(height));
+                assert!(version >= last);
+                last = version;
+            }
+        }
+
+        let canopy = NetworkUpgrade::Canopy
+            .activation_height(Network::Mainnet)
+            .unwrap();
+        assert_eq!(
+            Version::min_remote_for_height(Network::Mainnet, Some(canopy)),
+            Version(170_013)
+        );
+    }
+
+    #[test]
+    fn current_version_supports_every_upgrade() {
+        for &network in &[Network::Mainnet, Network::Testnet, Network::Regtest] {
+            for (_, network_upgrade) in NetworkUpgrade::activation_list(network) {
+                if let Some(version) = Version::min_for_upgrade(network, network_upgrade) {
+                    assert!(
+                        crate::constants::CURRENT_VERSION >= version,
+                        "{:?} on {:?} needs version {:?}",
+                        network_upgrade,
+                        network,
+                        version
+                    );
+                }
+            }
+        }
+
+        // Upgrades without an activation height are still supported.
+        assert!(
+            crate::constants::CURRENT_VERSION
+                >= Version::min_for_upgrade(Network::Mainnet, NetworkUpgrade::Nu5).unwrap()
+        );
     }
 
     proptest! {
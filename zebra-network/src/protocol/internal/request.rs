@@ -1,13 +1,19 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, sync::Arc};
 
-use zebra_chain::block::BlockHeaderHash;
+use zebra_chain::{
+    block,
+    transaction::{Transaction, UnminedTxId},
+};
 
-use super::super::types::Nonce;
+use super::super::types::{Nonce, PeerServices};
 
 /// A network request, represented in internal format.
 #[derive(Clone, Debug)]
 pub enum Request {
     /// Requests additional peers from the server.
+    ///
+    /// The peer set sends this request to several random peers, and responds
+    /// with the merged and sanitized addresses from all of them.
     Peers,
 
     /// Heartbeats triggered on peer connection start.
@@ -19,22 +25,118 @@ pub enum Request {
 
     /// Request block data by block hashes.
     ///
-    /// This uses a `HashSet` rather than a `Vec` for two reasons. First, it
-    /// automatically deduplicates the requested blocks. Second, the internal
-    /// protocol translator needs to maintain a `HashSet` anyways, in order to
-    /// keep track of which requested blocks have been received and when the
-    /// request is ready. Rather than force the internals to always convert into
-    /// a `HashSet`, we require the caller to pass one, so that if the caller
-    /// didn't start with a `Vec` but with, e.g., an iterator, they can collect
-    /// directly into a `HashSet` and save work.
-    BlocksByHash(HashSet<BlockHeaderHash>),
+    /// The blocks are returned in the order of the hashes, with duplicates
+    /// removed. Large requests are split into pipelined `getdata` batches, and
+    /// the blocks are reassembled in order, even if the peer answers the
+    /// batches out of order.
+    BlocksByHash(Vec<block::Hash>),
 
     /// Request block hashes of subsequent blocks in the chain, giving hashes of
     /// known blocks.
     FindBlocks {
         /// Hashes of known blocks, ordered from highest height to lowest height.
-        known_blocks: Vec<BlockHeaderHash>,
+        known_blocks: Vec<block::Hash>,
         /// Optionally, the last header to request.
-        stop: Option<BlockHeaderHash>,
+        stop: Option<block::Hash>,
     },
+
+    /// Request transactions by their unmined IDs.
+    ///
+    /// v5 transactions are requested by their wide ID, and earlier versions
+    /// by their transaction ID.
+    ///
+    /// This uses a `HashSet`, because transactions don't have a natural
+    /// order, and it automatically deduplicates the requested transactions.
+    TransactionsByHash(HashSet<UnminedTxId>),
+
+    /// Request headers of subsequent blocks in the chain, giving hashes of
+    /// known blocks.
+    FindHeaders {
+        /// Hashes of known blocks, ordered from highest height to lowest height.
+        known_blocks: Vec<block::Hash>,
+        /// Optionally, the last header to request.
+        stop: Option<block::Hash>,
+    },
+
+    /// Push a transaction to a remote peer, without advertising it first.
+    ///
+    /// The peer doesn't acknowledge pushed transactions, so this request
+    /// finishes as soon as the transaction is sent.
+    PushTransaction(Arc<Transaction>),
+
+    /// Advertise a set of transactions to a remote peer.
+    ///
+    /// The peer may request the transactions later, so this request
+    /// finishes as soon as the advertisement is sent.
+    ///
+    /// The peer set sends this request to a random subset of its ready
+    /// peers, rather than just one.
+    AdvertiseTransactionIds(HashSet<UnminedTxId>),
+
+    /// Advertise a block to a remote peer.
+    ///
+    /// The peer may request the block later, so this request finishes as
+    /// soon as the advertisement is sent.
+    ///
+    /// Like [`Request::AdvertiseTransactionIds`], the peer set sends this
+    /// request to a random subset of its ready peers.
+    AdvertiseBlock(block::Hash),
+
+    /// Request the transaction hashes in a remote peer's mempool.
+    MempoolTransactions,
+
+    /// Request BIP158 basic filters for the blocks from `start_height` up to
+    /// and including `stop`.
+    ///
+    /// BIP157 limits each request to 1000 blocks.
+    CompactFilters {
+        /// The height of the first block.
+        start_height: block::Height,
+        /// The hash of the last block.
+        stop: block::Hash,
+    },
+
+    /// Request basic filter hashes for the blocks from `start_height` up to
+    /// and including `stop`, and the filter header before them.
+    ///
+    /// BIP157 limits each request to 2000 blocks.
+    CompactFilterHeaders {
+        /// The height of the first block.
+        start_height: block::Height,
+        /// The hash of the last block.
+        stop: block::Hash,
+    },
+
+    /// Request the basic filter headers at every 1000th block, up to `stop`.
+    CompactFilterCheckpoints {
+        /// The hash of the last block to consider.
+        stop: block::Hash,
+    },
+}
+
+impl Request {
+    /// Returns the services that a peer must advertise for the peer set to
+    /// send it this request.
+    ///
+    /// Block and header downloads need a full node, and filter requests need
+    /// a node that serves BIP157 filters. Other requests can go to any peer.
+    /// We don't make bloom filter requests, so none of them need
+    /// `NODE_BLOOM`.
+    pub fn required_services(&self) -> PeerServices {
+        match self {
+            Request::BlocksByHash(_) | Request::FindBlocks { .. } | Request::FindHeaders { .. } => {
+                PeerServices::NODE_NETWORK
+            }
+            Request::CompactFilters { .. }
+            | Request::CompactFilterHeaders { .. }
+            | Request::CompactFilterCheckpoints { .. } => PeerServices::NODE_COMPACT_FILTERS,
+            Request::Peers
+            | Request::Ping(_)
+            | Request::TransactionsByHash(_)
+            | Request::PushTransaction(_)
+            | Request::AdvertiseTransactionIds(_)
+            | Request::AdvertiseBlock(_)
+            | Request::MempoolTransactions => PeerServices::empty(),
+        }
+    }
 }
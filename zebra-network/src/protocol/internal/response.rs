@@ -1,8 +1,15 @@
 // XXX clean module layout of zebra_chain
-use zebra_chain::block::{Block, BlockHeaderHash};
+use zebra_chain::{
+    block::{
+        self,
+        filter::{BlockFilter, FilterHash, FilterHeader},
+        Block,
+    },
+    transaction::{Transaction, UnminedTxId},
+};
 
 use crate::meta_addr::MetaAddr;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 /// A response to a network request, represented in internal format.
 #[derive(Clone, Debug)]
@@ -10,6 +17,10 @@ pub enum Response {
     /// A response with no data.
     Nil,
 
+    /// The round-trip time of an answered `Ping`, measured from when the
+    /// ping was sent to the peer.
+    Pong(Duration),
+
     /// A list of peers, used to respond to `GetPeers`.
     Peers(Vec<MetaAddr>),
 
@@ -17,5 +28,37 @@ pub enum Response {
     Blocks(Vec<Arc<Block>>),
 
     /// A list of block hashes.
-    BlockHeaderHashes(Vec<BlockHeaderHash>),
+    BlockHashes(Vec<block::Hash>),
+
+    /// A list of block headers.
+    BlockHeaders(Vec<block::Header>),
+
+    /// A list of transactions.
+    Transactions(Vec<Arc<Transaction>>),
+
+    /// A list of unmined transaction IDs.
+    TransactionHashes(Vec<UnminedTxId>),
+
+    /// A list of basic filters, with the hashes of their blocks.
+    CompactFilters(Vec<(block::Hash, BlockFilter)>),
+
+    /// A list of basic filter hashes, used to respond to
+    /// `CompactFilterHeaders`.
+    CompactFilterHeaders {
+        /// The hash of the last block in the range.
+        stop: block::Hash,
+        /// The filter header of the block before the range.
+        previous: FilterHeader,
+        /// The filter hashes of each block in the range.
+        hashes: Vec<FilterHash>,
+    },
+
+    /// A list of basic filter headers at every 1000th block, used to respond
+    /// to `CompactFilterCheckpoints`.
+    CompactFilterCheckpoints {
+        /// The hash of the last block considered.
+        stop: block::Hash,
+        /// The filter headers.
+        headers: Vec<FilterHeader>,
+    },
 }
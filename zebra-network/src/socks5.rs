@@ -0,0 +1,245 @@
+//! A minimal SOCKS5 client, as defined in [RFC 1928], for connecting to
+//! peers through a proxy such as Tor.
+//!
+//! Only unauthenticated `CONNECT` is supported, along with Tor's `RESOLVE`
+//! extension, which lets us look up DNS seeders without leaking the query to
+//! the local resolver.
+//!
+//! [RFC 1928]: https://tools.ietf.org/html/rfc1928
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+const VERSION: u8 = 0x05;
+const NO_AUTHENTICATION: u8 = 0x00;
+const CMD_CONNECT: u8 = 0x01;
+/// Tor's extension for resolving a hostname through the proxy.
+const CMD_RESOLVE: u8 = 0xF0;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+const REPLY_SUCCEEDED: u8 = 0x00;
+
+/// The destination of a SOCKS5 request.
+enum Target<'a> {
+    Addr(SocketAddr),
+    Domain(&'a str, u16),
+}
+
+/// Open a TCP connection to `target` through the SOCKS5 proxy at `proxy`.
+pub(crate) async fn connect(proxy: SocketAddr, target: SocketAddr) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(proxy).await?;
+    request(&mut stream, CMD_CONNECT, Target::Addr(target)).await?;
+    Ok(stream)
+}
+
+/// Resolve `host` to an IP address using the SOCKS5 proxy at `proxy`.
+///
+/// This uses Tor's `RESOLVE` extension, so it only works with Tor proxies.
+pub(crate) async fn resolve(proxy: SocketAddr, host: &str) -> io::Result<IpAddr> {
+    let mut stream = TcpStream::connect(proxy).await?;
+    let bound = request(&mut stream, CMD_RESOLVE, Target::Domain(host, 0)).await?;
+    Ok(bound.ip())
+}
+
+/// Negotiate authentication, then send a request with `command` and
+/// `target`, returning the bound address from the proxy's reply.
+async fn request(
+    stream: &mut TcpStream,
+    command: u8,
+    target: Target<'_>,
+) -> io::Result<SocketAddr> {
+    stream.write_all(&[VERSION, 1, NO_AUTHENTICATION]).await?;
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    if choice != [VERSION, NO_AUTHENTICATION] {
+        return Err(protocol_error("proxy requires authentication"));
+    }
+
+    let mut msg = vec![VERSION, command, 0x00];
+    let port = match target {
+        Target::Addr(SocketAddr::V4(addr)) => {
+            msg.push(ATYP_IPV4);
+            msg.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        Target::Addr(SocketAddr::V6(addr)) => {
+            msg.push(ATYP_IPV6);
+            msg.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        Target::Domain(host, port) => {
+            if host.len() > 255 {
+                return Err(protocol_error("hostname is too long"));
+            }
+            msg.push(ATYP_DOMAIN);
+            msg.push(host.len() as u8);
+            msg.extend_from_slice(host.as_bytes());
+            port
+        }
+    };
+    msg.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&msg).await?;
+
+    let mut head = [0u8; 4];
+    stream.read_exact(&mut head).await?;
+    if head[0] != VERSION {
+        return Err(protocol_error("proxy replied with an unknown version"));
+    }
+    if head[1] != REPLY_SUCCEEDED {
+        return Err(protocol_error(reply_message(head[1])));
+    }
+
+    let ip = match head[3] {
+        ATYP_IPV4 => {
+            let mut octets = [0u8; 4];
+            stream.read_exact(&mut octets).await?;
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        ATYP_IPV6 => {
+            let mut octets = [0u8; 16];
+            stream.read_exact(&mut octets).await?;
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        ATYP_DOMAIN => {
+            // We only use the bound address for RESOLVE, which always
+            // replies with an IP address, so skip bound hostnames.
+            let len = stream.read_u8().await?;
+            let mut domain = vec![0u8; len as usize];
+            stream.read_exact(&mut domain).await?;
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+        }
+        _ => return Err(protocol_error("proxy replied with an unknown address type")),
+    };
+    let port = stream.read_u16().await?;
+
+    Ok(SocketAddr::new(ip, port))
+}
+
+fn reply_message(reply: u8) -> &'static str {
+    match reply {
+        0x01 => "general SOCKS server failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown SOCKS failure",
+    }
+}
+
+fn protocol_error(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{net::TcpListener, runtime::Runtime};
+
+    use super::*;
+
+    /// Run a fake proxy that expects `expected_request`, then replies with
+    /// `reply`.
+    async fn fake_proxy(expected_request: Vec<u8>, reply: Vec<u8>) -> SocketAddr {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [VERSION, 1, NO_AUTHENTICATION]);
+            stream
+                .write_all(&[VERSION, NO_AUTHENTICATION])
+                .await
+                .unwrap();
+
+            let mut request = vec![0u8; expected_request.len()];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, expected_request);
+            stream.write_all(&reply).await.unwrap();
+        });
+        addr
+    }
+
+    #[test]
+    fn connect_sends_target_address() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let target: SocketAddr = "203.0.113.6:8233".parse().unwrap();
+            let proxy = fake_proxy(
+                vec![
+                    VERSION,
+                    CMD_CONNECT,
+                    0,
+                    ATYP_IPV4,
+                    203,
+                    0,
+                    113,
+                    6,
+                    0x20,
+                    0x29,
+                ],
+                vec![VERSION, REPLY_SUCCEEDED, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0],
+            )
+            .await;
+
+            connect(proxy, target).await.expect("proxy accepts CONNECT");
+        });
+    }
+
+    #[test]
+    fn resolve_returns_bound_address() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let mut request = vec![VERSION, CMD_RESOLVE, 0, ATYP_DOMAIN, 7];
+            request.extend_from_slice(b"example");
+            request.extend_from_slice(&[0, 0]);
+            let proxy = fake_proxy(
+                request,
+                vec![VERSION, REPLY_SUCCEEDED, 0, ATYP_IPV4, 192, 0, 2, 1, 0, 0],
+            )
+            .await;
+
+            let ip = resolve(proxy, "example").await.expect("proxy resolves");
+            assert_eq!(ip, IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        });
+    }
+
+    #[test]
+    fn failure_replies_are_errors() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let target: SocketAddr = "203.0.113.6:8233".parse().unwrap();
+            let proxy = fake_proxy(
+                vec![
+                    VERSION,
+                    CMD_CONNECT,
+                    0,
+                    ATYP_IPV4,
+                    203,
+                    0,
+                    113,
+                    6,
+                    0x20,
+                    0x29,
+                ],
+                vec![VERSION, 0x05, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0],
+            )
+            .await;
+
+            connect(proxy, target)
+                .await
+                .expect_err("proxy refused the connection");
+        });
+    }
+}
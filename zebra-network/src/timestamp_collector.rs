@@ -1,20 +1,40 @@
-//! The timestamp collector collects liveness information from peers.
+//! The timestamp collector collects liveness and latency information from
+//! peers.
 
-use std::sync::{Arc, Mutex};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use futures::{channel::mpsc, prelude::*};
 
 use crate::{types::MetaAddr, AddressBook};
 
+/// An update to a peer's [`AddressBook`] entry.
+#[derive(Copy, Clone, Debug)]
+pub enum AddressBookUpdate {
+    /// We received a message from the peer.
+    Seen(MetaAddr),
+    /// The peer answered a heartbeat `Ping`.
+    Rtt {
+        /// The peer's address.
+        addr: SocketAddr,
+        /// The time between sending the `Ping` and receiving the `Pong`.
+        rtt: Duration,
+    },
+}
+
 /// The timestamp collector hooks into incoming message streams for each peer and
-/// records per-connection last-seen timestamps into an [`AddressBook`].
+/// records per-connection last-seen timestamps and round-trip times into an
+/// [`AddressBook`].
 pub struct TimestampCollector {}
 
 impl TimestampCollector {
     /// Spawn a new [`TimestampCollector`] task, and return handles for the
     /// transmission channel for timestamp events and for the [`AddressBook`] it
     /// updates.
-    pub fn spawn() -> (Arc<Mutex<AddressBook>>, mpsc::Sender<MetaAddr>) {
+    pub fn spawn() -> (Arc<Mutex<AddressBook>>, mpsc::Sender<AddressBookUpdate>) {
         use tracing::Level;
         const TIMESTAMP_WORKER_BUFFER_SIZE: usize = 100;
         let (worker_tx, mut worker_rx) = mpsc::channel(TIMESTAMP_WORKER_BUFFER_SIZE);
@@ -26,10 +46,13 @@ impl TimestampCollector {
 
         let worker = async move {
             while let Some(event) = worker_rx.next().await {
-                worker_address_book
+                let mut address_book = worker_address_book
                     .lock()
-                    .expect("mutex should be unpoisoned")
-                    .update(event);
+                    .expect("mutex should be unpoisoned");
+                match event {
+                    AddressBookUpdate::Seen(meta) => address_book.update(meta),
+                    AddressBookUpdate::Rtt { addr, rtt } => address_book.record_rtt(addr, rtt),
+                }
             }
         };
         tokio::spawn(worker.boxed());
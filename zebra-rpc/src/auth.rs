@@ -0,0 +1,109 @@
+//! HTTP basic authentication for the RPC server.
+//!
+//! Like `zcashd`, clients can log in with the configured user and password,
+//! or with the password in the cookie file. If neither is configured, every
+//! request is accepted.
+
+use std::{
+    error::Error as StdError,
+    fmt,
+    fs::{self, OpenOptions},
+    io::Write,
+};
+
+use rand::Rng;
+
+use crate::Config;
+
+/// A boxed configuration or cookie file error.
+type BoxError = Box<dyn StdError + Send + Sync + 'static>;
+
+/// The user name for the password in the cookie file.
+pub const COOKIE_USER: &str = "__cookie__";
+
+/// The credentials that the RPC server accepts.
+#[derive(Clone, Default)]
+pub struct Auth {
+    /// The accepted `user:password` strings.
+    credentials: Vec<String>,
+}
+
+impl Auth {
+    /// Returns the credentials in `config`.
+    ///
+    /// If `config` has a cookie file, writes a new random password to it.
+    pub fn from_config(config: &Config) -> Result<Self, BoxError> {
+        let mut credentials = Vec::new();
+
+        match (&config.user, &config.password) {
+            (Some(user), Some(password)) => credentials.push(format!("{}:{}", user, password)),
+            (None, None) => {}
+            _ => return Err("the RPC user and password must be set together".into()),
+        }
+
+        if let Some(path) = &config.cookie_file {
+            let password = hex::encode(rand::thread_rng().gen::<[u8; 32]>());
+            let cookie = format!("{}:{}", COOKIE_USER, password);
+
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let mut options = OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+            options.open(path)?.write_all(cookie.as_bytes())?;
+
+            credentials.push(cookie);
+        }
+
+        Ok(Auth { credentials })
+    }
+
+    /// Returns true if requests must have credentials.
+    pub fn is_enabled(&self) -> bool {
+        !self.credentials.is_empty()
+    }
+
+    /// Returns true if `authorization`, the value of a request's
+    /// `Authorization` header, has credentials that this server accepts.
+    pub fn check(&self, authorization: Option<&[u8]>) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+
+        const PREFIX: &[u8] = b"Basic ";
+        let encoded = match authorization {
+            Some(value) if value.starts_with(PREFIX) => &value[PREFIX.len()..],
+            _ => return false,
+        };
+        let decoded = match base64::decode(encoded) {
+            Ok(decoded) => decoded,
+            Err(_) => return false,
+        };
+
+        // Check every credential, so the response time doesn't show which
+        // one was closest.
+        self.credentials.iter().fold(false, |found, expected| {
+            found | constant_time_eq(expected.as_bytes(), &decoded)
+        })
+    }
+}
+
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Don't log the passwords.
+        f.debug_struct("Auth")
+            .field("credentials", &self.credentials.len())
+            .finish()
+    }
+}
+
+/// Returns true if `a` and `b` are equal, in a time that only depends on
+/// their lengths.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,270 @@
+//! A state service for tools that query a running node, using its RPC
+//! server.
+//!
+//! sled takes an exclusive lock on the state database, so other processes
+//! can't open it while a node is running, even read-only. [`RemoteState`]
+//! answers the read requests that tools like block explorers need, by calling
+//! the node's RPC methods instead. Unlike a read-only state, it also sees the
+//! node's non-finalized blocks.
+
+use std::{
+    convert::TryFrom,
+    error::Error as StdError,
+    fs,
+    future::Future,
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::FutureExt;
+use hyper::{client::HttpConnector, header, Body, Client, Uri};
+use serde_json::{json, Value};
+use tower::Service;
+
+use zebra_chain::{
+    amount::Amount,
+    block::{self, Block},
+    serialization::ZcashDeserialize,
+    transaction::{self, OutPoint, Transaction, TransparentOutput},
+    transparent,
+};
+use zebra_state::{HashOrHeight, Request, Response};
+
+use crate::methods::{Error, INVALID_ADDRESS_OR_KEY};
+
+/// A boxed error from the RPC server, or the connection to it.
+type BoxError = Box<dyn StdError + Send + Sync + 'static>;
+
+/// A state service that answers read requests by calling a node's RPC
+/// server.
+///
+/// It supports `Tip`, `Block`, `Transaction`, and the address index
+/// requests. Other requests return an error. The node's best chain can
+/// change between the RPC calls for a request, so clients should check the
+/// tip if they need a consistent view.
+#[derive(Clone, Debug)]
+pub struct RemoteState {
+    client: Client<HttpConnector>,
+    uri: Uri,
+    /// The `Authorization` header for each request, if the server needs one.
+    authorization: Option<Arc<String>>,
+}
+
+impl RemoteState {
+    /// Returns a state service for the RPC server at `uri`, which logs in
+    /// with `credentials`, a `(user, password)` pair, if there are any.
+    pub fn new(uri: Uri, credentials: Option<(&str, &str)>) -> Self {
+        let authorization = credentials.map(|(user, password)| {
+            let credentials = format!("{}:{}", user, password);
+            Arc::new(format!("Basic {}", base64::encode(credentials)))
+        });
+
+        RemoteState {
+            client: Client::new(),
+            uri,
+            authorization,
+        }
+    }
+
+    /// Returns a state service for the RPC server at `uri`, which logs in
+    /// with the credentials in the node's cookie file at `path`.
+    ///
+    /// The node writes a new cookie each time it starts, so the service must
+    /// be created again after a restart.
+    pub fn with_cookie_file(uri: Uri, path: &Path) -> Result<Self, BoxError> {
+        let cookie = fs::read_to_string(path)?;
+        let mut parts = cookie.trim().splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(user), Some(password)) => Ok(RemoteState::new(uri, Some((user, password)))),
+            _ => Err(format!("the cookie file at {:?} has no password", path).into()),
+        }
+    }
+
+    /// Calls the RPC `method` with `params`, and returns its result.
+    async fn call_method(self, method: &'static str, params: Value) -> Result<Value, BoxError> {
+        let body = json!({
+            "jsonrpc": "1.0",
+            "id": method,
+            "method": method,
+            "params": params,
+        });
+        let mut request = hyper::Request::post(self.uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))?;
+        if let Some(authorization) = &self.authorization {
+            request
+                .headers_mut()
+                .insert(header::AUTHORIZATION, authorization.parse()?);
+        }
+
+        let response = self.client.request(request).await?;
+        if response.status() == hyper::StatusCode::UNAUTHORIZED {
+            return Err("the RPC server refused the credentials".into());
+        }
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        let mut response: Value = serde_json::from_slice(&body)?;
+
+        if !response["error"].is_null() {
+            let error: Error = serde_json::from_value(response["error"].take())?;
+            return Err(error.into());
+        }
+        Ok(response["result"].take())
+    }
+
+    /// Answers `request`, using the RPC methods with the same data.
+    async fn answer(self, request: Request) -> Result<Response, BoxError> {
+        match request {
+            Request::Tip => {
+                let info = self.call_method("getblockchaininfo", json!([])).await?;
+                let tip = match info["bestblockhash"].as_str() {
+                    Some(hash) => Some((block::Height(u32_field(&info, "blocks")?), hash.parse()?)),
+                    None => None,
+                };
+                Ok(Response::BestTip { tip })
+            }
+            Request::Block { hash_or_height } => {
+                let hash_or_height = match hash_or_height {
+                    HashOrHeight::Hash(hash) => hash.to_string(),
+                    HashOrHeight::Height(height) => height.0.to_string(),
+                };
+                let block = self
+                    .call_method("getblock", json!([hash_or_height, 0]))
+                    .await?;
+                let block = Block::zcash_deserialize(&hex_field(&block)?[..])?;
+                Ok(Response::Block {
+                    block: Arc::new(block),
+                })
+            }
+            Request::Transaction { hash } => {
+                let transaction = self
+                    .call_method("getrawtransaction", json!([hash.to_string(), 1]))
+                    .await;
+                Ok(Response::Transaction {
+                    transaction: mined_transaction(transaction)?,
+                })
+            }
+            Request::AddressBalance { address } => {
+                let balance = self
+                    .call_method("getaddressbalance", json!([address.to_string()]))
+                    .await?;
+                Ok(Response::AddressBalance {
+                    balance: Amount::try_from(i64_field(&balance, "balance")?)?,
+                })
+            }
+            Request::AddressUtxos { address } => {
+                let utxos = self
+                    .call_method("getaddressutxos", json!([address.to_string()]))
+                    .await?;
+                Ok(Response::AddressUtxos {
+                    utxos: address_utxos(&utxos)?,
+                })
+            }
+            Request::AddressTxIds { address } => {
+                let hashes = self
+                    .clone()
+                    .call_method("getaddresstxids", json!([address.to_string()]))
+                    .await?;
+                let hashes: Vec<String> = serde_json::from_value(hashes)?;
+
+                // The method only returns the hashes, so each transaction is
+                // looked up for its height.
+                let mut txids = Vec::new();
+                for hash in hashes {
+                    let hash: transaction::Hash = hash.parse()?;
+                    let transaction = self
+                        .clone()
+                        .call_method("getrawtransaction", json!([hash.to_string(), 1]))
+                        .await;
+                    if let Some((_, height)) = mined_transaction(transaction)? {
+                        txids.push((height, hash));
+                    }
+                }
+                Ok(Response::AddressTxIds { txids })
+            }
+            _ => Err("this state request isn't available over RPC".into()),
+        }
+    }
+}
+
+impl Service<Request> for RemoteState {
+    type Response = Response;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, BoxError>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        self.clone().answer(request).boxed()
+    }
+}
+
+/// Returns the mined transaction in a verbose `getrawtransaction` result,
+/// with its height.
+///
+/// Returns `None` if the transaction is unknown, or only in the mempool.
+fn mined_transaction(
+    result: Result<Value, BoxError>,
+) -> Result<Option<(Arc<Transaction>, block::Height)>, BoxError> {
+    let info = match result {
+        Ok(info) => info,
+        Err(error) => {
+            let unknown = error
+                .downcast_ref::<Error>()
+                .map_or(false, |error| error.code == INVALID_ADDRESS_OR_KEY);
+            return if unknown { Ok(None) } else { Err(error) };
+        }
+    };
+    if info["height"].is_null() {
+        return Ok(None);
+    }
+
+    let transaction = Transaction::zcash_deserialize(&hex_field(&info["hex"])?[..])?;
+    let height = block::Height(u32_field(&info, "height")?);
+    Ok(Some((Arc::new(transaction), height)))
+}
+
+/// Returns the outputs in a `getaddressutxos` result.
+fn address_utxos(utxos: &Value) -> Result<Vec<(OutPoint, TransparentOutput)>, BoxError> {
+    utxos
+        .as_array()
+        .ok_or("the address utxos aren't a list")?
+        .iter()
+        .map(|utxo| {
+            let hash = utxo["txid"]
+                .as_str()
+                .ok_or("the utxo has no transaction ID")?
+                .parse()?;
+            let outpoint = OutPoint {
+                hash,
+                index: u32_field(utxo, "outputIndex")?,
+            };
+            let output = TransparentOutput {
+                value: Amount::try_from(i64_field(utxo, "satoshis")?)?,
+                pk_script: transparent::Script(hex_field(&utxo["script"])?.into()),
+            };
+            Ok((outpoint, output))
+        })
+        .collect()
+}
+
+/// Returns the bytes in a hex string `value`.
+fn hex_field(value: &Value) -> Result<Vec<u8>, BoxError> {
+    let hex = value.as_str().ok_or("the result isn't a hex string")?;
+    Ok(hex::decode(hex)?)
+}
+
+/// Returns the integer field `name` in `value`.
+fn i64_field(value: &Value, name: &str) -> Result<i64, BoxError> {
+    value[name]
+        .as_i64()
+        .ok_or_else(|| format!("the result has no {} field", name).into())
+}
+
+/// Returns the height or index field `name` in `value`.
+fn u32_field(value: &Value, name: &str) -> Result<u32, BoxError> {
+    Ok(u32::try_from(i64_field(value, name)?)?)
+}
@@ -0,0 +1,49 @@
+//! Configuration for the JSON-RPC server.
+
+use std::{net::SocketAddr, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Configuration for the JSON-RPC server.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Config {
+    /// The address that the RPC server listens on, or `None` to disable the
+    /// server.
+    ///
+    /// Anyone who can connect to the server can submit blocks and
+    /// transactions, so it should listen on a local address, like
+    /// `127.0.0.1:8232`. Other addresses are refused, unless
+    /// `allow_external_access` is set, and there is a password or a cookie
+    /// file.
+    pub listen_addr: Option<SocketAddr>,
+
+    /// Allow the RPC server to listen on an address that isn't a loopback
+    /// address.
+    ///
+    /// Only set this if the server is behind a firewall. The server also
+    /// needs a password or a cookie file.
+    pub allow_external_access: bool,
+
+    /// The user name that RPC clients must send, with `password`.
+    pub user: Option<String>,
+
+    /// The password that RPC clients must send, with `user`.
+    pub password: Option<String>,
+
+    /// The file that the RPC server writes a random password to, when it
+    /// starts.
+    ///
+    /// Local clients, like lightwalletd, can read the file, then log in with
+    /// the user `__cookie__` and that password. The password changes every
+    /// time the server starts.
+    pub cookie_file: Option<PathBuf>,
+
+    /// The transparent address that `getblocktemplate` pays the miner's
+    /// reward to, or `None` to disable `getblocktemplate`.
+    pub miner_address: Option<String>,
+
+    /// Publish new blocks, chain reorganizations, and new mempool
+    /// transactions, which clients can stream from `/events`.
+    pub events: bool,
+}
@@ -0,0 +1,434 @@
+//! Events for new blocks, chain reorganizations, and mempool transactions.
+//!
+//! [`watch`] waits for the state's best tip to change, and for the mempool
+//! to accept transactions, and publishes an [`Event`] for each change.
+//! In-process subscribers can use the broadcast channel directly, and
+//! external clients can stream the events from the RPC server, see
+//! [`server`](crate::server).
+
+use std::{error::Error as StdError, time::Duration};
+
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::{json, Value};
+use tokio::sync::broadcast;
+use tower::{Service, ServiceExt};
+use tracing::{debug, warn};
+
+use zebra_chain::{block, transaction};
+
+/// A boxed error from the state service.
+type BoxError = Box<dyn StdError + Send + Sync + 'static>;
+
+/// How many events can wait for a slow subscriber before it misses some.
+pub const EVENT_CHANNEL_SIZE: usize = 1000;
+
+/// How long [`watch`] waits before its first retry after a state error.
+///
+/// The wait doubles after each failure, up to [`MAX_RETRY_DELAY`].
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// The longest that [`watch`] waits before retrying after a state error.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// How many of the latest best chain blocks are remembered, to find where a
+/// reorganization forked from.
+///
+/// Non-finalized blocks can only be rolled back this far.
+const RECENT_BLOCKS: usize = 100;
+
+/// A change to the best chain or the mempool.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// A block was added to the best chain.
+    Block {
+        /// The block's hash.
+        hash: block::Hash,
+        /// The block's height.
+        height: block::Height,
+    },
+    /// The best chain switched to a fork.
+    ///
+    /// The blocks above `fork_height` are no longer in the best chain. The
+    /// fork's blocks follow as [`Event::Block`]s.
+    Reorg {
+        /// The tip before the reorganization.
+        old_tip: block::Hash,
+        /// The height of the last block that both chains contain.
+        fork_height: block::Height,
+    },
+    /// A transaction was accepted into the mempool.
+    Transaction {
+        /// The transaction's hash.
+        hash: transaction::Hash,
+    },
+}
+
+impl Event {
+    /// Returns the event as a JSON object, with its kind in its `type`
+    /// field.
+    pub fn to_json(&self) -> Value {
+        match self {
+            Event::Block { hash, height } => json!({
+                "type": "block",
+                "hash": hash.to_string(),
+                "height": height.0,
+            }),
+            Event::Reorg {
+                old_tip,
+                fork_height,
+            } => json!({
+                "type": "reorg",
+                "oldtip": old_tip.to_string(),
+                "forkheight": fork_height.0,
+            }),
+            Event::Transaction { hash } => json!({
+                "type": "transaction",
+                "txid": hash.to_string(),
+            }),
+        }
+    }
+}
+
+/// Returns a sender for [`Event`]s, which [`watch`] publishes to.
+pub fn channel() -> broadcast::Sender<Event> {
+    broadcast::channel(EVENT_CHANNEL_SIZE).0
+}
+
+/// Waits for changes to the best tip of `state`, and for the hashes of new
+/// mempool transactions from `transactions`, and sends an event for each
+/// change to `events`.
+///
+/// Changes that happened before the watch started aren't sent. If the tip
+/// changes more than once while the watcher is reading the state, the
+/// intermediate tips are skipped, but their blocks are still sent if they
+/// end up in the best chain.
+///
+/// State errors are retried, waiting longer after each failure. Only
+/// finishes if the mempool is dropped.
+pub async fn watch<S>(
+    state: S,
+    mut transactions: broadcast::Receiver<transaction::Hash>,
+    events: broadcast::Sender<Event>,
+) -> Result<(), BoxError>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+{
+    let mut chain = RecentChain::default();
+    let mut retry_delay = MIN_RETRY_DELAY;
+    let mut tip_change = next_tip(state.clone(), None, Duration::from_secs(0));
+
+    loop {
+        let tip = tokio::select! {
+            tip = &mut tip_change => tip,
+            hash = transactions.recv() => {
+                match hash {
+                    Ok(hash) => {
+                        // Sending fails if there aren't any subscribers.
+                        let _ = events.send(Event::Transaction { hash });
+                    }
+                    Err(broadcast::RecvError::Lagged(skipped)) => {
+                        debug!(?skipped, "event watcher missed some mempool transactions");
+                    }
+                    Err(broadcast::RecvError::Closed) => {
+                        return Err("the mempool was dropped".into())
+                    }
+                }
+                continue;
+            }
+        };
+
+        let result = match tip {
+            Ok(Some((height, hash))) => chain.update(state.clone(), height, hash).await,
+            Ok(None) => Ok(Vec::new()),
+            Err(error) => Err(error),
+        };
+        let delay = match result {
+            Ok(changes) => {
+                for event in changes {
+                    let _ = events.send(event);
+                }
+                retry_delay = MIN_RETRY_DELAY;
+                Duration::from_secs(0)
+            }
+            Err(error) => {
+                warn!(
+                    ?error,
+                    ?retry_delay,
+                    "event watcher could not read the state"
+                );
+                let delay = retry_delay;
+                retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+                delay
+            }
+        };
+        tip_change = next_tip(state.clone(), chain.tip_hash(), delay);
+    }
+}
+
+/// Returns the best tip of `state`, once its hash is different from `known`,
+/// after waiting for `delay`.
+fn next_tip<S>(
+    state: S,
+    known: Option<block::Hash>,
+    delay: Duration,
+) -> BoxFuture<'static, Result<Option<(block::Height, block::Hash)>, BoxError>>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = BoxError>
+        + Send
+        + 'static,
+    S::Future: Send,
+{
+    async move {
+        if delay > Duration::from_secs(0) {
+            tokio::time::delay_for(delay).await;
+        }
+        match state
+            .oneshot(zebra_state::Request::AwaitTipChange { tip: known })
+            .await?
+        {
+            zebra_state::Response::BestTip { tip } => Ok(tip),
+            response => Err(format!("unexpected state response: {:?}", response).into()),
+        }
+    }
+    .boxed()
+}
+
+/// The latest best chain blocks that [`watch`] has seen, lowest first.
+#[derive(Debug, Default)]
+struct RecentChain {
+    blocks: Vec<(block::Height, block::Hash)>,
+}
+
+impl RecentChain {
+    /// Returns the hash of the latest block that the watcher has seen.
+    fn tip_hash(&self) -> Option<block::Hash> {
+        self.blocks.last().map(|&(_, hash)| hash)
+    }
+
+    /// Updates the chain to the best chain with tip `hash` at `height`, and
+    /// returns the events for the change.
+    async fn update<S>(
+        &mut self,
+        state: S,
+        height: block::Height,
+        hash: block::Hash,
+    ) -> Result<Vec<Event>, BoxError>
+    where
+        S: Service<zebra_state::Request, Response = zebra_state::Response, Error = BoxError>
+            + Clone,
+    {
+        let (old_height, old_tip) = match self.blocks.last() {
+            Some(&tip) => tip,
+            None => {
+                self.blocks.push((height, hash));
+                return Ok(Vec::new());
+            }
+        };
+        if old_tip == hash {
+            return Ok(Vec::new());
+        }
+
+        // Find the highest remembered block that is still in the best chain.
+        while let Some(&(recent_height, recent_hash)) = self.blocks.last() {
+            if recent_height <= height
+                && best_chain_hash(state.clone(), recent_height).await? == Some(recent_hash)
+            {
+                break;
+            }
+            self.blocks.pop();
+        }
+
+        let mut events = Vec::new();
+        let (fork_height, is_reorg) = match self.blocks.last() {
+            Some(&(fork_height, _)) => (fork_height, fork_height < old_height),
+            // The fork is too deep to find, so it is reported below the new
+            // tip, without the blocks in between.
+            None => (block::Height(height.0.saturating_sub(1)), true),
+        };
+        if is_reorg {
+            events.push(Event::Reorg {
+                old_tip,
+                fork_height,
+            });
+        }
+
+        for new_height in (fork_height.0 + 1)..=height.0 {
+            let new_height = block::Height(new_height);
+            // The tip can change while we're reading, so stop at any gaps.
+            let new_hash = match best_chain_hash(state.clone(), new_height).await? {
+                Some(new_hash) => new_hash,
+                None => break,
+            };
+            events.push(Event::Block {
+                hash: new_hash,
+                height: new_height,
+            });
+            self.blocks.push((new_height, new_hash));
+        }
+
+        if self.blocks.len() > RECENT_BLOCKS {
+            self.blocks.drain(..self.blocks.len() - RECENT_BLOCKS);
+        }
+        Ok(events)
+    }
+}
+
+/// Returns the hash of the best chain block at `height` in `state`.
+async fn best_chain_hash<S>(
+    state: S,
+    height: block::Height,
+) -> Result<Option<block::Hash>, BoxError>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = BoxError>,
+{
+    match state
+        .oneshot(zebra_state::Request::BestChainBlockHash { height })
+        .await?
+    {
+        zebra_state::Response::BlockHash { hash } => Ok(hash),
+        response => Err(format!("unexpected state response: {:?}", response).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use zebra_chain::{block::Block, serialization::ZcashDeserialize, Network};
+
+    #[tokio::test]
+    async fn new_blocks_are_published() {
+        let state = zebra_state::in_memory::init(Network::Mainnet);
+        let mut chain = RecentChain::default();
+
+        let blocks = [
+            &zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..],
+            &zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..],
+            &zebra_test_vectors::BLOCK_MAINNET_2_BYTES[..],
+        ];
+        let mut hashes = Vec::new();
+        for bytes in blocks.iter() {
+            let block: Arc<Block> = Block::zcash_deserialize(*bytes).unwrap().into();
+            hashes.push(block.hash());
+            state
+                .clone()
+                .oneshot(zebra_state::Request::AddBlock { block })
+                .await
+                .unwrap();
+
+            // The first check only records the tip.
+            if hashes.len() == 1 {
+                let events = chain
+                    .update(state.clone(), block::Height(0), hashes[0])
+                    .await
+                    .unwrap();
+                assert!(events.is_empty());
+            }
+        }
+
+        let events = chain
+            .update(state.clone(), block::Height(2), hashes[2])
+            .await
+            .unwrap();
+        assert_eq!(
+            events,
+            vec![
+                Event::Block {
+                    hash: hashes[1],
+                    height: block::Height(1),
+                },
+                Event::Block {
+                    hash: hashes[2],
+                    height: block::Height(2),
+                },
+            ]
+        );
+        assert_eq!(events[1].to_json()["type"], json!("block"));
+
+        let events = chain
+            .update(state, block::Height(2), hashes[2])
+            .await
+            .unwrap();
+        assert!(events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn reorgs_are_published() {
+        let genesis = block::Hash([0; 32]);
+        let old_chain = vec![genesis, block::Hash([1; 32]), block::Hash([2; 32])];
+        let new_chain = vec![
+            genesis,
+            block::Hash([3; 32]),
+            block::Hash([4; 32]),
+            block::Hash([5; 32]),
+        ];
+
+        // A state that only answers best chain lookups, so the test can
+        // switch between the competing chains.
+        let best_chain = Arc::new(Mutex::new(old_chain.clone()));
+        let state = {
+            let best_chain = best_chain.clone();
+            tower::service_fn(move |request| {
+                let chain = best_chain.lock().unwrap().clone();
+                async move {
+                    match request {
+                        zebra_state::Request::BestChainBlockHash { height } => {
+                            Ok(zebra_state::Response::BlockHash {
+                                hash: chain.get(height.0 as usize).cloned(),
+                            })
+                        }
+                        request => Err::<_, BoxError>(
+                            format!("unexpected state request: {:?}", request).into(),
+                        ),
+                    }
+                }
+            })
+        };
+
+        let mut chain = RecentChain::default();
+        chain
+            .update(state.clone(), block::Height(0), genesis)
+            .await
+            .unwrap();
+        let events = chain
+            .update(state.clone(), block::Height(2), old_chain[2])
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 2);
+
+        *best_chain.lock().unwrap() = new_chain.clone();
+        let events = chain
+            .update(state, block::Height(3), new_chain[3])
+            .await
+            .unwrap();
+        assert_eq!(
+            events,
+            vec![
+                Event::Reorg {
+                    old_tip: old_chain[2],
+                    fork_height: block::Height(0),
+                },
+                Event::Block {
+                    hash: new_chain[1],
+                    height: block::Height(1),
+                },
+                Event::Block {
+                    hash: new_chain[2],
+                    height: block::Height(2),
+                },
+                Event::Block {
+                    hash: new_chain[3],
+                    height: block::Height(3),
+                },
+            ]
+        );
+        assert_eq!(chain.tip_hash(), Some(new_chain[3]));
+        assert_eq!(events[0].to_json()["forkheight"], json!(0));
+    }
+}
@@ -1,10 +1,574 @@
+//! A JSON-RPC server for Zebra, which is compatible with `zcashd`'s RPC
+//! interface.
+//!
+//! [`methods::Rpc`] answers RPC requests using the state, mempool, and
+//! block submission services, and [`server::bind`] accepts them over HTTP.
+//! [`events::watch`] publishes new blocks, reorganizations, and mempool
+//! transactions, which clients can stream from the server.
+//!
+//! [`client::RemoteState`] lets other processes query a running node's
+//! state through the server.
+
 #![doc(html_logo_url = "https://www.zfnd.org/images/zebra-icon.png")]
 #![doc(html_root_url = "https://doc.zebra.zfnd.org/zebra_rpc")]
+#![deny(missing_docs)]
+
+mod config;
+
+pub mod auth;
+pub mod client;
+pub mod events;
+pub mod mempool;
+pub mod methods;
+pub mod server;
+pub mod submit;
+
+pub use config::Config;
 
 #[cfg(test)]
 mod tests {
+    use super::{
+        auth::Auth,
+        client, mempool,
+        methods::{
+            self, Rpc, DESERIALIZATION_ERROR, INVALID_ADDRESS_OR_KEY, INVALID_IP_OR_SUBNET,
+            INVALID_PARAMS, METHOD_NOT_FOUND, MISC_ERROR, NODE_NOT_CONNECTED,
+            TRANSACTION_ALREADY_IN_CHAIN, TRANSACTION_REJECTED,
+        },
+        server, submit, Config,
+    };
+    use futures::future::{self, Ready};
+    use serde_json::{json, Value};
+    use std::{
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+    };
+    use tower::{Service, ServiceExt};
+    use zebra_chain::{
+        block::{self, Block},
+        parameters::subsidy,
+        serialization::{ZcashDeserialize, ZcashSerialize},
+        transaction::{self, Transaction},
+        transparent,
+        work::difficulty::ExpandedDifficulty,
+        Network,
+    };
+    use zebra_network::{ConnectedPeers, PeerControl};
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+    /// A mempool that is always empty, and rejects every transaction.
+    #[derive(Clone, Debug)]
+    struct TestMempool;
+
+    impl Service<mempool::Request> for TestMempool {
+        type Response = mempool::Response;
+        type Error = BoxError;
+        type Future = Ready<Result<mempool::Response, BoxError>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: mempool::Request) -> Self::Future {
+            future::ok(match request {
+                mempool::Request::TransactionIds => mempool::Response::TransactionIds(vec![]),
+                mempool::Request::TransactionsByHash(_) => mempool::Response::Transactions(vec![]),
+                mempool::Request::Send(_) => mempool::Response::Rejected(
+                    mempool::Rejection::Invalid("rejected by the test mempool".to_string()),
+                ),
+                mempool::Request::BlockCandidates => mempool::Response::BlockCandidates(vec![]),
+            })
+        }
+    }
+
+    /// A block verifier that rejects every block.
+    #[derive(Clone, Debug)]
+    struct TestBlocks;
+
+    impl Service<submit::Request> for TestBlocks {
+        type Response = submit::Response;
+        type Error = BoxError;
+        type Future = Ready<Result<submit::Response, BoxError>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _request: submit::Request) -> Self::Future {
+            future::ok(submit::Response::Rejected(
+                "rejected by the test verifier".to_string(),
+            ))
+        }
+    }
+
+    /// Returns an RPC handler for `state`.
+    fn rpc<S>(state: S) -> Rpc<S, TestMempool, TestBlocks> {
+        rpc_with_miner(state, None)
+    }
+
+    /// Like `rpc`, but block templates pay `miner_address`.
+    fn rpc_with_miner<S>(
+        state: S,
+        miner_address: Option<transparent::Address>,
+    ) -> Rpc<S, TestMempool, TestBlocks> {
+        Rpc::new(
+            state,
+            TestMempool,
+            TestBlocks,
+            Network::Mainnet,
+            PeerControl::new(Arc::new(Mutex::new(ConnectedPeers::new()))),
+            "v1.0.0".to_string(),
+            "/Zebra:1.0.0/".to_string(),
+            miner_address,
+        )
+    }
+
+    #[tokio::test]
+    async fn getinfo_reports_an_empty_state() {
+        let info = rpc(zebra_state::in_memory::init(Network::Mainnet))
+            .call("getinfo", Value::Null)
+            .await
+            .unwrap();
+        assert_eq!(info["blocks"], json!(0));
+        assert_eq!(info["connections"], json!(0));
+        assert_eq!(info["testnet"], json!(false));
+        assert_eq!(info["build"], json!("v1.0.0"));
+    }
+
+    #[tokio::test]
+    async fn getblockchaininfo_lists_network_upgrades() {
+        let info = rpc(zebra_state::in_memory::init(Network::Mainnet))
+            .call("getblockchaininfo", json!([]))
+            .await
+            .unwrap();
+        assert_eq!(info["chain"], json!("main"));
+        assert_eq!(info["bestblockhash"], Value::Null);
+        assert_eq!(info["estimatedheight"], json!(0));
+        // Sapling's branch ID
+        assert_eq!(
+            info["upgrades"]["76b809bb"]["activationheight"],
+            json!(419_200)
+        );
+        assert_eq!(info["upgrades"]["76b809bb"]["status"], json!("pending"));
+    }
+
+    #[tokio::test]
+    async fn getblock_returns_state_blocks() {
+        let block: Arc<Block> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])
+                .unwrap()
+                .into();
+        let hash = block.hash().to_string();
+
+        let state = zebra_state::in_memory::init(Network::Mainnet);
+        state
+            .clone()
+            .oneshot(zebra_state::Request::AddBlock { block })
+            .await
+            .unwrap();
+        let rpc = rpc(state);
+
+        let best = rpc.call("getbestblockhash", Value::Null).await.unwrap();
+        assert_eq!(best, json!(hash));
+
+        let info = rpc.call("getblock", json!(["0"])).await.unwrap();
+        assert_eq!(info["hash"], json!(hash));
+        assert_eq!(info["confirmations"], json!(1));
+        assert_eq!(info["nextblockhash"], Value::Null);
+        assert_eq!(info["tx"].as_array().map(Vec::len), Some(1));
+
+        let raw = rpc.call("getblock", json!([hash, 0])).await.unwrap();
+        assert_eq!(
+            raw,
+            json!(hex::encode(
+                &zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..]
+            ))
+        );
+
+        let error = rpc.call("getblock", json!(["1"])).await.unwrap_err();
+        assert_eq!(error.code, INVALID_ADDRESS_OR_KEY);
+
+        let verbose = rpc.call("getblock", json!([0, 2])).await.unwrap();
+        assert_eq!(verbose["trees"]["sapling"]["size"], json!(0));
+        assert_eq!(verbose["tx"][0]["txid"], info["tx"][0]);
+    }
+
+    #[tokio::test]
+    async fn lightwalletd_methods_use_the_state() {
+        let block: Arc<Block> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])
+                .unwrap()
+                .into();
+        let hash = block.hash().to_string();
+
+        let state = zebra_state::in_memory::init(Network::Mainnet);
+        state
+            .clone()
+            .oneshot(zebra_state::Request::AddBlock { block })
+            .await
+            .unwrap();
+        let rpc = rpc(state);
+
+        let tree = rpc.call("z_gettreestate", json!(["0"])).await.unwrap();
+        assert_eq!(tree["hash"], json!(hash));
+        assert_eq!(
+            tree["sapling"]["commitments"]["finalState"],
+            json!("000000")
+        );
+        assert_eq!(
+            tree["orchard"]["commitments"]["finalState"],
+            json!("000000")
+        );
+
+        let block = rpc.call("getblock", json!(["0", 1])).await.unwrap();
+        assert_eq!(block["trees"]["orchard"]["size"], json!(0));
+
+        let mempool = rpc.call("getrawmempool", json!([])).await.unwrap();
+        assert_eq!(mempool, json!([]));
+        let mempool = rpc.call("getrawmempool", json!([true])).await.unwrap();
+        assert_eq!(mempool, json!({}));
+
+        let error = rpc
+            .call(
+                "getaddressbalance",
+                json!([{ "addresses": ["not an address"] }]),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, INVALID_ADDRESS_OR_KEY);
+    }
+
+    #[tokio::test]
+    async fn raw_transactions_are_found_and_checked() {
+        let block: Arc<Block> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])
+                .unwrap()
+                .into();
+        let coinbase = block.transactions[0].clone();
+        let txid = transaction::Hash::from(coinbase.as_ref()).to_string();
+        let mut raw = Vec::new();
+        coinbase.zcash_serialize(&mut raw).unwrap();
+
+        let state = zebra_state::in_memory::init(Network::Mainnet);
+        state
+            .clone()
+            .oneshot(zebra_state::Request::AddBlock { block })
+            .await
+            .unwrap();
+        let rpc = rpc(state);
+
+        let found = rpc.call("getrawtransaction", json!([txid])).await.unwrap();
+        assert_eq!(found, json!(hex::encode(&raw)));
+        let info = rpc
+            .call("getrawtransaction", json!([txid, 1]))
+            .await
+            .unwrap();
+        assert_eq!(info["height"], json!(0));
+        assert_eq!(info["confirmations"], json!(1));
+
+        let error = rpc
+            .call("sendrawtransaction", json!([hex::encode(&raw)]))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, TRANSACTION_ALREADY_IN_CHAIN);
+
+        let error = rpc
+            .call("sendrawtransaction", json!(["00"]))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, DESERIALIZATION_ERROR);
+
+        let missing = format!("{:064x}", 1);
+        let error = rpc
+            .call("getrawtransaction", json!([missing]))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, INVALID_ADDRESS_OR_KEY);
+    }
+
+    #[tokio::test]
+    async fn mempool_rejections_are_reported() {
+        let block: Arc<Block> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])
+                .unwrap()
+                .into();
+        let mut raw = Vec::new();
+        block.transactions[0].zcash_serialize(&mut raw).unwrap();
+
+        let error = rpc(zebra_state::in_memory::init(Network::Mainnet))
+            .call("sendrawtransaction", json!([hex::encode(&raw)]))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, TRANSACTION_REJECTED);
+        assert_eq!(error.message, "rejected by the test mempool");
+    }
+
+    #[tokio::test]
+    async fn getblocktemplate_pays_the_subsidy() {
+        let block: Arc<Block> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])
+                .unwrap()
+                .into();
+        let hash = block.hash().to_string();
+
+        let state = zebra_state::in_memory::init(Network::Mainnet);
+        state
+            .clone()
+            .oneshot(zebra_state::Request::AddBlock { block })
+            .await
+            .unwrap();
+
+        let error = rpc(state.clone())
+            .call("getblocktemplate", Value::Null)
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, METHOD_NOT_FOUND);
+
+        let founders = subsidy::founders_reward_address(block::Height(1), Network::Mainnet);
+        let template = rpc_with_miner(state, founders)
+            .call("getblocktemplate", json!([{ "mode": "template" }]))
+            .await
+            .unwrap();
+        assert_eq!(template["height"], json!(1));
+        assert_eq!(template["previousblockhash"], json!(hash));
+        assert_eq!(template["transactions"], json!([]));
+        // The chain is too short for the difficulty adjustment.
+        let limit = ExpandedDifficulty::target_difficulty_limit(Network::Mainnet).to_compact();
+        assert_eq!(template["bits"], json!(format!("{:08x}", limit.0)));
+        // Before Heartwood, the commitment is the Sapling root after the
+        // block, and there is no chain history root.
+        let mut empty_root = zebra_chain::sapling::tree::NoteCommitmentTree::default()
+            .root()
+            .0;
+        empty_root.reverse();
+        assert_eq!(
+            template["finalsaplingroothash"],
+            json!(hex::encode(empty_root))
+        );
+        assert_eq!(
+            template["blockcommitmentshash"],
+            template["finalsaplingroothash"]
+        );
+        assert_eq!(template["lightclientroothash"], Value::Null);
+
+        let coinbase = hex::decode(template["coinbasetxn"]["data"].as_str().unwrap()).unwrap();
+        let coinbase = Transaction::zcash_deserialize(&coinbase[..]).unwrap();
+        let values: Vec<i64> = coinbase
+            .outputs()
+            .map(|output| i64::from(output.value))
+            .collect();
+        // The slow start subsidy at height 1 is 62,500 zatoshis, and the
+        // founders' reward is a fifth of it.
+        assert_eq!(values, vec![50_000, 12_500]);
+    }
+
+    #[tokio::test]
+    async fn submitblock_reports_rejections() {
+        let rpc = rpc(zebra_state::in_memory::init(Network::Mainnet));
+
+        let block = hex::encode(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..]);
+        let result = rpc.call("submitblock", json!([block])).await.unwrap();
+        assert_eq!(result, json!("rejected by the test verifier"));
+
+        let error = rpc.call("submitblock", json!(["00"])).await.unwrap_err();
+        assert_eq!(error.code, DESERIALIZATION_ERROR);
+    }
+
+    #[tokio::test]
+    async fn bad_requests_are_rejected() {
+        let error = rpc(zebra_state::in_memory::init(Network::Mainnet))
+            .call("getnothing", Value::Null)
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, METHOD_NOT_FOUND);
+
+        let error = rpc(zebra_state::in_memory::init(Network::Mainnet))
+            .call("getinfo", json!([1]))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, INVALID_PARAMS);
+    }
+
+    #[test]
+    fn auth_checks_basic_credentials() {
+        let open = Auth::from_config(&Config::default()).unwrap();
+        assert!(open.check(None));
+
+        let auth = Auth::from_config(&Config {
+            user: Some("user".to_string()),
+            password: Some("password".to_string()),
+            ..Config::default()
+        })
+        .unwrap();
+        let header = format!("Basic {}", base64::encode("user:password"));
+        assert!(auth.check(Some(header.as_bytes())));
+        let wrong = format!("Basic {}", base64::encode("user:wrong"));
+        assert!(!auth.check(Some(wrong.as_bytes())));
+        assert!(!auth.check(None));
+
+        assert!(Auth::from_config(&Config {
+            user: Some("user".to_string()),
+            ..Config::default()
+        })
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn peers_can_be_banned_and_disconnected() {
+        let rpc = rpc(zebra_state::in_memory::init(Network::Mainnet));
+
+        let peers = rpc.call("getpeerinfo", Value::Null).await.unwrap();
+        assert_eq!(peers, json!([]));
+        let error = rpc
+            .call("disconnectnode", json!(["192.0.2.1:8233"]))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, NODE_NOT_CONNECTED);
+        // Test handles have no crawler to add peers to.
+        let error = rpc
+            .call("addnode", json!(["192.0.2.1", "onetry"]))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, MISC_ERROR);
+
+        rpc.call("setban", json!(["192.0.2.0/24", "add", 3600]))
+            .await
+            .unwrap();
+        let bans = rpc.call("listbanned", Value::Null).await.unwrap();
+        assert_eq!(bans[0]["address"], json!("192.0.2.0/24"));
+        let error = rpc
+            .call("addnode", json!(["192.0.2.1", "add"]))
+            .await
+            .unwrap_err();
+        assert!(error.message.contains("banned"));
+
+        rpc.call("setban", json!(["192.0.2.0/24", "remove"]))
+            .await
+            .unwrap();
+        let error = rpc
+            .call("setban", json!(["192.0.2.0/24", "remove"]))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, INVALID_IP_OR_SUBNET);
+        let error = rpc
+            .call("setban", json!(["not an address", "add"]))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, INVALID_IP_OR_SUBNET);
+
+        rpc.call("setban", json!(["198.51.100.7", "add"]))
+            .await
+            .unwrap();
+        rpc.call("clearbanned", Value::Null).await.unwrap();
+        assert_eq!(rpc.call("listbanned", json!([])).await.unwrap(), json!([]));
+    }
+
+    #[tokio::test]
+    async fn remote_state_queries_a_running_node() {
+        let block: Arc<Block> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])
+                .unwrap()
+                .into();
+        let state = zebra_state::in_memory::init(Network::Mainnet);
+        state
+            .clone()
+            .oneshot(zebra_state::Request::AddBlock {
+                block: block.clone(),
+            })
+            .await
+            .unwrap();
+
+        // Find a free port for the server.
+        let listen_addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let config = Config {
+            listen_addr: Some(listen_addr),
+            ..Config::default()
+        };
+        let (stop, stopped) = futures::channel::oneshot::channel::<()>();
+        let shutdown = async move {
+            let _ = stopped.await;
+        };
+        let server = tokio::spawn(server::bind(&config, rpc(state), shutdown).unwrap());
+
+        let remote =
+            client::RemoteState::new(format!("http://{}", listen_addr).parse().unwrap(), None);
+        match remote
+            .clone()
+            .oneshot(zebra_state::Request::Tip)
+            .await
+            .unwrap()
+        {
+            zebra_state::Response::BestTip { tip } => {
+                assert_eq!(tip, Some((block::Height(0), block.hash())))
+            }
+            response => panic!("unexpected response: {:?}", response),
+        }
+        match remote
+            .clone()
+            .oneshot(zebra_state::Request::Block {
+                hash_or_height: block::Height(0).into(),
+            })
+            .await
+            .unwrap()
+        {
+            zebra_state::Response::Block {
+                block: remote_block,
+            } => {
+                assert_eq!(remote_block, block)
+            }
+            response => panic!("unexpected response: {:?}", response),
+        }
+
+        let error = remote
+            .oneshot(zebra_state::Request::Block {
+                hash_or_height: block::Height(1).into(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error
+                .downcast_ref::<methods::Error>()
+                .map(|error| error.code),
+            Some(INVALID_ADDRESS_OR_KEY)
+        );
+
+        // The server finishes after it is shut down.
+        let _ = stop.send(());
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn external_addresses_need_opt_in() {
+        let config = Config {
+            listen_addr: Some("0.0.0.0:0".parse().unwrap()),
+            ..Config::default()
+        };
+        assert!(server::bind(
+            &config,
+            rpc(zebra_state::in_memory::init(Network::Mainnet)),
+            futures::future::pending(),
+        )
+        .is_err());
+
+        // Opting in isn't enough without credentials.
+        let config = Config {
+            allow_external_access: true,
+            ..config
+        };
+        assert!(server::bind(
+            &config,
+            rpc(zebra_state::in_memory::init(Network::Mainnet)),
+            futures::future::pending(),
+        )
+        .is_err());
+    }
 }
@@ -0,0 +1,68 @@
+//! The mempool requests that the RPC methods make.
+//!
+//! The mempool is part of `zebrad`, so the node provides a service that
+//! answers these requests using its mempool and peer set.
+
+use std::{collections::HashSet, sync::Arc};
+
+use zebra_chain::{
+    amount::{Amount, NonNegative},
+    transaction::{self, Transaction},
+};
+
+/// A mempool request from an RPC method.
+#[derive(Clone, Debug)]
+pub enum Request {
+    /// Get the hashes of every transaction in the mempool.
+    TransactionIds,
+    /// Get the mempool transactions with these hashes.
+    TransactionsByHash(HashSet<transaction::Hash>),
+    /// Verify a transaction, add it to the mempool, and advertise it to
+    /// peers.
+    ///
+    /// Fails if the transaction couldn't be checked.
+    Send(Arc<Transaction>),
+    /// Get every mempool transaction, with its fee, to choose the
+    /// transactions in a block template.
+    BlockCandidates,
+}
+
+/// A mempool response to an RPC method.
+#[derive(Clone, Debug)]
+pub enum Response {
+    /// The hashes of every transaction in the mempool.
+    TransactionIds(Vec<transaction::Hash>),
+    /// The requested mempool transactions.
+    Transactions(Vec<Arc<Transaction>>),
+    /// The transaction with this hash was added to the mempool, and
+    /// advertised.
+    Sent(transaction::Hash),
+    /// The transaction wasn't added to the mempool.
+    Rejected(Rejection),
+    /// Every mempool transaction, in any order.
+    BlockCandidates(Vec<Candidate>),
+}
+
+/// A mempool transaction that a miner could add to a block.
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    /// The transaction.
+    pub transaction: Arc<Transaction>,
+    /// The transaction's fee, which the miner can claim in the coinbase.
+    pub fee: Amount<NonNegative>,
+}
+
+/// Why the mempool didn't accept a transaction.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Rejection {
+    /// The transaction is already in the mempool.
+    AlreadyInMempool,
+    /// The transaction spends outputs that aren't in the best chain or the
+    /// mempool.
+    MissingInputs,
+    /// The transaction breaks a rule that depends on the current chain, so
+    /// it might be valid in another chain.
+    InvalidInChain(String),
+    /// The transaction is invalid, or doesn't meet the mempool's policy.
+    Invalid(String),
+}
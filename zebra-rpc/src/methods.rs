@@ -0,0 +1,1466 @@
+//! The RPC methods, which match the `zcashd` methods with the same names.
+//!
+//! Each method takes its JSON parameters, and returns a JSON result, in the
+//! same format as `zcashd`. Fields that Zebra can't provide yet, like wallet
+//! balances, are left out, rather than filled with placeholder values.
+
+mod block_template;
+
+use std::{
+    cmp::max,
+    collections::HashSet,
+    error::Error as StdError,
+    fmt, iter,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::{TimeZone, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tokio::sync::broadcast;
+use tower::{Service, ServiceExt};
+use tracing::info;
+
+use zebra_chain::{
+    amount::{self, Amount, NonNegative},
+    block::{
+        self, merkle, AuthDataRoot, Block, ChainHistoryBlockTxAuthCommitmentHash,
+        ChainHistoryMmrRootHash, Header, MAX_BLOCK_BYTES,
+    },
+    history_tree::HistoryTree,
+    network_upgrade::NetworkUpgrade,
+    orchard,
+    sapling::tree::NoteCommitmentTree,
+    serialization::{ZcashDeserialize, ZcashSerialize},
+    transaction::{self, Transaction, TransparentInput},
+    transparent, Network,
+};
+use zebra_consensus::block::{
+    check::MAX_BLOCK_SIGOPS,
+    difficulty::{
+        median_time, AdjustedDifficulty, POW_ADJUSTMENT_BLOCK_SPAN, POW_MEDIAN_BLOCK_SPAN,
+    },
+};
+use zebra_network::{Direction, IpNetwork, PeerControl};
+
+use crate::{
+    events::Event,
+    mempool::{self, Candidate, Rejection},
+    submit,
+};
+
+/// A boxed error from the state service.
+type BoxError = Box<dyn StdError + Send + Sync + 'static>;
+
+/// The JSON-RPC error code for a request that isn't valid JSON.
+pub const PARSE_ERROR: i64 = -32700;
+
+/// The JSON-RPC error code for a request that isn't a JSON-RPC request.
+pub const INVALID_REQUEST: i64 = -32600;
+
+/// The JSON-RPC error code for an unknown method.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+
+/// The JSON-RPC error code for invalid method parameters.
+pub const INVALID_PARAMS: i64 = -32602;
+
+/// The JSON-RPC error code for a failed request.
+pub const INTERNAL_ERROR: i64 = -32603;
+
+/// The `zcashd` error code for an unknown block, transaction, or address.
+pub const INVALID_ADDRESS_OR_KEY: i64 = -5;
+
+/// The `zcashd` error code for data that can't be deserialized.
+pub const DESERIALIZATION_ERROR: i64 = -22;
+
+/// The `zcashd` error code for a transaction with missing inputs, or that is
+/// otherwise invalid in the current chain.
+pub const TRANSACTION_ERROR: i64 = -25;
+
+/// The `zcashd` error code for a transaction that isn't accepted into the
+/// mempool.
+pub const TRANSACTION_REJECTED: i64 = -26;
+
+/// The `zcashd` error code for a transaction that is already mined.
+pub const TRANSACTION_ALREADY_IN_CHAIN: i64 = -27;
+
+/// The `zcashd` error code for a failed request that has no specific code.
+pub const MISC_ERROR: i64 = -1;
+
+/// The `zcashd` error code for a peer that isn't connected.
+pub const NODE_NOT_CONNECTED: i64 = -29;
+
+/// The `zcashd` error code for an invalid IP address or subnet.
+pub const INVALID_IP_OR_SUBNET: i64 = -30;
+
+/// How long `setban` bans a subnet for, if the request doesn't say.
+const DEFAULT_BAN_TIME: i64 = 24 * 60 * 60;
+
+/// How long a `getblocktemplate` long poll waits for the tip or the mempool
+/// to change, before returning the current template.
+pub const LONGPOLL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often a long poll checks the tip and the mempool for changes.
+const LONGPOLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The optional first parameter of `getblocktemplate`.
+///
+/// Other fields, like the client's `capabilities`, are ignored.
+#[derive(Clone, Debug, Default, Deserialize)]
+struct TemplateRequest {
+    /// `template`, the default, or `proposal`, which isn't supported yet.
+    mode: Option<String>,
+    /// The long poll ID of the client's current template.
+    longpollid: Option<String>,
+}
+
+/// An RPC error, which is sent to the client in the response's `error`
+/// field.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Error {
+    /// The JSON-RPC error code.
+    pub code: i64,
+    /// A description of the error.
+    pub message: String,
+}
+
+impl Error {
+    /// Returns an error with `code` and `message`.
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Returns an error for a failed state or network request.
+    fn internal(error: BoxError) -> Self {
+        Self::new(INTERNAL_ERROR, error.to_string())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RPC error {}: {}", self.code, self.message)
+    }
+}
+
+impl StdError for Error {}
+
+/// Answers RPC requests using the state service `S`, the mempool service
+/// `M`, the block submission service `B`, and the peer set's
+/// [`PeerControl`].
+#[derive(Clone, Debug)]
+pub struct Rpc<S, M, B> {
+    state: S,
+    mempool: M,
+    blocks: B,
+    network: Network,
+    peers: PeerControl,
+    /// The node's version, like `v1.0.0`.
+    build: String,
+    /// The node's user agent, which it sends to peers.
+    user_agent: String,
+    /// The address that block templates pay the miner's reward to.
+    miner_address: Option<transparent::Address>,
+    /// The chain and mempool events that clients can stream, if they are
+    /// enabled.
+    events: Option<broadcast::Sender<Event>>,
+}
+
+impl<S, M, B> Rpc<S, M, B> {
+    /// Returns an RPC handler for a node on `network`, with version `build`.
+    ///
+    /// `getblocktemplate` is only available if there is a `miner_address`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        state: S,
+        mempool: M,
+        blocks: B,
+        network: Network,
+        peers: PeerControl,
+        build: String,
+        user_agent: String,
+        miner_address: Option<transparent::Address>,
+    ) -> Self {
+        Self {
+            state,
+            mempool,
+            blocks,
+            network,
+            peers,
+            build,
+            user_agent,
+            miner_address,
+            events: None,
+        }
+    }
+
+    /// Lets clients stream the events sent to `events`.
+    pub fn with_events(mut self, events: broadcast::Sender<Event>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Returns a subscription to the chain and mempool events, or `None` if
+    /// they aren't enabled.
+    pub fn subscribe(&self) -> Option<broadcast::Receiver<Event>> {
+        self.events.as_ref().map(broadcast::Sender::subscribe)
+    }
+}
+
+impl<S, M, B> Rpc<S, M, B>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+    M: Service<mempool::Request, Response = mempool::Response, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    M::Future: Send,
+    B: Service<submit::Request, Response = submit::Response, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    B::Future: Send,
+{
+    /// Calls the RPC method named `method`, with `params`.
+    pub async fn call(&self, method: &str, params: Value) -> Result<Value, Error> {
+        match method {
+            "getinfo" => self.get_info(params).await,
+            "getblockchaininfo" => self.get_blockchain_info(params).await,
+            "getbestblockhash" => self.get_best_block_hash(params).await,
+            "getblock" => self.get_block(params).await,
+            "getrawtransaction" => self.get_raw_transaction(params).await,
+            "sendrawtransaction" => self.send_raw_transaction(params).await,
+            "getblocktemplate" => self.get_block_template(params).await,
+            "submitblock" => self.submit_block(params).await,
+            "z_gettreestate" => self.z_get_tree_state(params).await,
+            "getrawmempool" => self.get_raw_mempool(params).await,
+            "getaddressbalance" => self.get_address_balance(params).await,
+            "getaddresstxids" => self.get_address_tx_ids(params).await,
+            "getaddressutxos" => self.get_address_utxos(params).await,
+            "getpeerinfo" => self.get_peer_info(params),
+            "addnode" => self.add_node(params),
+            "disconnectnode" => self.disconnect_node(params),
+            "setban" => self.set_ban(params),
+            "listbanned" => self.list_banned(params),
+            "clearbanned" => self.clear_banned(params),
+            _ => Err(Error::new(
+                METHOD_NOT_FOUND,
+                format!("method {:?} not found", method),
+            )),
+        }
+    }
+
+    /// Returns general information about the node, like `zcashd`'s
+    /// `getinfo`.
+    async fn get_info(&self, params: Value) -> Result<Value, Error> {
+        no_params(&params)?;
+        let tip = self.tip().await?;
+
+        Ok(json!({
+            "build": self.build,
+            "subversion": self.user_agent,
+            "blocks": tip.map(|(height, _)| height.0).unwrap_or(0),
+            "connections": self.peers.connected_peers().lock().unwrap().len(),
+            "testnet": self.network != Network::Mainnet,
+            "errors": "",
+        }))
+    }
+
+    /// Returns information about the best chain, like `zcashd`'s
+    /// `getblockchaininfo`.
+    ///
+    /// The chain is `main`, `test`, or `regtest`, and each network upgrade is
+    /// listed by its consensus branch ID, like `zcashd`.
+    async fn get_blockchain_info(&self, params: Value) -> Result<Value, Error> {
+        no_params(&params)?;
+        let tip = self.tip().await?;
+        let height = tip.map(|(height, _)| height).unwrap_or(block::Height(0));
+        let next_height = block::Height(height.0 + 1);
+
+        let mut upgrades = Map::new();
+        for (activation_height, upgrade) in NetworkUpgrade::activation_list(self.network) {
+            if let Some(branch_id) = upgrade.branch_id() {
+                let status = if tip.is_some() && activation_height <= height {
+                    "active"
+                } else {
+                    "pending"
+                };
+                upgrades.insert(
+                    branch_id.to_string(),
+                    json!({
+                        "name": format!("{:?}", upgrade),
+                        "activationheight": activation_height.0,
+                        "status": status,
+                    }),
+                );
+            }
+        }
+
+        // Like `zcashd`, the estimate is never below our own tip.
+        let estimated_height = self
+            .peers
+            .connected_peers()
+            .lock()
+            .unwrap()
+            .estimated_tip_height(self.network, Utc::now())
+            .map_or(height, |estimate| max(estimate, height));
+        let progress = if estimated_height.0 == 0 {
+            0.0
+        } else {
+            f64::from(height.0) / f64::from(estimated_height.0)
+        };
+
+        let branch_id = |height| {
+            NetworkUpgrade::current(self.network, height)
+                .branch_id()
+                .map(|branch_id| branch_id.to_string())
+                .unwrap_or_else(|| "00000000".to_string())
+        };
+
+        Ok(json!({
+            "chain": match self.network {
+                Network::Mainnet => "main",
+                Network::Testnet => "test",
+                Network::Regtest => "regtest",
+            },
+            "blocks": height.0,
+            "estimatedheight": estimated_height.0,
+            "verificationprogress": progress,
+            "bestblockhash": tip.map(|(_, hash)| hash.to_string()),
+            "upgrades": upgrades,
+            "consensus": {
+                "chaintip": branch_id(height),
+                "nextblock": branch_id(next_height),
+            },
+        }))
+    }
+
+    /// Returns the hash of the best chain tip, like `zcashd`'s
+    /// `getbestblockhash`.
+    async fn get_best_block_hash(&self, params: Value) -> Result<Value, Error> {
+        no_params(&params)?;
+        match self.tip().await? {
+            Some((_, hash)) => Ok(json!(hash.to_string())),
+            None => Err(Error::new(INTERNAL_ERROR, "the state is empty")),
+        }
+    }
+
+    /// Returns the best chain block with the hash or height in the first
+    /// parameter, like `zcashd`'s `getblock`.
+    ///
+    /// With verbosity 0, returns the serialized block in hex. With verbosity
+    /// 1, the default, returns the block header fields, transaction IDs, the
+    /// size of the note commitment trees, and its position in the best
+    /// chain. Verbosity 2 is like 1, but each transaction also has its hex
+    /// and size.
+    ///
+    /// Like `zcashd`, the height can be a string, which is what lightwalletd
+    /// sends, or a number.
+    async fn get_block(&self, params: Value) -> Result<Value, Error> {
+        let verbosity: u8 = param(&params, 1)?.unwrap_or(1);
+        if verbosity > 2 {
+            return Err(Error::new(INVALID_PARAMS, "verbosity must be 0, 1, or 2"));
+        }
+        let (tip_height, height, hash) = self.find_block(&params).await?;
+
+        let block = match self
+            .state
+            .clone()
+            .oneshot(zebra_state::Request::Block {
+                hash_or_height: hash.into(),
+            })
+            .await
+            .map_err(Error::internal)?
+        {
+            zebra_state::Response::Block { block } => block,
+            response => return Err(unexpected(response)),
+        };
+
+        if verbosity == 0 {
+            let mut bytes = Vec::new();
+            block
+                .zcash_serialize(&mut bytes)
+                .expect("serializing to a vector doesn't fail");
+            return Ok(json!(hex::encode(bytes)));
+        }
+
+        let next_hash = self.best_chain_hash(block::Height(height.0 + 1)).await?;
+        let sapling_tree = self.sapling_tree(hash).await?;
+        let orchard_tree = self.orchard_tree(hash).await?;
+        let header = &block.header;
+        let tx: Vec<_> = block
+            .transactions
+            .iter()
+            .map(|transaction| {
+                let txid = transaction::Hash::from(transaction.as_ref()).to_string();
+                if verbosity == 1 {
+                    return json!(txid);
+                }
+                let mut bytes = Vec::new();
+                transaction
+                    .zcash_serialize(&mut bytes)
+                    .expect("serializing to a vector doesn't fail");
+                json!({
+                    "hex": hex::encode(&bytes),
+                    "txid": txid,
+                    "size": bytes.len(),
+                })
+            })
+            .collect();
+
+        Ok(json!({
+            "hash": hash.to_string(),
+            "confirmations": tip_height.0 - height.0 + 1,
+            "size": block.serialized_size(),
+            "height": height.0,
+            "version": header.version,
+            "merkleroot": header.merkle_root.to_string(),
+            "finalsaplingroot": reversed_hex(&header.final_sapling_root_hash.0),
+            "tx": tx,
+            "time": header.time.timestamp(),
+            "nonce": reversed_hex(&header.nonce),
+            "bits": format!("{:08x}", header.bits.0),
+            "previousblockhash": header.previous_block_hash.to_string(),
+            "nextblockhash": next_hash.map(|hash| hash.to_string()),
+            "trees": {
+                "sapling": {
+                    "size": sapling_tree.count(),
+                },
+                "orchard": {
+                    "size": orchard_tree.count(),
+                },
+            },
+        }))
+    }
+
+    /// Returns the Sapling and Orchard note commitment trees after the best
+    /// chain block with the hash or height in the first parameter, like
+    /// `zcashd`'s `z_gettreestate`.
+    ///
+    /// lightwalletd sends the serialized trees to wallets, so they can start
+    /// scanning from that block. Before NU5, the Orchard tree is empty.
+    async fn z_get_tree_state(&self, params: Value) -> Result<Value, Error> {
+        let (_, height, hash) = self.find_block(&params).await?;
+
+        let header = match self
+            .state
+            .clone()
+            .oneshot(zebra_state::Request::BlockHeader {
+                hash_or_height: hash.into(),
+            })
+            .await
+            .map_err(Error::internal)?
+        {
+            zebra_state::Response::BlockHeader { header } => header,
+            response => return Err(unexpected(response)),
+        };
+        let sapling_tree = self.sapling_tree(hash).await?;
+        let orchard_tree = self.orchard_tree(hash).await?;
+
+        Ok(json!({
+            "hash": hash.to_string(),
+            "height": height.0,
+            "time": header.time.timestamp(),
+            "sapling": {
+                "commitments": {
+                    "finalRoot": reversed_hex(&sapling_tree.root().0),
+                    "finalState": serialized_hex(&sapling_tree),
+                },
+            },
+            "orchard": {
+                "commitments": {
+                    "finalRoot": reversed_hex(&orchard_tree.root().0),
+                    "finalState": serialized_hex(&orchard_tree),
+                },
+            },
+        }))
+    }
+
+    /// Returns the hashes of every mempool transaction, like `zcashd`'s
+    /// `getrawmempool`.
+    ///
+    /// If the first parameter is true, returns an object with each
+    /// transaction's size, fee in ZEC, and the mempool transactions it
+    /// spends. The mempool doesn't record when transactions arrived, so the
+    /// entries don't have `zcashd`'s time and height fields.
+    async fn get_raw_mempool(&self, params: Value) -> Result<Value, Error> {
+        let verbose: bool = param(&params, 0)?.unwrap_or(false);
+        if verbose {
+            let candidates = self.block_candidates().await?;
+            let hashes: HashSet<transaction::Hash> = candidates
+                .iter()
+                .map(|candidate| transaction::Hash::from(candidate.transaction.as_ref()))
+                .collect();
+
+            let mut entries = serde_json::Map::new();
+            for candidate in candidates {
+                let transaction = &candidate.transaction;
+                let depends: HashSet<transaction::Hash> = transaction
+                    .inputs()
+                    .filter_map(|input| match input {
+                        TransparentInput::PrevOut { outpoint, .. } => Some(outpoint.hash),
+                        TransparentInput::Coinbase { .. } => None,
+                    })
+                    .filter(|hash| hashes.contains(hash))
+                    .collect();
+                entries.insert(
+                    transaction::Hash::from(transaction.as_ref()).to_string(),
+                    json!({
+                        "size": transaction.zcash_serialized_size(),
+                        "fee": i64::from(candidate.fee) as f64 / amount::COIN as f64,
+                        "depends": depends.iter().map(|hash| hash.to_string()).collect::<Vec<_>>(),
+                    }),
+                );
+            }
+            return Ok(Value::Object(entries));
+        }
+
+        match self
+            .mempool
+            .clone()
+            .oneshot(mempool::Request::TransactionIds)
+            .await
+            .map_err(Error::internal)?
+        {
+            mempool::Response::TransactionIds(hashes) => Ok(json!(hashes
+                .iter()
+                .map(|hash| hash.to_string())
+                .collect::<Vec<_>>())),
+            response => Err(Error::new(
+                INTERNAL_ERROR,
+                format!("unexpected mempool response: {:?}", response),
+            )),
+        }
+    }
+
+    /// Returns the total unspent value of the addresses in the first
+    /// parameter, like `zcashd`'s `getaddressbalance`.
+    ///
+    /// The total received value isn't indexed, so it is left out.
+    async fn get_address_balance(&self, params: Value) -> Result<Value, Error> {
+        let request = address_request(&params)?;
+
+        let mut balance = Amount::<NonNegative>::zero();
+        for address in request.addresses {
+            match self
+                .address_query(zebra_state::Request::AddressBalance { address })
+                .await?
+            {
+                zebra_state::Response::AddressBalance { balance: value } => {
+                    balance =
+                        (balance + value).map_err(|e| Error::new(INTERNAL_ERROR, e.to_string()))?;
+                }
+                response => return Err(unexpected(response)),
+            }
+        }
+
+        Ok(json!({ "balance": i64::from(balance) }))
+    }
+
+    /// Returns the best chain transactions that spend from or pay to the
+    /// addresses in the first parameter, in chain order, like `zcashd`'s
+    /// `getaddresstxids`.
+    ///
+    /// If the request has `start` and `end` heights, only returns the
+    /// transactions in that range, including both ends.
+    async fn get_address_tx_ids(&self, params: Value) -> Result<Value, Error> {
+        let request = address_request(&params)?;
+        let range = match (request.start, request.end) {
+            (Some(start), Some(end)) if end < start => {
+                return Err(Error::new(
+                    INVALID_PARAMS,
+                    "start height must not be after end height",
+                ))
+            }
+            (Some(start), Some(end)) => Some(block::Height(start)..=block::Height(end)),
+            _ => None,
+        };
+
+        let mut txids = Vec::new();
+        for address in request.addresses {
+            match self
+                .address_query(zebra_state::Request::AddressTxIds { address })
+                .await?
+            {
+                zebra_state::Response::AddressTxIds { txids: found } => txids.extend(found),
+                response => return Err(unexpected(response)),
+            }
+        }
+        if let Some(range) = range {
+            txids.retain(|(height, _)| range.contains(height));
+        }
+
+        // Transactions can involve more than one of the addresses. The sort
+        // is stable, so transactions in the same block stay in chain order.
+        txids.sort_by_key(|(height, _)| *height);
+        let mut seen = HashSet::new();
+        txids.retain(|(_, hash)| seen.insert(*hash));
+
+        Ok(json!(txids
+            .iter()
+            .map(|(_, hash)| hash.to_string())
+            .collect::<Vec<_>>()))
+    }
+
+    /// Returns the best chain unspent outputs that pay to the addresses in
+    /// the first parameter, like `zcashd`'s `getaddressutxos`.
+    ///
+    /// If the request has `chainInfo`, also returns the tip that the outputs
+    /// are unspent at.
+    async fn get_address_utxos(&self, params: Value) -> Result<Value, Error> {
+        let request = address_request(&params)?;
+        // Get the tip first, so the outputs are unspent at or after it.
+        let tip = self.tip().await?;
+
+        let mut utxos = Vec::new();
+        for address in request.addresses {
+            let found = match self
+                .address_query(zebra_state::Request::AddressUtxos { address })
+                .await?
+            {
+                zebra_state::Response::AddressUtxos { utxos } => utxos,
+                response => return Err(unexpected(response)),
+            };
+            for (outpoint, output) in found {
+                let height = self
+                    .chain_transaction(outpoint.hash)
+                    .await?
+                    .map(|(_, height)| height.0);
+                utxos.push((height, address, outpoint, output));
+            }
+        }
+        utxos.sort_by_key(|(height, ..)| *height);
+
+        let utxos: Vec<_> = utxos
+            .into_iter()
+            .map(|(height, address, outpoint, output)| {
+                json!({
+                    "address": address.to_string(),
+                    "txid": outpoint.hash.to_string(),
+                    "outputIndex": outpoint.index,
+                    "script": hex::encode(&output.pk_script.0),
+                    "satoshis": i64::from(output.value),
+                    "height": height,
+                })
+            })
+            .collect();
+
+        if !request.chain_info {
+            return Ok(json!(utxos));
+        }
+        Ok(json!({
+            "utxos": utxos,
+            "hash": tip.map(|(_, hash)| hash.to_string()),
+            "height": tip.map(|(height, _)| height.0),
+        }))
+    }
+
+    /// Returns the mempool or best chain transaction with the hash in the
+    /// first parameter, like `zcashd`'s `getrawtransaction`.
+    ///
+    /// With verbosity 0, the default, returns the serialized transaction in
+    /// hex. With verbosity 1, also returns its hash and size, and the block
+    /// that contains it, if it is mined.
+    async fn get_raw_transaction(&self, params: Value) -> Result<Value, Error> {
+        let hash: String = param(&params, 0)?
+            .ok_or_else(|| Error::new(INVALID_PARAMS, "missing transaction ID"))?;
+        let hash: transaction::Hash = hash
+            .parse()
+            .map_err(|_| Error::new(INVALID_PARAMS, "invalid transaction ID"))?;
+        let verbose: u8 = param(&params, 1)?.unwrap_or(0);
+
+        let mut hashes = HashSet::new();
+        hashes.insert(hash);
+        let mined = match self
+            .mempool
+            .clone()
+            .oneshot(mempool::Request::TransactionsByHash(hashes))
+            .await
+            .map_err(Error::internal)?
+        {
+            mempool::Response::Transactions(transactions) if !transactions.is_empty() => {
+                Some((transactions[0].clone(), None))
+            }
+            mempool::Response::Transactions(_) => None,
+            response => {
+                return Err(Error::new(
+                    INTERNAL_ERROR,
+                    format!("unexpected mempool response: {:?}", response),
+                ))
+            }
+        };
+        let (transaction, height) = match mined {
+            Some(found) => found,
+            None => match self.chain_transaction(hash).await? {
+                Some((transaction, height)) => (transaction, Some(height)),
+                None => {
+                    return Err(Error::new(
+                        INVALID_ADDRESS_OR_KEY,
+                        "No such mempool or blockchain transaction",
+                    ))
+                }
+            },
+        };
+
+        let mut bytes = Vec::new();
+        transaction
+            .zcash_serialize(&mut bytes)
+            .expect("serializing to a vector doesn't fail");
+        if verbose == 0 {
+            return Ok(json!(hex::encode(bytes)));
+        }
+
+        let mut info = json!({
+            "hex": hex::encode(&bytes),
+            "txid": hash.to_string(),
+            "size": bytes.len(),
+        });
+        if let Some(height) = height {
+            let tip_height = self
+                .tip()
+                .await?
+                .map(|(height, _)| height)
+                .unwrap_or(height);
+            info["height"] = json!(height.0);
+            info["confirmations"] = json!(tip_height.0 - height.0 + 1);
+            info["blockhash"] = json!(self
+                .best_chain_hash(height)
+                .await?
+                .map(|hash| hash.to_string()));
+        }
+        Ok(info)
+    }
+
+    /// Verifies the hex-encoded transaction in the first parameter, adds it
+    /// to the mempool, and advertises it to peers, like `zcashd`'s
+    /// `sendrawtransaction`.
+    ///
+    /// Returns the transaction ID, or an error with `zcashd`'s code for the
+    /// reason that the transaction was rejected.
+    async fn send_raw_transaction(&self, params: Value) -> Result<Value, Error> {
+        let raw: String =
+            param(&params, 0)?.ok_or_else(|| Error::new(INVALID_PARAMS, "missing transaction"))?;
+        let transaction = hex::decode(&raw)
+            .ok()
+            .and_then(|bytes| Transaction::zcash_deserialize(&bytes[..]).ok())
+            .ok_or_else(|| Error::new(DESERIALIZATION_ERROR, "TX decode failed"))?;
+        let hash = transaction::Hash::from(&transaction);
+
+        if self.chain_transaction(hash).await?.is_some() {
+            return Err(Error::new(
+                TRANSACTION_ALREADY_IN_CHAIN,
+                "transaction already in block chain",
+            ));
+        }
+
+        match self
+            .mempool
+            .clone()
+            .oneshot(mempool::Request::Send(Arc::new(transaction)))
+            .await
+            .map_err(Error::internal)?
+        {
+            mempool::Response::Sent(hash) => Ok(json!(hash.to_string())),
+            mempool::Response::Rejected(Rejection::AlreadyInMempool) => Err(Error::new(
+                TRANSACTION_REJECTED,
+                "transaction already in the mempool",
+            )),
+            mempool::Response::Rejected(Rejection::MissingInputs) => {
+                Err(Error::new(TRANSACTION_ERROR, "Missing inputs"))
+            }
+            mempool::Response::Rejected(Rejection::InvalidInChain(reason)) => {
+                Err(Error::new(TRANSACTION_ERROR, reason))
+            }
+            mempool::Response::Rejected(Rejection::Invalid(reason)) => {
+                Err(Error::new(TRANSACTION_REJECTED, reason))
+            }
+            response => Err(Error::new(
+                INTERNAL_ERROR,
+                format!("unexpected mempool response: {:?}", response),
+            )),
+        }
+    }
+
+    /// Returns a template for a block on the best chain tip, like `zcashd`'s
+    /// `getblocktemplate`.
+    ///
+    /// If the request has a `longpollid`, waits until the tip or the mempool
+    /// changes, or [`LONGPOLL_TIMEOUT`] passes.
+    ///
+    /// `blockcommitmentshash` is the header's commitment field at the
+    /// template's height. `finalsaplingroothash` and `lightclientroothash`
+    /// are the Sapling root after the block, and the chain history root
+    /// before it, which the commitment is made from. The chain history root
+    /// is `null` before Heartwood activation.
+    async fn get_block_template(&self, params: Value) -> Result<Value, Error> {
+        let request: TemplateRequest = param(&params, 0)?.unwrap_or_default();
+        match request.mode.as_deref() {
+            None | Some("template") => {}
+            Some(mode) => {
+                return Err(Error::new(
+                    INVALID_PARAMS,
+                    format!("unsupported template mode {:?}", mode),
+                ))
+            }
+        }
+        let miner_address = self.miner_address.ok_or_else(|| {
+            Error::new(
+                METHOD_NOT_FOUND,
+                "getblocktemplate needs a miner address in the RPC config",
+            )
+        })?;
+
+        let started = Instant::now();
+        let (tip_height, tip_hash, candidates, longpoll_id) = loop {
+            let (tip_height, tip_hash) = self
+                .tip()
+                .await?
+                .ok_or_else(|| Error::new(INTERNAL_ERROR, "the state is empty"))?;
+            let candidates = self.block_candidates().await?;
+            let id = longpoll_id(tip_hash, &candidates);
+
+            if request.longpollid.as_ref() != Some(&id) || started.elapsed() >= LONGPOLL_TIMEOUT {
+                break (tip_height, tip_hash, candidates, id);
+            }
+            tokio::time::delay_for(LONGPOLL_INTERVAL).await;
+        };
+        let height = block::Height(tip_height.0 + 1);
+
+        let context = self.previous_headers(tip_height).await?;
+        let times: Vec<_> = context
+            .iter()
+            .take(POW_MEDIAN_BLOCK_SPAN)
+            .map(|header| header.time)
+            .collect();
+        let min_time = median_time(&times) + chrono::Duration::seconds(1);
+        // Header times are in whole seconds.
+        let cur_time = max(Utc.timestamp(Utc::now().timestamp(), 0), min_time);
+
+        let bits = AdjustedDifficulty::new_from_time(cur_time, height, self.network, context)
+            .expected_difficulty_threshold();
+        let target = bits
+            .to_expanded()
+            .expect("adjusted difficulty thresholds are valid");
+
+        let selected = block_template::select_transactions(candidates, height, cur_time);
+        let fees: amount::Result<Amount<NonNegative>> = selected.iter().map(|tx| tx.fee).sum();
+        let fees = fees.map_err(|e| Error::new(INTERNAL_ERROR, e.to_string()))?;
+        let coinbase =
+            block_template::coinbase_transaction(self.network, height, &miner_address, fees)?;
+
+        let transactions: Vec<Arc<Transaction>> = iter::once(Arc::new(coinbase.clone()))
+            .chain(selected.iter().map(|tx| tx.transaction.clone()))
+            .collect();
+        let merkle_root = merkle::Root::from_transactions(&transactions);
+
+        let tree = self.sapling_tree(tip_hash).await?;
+        let final_sapling_root = block_template::final_sapling_root(tree, &transactions)?;
+        let auth_data_root: AuthDataRoot = transactions.iter().map(|tx| tx.auth_digest()).collect();
+        let upgrade = NetworkUpgrade::current(self.network, height);
+        // The tree is empty before the Heartwood activation block, so its
+        // root is all zeroes, like the activation block's commitment.
+        let history_root = match upgrade {
+            NetworkUpgrade::Heartwood | NetworkUpgrade::Canopy | NetworkUpgrade::Nu5 => Some(
+                self.history_tree(tip_hash)
+                    .await?
+                    .hash()
+                    .unwrap_or(ChainHistoryMmrRootHash([0; 32])),
+            ),
+            _ => None,
+        };
+        let commitment = match (upgrade, history_root) {
+            (NetworkUpgrade::Nu5, Some(history_root)) => {
+                ChainHistoryBlockTxAuthCommitmentHash::from_commitments(
+                    &history_root,
+                    &auth_data_root,
+                )
+                .0
+            }
+            (_, Some(history_root)) => history_root.0,
+            (_, None) => final_sapling_root.0,
+        };
+
+        let template_transactions: Vec<_> = selected.iter().map(|tx| tx.to_json()).collect();
+
+        Ok(json!({
+            "capabilities": [],
+            // 4 is the only valid block version.
+            "version": 4,
+            "previousblockhash": tip_hash.to_string(),
+            "blockcommitmentshash": reversed_hex(&commitment),
+            "lightclientroothash": history_root.map(|root| reversed_hex(&root.0)),
+            "finalsaplingroothash": reversed_hex(&final_sapling_root.0),
+            "defaultroots": {
+                "merkleroot": merkle_root.to_string(),
+                "chainhistoryroot": history_root.map(|root| reversed_hex(&root.0)),
+                "authdataroot": reversed_hex(&auth_data_root.0),
+                "blockcommitmentshash": reversed_hex(&commitment),
+            },
+            "transactions": template_transactions,
+            "coinbasetxn": block_template::transaction_json(
+                &coinbase,
+                -i64::from(fees),
+                &[],
+                true,
+            ),
+            "longpollid": longpoll_id,
+            "target": hex::encode(target.bytes_in_display_order()),
+            "mintime": min_time.timestamp(),
+            "mutable": ["time", "transactions", "prevblock"],
+            "noncerange": "00000000ffffffff",
+            "sigoplimit": MAX_BLOCK_SIGOPS,
+            "sizelimit": MAX_BLOCK_BYTES,
+            "curtime": cur_time.timestamp(),
+            "bits": format!("{:08x}", bits.0),
+            "height": height.0,
+        }))
+    }
+
+    /// Verifies the hex-encoded block in the first parameter, adds it to the
+    /// state, and advertises it to peers, like `zcashd`'s `submitblock`.
+    ///
+    /// Returns `null` if the block is the new best tip. Otherwise, returns
+    /// `duplicate`, `inconclusive`, or the reason the block was rejected,
+    /// like BIP 22.
+    async fn submit_block(&self, params: Value) -> Result<Value, Error> {
+        let raw: String =
+            param(&params, 0)?.ok_or_else(|| Error::new(INVALID_PARAMS, "missing block"))?;
+        let block = hex::decode(&raw)
+            .ok()
+            .and_then(|bytes| Block::zcash_deserialize(&bytes[..]).ok())
+            .ok_or_else(|| Error::new(DESERIALIZATION_ERROR, "Block decode failed"))?;
+
+        match self
+            .blocks
+            .clone()
+            .oneshot(submit::Request::Submit(Arc::new(block)))
+            .await
+            .map_err(Error::internal)?
+        {
+            submit::Response::Accepted => Ok(Value::Null),
+            submit::Response::Duplicate => Ok(json!("duplicate")),
+            submit::Response::Inconclusive => Ok(json!("inconclusive")),
+            submit::Response::Rejected(reason) => {
+                info!(?reason, "submitted block was rejected");
+                Ok(json!(reason))
+            }
+        }
+    }
+
+    /// Returns each connected peer, like `zcashd`'s `getpeerinfo`.
+    ///
+    /// Times are in seconds since the epoch, and pings are in seconds.
+    /// Zebra doesn't count the bytes it sends or receives, or number its
+    /// peers, so those fields are left out.
+    fn get_peer_info(&self, params: Value) -> Result<Value, Error> {
+        no_params(&params)?;
+        let connected_peers = self.peers.connected_peers().lock().unwrap();
+
+        let peers: Vec<Value> = connected_peers
+            .peers()
+            .map(|peer| {
+                let ping = peer.min_ping.map(|ping| ping.as_secs_f64());
+                json!({
+                    "addr": peer.addr.to_string(),
+                    "services": format!("{:016x}", peer.services.bits()),
+                    "relaytxes": peer.relay,
+                    "lastsend": peer.last_request.map_or(0, |time| time.timestamp()),
+                    "lastrecv": peer.last_response.map_or(0, |time| time.timestamp()),
+                    "conntime": peer.connected_at.timestamp(),
+                    "pingtime": ping,
+                    "minping": ping,
+                    "version": peer.version.0,
+                    "subver": peer.user_agent,
+                    "inbound": peer.direction != Direction::Outbound,
+                    "startingheight": peer.start_height.0,
+                    "inflight": peer.in_flight_requests,
+                    "timedout": peer.timed_out_requests,
+                })
+            })
+            .collect();
+
+        Ok(json!(peers))
+    }
+
+    /// Adds the peer in the first parameter, like `zcashd`'s `addnode`.
+    ///
+    /// The `add` and `onetry` commands both ask the crawler to connect to
+    /// the peer once. Zebra doesn't keep a list of added peers, so `remove`
+    /// isn't supported: use `disconnectnode` instead.
+    fn add_node(&self, params: Value) -> Result<Value, Error> {
+        let node: String =
+            param(&params, 0)?.ok_or_else(|| Error::new(INVALID_PARAMS, "missing node"))?;
+        let command: String =
+            param(&params, 1)?.ok_or_else(|| Error::new(INVALID_PARAMS, "missing command"))?;
+
+        match command.as_str() {
+            "add" | "onetry" => {}
+            "remove" => {
+                return Err(Error::new(
+                    INVALID_PARAMS,
+                    "added nodes can't be removed, use disconnectnode instead",
+                ))
+            }
+            _ => {
+                return Err(Error::new(
+                    INVALID_PARAMS,
+                    format!("unknown addnode command {:?}", command),
+                ))
+            }
+        }
+
+        let addr = self.peer_addr(&node)?;
+        self.peers
+            .add_peer(addr)
+            .map_err(|e| Error::new(MISC_ERROR, e.to_string()))?;
+        info!(?addr, "adding a peer requested by an RPC client");
+
+        Ok(Value::Null)
+    }
+
+    /// Disconnects the peer in the first parameter, like `zcashd`'s
+    /// `disconnectnode`.
+    fn disconnect_node(&self, params: Value) -> Result<Value, Error> {
+        let node: String =
+            param(&params, 0)?.ok_or_else(|| Error::new(INVALID_PARAMS, "missing address"))?;
+        let addr = self.peer_addr(&node)?;
+
+        if !self.peers.disconnect(&addr) {
+            return Err(Error::new(
+                NODE_NOT_CONNECTED,
+                "Node not found in connected nodes",
+            ));
+        }
+        info!(?addr, "disconnecting a peer requested by an RPC client");
+
+        Ok(Value::Null)
+    }
+
+    /// Bans or unbans the subnet in the first parameter, like `zcashd`'s
+    /// `setban`.
+    ///
+    /// Bans last for `bantime` seconds, or until the `bantime` timestamp
+    /// if `absolute` is true. Bans are lost when the node restarts.
+    fn set_ban(&self, params: Value) -> Result<Value, Error> {
+        let subnet: String =
+            param(&params, 0)?.ok_or_else(|| Error::new(INVALID_PARAMS, "missing subnet"))?;
+        let command: String =
+            param(&params, 1)?.ok_or_else(|| Error::new(INVALID_PARAMS, "missing command"))?;
+        let subnet: IpNetwork = subnet
+            .parse()
+            .map_err(|_| Error::new(INVALID_IP_OR_SUBNET, "Invalid IP/Subnet"))?;
+
+        match command.as_str() {
+            "add" => {
+                let ban_time: i64 = param(&params, 2)?
+                    .filter(|&time| time > 0)
+                    .unwrap_or(DEFAULT_BAN_TIME);
+                let absolute: bool = param(&params, 3)?.unwrap_or(false);
+                let until = if absolute {
+                    Utc.timestamp(ban_time, 0)
+                } else {
+                    Utc::now() + chrono::Duration::seconds(ban_time)
+                };
+                self.peers.ban(subnet, until);
+                info!(%subnet, %until, "banning a subnet requested by an RPC client");
+            }
+            "remove" => {
+                if !self.peers.unban(&subnet) {
+                    return Err(Error::new(
+                        INVALID_IP_OR_SUBNET,
+                        "Error: Unban failed. Requested address/subnet was not previously banned.",
+                    ));
+                }
+                info!(%subnet, "unbanning a subnet requested by an RPC client");
+            }
+            _ => {
+                return Err(Error::new(
+                    INVALID_PARAMS,
+                    format!("unknown setban command {:?}", command),
+                ))
+            }
+        }
+
+        Ok(Value::Null)
+    }
+
+    /// Returns the banned subnets, like `zcashd`'s `listbanned`.
+    fn list_banned(&self, params: Value) -> Result<Value, Error> {
+        no_params(&params)?;
+        let bans: Vec<Value> = self
+            .peers
+            .bans()
+            .into_iter()
+            .map(|(subnet, until)| {
+                json!({
+                    "address": subnet.to_string(),
+                    "banned_until": until.timestamp(),
+                })
+            })
+            .collect();
+
+        Ok(json!(bans))
+    }
+
+    /// Removes every ban, like `zcashd`'s `clearbanned`.
+    fn clear_banned(&self, params: Value) -> Result<Value, Error> {
+        no_params(&params)?;
+        self.peers.clear_bans();
+        info!("clearing all bans requested by an RPC client");
+
+        Ok(Value::Null)
+    }
+
+    /// Returns the tip height, and the height and hash of the best chain block
+    /// with the hash or height in the first parameter.
+    async fn find_block(
+        &self,
+        params: &Value,
+    ) -> Result<(block::Height, block::Height, block::Hash), Error> {
+        let hash_or_height = match param::<Value>(params, 0)? {
+            Some(Value::String(hash_or_height)) => hash_or_height,
+            Some(Value::Number(height)) => height.to_string(),
+            Some(_) => return Err(Error::new(INVALID_PARAMS, "invalid block hash or height")),
+            None => return Err(Error::new(INVALID_PARAMS, "missing block hash or height")),
+        };
+
+        let (tip_height, _) = self
+            .tip()
+            .await?
+            .ok_or_else(|| Error::new(INVALID_ADDRESS_OR_KEY, "Block not found"))?;
+        match hash_or_height.parse::<u32>() {
+            Ok(height) => {
+                let height = block::Height(height);
+                let hash = self.best_chain_hash(height).await?.ok_or_else(|| {
+                    Error::new(INVALID_ADDRESS_OR_KEY, "Block height out of range")
+                })?;
+                Ok((tip_height, height, hash))
+            }
+            Err(_) => {
+                let hash: block::Hash = hash_or_height
+                    .parse()
+                    .map_err(|_| Error::new(INVALID_PARAMS, "invalid block hash or height"))?;
+                let depth = self
+                    .depth(hash)
+                    .await?
+                    .ok_or_else(|| Error::new(INVALID_ADDRESS_OR_KEY, "Block not found"))?;
+                Ok((tip_height, block::Height(tip_height.0 - depth), hash))
+            }
+        }
+    }
+
+    /// Sends an address index `request` to the state.
+    ///
+    /// The state fails these requests if the address index is disabled.
+    async fn address_query(
+        &self,
+        request: zebra_state::Request,
+    ) -> Result<zebra_state::Response, Error> {
+        self.state
+            .clone()
+            .oneshot(request)
+            .await
+            .map_err(Error::internal)
+    }
+
+    /// Returns every mempool transaction, with its fee.
+    async fn block_candidates(&self) -> Result<Vec<Candidate>, Error> {
+        match self
+            .mempool
+            .clone()
+            .oneshot(mempool::Request::BlockCandidates)
+            .await
+            .map_err(Error::internal)?
+        {
+            mempool::Response::BlockCandidates(candidates) => Ok(candidates),
+            response => Err(Error::new(
+                INTERNAL_ERROR,
+                format!("unexpected mempool response: {:?}", response),
+            )),
+        }
+    }
+
+    /// Returns the headers of the best chain blocks from `tip_height` down,
+    /// for the difficulty adjustment.
+    ///
+    /// Near the genesis block, there are fewer than
+    /// [`POW_ADJUSTMENT_BLOCK_SPAN`] headers.
+    async fn previous_headers(&self, tip_height: block::Height) -> Result<Vec<Header>, Error> {
+        let mut headers = Vec::with_capacity(POW_ADJUSTMENT_BLOCK_SPAN);
+        for height in (0..=tip_height.0).rev().take(POW_ADJUSTMENT_BLOCK_SPAN) {
+            match self
+                .state
+                .clone()
+                .oneshot(zebra_state::Request::BlockHeader {
+                    hash_or_height: block::Height(height).into(),
+                })
+                .await
+                .map_err(Error::internal)?
+            {
+                zebra_state::Response::BlockHeader { header } => headers.push(header),
+                response => return Err(unexpected(response)),
+            }
+        }
+        Ok(headers)
+    }
+
+    /// Returns the Sapling note commitment tree after the block with `hash`.
+    async fn sapling_tree(&self, hash: block::Hash) -> Result<NoteCommitmentTree, Error> {
+        match self
+            .state
+            .clone()
+            .oneshot(zebra_state::Request::GetSaplingTree { hash })
+            .await
+            .map_err(Error::internal)?
+        {
+            zebra_state::Response::SaplingTree { tree: Some(tree) } => Ok(tree),
+            zebra_state::Response::SaplingTree { tree: None } => Err(Error::new(
+                INTERNAL_ERROR,
+                format!("missing Sapling note commitment tree for {:?}", hash),
+            )),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Returns the Orchard note commitment tree after the block with `hash`.
+    async fn orchard_tree(
+        &self,
+        hash: block::Hash,
+    ) -> Result<orchard::tree::NoteCommitmentTree, Error> {
+        match self
+            .state
+            .clone()
+            .oneshot(zebra_state::Request::GetOrchardTree { hash })
+            .await
+            .map_err(Error::internal)?
+        {
+            zebra_state::Response::OrchardTree { tree: Some(tree) } => Ok(tree),
+            zebra_state::Response::OrchardTree { tree: None } => Err(Error::new(
+                INTERNAL_ERROR,
+                format!("missing Orchard note commitment tree for {:?}", hash),
+            )),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Returns the chain history tree after the block with `hash`.
+    async fn history_tree(&self, hash: block::Hash) -> Result<HistoryTree, Error> {
+        match self
+            .state
+            .clone()
+            .oneshot(zebra_state::Request::GetHistoryTree { hash })
+            .await
+            .map_err(Error::internal)?
+        {
+            zebra_state::Response::HistoryTree { tree: Some(tree) } => Ok(tree),
+            zebra_state::Response::HistoryTree { tree: None } => Err(Error::new(
+                INTERNAL_ERROR,
+                format!("missing chain history tree for {:?}", hash),
+            )),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Returns the best chain transaction with `hash`, and the height of the
+    /// block that contains it.
+    async fn chain_transaction(
+        &self,
+        hash: transaction::Hash,
+    ) -> Result<Option<(Arc<Transaction>, block::Height)>, Error> {
+        match self
+            .state
+            .clone()
+            .oneshot(zebra_state::Request::Transaction { hash })
+            .await
+            .map_err(Error::internal)?
+        {
+            zebra_state::Response::Transaction { transaction } => Ok(transaction),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Returns the hash of the best chain block at `height`.
+    async fn best_chain_hash(&self, height: block::Height) -> Result<Option<block::Hash>, Error> {
+        match self
+            .state
+            .clone()
+            .oneshot(zebra_state::Request::BestChainBlockHash { height })
+            .await
+            .map_err(Error::internal)?
+        {
+            zebra_state::Response::BlockHash { hash } => Ok(hash),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Returns the depth of the block with `hash` in the best chain.
+    async fn depth(&self, hash: block::Hash) -> Result<Option<u32>, Error> {
+        match self
+            .state
+            .clone()
+            .oneshot(zebra_state::Request::Depth { hash })
+            .await
+            .map_err(Error::internal)?
+        {
+            zebra_state::Response::Depth { depth } => Ok(depth),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Returns the height and hash of the best chain tip, or `None` if the
+    /// state is empty.
+    /// Returns the peer address in `node`, which is an IP address with an
+    /// optional port.
+    ///
+    /// Addresses without a port use the network's default port. Unlike
+    /// `zcashd`, host names aren't resolved.
+    fn peer_addr(&self, node: &str) -> Result<SocketAddr, Error> {
+        node.parse()
+            .or_else(|_| {
+                node.parse::<IpAddr>()
+                    .map(|ip| SocketAddr::new(ip, self.network.default_port()))
+            })
+            .map_err(|_| {
+                Error::new(
+                    INVALID_PARAMS,
+                    format!("{:?} is not an IP address, or an IP address and port", node),
+                )
+            })
+    }
+
+    async fn tip(&self) -> Result<Option<(block::Height, block::Hash)>, Error> {
+        match self
+            .state
+            .clone()
+            .oneshot(zebra_state::Request::Tip)
+            .await
+            .map_err(Error::internal)?
+        {
+            zebra_state::Response::BestTip { tip } => Ok(tip),
+            response => Err(unexpected(response)),
+        }
+    }
+}
+
+/// Returns an error for an unexpected state response.
+fn unexpected(response: zebra_state::Response) -> Error {
+    Error::new(
+        INTERNAL_ERROR,
+        format!("unexpected state response: {:?}", response),
+    )
+}
+
+/// The first parameter of the address index methods.
+///
+/// `zcashd` also accepts a single address string, which is parsed into a
+/// request with no other fields.
+#[derive(Clone, Debug, Default, Deserialize)]
+struct AddressRequest {
+    addresses: Vec<String>,
+    start: Option<u32>,
+    end: Option<u32>,
+    #[serde(default, rename = "chainInfo")]
+    chain_info: bool,
+}
+
+/// The parsed first parameter of the address index methods.
+#[derive(Clone, Debug)]
+struct Addresses {
+    addresses: Vec<transparent::Address>,
+    start: Option<u32>,
+    end: Option<u32>,
+    chain_info: bool,
+}
+
+/// Returns the addresses and options in the first parameter of an address
+/// index method.
+fn address_request(params: &Value) -> Result<Addresses, Error> {
+    let request = match param::<Value>(params, 0)? {
+        Some(Value::String(address)) => AddressRequest {
+            addresses: vec![address],
+            ..AddressRequest::default()
+        },
+        Some(request) => serde_json::from_value(request)
+            .map_err(|e| Error::new(INVALID_PARAMS, format!("invalid parameter 0: {}", e)))?,
+        None => return Err(Error::new(INVALID_PARAMS, "missing addresses")),
+    };
+
+    let addresses = request
+        .addresses
+        .iter()
+        .map(|address| {
+            address
+                .parse()
+                .map_err(|_| Error::new(INVALID_ADDRESS_OR_KEY, "Invalid address"))
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Addresses {
+        addresses,
+        start: request.start,
+        end: request.end,
+        chain_info: request.chain_info,
+    })
+}
+
+/// Returns the long poll ID for a template on `tip_hash`, with the mempool
+/// `candidates`.
+///
+/// Like `zcashd`, the ID is the tip hash, followed by a number that changes
+/// when the mempool changes. We use the number of mempool transactions.
+fn longpoll_id(tip_hash: block::Hash, candidates: &[Candidate]) -> String {
+    format!("{}{}", tip_hash, candidates.len())
+}
+
+/// Returns `bytes` in reversed hex, like `zcashd` displays hashes.
+fn reversed_hex(bytes: &[u8]) -> String {
+    hex::encode(bytes.iter().rev().cloned().collect::<Vec<u8>>())
+}
+
+/// Returns `value` serialized in hex.
+fn serialized_hex<T: ZcashSerialize>(value: &T) -> String {
+    let mut bytes = Vec::new();
+    value
+        .zcash_serialize(&mut bytes)
+        .expect("serializing to a vector doesn't fail");
+    hex::encode(bytes)
+}
+
+/// Returns the parameter at `index`, or `None` if there are fewer
+/// parameters.
+fn param<T: DeserializeOwned>(params: &Value, index: usize) -> Result<Option<T>, Error> {
+    match params {
+        Value::Null => Ok(None),
+        Value::Array(params) => params
+            .get(index)
+            .map(|param| {
+                serde_json::from_value(param.clone()).map_err(|e| {
+                    Error::new(
+                        INVALID_PARAMS,
+                        format!("invalid parameter {}: {}", index, e),
+                    )
+                })
+            })
+            .transpose(),
+        _ => Err(Error::new(INVALID_PARAMS, "parameters must be an array")),
+    }
+}
+
+/// Returns an error if `params` isn't empty.
+///
+/// Clients send either an empty array, or no parameters at all.
+fn no_params(params: &Value) -> Result<(), Error> {
+    match params {
+        Value::Null => Ok(()),
+        Value::Array(params) if params.is_empty() => Ok(()),
+        _ => Err(Error::new(INVALID_PARAMS, "method takes no parameters")),
+    }
+}
@@ -0,0 +1,304 @@
+//! Block templates for the `getblocktemplate` method.
+//!
+//! A template is a block without a proof of work: a coinbase transaction,
+//! the mempool transactions with the highest fee rates, and the header
+//! fields that miners need. Miners choose the time, nonce, and Equihash
+//! solution, then submit the block.
+
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+
+use zebra_chain::{
+    amount::{Amount, NonNegative},
+    block::{self, MAX_BLOCK_BYTES},
+    network_upgrade::NetworkUpgrade,
+    parameters::subsidy::{self, FundingStreamReceiver},
+    sapling,
+    serialization::ZcashSerialize,
+    transaction::{self, CoinbaseData, LockTime, Transaction, TransparentInput, TransparentOutput},
+    transparent, Network,
+};
+use zebra_consensus::block::check::MAX_BLOCK_SIGOPS;
+
+use super::{Error, INTERNAL_ERROR};
+use crate::mempool::Candidate;
+
+/// The bytes reserved for the block header, the transaction count, and the
+/// coinbase transaction.
+///
+/// Mempool transactions are selected until the block reaches
+/// `MAX_BLOCK_BYTES - RESERVED_BYTES`.
+pub const RESERVED_BYTES: usize = 3_000;
+
+/// The signature operations reserved for the coinbase transaction, like
+/// `zcashd`.
+pub const RESERVED_SIGOPS: u32 = 100;
+
+/// The data after the height in the coinbase script.
+///
+/// `zcashd` pushes an empty byte string, so the script is never shorter
+/// than the two bytes that Bitcoin requires.
+const COINBASE_DATA: [u8; 1] = [0x00];
+
+/// A transaction selected for a block template.
+#[derive(Clone, Debug)]
+pub struct TemplateTransaction {
+    /// The transaction.
+    pub transaction: Arc<Transaction>,
+    /// The transaction's hash.
+    pub hash: transaction::Hash,
+    /// The transaction's fee.
+    pub fee: Amount<NonNegative>,
+    /// The transaction's serialized size, in bytes.
+    pub size: usize,
+    /// The transaction's legacy signature operation count.
+    pub sigops: u32,
+    /// The positions of the template transactions that this transaction
+    /// spends outputs from, counting from 1, like `zcashd`.
+    pub depends: Vec<usize>,
+}
+
+impl TemplateTransaction {
+    fn new(candidate: Candidate) -> Self {
+        let transaction = candidate.transaction;
+        Self {
+            hash: transaction::Hash::from(transaction.as_ref()),
+            fee: candidate.fee,
+            size: transaction.zcash_serialized_size(),
+            sigops: transaction.legacy_sigop_count(),
+            depends: Vec::new(),
+            transaction,
+        }
+    }
+
+    /// Compares the fee rates of `self` and `other`, without rounding.
+    fn cmp_fee_rate(&self, other: &Self) -> Ordering {
+        let fee = |tx: &Self| u128::from(u64::from(tx.fee));
+        (fee(self) * other.size as u128).cmp(&(fee(other) * self.size as u128))
+    }
+
+    /// Returns this transaction in `getblocktemplate`'s JSON format.
+    pub fn to_json(&self) -> Value {
+        transaction_json(&self.transaction, i64::from(self.fee), &self.depends, false)
+    }
+}
+
+/// Returns the `candidates` that can be mined in a block at `height` with
+/// `time`, in block order.
+///
+/// Candidates are added in fee rate order, while the block stays under the
+/// size and signature operation limits. Expired transactions, and
+/// transactions with unexpired lock times, are skipped. Transactions that
+/// spend outputs from other mempool transactions are only added after
+/// them.
+pub fn select_transactions(
+    candidates: Vec<Candidate>,
+    height: block::Height,
+    time: DateTime<Utc>,
+) -> Vec<TemplateTransaction> {
+    let mempool_hashes: HashSet<_> = candidates
+        .iter()
+        .map(|candidate| transaction::Hash::from(candidate.transaction.as_ref()))
+        .collect();
+
+    let mut pending: Vec<_> = candidates
+        .into_iter()
+        .filter(|candidate| {
+            !candidate.transaction.is_expired_at(height)
+                && candidate.transaction.is_final(height, time)
+        })
+        .map(TemplateTransaction::new)
+        .collect();
+    pending.sort_by(|a, b| b.cmp_fee_rate(a));
+
+    let mut selected: Vec<TemplateTransaction> = Vec::new();
+    let mut positions = HashMap::new();
+    let mut bytes = RESERVED_BYTES;
+    let mut sigops = RESERVED_SIGOPS;
+
+    // Each pass adds the transactions whose mempool parents have already
+    // been added. Totals only grow, so skipped transactions that don't fit
+    // are dropped.
+    loop {
+        let mut waiting = Vec::new();
+        let mut added = false;
+
+        for mut tx in pending {
+            if bytes + tx.size > MAX_BLOCK_BYTES || sigops + tx.sigops > MAX_BLOCK_SIGOPS {
+                continue;
+            }
+
+            let parents: HashSet<_> = tx
+                .transaction
+                .inputs()
+                .filter_map(|input| match input {
+                    TransparentInput::PrevOut { outpoint, .. } => Some(outpoint.hash),
+                    TransparentInput::Coinbase { .. } => None,
+                })
+                .filter(|hash| mempool_hashes.contains(hash))
+                .collect();
+            if !parents.iter().all(|hash| positions.contains_key(hash)) {
+                waiting.push(tx);
+                continue;
+            }
+
+            tx.depends = parents.iter().map(|hash| positions[hash] + 1).collect();
+            tx.depends.sort_unstable();
+            positions.insert(tx.hash, selected.len());
+            bytes += tx.size;
+            sigops += tx.sigops;
+            selected.push(tx);
+            added = true;
+        }
+
+        pending = waiting;
+        if !added || pending.is_empty() {
+            break;
+        }
+    }
+
+    selected
+}
+
+/// Returns the coinbase transaction for a block at `height` on `network`.
+///
+/// The miner's output pays the miner subsidy and `fees` to
+/// `miner_address`. The other outputs pay the founders' reward or the
+/// funding streams that are required at `height`.
+pub fn coinbase_transaction(
+    network: Network,
+    height: block::Height,
+    miner_address: &transparent::Address,
+    fees: Amount<NonNegative>,
+) -> Result<Transaction, Error> {
+    let value_error = |e| Error::new(INTERNAL_ERROR, format!("invalid coinbase value: {}", e));
+    let miner_value = (subsidy::miner_subsidy(height, network) + fees).map_err(value_error)?;
+
+    let mut outputs = vec![TransparentOutput {
+        value: miner_value,
+        pk_script: miner_address.lock_script(),
+    }];
+
+    if let Some(address) = subsidy::founders_reward_address(height, network) {
+        outputs.push(TransparentOutput {
+            value: subsidy::founders_reward(height, network),
+            pk_script: address.lock_script(),
+        });
+    }
+
+    // The funding streams are in a map, so we sort them to keep the
+    // coinbase the same for the same template.
+    let mut funding_streams: Vec<_> = subsidy::funding_stream_values(height, network)
+        .into_iter()
+        .collect();
+    funding_streams.sort_by_key(|(receiver, _)| {
+        FundingStreamReceiver::ALL
+            .iter()
+            .position(|known| known == receiver)
+    });
+    for (receiver, value) in funding_streams {
+        let address =
+            subsidy::funding_stream_address(height, network, receiver).ok_or_else(|| {
+                Error::new(
+                    INTERNAL_ERROR,
+                    format!("the {:?} funding stream address is unknown", receiver),
+                )
+            })?;
+        outputs.push(TransparentOutput {
+            value,
+            pk_script: address.lock_script(),
+        });
+    }
+
+    let inputs = vec![TransparentInput::Coinbase {
+        height,
+        data: CoinbaseData::new(COINBASE_DATA.to_vec()).expect("coinbase data is short"),
+        sequence: u32::MAX,
+    }];
+    let lock_time = LockTime::unlocked();
+
+    Ok(match NetworkUpgrade::current(network, height) {
+        NetworkUpgrade::Genesis | NetworkUpgrade::BeforeOverwinter => Transaction::V1 {
+            inputs,
+            outputs,
+            lock_time,
+        },
+        NetworkUpgrade::Overwinter => Transaction::V3 {
+            inputs,
+            outputs,
+            lock_time,
+            expiry_height: height,
+            joinsplit_data: None,
+        },
+        NetworkUpgrade::Sapling
+        | NetworkUpgrade::Blossom
+        | NetworkUpgrade::Heartwood
+        | NetworkUpgrade::Canopy => Transaction::V4 {
+            inputs,
+            outputs,
+            lock_time,
+            expiry_height: height,
+            value_balance: Amount::zero(),
+            shielded_data: None,
+            joinsplit_data: None,
+        },
+        NetworkUpgrade::Nu5 => Transaction::V5 {
+            inputs,
+            outputs,
+            lock_time,
+            expiry_height: height,
+            consensus_branch_id: NetworkUpgrade::Nu5
+                .branch_id()
+                .expect("NU5 has a branch ID")
+                .into(),
+            sapling_value_balance: Amount::zero(),
+            sapling_shielded_data: None,
+            orchard_shielded_data: None,
+        },
+    })
+}
+
+/// Returns the Sapling note commitment tree root after adding the note
+/// commitments in `transactions` to `tree`.
+pub fn final_sapling_root(
+    mut tree: sapling::tree::NoteCommitmentTree,
+    transactions: &[Arc<Transaction>],
+) -> Result<sapling::tree::Root, Error> {
+    for output in transactions.iter().flat_map(|tx| tx.sapling_outputs()) {
+        tree.append(output.cmu)
+            .map_err(|e| Error::new(INTERNAL_ERROR, e.to_string()))?;
+    }
+    Ok(tree.root())
+}
+
+/// Returns `transaction` in `getblocktemplate`'s JSON format.
+///
+/// The coinbase has a negative `fee`, which is the total fee that it
+/// claims.
+pub fn transaction_json(
+    transaction: &Transaction,
+    fee: i64,
+    depends: &[usize],
+    required: bool,
+) -> Value {
+    let mut data = Vec::new();
+    transaction
+        .zcash_serialize(&mut data)
+        .expect("serializing to a vector doesn't fail");
+
+    json!({
+        "data": hex::encode(data),
+        "hash": transaction::Hash::from(transaction).to_string(),
+        "authdigest": transaction.auth_digest().to_string(),
+        "depends": depends,
+        "fee": fee,
+        "sigops": transaction.legacy_sigop_count(),
+        "required": required,
+    })
+}
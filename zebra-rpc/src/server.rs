@@ -0,0 +1,289 @@
+//! The HTTP server for JSON-RPC requests.
+//!
+//! Like `zcashd`, the server accepts JSON-RPC 1.0 and 2.0 requests, POSTed
+//! to any path, and responds with a `result`, an `error`, and the request's
+//! `id`. Batch requests aren't supported yet.
+//!
+//! If the server has a password or a cookie file, requests must have HTTP
+//! basic authentication.
+//!
+//! If events are enabled, a `GET` request for `/events` streams each
+//! [`Event`](crate::events::Event) as a line of JSON, until the client
+//! disconnects. If the client falls behind, it gets a `lagged` event with
+//! the number of events it missed, and should check the chain tip.
+//!
+//! When the server shuts down, it stops accepting connections, closes the
+//! event streams, and finishes once it has answered the other requests in
+//! progress, such as block submissions that are still being verified.
+
+use std::{convert::Infallible, error::Error as StdError, future::Future, sync::Arc};
+
+use futures::{
+    future::{self, BoxFuture, Either, Shared},
+    stream, FutureExt,
+};
+
+use hyper::{
+    header,
+    service::{make_service_fn, service_fn},
+    Body, Method, Server, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::broadcast::{self, RecvError};
+use tower::Service;
+use tracing::{debug, info, warn};
+
+use crate::{
+    auth::Auth,
+    events::Event,
+    mempool,
+    methods::{Error, Rpc, INVALID_REQUEST, PARSE_ERROR},
+    submit, Config,
+};
+
+/// A boxed error from the state service.
+type BoxError = Box<dyn StdError + Send + Sync + 'static>;
+
+/// A signal that the server is shutting down, which each request can wait
+/// on.
+type Shutdown = Shared<BoxFuture<'static, ()>>;
+
+/// A JSON-RPC request.
+#[derive(Clone, Debug, Deserialize)]
+struct Request {
+    /// The request ID, which is copied to the response.
+    #[serde(default)]
+    id: Value,
+    /// The name of the method.
+    method: String,
+    /// The method parameters, usually an array.
+    #[serde(default)]
+    params: Value,
+}
+
+/// A JSON-RPC response.
+///
+/// Exactly one of `result` and `error` is set, but `zcashd` clients expect
+/// both fields.
+#[derive(Clone, Debug, Serialize)]
+struct Response {
+    result: Option<Value>,
+    error: Option<Error>,
+    id: Value,
+}
+
+impl Response {
+    fn new(id: Value, result: Result<Value, Error>) -> Self {
+        match result {
+            Ok(result) => Response {
+                result: Some(result),
+                error: None,
+                id,
+            },
+            Err(error) => Response {
+                result: None,
+                error: Some(error),
+                id,
+            },
+        }
+    }
+}
+
+/// Listens on the address in `config`, and returns a server that answers
+/// JSON-RPC requests using `rpc`, until `shutdown` finishes.
+///
+/// Fails if `config` doesn't have a listen address, if the address isn't a
+/// loopback address and external access isn't allowed or there is no
+/// password or cookie file, if the cookie file can't be written, or if the
+/// server can't listen on the address.
+///
+/// The returned future finishes if the server fails, or after `shutdown`
+/// finishes and the requests in progress have been answered.
+pub fn bind<S, M, B, F>(
+    config: &Config,
+    rpc: Rpc<S, M, B>,
+    shutdown: F,
+) -> Result<impl Future<Output = Result<(), hyper::Error>>, BoxError>
+where
+    F: Future<Output = ()> + Send + 'static,
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+    M: Service<mempool::Request, Response = mempool::Response, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    M::Future: Send,
+    B: Service<submit::Request, Response = submit::Response, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    B::Future: Send,
+{
+    let listen_addr = config
+        .listen_addr
+        .ok_or("the RPC server doesn't have a listen address")?;
+    let auth = Arc::new(Auth::from_config(config)?);
+    if !listen_addr.ip().is_loopback() {
+        if !config.allow_external_access {
+            return Err(format!(
+                "refusing to listen for RPC requests on {}, which isn't a loopback address: \
+                 set allow_external_access to listen on it",
+                listen_addr
+            )
+            .into());
+        }
+        if !auth.is_enabled() {
+            return Err(format!(
+                "refusing to listen for RPC requests on {}, which isn't a loopback address, \
+                 without a password or a cookie file",
+                listen_addr
+            )
+            .into());
+        }
+        warn!(
+            ?listen_addr,
+            "RPC server is listening on a non-local address: \
+             anyone with the password can submit blocks and transactions"
+        );
+    }
+
+    if !auth.is_enabled() {
+        info!("RPC server doesn't have a password or cookie file, so it accepts every request");
+    }
+
+    let shutdown: Shutdown = shutdown.boxed().shared();
+    let server_shutdown = shutdown.clone();
+    let make_service = make_service_fn(move |_| {
+        let rpc = rpc.clone();
+        let auth = auth.clone();
+        let shutdown = shutdown.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let rpc = rpc.clone();
+                let auth = auth.clone();
+                let shutdown = shutdown.clone();
+                async move { Ok::<_, Infallible>(handle(rpc, &auth, shutdown, req).await) }
+            }))
+        }
+    });
+
+    info!(?listen_addr, "starting RPC server");
+    Ok(Server::try_bind(&listen_addr)?
+        .serve(make_service)
+        .with_graceful_shutdown(server_shutdown))
+}
+
+/// Answers the HTTP request `req`, if it has credentials that `auth`
+/// accepts.
+///
+/// Event streams end when `shutdown` finishes, so that the server can stop.
+async fn handle<S, M, B>(
+    rpc: Rpc<S, M, B>,
+    auth: &Auth,
+    shutdown: Shutdown,
+    req: hyper::Request<Body>,
+) -> hyper::Response<Body>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+    M: Service<mempool::Request, Response = mempool::Response, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    M::Future: Send,
+    B: Service<submit::Request, Response = submit::Response, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    B::Future: Send,
+{
+    let authorization = req.headers().get(header::AUTHORIZATION);
+    if !auth.check(authorization.map(|value| value.as_bytes())) {
+        debug!("RPC request with missing or incorrect credentials");
+        return hyper::Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(header::WWW_AUTHENTICATE, "Basic realm=\"jsonrpc\"")
+            .body(Body::empty())
+            .expect("response with known status code cannot fail");
+    }
+
+    if req.method() == Method::GET && req.uri().path() == "/events" {
+        return match rpc.subscribe() {
+            Some(events) => {
+                debug!("RPC client subscribed to events");
+                hyper::Response::builder()
+                    .status(StatusCode::OK)
+                    .header("Content-Type", "application/x-ndjson")
+                    .body(Body::wrap_stream(event_lines(events, shutdown)))
+                    .expect("response with known status code cannot fail")
+            }
+            None => hyper::Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("events are not enabled"))
+                .expect("response with known status code cannot fail"),
+        };
+    }
+
+    if req.method() != Method::POST {
+        return hyper::Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::from("JSON-RPC requests must be POSTed"))
+            .expect("response with known status code cannot fail");
+    }
+
+    let response = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => match serde_json::from_slice::<Value>(&body) {
+            Ok(value) => match serde_json::from_value::<Request>(value) {
+                Ok(request) => {
+                    debug!(method = ?request.method, "RPC request");
+                    let result = rpc.call(&request.method, request.params).await;
+                    Response::new(request.id, result)
+                }
+                Err(e) => {
+                    Response::new(Value::Null, Err(Error::new(INVALID_REQUEST, e.to_string())))
+                }
+            },
+            Err(e) => Response::new(Value::Null, Err(Error::new(PARSE_ERROR, e.to_string()))),
+        },
+        Err(e) => Response::new(Value::Null, Err(Error::new(PARSE_ERROR, e.to_string()))),
+    };
+
+    hyper::Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(Body::from(
+            serde_json::to_vec(&response).expect("RPC responses can be serialized"),
+        ))
+        .expect("response with known status code cannot fail")
+}
+
+/// Returns a stream of JSON lines for `events`, which ends when the event
+/// sender is dropped, or when `shutdown` finishes.
+fn event_lines(
+    events: broadcast::Receiver<Event>,
+    shutdown: Shutdown,
+) -> impl futures::Stream<Item = Result<String, Infallible>> {
+    stream::unfold((events, shutdown), |(mut events, shutdown)| async move {
+        let event = {
+            let recv = events.recv();
+            futures::pin_mut!(recv);
+            match future::select(recv, shutdown.clone()).await {
+                Either::Left((event, _)) => event,
+                Either::Right(_) => return None,
+            }
+        };
+        let line = match event {
+            Ok(event) => event.to_json(),
+            Err(RecvError::Lagged(missed)) => json!({ "type": "lagged", "missed": missed }),
+            Err(RecvError::Closed) => return None,
+        };
+        Some((Ok(format!("{}\n", line)), (events, shutdown)))
+    })
+}
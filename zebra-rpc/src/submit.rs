@@ -0,0 +1,31 @@
+//! The block submission requests that the `submitblock` method makes.
+//!
+//! Blocks are verified and gossiped by `zebrad`, so the node provides a
+//! service that answers these requests using its verifier, state, and peer
+//! set.
+
+use std::sync::Arc;
+
+use zebra_chain::block::Block;
+
+/// A block submission request from an RPC method.
+#[derive(Clone, Debug)]
+pub enum Request {
+    /// Verify a mined block, add it to the state, and advertise it to peers
+    /// if it is the new best tip.
+    Submit(Arc<Block>),
+}
+
+/// The result of a block submission.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Response {
+    /// The block is valid, and it is the new best chain tip.
+    Accepted,
+    /// The block is already in the best chain.
+    Duplicate,
+    /// The block is valid, but it isn't on the best chain, or it couldn't be
+    /// checked yet.
+    Inconclusive,
+    /// The block is invalid, for this reason.
+    Rejected(String),
+}
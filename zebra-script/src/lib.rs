@@ -1,10 +1,216 @@
+//! Transparent script verification for Zebra. 🦓
+//!
+//! Scripts are verified by the `zcash_script` library, which is built from
+//! the `zcashd` script interpreter, so that Zebra accepts exactly the same
+//! transparent spends as `zcashd`.
+//!
+//! Use [`inputs_are_valid`] to verify every input of a transaction. From
+//! NU5 onwards, signatures commit to all the outputs spent by the
+//! transaction, so they can't be verified one input at a time.
+
 #![doc(html_logo_url = "https://www.zfnd.org/images/zebra-icon.png")]
 #![doc(html_root_url = "https://doc.zebra.zfnd.org/zebra_script")]
+#![deny(missing_docs)]
 
 #[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
+mod tests;
+
+use std::{convert::TryFrom, ffi::c_void, os::raw::c_uint};
+
+use thiserror::Error;
+use zcash_script::{
+    zcash_script_SCRIPT_FLAGS_VERIFY_CHECKLOCKTIMEVERIFY, zcash_script_SCRIPT_FLAGS_VERIFY_P2SH,
+    zcash_script_error_t, zcash_script_error_t_zcash_script_ERR_OK,
+    zcash_script_error_t_zcash_script_ERR_TX_DESERIALIZE,
+    zcash_script_error_t_zcash_script_ERR_TX_INDEX,
+    zcash_script_error_t_zcash_script_ERR_TX_SIZE_MISMATCH,
+};
+
+use zebra_chain::{
+    amount::{Amount, NonNegative},
+    network_upgrade::ConsensusBranchId,
+    serialization::{zcash_serialize_vec, ZcashSerialize},
+    transaction::{Transaction, TransparentOutput},
+    transparent::Script,
+};
+
+/// The script verification flags used by `zcashd` for consensus.
+const CONSENSUS_FLAGS: c_uint =
+    zcash_script_SCRIPT_FLAGS_VERIFY_P2SH | zcash_script_SCRIPT_FLAGS_VERIFY_CHECKLOCKTIMEVERIFY;
+
+/// A script verification failure.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The spending script failed to satisfy the spent output's script.
+    #[error("script evaluation failed")]
+    ScriptInvalid,
+    /// The input index is not in the transaction.
+    #[error("input index {0} is not in the transaction")]
+    TxIndex(u32),
+    /// `zcash_script` read a different transaction length than it was given.
+    #[error("transaction size mismatch")]
+    TxSizeMismatch,
+    /// `zcash_script` couldn't deserialize the transaction.
+    #[error("transaction could not be deserialized")]
+    TxDeserialize,
+    /// The number of spent outputs is different to the number of inputs.
+    #[error("transaction has {inputs} inputs, but {spent_outputs} spent outputs")]
+    SpentOutputsMismatch {
+        /// The number of inputs in the transaction.
+        inputs: usize,
+        /// The number of spent outputs.
+        spent_outputs: usize,
+    },
+    /// An error code that this crate doesn't know about.
+    #[error("unknown zcash_script error {0}")]
+    Unknown(zcash_script_error_t),
+}
+
+impl Error {
+    /// Returns the error for a `zcash_script` error `code`, from verifying
+    /// `input_index`.
+    #[allow(non_upper_case_globals)]
+    fn from_code(code: zcash_script_error_t, input_index: u32) -> Error {
+        match code {
+            // The script was evaluated, but it failed.
+            zcash_script_error_t_zcash_script_ERR_OK => Error::ScriptInvalid,
+            zcash_script_error_t_zcash_script_ERR_TX_INDEX => Error::TxIndex(input_index),
+            zcash_script_error_t_zcash_script_ERR_TX_SIZE_MISMATCH => Error::TxSizeMismatch,
+            zcash_script_error_t_zcash_script_ERR_TX_DESERIALIZE => Error::TxDeserialize,
+            unknown => Error::Unknown(unknown),
+        }
+    }
+}
+
+/// Returns `Ok(())` if input `input_index` of `tx` can spend an output with
+/// `script_pubkey` and `amount`, under the consensus rules for `branch_id`.
+///
+/// `amount` is the value of the spent output, which is part of the signature
+/// hash from Overwinter onwards.
+pub fn is_valid(
+    script_pubkey: &Script,
+    amount: Amount<NonNegative>,
+    tx: &Transaction,
+    input_index: u32,
+    branch_id: ConsensusBranchId,
+) -> Result<(), Error> {
+    if usize::try_from(input_index).map_or(true, |index| index >= tx.inputs().count()) {
+        return Err(Error::TxIndex(input_index));
+    }
+
+    let mut tx_bytes = Vec::new();
+    tx.zcash_serialize(&mut tx_bytes)
+        .expect("serializing into a Vec never fails");
+    let tx_len = c_uint::try_from(tx_bytes.len()).map_err(|_| Error::TxSizeMismatch)?;
+    let script_len = c_uint::try_from(script_pubkey.0.len()).map_err(|_| Error::ScriptInvalid)?;
+
+    let mut error: zcash_script_error_t = 0;
+    // SAFETY: the pointers and lengths describe live byte slices, which
+    // `zcash_script_verify` only reads for the duration of the call, and
+    // `error` is a valid location for the error code.
+    let valid = unsafe {
+        zcash_script::zcash_script_verify(
+            script_pubkey.0.as_ptr(),
+            script_len,
+            i64::from(amount),
+            tx_bytes.as_ptr(),
+            tx_len,
+            input_index,
+            CONSENSUS_FLAGS,
+            branch_id.into(),
+            &mut error,
+        )
+    };
+
+    if valid == 1 {
+        Ok(())
+    } else {
+        Err(Error::from_code(error, input_index))
     }
 }
+
+/// A transaction that has been deserialized and hashed by `zcash_script`,
+/// so that each of its inputs can be verified without repeating that work.
+///
+/// The transaction is freed when this is dropped.
+struct PrecomputedTx(*mut c_void);
+
+impl Drop for PrecomputedTx {
+    fn drop(&mut self) {
+        // SAFETY: the pointer was returned by `zcash_script`, and is only
+        // freed here.
+        unsafe { zcash_script::zcash_script_free_precomputed_tx(self.0) }
+    }
+}
+
+/// Returns `Ok(())` if every input of `tx` can spend the output at the same
+/// position in `spent_outputs`, under the consensus rules for `branch_id`.
+///
+/// Coinbase transactions don't spend any outputs, so they shouldn't be
+/// verified.
+pub fn inputs_are_valid(
+    tx: &Transaction,
+    spent_outputs: &[TransparentOutput],
+    branch_id: ConsensusBranchId,
+) -> Result<(), Error> {
+    let inputs = tx.inputs().count();
+    if inputs != spent_outputs.len() {
+        return Err(Error::SpentOutputsMismatch {
+            inputs,
+            spent_outputs: spent_outputs.len(),
+        });
+    }
+
+    let mut tx_bytes = Vec::new();
+    tx.zcash_serialize(&mut tx_bytes)
+        .expect("serializing into a Vec never fails");
+    let tx_len = c_uint::try_from(tx_bytes.len()).map_err(|_| Error::TxSizeMismatch)?;
+    let mut outputs_bytes = Vec::new();
+    zcash_serialize_vec(spent_outputs, &mut outputs_bytes)
+        .expect("serializing into a Vec never fails");
+    let outputs_len = c_uint::try_from(outputs_bytes.len()).map_err(|_| Error::TxSizeMismatch)?;
+
+    let mut error: zcash_script_error_t = 0;
+    // SAFETY: the pointers and lengths describe live byte slices, which
+    // `zcash_script` copies what it needs from, and `error` is a valid
+    // location for the error code.
+    let precomputed = unsafe {
+        zcash_script::zcash_script_new_precomputed_tx_v5(
+            tx_bytes.as_ptr(),
+            tx_len,
+            outputs_bytes.as_ptr(),
+            outputs_len,
+            &mut error,
+        )
+    };
+    if precomputed.is_null() {
+        return Err(Error::from_code(error, 0));
+    }
+    let precomputed = PrecomputedTx(precomputed);
+
+    for (input_index, output) in spent_outputs.iter().enumerate() {
+        let input_index = input_index as u32;
+        let script_len =
+            c_uint::try_from(output.pk_script.0.len()).map_err(|_| Error::ScriptInvalid)?;
+
+        // SAFETY: `precomputed` is live until the end of this function, and
+        // the script pointer and length describe a live byte slice.
+        let valid = unsafe {
+            zcash_script::zcash_script_verify_precomputed(
+                precomputed.0,
+                input_index,
+                output.pk_script.0.as_ptr(),
+                script_len,
+                i64::from(output.value),
+                CONSENSUS_FLAGS,
+                branch_id.into(),
+                &mut error,
+            )
+        };
+        if valid != 1 {
+            return Err(Error::from_code(error, input_index));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,130 @@
+//! Tests for script verification.
+
+use std::convert::TryFrom;
+
+use zebra_chain::{
+    network_upgrade::NetworkUpgrade,
+    transaction::{self, LockTime, OutPoint, TransparentInput, TransparentOutput},
+};
+
+use super::*;
+
+/// The `OP_0` opcode, which pushes an empty (false) value.
+const OP_0: u8 = 0x00;
+/// The `OP_1` opcode, which pushes a true value.
+const OP_1: u8 = 0x51;
+
+/// Returns a version 4 transaction with one input, which has `script_sig`.
+fn spend(script_sig: Vec<u8>) -> Transaction {
+    Transaction::V4 {
+        inputs: vec![TransparentInput::PrevOut {
+            outpoint: OutPoint {
+                hash: transaction::Hash([0x11; 32]),
+                index: 0,
+            },
+            script: Script(script_sig.into()),
+            sequence: u32::MAX,
+        }],
+        outputs: vec![TransparentOutput {
+            value: Amount::try_from(1i64).expect("1 is a valid amount"),
+            pk_script: Script(vec![].into()),
+        }],
+        lock_time: LockTime::unlocked(),
+        expiry_height: zebra_chain::block::Height(0),
+        value_balance: Amount::zero(),
+        shielded_data: None,
+        joinsplit_data: None,
+    }
+}
+
+fn sapling_branch_id() -> ConsensusBranchId {
+    NetworkUpgrade::Sapling
+        .branch_id()
+        .expect("Sapling has a branch ID")
+}
+
+#[test]
+fn scripts_are_evaluated() {
+    let amount = Amount::try_from(2i64).expect("2 is a valid amount");
+    let empty_script = Script(vec![].into());
+
+    assert_eq!(
+        is_valid(
+            &empty_script,
+            amount,
+            &spend(vec![OP_1]),
+            0,
+            sapling_branch_id()
+        ),
+        Ok(())
+    );
+    assert_eq!(
+        is_valid(
+            &empty_script,
+            amount,
+            &spend(vec![OP_0]),
+            0,
+            sapling_branch_id()
+        ),
+        Err(Error::ScriptInvalid)
+    );
+    assert_eq!(
+        is_valid(
+            &Script(vec![OP_0].into()),
+            amount,
+            &spend(vec![]),
+            0,
+            sapling_branch_id()
+        ),
+        Err(Error::ScriptInvalid)
+    );
+}
+
+#[test]
+fn input_index_is_checked() {
+    let amount = Amount::try_from(2i64).expect("2 is a valid amount");
+
+    assert_eq!(
+        is_valid(
+            &Script(vec![].into()),
+            amount,
+            &spend(vec![OP_1]),
+            1,
+            sapling_branch_id()
+        ),
+        Err(Error::TxIndex(1))
+    );
+}
+
+#[test]
+fn every_input_is_verified() {
+    let amount = Amount::try_from(2i64).expect("2 is a valid amount");
+    let spent_output = TransparentOutput {
+        value: amount,
+        pk_script: Script(vec![].into()),
+    };
+
+    assert_eq!(
+        inputs_are_valid(
+            &spend(vec![OP_1]),
+            &[spent_output.clone()],
+            sapling_branch_id()
+        ),
+        Ok(())
+    );
+    assert_eq!(
+        inputs_are_valid(
+            &spend(vec![OP_0]),
+            &[spent_output.clone()],
+            sapling_branch_id()
+        ),
+        Err(Error::ScriptInvalid)
+    );
+    assert_eq!(
+        inputs_are_valid(&spend(vec![OP_1]), &[], sapling_branch_id()),
+        Err(Error::SpentOutputsMismatch {
+            inputs: 1,
+            spent_outputs: 0
+        })
+    );
+}
@@ -0,0 +1,95 @@
+//! Configuration for the state service.
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use zebra_chain::Network;
+
+/// Configuration for the on-disk state.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Config {
+    /// The root directory for the state databases.
+    ///
+    /// Defaults to a `zebra` directory in the platform's data directory, like
+    /// `~/.local/share/zebra` on Linux, or in its cache directory if it
+    /// doesn't have a data directory.
+    ///
+    /// Each network has its own database in a subdirectory, so a node can
+    /// switch networks without mixing their blocks.
+    pub cache_dir: PathBuf,
+
+    /// Whether to index transparent outputs and transactions by address.
+    ///
+    /// The index is needed for address balance and history queries, like
+    /// `lightwalletd`'s `getaddresstxids`, but it uses extra disk space.
+    ///
+    /// Address queries are refused if the index was ever disabled after the
+    /// state was created, because it would be missing blocks. Delete the
+    /// state to rebuild the index.
+    pub index_addresses: bool,
+
+    /// Whether to index the BIP158 basic filter of each block.
+    ///
+    /// The index is needed to serve BIP157 compact filters to light clients,
+    /// but it uses extra disk space. Like the address index, filter queries
+    /// are refused if the index was ever disabled after the state was
+    /// created.
+    pub index_compact_filters: bool,
+
+    /// The number of finalized blocks, below the finalized tip, that keep
+    /// their full bodies, or `None` to keep every block.
+    ///
+    /// Older blocks are replaced by their headers, but their outputs,
+    /// nullifiers, and note commitment trees are kept, so the node can still
+    /// validate new blocks. Pruned blocks can't be served to peers or
+    /// queried, and are only restored by deleting the state and resyncing.
+    pub prune_depth: Option<u32>,
+
+    /// The number of blocks, down from the finalized tip, that are checked
+    /// for corruption when the state is opened.
+    ///
+    /// Set this to zero to skip the check, or above the tip height to check
+    /// every block.
+    pub startup_check_depth: u32,
+}
+
+impl Config {
+    /// Returns the directory for the finalized state of `network`.
+    pub fn db_path(&self, network: Network) -> PathBuf {
+        let network = match network {
+            Network::Mainnet => "mainnet",
+            Network::Testnet => "testnet",
+            Network::Regtest => "regtest",
+        };
+
+        self.cache_dir.join("state").join(network)
+    }
+
+    /// Returns the sled configuration for the finalized state of `network`.
+    pub(crate) fn sled_config(&self, network: Network) -> sled::Config {
+        sled::Config::default().path(self.db_path(network))
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        // If the platform doesn't have a data directory, use its cache
+        // directory, then the working directory. The relative path is a
+        // last resort, if the working directory has been deleted.
+        let cache_dir = dirs::data_dir()
+            .or_else(dirs::cache_dir)
+            .or_else(|| std::env::current_dir().ok().map(|dir| dir.join("cache")))
+            .unwrap_or_else(|| PathBuf::from("cache"))
+            .join("zebra");
+
+        Config {
+            cache_dir,
+            index_addresses: false,
+            index_compact_filters: false,
+            prune_depth: None,
+            startup_check_depth: 100,
+        }
+    }
+}
@@ -1,4 +1,8 @@
-use super::{Request, Response};
+use super::{
+    block_locator_heights, non_finalized::Pool, pending_tips::PendingTips,
+    pending_utxos::PendingUtxos, Request, Response, MAX_FIND_BLOCK_HASHES_RESULTS,
+    MAX_FIND_BLOCK_HEADERS_RESULTS,
+};
 use futures::prelude::*;
 use std::{
     error::Error,
@@ -7,12 +11,14 @@ use std::{
     task::{Context, Poll},
 };
 use tower::{buffer::Buffer, Service};
+use zebra_chain::Network;
 
 mod block_index;
 
-#[derive(Default)]
 struct ZebraState {
     index: block_index::BlockIndex,
+    pending_utxos: PendingUtxos,
+    pending_tips: PendingTips,
 }
 
 impl Service<Request> for ZebraState {
@@ -28,7 +34,24 @@ impl Service<Request> for ZebraState {
     fn call(&mut self, req: Request) -> Self::Future {
         match req {
             Request::AddBlock { block } => {
-                let result = self.index.insert(block).map(|_| Response::Added);
+                let result = self.index.insert(block.clone()).map(|_| Response::Added);
+                if result.is_ok() {
+                    self.pending_utxos.check_block(&block);
+                    self.pending_tips.check_tip(self.index.tip());
+                }
+
+                async { result }.boxed()
+            }
+            Request::CommitFinalizedBlock { block } => {
+                let hash = block.hash();
+                let result = self
+                    .index
+                    .insert(block.clone())
+                    .map(|_| Response::Committed { hash });
+                if result.is_ok() {
+                    self.pending_utxos.check_block(&block);
+                    self.pending_tips.check_tip(self.index.tip());
+                }
 
                 async { result }.boxed()
             }
@@ -41,6 +64,89 @@ impl Service<Request> for ZebraState {
 
                 async move { result }.boxed()
             }
+            Request::Block { hash_or_height } => {
+                let result = self
+                    .index
+                    .get(hash_or_height)
+                    .map(|block| Response::Block { block })
+                    .ok_or_else(|| "block could not be found".into());
+
+                async move { result }.boxed()
+            }
+            Request::BlockHeader { hash_or_height } => {
+                let result = self
+                    .index
+                    .get(hash_or_height)
+                    .map(|block| Response::BlockHeader {
+                        header: block.header,
+                    })
+                    .ok_or_else(|| "block could not be found".into());
+
+                async move { result }.boxed()
+            }
+            Request::Tip => {
+                let tip = self.index.tip();
+
+                async move { Ok(Response::BestTip { tip }) }.boxed()
+            }
+            Request::AwaitTipChange { tip: known } => {
+                let tip = self.index.tip();
+                let pending = if tip.map(|(_, hash)| hash) != known {
+                    async move { Ok(tip) }.boxed()
+                } else {
+                    self.pending_tips.queue(known).boxed()
+                };
+
+                async move {
+                    let tip = pending.await?;
+                    Ok(Response::BestTip { tip })
+                }
+                .boxed()
+            }
+            Request::Depth { hash } => {
+                let depth = self.index.depth(hash);
+
+                async move { Ok(Response::Depth { depth }) }.boxed()
+            }
+            Request::BestChainBlockHash { height } => {
+                let hash = self.index.get(height).map(|block| block.hash());
+
+                async move { Ok(Response::BlockHash { hash }) }.boxed()
+            }
+            Request::BlockLocator => {
+                let hashes = match self.index.tip() {
+                    Some((tip, _)) => block_locator_heights(tip)
+                        .into_iter()
+                        .filter_map(|height| self.index.get(height))
+                        .map(|block| block.hash())
+                        .collect(),
+                    None => Vec::new(),
+                };
+
+                async move { Ok(Response::BlockLocator { hashes }) }.boxed()
+            }
+            Request::AddressBalance { .. }
+            | Request::AddressUtxos { .. }
+            | Request::AddressTxIds { .. } => {
+                async { Err("the in-memory state doesn't index addresses".into()) }.boxed()
+            }
+            Request::CompactFilters { .. } | Request::CompactFilterCheckpoints { .. } => {
+                async { Err("the in-memory state doesn't index compact filters".into()) }.boxed()
+            }
+            Request::CheckIntegrity { .. } => {
+                async { Err("the in-memory state doesn't check its integrity".into()) }.boxed()
+            }
+            Request::Flush => async { Ok(Response::Flushed) }.boxed(),
+            Request::FinalizeBestChain => {
+                let tip = self.index.tip();
+
+                async move { Ok(Response::Finalized { tip }) }.boxed()
+            }
+            Request::Transaction { hash } => {
+                let transaction = self.index.transaction(hash);
+
+                async move { Ok(Response::Transaction { transaction }) }.boxed()
+            }
             Request::GetTip => {
                 let result = self
                     .index
@@ -51,11 +157,107 @@ impl Service<Request> for ZebraState {
 
                 async move { result }.boxed()
             }
+            Request::FindBlockHashes { known_blocks, stop } => {
+                let hashes = self
+                    .index
+                    .find_chain_blocks(&known_blocks, stop, MAX_FIND_BLOCK_HASHES_RESULTS)
+                    .iter()
+                    .map(|block| block.as_ref().into())
+                    .collect();
+
+                async move { Ok(Response::BlockHashes { hashes }) }.boxed()
+            }
+            Request::FindBlockHeaders { known_blocks, stop } => {
+                let headers = self
+                    .index
+                    .find_chain_blocks(&known_blocks, stop, MAX_FIND_BLOCK_HEADERS_RESULTS)
+                    .iter()
+                    .map(|block| block.header)
+                    .collect();
+
+                async move { Ok(Response::BlockHeaders { headers }) }.boxed()
+            }
+            Request::GetUtxo { outpoint } => {
+                let output = self.index.utxo(&outpoint);
+
+                async move { Ok(Response::Utxo { output }) }.boxed()
+            }
+            Request::AwaitUtxo { outpoint } => {
+                let output = self.index.utxo(&outpoint);
+                self.pending_utxos.prune();
+                let pending = match output {
+                    Some(output) => async move { Ok(output) }.boxed(),
+                    None => self.pending_utxos.queue(outpoint).boxed(),
+                };
+
+                async move {
+                    let output = pending.await?;
+                    Ok(Response::Utxo {
+                        output: Some(output),
+                    })
+                }
+                .boxed()
+            }
+            // The index only has one chain, so every block is in the chain
+            // ending at the anchor tip.
+            Request::ContainsSaplingAnchor { anchor, .. } => {
+                let contains = self.index.contains_sapling_anchor(&anchor);
+
+                async move { Ok(Response::ContainsAnchor { contains }) }.boxed()
+            }
+            Request::GetSaplingTree { hash } => {
+                let tree = self.index.sapling_tree(&hash);
+
+                async move { Ok(Response::SaplingTree { tree }) }.boxed()
+            }
+            Request::GetSproutTree { anchor, .. } => {
+                let tree = self.index.sprout_tree(&anchor);
+
+                async move { Ok(Response::SproutTree { tree }) }.boxed()
+            }
+            Request::ContainsOrchardAnchor { anchor, .. } => {
+                let contains = self.index.contains_orchard_anchor(&anchor);
+
+                async move { Ok(Response::ContainsAnchor { contains }) }.boxed()
+            }
+            Request::GetOrchardTree { hash } => {
+                let tree = self.index.orchard_tree(&hash);
+
+                async move { Ok(Response::OrchardTree { tree }) }.boxed()
+            }
+            Request::ContainsSproutNullifier { nullifier } => {
+                let contains = self.index.contains_nullifier(Pool::Sprout, nullifier.0);
+
+                async move { Ok(Response::ContainsNullifier { contains }) }.boxed()
+            }
+            Request::ContainsSaplingNullifier { nullifier } => {
+                let contains = self.index.contains_nullifier(Pool::Sapling, nullifier.0);
+
+                async move { Ok(Response::ContainsNullifier { contains }) }.boxed()
+            }
+            Request::ContainsOrchardNullifier { nullifier } => {
+                let contains = self.index.contains_nullifier(Pool::Orchard, nullifier.0);
+
+                async move { Ok(Response::ContainsNullifier { contains }) }.boxed()
+            }
+            Request::GetHistoryTree { hash } => {
+                let tree = self.index.history_tree(&hash);
+
+                async move { Ok(Response::HistoryTree { tree }) }.boxed()
+            }
+            Request::GetChainValuePools { hash } => {
+                let pools = self.index.value_pools(&hash);
+
+                async move { Ok(Response::ChainValuePools { pools }) }.boxed()
+            }
         }
     }
 }
 
-pub fn init() -> impl Service<
+/// Returns an in-memory state service for `network`.
+pub fn init(
+    network: Network,
+) -> impl Service<
     Request,
     Response = Response,
     Error = Box<dyn Error + Send + Sync + 'static>,
@@ -63,5 +265,10 @@ pub fn init() -> impl Service<
 > + Send
        + Clone
        + 'static {
-    Buffer::new(ZebraState::default(), 1)
+    let state = ZebraState {
+        index: block_index::BlockIndex::new(network),
+        pending_utxos: PendingUtxos::default(),
+        pending_tips: PendingTips::default(),
+    };
+    Buffer::new(state, 1)
 }
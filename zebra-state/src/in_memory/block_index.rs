@@ -1,19 +1,68 @@
+use crate::{
+    non_finalized::{nullifiers, Pool},
+    note_commitment_trees::NoteCommitmentTrees,
+    HashOrHeight,
+};
 use std::{
-    collections::{btree_map::Entry, BTreeMap, HashMap},
+    collections::{btree_map::Entry, BTreeMap, HashMap, HashSet},
     error::Error,
+    ops::Bound::{Excluded, Unbounded},
     sync::Arc,
 };
 use zebra_chain::{
-    block::{Block, BlockHeaderHash},
-    types::BlockHeight,
+    amount::NonNegative,
+    block::{self, Block},
+    history_tree::HistoryTree,
+    orchard, sapling, sprout,
+    transaction::{self, OutPoint, Transaction, TransparentInput, TransparentOutput},
+    value_balance::ValueBalance,
+    Network,
 };
 #[derive(Default)]
 pub(super) struct BlockIndex {
-    by_hash: HashMap<BlockHeaderHash, Arc<Block>>,
-    by_height: BTreeMap<BlockHeight, Arc<Block>>,
+    /// The network, which decides where the chain history tree starts
+    /// again.
+    network: Network,
+    by_hash: HashMap<block::Hash, Arc<Block>>,
+    by_height: BTreeMap<block::Height, Arc<Block>>,
+    /// The note commitment trees, after the block at `contiguous_height`.
+    note_commitment_trees: NoteCommitmentTrees,
+    /// The note commitment trees after each block, from genesis to
+    /// `contiguous_height`.
+    trees_by_hash: HashMap<block::Hash, NoteCommitmentTrees>,
+    /// The tree roots after each block, and their pools, from genesis to
+    /// `contiguous_height`.
+    anchors: HashSet<(Pool, [u8; 32])>,
+    /// The nullifiers revealed by each block, and their pools, from genesis
+    /// to `contiguous_height`.
+    nullifiers: HashSet<(Pool, [u8; 32])>,
+    /// The chain value pools, after the block at `contiguous_height`.
+    chain_value_pools: ValueBalance<NonNegative>,
+    /// The chain value pools after each block, from genesis to
+    /// `contiguous_height`.
+    ///
+    /// A fork would start from the pools of the block before it, so they are
+    /// kept for every block.
+    value_pools: HashMap<block::Hash, ValueBalance<NonNegative>>,
+    /// The unspent transparent outputs, after the block at
+    /// `contiguous_height`.
+    utxos: HashMap<OutPoint, TransparentOutput>,
+    /// The height of the block, and the index in that block, of each
+    /// transaction, from genesis to `contiguous_height`.
+    tx_by_hash: HashMap<transaction::Hash, (block::Height, usize)>,
+    /// The height of the last block in the chain state, or `None` if the
+    /// genesis block hasn't been added yet.
+    contiguous_height: Option<block::Height>,
 }
 
 impl BlockIndex {
+    pub(super) fn new(network: Network) -> BlockIndex {
+        BlockIndex {
+            network,
+            ..BlockIndex::default()
+        }
+    }
+
     pub(super) fn insert(
         &mut self,
         block: impl Into<Arc<Block>>,
@@ -26,42 +75,250 @@ impl BlockIndex {
             Entry::Vacant(entry) => {
                 let _ = entry.insert(block.clone());
                 let _ = self.by_hash.insert(hash, block);
-                Ok(())
+                self.update_chain_state()
             }
             Entry::Occupied(_) => Err("forks in the chain aren't supported yet")?,
         }
     }
 
-    pub(super) fn get(&mut self, query: impl Into<BlockQuery>) -> Option<Arc<Block>> {
+    pub(super) fn get(&mut self, query: impl Into<HashOrHeight>) -> Option<Arc<Block>> {
         match query.into() {
-            BlockQuery::ByHash(hash) => self.by_hash.get(&hash),
-            BlockQuery::ByHeight(height) => self.by_height.get(&height),
+            HashOrHeight::Hash(hash) => self.by_hash.get(&hash),
+            HashOrHeight::Height(height) => self.by_height.get(&height),
         }
         .cloned()
     }
 
-    pub(super) fn get_tip(&self) -> Option<Arc<Block>> {
+    /// Returns up to `max_len` best chain blocks after the first block in
+    /// `known_blocks` that is in the best chain, ending early at `stop`.
+    ///
+    /// If none of the known blocks are in the best chain, returns the blocks
+    /// after the genesis block.
+    pub(super) fn find_chain_blocks(
+        &self,
+        known_blocks: &[block::Hash],
+        stop: Option<block::Hash>,
+        max_len: usize,
+    ) -> Vec<Arc<Block>> {
+        let start = known_blocks
+            .iter()
+            .filter_map(|hash| self.by_hash.get(hash))
+            .filter_map(|block| block.coinbase_height())
+            .next()
+            .unwrap_or(block::Height(0));
+
+        let mut blocks = Vec::new();
+        for block in self
+            .by_height
+            .range((Excluded(start), Unbounded))
+            .map(|(_, block)| block)
+            .take(max_len)
+        {
+            blocks.push(block.clone());
+            if Some(block::Hash::from(block.as_ref())) == stop {
+                break;
+            }
+        }
+        blocks
+    }
+
+    /// Returns true if `anchor` is the Sapling tree root after any block in
+    /// the contiguous chain from genesis.
+    pub(super) fn contains_sapling_anchor(&self, anchor: &sapling::tree::Root) -> bool {
+        self.anchors.contains(&(Pool::Sapling, anchor.0))
+    }
+
+    /// Returns the Sprout note commitment tree with root `anchor`, if it is
+    /// the root after any block in the contiguous chain from genesis.
+    pub(super) fn sprout_tree(
+        &self,
+        anchor: &sprout::tree::Root,
+    ) -> Option<sprout::tree::NoteCommitmentTree> {
+        if !self.anchors.contains(&(Pool::Sprout, anchor.0)) {
+            return None;
+        }
+        self.trees_by_hash
+            .values()
+            .find(|trees| trees.sprout.root() == *anchor)
+            .map(|trees| trees.sprout.clone())
+    }
+
+    /// Returns true if `anchor` is the Orchard tree root after any block in
+    /// the contiguous chain from genesis.
+    pub(super) fn contains_orchard_anchor(&self, anchor: &orchard::tree::Root) -> bool {
+        self.anchors.contains(&(Pool::Orchard, anchor.0))
+    }
+
+    /// Returns true if a block in the contiguous chain from genesis reveals
+    /// `nullifier` in `pool`.
+    pub(super) fn contains_nullifier(&self, pool: Pool, nullifier: [u8; 32]) -> bool {
+        self.nullifiers.contains(&(pool, nullifier))
+    }
+
+    /// Returns the Sapling note commitment tree after the block with `hash`,
+    /// if it is in the contiguous chain from genesis.
+    pub(super) fn sapling_tree(
+        &self,
+        hash: &block::Hash,
+    ) -> Option<sapling::tree::NoteCommitmentTree> {
+        Some(self.trees_by_hash.get(hash)?.sapling.clone())
+    }
+
+    /// Returns the Orchard note commitment tree after the block with `hash`,
+    /// if it is in the contiguous chain from genesis.
+    pub(super) fn orchard_tree(
+        &self,
+        hash: &block::Hash,
+    ) -> Option<orchard::tree::NoteCommitmentTree> {
+        Some(self.trees_by_hash.get(hash)?.orchard.clone())
+    }
+
+    /// Returns the chain history tree after the block with `hash`, if it is
+    /// in the contiguous chain from genesis.
+    pub(super) fn history_tree(&self, hash: &block::Hash) -> Option<HistoryTree> {
+        Some(self.trees_by_hash.get(hash)?.history.clone())
+    }
+
+    /// Returns the transaction with `hash`, and the height of its block, if
+    /// it is in the contiguous chain from genesis.
+    pub(super) fn transaction(
+        &self,
+        hash: transaction::Hash,
+    ) -> Option<(Arc<Transaction>, block::Height)> {
+        let (height, index) = self.tx_by_hash.get(&hash)?;
+        let transaction = self.by_height.get(height)?.transactions.get(*index)?;
+        Some((transaction.clone(), *height))
+    }
+
+    /// Returns the transparent output at `outpoint`, if it is unspent after
+    /// the last contiguous block.
+    pub(super) fn utxo(&self, outpoint: &OutPoint) -> Option<TransparentOutput> {
+        self.utxos.get(outpoint).cloned()
+    }
+
+    /// Returns the chain value pools after the block with `hash`, if it is
+    /// in the contiguous chain from genesis.
+    pub(super) fn value_pools(&self, hash: &block::Hash) -> Option<ValueBalance<NonNegative>> {
+        self.value_pools.get(hash).copied()
+    }
+
+    /// Updates the note commitment trees, value pools, and unspent outputs
+    /// with each block after `contiguous_height`, until there is a gap in the
+    /// chain.
+    ///
+    /// Blocks can be added out of order, so they are only applied once all
+    /// the previous blocks are in the index. If a block can't be applied, it
+    /// is removed from the index.
+    fn update_chain_state(&mut self) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        loop {
+            let next = match self.contiguous_height {
+                None => block::Height(0),
+                Some(height) => block::Height(height.0 + 1),
+            };
+            let block = match self.by_height.get(&next) {
+                Some(block) => block.clone(),
+                None => return Ok(()),
+            };
+
+            if let Err(error) = self.apply_block(&block) {
+                let _ = self.by_height.remove(&next);
+                let _ = self.by_hash.remove(&block.hash());
+                return Err(error);
+            }
+            self.contiguous_height = Some(next);
+        }
+    }
+
+    /// Applies `block` to the chain state after `contiguous_height`.
+    ///
+    /// Returns an error, and leaves the chain state unchanged, if the block
+    /// spends a missing output, has an invalid note commitment or overflows a
+    /// note commitment tree, or makes a value pool negative.
+    fn apply_block(&mut self, block: &Block) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let mut trees = self.note_commitment_trees.clone();
+        trees.append_block(self.network, block)?;
+
+        // Outputs can be spent by later transactions in the same block.
+        let mut pools = self.chain_value_pools;
+        let mut created = HashMap::new();
+        let mut spent = HashSet::new();
+        // The genesis coinbase can't be spent, so it isn't in the pools.
+        let is_genesis = self.contiguous_height.is_none();
+        for transaction in block.transactions.iter().filter(|_| !is_genesis) {
+            let mut spent_outputs = HashMap::new();
+            for input in transaction.inputs() {
+                let outpoint = match input {
+                    TransparentInput::PrevOut { outpoint, .. } => *outpoint,
+                    TransparentInput::Coinbase { .. } => continue,
+                };
+                let output = match created.remove(&outpoint) {
+                    Some(output) => output,
+                    None if spent.insert(outpoint) => {
+                        self.utxos.get(&outpoint).cloned().ok_or_else(|| {
+                            format!("block spends a missing output {:?}", outpoint)
+                        })?
+                    }
+                    None => {
+                        return Err(format!("block spends {:?} more than once", outpoint).into())
+                    }
+                };
+                let _ = spent_outputs.insert(outpoint, output);
+            }
+
+            pools = pools.add_transaction(transaction.value_balance(&spent_outputs)?)?;
+
+            let hash = transaction::Hash::from(transaction.as_ref());
+            for (index, output) in transaction.outputs().enumerate() {
+                let index = index as u32;
+                let _ = created.insert(OutPoint { hash, index }, output.clone());
+            }
+        }
+
+        for outpoint in spent {
+            let _ = self.utxos.remove(&outpoint);
+        }
+        self.utxos.extend(created);
+
+        let height = block
+            .coinbase_height()
+            .expect("blocks in the index have a coinbase height");
+        for (index, transaction) in block.transactions.iter().enumerate() {
+            let hash = transaction::Hash::from(transaction.as_ref());
+            let _ = self.tx_by_hash.insert(hash, (height, index));
+        }
+
+        let hash = block.hash();
+        self.anchors.extend(trees.anchors());
+        self.nullifiers.extend(nullifiers(block));
+        let _ = self.trees_by_hash.insert(hash, trees.clone());
+        self.note_commitment_trees = trees;
+        let _ = self.value_pools.insert(hash, pools);
+        self.chain_value_pools = pools;
+
+        Ok(())
+    }
+
+    /// Returns the height and hash of the highest block in the index.
+    pub(super) fn tip(&self) -> Option<(block::Height, block::Hash)> {
         self.by_height
             .iter()
             .next_back()
-            .map(|(_key, value)| value)
-            .cloned()
+            .map(|(height, block)| (*height, block.hash()))
     }
-}
-
-pub(super) enum BlockQuery {
-    ByHash(BlockHeaderHash),
-    ByHeight(BlockHeight),
-}
 
-impl From<BlockHeaderHash> for BlockQuery {
-    fn from(hash: BlockHeaderHash) -> Self {
-        Self::ByHash(hash)
+    /// Returns the number of blocks above the block with `hash`, if it is in
+    /// the index.
+    pub(super) fn depth(&self, hash: block::Hash) -> Option<u32> {
+        let height = self.by_hash.get(&hash)?.coinbase_height()?;
+        let (tip_height, _) = self.tip()?;
+        Some(tip_height.0 - height.0)
     }
-}
 
-impl From<BlockHeight> for BlockQuery {
-    fn from(height: BlockHeight) -> Self {
-        Self::ByHeight(height)
+    pub(super) fn get_tip(&self) -> Option<Arc<Block>> {
+        self.by_height
+            .iter()
+            .next_back()
+            .map(|(_key, value)| value)
+            .cloned()
     }
 }
@@ -2,23 +2,436 @@
 #![doc(html_root_url = "https://doc.zebra.zfnd.org/zebra_state")]
 #![allow(clippy::try_err)]
 use std::sync::Arc;
-use zebra_chain::block::{Block, BlockHeaderHash};
+use zebra_chain::{
+    amount::{Amount, NonNegative},
+    block::{
+        self,
+        filter::{BlockFilter, FilterHeader},
+        Block,
+    },
+    history_tree::HistoryTree,
+    orchard, sapling, sprout,
+    transaction::{self, OutPoint, Transaction, TransparentOutput},
+    transparent::Address,
+    value_balance::ValueBalance,
+};
+
+mod config;
 
 pub mod in_memory;
+mod non_finalized;
+mod note_commitment_trees;
+pub mod on_disk;
+mod pending_tips;
+mod pending_utxos;
+mod queued_blocks;
+
+pub use config::Config;
+
+/// A block identifier, for queries that accept either.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HashOrHeight {
+    Hash(block::Hash),
+    Height(block::Height),
+}
+
+impl From<block::Hash> for HashOrHeight {
+    fn from(hash: block::Hash) -> Self {
+        Self::Hash(hash)
+    }
+}
+
+impl From<block::Height> for HashOrHeight {
+    fn from(height: block::Height) -> Self {
+        Self::Height(height)
+    }
+}
+
+/// The maximum number of hashes returned by `FindBlockHashes`, matching the
+/// `getblocks` limit.
+pub(crate) const MAX_FIND_BLOCK_HASHES_RESULTS: usize = 500;
+
+/// The maximum number of headers returned by `FindBlockHeaders`, matching the
+/// `getheaders` limit.
+pub(crate) const MAX_FIND_BLOCK_HEADERS_RESULTS: usize = 2000;
+
+/// The maximum number of blocks in a `CompactFilters` request, matching the
+/// BIP157 `getcfheaders` limit.
+pub(crate) const MAX_COMPACT_FILTERS_RESULTS: u32 = 2000;
+
+/// The number of blocks between the filter headers returned by
+/// `CompactFilterCheckpoints`, matching the BIP157 `getcfcheckpt` interval.
+pub(crate) const COMPACT_FILTER_CHECKPOINT_INTERVAL: u32 = 1000;
+
+/// The basic filters of a range of best chain blocks.
+#[derive(Clone, Debug)]
+pub struct CompactFilters {
+    /// The filter header of the block before the range, which is all zeroes
+    /// if the range starts at the genesis block.
+    pub previous: FilterHeader,
+    /// The hash, basic filter, and filter header of each block in the range.
+    pub filters: Vec<(block::Hash, BlockFilter, FilterHeader)>,
+}
+
+/// The number of consecutive blocks at the start of a block locator, before
+/// the gaps start doubling.
+const BLOCK_LOCATOR_DENSE_BLOCKS: u32 = 10;
+
+/// Returns the heights in a block locator for a chain with `tip`.
+///
+/// The locator starts at the tip, and includes each of the blocks below it,
+/// until the gaps between blocks start doubling. It always ends at the
+/// genesis block, so peers can find a common ancestor even after a long fork.
+pub(crate) fn block_locator_heights(tip: block::Height) -> Vec<block::Height> {
+    let mut heights = Vec::new();
+    let mut height = tip.0;
+    let mut step = 1;
+
+    while height > 0 {
+        heights.push(block::Height(height));
+        if heights.len() as u32 >= BLOCK_LOCATOR_DENSE_BLOCKS {
+            step *= 2;
+        }
+        height = height.saturating_sub(step);
+    }
+    heights.push(block::Height(0));
+
+    heights
+}
 
 #[derive(Debug)]
 pub enum Request {
+    /// Add a block to the state.
+    ///
+    /// Blocks can arrive before their parent, and wait until it is added.
     // TODO(jlusby): deprecate in the future based on our validation story
-    AddBlock { block: Arc<Block> },
-    GetBlock { hash: BlockHeaderHash },
+    AddBlock {
+        block: Arc<Block>,
+    },
+    /// Commit a block that can't be rolled back to the finalized state.
+    ///
+    /// The block must be the child of the current tip.
+    CommitFinalizedBlock {
+        block: Arc<Block>,
+    },
+    GetBlock {
+        hash: block::Hash,
+    },
     GetTip,
+    /// Get the height and hash of the best chain tip, if there are any
+    /// blocks in the state.
+    Tip,
+    /// Get the best chain tip, like `Tip`, once its hash is different from
+    /// `tip`.
+    ///
+    /// Returns straight away if the tip has already changed, otherwise waits
+    /// until a committed block changes the tip.
+    AwaitTipChange {
+        tip: Option<block::Hash>,
+    },
+    /// Get the number of blocks on top of the block with `hash`, if it is in
+    /// the best chain.
+    ///
+    /// The tip has depth 0.
+    Depth {
+        hash: block::Hash,
+    },
+    /// Get the hash of the best chain block at `height`.
+    BestChainBlockHash {
+        height: block::Height,
+    },
+    /// Get the block with `hash_or_height`.
+    ///
+    /// Looks in the non-finalized state first, then the finalized state.
+    /// Hashes can be in any non-finalized chain, but heights are always in
+    /// the best chain.
+    Block {
+        hash_or_height: HashOrHeight,
+    },
+    /// Like `Block`, but only returns the block header.
+    BlockHeader {
+        hash_or_height: HashOrHeight,
+    },
+    /// Get the best chain transaction with `hash`, and the height of the
+    /// block that contains it.
+    Transaction {
+        hash: transaction::Hash,
+    },
+    /// Get the total value of the unspent outputs that pay to `address`.
+    ///
+    /// Fails if the address index is disabled, or is missing blocks.
+    AddressBalance {
+        address: Address,
+    },
+    /// Get the unspent outputs that pay to `address`.
+    ///
+    /// Fails if the address index is disabled, or is missing blocks.
+    AddressUtxos {
+        address: Address,
+    },
+    /// Get the transactions that spend from or pay to `address`, in chain
+    /// order.
+    ///
+    /// Fails if the address index is disabled, or is missing blocks.
+    AddressTxIds {
+        address: Address,
+    },
+    /// Get the BIP158 basic filters of the best chain blocks from
+    /// `start_height` up to and including `stop`.
+    ///
+    /// Returns `None` if `stop` isn't in the best chain, or is below
+    /// `start_height`. Fails if the compact filter index is disabled, or is
+    /// missing blocks, or if there are more than 2000 blocks in the range.
+    CompactFilters {
+        start_height: block::Height,
+        stop: block::Hash,
+    },
+    /// Get the basic filter headers of the best chain blocks at every 1000th
+    /// height, up to and including `stop`.
+    ///
+    /// Returns `None` if `stop` isn't in the best chain. Fails if the compact
+    /// filter index is disabled, or is missing blocks.
+    CompactFilterCheckpoints {
+        stop: block::Hash,
+    },
+    /// Get the transparent output at `outpoint`, if it is unspent.
+    GetUtxo {
+        outpoint: OutPoint,
+    },
+    /// Wait for the transparent output at `outpoint`.
+    ///
+    /// Returns the output as soon as a block that creates it is committed,
+    /// or immediately if it is already unspent. Fails if the output doesn't
+    /// arrive before the lookup timeout.
+    ///
+    /// Blocks are verified out of order, so a block can spend outputs from
+    /// blocks that haven't been committed yet.
+    AwaitUtxo {
+        outpoint: OutPoint,
+    },
+    /// Find the hashes of the best chain blocks after the first block in
+    /// `known_blocks` that is in the best chain, like a `getblocks` request.
+    ///
+    /// Returns up to 500 hashes, or fewer if the block with hash `stop` is
+    /// reached first. If no known block is in the best chain, starts after
+    /// the genesis block.
+    FindBlockHashes {
+        known_blocks: Vec<block::Hash>,
+        stop: Option<block::Hash>,
+    },
+    /// Like `FindBlockHashes`, but returns up to 2000 block headers, like a
+    /// `getheaders` request.
+    FindBlockHeaders {
+        known_blocks: Vec<block::Hash>,
+        stop: Option<block::Hash>,
+    },
+    /// Check whether `anchor` is the Sapling note commitment tree root at
+    /// the end of a block in the chain ending at `tip`.
+    ///
+    /// Sapling spends must use one of these roots as their anchor. `tip` is
+    /// the parent of the block containing the spend, or `None` for the best
+    /// chain, so anchors from other forks are rejected. Returns an error if
+    /// `tip` isn't in the state.
+    ContainsSaplingAnchor {
+        anchor: sapling::tree::Root,
+        tip: Option<block::Hash>,
+    },
+    /// Get the Sapling note commitment tree after the block with `hash`.
+    GetSaplingTree {
+        hash: block::Hash,
+    },
+    /// Get the Sprout note commitment tree with root `anchor`, if it is the
+    /// root at the end of a block in the chain ending at `tip`.
+    ///
+    /// JoinSplits must use one of these roots as their anchor, or the root
+    /// after an earlier JoinSplit in their transaction, so the tree is
+    /// needed to compute the later roots. `tip` is used like it is in
+    /// `ContainsSaplingAnchor`.
+    GetSproutTree {
+        anchor: sprout::tree::Root,
+        tip: Option<block::Hash>,
+    },
+    /// Check whether `anchor` is the Orchard note commitment tree root at
+    /// the end of a block in the chain ending at `tip`.
+    ///
+    /// Orchard actions must use one of these roots as their anchor. `tip` is
+    /// used like it is in `ContainsSaplingAnchor`.
+    ContainsOrchardAnchor {
+        anchor: orchard::tree::Root,
+        tip: Option<block::Hash>,
+    },
+    /// Get the Orchard note commitment tree after the block with `hash`.
+    GetOrchardTree {
+        hash: block::Hash,
+    },
+    /// Check whether a JoinSplit in the best chain reveals the Sprout
+    /// `nullifier`.
+    ///
+    /// Each nullifier can only be revealed once, so the mempool uses this to
+    /// reject shielded double-spends.
+    ContainsSproutNullifier {
+        nullifier: sprout::Nullifier,
+    },
+    /// Like `ContainsSproutNullifier`, for a Sapling spend.
+    ContainsSaplingNullifier {
+        nullifier: sapling::Nullifier,
+    },
+    /// Like `ContainsSproutNullifier`, for an Orchard action.
+    ContainsOrchardNullifier {
+        nullifier: orchard::Nullifier,
+    },
+    /// Get the chain history tree after the block with `hash`.
+    ///
+    /// The next block's commitment field commits to its root.
+    GetHistoryTree {
+        hash: block::Hash,
+    },
+    /// Get the total value in each chain value pool, after the block with
+    /// `hash`.
+    GetChainValuePools {
+        hash: block::Hash,
+    },
+    /// Get a block locator for the best chain, from the tip back to the
+    /// genesis block, for `getblocks` and `getheaders` requests.
+    BlockLocator,
+    /// Check the `depth` finalized blocks below and including the tip for
+    /// corruption, or the whole finalized state if `depth` is `None`.
+    CheckIntegrity {
+        depth: Option<u32>,
+    },
+    /// Write every committed block to disk, so a shutdown doesn't lose
+    /// them.
+    Flush,
+    /// Commit every block in the best chain to the finalized state, and drop
+    /// the other chains, so the whole chain is kept after a restart.
+    ///
+    /// Only use this when nothing else is adding blocks, because the
+    /// committed blocks can't be rolled back by a later reorg.
+    FinalizeBestChain,
+}
+
+impl Request {
+    /// Returns the name of this kind of request, for metrics.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Request::AddBlock { .. } => "add_block",
+            Request::CommitFinalizedBlock { .. } => "commit_finalized_block",
+            Request::GetBlock { .. } => "get_block",
+            Request::GetTip => "get_tip",
+            Request::Tip => "tip",
+            Request::AwaitTipChange { .. } => "await_tip_change",
+            Request::Depth { .. } => "depth",
+            Request::BestChainBlockHash { .. } => "best_chain_block_hash",
+            Request::Block { .. } => "block",
+            Request::BlockHeader { .. } => "block_header",
+            Request::Transaction { .. } => "transaction",
+            Request::AddressBalance { .. } => "address_balance",
+            Request::AddressUtxos { .. } => "address_utxos",
+            Request::AddressTxIds { .. } => "address_tx_ids",
+            Request::CompactFilters { .. } => "compact_filters",
+            Request::CompactFilterCheckpoints { .. } => "compact_filter_checkpoints",
+            Request::GetUtxo { .. } => "get_utxo",
+            Request::AwaitUtxo { .. } => "await_utxo",
+            Request::FindBlockHashes { .. } => "find_block_hashes",
+            Request::FindBlockHeaders { .. } => "find_block_headers",
+            Request::ContainsSaplingAnchor { .. } => "contains_sapling_anchor",
+            Request::GetSaplingTree { .. } => "get_sapling_tree",
+            Request::GetSproutTree { .. } => "get_sprout_tree",
+            Request::ContainsOrchardAnchor { .. } => "contains_orchard_anchor",
+            Request::GetOrchardTree { .. } => "get_orchard_tree",
+            Request::ContainsSproutNullifier { .. } => "contains_sprout_nullifier",
+            Request::ContainsSaplingNullifier { .. } => "contains_sapling_nullifier",
+            Request::ContainsOrchardNullifier { .. } => "contains_orchard_nullifier",
+            Request::GetHistoryTree { .. } => "get_history_tree",
+            Request::GetChainValuePools { .. } => "get_chain_value_pools",
+            Request::BlockLocator => "block_locator",
+            Request::CheckIntegrity { .. } => "check_integrity",
+            Request::Flush => "flush",
+            Request::FinalizeBestChain => "finalize_best_chain",
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum Response {
     Added,
-    Block { block: Arc<Block> },
-    Tip { hash: BlockHeaderHash },
+    Committed {
+        hash: block::Hash,
+    },
+    Block {
+        block: Arc<Block>,
+    },
+    Tip {
+        hash: block::Hash,
+    },
+    BlockHeader {
+        header: block::Header,
+    },
+    BestTip {
+        tip: Option<(block::Height, block::Hash)>,
+    },
+    Depth {
+        depth: Option<u32>,
+    },
+    BlockHash {
+        hash: Option<block::Hash>,
+    },
+    BlockHashes {
+        hashes: Vec<block::Hash>,
+    },
+    BlockHeaders {
+        headers: Vec<block::Header>,
+    },
+    ContainsAnchor {
+        contains: bool,
+    },
+    ContainsNullifier {
+        contains: bool,
+    },
+    SproutTree {
+        tree: Option<sprout::tree::NoteCommitmentTree>,
+    },
+    SaplingTree {
+        tree: Option<sapling::tree::NoteCommitmentTree>,
+    },
+    OrchardTree {
+        tree: Option<orchard::tree::NoteCommitmentTree>,
+    },
+    HistoryTree {
+        tree: Option<HistoryTree>,
+    },
+    Transaction {
+        transaction: Option<(Arc<Transaction>, block::Height)>,
+    },
+    Utxo {
+        output: Option<TransparentOutput>,
+    },
+    AddressBalance {
+        balance: Amount<NonNegative>,
+    },
+    AddressUtxos {
+        utxos: Vec<(OutPoint, TransparentOutput)>,
+    },
+    AddressTxIds {
+        txids: Vec<(block::Height, transaction::Hash)>,
+    },
+    CompactFilters {
+        filters: Option<CompactFilters>,
+    },
+    CompactFilterCheckpoints {
+        headers: Option<Vec<FilterHeader>>,
+    },
+    ChainValuePools {
+        pools: Option<ValueBalance<NonNegative>>,
+    },
+    Checked,
+    Flushed,
+    Finalized {
+        tip: Option<(block::Height, block::Hash)>,
+    },
+    BlockLocator {
+        hashes: Vec<block::Hash>,
+    },
 }
 
 #[cfg(test)]
@@ -27,7 +440,7 @@ mod tests {
     use color_eyre::Report;
     use eyre::{bail, ensure, eyre};
     use tower::Service;
-    use zebra_chain::serialization::ZcashDeserialize;
+    use zebra_chain::{serialization::ZcashDeserialize, Network};
 
     fn install_tracing() {
         use tracing_error::ErrorLayer;
@@ -52,7 +465,7 @@ mod tests {
             Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_415000_BYTES[..])?.into();
         let hash = block.as_ref().into();
 
-        let mut service = in_memory::init();
+        let mut service = in_memory::init(Network::Mainnet);
 
         let response = service
             .call(Request::AddBlock {
@@ -92,9 +505,9 @@ mod tests {
         let block1: Arc<_> =
             Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.into();
 
-        let expected_hash: BlockHeaderHash = block1.as_ref().into();
+        let expected_hash: block::Hash = block1.as_ref().into();
 
-        let mut service = in_memory::init();
+        let mut service = in_memory::init(Network::Mainnet);
 
         /// insert the higher block first
         let response = service
@@ -132,4 +545,1560 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn find_block_hashes() -> Result<(), Report> {
+        let block0: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.into();
+        let block1: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.into();
+
+        let hash0: block::Hash = block0.as_ref().into();
+        let hash1: block::Hash = block1.as_ref().into();
+
+        let mut service = in_memory::init(Network::Mainnet);
+        for block in vec![block0, block1.clone()] {
+            service
+                .call(Request::AddBlock { block })
+                .await
+                .map_err(|e| eyre!(e))?;
+        }
+
+        // An unknown hash is skipped, and the walk starts after the first
+        // known block.
+        let response = service
+            .call(Request::FindBlockHashes {
+                known_blocks: vec![block::Hash([0xff; 32]), hash0],
+                stop: None,
+            })
+            .await
+            .map_err(|e| eyre!(e))?;
+        match response {
+            Response::BlockHashes { hashes } => assert_eq!(hashes, vec![hash1]),
+            _ => bail!("unexpected response kind: {:?}", response),
+        }
+
+        let response = service
+            .call(Request::FindBlockHeaders {
+                known_blocks: vec![hash1],
+                stop: None,
+            })
+            .await
+            .map_err(|e| eyre!(e))?;
+        match response {
+            Response::BlockHeaders { headers } => assert!(headers.is_empty()),
+            _ => bail!("unexpected response kind: {:?}", response),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn contains_sapling_anchor() -> Result<(), Report> {
+        use zebra_chain::sapling::tree::{NoteCommitmentTree, Root};
+
+        let block1: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.into();
+        let block0: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.into();
+
+        // The anchors are computed when the missing blocks are added.
+        let mut service = in_memory::init(Network::Mainnet);
+        for block in vec![block1, block0] {
+            service
+                .call(Request::AddBlock { block })
+                .await
+                .map_err(|e| eyre!(e))?;
+        }
+
+        // The early blocks don't have any Sapling outputs.
+        let empty_root = NoteCommitmentTree::default().root();
+        for (anchor, expected) in &[(empty_root, true), (Root([0xff; 32]), false)] {
+            let response = service
+                .call(Request::ContainsSaplingAnchor {
+                    anchor: *anchor,
+                    tip: None,
+                })
+                .await
+                .map_err(|e| eyre!(e))?;
+            match response {
+                Response::ContainsAnchor { contains } => assert_eq!(contains, *expected),
+                _ => bail!("unexpected response kind: {:?}", response),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chain_value_pools() -> Result<(), Report> {
+        use zebra_chain::amount::Amount;
+
+        let block0: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.into();
+        let block1: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.into();
+        let hash1 = block1.hash();
+
+        let mut service = in_memory::init(Network::Mainnet);
+        for block in vec![block0, block1.clone()] {
+            service
+                .call(Request::AddBlock { block })
+                .await
+                .map_err(|e| eyre!(e))?;
+        }
+
+        // The genesis coinbase isn't in the pools, so they only contain the
+        // block 1 coinbase outputs.
+        let expected: Amount<NonNegative> = block1.transactions[0]
+            .outputs()
+            .map(|output| output.value)
+            .sum::<Result<_, _>>()?;
+
+        let response = service
+            .call(Request::GetChainValuePools { hash: hash1 })
+            .await
+            .map_err(|e| eyre!(e))?;
+        match response {
+            Response::ChainValuePools { pools: Some(pools) } => {
+                ensure!(
+                    pools == ValueBalance::from_transparent_amount(expected),
+                    "unexpected pools: {:?}",
+                    pools
+                );
+            }
+            _ => bail!("unexpected response kind: {:?}", response),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn finalized_state_persists() -> Result<(), Report> {
+        use tower::ServiceExt;
+
+        let block0: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.into();
+        let block1: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.into();
+        let hash1 = block1.hash();
+
+        let cache_dir = tempdir::TempDir::new("zebra_state_finalized")?;
+        let config = Config {
+            cache_dir: cache_dir.path().to_owned(),
+            ..Config::default()
+        };
+
+        {
+            let mut service =
+                on_disk::init(config.clone(), Network::Mainnet).map_err(|e| eyre!(e))?;
+
+            // Blocks must be committed in order.
+            let response = service
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(Request::CommitFinalizedBlock {
+                    block: block1.clone(),
+                })
+                .await;
+            ensure!(
+                response.is_err(),
+                "block 1 can't be committed before genesis"
+            );
+
+            for block in vec![block0, block1.clone()] {
+                let hash = block.hash();
+                let response = service
+                    .ready_and()
+                    .await
+                    .map_err(|e| eyre!(e))?
+                    .call(Request::CommitFinalizedBlock { block })
+                    .await
+                    .map_err(|e| eyre!(e))?;
+                match response {
+                    Response::Committed { hash: committed } => {
+                        ensure!(committed == hash, "wrong hash")
+                    }
+                    _ => bail!("unexpected response kind: {:?}", response),
+                }
+            }
+        }
+
+        // The blocks and outputs are still there after the state is reopened.
+        let mut service = on_disk::init(config, Network::Mainnet).map_err(|e| eyre!(e))?;
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::GetTip)
+            .await
+            .map_err(|e| eyre!(e))?;
+        match response {
+            Response::Tip { hash } => ensure!(hash == hash1, "the tip should be block 1"),
+            _ => bail!("unexpected response kind: {:?}", response),
+        }
+
+        // The early blocks don't have any Sapling outputs.
+        let empty_root = sapling::tree::NoteCommitmentTree::default().root();
+        for (anchor, expected) in &[(empty_root, true), (sapling::tree::Root([0xff; 32]), false)] {
+            let response = service
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(Request::ContainsSaplingAnchor {
+                    anchor: *anchor,
+                    tip: None,
+                })
+                .await
+                .map_err(|e| eyre!(e))?;
+            match response {
+                Response::ContainsAnchor { contains } => {
+                    ensure!(contains == *expected, "wrong anchor")
+                }
+                _ => bail!("unexpected response kind: {:?}", response),
+            }
+        }
+
+        // Each pool has its own anchors, even if their bytes are the same.
+        let empty_orchard_root = orchard::tree::NoteCommitmentTree::default().root();
+        for (anchor, expected) in &[
+            (empty_orchard_root, true),
+            (orchard::tree::Root(empty_root.0), false),
+        ] {
+            let response = service
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(Request::ContainsOrchardAnchor {
+                    anchor: *anchor,
+                    tip: None,
+                })
+                .await
+                .map_err(|e| eyre!(e))?;
+            match response {
+                Response::ContainsAnchor { contains } => {
+                    ensure!(contains == *expected, "wrong Orchard anchor")
+                }
+                _ => bail!("unexpected response kind: {:?}", response),
+            }
+        }
+
+        // Sprout anchors are looked up with their tree.
+        let empty_sprout_tree = sprout::tree::NoteCommitmentTree::default();
+        for (anchor, expected) in &[
+            (empty_sprout_tree.root(), Some(empty_sprout_tree.clone())),
+            (sprout::tree::Root(empty_root.0), None),
+        ] {
+            let response = service
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(Request::GetSproutTree {
+                    anchor: *anchor,
+                    tip: None,
+                })
+                .await
+                .map_err(|e| eyre!(e))?;
+            match response {
+                Response::SproutTree { tree } => ensure!(tree == *expected, "wrong Sprout tree"),
+                _ => bail!("unexpected response kind: {:?}", response),
+            }
+        }
+
+        // The chain history tree starts at Heartwood activation.
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::GetHistoryTree { hash: hash1 })
+            .await
+            .map_err(|e| eyre!(e))?;
+        match response {
+            Response::HistoryTree { tree: Some(tree) } => {
+                ensure!(tree.is_empty(), "the history tree should be empty")
+            }
+            _ => bail!("unexpected response kind: {:?}", response),
+        }
+
+        // The early blocks don't reveal any nullifiers.
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::ContainsSaplingNullifier {
+                nullifier: sapling::Nullifier([0; 32]),
+            })
+            .await
+            .map_err(|e| eyre!(e))?;
+        match response {
+            Response::ContainsNullifier { contains } => {
+                ensure!(!contains, "the nullifier isn't in the chain")
+            }
+            _ => bail!("unexpected response kind: {:?}", response),
+        }
+
+        let outpoint = OutPoint {
+            hash: block1.transactions[0].as_ref().into(),
+            index: 0,
+        };
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::GetUtxo { outpoint })
+            .await
+            .map_err(|e| eyre!(e))?;
+        match response {
+            Response::Utxo {
+                output: Some(output),
+            } => ensure!(
+                &output == block1.transactions[0].outputs().next().unwrap(),
+                "wrong output"
+            ),
+            _ => bail!("unexpected response kind: {:?}", response),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn best_chain_is_finalized_on_request() -> Result<(), Report> {
+        use tower::ServiceExt;
+
+        let block0: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.into();
+        let block1: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.into();
+        let hash1 = block1.hash();
+
+        let cache_dir = tempdir::TempDir::new("zebra_state_finalize_best_chain")?;
+        let config = Config {
+            cache_dir: cache_dir.path().to_owned(),
+            ..Config::default()
+        };
+
+        {
+            let mut service =
+                on_disk::init(config.clone(), Network::Mainnet).map_err(|e| eyre!(e))?;
+            for block in vec![block0, block1] {
+                service
+                    .ready_and()
+                    .await
+                    .map_err(|e| eyre!(e))?
+                    .call(Request::AddBlock { block })
+                    .await
+                    .map_err(|e| eyre!(e))?;
+            }
+
+            let response = service
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(Request::FinalizeBestChain)
+                .await
+                .map_err(|e| eyre!(e))?;
+            match response {
+                Response::Finalized { tip } => ensure!(
+                    tip == Some((block::Height(1), hash1)),
+                    "the whole chain should be finalized"
+                ),
+                _ => bail!("unexpected response kind: {:?}", response),
+            }
+        }
+
+        // The non-finalized blocks would be lost without the request.
+        let mut service = on_disk::init(config, Network::Mainnet).map_err(|e| eyre!(e))?;
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::GetTip)
+            .await
+            .map_err(|e| eyre!(e))?;
+        match response {
+            Response::Tip { hash } => ensure!(hash == hash1, "the tip should be block 1"),
+            _ => bail!("unexpected response kind: {:?}", response),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn await_utxo_waits_for_the_block() -> Result<(), Report> {
+        use tower::ServiceExt;
+
+        let block0: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.into();
+        let block1: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.into();
+        let outpoint = OutPoint {
+            hash: block1.transactions[0].as_ref().into(),
+            index: 0,
+        };
+
+        let mut service = in_memory::init(Network::Mainnet);
+        service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::AddBlock { block: block0 })
+            .await
+            .map_err(|e| eyre!(e))?;
+
+        // The output isn't in the state yet, so the request waits.
+        let pending = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::AwaitUtxo { outpoint });
+
+        service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::AddBlock {
+                block: block1.clone(),
+            })
+            .await
+            .map_err(|e| eyre!(e))?;
+
+        match pending.await.map_err(|e| eyre!(e))? {
+            Response::Utxo {
+                output: Some(output),
+            } => ensure!(
+                &output == block1.transactions[0].outputs().next().unwrap(),
+                "wrong output"
+            ),
+            response => bail!("unexpected response kind: {:?}", response),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn await_tip_change_waits_for_a_commit() -> Result<(), Report> {
+        use tower::ServiceExt;
+
+        let block0: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.into();
+        let hash0 = block0.hash();
+
+        let cache_dir = tempdir::TempDir::new("zebra_state_tip_change")?;
+        let config = Config {
+            cache_dir: cache_dir.path().to_owned(),
+            ..Config::default()
+        };
+        let mut service = on_disk::init(config, Network::Mainnet).map_err(|e| eyre!(e))?;
+
+        // The state is empty, so the request waits for the first block.
+        let pending = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::AwaitTipChange { tip: None });
+
+        service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::AddBlock { block: block0 })
+            .await
+            .map_err(|e| eyre!(e))?;
+
+        match pending.await.map_err(|e| eyre!(e))? {
+            Response::BestTip { tip } => {
+                ensure!(tip == Some((block::Height(0), hash0)), "wrong tip")
+            }
+            response => bail!("unexpected response kind: {:?}", response),
+        }
+
+        // A request that doesn't know about the new tip returns straight away.
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::AwaitTipChange { tip: None })
+            .await
+            .map_err(|e| eyre!(e))?;
+        match response {
+            Response::BestTip { tip } => {
+                ensure!(tip == Some((block::Height(0), hash0)), "wrong tip")
+            }
+            response => bail!("unexpected response kind: {:?}", response),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn blocks_by_hash_or_height() -> Result<(), Report> {
+        use tower::ServiceExt;
+
+        let block0: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.into();
+        let block1: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.into();
+
+        let cache_dir = tempdir::TempDir::new("zebra_state_queries")?;
+        let config = Config {
+            cache_dir: cache_dir.path().to_owned(),
+            ..Config::default()
+        };
+        let mut service = on_disk::init(config, Network::Mainnet).map_err(|e| eyre!(e))?;
+
+        // Genesis is finalized, and block 1 is in the non-finalized chain.
+        let requests = vec![
+            Request::CommitFinalizedBlock {
+                block: block0.clone(),
+            },
+            Request::AddBlock {
+                block: block1.clone(),
+            },
+        ];
+        for request in requests {
+            service
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(request)
+                .await
+                .map_err(|e| eyre!(e))?;
+        }
+
+        for (block, hash_or_height) in &[
+            (&block0, HashOrHeight::Height(block::Height(0))),
+            (&block0, block0.hash().into()),
+            (&block1, HashOrHeight::Height(block::Height(1))),
+            (&block1, block1.hash().into()),
+        ] {
+            let hash_or_height = *hash_or_height;
+            let response = service
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(Request::Block { hash_or_height })
+                .await
+                .map_err(|e| eyre!(e))?;
+            match response {
+                Response::Block { block: found } => ensure!(&&found == block, "wrong block"),
+                _ => bail!("unexpected response kind: {:?}", response),
+            }
+
+            let response = service
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(Request::BlockHeader { hash_or_height })
+                .await
+                .map_err(|e| eyre!(e))?;
+            match response {
+                Response::BlockHeader { header } => {
+                    ensure!(header == block.header, "wrong header")
+                }
+                _ => bail!("unexpected response kind: {:?}", response),
+            }
+        }
+
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::Block {
+                hash_or_height: block::Height(2).into(),
+            })
+            .await;
+        ensure!(response.is_err(), "block 2 isn't in the state");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn transactions_by_hash() -> Result<(), Report> {
+        let block0: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.into();
+        let block1: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.into();
+        let coinbase = block1.transactions[0].clone();
+
+        let mut service = in_memory::init(Network::Mainnet);
+        for block in vec![block0, block1] {
+            service
+                .call(Request::AddBlock { block })
+                .await
+                .map_err(|e| eyre!(e))?;
+        }
+
+        let response = service
+            .call(Request::Transaction {
+                hash: coinbase.as_ref().into(),
+            })
+            .await
+            .map_err(|e| eyre!(e))?;
+        match response {
+            Response::Transaction {
+                transaction: Some((transaction, height)),
+            } => {
+                ensure!(transaction == coinbase, "wrong transaction");
+                ensure!(height == block::Height(1), "wrong height");
+            }
+            _ => bail!("unexpected response kind: {:?}", response),
+        }
+
+        let response = service
+            .call(Request::Transaction {
+                hash: transaction::Hash([0; 32]),
+            })
+            .await
+            .map_err(|e| eyre!(e))?;
+        ensure!(
+            matches!(response, Response::Transaction { transaction: None }),
+            "unknown transactions aren't found"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn address_index() -> Result<(), Report> {
+        use tower::ServiceExt;
+
+        let block0: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.into();
+        let block1: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.into();
+
+        // Block 1 has a founders' reward output, which pays to a P2SH address.
+        let coinbase = block1.transactions[0].clone();
+        let (index, output) = coinbase
+            .outputs()
+            .enumerate()
+            .find(|(_, output)| output.pk_script.address(Network::Mainnet).is_some())
+            .ok_or_else(|| eyre!("block 1 pays to an address"))?;
+        let address = output.pk_script.address(Network::Mainnet).unwrap();
+
+        let cache_dir = tempdir::TempDir::new("zebra_state_addresses")?;
+        let config = Config {
+            cache_dir: cache_dir.path().to_owned(),
+            index_addresses: true,
+            ..Config::default()
+        };
+        let mut service = on_disk::init(config, Network::Mainnet).map_err(|e| eyre!(e))?;
+
+        // Genesis is finalized, and block 1 is in the non-finalized chain.
+        let requests = vec![
+            Request::CommitFinalizedBlock { block: block0 },
+            Request::AddBlock { block: block1 },
+        ];
+        for request in requests {
+            service
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(request)
+                .await
+                .map_err(|e| eyre!(e))?;
+        }
+
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::AddressUtxos { address })
+            .await
+            .map_err(|e| eyre!(e))?;
+        match response {
+            Response::AddressUtxos { utxos } => ensure!(
+                utxos
+                    == vec![(
+                        OutPoint {
+                            hash: coinbase.as_ref().into(),
+                            index: index as u32,
+                        },
+                        output.clone()
+                    )],
+                "unexpected utxos: {:?}",
+                utxos
+            ),
+            _ => bail!("unexpected response kind: {:?}", response),
+        }
+
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::AddressBalance { address })
+            .await
+            .map_err(|e| eyre!(e))?;
+        ensure!(
+            matches!(response, Response::AddressBalance { balance } if balance == output.value),
+            "the balance is the output value"
+        );
+
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::AddressTxIds { address })
+            .await
+            .map_err(|e| eyre!(e))?;
+        match response {
+            Response::AddressTxIds { txids } => ensure!(
+                txids == vec![(block::Height(1), coinbase.as_ref().into())],
+                "unexpected txids: {:?}",
+                txids
+            ),
+            _ => bail!("unexpected response kind: {:?}", response),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn compact_filter_index() -> Result<(), Report> {
+        use tower::ServiceExt;
+
+        let block0: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.into();
+        let block1: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.into();
+
+        let cache_dir = tempdir::TempDir::new("zebra_state_filters")?;
+        let config = Config {
+            cache_dir: cache_dir.path().to_owned(),
+            index_compact_filters: true,
+            ..Config::default()
+        };
+        let mut service = on_disk::init(config, Network::Mainnet).map_err(|e| eyre!(e))?;
+
+        // Genesis is finalized, and block 1 is in the non-finalized chain.
+        let requests = vec![
+            Request::CommitFinalizedBlock {
+                block: block0.clone(),
+            },
+            Request::AddBlock {
+                block: block1.clone(),
+            },
+        ];
+        for request in requests {
+            service
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(request)
+                .await
+                .map_err(|e| eyre!(e))?;
+        }
+
+        // Neither block spends any outputs.
+        let filter0 = BlockFilter::basic(&block0, Vec::new());
+        let filter1 = BlockFilter::basic(&block1, Vec::new());
+        let header0 = FilterHeader([0; 32]).next(filter0.hash());
+        let header1 = header0.next(filter1.hash());
+
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::CompactFilters {
+                start_height: block::Height(0),
+                stop: block1.hash(),
+            })
+            .await
+            .map_err(|e| eyre!(e))?;
+        match response {
+            Response::CompactFilters {
+                filters: Some(CompactFilters { previous, filters }),
+            } => ensure!(
+                previous == FilterHeader([0; 32])
+                    && filters
+                        == vec![
+                            (block0.hash(), filter0, header0),
+                            (block1.hash(), filter1, header1),
+                        ],
+                "unexpected filters: {:?} {:?}",
+                previous,
+                filters
+            ),
+            _ => bail!("unexpected response: {:?}", response),
+        }
+
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::CompactFilters {
+                start_height: block::Height(1),
+                stop: block1.hash(),
+            })
+            .await
+            .map_err(|e| eyre!(e))?;
+        ensure!(
+            matches!(
+                &response,
+                Response::CompactFilters { filters: Some(CompactFilters { previous, filters }) }
+                    if *previous == header0 && filters.len() == 1
+            ),
+            "ranges after genesis start with the previous filter header: {:?}",
+            response
+        );
+
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::CompactFilterCheckpoints {
+                stop: block1.hash(),
+            })
+            .await
+            .map_err(|e| eyre!(e))?;
+        ensure!(
+            matches!(
+                &response,
+                Response::CompactFilterCheckpoints { headers: Some(headers) } if headers.is_empty()
+            ),
+            "there are no checkpoints below height 1000: {:?}",
+            response
+        );
+
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::CompactFilters {
+                start_height: block::Height(0),
+                stop: block::Hash([0; 32]),
+            })
+            .await
+            .map_err(|e| eyre!(e))?;
+        ensure!(
+            matches!(response, Response::CompactFilters { filters: None }),
+            "unknown stop hashes have no filters"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn address_index_is_complete_from_genesis() -> Result<(), Report> {
+        use on_disk::format::check_address_index;
+
+        let cache_dir = tempdir::TempDir::new("zebra_state_address_index")?;
+        let config = Config {
+            cache_dir: cache_dir.path().to_owned(),
+            ..Config::default()
+        };
+
+        // An index that was enabled after blocks were committed is missing
+        // their addresses.
+        {
+            let db = config.sled_config(Network::Mainnet).open()?;
+            ensure!(
+                !check_address_index(&db, false, false).map_err(|e| eyre!(e))?,
+                "disabled indexes are incomplete"
+            );
+            db.open_tree(b"hash_by_height")?
+                .insert(&0u32.to_be_bytes()[..], &[0; 32][..])?;
+            ensure!(
+                !check_address_index(&db, true, false).map_err(|e| eyre!(e))?,
+                "indexes enabled after genesis are incomplete"
+            );
+        }
+
+        // An index that was enabled from genesis is complete, until it is
+        // disabled.
+        let db = config.sled_config(Network::Testnet).open()?;
+        ensure!(
+            check_address_index(&db, true, false).map_err(|e| eyre!(e))?,
+            "indexes enabled from genesis are complete"
+        );
+        ensure!(
+            check_address_index(&db, true, true).map_err(|e| eyre!(e))?,
+            "read-only states can use complete indexes"
+        );
+        ensure!(
+            !check_address_index(&db, false, false).map_err(|e| eyre!(e))?,
+            "disabled indexes are incomplete"
+        );
+        ensure!(
+            !check_address_index(&db, true, false).map_err(|e| eyre!(e))?,
+            "indexes that were disabled are incomplete"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn newer_database_formats_are_refused() -> Result<(), Report> {
+        use on_disk::format::{DATABASE_FORMAT_VERSION, FORMAT_VERSION_KEY};
+
+        let cache_dir = tempdir::TempDir::new("zebra_state_format")?;
+        let config = Config {
+            cache_dir: cache_dir.path().to_owned(),
+            ..Config::default()
+        };
+
+        // A new database is marked with the current version.
+        {
+            let db = config.sled_config(Network::Testnet).open()?;
+            on_disk::format::check_and_upgrade(&db, &config.db_path(Network::Testnet), false)
+                .map_err(|e| eyre!(e))?;
+            ensure!(
+                db.get(FORMAT_VERSION_KEY)?.as_deref()
+                    == Some(&DATABASE_FORMAT_VERSION.to_be_bytes()[..]),
+                "new databases have the current version"
+            );
+
+            let newer = DATABASE_FORMAT_VERSION + 1;
+            db.insert(FORMAT_VERSION_KEY, &newer.to_be_bytes()[..])?;
+            db.flush()?;
+        }
+
+        let db = config.sled_config(Network::Testnet).open()?;
+        ensure!(
+            on_disk::format::check_and_upgrade(&db, &config.db_path(Network::Testnet), false)
+                .is_err(),
+            "databases from newer versions aren't opened"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn networks_have_separate_databases() -> Result<(), Report> {
+        use tower::ServiceExt;
+
+        let block0: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.into();
+
+        let cache_dir = tempdir::TempDir::new("zebra_state_networks")?;
+        let config = Config {
+            cache_dir: cache_dir.path().to_owned(),
+            ..Config::default()
+        };
+        ensure!(
+            config.db_path(Network::Mainnet) != config.db_path(Network::Testnet),
+            "each network has its own directory"
+        );
+
+        let mut mainnet = on_disk::init(config.clone(), Network::Mainnet).map_err(|e| eyre!(e))?;
+        mainnet
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::CommitFinalizedBlock { block: block0 })
+            .await
+            .map_err(|e| eyre!(e))?;
+
+        // The mainnet block isn't visible on testnet.
+        let mut testnet = on_disk::init(config, Network::Testnet).map_err(|e| eyre!(e))?;
+        let response = testnet
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::Tip)
+            .await
+            .map_err(|e| eyre!(e))?;
+        ensure!(
+            matches!(response, Response::BestTip { tip: None }),
+            "the testnet state is empty"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_only_state_rejects_writes() -> Result<(), Report> {
+        use tower::ServiceExt;
+
+        let block0: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.into();
+        let block1: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.into();
+        let hash0 = block0.hash();
+
+        let cache_dir = tempdir::TempDir::new("zebra_state_read_only")?;
+        let config = Config {
+            cache_dir: cache_dir.path().to_owned(),
+            ..Config::default()
+        };
+
+        ensure!(
+            on_disk::init_read_only(config.clone(), Network::Mainnet).is_err(),
+            "missing databases aren't created"
+        );
+
+        {
+            let mut service =
+                on_disk::init(config.clone(), Network::Mainnet).map_err(|e| eyre!(e))?;
+            service
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(Request::CommitFinalizedBlock { block: block0 })
+                .await
+                .map_err(|e| eyre!(e))?;
+        }
+
+        let mut service =
+            on_disk::init_read_only(config, Network::Mainnet).map_err(|e| eyre!(e))?;
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::Tip)
+            .await
+            .map_err(|e| eyre!(e))?;
+        ensure!(
+            matches!(response, Response::BestTip { tip: Some((_, hash)) } if hash == hash0),
+            "the read-only state has the committed blocks"
+        );
+
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::CommitFinalizedBlock { block: block1 })
+            .await;
+        ensure!(response.is_err(), "read-only states can't be written");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pruned_blocks_keep_their_headers() -> Result<(), Report> {
+        use tower::ServiceExt;
+        use zebra_chain::{transaction, Network};
+
+        let block0: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.into();
+        let block1: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.into();
+        let hash0 = block0.hash();
+        let header0 = block0.header;
+        let tx_hash0 = transaction::Hash::from(block0.transactions[0].as_ref());
+
+        let cache_dir = tempdir::TempDir::new("zebra_state_pruned")?;
+        let config = Config {
+            cache_dir: cache_dir.path().to_owned(),
+            prune_depth: Some(1),
+            ..Config::default()
+        };
+
+        let mut service = on_disk::init(config, Network::Mainnet).map_err(|e| eyre!(e))?;
+        for block in vec![block0, block1.clone()] {
+            service
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(Request::CommitFinalizedBlock { block })
+                .await
+                .map_err(|e| eyre!(e))?;
+        }
+
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::Block {
+                hash_or_height: hash0.into(),
+            })
+            .await;
+        ensure!(response.is_err(), "the genesis block body was pruned");
+
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::BlockHeader {
+                hash_or_height: hash0.into(),
+            })
+            .await
+            .map_err(|e| eyre!(e))?;
+        match response {
+            Response::BlockHeader { header } => ensure!(header == header0, "wrong header"),
+            _ => bail!("unexpected response kind: {:?}", response),
+        }
+
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::Transaction { hash: tx_hash0 })
+            .await
+            .map_err(|e| eyre!(e))?;
+        ensure!(
+            matches!(response, Response::Transaction { transaction: None }),
+            "pruned transactions aren't indexed"
+        );
+
+        // The tip is within the prune depth, so it keeps its body.
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::Block {
+                hash_or_height: block1.hash().into(),
+            })
+            .await
+            .map_err(|e| eyre!(e))?;
+        match response {
+            Response::Block { block } => ensure!(block == block1, "wrong block"),
+            _ => bail!("unexpected response kind: {:?}", response),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn corrupt_states_are_reported() -> Result<(), Report> {
+        use tower::ServiceExt;
+        use zebra_chain::{transaction, Network};
+
+        let block0: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.into();
+        let block1: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.into();
+        let tx_hash1 = transaction::Hash::from(block1.transactions[0].as_ref());
+
+        let cache_dir = tempdir::TempDir::new("zebra_state_integrity")?;
+        let config = Config {
+            cache_dir: cache_dir.path().to_owned(),
+            ..Config::default()
+        };
+
+        {
+            let mut service =
+                on_disk::init(config.clone(), Network::Mainnet).map_err(|e| eyre!(e))?;
+            for block in vec![block0, block1] {
+                service
+                    .ready_and()
+                    .await
+                    .map_err(|e| eyre!(e))?
+                    .call(Request::CommitFinalizedBlock { block })
+                    .await
+                    .map_err(|e| eyre!(e))?;
+            }
+
+            let response = service
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(Request::CheckIntegrity { depth: None })
+                .await
+                .map_err(|e| eyre!(e))?;
+            ensure!(
+                matches!(response, Response::Checked),
+                "a consistent state passes a full check"
+            );
+
+            let response = service
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(Request::Flush)
+                .await
+                .map_err(|e| eyre!(e))?;
+            ensure!(
+                matches!(response, Response::Flushed),
+                "flushing a writable state succeeds"
+            );
+        }
+
+        let pools_key = 1u32.to_be_bytes();
+        let original_pools = {
+            let db = config.sled_config(Network::Mainnet).open()?;
+            let pools = db.open_tree(b"value_pools_by_height")?;
+            let original = pools
+                .get(&pools_key[..])?
+                .ok_or_else(|| eyre!("block 1 has chain value pools"))?;
+            let mut wrong = original.to_vec();
+            // Add 1 zatoshi to the little-endian Sprout amount.
+            wrong[8] = wrong[8].wrapping_add(1);
+            pools.insert(&pools_key[..], wrong)?;
+            db.flush()?;
+            original
+        };
+        ensure!(
+            on_disk::init(config.clone(), Network::Mainnet).is_err(),
+            "a chain value pool that doesn't match the blocks is reported on startup"
+        );
+
+        {
+            let db = config.sled_config(Network::Mainnet).open()?;
+            db.open_tree(b"value_pools_by_height")?
+                .insert(&pools_key[..], original_pools)?;
+            db.open_tree(b"tx_by_hash")?.remove(&tx_hash1.0[..])?;
+            db.flush()?;
+        }
+
+        ensure!(
+            on_disk::init(config, Network::Mainnet).is_err(),
+            "a missing transaction index entry is reported on startup"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn block_locators_end_at_genesis() {
+        let heights = |tip| {
+            block_locator_heights(block::Height(tip))
+                .into_iter()
+                .map(|height| height.0)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(heights(0), vec![0]);
+        assert_eq!(heights(3), vec![3, 2, 1, 0]);
+        assert_eq!(
+            heights(100),
+            vec![100, 99, 98, 97, 96, 95, 94, 93, 92, 91, 89, 85, 77, 61, 29, 0]
+        );
+    }
+
+    #[test]
+    fn full_block_queues_wait_for_space() -> Result<(), Report> {
+        use crate::queued_blocks::{QueuedBlocks, MAX_QUEUED_BLOCKS};
+        use futures::task::{noop_waker_ref, Context, Poll};
+
+        let block1: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.into();
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        let mut queue = QueuedBlocks::default();
+        let mut queued = Vec::new();
+        for _ in 0..MAX_QUEUED_BLOCKS {
+            ensure!(queue.poll_ready(&mut cx).is_ready(), "the queue has space");
+            queued.push(queue.queue(block1.clone()).map_err(|e| eyre!(e))?);
+        }
+        ensure!(
+            queue.poll_ready(&mut cx) == Poll::Pending,
+            "full queues should wait"
+        );
+
+        // Dropping a queued block's future makes space for another block.
+        queued.pop();
+        ensure!(
+            queue.poll_ready(&mut cx).is_ready(),
+            "dropped blocks should leave the queue"
+        );
+        ensure!(
+            queue.len() == MAX_QUEUED_BLOCKS - 1,
+            "one block was dropped"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn out_of_order_blocks_wait_for_their_parents() -> Result<(), Report> {
+        use tower::ServiceExt;
+
+        let block0: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.into();
+        let block1: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.into();
+
+        let cache_dir = tempdir::TempDir::new("zebra_state_queued")?;
+        let config = Config {
+            cache_dir: cache_dir.path().to_owned(),
+            ..Config::default()
+        };
+        let mut service = on_disk::init(config, Network::Mainnet).map_err(|e| eyre!(e))?;
+
+        // Block 1 is queued until genesis is added.
+        let queued = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::AddBlock {
+                block: block1.clone(),
+            });
+        service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::AddBlock { block: block0 })
+            .await
+            .map_err(|e| eyre!(e))?;
+        let response = queued.await.map_err(|e| eyre!(e))?;
+        ensure!(
+            matches!(response, Response::Added),
+            "unexpected response kind: {:?}",
+            response
+        );
+
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::GetTip)
+            .await
+            .map_err(|e| eyre!(e))?;
+        match response {
+            Response::Tip { hash } => ensure!(hash == block1.hash(), "block 1 should be the tip"),
+            _ => bail!("unexpected response kind: {:?}", response),
+        }
+
+        Ok(())
+    }
+
+    /// Returns a copy of `block` with a coinbase at `height`, and `parent` as
+    /// its previous block.
+    fn block_at(block: &Block, height: u32, parent: block::Hash) -> Arc<Block> {
+        use zebra_chain::transaction::{Transaction, TransparentInput};
+
+        let mut block = block.clone();
+        let mut coinbase = block.transactions[0].as_ref().clone();
+        if let Transaction::V1 { inputs, .. } = &mut coinbase {
+            if let Some(TransparentInput::Coinbase { height: h, .. }) = inputs.first_mut() {
+                *h = block::Height(height);
+            }
+        }
+        block.transactions[0] = coinbase.into();
+        block.header.previous_block_hash = parent;
+        block.into()
+    }
+
+    #[tokio::test]
+    async fn best_chain_reorgs_to_more_work() -> Result<(), Report> {
+        use tower::ServiceExt;
+
+        let block0: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.into();
+        let block1: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.into();
+
+        // A competing block 1, and a block 2 on top of it.
+        let mut fork1 = block1.as_ref().clone();
+        fork1.header.nonce[0] ^= 0xff;
+        let fork1: Arc<_> = fork1.into();
+        let fork1_hash = fork1.hash();
+        let fork2 = block_at(&block1, 2, fork1.hash());
+
+        let cache_dir = tempdir::TempDir::new("zebra_state_reorg")?;
+        let config = Config {
+            cache_dir: cache_dir.path().to_owned(),
+            ..Config::default()
+        };
+        let mut service = on_disk::init(config, Network::Mainnet).map_err(|e| eyre!(e))?;
+
+        let mut tips = Vec::new();
+        for block in vec![block0, block1.clone(), fork1, fork2.clone()] {
+            service
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(Request::AddBlock { block })
+                .await
+                .map_err(|e| eyre!(e))?;
+
+            let response = service
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(Request::GetTip)
+                .await
+                .map_err(|e| eyre!(e))?;
+            match response {
+                Response::Tip { hash } => tips.push(hash),
+                _ => bail!("unexpected response kind: {:?}", response),
+            }
+        }
+
+        // The first block 1 stays the tip until the fork has more work.
+        ensure!(tips[1] == block1.hash(), "block 1 should be the tip");
+        ensure!(tips[2] == block1.hash(), "ties keep the first chain");
+        ensure!(tips[3] == fork2.hash(), "the fork should be the best chain");
+
+        // Blocks on the old chain aren't in the best chain any more.
+        for (hash, expected) in &[(block1.hash(), None), (fork1_hash, Some(1))] {
+            let response = service
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(Request::Depth { hash: *hash })
+                .await
+                .map_err(|e| eyre!(e))?;
+            match response {
+                Response::Depth { depth } => ensure!(depth == *expected, "wrong depth"),
+                _ => bail!("unexpected response kind: {:?}", response),
+            }
+        }
+
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::BestChainBlockHash {
+                height: block::Height(1),
+            })
+            .await
+            .map_err(|e| eyre!(e))?;
+        ensure!(
+            matches!(response, Response::BlockHash { hash: Some(hash) } if hash == fork1_hash),
+            "the fork is the best chain at height 1"
+        );
+
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::Tip)
+            .await
+            .map_err(|e| eyre!(e))?;
+        ensure!(
+            matches!(
+                response,
+                Response::BestTip { tip: Some((block::Height(2), hash)) } if hash == fork2.hash()
+            ),
+            "the fork's tip is the best tip"
+        );
+
+        // Peers are sent the best chain, after the first known block in it.
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::FindBlockHashes {
+                known_blocks: vec![block1.hash(), block::Hash([0xff; 32])],
+                stop: None,
+            })
+            .await
+            .map_err(|e| eyre!(e))?;
+        match response {
+            Response::BlockHashes { hashes } => ensure!(
+                hashes == vec![fork1_hash, fork2.hash()],
+                "unexpected hashes: {:?}",
+                hashes
+            ),
+            _ => bail!("unexpected response kind: {:?}", response),
+        }
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::FindBlockHeaders {
+                known_blocks: vec![fork1_hash],
+                stop: None,
+            })
+            .await
+            .map_err(|e| eyre!(e))?;
+        match response {
+            Response::BlockHeaders { headers } => ensure!(
+                headers
+                    .iter()
+                    .map(|header| header.hash())
+                    .collect::<Vec<_>>()
+                    == vec![fork2.hash()],
+                "unexpected headers: {:?}",
+                headers
+            ),
+            _ => bail!("unexpected response kind: {:?}", response),
+        }
+
+        // Each chain has its own value pools, so the old chain's pools are
+        // unchanged by the reorg.
+        let coinbase_value: Amount<NonNegative> = block1.transactions[0]
+            .outputs()
+            .map(|output| output.value)
+            .sum::<Result<_, _>>()?;
+        for (hash, coinbases) in &[(block1.hash(), 1), (fork2.hash(), 2)] {
+            let response = service
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(Request::GetChainValuePools { hash: *hash })
+                .await
+                .map_err(|e| eyre!(e))?;
+            let expected = (0..*coinbases)
+                .map(|_| coinbase_value)
+                .sum::<Result<_, _>>()?;
+            match response {
+                Response::ChainValuePools { pools: Some(pools) } => ensure!(
+                    pools == ValueBalance::from_transparent_amount(expected),
+                    "unexpected pools: {:?}",
+                    pools
+                ),
+                _ => bail!("unexpected response kind: {:?}", response),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn negative_value_pools_are_rejected() -> Result<(), Report> {
+        use std::convert::TryFrom;
+        use tower::ServiceExt;
+        use zebra_chain::{
+            amount::Amount,
+            transaction::{LockTime, Transaction},
+            Network,
+        };
+
+        let block0: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])?.into();
+        let block1: Arc<_> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])?.into();
+
+        // Block 2 removes value from the Sapling pool, which is empty.
+        let mut block2 = block_at(&block1, 2, block1.hash()).as_ref().clone();
+        block2.transactions.push(Arc::new(Transaction::V4 {
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            lock_time: LockTime::unlocked(),
+            expiry_height: block::Height(0),
+            value_balance: Amount::try_from(1i64)?,
+            shielded_data: None,
+            joinsplit_data: None,
+        }));
+
+        let cache_dir = tempdir::TempDir::new("zebra_state_value_pools")?;
+        let config = Config {
+            cache_dir: cache_dir.path().to_owned(),
+            ..Config::default()
+        };
+        let mut service = on_disk::init(config, Network::Mainnet).map_err(|e| eyre!(e))?;
+
+        for block in vec![block0, block1.clone()] {
+            service
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(Request::AddBlock { block })
+                .await
+                .map_err(|e| eyre!(e))?;
+        }
+
+        let error = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::AddBlock {
+                block: block2.into(),
+            })
+            .await
+            .expect_err("the Sapling pool can't go negative");
+        ensure!(
+            error.to_string().contains("value pool"),
+            "unexpected error: {}",
+            error
+        );
+
+        let response = service
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(Request::GetTip)
+            .await
+            .map_err(|e| eyre!(e))?;
+        match response {
+            Response::Tip { hash } => ensure!(hash == block1.hash(), "block 1 is still the tip"),
+            _ => bail!("unexpected response kind: {:?}", response),
+        }
+
+        Ok(())
+    }
 }
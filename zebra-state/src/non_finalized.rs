@@ -0,0 +1,588 @@
+//! Blocks that can still be rolled back, in each of the competing chains.
+//!
+//! Each chain starts at a child of the finalized tip, and they can share
+//! blocks. The best chain is the one with the most cumulative work. A reorg
+//! doesn't need any special handling: the new best chain already has its own
+//! copy of the fork's blocks and outputs, so switching to it rolls back the
+//! old tip blocks, and replays the new ones.
+//!
+//! Once the best chain is longer than [`MAX_NON_FINALIZED_BLOCKS`], its
+//! first block is finalized, and chains that don't include it are dropped.
+use crate::{note_commitment_trees::NoteCommitmentTrees, HashOrHeight};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+};
+use zebra_chain::{
+    amount::NonNegative,
+    block::{self, Block},
+    history_tree::HistoryTree,
+    orchard, sapling,
+    transaction::{self, OutPoint, TransparentInput, TransparentOutput},
+    value_balance::ValueBalance,
+    work::difficulty::Work,
+};
+
+/// The number of blocks in the best chain that can be rolled back.
+///
+/// Blocks that are deeper than this are finalized, matching the `zcashd`
+/// reorg limit.
+pub(crate) const MAX_NON_FINALIZED_BLOCKS: usize = 100;
+
+/// A chain of non-finalized blocks, starting at a child of the finalized tip.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Chain {
+    blocks: BTreeMap<block::Height, Arc<Block>>,
+    height_by_hash: HashMap<block::Hash, block::Height>,
+    /// The height of the block, and the index in that block, of each
+    /// transaction.
+    tx_by_hash: HashMap<transaction::Hash, (block::Height, usize)>,
+    /// Every transparent output created by this chain, including spent
+    /// outputs.
+    created_utxos: HashMap<OutPoint, TransparentOutput>,
+    /// Every outpoint spent by this chain, including finalized outputs.
+    spent_utxos: HashSet<OutPoint>,
+    /// The nullifiers revealed by this chain, and their pools.
+    nullifiers: HashSet<(Pool, [u8; 32])>,
+    /// The note commitment trees after each block.
+    ///
+    /// Their roots are the anchors that spends in later blocks can use.
+    note_commitment_trees: BTreeMap<block::Height, NoteCommitmentTrees>,
+    /// The chain value pools after each block.
+    ///
+    /// Each chain has its own pools, so a reorg uses the pools of the new
+    /// best chain.
+    value_pools: BTreeMap<block::Height, ValueBalance<NonNegative>>,
+}
+
+impl Chain {
+    /// Adds `block` to the tip of this chain, with the note commitment trees
+    /// and chain value pools after the block.
+    pub(crate) fn push(
+        &mut self,
+        block: Arc<Block>,
+        trees: NoteCommitmentTrees,
+        value_pools: ValueBalance<NonNegative>,
+    ) {
+        let height = self.next_height(&block);
+        let _ = self.height_by_hash.insert(block.hash(), height);
+        let _ = self.note_commitment_trees.insert(height, trees);
+        let _ = self.value_pools.insert(height, value_pools);
+
+        for (tx_index, transaction) in block.transactions.iter().enumerate() {
+            for outpoint in spent_outpoints(transaction) {
+                let _ = self.spent_utxos.insert(outpoint);
+            }
+
+            let hash = transaction::Hash::from(transaction.as_ref());
+            let _ = self.tx_by_hash.insert(hash, (height, tx_index));
+
+            // The genesis coinbase can't be spent.
+            if height == block::Height(0) {
+                continue;
+            }
+            for (index, output) in transaction.outputs().enumerate() {
+                let outpoint = OutPoint {
+                    hash,
+                    index: index as u32,
+                };
+                let _ = self.created_utxos.insert(outpoint, output.clone());
+            }
+        }
+
+        self.nullifiers.extend(nullifiers(&block));
+        let _ = self.blocks.insert(height, block);
+    }
+
+    /// Removes the tip block of this chain, rolling back its outputs.
+    fn pop_tip(&mut self) -> Option<Arc<Block>> {
+        let height = *self.blocks.keys().next_back()?;
+        let block = self.blocks.remove(&height)?;
+        self.revert(&block);
+        Some(block)
+    }
+
+    /// Removes the first block of this chain, so it can be finalized.
+    fn pop_root(&mut self) -> Option<Arc<Block>> {
+        let height = *self.blocks.keys().next()?;
+        let block = self.blocks.remove(&height)?;
+        self.revert(&block);
+        Some(block)
+    }
+
+    /// Removes the indexes and outputs for `block`.
+    ///
+    /// Each outpoint can only be spent once in a chain, so this works for
+    /// blocks at either end of the chain.
+    fn revert(&mut self, block: &Block) {
+        if let Some(height) = self.height_by_hash.remove(&block.hash()) {
+            let _ = self.value_pools.remove(&height);
+            let _ = self.note_commitment_trees.remove(&height);
+        }
+        for nullifier in nullifiers(block) {
+            let _ = self.nullifiers.remove(&nullifier);
+        }
+
+        for transaction in &block.transactions {
+            for outpoint in spent_outpoints(transaction) {
+                let _ = self.spent_utxos.remove(&outpoint);
+            }
+
+            let hash = transaction::Hash::from(transaction.as_ref());
+            let _ = self.tx_by_hash.remove(&hash);
+            for index in 0..transaction.outputs().count() {
+                let outpoint = OutPoint {
+                    hash,
+                    index: index as u32,
+                };
+                let _ = self.created_utxos.remove(&outpoint);
+            }
+        }
+    }
+
+    /// Returns a copy of this chain, ending at the block with `hash`, if this
+    /// chain contains that block.
+    fn fork(&self, hash: block::Hash) -> Option<Chain> {
+        if !self.height_by_hash.contains_key(&hash) {
+            return None;
+        }
+
+        let mut fork = self.clone();
+        while fork.tip_hash() != Some(hash) {
+            let _ = fork.pop_tip();
+        }
+        Some(fork)
+    }
+
+    /// Returns the height of `block` in this chain.
+    fn next_height(&self, block: &Block) -> block::Height {
+        match self.blocks.keys().next_back() {
+            Some(height) => block::Height(height.0 + 1),
+            None => block
+                .coinbase_height()
+                .expect("verified blocks have a coinbase height"),
+        }
+    }
+
+    /// Returns the height and hash of the tip block, or `None` if the chain
+    /// is empty.
+    pub(crate) fn tip(&self) -> Option<(block::Height, block::Hash)> {
+        self.blocks
+            .iter()
+            .next_back()
+            .map(|(height, block)| (*height, block.hash()))
+    }
+
+    /// Returns the height of the block with `hash`, if it is in this chain.
+    pub(crate) fn height(&self, hash: &block::Hash) -> Option<block::Height> {
+        self.height_by_hash.get(hash).copied()
+    }
+
+    /// Returns the hash of the tip block, or `None` if the chain is empty.
+    fn tip_hash(&self) -> Option<block::Hash> {
+        self.blocks.values().next_back().map(|block| block.hash())
+    }
+
+    /// Returns the block with `hash_or_height`, if it is in this chain.
+    pub(crate) fn block(&self, hash_or_height: HashOrHeight) -> Option<Arc<Block>> {
+        let height = match hash_or_height {
+            HashOrHeight::Hash(hash) => *self.height_by_hash.get(&hash)?,
+            HashOrHeight::Height(height) => height,
+        };
+        self.blocks.get(&height).cloned()
+    }
+
+    /// Returns the transaction with `hash`, and the height of its block, if
+    /// it is in this chain.
+    pub(crate) fn transaction(
+        &self,
+        hash: transaction::Hash,
+    ) -> Option<(Arc<transaction::Transaction>, block::Height)> {
+        let (height, index) = self.tx_by_hash.get(&hash)?;
+        let transaction = self.blocks.get(height)?.transactions.get(*index)?;
+        Some((transaction.clone(), *height))
+    }
+
+    /// Returns true if the block with `hash` is in this chain.
+    pub(crate) fn contains(&self, hash: &block::Hash) -> bool {
+        self.height_by_hash.contains_key(hash)
+    }
+
+    /// Returns the output at `outpoint`, if it was created by this chain, and
+    /// hasn't been spent.
+    pub(crate) fn created_utxo(&self, outpoint: &OutPoint) -> Option<TransparentOutput> {
+        if self.spent_utxos.contains(outpoint) {
+            return None;
+        }
+        self.created_utxos.get(outpoint).cloned()
+    }
+
+    /// Returns the note commitment trees after the tip block, or `None` if
+    /// the chain is empty.
+    pub(crate) fn note_commitment_trees(&self) -> Option<&NoteCommitmentTrees> {
+        self.note_commitment_trees.values().next_back()
+    }
+
+    /// Returns the chain value pools after the tip block, or `None` if the
+    /// chain is empty.
+    pub(crate) fn value_pools(&self) -> Option<ValueBalance<NonNegative>> {
+        self.value_pools.values().next_back().copied()
+    }
+
+    /// Returns true if this chain reveals `nullifier` in `pool`.
+    pub(crate) fn contains_nullifier(&self, pool: Pool, nullifier: [u8; 32]) -> bool {
+        self.nullifiers.contains(&(pool, nullifier))
+    }
+
+    /// Returns the output at `outpoint`, if it was created by this chain,
+    /// even if it has been spent.
+    pub(crate) fn created_output(&self, outpoint: &OutPoint) -> Option<&TransparentOutput> {
+        self.created_utxos.get(outpoint)
+    }
+
+    /// Returns the outputs created by this chain that haven't been spent.
+    pub(crate) fn unspent_utxos(&self) -> impl Iterator<Item = (&OutPoint, &TransparentOutput)> {
+        self.created_utxos
+            .iter()
+            .filter(move |(outpoint, _)| !self.spent_utxos.contains(outpoint))
+    }
+
+    /// Returns the blocks in this chain, in height order.
+    pub(crate) fn blocks(&self) -> impl Iterator<Item = (&block::Height, &Arc<Block>)> {
+        self.blocks.iter()
+    }
+
+    /// Returns true if this chain spends `outpoint`.
+    pub(crate) fn is_spent(&self, outpoint: &OutPoint) -> bool {
+        self.spent_utxos.contains(outpoint)
+    }
+
+    /// Returns the cumulative work of the blocks in this chain.
+    ///
+    /// Every chain starts after the finalized tip, so their work can be
+    /// compared without the finalized blocks.
+    fn work(&self) -> Work {
+        self.blocks
+            .values()
+            .map(|block| block.header.bits.to_work().unwrap_or_default())
+            .sum()
+    }
+
+    fn len(&self) -> usize {
+        self.blocks.len()
+    }
+}
+
+/// Returns the outpoints spent by `transaction`.
+fn spent_outpoints(transaction: &transaction::Transaction) -> impl Iterator<Item = OutPoint> + '_ {
+    transaction.inputs().filter_map(|input| match input {
+        TransparentInput::PrevOut { outpoint, .. } => Some(*outpoint),
+        TransparentInput::Coinbase { .. } => None,
+    })
+}
+
+/// A shielded value pool, which has its own nullifier set.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub(crate) enum Pool {
+    Sprout,
+    Sapling,
+    Orchard,
+}
+
+/// Returns the nullifiers revealed by `block`, and their pools.
+pub(crate) fn nullifiers(block: &Block) -> Vec<(Pool, [u8; 32])> {
+    block
+        .transactions
+        .iter()
+        .flat_map(|transaction| {
+            let sprout = transaction
+                .sprout_nullifiers()
+                .map(|nullifier| (Pool::Sprout, nullifier.0));
+            let sapling = transaction
+                .sapling_nullifiers()
+                .map(|nullifier| (Pool::Sapling, nullifier.0));
+            let orchard = transaction
+                .orchard_nullifiers()
+                .map(|nullifier| (Pool::Orchard, nullifier.0));
+            sprout.chain(sapling).chain(orchard).collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// The competing chains of non-finalized blocks.
+#[derive(Debug, Default)]
+pub(crate) struct NonFinalizedState {
+    chains: Vec<Chain>,
+}
+
+impl NonFinalizedState {
+    /// Returns the chain that a child of the block with `parent` would
+    /// extend, or `None` if `parent` is not in any chain, and is not the
+    /// finalized tip.
+    ///
+    /// The chain is a copy, so it can be checked before it is added with
+    /// [`NonFinalizedState::insert`].
+    pub(crate) fn parent_chain(
+        &self,
+        parent: block::Hash,
+        finalized_tip: Option<block::Hash>,
+    ) -> Option<Chain> {
+        if let Some(chain) = self
+            .chains
+            .iter()
+            .find(|chain| chain.tip_hash() == Some(parent))
+        {
+            return Some(chain.clone());
+        }
+
+        if let Some(fork) = self.chains.iter().find_map(|chain| chain.fork(parent)) {
+            return Some(fork);
+        }
+
+        if finalized_tip == Some(parent) {
+            return Some(Chain::default());
+        }
+
+        None
+    }
+
+    /// Adds `chain`, replacing the chain it extends, if there is one.
+    pub(crate) fn insert(&mut self, chain: Chain) {
+        let parent = chain
+            .blocks
+            .values()
+            .next_back()
+            .map(|block| block.header.previous_block_hash);
+        self.chains.retain(|existing| existing.tip_hash() != parent);
+        self.chains.push(chain);
+    }
+
+    /// Removes and returns the first block of the best chain, if the best
+    /// chain has too many blocks.
+    ///
+    /// Chains that don't include that block can never become the best chain,
+    /// so they are dropped.
+    pub(crate) fn finalize(&mut self) -> Option<Arc<Block>> {
+        if self.best_chain()?.len() <= MAX_NON_FINALIZED_BLOCKS {
+            return None;
+        }
+
+        self.finalize_root()
+    }
+
+    /// Removes and returns the first block of the best chain, however many
+    /// blocks it has, and drops the chains that don't include it.
+    pub(crate) fn finalize_root(&mut self) -> Option<Arc<Block>> {
+        let root = self.best_chain()?.blocks.values().next()?.clone();
+        let root_hash = root.hash();
+
+        self.chains.retain(|chain| chain.contains(&root_hash));
+        for chain in &mut self.chains {
+            let _ = chain.pop_root();
+        }
+        self.chains.retain(|chain| !chain.blocks.is_empty());
+
+        Some(root)
+    }
+
+    /// Returns the chain with the most work.
+    ///
+    /// If chains have the same work, the first one that was added is
+    /// preferred.
+    pub(crate) fn best_chain(&self) -> Option<&Chain> {
+        self.chains.iter().rev().max_by_key(|chain| chain.work())
+    }
+
+    /// Returns the block with `hash_or_height`, if it is in a chain.
+    ///
+    /// Hashes are found in any chain, so the parents of side chain blocks
+    /// can be looked up. Heights are only found in the best chain.
+    pub(crate) fn block(&self, hash_or_height: HashOrHeight) -> Option<Arc<Block>> {
+        match hash_or_height {
+            HashOrHeight::Hash(_) => self
+                .chains
+                .iter()
+                .find_map(|chain| chain.block(hash_or_height)),
+            HashOrHeight::Height(_) => self.best_chain()?.block(hash_or_height),
+        }
+    }
+
+    /// Returns true if the block with `hash` is in any chain.
+    pub(crate) fn any_chain_contains(&self, hash: &block::Hash) -> bool {
+        self.chains.iter().any(|chain| chain.contains(hash))
+    }
+
+    /// Returns the note commitment trees after the block with `hash`, if it
+    /// is in any chain.
+    fn note_commitment_trees(&self, hash: &block::Hash) -> Option<&NoteCommitmentTrees> {
+        self.chains.iter().find_map(|chain| {
+            let height = chain.height(hash)?;
+            chain.note_commitment_trees.get(&height)
+        })
+    }
+
+    /// Returns the note commitment trees after each block in the chain
+    /// ending at the block with `tip`, up to and including that block, or in
+    /// the best chain if `tip` is `None`.
+    ///
+    /// Returns `None` if `tip` isn't in any chain. Blocks on other forks are
+    /// never included, so spends can only use the anchors in their own
+    /// chain.
+    pub(crate) fn chain_trees(
+        &self,
+        tip: Option<block::Hash>,
+    ) -> Option<Vec<&NoteCommitmentTrees>> {
+        let found = match tip {
+            Some(hash) => self
+                .chains
+                .iter()
+                .find_map(|chain| Some((chain, chain.height(&hash)?))),
+            None => self
+                .best_chain()
+                .and_then(|chain| Some((chain, chain.tip()?.0))),
+        };
+        let (chain, height) = match (found, tip) {
+            (Some(found), _) => found,
+            (None, Some(_)) => return None,
+            (None, None) => return Some(Vec::new()),
+        };
+        Some(
+            chain
+                .note_commitment_trees
+                .range(..=height)
+                .map(|(_, trees)| trees)
+                .collect(),
+        )
+    }
+
+    /// Returns the Sapling note commitment tree after the block with `hash`,
+    /// if it is in any chain.
+    pub(crate) fn sapling_tree(
+        &self,
+        hash: &block::Hash,
+    ) -> Option<sapling::tree::NoteCommitmentTree> {
+        Some(self.note_commitment_trees(hash)?.sapling.clone())
+    }
+
+    /// Returns the Orchard note commitment tree after the block with `hash`,
+    /// if it is in any chain.
+    pub(crate) fn orchard_tree(
+        &self,
+        hash: &block::Hash,
+    ) -> Option<orchard::tree::NoteCommitmentTree> {
+        Some(self.note_commitment_trees(hash)?.orchard.clone())
+    }
+
+    /// Returns the chain history tree after the block with `hash`, if it is
+    /// in any chain.
+    pub(crate) fn history_tree(&self, hash: &block::Hash) -> Option<HistoryTree> {
+        Some(self.note_commitment_trees(hash)?.history.clone())
+    }
+
+    /// Returns the chain value pools after the block with `hash`, if it is
+    /// in any chain.
+    pub(crate) fn value_pools(&self, hash: &block::Hash) -> Option<ValueBalance<NonNegative>> {
+        self.chains.iter().find_map(|chain| {
+            let height = chain.height(hash)?;
+            chain.value_pools.get(&height).copied()
+        })
+    }
+
+    /// Returns true if there are no non-finalized blocks.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.chains.is_empty()
+    }
+
+    /// Returns the number of non-finalized chains.
+    pub(crate) fn chain_count(&self) -> usize {
+        self.chains.len()
+    }
+
+    /// Returns the number of blocks that left the best chain, if the chain
+    /// with `old_tip` was the best chain, and a different chain is now.
+    pub(crate) fn reorg_depth(&self, old_tip: block::Hash) -> Option<usize> {
+        let best_chain = self.best_chain()?;
+        if best_chain.contains(&old_tip) {
+            return None;
+        }
+
+        let old_chain = self
+            .chains
+            .iter()
+            .find(|chain| chain.tip_hash() == Some(old_tip))?;
+        Some(
+            old_chain
+                .blocks()
+                .filter(|(_, block)| !best_chain.contains(&block.hash()))
+                .count(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use zebra_chain::serialization::ZcashDeserialize;
+
+    #[test]
+    fn genesis_coinbase_is_unspendable() {
+        let genesis: Arc<Block> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])
+                .unwrap()
+                .into();
+        let coinbase = transaction::Hash::from(genesis.transactions[0].as_ref());
+
+        let mut chain = Chain::default();
+        chain.push(
+            genesis,
+            NoteCommitmentTrees::default(),
+            ValueBalance::zero(),
+        );
+
+        let outpoint = OutPoint {
+            hash: coinbase,
+            index: 0,
+        };
+        assert!(chain.created_utxo(&outpoint).is_none());
+        assert_eq!(chain.unspent_utxos().count(), 0);
+    }
+
+    #[test]
+    fn anchors_are_limited_to_the_chain_before_the_tip() {
+        let genesis: Arc<Block> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_GENESIS_BYTES[..])
+                .unwrap()
+                .into();
+        let block1: Arc<Block> =
+            Block::zcash_deserialize(&zebra_test_vectors::BLOCK_MAINNET_1_BYTES[..])
+                .unwrap()
+                .into();
+        let (genesis_hash, block1_hash) = (genesis.hash(), block1.hash());
+
+        let mut trees = NoteCommitmentTrees::default();
+        trees.orchard.append([1; 32]).unwrap();
+        let anchor = (Pool::Orchard, trees.orchard.root().0);
+
+        let mut chain = Chain::default();
+        chain.push(
+            genesis,
+            NoteCommitmentTrees::default(),
+            ValueBalance::zero(),
+        );
+        chain.push(block1, trees, ValueBalance::zero());
+        let mut state = NonFinalizedState::default();
+        state.insert(chain);
+
+        let has_anchor = |tip| {
+            state
+                .chain_trees(tip)
+                .map(|trees| trees.iter().any(|trees| trees.anchors().contains(&anchor)))
+        };
+        assert_eq!(has_anchor(None), Some(true));
+        assert_eq!(has_anchor(Some(block1_hash)), Some(true));
+        // A child of the genesis block would be on a different fork.
+        assert_eq!(has_anchor(Some(genesis_hash)), Some(false));
+        assert_eq!(has_anchor(Some(block::Hash([0xff; 32]))), None);
+    }
+}
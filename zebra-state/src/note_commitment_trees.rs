@@ -0,0 +1,88 @@
+//! The note commitment trees of each shielded pool, after a block.
+//!
+//! Each pool has its own tree, and spends in each pool use the roots of
+//! their pool's tree as anchors. The trees are updated together, so the
+//! state stores them together.
+//!
+//! The chain history tree is updated with the same blocks, and uses the
+//! Sapling and Orchard roots, so it is stored with them.
+use std::{error::Error, io};
+
+use zebra_chain::{
+    block::Block,
+    history_tree::HistoryTree,
+    orchard, sapling,
+    serialization::{SerializationError, ZcashDeserialize, ZcashSerialize},
+    sprout, Network,
+};
+
+use crate::non_finalized::Pool;
+
+type BoxError = Box<dyn Error + Send + Sync + 'static>;
+
+/// The note commitment tree of each shielded pool, and the chain history
+/// tree.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct NoteCommitmentTrees {
+    pub(crate) sprout: sprout::tree::NoteCommitmentTree,
+    pub(crate) sapling: sapling::tree::NoteCommitmentTree,
+    pub(crate) orchard: orchard::tree::NoteCommitmentTree,
+    pub(crate) history: HistoryTree,
+}
+
+impl NoteCommitmentTrees {
+    /// Appends the note commitments created by `block` to each tree, in
+    /// block order, then adds `block` to the history tree for `network`.
+    ///
+    /// Returns an error if a tree is full, or a commitment is invalid. The
+    /// trees may have been partly updated.
+    pub(crate) fn append_block(&mut self, network: Network, block: &Block) -> Result<(), BoxError> {
+        for transaction in &block.transactions {
+            for cm in transaction.sprout_note_commitments() {
+                self.sprout.append(cm)?;
+            }
+            for output in transaction.sapling_outputs() {
+                self.sapling.append(output.cmu)?;
+            }
+            for cm_x in transaction.orchard_note_commitments() {
+                self.orchard.append(*cm_x)?;
+            }
+        }
+        self.history
+            .push(network, block, self.sapling.root(), self.orchard.root())?;
+        Ok(())
+    }
+
+    /// Returns the root of each tree, with its pool.
+    ///
+    /// These are the anchors that spends can use after the block with these
+    /// trees. JoinSplits can also use the Sprout roots after earlier
+    /// JoinSplits in their transaction, which aren't included.
+    pub(crate) fn anchors(&self) -> Vec<(Pool, [u8; 32])> {
+        vec![
+            (Pool::Sprout, self.sprout.root().0),
+            (Pool::Sapling, self.sapling.root().0),
+            (Pool::Orchard, self.orchard.root().0),
+        ]
+    }
+}
+
+impl ZcashSerialize for NoteCommitmentTrees {
+    fn zcash_serialize<W: io::Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        self.sprout.zcash_serialize(&mut writer)?;
+        self.sapling.zcash_serialize(&mut writer)?;
+        self.orchard.zcash_serialize(&mut writer)?;
+        self.history.zcash_serialize(&mut writer)
+    }
+}
+
+impl ZcashDeserialize for NoteCommitmentTrees {
+    fn zcash_deserialize<R: io::Read>(mut reader: R) -> Result<Self, SerializationError> {
+        Ok(NoteCommitmentTrees {
+            sprout: ZcashDeserialize::zcash_deserialize(&mut reader)?,
+            sapling: ZcashDeserialize::zcash_deserialize(&mut reader)?,
+            orchard: ZcashDeserialize::zcash_deserialize(&mut reader)?,
+            history: ZcashDeserialize::zcash_deserialize(&mut reader)?,
+        })
+    }
+}
@@ -0,0 +1,1963 @@
+//! The finalized part of the chain state, stored on disk.
+//!
+//! Finalized blocks can't be rolled back, so they are stored in a `sled`
+//! database, indexed by height and by hash. The unspent transparent outputs
+//! after the finalized tip are stored alongside the blocks, and are updated
+//! in the same database transaction as each block.
+//!
+//! The state service keeps the most recent blocks in memory, in the
+//! non-finalized chains, and only writes them to disk once they are too deep
+//! to be rolled back.
+use super::{
+    block_locator_heights,
+    non_finalized::{nullifiers, Chain, NonFinalizedState, Pool, MAX_NON_FINALIZED_BLOCKS},
+    note_commitment_trees::NoteCommitmentTrees,
+    pending_tips::PendingTips,
+    pending_utxos::PendingUtxos,
+    queued_blocks::QueuedBlocks,
+    CompactFilters, Config, HashOrHeight, Request, Response, COMPACT_FILTER_CHECKPOINT_INTERVAL,
+    MAX_COMPACT_FILTERS_RESULTS, MAX_FIND_BLOCK_HASHES_RESULTS, MAX_FIND_BLOCK_HEADERS_RESULTS,
+};
+use futures::prelude::*;
+use sled::{
+    transaction::{abort, TransactionError},
+    Transactional,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+use tower::{buffer::Buffer, Service};
+use zebra_chain::{
+    amount::{Amount, NonNegative},
+    block::{
+        self,
+        filter::{BlockFilter, FilterHeader},
+        Block, Header,
+    },
+    history_tree::HistoryTree,
+    orchard, sapling,
+    serialization::{ZcashDeserialize, ZcashSerialize},
+    sprout,
+    transaction::{self, OutPoint, Transaction, TransparentInput, TransparentOutput},
+    transparent::{Address, Script},
+    value_balance::ValueBalance,
+    Network,
+};
+
+pub(crate) mod format;
+mod integrity;
+
+type BoxError = Box<dyn Error + Send + Sync + 'static>;
+
+/// The finalized state, in a `sled` database.
+struct FinalizedState {
+    /// Block hashes, keyed by big-endian height, so the last entry is the tip.
+    hash_by_height: sled::Tree,
+    /// Big-endian block heights, keyed by hash.
+    height_by_hash: sled::Tree,
+    /// Serialized blocks, keyed by big-endian height.
+    block_by_height: sled::Tree,
+    /// Serialized unspent outputs, keyed by serialized outpoint.
+    utxo_by_outpoint: sled::Tree,
+    /// The block hash and big-endian index of each transaction, keyed by
+    /// transaction hash.
+    tx_by_hash: sled::Tree,
+    /// The revealed nullifiers for each shielded pool, as keys with empty
+    /// values.
+    ///
+    /// Each pool has its own set, because nullifiers from different pools
+    /// can have the same bytes.
+    sprout_nullifiers: sled::Tree,
+    sapling_nullifiers: sled::Tree,
+    orchard_nullifiers: sled::Tree,
+    /// The serialized note commitment trees after each block, keyed by
+    /// big-endian height.
+    note_commitment_trees_by_height: sled::Tree,
+    /// The tree roots after each block, keyed by pool then root.
+    ///
+    /// Sprout roots have the serialized Sprout tree as their value, so
+    /// JoinSplits can compute the roots after earlier JoinSplits in their
+    /// transaction. Other roots have empty values.
+    anchors: sled::Tree,
+    /// The serialized chain value pools after each block, keyed by
+    /// big-endian height.
+    value_pools_by_height: sled::Tree,
+    /// The unspent outputs for each transparent address, keyed by serialized
+    /// address then outpoint, with empty values.
+    utxos_by_address: sled::Tree,
+    /// The transactions that spend from or pay to each transparent address,
+    /// keyed by serialized address, then big-endian height and index, with
+    /// transaction hash values.
+    txids_by_address: sled::Tree,
+    /// The serialized headers of pruned blocks, keyed by big-endian height.
+    header_by_height: sled::Tree,
+    /// The BIP158 basic filter of each block, keyed by big-endian height,
+    /// with the block's filter header followed by the serialized filter.
+    ///
+    /// Filters are kept when blocks are pruned, because light clients need
+    /// the whole filter chain.
+    filter_by_height: sled::Tree,
+    /// The whole database, for its size on disk.
+    db: sled::Db,
+    /// The database directory, for error messages.
+    path: PathBuf,
+    /// The network, for decoding addresses from output scripts.
+    network: Network,
+    /// Whether the address trees are updated.
+    index_addresses: bool,
+    /// Whether the address trees have been updated since genesis, so
+    /// address queries are complete.
+    address_index_complete: bool,
+    /// Whether filter queries are allowed.
+    index_compact_filters: bool,
+    /// Whether the filter tree has been updated since genesis. The tree is
+    /// only updated if it is complete.
+    filter_index_complete: bool,
+    /// The number of blocks below the tip that keep their bodies, if the
+    /// state is pruned.
+    prune_depth: Option<u32>,
+}
+
+impl FinalizedState {
+    /// Opens the finalized state for `network`, creating it if it doesn't
+    /// exist.
+    ///
+    /// If `read_only` is true, the state must already exist, and is never
+    /// upgraded or marked. The last `config.startup_check_depth` blocks are
+    /// checked for corruption.
+    fn new(config: &Config, network: Network, read_only: bool) -> Result<Self, BoxError> {
+        let path = config.db_path(network);
+        if read_only && !path.exists() {
+            Err(format!("there is no state database at {:?}", path))?;
+        }
+
+        let db = config.sled_config(network).open()?;
+        format::check_and_upgrade(&db, &path, read_only)?;
+        format::check_network(&db, network, &path, read_only)?;
+        let address_index_complete =
+            format::check_address_index(&db, config.index_addresses, read_only)?;
+        let filter_index_complete =
+            format::check_filter_index(&db, config.index_compact_filters, read_only)?;
+
+        let state = FinalizedState {
+            hash_by_height: db.open_tree(b"hash_by_height")?,
+            height_by_hash: db.open_tree(b"height_by_hash")?,
+            block_by_height: db.open_tree(b"block_by_height")?,
+            utxo_by_outpoint: db.open_tree(b"utxo_by_outpoint")?,
+            tx_by_hash: db.open_tree(b"tx_by_hash")?,
+            sprout_nullifiers: db.open_tree(b"sprout_nullifiers")?,
+            sapling_nullifiers: db.open_tree(b"sapling_nullifiers")?,
+            orchard_nullifiers: db.open_tree(b"orchard_nullifiers")?,
+            note_commitment_trees_by_height: db.open_tree(b"note_commitment_trees_by_height")?,
+            anchors: db.open_tree(b"anchors")?,
+            value_pools_by_height: db.open_tree(b"value_pools_by_height")?,
+            utxos_by_address: db.open_tree(b"utxos_by_address")?,
+            txids_by_address: db.open_tree(b"txids_by_address")?,
+            header_by_height: db.open_tree(b"header_by_height")?,
+            filter_by_height: db.open_tree(b"filter_by_height")?,
+            db,
+            path,
+            network,
+            index_addresses: config.index_addresses,
+            address_index_complete,
+            index_compact_filters: config.index_compact_filters,
+            filter_index_complete,
+            prune_depth: config.prune_depth,
+        };
+        state.check_integrity(Some(config.startup_check_depth))?;
+
+        Ok(state)
+    }
+
+    /// Commits `block` to the state, and returns its hash.
+    ///
+    /// The block must be the child of the finalized tip, or the genesis block
+    /// if the state is empty, can only spend unspent outputs, and can't make
+    /// a chain value pool negative.
+    fn commit_finalized(&mut self, block: Arc<Block>) -> Result<block::Hash, BoxError> {
+        let hash = block.hash();
+        let height = block
+            .coinbase_height()
+            .ok_or("block has no coinbase height")?;
+
+        match self.tip()? {
+            Some((tip_height, tip_hash)) => {
+                if height.0 != tip_height.0 + 1 || block.header.previous_block_hash != tip_hash {
+                    Err("block is not the child of the finalized tip")?;
+                }
+            }
+            None if height != block::Height(0) => {
+                Err("the first finalized block must be the genesis block")?;
+            }
+            None => {}
+        }
+
+        let height_bytes = height.0.to_be_bytes();
+        let block_bytes = serialize(block.as_ref());
+
+        let mut trees = self.tip_note_commitment_trees()?;
+        trees.append_block(self.network, &block)?;
+        let anchor_entries: Vec<_> = trees
+            .anchors()
+            .into_iter()
+            .map(|(pool, anchor)| {
+                let value = match pool {
+                    Pool::Sprout => serialize(&trees.sprout),
+                    Pool::Sapling | Pool::Orchard => Vec::new(),
+                };
+                (anchor_key(pool, anchor), value)
+            })
+            .collect();
+        let trees_bytes = serialize(&trees);
+        let value_pools = block_value_pools(self.tip_value_pools()?, &block, |outpoint| {
+            self.utxo(outpoint)
+        })?;
+        let value_pools_bytes = serialize(&value_pools);
+        let filter_entry = if self.filter_index_complete {
+            Some(self.filter_entry(&block, height)?)
+        } else {
+            None
+        };
+        let network = self.network;
+        let index_addresses = self.index_addresses;
+
+        let result = (
+            &self.hash_by_height,
+            &self.height_by_hash,
+            &self.block_by_height,
+            &self.utxo_by_outpoint,
+            &self.tx_by_hash,
+            &self.sprout_nullifiers,
+            &self.sapling_nullifiers,
+            &self.orchard_nullifiers,
+            &self.note_commitment_trees_by_height,
+            &self.anchors,
+            &self.value_pools_by_height,
+            &self.utxos_by_address,
+            &self.txids_by_address,
+            &self.filter_by_height,
+        )
+            .transaction(
+                |(
+                    hash_by_height,
+                    height_by_hash,
+                    block_by_height,
+                    utxos,
+                    tx_by_hash,
+                    sprout_nullifiers,
+                    sapling_nullifiers,
+                    orchard_nullifiers,
+                    note_commitment_trees_by_height,
+                    anchors,
+                    value_pools_by_height,
+                    utxos_by_address,
+                    txids_by_address,
+                    filter_by_height,
+                )| {
+                    hash_by_height.insert(&height_bytes[..], &hash.0[..])?;
+                    if let Some(entry) = &filter_entry {
+                        filter_by_height.insert(&height_bytes[..], entry.as_slice())?;
+                    }
+                    note_commitment_trees_by_height
+                        .insert(&height_bytes[..], trees_bytes.as_slice())?;
+                    for (key, value) in &anchor_entries {
+                        anchors.insert(key.as_slice(), value.as_slice())?;
+                    }
+                    value_pools_by_height
+                        .insert(&height_bytes[..], value_pools_bytes.as_slice())?;
+                    height_by_hash.insert(&hash.0[..], &height_bytes[..])?;
+                    block_by_height.insert(&height_bytes[..], block_bytes.as_slice())?;
+
+                    for (index, transaction) in block.transactions.iter().enumerate() {
+                        let mut location = hash.0.to_vec();
+                        location.extend_from_slice(&(index as u32).to_be_bytes());
+                        let tx_hash = transaction::Hash::from(transaction.as_ref());
+                        tx_by_hash.insert(&tx_hash.0[..], location)?;
+                    }
+
+                    // Each nullifier can only be revealed once.
+                    for (pool, nullifier) in nullifiers(&block) {
+                        let tree = match pool {
+                            Pool::Sprout => sprout_nullifiers,
+                            Pool::Sapling => sapling_nullifiers,
+                            Pool::Orchard => orchard_nullifiers,
+                        };
+                        if tree
+                            .insert(&nullifier[..], sled::IVec::default())?
+                            .is_some()
+                        {
+                            return abort(format!(
+                                "block reveals a duplicate nullifier {}",
+                                hex::encode(nullifier)
+                            ));
+                        }
+                    }
+
+                    // The genesis coinbase can't be spent.
+                    if height == block::Height(0) {
+                        return Ok(());
+                    }
+
+                    // Outputs can be spent by later transactions in the same
+                    // block, so each transaction is applied in order.
+                    for (tx_index, transaction) in block.transactions.iter().enumerate() {
+                        let hash = transaction::Hash::from(transaction.as_ref());
+                        let mut addresses = HashSet::new();
+
+                        for input in transaction.inputs() {
+                            if let TransparentInput::PrevOut { outpoint, .. } = input {
+                                let spent = match utxos.remove(serialize(outpoint))? {
+                                    Some(spent) => spent,
+                                    None => {
+                                        return abort(format!(
+                                            "block spends a missing output {:?}",
+                                            outpoint
+                                        ))
+                                    }
+                                };
+
+                                if index_addresses {
+                                    let spent = match TransparentOutput::zcash_deserialize(
+                                        spent.as_ref(),
+                                    ) {
+                                        Ok(spent) => spent,
+                                        Err(error) => return abort(error.to_string()),
+                                    };
+                                    if let Some(address) = spent.pk_script.address(network) {
+                                        let address = serialize(&address);
+                                        utxos_by_address
+                                            .remove(address_key(&address, &serialize(outpoint)))?;
+                                        let _ = addresses.insert(address);
+                                    }
+                                }
+                            }
+                        }
+
+                        for (index, output) in transaction.outputs().enumerate() {
+                            let outpoint = serialize(&OutPoint {
+                                hash,
+                                index: index as u32,
+                            });
+                            utxos.insert(outpoint.as_slice(), serialize(output))?;
+
+                            if index_addresses {
+                                if let Some(address) = output.pk_script.address(network) {
+                                    let address = serialize(&address);
+                                    utxos_by_address.insert(
+                                        address_key(&address, &outpoint),
+                                        sled::IVec::default(),
+                                    )?;
+                                    let _ = addresses.insert(address);
+                                }
+                            }
+                        }
+
+                        let mut location = height_bytes.to_vec();
+                        location.extend_from_slice(&(tx_index as u32).to_be_bytes());
+                        for address in addresses {
+                            txids_by_address
+                                .insert(address_key(&address, &location), &hash.0[..])?;
+                        }
+                    }
+
+                    Ok(())
+                },
+            );
+
+        match result {
+            Ok(()) => {}
+            Err(TransactionError::Abort(error)) => Err(error)?,
+            Err(TransactionError::Storage(error)) => Err(error)?,
+        }
+
+        if let Some(depth) = self.prune_depth {
+            if let Some(pruned) = height.0.checked_sub(depth) {
+                self.prune(block::Height(pruned))?;
+            }
+        }
+
+        Ok(hash)
+    }
+
+    /// Returns the filter tree entry for `block`, which is the child of the
+    /// finalized tip at `height`.
+    fn filter_entry(&self, block: &Block, height: block::Height) -> Result<Vec<u8>, BoxError> {
+        // BIP157 chains the genesis filter header to a zero header.
+        let previous = match height.0.checked_sub(1) {
+            Some(previous) => self
+                .filter_header(block::Height(previous))?
+                .ok_or("filter index is missing the finalized tip")?,
+            None => FilterHeader([0; 32]),
+        };
+        let spent = spent_scripts(block, |outpoint| self.utxo(outpoint))?;
+        let filter = BlockFilter::basic(block, &spent);
+
+        let mut entry = previous.next(filter.hash()).0.to_vec();
+        entry.extend(serialize(&filter));
+        Ok(entry)
+    }
+
+    /// Replaces the finalized block at `height` with its header, and removes
+    /// its transactions from the transaction index.
+    ///
+    /// Does nothing if the block has already been pruned.
+    fn prune(&self, height: block::Height) -> Result<(), BoxError> {
+        let height_bytes = height.0.to_be_bytes();
+        let block = match self.block_by_height.get(&height_bytes[..])? {
+            Some(bytes) => Block::zcash_deserialize(bytes.as_ref())?,
+            None => return Ok(()),
+        };
+        let header_bytes = serialize(&block.header);
+
+        let result: Result<(), TransactionError<String>> = (
+            &self.block_by_height,
+            &self.header_by_height,
+            &self.tx_by_hash,
+        )
+            .transaction(|(block_by_height, header_by_height, tx_by_hash)| {
+                header_by_height.insert(&height_bytes[..], header_bytes.as_slice())?;
+                block_by_height.remove(&height_bytes[..])?;
+                for transaction in &block.transactions {
+                    let tx_hash = transaction::Hash::from(transaction.as_ref());
+                    tx_by_hash.remove(&tx_hash.0[..])?;
+                }
+                Ok(())
+            });
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(TransactionError::Abort(error)) => Err(error.into()),
+            Err(TransactionError::Storage(error)) => Err(error.into()),
+        }
+    }
+
+    /// Returns the height and hash of the finalized tip, if there are any
+    /// blocks in the state.
+    fn tip(&self) -> Result<Option<(block::Height, block::Hash)>, BoxError> {
+        match self.hash_by_height.last()? {
+            Some((height, hash)) => Ok(Some((read_height(&height)?, read_hash(&hash)?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the number of bytes the database uses on disk.
+    fn size_on_disk(&self) -> Result<u64, BoxError> {
+        Ok(self.db.size_on_disk()?)
+    }
+
+    /// Returns the height of the finalized block with `hash`, if it is in the
+    /// state.
+    fn height(&self, hash: block::Hash) -> Result<Option<block::Height>, BoxError> {
+        match self.height_by_hash.get(&hash.0[..])? {
+            Some(height) => Ok(Some(read_height(&height)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the hash of the finalized block at `height`, if it is in the
+    /// state.
+    fn hash(&self, height: block::Height) -> Result<Option<block::Hash>, BoxError> {
+        match self.hash_by_height.get(&height.0.to_be_bytes()[..])? {
+            Some(hash) => Ok(Some(read_hash(&hash)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns true if `nullifier` has been revealed in the `pool` tree of
+    /// the finalized state.
+    fn contains_nullifier(&self, pool: Pool, nullifier: [u8; 32]) -> Result<bool, BoxError> {
+        let tree = match pool {
+            Pool::Sprout => &self.sprout_nullifiers,
+            Pool::Sapling => &self.sapling_nullifiers,
+            Pool::Orchard => &self.orchard_nullifiers,
+        };
+        Ok(tree.contains_key(&nullifier[..])?)
+    }
+
+    /// Returns the note commitment trees after the finalized block with
+    /// `hash_or_height`, if it is in the state.
+    fn note_commitment_trees(
+        &self,
+        hash_or_height: HashOrHeight,
+    ) -> Result<Option<NoteCommitmentTrees>, BoxError> {
+        let height = match hash_or_height {
+            HashOrHeight::Hash(hash) => match self.height(hash)? {
+                Some(height) => height,
+                None => return Ok(None),
+            },
+            HashOrHeight::Height(height) => height,
+        };
+
+        match self
+            .note_commitment_trees_by_height
+            .get(&height.0.to_be_bytes()[..])?
+        {
+            Some(bytes) => Ok(Some(NoteCommitmentTrees::zcash_deserialize(
+                bytes.as_ref(),
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the note commitment trees after the finalized tip, or empty
+    /// trees if the state is empty.
+    fn tip_note_commitment_trees(&self) -> Result<NoteCommitmentTrees, BoxError> {
+        match self.tip()? {
+            Some((height, _)) => Ok(self
+                .note_commitment_trees(height.into())?
+                .ok_or("finalized tip is missing its note commitment trees")?),
+            None => Ok(NoteCommitmentTrees::default()),
+        }
+    }
+
+    /// Returns the Sprout note commitment tree with root `anchor`, if it is
+    /// the root after a finalized block.
+    fn sprout_tree(
+        &self,
+        anchor: &sprout::tree::Root,
+    ) -> Result<Option<sprout::tree::NoteCommitmentTree>, BoxError> {
+        match self.anchors.get(anchor_key(Pool::Sprout, anchor.0))? {
+            Some(bytes) => Ok(Some(sprout::tree::NoteCommitmentTree::zcash_deserialize(
+                bytes.as_ref(),
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns true if `anchor` is the root of the `pool` tree after a
+    /// finalized block.
+    fn contains_anchor(&self, pool: Pool, anchor: [u8; 32]) -> Result<bool, BoxError> {
+        Ok(self.anchors.contains_key(anchor_key(pool, anchor))?)
+    }
+
+    /// Returns the chain value pools after the finalized block with
+    /// `hash_or_height`, if it is in the state.
+    fn value_pools(
+        &self,
+        hash_or_height: HashOrHeight,
+    ) -> Result<Option<ValueBalance<NonNegative>>, BoxError> {
+        let height = match hash_or_height {
+            HashOrHeight::Hash(hash) => match self.height(hash)? {
+                Some(height) => height,
+                None => return Ok(None),
+            },
+            HashOrHeight::Height(height) => height,
+        };
+
+        match self
+            .value_pools_by_height
+            .get(&height.0.to_be_bytes()[..])?
+        {
+            Some(bytes) => Ok(Some(ValueBalance::zcash_deserialize(bytes.as_ref())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the chain value pools after the finalized tip, or empty pools
+    /// if the state is empty.
+    fn tip_value_pools(&self) -> Result<ValueBalance<NonNegative>, BoxError> {
+        match self.tip()? {
+            Some((height, _)) => Ok(self
+                .value_pools(height.into())?
+                .ok_or("finalized tip is missing its chain value pools")?),
+            None => Ok(ValueBalance::zero()),
+        }
+    }
+
+    /// Returns an error if the address index is disabled, or doesn't cover
+    /// every finalized block.
+    fn check_address_index(&self) -> Result<(), BoxError> {
+        if !self.index_addresses {
+            Err("the address index is disabled")?;
+        }
+        if !self.address_index_complete {
+            Err(format!(
+                "the address index in {:?} is missing blocks, because it was enabled after the \
+                 state was synced: delete the directory, and Zebra will resync with the index",
+                self.path
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Returns an error if the filter index is disabled, or doesn't cover
+    /// every finalized block.
+    fn check_filter_index(&self) -> Result<(), BoxError> {
+        if !self.index_compact_filters {
+            Err("the compact filter index is disabled")?;
+        }
+        if !self.filter_index_complete {
+            Err(format!(
+                "the compact filter index in {:?} is missing blocks, because it was enabled \
+                 after the state was synced: delete the directory, and Zebra will resync with \
+                 the index",
+                self.path
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the basic filter and filter header of the finalized block at
+    /// `height`.
+    fn compact_filter(
+        &self,
+        height: block::Height,
+    ) -> Result<Option<(BlockFilter, FilterHeader)>, BoxError> {
+        self.check_filter_index()?;
+
+        match self.filter_by_height.get(&height.0.to_be_bytes()[..])? {
+            Some(entry) if entry.len() >= 32 => Ok(Some((
+                BlockFilter::zcash_deserialize(&entry[32..])?,
+                FilterHeader(read_hash(&entry[..32])?.0),
+            ))),
+            Some(_) => Err("filter index entry is too short")?,
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the filter header of the finalized block at `height`.
+    fn filter_header(&self, height: block::Height) -> Result<Option<FilterHeader>, BoxError> {
+        match self.filter_by_height.get(&height.0.to_be_bytes()[..])? {
+            Some(entry) if entry.len() >= 32 => Ok(Some(FilterHeader(read_hash(&entry[..32])?.0))),
+            Some(_) => Err("filter index entry is too short")?,
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the finalized unspent outputs that pay to `address`.
+    fn address_utxos(
+        &self,
+        address: &Address,
+    ) -> Result<Vec<(OutPoint, TransparentOutput)>, BoxError> {
+        self.check_address_index()?;
+
+        let address = serialize(address);
+        let mut utxos = Vec::new();
+        for entry in self.utxos_by_address.scan_prefix(&address) {
+            let (key, _) = entry?;
+            let outpoint = OutPoint::zcash_deserialize(&key[address.len()..])?;
+            let output = self
+                .utxo(&outpoint)?
+                .ok_or("address index is missing an output")?;
+            utxos.push((outpoint, output));
+        }
+        Ok(utxos)
+    }
+
+    /// Returns the heights and hashes of the finalized transactions that
+    /// spend from or pay to `address`, in chain order.
+    fn address_txids(
+        &self,
+        address: &Address,
+    ) -> Result<Vec<(block::Height, transaction::Hash)>, BoxError> {
+        self.check_address_index()?;
+
+        let address = serialize(address);
+        let mut txids = Vec::new();
+        for entry in self.txids_by_address.scan_prefix(&address) {
+            let (key, hash) = entry?;
+            let location = &key[address.len()..];
+            if location.len() != 8 {
+                Err("address history key has the wrong length")?;
+            }
+            let height = read_height(&location[..4])?;
+            txids.push((height, transaction::Hash(read_hash(&hash)?.0)));
+        }
+        Ok(txids)
+    }
+
+    /// Returns true if the block with `hash` is finalized.
+    fn contains(&self, hash: block::Hash) -> Result<bool, BoxError> {
+        Ok(self.height_by_hash.contains_key(&hash.0)?)
+    }
+
+    /// Returns the finalized block with `hash_or_height`, if it is in the
+    /// state.
+    fn block(&self, hash_or_height: HashOrHeight) -> Result<Option<Arc<Block>>, BoxError> {
+        let height = match hash_or_height {
+            HashOrHeight::Hash(hash) => match self.height_by_hash.get(&hash.0)? {
+                Some(height) => read_height(&height)?,
+                None => return Ok(None),
+            },
+            HashOrHeight::Height(height) => height,
+        };
+
+        match self.block_by_height.get(&height.0.to_be_bytes()[..])? {
+            Some(bytes) => Ok(Some(Block::zcash_deserialize(bytes.as_ref())?.into())),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the header of the finalized block with `hash_or_height`, if it
+    /// is in the state, including pruned blocks.
+    fn header(&self, hash_or_height: HashOrHeight) -> Result<Option<Header>, BoxError> {
+        let height = match hash_or_height {
+            HashOrHeight::Hash(hash) => match self.height(hash)? {
+                Some(height) => height,
+                None => return Ok(None),
+            },
+            HashOrHeight::Height(height) => height,
+        };
+
+        if let Some(block) = self.block(height.into())? {
+            return Ok(Some(block.header));
+        }
+        match self.header_by_height.get(&height.0.to_be_bytes()[..])? {
+            Some(bytes) => Ok(Some(Header::zcash_deserialize(bytes.as_ref())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the finalized transaction with `hash`, and the height of its
+    /// block, if it is in the state.
+    fn transaction(
+        &self,
+        hash: transaction::Hash,
+    ) -> Result<Option<(Arc<Transaction>, block::Height)>, BoxError> {
+        let location = match self.tx_by_hash.get(&hash.0[..])? {
+            Some(location) => location,
+            None => return Ok(None),
+        };
+        if location.len() != 36 {
+            Err("transaction location has the wrong length")?;
+        }
+
+        let block_hash = read_hash(&location[..32])?;
+        let mut index = [0; 4];
+        index.copy_from_slice(&location[32..]);
+        let index = u32::from_be_bytes(index) as usize;
+
+        let block = self
+            .block(block_hash.into())?
+            .ok_or("transaction index is missing a block")?;
+        let height = block
+            .coinbase_height()
+            .ok_or("finalized block has no coinbase height")?;
+        let transaction = block
+            .transactions
+            .get(index)
+            .cloned()
+            .ok_or("transaction index is past the end of the block")?;
+
+        Ok(Some((transaction, height)))
+    }
+
+    /// Returns the unspent output at `outpoint`, if it is unspent after the
+    /// finalized tip.
+    fn utxo(&self, outpoint: &OutPoint) -> Result<Option<TransparentOutput>, BoxError> {
+        match self.utxo_by_outpoint.get(serialize(outpoint))? {
+            Some(bytes) => Ok(Some(TransparentOutput::zcash_deserialize(bytes.as_ref())?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Returns the Zcash serialization of `item`, for use as a key or value.
+/// Returns the `anchors` tree key for `anchor` in `pool`.
+///
+/// Roots from different pools can have the same bytes, so the key starts
+/// with the pool.
+fn anchor_key(pool: Pool, anchor: [u8; 32]) -> Vec<u8> {
+    let pool = match pool {
+        Pool::Sprout => 0u8,
+        Pool::Sapling => 1,
+        Pool::Orchard => 2,
+    };
+    let mut key = vec![pool];
+    key.extend_from_slice(&anchor);
+    key
+}
+
+fn serialize<T: ZcashSerialize + ?Sized>(item: &T) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    item.zcash_serialize(&mut bytes)
+        .expect("serializing into a vec doesn't fail");
+    bytes
+}
+
+/// Returns the chain value pools after `block`, starting from the `pools`
+/// after its parent.
+///
+/// Outputs created earlier in the block are spent directly, and the others
+/// are looked up with `utxo`. Returns an error if the block makes a pool
+/// negative.
+fn block_value_pools(
+    mut pools: ValueBalance<NonNegative>,
+    block: &Block,
+    mut utxo: impl FnMut(&OutPoint) -> Result<Option<TransparentOutput>, BoxError>,
+) -> Result<ValueBalance<NonNegative>, BoxError> {
+    // The genesis coinbase can't be spent, so it isn't in the pools.
+    if block.coinbase_height() == Some(block::Height(0)) {
+        return Ok(pools);
+    }
+
+    let mut created = HashMap::new();
+    for transaction in &block.transactions {
+        let mut spent = HashMap::new();
+        for input in transaction.inputs() {
+            if let TransparentInput::PrevOut { outpoint, .. } = input {
+                let output = match created.remove(outpoint) {
+                    Some(output) => output,
+                    None => utxo(outpoint)?
+                        .ok_or_else(|| format!("block spends a missing output {:?}", outpoint))?,
+                };
+                let _ = spent.insert(*outpoint, output);
+            }
+        }
+
+        pools = pools
+            .add_transaction(transaction.value_balance(&spent)?)
+            .map_err(|error| format!("block makes a chain value pool invalid: {}", error))?;
+
+        let hash = transaction::Hash::from(transaction.as_ref());
+        for (index, output) in transaction.outputs().enumerate() {
+            let outpoint = OutPoint {
+                hash,
+                index: index as u32,
+            };
+            let _ = created.insert(outpoint, output.clone());
+        }
+    }
+
+    Ok(pools)
+}
+
+/// Returns the scripts of the transparent outputs spent by `block`, for its
+/// basic filter.
+///
+/// Outputs from earlier blocks are looked up using `utxo`.
+fn spent_scripts(
+    block: &Block,
+    mut utxo: impl FnMut(&OutPoint) -> Result<Option<TransparentOutput>, BoxError>,
+) -> Result<Vec<Script>, BoxError> {
+    let mut created = HashMap::new();
+    let mut scripts = Vec::new();
+    for transaction in &block.transactions {
+        for input in transaction.inputs() {
+            if let TransparentInput::PrevOut { outpoint, .. } = input {
+                let output = match created.remove(outpoint) {
+                    Some(output) => output,
+                    None => utxo(outpoint)?
+                        .ok_or_else(|| format!("block spends a missing output {:?}", outpoint))?,
+                };
+                scripts.push(output.pk_script);
+            }
+        }
+
+        let hash = transaction::Hash::from(transaction.as_ref());
+        for (index, output) in transaction.outputs().enumerate() {
+            let outpoint = OutPoint {
+                hash,
+                index: index as u32,
+            };
+            let _ = created.insert(outpoint, output.clone());
+        }
+    }
+
+    Ok(scripts)
+}
+
+/// Returns an address tree key: the serialized `address`, then `suffix`.
+///
+/// Serialized addresses all have the same length, so each address's keys
+/// can be found with a prefix scan.
+fn address_key(address: &[u8], suffix: &[u8]) -> Vec<u8> {
+    let mut key = address.to_vec();
+    key.extend_from_slice(suffix);
+    key
+}
+
+/// Decodes a big-endian height key.
+fn read_height(bytes: &[u8]) -> Result<block::Height, BoxError> {
+    let mut height = [0; 4];
+    if bytes.len() != height.len() {
+        Err("height key has the wrong length")?;
+    }
+    height.copy_from_slice(bytes);
+    Ok(block::Height(u32::from_be_bytes(height)))
+}
+
+/// Decodes a block hash key or value.
+fn read_hash(bytes: &[u8]) -> Result<block::Hash, BoxError> {
+    let mut hash = [0; 32];
+    if bytes.len() != hash.len() {
+        Err("block hash has the wrong length")?;
+    }
+    hash.copy_from_slice(bytes);
+    Ok(block::Hash(hash))
+}
+
+/// The state service, with non-finalized chains on top of the finalized
+/// state.
+struct StateService {
+    finalized: FinalizedState,
+    non_finalized: NonFinalizedState,
+    pending_utxos: PendingUtxos,
+    pending_tips: PendingTips,
+    queued_blocks: QueuedBlocks,
+    /// Rejects every request that would change the state.
+    read_only: bool,
+}
+
+impl StateService {
+    /// Adds `block` to the non-finalized chains, then finalizes any blocks
+    /// that are too deep to be rolled back.
+    ///
+    /// The block's parent must be the finalized tip, or in a non-finalized
+    /// chain. Its transparent inputs must spend outputs that are unspent in
+    /// the chain it extends, and it can't make that chain's value pools
+    /// negative.
+    fn commit_non_finalized(&mut self, block: Arc<Block>) -> Result<(), BoxError> {
+        let hash = block.hash();
+        if self.non_finalized.any_chain_contains(&hash) || self.finalized.contains(hash)? {
+            Err("block is already in the state")?;
+        }
+
+        // The genesis block extends the empty state.
+        let finalized_tip = match self.finalized.tip()? {
+            Some((_, hash)) => Some(hash),
+            None if block.coinbase_height() == Some(block::Height(0)) => {
+                Some(block.header.previous_block_hash)
+            }
+            None => None,
+        };
+        let mut chain = self
+            .non_finalized
+            .parent_chain(block.header.previous_block_hash, finalized_tip)
+            .ok_or("block's parent is not in the state")?;
+
+        // Outputs can be spent by later transactions in the same block.
+        let mut created = HashSet::new();
+        let mut spent = HashSet::new();
+        for transaction in &block.transactions {
+            for input in transaction.inputs() {
+                if let TransparentInput::PrevOut { outpoint, .. } = input {
+                    if !spent.insert(*outpoint) {
+                        Err(format!("block spends {:?} more than once", outpoint))?;
+                    }
+                    let unspent = created.remove(outpoint)
+                        || chain.created_utxo(outpoint).is_some()
+                        || (!chain.is_spent(outpoint) && self.finalized.utxo(outpoint)?.is_some());
+                    if !unspent {
+                        Err(format!("block spends a missing output {:?}", outpoint))?;
+                    }
+                }
+            }
+
+            let hash = transaction::Hash::from(transaction.as_ref());
+            for index in 0..transaction.outputs().count() {
+                let _ = created.insert(OutPoint {
+                    hash,
+                    index: index as u32,
+                });
+            }
+        }
+
+        let mut revealed = HashSet::new();
+        for (pool, nullifier) in nullifiers(&block) {
+            if !revealed.insert((pool, nullifier))
+                || chain.contains_nullifier(pool, nullifier)
+                || self.finalized.contains_nullifier(pool, nullifier)?
+            {
+                Err(format!(
+                    "block reveals a duplicate nullifier {}",
+                    hex::encode(nullifier)
+                ))?;
+            }
+        }
+
+        let parent_pools = match chain.value_pools() {
+            Some(pools) => pools,
+            None => self.finalized.tip_value_pools()?,
+        };
+        let finalized = &self.finalized;
+        let value_pools = block_value_pools(parent_pools, &block, |outpoint| {
+            match chain.created_utxo(outpoint) {
+                Some(output) => Ok(Some(output)),
+                None if chain.is_spent(outpoint) => Ok(None),
+                None => finalized.utxo(outpoint),
+            }
+        })?;
+
+        let mut trees = match chain.note_commitment_trees() {
+            Some(trees) => trees.clone(),
+            None => self.finalized.tip_note_commitment_trees()?,
+        };
+        trees.append_block(self.finalized.network, &block)?;
+
+        let old_tip = self
+            .non_finalized
+            .best_chain()
+            .and_then(Chain::tip)
+            .map(|(_, hash)| hash);
+
+        self.pending_utxos.check_block(&block);
+        chain.push(block, trees, value_pools);
+        self.non_finalized.insert(chain);
+
+        if let Some(depth) = old_tip.and_then(|tip| self.non_finalized.reorg_depth(tip)) {
+            metrics::counter!("state.reorgs", 1);
+            metrics::histogram!("state.reorg_depth", depth as u64);
+        }
+
+        while let Some(block) = self.non_finalized.finalize() {
+            let _ = self.finalized.commit_finalized(block)?;
+        }
+
+        Ok(())
+    }
+
+    /// Commits every block in the best chain to the finalized state, and
+    /// returns the new finalized tip.
+    fn finalize_best_chain(&mut self) -> Result<Response, BoxError> {
+        while let Some(block) = self.non_finalized.finalize_root() {
+            let _ = self.finalized.commit_finalized(block)?;
+        }
+
+        Ok(Response::Finalized {
+            tip: self.finalized.tip()?,
+        })
+    }
+
+    /// Updates the gauges for the tips, chains, queue, and database size.
+    fn update_metrics(&self) {
+        if let Ok(Some((height, _))) = self.finalized.tip() {
+            metrics::gauge!("state.finalized.tip_height", height.0 as i64);
+        }
+        if let Some((height, _)) = self.non_finalized.best_chain().and_then(Chain::tip) {
+            metrics::gauge!("state.non_finalized.tip_height", height.0 as i64);
+        }
+        metrics::gauge!(
+            "state.non_finalized.chains",
+            self.non_finalized.chain_count() as i64
+        );
+        metrics::gauge!("state.queued_blocks", self.queued_blocks.len() as i64);
+        if let Ok(bytes) = self.finalized.size_on_disk() {
+            metrics::gauge!("state.disk_bytes", bytes as i64);
+        }
+    }
+
+    /// Returns true if the parent of `block` has been committed, or `block`
+    /// is the genesis block.
+    fn contains_parent(&self, block: &Block) -> Result<bool, BoxError> {
+        let parent = block.header.previous_block_hash;
+        Ok(block.coinbase_height() == Some(block::Height(0))
+            || self.non_finalized.any_chain_contains(&parent)
+            || self.finalized.contains(parent)?)
+    }
+
+    /// Commits `block`, then commits any queued blocks that were waiting for
+    /// it, or for one of their committed ancestors.
+    fn commit_and_dequeue(&mut self, block: Arc<Block>) -> Result<(), BoxError> {
+        let hash = block.hash();
+        self.commit_non_finalized(block)?;
+
+        let mut parents = vec![hash];
+        while let Some(parent) = parents.pop() {
+            for (child, tx) in self.queued_blocks.dequeue_children(parent) {
+                let hash = child.hash();
+                let result = self.commit_non_finalized(child);
+                if result.is_ok() {
+                    parents.push(hash);
+                }
+                let _ = tx.send(result);
+            }
+        }
+
+        let finalized_height = self.finalized.tip()?.map(|(height, _)| height);
+        self.queued_blocks.prune(finalized_height);
+
+        Ok(())
+    }
+
+    /// Returns the block with `hash_or_height`, if it is in the non-finalized
+    /// or finalized state.
+    ///
+    /// Hashes are found in any non-finalized chain, and heights are found in
+    /// the best chain.
+    fn block(&self, hash_or_height: HashOrHeight) -> Result<Option<Arc<Block>>, BoxError> {
+        match self.non_finalized.block(hash_or_height) {
+            Some(block) => Ok(Some(block)),
+            None => self.finalized.block(hash_or_height),
+        }
+    }
+
+    /// Returns the header of the block with `hash_or_height`, using the same
+    /// lookups as [`StateService::block`].
+    ///
+    /// Unlike [`StateService::block`], this finds pruned blocks.
+    fn header(&self, hash_or_height: HashOrHeight) -> Result<Option<Header>, BoxError> {
+        match self.non_finalized.block(hash_or_height) {
+            Some(block) => Ok(Some(block.header)),
+            None => self.finalized.header(hash_or_height),
+        }
+    }
+
+    /// Returns the transaction with `hash`, and the height of its block, if
+    /// it is in the best chain, or the finalized state.
+    fn transaction(
+        &self,
+        hash: transaction::Hash,
+    ) -> Result<Option<(Arc<Transaction>, block::Height)>, BoxError> {
+        match self
+            .non_finalized
+            .best_chain()
+            .and_then(|chain| chain.transaction(hash))
+        {
+            Some(found) => Ok(Some(found)),
+            None => self.finalized.transaction(hash),
+        }
+    }
+
+    /// Returns the Sapling note commitment tree after the block with `hash`,
+    /// if it is in any chain, or the finalized state.
+    fn sapling_tree(
+        &self,
+        hash: block::Hash,
+    ) -> Result<Option<sapling::tree::NoteCommitmentTree>, BoxError> {
+        match self.non_finalized.sapling_tree(&hash) {
+            Some(tree) => Ok(Some(tree)),
+            None => Ok(self
+                .finalized
+                .note_commitment_trees(hash.into())?
+                .map(|trees| trees.sapling)),
+        }
+    }
+
+    /// Returns the note commitment trees after each non-finalized block in
+    /// the chain ending at the block with `tip`, or in the best chain if
+    /// `tip` is `None`.
+    ///
+    /// Returns an error if `tip` isn't in the state. If `tip` is finalized,
+    /// there are no non-finalized trees in its chain.
+    fn chain_trees(&self, tip: Option<block::Hash>) -> Result<Vec<&NoteCommitmentTrees>, BoxError> {
+        if let Some(trees) = self.non_finalized.chain_trees(tip) {
+            return Ok(trees);
+        }
+        match tip {
+            Some(tip) if self.finalized.contains(tip)? => Ok(Vec::new()),
+            _ => Err(format!("the anchor tip {:?} is not in the state", tip).into()),
+        }
+    }
+
+    /// Returns the Sprout note commitment tree with root `anchor`, if it is
+    /// the root after a block in the chain ending at `tip`, or in the best
+    /// chain if `tip` is `None`.
+    ///
+    /// Finalized blocks are in every chain, so their roots are always found.
+    /// Children of finalized blocks below the finalized tip are never
+    /// committed, so it doesn't matter that the later finalized roots are
+    /// found too.
+    fn sprout_tree(
+        &self,
+        anchor: &sprout::tree::Root,
+        tip: Option<block::Hash>,
+    ) -> Result<Option<sprout::tree::NoteCommitmentTree>, BoxError> {
+        match self
+            .chain_trees(tip)?
+            .into_iter()
+            .find(|trees| trees.sprout.root() == *anchor)
+        {
+            Some(trees) => Ok(Some(trees.sprout.clone())),
+            None => self.finalized.sprout_tree(anchor),
+        }
+    }
+
+    /// Returns the Orchard note commitment tree after the block with `hash`,
+    /// if it is in any chain, or the finalized state.
+    fn orchard_tree(
+        &self,
+        hash: block::Hash,
+    ) -> Result<Option<orchard::tree::NoteCommitmentTree>, BoxError> {
+        match self.non_finalized.orchard_tree(&hash) {
+            Some(tree) => Ok(Some(tree)),
+            None => Ok(self
+                .finalized
+                .note_commitment_trees(hash.into())?
+                .map(|trees| trees.orchard)),
+        }
+    }
+
+    /// Returns the chain history tree after the block with `hash`, if it is
+    /// in the state.
+    fn history_tree(&self, hash: block::Hash) -> Result<Option<HistoryTree>, BoxError> {
+        match self.non_finalized.history_tree(&hash) {
+            Some(tree) => Ok(Some(tree)),
+            None => Ok(self
+                .finalized
+                .note_commitment_trees(hash.into())?
+                .map(|trees| trees.history)),
+        }
+    }
+
+    /// Returns true if `anchor` is the root of the `pool` tree after a block
+    /// in the chain ending at `tip`, or in the best chain if `tip` is `None`.
+    ///
+    /// Like [`StateService::sprout_tree`], the finalized roots are always
+    /// found.
+    fn contains_anchor(
+        &self,
+        pool: Pool,
+        anchor: [u8; 32],
+        tip: Option<block::Hash>,
+    ) -> Result<bool, BoxError> {
+        Ok(self
+            .chain_trees(tip)?
+            .iter()
+            .any(|trees| trees.anchors().contains(&(pool, anchor)))
+            || self.finalized.contains_anchor(pool, anchor)?)
+    }
+
+    /// Returns true if the best chain reveals `nullifier` in `pool`.
+    fn contains_nullifier(&self, pool: Pool, nullifier: [u8; 32]) -> Result<bool, BoxError> {
+        Ok(self
+            .non_finalized
+            .best_chain()
+            .map_or(false, |chain| chain.contains_nullifier(pool, nullifier))
+            || self.finalized.contains_nullifier(pool, nullifier)?)
+    }
+
+    /// Returns the chain value pools after the block with `hash`, if it is in
+    /// any chain, or the finalized state.
+    fn value_pools(
+        &self,
+        hash: block::Hash,
+    ) -> Result<Option<ValueBalance<NonNegative>>, BoxError> {
+        match self.non_finalized.value_pools(&hash) {
+            Some(pools) => Ok(Some(pools)),
+            None => self.finalized.value_pools(hash.into()),
+        }
+    }
+
+    /// Returns the unspent outputs that pay to `address` in the best chain.
+    fn address_utxos(
+        &self,
+        address: &Address,
+    ) -> Result<Vec<(OutPoint, TransparentOutput)>, BoxError> {
+        let mut utxos = self.finalized.address_utxos(address)?;
+
+        if let Some(chain) = self.non_finalized.best_chain() {
+            utxos.retain(|(outpoint, _)| !chain.is_spent(outpoint));
+            let network = self.finalized.network;
+            utxos.extend(
+                chain
+                    .unspent_utxos()
+                    .filter(|(_, output)| output.pk_script.address(network) == Some(*address))
+                    .map(|(outpoint, output)| (*outpoint, output.clone())),
+            );
+        }
+
+        Ok(utxos)
+    }
+
+    /// Returns the heights and hashes of the best chain transactions that
+    /// spend from or pay to `address`, in chain order.
+    ///
+    /// Non-finalized blocks aren't indexed, because they can be rolled back,
+    /// so they are searched instead.
+    fn address_txids(
+        &self,
+        address: &Address,
+    ) -> Result<Vec<(block::Height, transaction::Hash)>, BoxError> {
+        let mut txids = self.finalized.address_txids(address)?;
+
+        let chain = match self.non_finalized.best_chain() {
+            Some(chain) => chain,
+            None => return Ok(txids),
+        };
+        let network = self.finalized.network;
+        let pays_to =
+            |output: &TransparentOutput| output.pk_script.address(network) == Some(*address);
+
+        for (height, block) in chain.blocks() {
+            for transaction in &block.transactions {
+                let mut matches = transaction.outputs().any(pays_to);
+                for input in transaction.inputs() {
+                    if let TransparentInput::PrevOut { outpoint, .. } = input {
+                        let spent = match chain.created_output(outpoint) {
+                            Some(output) => Some(output.clone()),
+                            None => self.finalized.utxo(outpoint)?,
+                        };
+                        matches |= spent.as_ref().map(pays_to).unwrap_or(false);
+                    }
+                }
+
+                if matches {
+                    txids.push((*height, transaction.as_ref().into()));
+                }
+            }
+        }
+
+        Ok(txids)
+    }
+
+    /// Returns the heights, hashes, basic filters, and filter headers of the
+    /// blocks in the best non-finalized chain, in height order.
+    ///
+    /// Non-finalized blocks aren't indexed, because they can be rolled back,
+    /// so their filters are built from the chain instead.
+    fn non_finalized_filters(
+        &self,
+    ) -> Result<Vec<(block::Height, block::Hash, BlockFilter, FilterHeader)>, BoxError> {
+        let chain = match self.non_finalized.best_chain() {
+            Some(chain) => chain,
+            None => return Ok(Vec::new()),
+        };
+        let mut header = match self.finalized.tip()? {
+            Some((height, _)) => self
+                .finalized
+                .filter_header(height)?
+                .ok_or("filter index is missing the finalized tip")?,
+            None => FilterHeader([0; 32]),
+        };
+
+        let mut filters = Vec::new();
+        for (height, block) in chain.blocks() {
+            let spent = spent_scripts(block, |outpoint| match chain.created_output(outpoint) {
+                Some(output) => Ok(Some(output.clone())),
+                None => self.finalized.utxo(outpoint),
+            })?;
+            let filter = BlockFilter::basic(block, &spent);
+            header = header.next(filter.hash());
+            filters.push((*height, block.hash(), filter, header));
+        }
+        Ok(filters)
+    }
+
+    /// Returns the filter header of the best chain block before
+    /// `start_height`, then the hashes, basic filters, and filter headers of
+    /// the best chain blocks from `start_height` up to and including `stop`.
+    ///
+    /// Returns `None` if `stop` isn't in the best chain, or is below
+    /// `start_height`.
+    fn compact_filters(
+        &self,
+        start_height: block::Height,
+        stop: block::Hash,
+    ) -> Result<Option<CompactFilters>, BoxError> {
+        self.finalized.check_filter_index()?;
+
+        let stop_height = match self.best_chain_height(stop)? {
+            Some(stop_height) if stop_height >= start_height => stop_height,
+            _ => return Ok(None),
+        };
+        if stop_height.0 - start_height.0 >= MAX_COMPACT_FILTERS_RESULTS {
+            Err(format!(
+                "compact filter requests are limited to {} blocks",
+                MAX_COMPACT_FILTERS_RESULTS
+            ))?;
+        }
+
+        let finalized_tip = self.finalized.tip()?.map(|(height, _)| height);
+        let is_finalized = |height: block::Height| finalized_tip.map_or(false, |tip| height <= tip);
+        let non_finalized = if is_finalized(stop_height) {
+            Vec::new()
+        } else {
+            self.non_finalized_filters()?
+        };
+        let filter = |height: block::Height| -> Result<_, BoxError> {
+            if is_finalized(height) {
+                let hash = self
+                    .finalized
+                    .hash(height)?
+                    .ok_or("finalized state is missing a block")?;
+                let (filter, header) = self
+                    .finalized
+                    .compact_filter(height)?
+                    .ok_or("filter index is missing a finalized block")?;
+                Ok((hash, filter, header))
+            } else {
+                non_finalized
+                    .iter()
+                    .find(|(filter_height, ..)| *filter_height == height)
+                    .map(|(_, hash, filter, header)| (*hash, filter.clone(), *header))
+                    .ok_or_else(|| "best chain is missing a block".into())
+            }
+        };
+
+        let previous = match start_height.0.checked_sub(1) {
+            Some(previous) => filter(block::Height(previous))?.2,
+            None => FilterHeader([0; 32]),
+        };
+        let filters = (start_height.0..=stop_height.0)
+            .map(|height| filter(block::Height(height)))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Some(CompactFilters { previous, filters }))
+    }
+
+    /// Returns the filter headers of the best chain blocks at every
+    /// [`COMPACT_FILTER_CHECKPOINT_INTERVAL`]th height, up to and including
+    /// `stop`.
+    ///
+    /// Returns `None` if `stop` isn't in the best chain.
+    fn compact_filter_checkpoints(
+        &self,
+        stop: block::Hash,
+    ) -> Result<Option<Vec<FilterHeader>>, BoxError> {
+        self.finalized.check_filter_index()?;
+
+        let stop_height = match self.best_chain_height(stop)? {
+            Some(stop_height) => stop_height,
+            None => return Ok(None),
+        };
+        let finalized_tip = self.finalized.tip()?.map(|(height, _)| height);
+        let non_finalized = if finalized_tip.map_or(false, |tip| stop_height <= tip) {
+            Vec::new()
+        } else {
+            self.non_finalized_filters()?
+        };
+
+        let mut headers = Vec::new();
+        let mut height = COMPACT_FILTER_CHECKPOINT_INTERVAL;
+        while height <= stop_height.0 {
+            let header = if finalized_tip.map_or(false, |tip| height <= tip.0) {
+                self.finalized.filter_header(block::Height(height))?
+            } else {
+                non_finalized
+                    .iter()
+                    .find(|(filter_height, ..)| filter_height.0 == height)
+                    .map(|(.., header)| *header)
+            };
+            headers.push(header.ok_or("filter index is missing a checkpoint")?);
+            height += COMPACT_FILTER_CHECKPOINT_INTERVAL;
+        }
+        Ok(Some(headers))
+    }
+
+    /// Returns the height and hash of the best chain tip.
+    fn tip(&self) -> Result<Option<(block::Height, block::Hash)>, BoxError> {
+        match self.non_finalized.best_chain().and_then(Chain::tip) {
+            Some(tip) => Ok(Some(tip)),
+            None => self.finalized.tip(),
+        }
+    }
+
+    /// Returns the number of blocks above the block with `hash`, if it is in
+    /// the best chain.
+    fn depth(&self, hash: block::Hash) -> Result<Option<u32>, BoxError> {
+        let tip_height = match self.tip()? {
+            Some((height, _)) => height,
+            None => return Ok(None),
+        };
+
+        let height = match self
+            .non_finalized
+            .best_chain()
+            .and_then(|chain| chain.height(&hash))
+        {
+            Some(height) => Some(height),
+            None => self.finalized.height(hash)?,
+        };
+
+        Ok(height.map(|height| tip_height.0 - height.0))
+    }
+
+    /// Returns the hash of the best chain block at `height`.
+    fn best_chain_hash(&self, height: block::Height) -> Result<Option<block::Hash>, BoxError> {
+        match self
+            .non_finalized
+            .best_chain()
+            .and_then(|chain| chain.block(height.into()))
+        {
+            Some(block) => Ok(Some(block.hash())),
+            None => self.finalized.hash(height),
+        }
+    }
+
+    /// Returns the height of the block with `hash`, if it is in the best
+    /// chain, or the finalized state.
+    fn best_chain_height(&self, hash: block::Hash) -> Result<Option<block::Height>, BoxError> {
+        match self
+            .non_finalized
+            .best_chain()
+            .and_then(|chain| chain.height(&hash))
+        {
+            Some(height) => Ok(Some(height)),
+            None => self.finalized.height(hash),
+        }
+    }
+
+    /// Returns the heights and hashes of up to `max_len` best chain blocks
+    /// after the first block in `known_blocks` that is in the best chain,
+    /// ending early at `stop`.
+    ///
+    /// If none of the known blocks are in the best chain, returns the blocks
+    /// after the genesis block.
+    fn find_chain_hashes(
+        &self,
+        known_blocks: &[block::Hash],
+        stop: Option<block::Hash>,
+        max_len: usize,
+    ) -> Result<Vec<(block::Height, block::Hash)>, BoxError> {
+        let tip = match self.tip()? {
+            Some((tip, _)) => tip,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut start = block::Height(0);
+        for hash in known_blocks {
+            if let Some(height) = self.best_chain_height(*hash)? {
+                start = height;
+                break;
+            }
+        }
+
+        let mut hashes = Vec::new();
+        for height in (start.0 + 1..=tip.0).map(block::Height).take(max_len) {
+            let hash = self
+                .best_chain_hash(height)?
+                .ok_or("best chain is missing a block below the tip")?;
+            hashes.push((height, hash));
+            if Some(hash) == stop {
+                break;
+            }
+        }
+        Ok(hashes)
+    }
+
+    /// Returns a block locator for the best chain, or an empty locator if
+    /// the state is empty.
+    fn block_locator(&self) -> Result<Vec<block::Hash>, BoxError> {
+        let tip = match self.tip()? {
+            Some((tip, _)) => tip,
+            None => return Ok(Vec::new()),
+        };
+
+        block_locator_heights(tip)
+            .into_iter()
+            .map(|height| {
+                self.best_chain_hash(height)?
+                    .ok_or_else(|| "best chain is missing a block below the tip".into())
+            })
+            .collect()
+    }
+
+    /// Returns the output at `outpoint`, if it is unspent in the best chain.
+    fn utxo(&self, outpoint: &OutPoint) -> Result<Option<TransparentOutput>, BoxError> {
+        match self.non_finalized.best_chain() {
+            Some(chain) if chain.is_spent(outpoint) => Ok(None),
+            Some(chain) => match chain.created_utxo(outpoint) {
+                Some(output) => Ok(Some(output)),
+                None => self.finalized.utxo(outpoint),
+            },
+            None => self.finalized.utxo(outpoint),
+        }
+    }
+
+    /// Returns the response to `req`.
+    fn respond(&mut self, req: Request) -> <Self as Service<Request>>::Future {
+        match req {
+            Request::CommitFinalizedBlock { .. }
+            | Request::AddBlock { .. }
+            | Request::FinalizeBestChain
+                if self.read_only =>
+            {
+                let result = Err("the state was opened read-only".into());
+
+                async move { result }.boxed()
+            }
+            Request::CommitFinalizedBlock { block } => {
+                let result = if self.non_finalized.is_empty() {
+                    self.finalized
+                        .commit_finalized(block.clone())
+                        .map(|hash| Response::Committed { hash })
+                } else {
+                    Err("finalized blocks can't be committed on top of non-finalized blocks".into())
+                };
+
+                if result.is_ok() {
+                    self.pending_utxos.check_block(&block);
+                }
+
+                async { result }.boxed()
+            }
+            Request::AddBlock { block } => match self.contains_parent(&block) {
+                Ok(true) => {
+                    let result = self.commit_and_dequeue(block).map(|_| Response::Added);
+
+                    async { result }.boxed()
+                }
+                Ok(false) => match self.queued_blocks.queue(block) {
+                    Ok(committed) => {
+                        async move { committed.await.map(|_| Response::Added) }.boxed()
+                    }
+                    Err(error) => async move { Err(error) }.boxed(),
+                },
+                Err(error) => async move { Err(error) }.boxed(),
+            },
+            Request::GetBlock { hash } => {
+                let result = self.block(hash.into()).and_then(|block| {
+                    block
+                        .map(|block| Response::Block { block })
+                        .ok_or_else(|| "block could not be found".into())
+                });
+
+                async move { result }.boxed()
+            }
+            Request::Block { hash_or_height } => {
+                let result = self.block(hash_or_height).and_then(|block| {
+                    block
+                        .map(|block| Response::Block { block })
+                        .ok_or_else(|| "block could not be found".into())
+                });
+
+                async move { result }.boxed()
+            }
+            Request::BlockHeader { hash_or_height } => {
+                let result = self.header(hash_or_height).and_then(|header| {
+                    header
+                        .map(|header| Response::BlockHeader { header })
+                        .ok_or_else(|| "block could not be found".into())
+                });
+
+                async move { result }.boxed()
+            }
+            Request::ContainsSaplingAnchor { anchor, tip } => {
+                let result = self
+                    .contains_anchor(Pool::Sapling, anchor.0, tip)
+                    .map(|contains| Response::ContainsAnchor { contains });
+
+                async move { result }.boxed()
+            }
+            Request::GetSproutTree { anchor, tip } => {
+                let result = self
+                    .sprout_tree(&anchor, tip)
+                    .map(|tree| Response::SproutTree { tree });
+
+                async move { result }.boxed()
+            }
+            Request::ContainsOrchardAnchor { anchor, tip } => {
+                let result = self
+                    .contains_anchor(Pool::Orchard, anchor.0, tip)
+                    .map(|contains| Response::ContainsAnchor { contains });
+
+                async move { result }.boxed()
+            }
+            Request::ContainsSproutNullifier { nullifier } => {
+                let result = self
+                    .contains_nullifier(Pool::Sprout, nullifier.0)
+                    .map(|contains| Response::ContainsNullifier { contains });
+
+                async move { result }.boxed()
+            }
+            Request::ContainsSaplingNullifier { nullifier } => {
+                let result = self
+                    .contains_nullifier(Pool::Sapling, nullifier.0)
+                    .map(|contains| Response::ContainsNullifier { contains });
+
+                async move { result }.boxed()
+            }
+            Request::ContainsOrchardNullifier { nullifier } => {
+                let result = self
+                    .contains_nullifier(Pool::Orchard, nullifier.0)
+                    .map(|contains| Response::ContainsNullifier { contains });
+
+                async move { result }.boxed()
+            }
+            Request::CheckIntegrity { depth } => {
+                let result = self
+                    .finalized
+                    .check_integrity(depth)
+                    .map(|_| Response::Checked);
+
+                async move { result }.boxed()
+            }
+            Request::Flush => {
+                // Blocks in the non-finalized state are only kept in memory,
+                // so they are downloaded again after a restart.
+                let result = self
+                    .finalized
+                    .db
+                    .flush()
+                    .map(|_| Response::Flushed)
+                    .map_err(Into::into);
+
+                async move { result }.boxed()
+            }
+            Request::FinalizeBestChain => {
+                let result = self.finalize_best_chain();
+
+                async move { result }.boxed()
+            }
+            Request::GetSaplingTree { hash } => {
+                let result = self
+                    .sapling_tree(hash)
+                    .map(|tree| Response::SaplingTree { tree });
+
+                async move { result }.boxed()
+            }
+            Request::GetOrchardTree { hash } => {
+                let result = self
+                    .orchard_tree(hash)
+                    .map(|tree| Response::OrchardTree { tree });
+
+                async move { result }.boxed()
+            }
+            Request::GetHistoryTree { hash } => {
+                let result = self
+                    .history_tree(hash)
+                    .map(|tree| Response::HistoryTree { tree });
+
+                async move { result }.boxed()
+            }
+            Request::GetChainValuePools { hash } => {
+                let result = self
+                    .value_pools(hash)
+                    .map(|pools| Response::ChainValuePools { pools });
+
+                async move { result }.boxed()
+            }
+            Request::FindBlockHashes { known_blocks, stop } => {
+                let result = self
+                    .find_chain_hashes(&known_blocks, stop, MAX_FIND_BLOCK_HASHES_RESULTS)
+                    .map(|hashes| Response::BlockHashes {
+                        hashes: hashes.into_iter().map(|(_, hash)| hash).collect(),
+                    });
+
+                async move { result }.boxed()
+            }
+            Request::FindBlockHeaders { known_blocks, stop } => {
+                let result = self
+                    .find_chain_hashes(&known_blocks, stop, MAX_FIND_BLOCK_HEADERS_RESULTS)
+                    .and_then(|hashes| {
+                        hashes
+                            .into_iter()
+                            .map(|(height, _)| {
+                                self.header(height.into())?
+                                    .ok_or_else(|| "best chain is missing a header".into())
+                            })
+                            .collect::<Result<Vec<_>, BoxError>>()
+                    })
+                    .map(|headers| Response::BlockHeaders { headers });
+
+                async move { result }.boxed()
+            }
+            Request::AddressBalance { address } => {
+                let result = self.address_utxos(&address).and_then(|utxos| {
+                    let balance: Result<Amount<NonNegative>, _> =
+                        utxos.iter().map(|(_, output)| output.value).sum();
+                    Ok(Response::AddressBalance { balance: balance? })
+                });
+
+                async move { result }.boxed()
+            }
+            Request::AddressUtxos { address } => {
+                let result = self
+                    .address_utxos(&address)
+                    .map(|utxos| Response::AddressUtxos { utxos });
+
+                async move { result }.boxed()
+            }
+            Request::AddressTxIds { address } => {
+                let result = self
+                    .address_txids(&address)
+                    .map(|txids| Response::AddressTxIds { txids });
+
+                async move { result }.boxed()
+            }
+            Request::CompactFilters { start_height, stop } => {
+                let result = self
+                    .compact_filters(start_height, stop)
+                    .map(|filters| Response::CompactFilters { filters });
+
+                async move { result }.boxed()
+            }
+            Request::CompactFilterCheckpoints { stop } => {
+                let result = self
+                    .compact_filter_checkpoints(stop)
+                    .map(|headers| Response::CompactFilterCheckpoints { headers });
+
+                async move { result }.boxed()
+            }
+            Request::Tip => {
+                let result = self.tip().map(|tip| Response::BestTip { tip });
+
+                async move { result }.boxed()
+            }
+            Request::AwaitTipChange { tip: known } => {
+                let pending = match self.tip() {
+                    Ok(tip) if tip.map(|(_, hash)| hash) != known => async move { Ok(tip) }.boxed(),
+                    Ok(_) => self.pending_tips.queue(known).boxed(),
+                    Err(error) => async move { Err(error) }.boxed(),
+                };
+
+                async move {
+                    let tip = pending.await?;
+                    Ok(Response::BestTip { tip })
+                }
+                .boxed()
+            }
+            Request::Depth { hash } => {
+                let result = self.depth(hash).map(|depth| Response::Depth { depth });
+
+                async move { result }.boxed()
+            }
+            Request::BestChainBlockHash { height } => {
+                let result = self
+                    .best_chain_hash(height)
+                    .map(|hash| Response::BlockHash { hash });
+
+                async move { result }.boxed()
+            }
+            Request::BlockLocator => {
+                let result = self
+                    .block_locator()
+                    .map(|hashes| Response::BlockLocator { hashes });
+
+                async move { result }.boxed()
+            }
+            Request::Transaction { hash } => {
+                let result = self
+                    .transaction(hash)
+                    .map(|transaction| Response::Transaction { transaction });
+
+                async move { result }.boxed()
+            }
+            Request::GetTip => {
+                let result = self.tip().and_then(|tip| {
+                    tip.map(|(_, hash)| Response::Tip { hash })
+                        .ok_or_else(|| "zebra-state contains no blocks".into())
+                });
+
+                async move { result }.boxed()
+            }
+            Request::GetUtxo { outpoint } => {
+                let result = self.utxo(&outpoint).map(|output| Response::Utxo { output });
+
+                async move { result }.boxed()
+            }
+            Request::AwaitUtxo { outpoint } => {
+                self.pending_utxos.prune();
+                let pending = match self.utxo(&outpoint) {
+                    Ok(Some(output)) => async move { Ok(output) }.boxed(),
+                    Ok(None) => self.pending_utxos.queue(outpoint).boxed(),
+                    Err(error) => async move { Err(error) }.boxed(),
+                };
+
+                async move {
+                    let output = pending.await?;
+                    Ok(Response::Utxo {
+                        output: Some(output),
+                    })
+                }
+                .boxed()
+            }
+        }
+    }
+}
+
+impl Service<Request> for StateService {
+    type Response = Response;
+    type Error = BoxError;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Wait for a queued block to be committed, or dropped, rather than
+        // rejecting new blocks.
+        self.queued_blocks.poll_ready(cx).map(Ok)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let kind = req.kind();
+        let commits = matches!(
+            req,
+            Request::AddBlock { .. }
+                | Request::CommitFinalizedBlock { .. }
+                | Request::FinalizeBestChain
+        );
+
+        let started = Instant::now();
+        let response = self.respond(req);
+        metrics::histogram!(
+            "state.request_duration_us",
+            started.elapsed().as_micros() as u64,
+            "request" => kind,
+        );
+
+        if commits {
+            self.update_metrics();
+            if let Ok(tip) = self.tip() {
+                self.pending_tips.check_tip(tip);
+            }
+        }
+        response
+    }
+}
+
+/// Returns a state service for `network`, which stores finalized blocks on
+/// disk, and keeps the last [`MAX_NON_FINALIZED_BLOCKS`] blocks in memory, so
+/// they can be rolled back.
+///
+/// The finalized state is stored in the `network` subdirectory of
+/// `config.cache_dir`, and persists between runs.
+pub fn init(
+    config: Config,
+    network: Network,
+) -> Result<
+    impl Service<
+            Request,
+            Response = Response,
+            Error = BoxError,
+            Future = impl Future<Output = Result<Response, BoxError>>,
+        > + Send
+        + Clone
+        + 'static,
+    BoxError,
+> {
+    let state = StateService {
+        finalized: FinalizedState::new(&config, network, false)?,
+        non_finalized: NonFinalizedState::default(),
+        pending_utxos: PendingUtxos::default(),
+        pending_tips: PendingTips::default(),
+        queued_blocks: QueuedBlocks::default(),
+        read_only: false,
+    };
+
+    Ok(Buffer::new(state, 1))
+}
+
+/// Returns a read-only state service for an existing finalized state, so
+/// tools like block explorers can query a stopped node's database.
+///
+/// The database is never created, upgraded, or written, and requests that
+/// would add blocks return an error. Non-finalized blocks are only kept in
+/// the node's memory, so they aren't available.
+///
+/// sled doesn't support secondary readers, and takes an exclusive lock on
+/// the database, so this fails while a node is using the same `cache_dir`.
+/// To query a running node, use `zebra_rpc::client::RemoteState`, which
+/// calls the node's RPC server.
+pub fn init_read_only(
+    config: Config,
+    network: Network,
+) -> Result<
+    impl Service<
+            Request,
+            Response = Response,
+            Error = BoxError,
+            Future = impl Future<Output = Result<Response, BoxError>>,
+        > + Send
+        + Clone
+        + 'static,
+    BoxError,
+> {
+    let state = StateService {
+        finalized: FinalizedState::new(&config, network, true)?,
+        non_finalized: NonFinalizedState::default(),
+        pending_utxos: PendingUtxos::default(),
+        pending_tips: PendingTips::default(),
+        queued_blocks: QueuedBlocks::default(),
+        read_only: true,
+    };
+
+    Ok(Buffer::new(state, 1))
+}
@@ -0,0 +1,277 @@
+//! The on-disk format version, and upgrades between versions.
+//!
+//! The version is stored in the database's default tree. Databases from a
+//! newer version of Zebra are never opened, because they might have data
+//! this version would misread. Older databases are upgraded in place by each
+//! [`Migration`] in turn, or rejected with instructions to resync if there is
+//! no migration for their version.
+use std::{error::Error, path::Path};
+use zebra_chain::Network;
+
+/// The current database format version.
+///
+/// Increment this, and add a [`Migration`] from the previous version, when
+/// the layout of any tree changes.
+pub(crate) const DATABASE_FORMAT_VERSION: u32 = 6;
+
+/// The default tree key for the big-endian format version.
+pub(crate) const FORMAT_VERSION_KEY: &[u8] = b"database_format_version";
+
+/// The default tree key for the network the database belongs to.
+const NETWORK_KEY: &[u8] = b"network";
+
+/// The default tree key that marks the address index as complete.
+///
+/// The mark is `[1]` if every finalized block has been indexed since
+/// genesis.
+const ADDRESS_INDEX_KEY: &[u8] = b"address_index_complete";
+
+/// The default tree key that marks the compact filter index as complete,
+/// like [`ADDRESS_INDEX_KEY`].
+const FILTER_INDEX_KEY: &[u8] = b"filter_index_complete";
+
+type BoxError = Box<dyn Error + Send + Sync + 'static>;
+
+/// An in-place upgrade from version `from` to version `from + 1`.
+struct Migration {
+    from: u32,
+    migrate: fn(&sled::Db) -> Result<(), BoxError>,
+}
+
+/// The migrations between format versions, in order.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 0,
+        migrate: mark_unversioned,
+    },
+    Migration {
+        from: 1,
+        migrate: add_pruned_headers,
+    },
+];
+
+/// The trees in version 1 databases.
+const VERSION_1_TREES: &[&str] = &[
+    "hash_by_height",
+    "height_by_hash",
+    "block_by_height",
+    "utxo_by_outpoint",
+    "tx_by_hash",
+    "sprout_nullifiers",
+    "sapling_nullifiers",
+    "orchard_nullifiers",
+    "sapling_tree_by_height",
+    "sapling_anchors",
+    "utxos_by_address",
+    "txids_by_address",
+];
+
+/// Upgrades a database from before format versioning, which is version 0.
+///
+/// Version 1 only added the version marker, so unversioned databases with
+/// every version 1 tree are already in the right layout. Older unversioned
+/// databases are missing data, and must be resynced.
+fn mark_unversioned(db: &sled::Db) -> Result<(), BoxError> {
+    let names = db.tree_names();
+    for tree in VERSION_1_TREES {
+        if !names.iter().any(|name| name.as_ref() == tree.as_bytes()) {
+            Err(format!(
+                "the unversioned state database is missing the {} tree: delete the \
+                 directory, and Zebra will resync",
+                tree
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// Upgrades a version 1 database to version 2, which can prune blocks.
+///
+/// Version 2 keeps the headers of pruned blocks in `header_by_height`.
+/// Version 1 databases never prune, so the new tree starts empty.
+fn add_pruned_headers(db: &sled::Db) -> Result<(), BoxError> {
+    db.open_tree(b"header_by_height")?;
+    Ok(())
+}
+
+// There is no migration from version 2 to version 3, which stores the chain
+// value pools after each block in `value_pools_by_height`. The pools depend
+// on the values of spent outputs, which are deleted once they are spent, so
+// version 2 databases are resynced.
+
+// There is no migration from version 3 to version 4, which replaces the
+// Sapling trees with `note_commitment_trees_by_height` and `anchors`, so it
+// can store the Orchard tree too. The Orchard note commitments are only in
+// the blocks, and pruned blocks are deleted, so version 3 databases are
+// resynced.
+//
+// Version 5 adds the Sprout tree to each entry in
+// `note_commitment_trees_by_height`, and Sprout roots to `anchors`. There is
+// no migration from version 4, for the same reason.
+//
+// Version 6 adds the chain history tree to each entry in
+// `note_commitment_trees_by_height`. The tree counts the shielded
+// transactions in each block, and pruned blocks are deleted, so there is no
+// migration from version 5 either.
+
+/// Checks the format version of `db`, at `path`, and upgrades it to
+/// [`DATABASE_FORMAT_VERSION`] if needed.
+///
+/// New databases are marked with the current version. If `read_only` is
+/// true, databases that would need to be written are refused instead.
+pub(crate) fn check_and_upgrade(
+    db: &sled::Db,
+    path: &Path,
+    read_only: bool,
+) -> Result<(), BoxError> {
+    let mut version = match db.get(FORMAT_VERSION_KEY)? {
+        Some(bytes) => {
+            if bytes.len() != 4 {
+                Err(format!(
+                    "the state database at {:?} has a corrupt format version",
+                    path
+                ))?;
+            }
+            let mut version = [0; 4];
+            version.copy_from_slice(&bytes);
+            u32::from_be_bytes(version)
+        }
+        None if db.tree_names().len() <= 1 && !read_only => {
+            // Only the default tree exists, so there's no data to upgrade.
+            return write_version(db, DATABASE_FORMAT_VERSION);
+        }
+        None => 0,
+    };
+
+    if version > DATABASE_FORMAT_VERSION {
+        Err(format!(
+            "the state database at {:?} has format version {}, but this version of Zebra only \
+             supports version {}: upgrade Zebra, or use a different cache_dir",
+            path, version, DATABASE_FORMAT_VERSION
+        ))?;
+    }
+
+    if version < DATABASE_FORMAT_VERSION && read_only {
+        Err(format!(
+            "the state database at {:?} has format version {}, and must be upgraded to \
+             version {} before it can be opened read-only: run zebrad to upgrade it",
+            path, version, DATABASE_FORMAT_VERSION
+        ))?;
+    }
+
+    while version < DATABASE_FORMAT_VERSION {
+        let migration = MIGRATIONS
+            .iter()
+            .find(|migration| migration.from == version)
+            .ok_or_else(|| {
+                format!(
+                    "the state database at {:?} has format version {}, which can't be \
+                     upgraded to version {}: delete the directory, and Zebra will resync",
+                    path, version, DATABASE_FORMAT_VERSION
+                )
+            })?;
+
+        (migration.migrate)(db)?;
+        version += 1;
+        // Record each step, so an interrupted upgrade resumes where it stopped.
+        write_version(db, version)?;
+    }
+
+    Ok(())
+}
+
+/// Checks that `db`, at `path`, belongs to `network`, and marks new
+/// databases with `network`, unless `read_only` is true.
+///
+/// Each network has its own directory, so this only fails if a database was
+/// copied or moved.
+pub(crate) fn check_network(
+    db: &sled::Db,
+    network: Network,
+    path: &Path,
+    read_only: bool,
+) -> Result<(), BoxError> {
+    let expected = match network {
+        Network::Mainnet => 0u8,
+        Network::Testnet => 1,
+        Network::Regtest => 2,
+    };
+
+    match db.get(NETWORK_KEY)? {
+        Some(actual) if actual.as_ref() == [expected] => Ok(()),
+        Some(_) => Err(format!(
+            "the state database at {:?} belongs to a different network than {:?}",
+            path, network
+        ))?,
+        None if read_only => Ok(()),
+        None => {
+            db.insert(NETWORK_KEY, &[expected][..])?;
+            db.flush()?;
+            Ok(())
+        }
+    }
+}
+
+/// Returns true if the address index of `db` covers every finalized block.
+///
+/// Empty databases opened with `index_addresses` are marked complete.
+/// Opening a database without the index removes the mark, because the blocks
+/// committed after that aren't indexed. If `read_only` is true, the mark is
+/// never changed.
+pub(crate) fn check_address_index(
+    db: &sled::Db,
+    index_addresses: bool,
+    read_only: bool,
+) -> Result<bool, BoxError> {
+    check_index(db, ADDRESS_INDEX_KEY, index_addresses, read_only)
+}
+
+/// Returns true if the compact filter index of `db` covers every finalized
+/// block, like [`check_address_index`].
+///
+/// Each filter header commits to the previous one, so an incomplete index
+/// can't be extended, and is never updated.
+pub(crate) fn check_filter_index(
+    db: &sled::Db,
+    index_compact_filters: bool,
+    read_only: bool,
+) -> Result<bool, BoxError> {
+    check_index(db, FILTER_INDEX_KEY, index_compact_filters, read_only)
+}
+
+/// Checks and updates the completeness mark at `key`, for an index that is
+/// enabled if `enabled` is true.
+fn check_index(
+    db: &sled::Db,
+    key: &[u8],
+    enabled: bool,
+    read_only: bool,
+) -> Result<bool, BoxError> {
+    let complete = match db.get(key)? {
+        Some(mark) => mark.as_ref() == [1],
+        None => false,
+    };
+
+    if read_only {
+        Ok(enabled && complete)
+    } else if !enabled {
+        if complete {
+            db.remove(key)?;
+            db.flush()?;
+        }
+        Ok(false)
+    } else if !complete && db.open_tree(b"hash_by_height")?.is_empty() {
+        db.insert(key, &[1][..])?;
+        db.flush()?;
+        Ok(true)
+    } else {
+        Ok(complete)
+    }
+}
+
+/// Writes `version` to `db`, and waits until it is on disk.
+fn write_version(db: &sled::Db, version: u32) -> Result<(), BoxError> {
+    db.insert(FORMAT_VERSION_KEY, &version.to_be_bytes()[..])?;
+    db.flush()?;
+    Ok(())
+}
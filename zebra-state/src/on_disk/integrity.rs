@@ -0,0 +1,260 @@
+//! Consistency checks for the finalized state.
+//!
+//! A database that was corrupted by a disk fault, or by an interrupted write
+//! outside of a transaction, would otherwise fail later with errors that
+//! don't mention the cause. These checks walk the chain down from the tip,
+//! and report the first inconsistency they find.
+
+use std::sync::Arc;
+
+use zebra_chain::{
+    amount::{Amount, NonNegative},
+    block::{self, Block, Header},
+    serialization::ZcashDeserialize,
+    transaction::{self, Transaction, TransparentOutput},
+    value_balance::ValueBalance,
+};
+
+use super::{BoxError, FinalizedState};
+
+impl FinalizedState {
+    /// Checks the `depth` blocks below and including the finalized tip, or
+    /// the whole state if `depth` is `None`.
+    ///
+    /// Each block must be indexed by its hash and height, link to the block
+    /// below it, and have note commitment trees, anchors, and chain value
+    /// pools. Full checks also count the height index, and compare each
+    /// chain value pool with a total recomputed from the unspent outputs, or
+    /// from the blocks' shielded value balances.
+    pub(super) fn check_integrity(&self, depth: Option<u32>) -> Result<(), BoxError> {
+        self.check_blocks(depth).map_err(|error| {
+            format!(
+                "the state database at {:?} is corrupt: {}: delete the directory, and Zebra \
+                 will resync",
+                self.path, error
+            )
+            .into()
+        })
+    }
+
+    /// Does the checks for [`FinalizedState::check_integrity`], without the
+    /// database path.
+    fn check_blocks(&self, depth: Option<u32>) -> Result<(), BoxError> {
+        let tip = match self.tip()? {
+            Some((tip, _)) => tip,
+            None => return Ok(()),
+        };
+        let start = match depth {
+            Some(0) => return Ok(()),
+            Some(depth) => (tip.0 + 1).saturating_sub(depth),
+            None => 0,
+        };
+
+        // The shielded pools can only be recomputed from every block since
+        // genesis, so pruned states and partial checks skip them.
+        let mut shielded = if start == 0 {
+            Some(ValueBalance::<NonNegative>::zero())
+        } else {
+            None
+        };
+
+        let mut previous = match start.checked_sub(1) {
+            Some(height) => Some(
+                self.hash(block::Height(height))?
+                    .ok_or_else(|| format!("the hash at height {} is missing", height))?,
+            ),
+            None => None,
+        };
+
+        for height in (start..=tip.0).map(block::Height) {
+            let hash = self
+                .hash(height)?
+                .ok_or_else(|| format!("the hash at height {} is missing", height.0))?;
+            if self.height(hash)? != Some(height) {
+                Err(format!(
+                    "the height index doesn't match block {:?} at height {}",
+                    hash, height.0
+                ))?;
+            }
+
+            let header = match self.block(height.into())? {
+                Some(block) => {
+                    if block.coinbase_height() != Some(height) {
+                        Err(format!(
+                            "the block at height {} has the wrong coinbase height",
+                            height.0
+                        ))?;
+                    }
+                    self.check_transaction_index(hash, &block.transactions)?;
+                    if let Some(pools) = shielded {
+                        shielded = Some(add_shielded_value(pools, &block)?);
+                    }
+                    block.header
+                }
+                None => {
+                    shielded = None;
+                    match self.header_by_height.get(&height.0.to_be_bytes()[..])? {
+                        Some(bytes) => Header::zcash_deserialize(bytes.as_ref())?,
+                        None => Err(format!("the block at height {} is missing", height.0))?,
+                    }
+                }
+            };
+
+            if header.hash() != hash {
+                Err(format!(
+                    "the block at height {} doesn't match its hash",
+                    height.0
+                ))?;
+            }
+            if let Some(previous) = previous {
+                if header.previous_block_hash != previous {
+                    Err(format!(
+                        "the block at height {} isn't the child of the block below it",
+                        height.0
+                    ))?;
+                }
+            }
+
+            let trees = self.note_commitment_trees(height.into())?.ok_or_else(|| {
+                format!(
+                    "the note commitment trees at height {} are missing",
+                    height.0
+                )
+            })?;
+            if self.sprout_tree(&trees.sprout.root())? != Some(trees.sprout.clone()) {
+                Err(format!(
+                    "the Sprout anchor at height {} doesn't match its tree",
+                    height.0
+                ))?;
+            }
+            for (pool, anchor) in trees.anchors() {
+                if !self.contains_anchor(pool, anchor)? {
+                    Err(format!(
+                        "the {:?} anchor at height {} is missing",
+                        pool, height.0
+                    ))?;
+                }
+            }
+
+            let pools = self.value_pools(height.into())?.ok_or_else(|| {
+                format!("the chain value pools at height {} are missing", height.0)
+            })?;
+            if let Some(expected) = shielded {
+                check_pool(
+                    "Sprout",
+                    height,
+                    pools.sprout_amount(),
+                    expected.sprout_amount(),
+                )?;
+                check_pool(
+                    "Sapling",
+                    height,
+                    pools.sapling_amount(),
+                    expected.sapling_amount(),
+                )?;
+                check_pool(
+                    "Orchard",
+                    height,
+                    pools.orchard_amount(),
+                    expected.orchard_amount(),
+                )?;
+            }
+
+            previous = Some(hash);
+        }
+
+        if depth.is_none() {
+            let indexed = self.height_by_hash.len();
+            if indexed != tip.0 as usize + 1 {
+                Err(format!(
+                    "the height index has {} blocks, but the tip is at height {}",
+                    indexed, tip.0
+                ))?;
+            }
+
+            let pools = self
+                .value_pools(tip.into())?
+                .ok_or("the chain value pools at the tip are missing")?;
+            check_pool(
+                "transparent",
+                tip,
+                pools.transparent_amount(),
+                self.transparent_pool()?,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that each transaction in the block with `hash` is indexed at
+    /// its position in the block.
+    fn check_transaction_index(
+        &self,
+        hash: block::Hash,
+        transactions: &[Arc<Transaction>],
+    ) -> Result<(), BoxError> {
+        for (index, transaction) in transactions.iter().enumerate() {
+            let tx_hash = transaction::Hash::from(transaction.as_ref());
+            let mut expected = hash.0.to_vec();
+            expected.extend_from_slice(&(index as u32).to_be_bytes());
+
+            match self.tx_by_hash.get(&tx_hash.0[..])? {
+                Some(location) if location.as_ref() == expected.as_slice() => {}
+                _ => Err(format!(
+                    "transaction {:?} isn't indexed at its position in block {:?}",
+                    tx_hash, hash
+                ))?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the total value of the unspent transparent outputs, or an
+    /// error if it is out of range.
+    fn transparent_pool(&self) -> Result<Amount<NonNegative>, BoxError> {
+        let mut total = Amount::<NonNegative>::zero();
+        for entry in self.utxo_by_outpoint.iter() {
+            let (_, bytes) = entry?;
+            let output = TransparentOutput::zcash_deserialize(bytes.as_ref())?;
+            total =
+                (total + output.value).map_err(|_| "the transparent value pool is out of range")?;
+        }
+        Ok(total)
+    }
+}
+
+/// Returns the shielded chain value pools after `block`, starting from the
+/// `pools` after its parent.
+///
+/// Like the stored pools, the genesis block doesn't change them.
+fn add_shielded_value(
+    mut pools: ValueBalance<NonNegative>,
+    block: &Block,
+) -> Result<ValueBalance<NonNegative>, BoxError> {
+    if block.coinbase_height() == Some(block::Height(0)) {
+        return Ok(pools);
+    }
+    for transaction in &block.transactions {
+        pools = pools
+            .add_transaction(transaction.shielded_value_balance()?)
+            .map_err(|error| format!("the shielded value pools are invalid: {}", error))?;
+    }
+    Ok(pools)
+}
+
+/// Returns an error if the stored `pool` at `height` isn't the `expected`
+/// total.
+fn check_pool(
+    pool: &str,
+    height: block::Height,
+    stored: Amount<NonNegative>,
+    expected: Amount<NonNegative>,
+) -> Result<(), BoxError> {
+    if stored != expected {
+        Err(format!(
+            "the {} value pool at height {} is {:?}, but the chain totals {:?}",
+            pool, height.0, stored, expected
+        ))?;
+    }
+    Ok(())
+}
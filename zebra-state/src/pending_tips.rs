@@ -0,0 +1,53 @@
+//! Requests that wait for the best chain tip to change.
+//!
+//! Subscribers like the RPC event watcher would otherwise have to poll the
+//! tip, and miss the blocks that are replaced between polls. These requests
+//! are answered as soon as a commit changes the tip.
+use futures::{channel::oneshot, prelude::*};
+use std::error::Error;
+use zebra_chain::block;
+
+/// The height and hash of a best chain tip.
+type Tip = Option<(block::Height, block::Hash)>;
+
+/// Waiting `AwaitTipChange` requests, with the tip hash that each one already
+/// knows about.
+#[derive(Debug, Default)]
+pub(crate) struct PendingTips(Vec<(Option<block::Hash>, oneshot::Sender<Tip>)>);
+
+impl PendingTips {
+    /// Returns a future that resolves to the best tip, once it is different
+    /// from `known`.
+    pub(crate) fn queue(
+        &mut self,
+        known: Option<block::Hash>,
+    ) -> impl Future<Output = Result<Tip, Box<dyn Error + Send + Sync + 'static>>> {
+        let (tx, rx) = oneshot::channel();
+        self.0.push((known, tx));
+
+        async move {
+            match rx.await {
+                Ok(tip) => Ok(tip),
+                Err(_) => Err("the state service was dropped".into()),
+            }
+        }
+    }
+
+    /// Sends `tip` to the requests that know about a different tip, and
+    /// removes requests that have been dropped.
+    pub(crate) fn check_tip(&mut self, tip: Tip) {
+        let hash = tip.map(|(_, hash)| hash);
+        let mut waiting = Vec::new();
+        for (known, tx) in self.0.drain(..) {
+            if tx.is_canceled() {
+                continue;
+            }
+            if known == hash {
+                waiting.push((known, tx));
+            } else {
+                let _ = tx.send(tip);
+            }
+        }
+        self.0 = waiting;
+    }
+}
@@ -0,0 +1,68 @@
+//! Requests for transparent outputs that aren't in the state yet.
+//!
+//! Blocks are downloaded and verified in parallel, so a block can be checked
+//! before the block that creates one of its spent outputs is committed. These
+//! lookups wait until the output arrives, or the timeout expires.
+use futures::{channel::oneshot, prelude::*};
+use std::{collections::HashMap, error::Error, time::Duration};
+use zebra_chain::{
+    block::Block,
+    transaction::{self, OutPoint, TransparentOutput},
+};
+
+/// How long an `AwaitUtxo` request waits for its output to be committed.
+pub(crate) const UTXO_LOOKUP_TIMEOUT: Duration = Duration::from_secs(3 * 60);
+
+/// Waiting `AwaitUtxo` requests, by outpoint.
+#[derive(Debug, Default)]
+pub(crate) struct PendingUtxos(HashMap<OutPoint, Vec<oneshot::Sender<TransparentOutput>>>);
+
+impl PendingUtxos {
+    /// Returns a future that resolves to the output at `outpoint`, once it is
+    /// committed, or fails after [`UTXO_LOOKUP_TIMEOUT`].
+    pub(crate) fn queue(
+        &mut self,
+        outpoint: OutPoint,
+    ) -> impl Future<Output = Result<TransparentOutput, Box<dyn Error + Send + Sync + 'static>>>
+    {
+        let (tx, rx) = oneshot::channel();
+        self.0.entry(outpoint).or_default().push(tx);
+
+        async move {
+            match tokio::time::timeout(UTXO_LOOKUP_TIMEOUT, rx).await {
+                Ok(Ok(output)) => Ok(output),
+                Ok(Err(_)) => Err("the state service was dropped".into()),
+                Err(_) => Err(format!("timed out waiting for output {:?}", outpoint).into()),
+            }
+        }
+    }
+
+    /// Sends the outputs created by `block` to any requests that are waiting
+    /// for them.
+    pub(crate) fn check_block(&mut self, block: &Block) {
+        if self.0.is_empty() {
+            return;
+        }
+
+        for transaction in &block.transactions {
+            let hash = transaction::Hash::from(transaction.as_ref());
+            for (index, output) in transaction.outputs().enumerate() {
+                let outpoint = OutPoint {
+                    hash,
+                    index: index as u32,
+                };
+                for tx in self.0.remove(&outpoint).unwrap_or_default() {
+                    let _ = tx.send(output.clone());
+                }
+            }
+        }
+    }
+
+    /// Removes requests that have timed out, or been dropped.
+    pub(crate) fn prune(&mut self) {
+        self.0.retain(|_, senders| {
+            senders.retain(|tx| !tx.is_canceled());
+            !senders.is_empty()
+        });
+    }
+}
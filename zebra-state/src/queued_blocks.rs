@@ -0,0 +1,132 @@
+//! Blocks that arrived before their parent.
+//!
+//! Blocks are downloaded and verified in parallel, so they can reach the
+//! state out of order. Each block waits here until its parent is committed,
+//! then it is committed, and its caller gets the result.
+//!
+//! The queue is bounded, so a downloader that gets far ahead of the state
+//! has to wait, rather than filling memory. While the queue is full, the
+//! state service isn't ready, until a caller gives up on one of its queued
+//! blocks. (The state can't commit any more blocks until it gets their
+//! parents, so callers need a timeout.)
+use futures::{channel::oneshot, prelude::*};
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use zebra_chain::block::{self, Block};
+
+type BoxError = Box<dyn Error + Send + Sync + 'static>;
+
+/// The maximum number of blocks that can wait for their parents.
+pub(crate) const MAX_QUEUED_BLOCKS: usize = 400;
+
+/// A queued block, and the sender for its commit result.
+pub(crate) type QueuedBlock = (Arc<Block>, oneshot::Sender<Result<(), BoxError>>);
+
+/// Blocks waiting for their parents, by parent hash.
+#[derive(Debug, Default)]
+pub(crate) struct QueuedBlocks {
+    by_parent: HashMap<block::Hash, Vec<QueuedBlock>>,
+    len: usize,
+}
+
+impl QueuedBlocks {
+    /// Queues `block` until its parent is committed, and returns a future
+    /// that resolves to its commit result.
+    ///
+    /// Returns an error if there are already [`MAX_QUEUED_BLOCKS`] queued
+    /// blocks, which only happens if the caller didn't wait for
+    /// [`QueuedBlocks::poll_ready`].
+    pub(crate) fn queue(
+        &mut self,
+        block: Arc<Block>,
+    ) -> Result<impl Future<Output = Result<(), BoxError>>, BoxError> {
+        if self.len >= MAX_QUEUED_BLOCKS {
+            Err("too many blocks are waiting for their parents: \
+                 retry after earlier blocks are committed")?;
+        }
+
+        let (tx, rx) = oneshot::channel();
+        self.by_parent
+            .entry(block.header.previous_block_hash)
+            .or_default()
+            .push((block, tx));
+        self.len += 1;
+
+        Ok(async move {
+            match rx.await {
+                Ok(result) => result,
+                Err(_) => Err("the state service was dropped".into()),
+            }
+        })
+    }
+
+    /// Returns `Poll::Ready` if another block can be queued.
+    ///
+    /// While the queue is full, removes blocks whose callers have gone away,
+    /// and wakes the current task when another caller goes away.
+    pub(crate) fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.len < MAX_QUEUED_BLOCKS {
+            return Poll::Ready(());
+        }
+
+        let mut len = 0;
+        self.by_parent.retain(|_, children| {
+            let mut kept = Vec::new();
+            for (block, mut tx) in children.drain(..) {
+                if tx.poll_canceled(cx).is_pending() {
+                    kept.push((block, tx));
+                }
+            }
+            len += kept.len();
+            *children = kept;
+            !children.is_empty()
+        });
+        self.len = len;
+
+        if self.len < MAX_QUEUED_BLOCKS {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Returns the number of queued blocks.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Removes and returns the blocks waiting for `parent`.
+    pub(crate) fn dequeue_children(&mut self, parent: block::Hash) -> Vec<QueuedBlock> {
+        let children = self.by_parent.remove(&parent).unwrap_or_default();
+        self.len -= children.len();
+        children
+    }
+
+    /// Rejects blocks at or below `finalized_height`, which can't be
+    /// committed, and removes blocks whose callers have gone away.
+    pub(crate) fn prune(&mut self, finalized_height: Option<block::Height>) {
+        let mut len = 0;
+        self.by_parent.retain(|_, children| {
+            let mut kept = Vec::new();
+            for (block, tx) in children.drain(..) {
+                let height = block.coinbase_height();
+                if tx.is_canceled() {
+                    continue;
+                }
+                if finalized_height.is_some() && height <= finalized_height {
+                    let _ = tx.send(Err("block is below the finalized tip".into()));
+                    continue;
+                }
+                kept.push((block, tx));
+            }
+            len += kept.len();
+            *children = kept;
+            !children.is_empty()
+        });
+        self.len = len;
+    }
+}
@@ -0,0 +1,69 @@
+//! Prints a checkpoint list for Zebra, using a synced `zcashd`.
+//!
+//! The output has one `height hash` line per checkpoint, which is the format
+//! of Zebra's hard-coded lists and list files. Arguments after `--` are
+//! passed to `zcash-cli`, so `zebra-checkpoints -- -testnet` prints a testnet
+//! list.
+
+use std::process::Command;
+
+use color_eyre::Report;
+use eyre::{eyre, WrapErr};
+use gumdrop::Options;
+
+use zebra_chain::block;
+use zebra_utils::checkpoints::checkpoint_heights;
+
+/// `zebra-checkpoints` options
+#[derive(Debug, Options)]
+struct Args {
+    /// Print help
+    help: bool,
+
+    /// The `zcash-cli` command.
+    #[options(help = "the zcash-cli command to run (defaults to zcash-cli)")]
+    cli: Option<String>,
+
+    /// Extra arguments for `zcash-cli`.
+    #[options(free)]
+    cli_args: Vec<String>,
+}
+
+/// Runs `zcash-cli` with `args`, and returns its trimmed output.
+fn zcash_cli(args: &Args, method: &[&str]) -> Result<String, Report> {
+    let cli = args.cli.as_deref().unwrap_or("zcash-cli");
+    let output = Command::new(cli)
+        .args(&args.cli_args)
+        .args(method)
+        .output()
+        .wrap_err_with(|| format!("could not run {:?}", cli))?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "{} {:?} failed: {}",
+            cli,
+            method,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8(output.stdout)
+        .wrap_err("zcash-cli output is not UTF-8")?
+        .trim()
+        .to_owned())
+}
+
+fn main() -> Result<(), Report> {
+    let args = Args::parse_args_default_or_exit();
+
+    let tip = zcash_cli(&args, &["getblockcount"])?
+        .parse::<u32>()
+        .wrap_err("getblockcount did not return a height")?;
+
+    for height in checkpoint_heights(block::Height(tip)) {
+        let hash = zcash_cli(&args, &["getblockhash", &height.0.to_string()])?
+            .parse::<block::Hash>()
+            .map_err(|e| eyre!("getblockhash did not return a hash: {}", e))?;
+        println!("{} {}", height.0, hash);
+    }
+
+    Ok(())
+}
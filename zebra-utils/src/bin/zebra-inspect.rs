@@ -0,0 +1,77 @@
+//! Prints a breakdown of a hex-encoded block or transaction.
+//!
+//! The input is a hex string argument, or a file containing hex or raw bytes.
+
+use std::{fs, path::PathBuf};
+
+use color_eyre::Report;
+use eyre::{eyre, WrapErr};
+use gumdrop::Options;
+
+use zebra_chain::{block, network_upgrade::ConsensusBranchId, Network};
+use zebra_utils::inspect::{self, Parsed};
+
+/// `zebra-inspect` options
+#[derive(Debug, Options)]
+struct Args {
+    /// Print help
+    help: bool,
+
+    /// The hex-encoded block or transaction.
+    #[options(free)]
+    hex: Option<String>,
+
+    /// A file containing the block or transaction, in hex or raw bytes.
+    #[options(help = "read the block or transaction from a file, in hex or raw bytes")]
+    file: Option<PathBuf>,
+
+    /// The network the block or transaction is from.
+    #[options(help = "mainnet, testnet, or regtest (defaults to mainnet)")]
+    network: Option<String>,
+
+    /// The height that a standalone transaction is mined at.
+    #[options(
+        help = "the height to use for a transaction's signature hashes (blocks use their own)"
+    )]
+    height: Option<u32>,
+}
+
+fn main() -> Result<(), Report> {
+    let args = Args::parse_args_default_or_exit();
+
+    let network = match args.network.as_deref() {
+        None | Some("mainnet") => Network::Mainnet,
+        Some("testnet") => Network::Testnet,
+        Some("regtest") => Network::Regtest,
+        Some(other) => return Err(eyre!("unknown network {:?}", other)),
+    };
+
+    let bytes = match (args.hex, args.file) {
+        (Some(hex), None) => {
+            hex::decode(hex.trim()).wrap_err("the argument is not a hex string")?
+        }
+        (None, Some(file)) => {
+            let contents =
+                fs::read(&file).wrap_err_with(|| format!("could not read {:?}", file))?;
+            // Files from `zcash-cli` are hex, but raw dumps are binary.
+            let text = String::from_utf8_lossy(&contents);
+            hex::decode(text.trim()).unwrap_or(contents)
+        }
+        _ => return Err(eyre!("pass a hex string, or --file, but not both")),
+    };
+
+    let description = match inspect::parse(&bytes)
+        .map_err(|e| eyre!("could not parse a block or transaction: {}", e))?
+    {
+        Parsed::Block(block) => inspect::describe_block(&block, network),
+        Parsed::Transaction(transaction) => {
+            let branch_id = args
+                .height
+                .and_then(|height| ConsensusBranchId::current(network, block::Height(height)));
+            inspect::describe_transaction(&transaction, None, branch_id)
+        }
+    };
+    print!("{}", description);
+
+    Ok(())
+}
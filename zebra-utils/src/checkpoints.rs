@@ -0,0 +1,57 @@
+//! Checkpoint list generation.
+//!
+//! Checkpoints are taken from a synced `zcashd`, so Zebra's hard-coded lists
+//! follow the chain that `zcashd` follows.
+
+use zebra_chain::block;
+use zebra_consensus::checkpoint::list::MAX_CHECKPOINT_HEIGHT_GAP;
+
+/// The number of confirmations a block needs before it can be a checkpoint.
+///
+/// `zcashd` won't reorganize more than 99 blocks, so deeper blocks are final.
+pub const CHECKPOINT_CONFIRMATIONS: u32 = 100;
+
+/// Returns the checkpoint heights for a chain with `tip`.
+///
+/// The heights start at the genesis block, are [`MAX_CHECKPOINT_HEIGHT_GAP`]
+/// apart, and end at the last block with [`CHECKPOINT_CONFIRMATIONS`].
+pub fn checkpoint_heights(tip: block::Height) -> Vec<block::Height> {
+    let last = match tip.0.checked_sub(CHECKPOINT_CONFIRMATIONS) {
+        Some(last) => last,
+        None => return vec![block::Height(0)],
+    };
+
+    let mut heights: Vec<_> = (0..=last)
+        .step_by(MAX_CHECKPOINT_HEIGHT_GAP as usize)
+        .map(block::Height)
+        .collect();
+    if heights.last() != Some(&block::Height(last)) {
+        heights.push(block::Height(last));
+    }
+    heights
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkpoint_heights_are_confirmed_and_close_together() {
+        assert_eq!(checkpoint_heights(block::Height(5)), vec![block::Height(0)]);
+        assert_eq!(
+            checkpoint_heights(block::Height(CHECKPOINT_CONFIRMATIONS)),
+            vec![block::Height(0)]
+        );
+
+        let tip = block::Height(2 * MAX_CHECKPOINT_HEIGHT_GAP + CHECKPOINT_CONFIRMATIONS + 7);
+        assert_eq!(
+            checkpoint_heights(tip),
+            vec![
+                block::Height(0),
+                block::Height(MAX_CHECKPOINT_HEIGHT_GAP),
+                block::Height(2 * MAX_CHECKPOINT_HEIGHT_GAP),
+                block::Height(2 * MAX_CHECKPOINT_HEIGHT_GAP + 7),
+            ]
+        );
+    }
+}
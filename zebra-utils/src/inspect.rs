@@ -0,0 +1,239 @@
+//! Human-readable breakdowns of blocks and transactions.
+
+use std::fmt::Write;
+
+use zebra_chain::{
+    block::{self, Block},
+    network_upgrade::ConsensusBranchId,
+    serialization::{SerializationError, ZcashDeserialize, ZcashSerialize},
+    transaction::{self, HashType, Transaction, TransparentInput},
+    Network,
+};
+
+/// A parsed block or transaction.
+#[derive(Debug)]
+pub enum Parsed {
+    /// The bytes are a block.
+    Block(Block),
+    /// The bytes are a transaction.
+    Transaction(Transaction),
+}
+
+/// Parses `bytes` as a block, or if that fails, as a transaction.
+///
+/// Every byte must be used, so a transaction with trailing data is an error,
+/// rather than a truncated block.
+pub fn parse(bytes: &[u8]) -> Result<Parsed, SerializationError> {
+    let mut reader = bytes;
+    if let Ok(block) = Block::zcash_deserialize(&mut reader) {
+        if reader.is_empty() {
+            return Ok(Parsed::Block(block));
+        }
+    }
+
+    let mut reader = bytes;
+    let transaction = Transaction::zcash_deserialize(&mut reader)?;
+    if !reader.is_empty() {
+        return Err(SerializationError::Parse(
+            "extra bytes after the block or transaction",
+        ));
+    }
+    Ok(Parsed::Transaction(transaction))
+}
+
+/// Returns a breakdown of `block`, and each of its transactions.
+///
+/// Signature hashes use the branch ID for the block's height on `network`.
+pub fn describe_block(block: &Block, network: Network) -> String {
+    let header = &block.header;
+    let height = block.coinbase_height();
+    let branch_id = height.and_then(|height| ConsensusBranchId::current(network, height));
+
+    let mut out = String::new();
+    writeln!(out, "block {}", block.hash()).unwrap();
+    writeln!(out, "  height: {}", display_height(height)).unwrap();
+    writeln!(out, "  size: {} bytes", block.zcash_serialized_size()).unwrap();
+    writeln!(out, "  version: {}", header.version).unwrap();
+    writeln!(out, "  previous block: {}", header.previous_block_hash).unwrap();
+    writeln!(out, "  merkle root: {:?}", header.merkle_root).unwrap();
+    writeln!(
+        out,
+        "  final sapling root: {:?}",
+        header.final_sapling_root_hash
+    )
+    .unwrap();
+    writeln!(out, "  time: {}", header.time).unwrap();
+    writeln!(out, "  bits: {:?}", header.bits).unwrap();
+    writeln!(out, "  nonce: {}", hex::encode(header.nonce)).unwrap();
+    writeln!(out, "  transactions: {}", block.transactions.len()).unwrap();
+
+    for (index, transaction) in block.transactions.iter().enumerate() {
+        out.push('\n');
+        out.push_str(&describe_transaction(transaction, Some(index), branch_id));
+    }
+    out
+}
+
+/// Returns a breakdown of `transaction`, which is at `index` in its block, if
+/// it is in one.
+///
+/// Version 3 and 4 signature hashes need the network upgrade's
+/// `branch_id`, so they are left out if it is `None`. Transparent input
+/// signature hashes need the outputs that the inputs spend, so they are
+/// always left out.
+pub fn describe_transaction(
+    transaction: &Transaction,
+    index: Option<usize>,
+    branch_id: Option<ConsensusBranchId>,
+) -> String {
+    let hash = transaction::Hash::from(transaction);
+    let (version, branch_id) = match transaction {
+        Transaction::V1 { .. } => (1, branch_id),
+        Transaction::V2 { .. } => (2, branch_id),
+        Transaction::V3 { .. } => (3, branch_id),
+        Transaction::V4 { .. } => (4, branch_id),
+        Transaction::V5 {
+            consensus_branch_id,
+            ..
+        } => (5, Some(ConsensusBranchId(*consensus_branch_id))),
+    };
+
+    let mut out = String::new();
+    match index {
+        Some(index) => writeln!(out, "transaction {}: {}", index, hash).unwrap(),
+        None => writeln!(out, "transaction {}", hash).unwrap(),
+    }
+    let indent = if index.is_some() { "    " } else { "  " };
+    let mut field = |name: &str, value: String| {
+        writeln!(out, "{}{}: {}", indent, name, value).unwrap();
+    };
+
+    field("version", version.to_string());
+    field(
+        "size",
+        format!("{} bytes", transaction.zcash_serialized_size()),
+    );
+    if version >= 5 {
+        field("auth digest", transaction.auth_digest().to_string());
+    }
+    if let Some(branch_id) = branch_id {
+        field("branch id", branch_id.to_string());
+    }
+    field("coinbase", transaction.is_coinbase().to_string());
+    field("lock time", format!("{:?}", transaction.lock_time()));
+    if let Some(expiry_height) = transaction.expiry_height() {
+        field("expiry height", expiry_height.0.to_string());
+    }
+
+    let coinbase_height = transaction.inputs().find_map(|input| match input {
+        TransparentInput::Coinbase { height, .. } => Some(*height),
+        TransparentInput::PrevOut { .. } => None,
+    });
+    if let Some(height) = coinbase_height {
+        field("coinbase height", height.0.to_string());
+    }
+    let output_value: u64 = transaction
+        .outputs()
+        .map(|output| u64::from(output.value))
+        .sum();
+    field(
+        "transparent",
+        format!(
+            "{} inputs, {} outputs, {} zatoshis out",
+            transaction.inputs().count(),
+            transaction.outputs().count(),
+            output_value,
+        ),
+    );
+
+    let joinsplits = match transaction {
+        Transaction::V2 {
+            joinsplit_data: Some(data),
+            ..
+        }
+        | Transaction::V3 {
+            joinsplit_data: Some(data),
+            ..
+        } => data.joinsplits().count(),
+        Transaction::V4 {
+            joinsplit_data: Some(data),
+            ..
+        } => data.joinsplits().count(),
+        _ => 0,
+    };
+    if joinsplits > 0 {
+        field("sprout", format!("{} joinsplits", joinsplits));
+    }
+    if transaction.sapling_shielded_data().is_some() {
+        field(
+            "sapling",
+            format!(
+                "{} spends, {} outputs, value balance {}",
+                transaction.sapling_spends().count(),
+                transaction.sapling_outputs().count(),
+                i64::from(transaction.sapling_value_balance()),
+            ),
+        );
+    }
+    if let Some(orchard) = transaction.orchard_shielded_data() {
+        field(
+            "orchard",
+            format!(
+                "{} actions, value balance {}",
+                orchard.actions().count(),
+                i64::from(orchard.value_balance),
+            ),
+        );
+    }
+
+    if let Some(branch_id) = branch_id {
+        let branch_id = u32::from(branch_id);
+        if let Ok(sighash) = transaction.sighash(branch_id, HashType::ALL, &[], None) {
+            field("shielded sighash", hex::encode(sighash));
+        }
+        if let Some(sighash) = transaction.joinsplit_sighash(branch_id) {
+            field("joinsplit sighash", hex::encode(sighash));
+        }
+    }
+
+    out
+}
+
+fn display_height(height: Option<block::Height>) -> String {
+    match height {
+        Some(height) => height.0.to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_and_transactions_are_described() {
+        let bytes = &zebra_test_vectors::BLOCK_MAINNET_415000_BYTES[..];
+        let block = match parse(bytes).expect("the test block parses") {
+            Parsed::Block(block) => block,
+            Parsed::Transaction(_) => panic!("the test block parsed as a transaction"),
+        };
+        let description = describe_block(&block, Network::Mainnet);
+        assert!(description.contains(&block.hash().to_string()));
+        assert!(description.contains("height: 415000"));
+        assert!(description.contains("shielded sighash"));
+
+        let mut transaction = Vec::new();
+        block.transactions[0]
+            .zcash_serialize(&mut transaction)
+            .unwrap();
+        match parse(&transaction).expect("the coinbase parses") {
+            Parsed::Transaction(parsed) => assert_eq!(parsed, *block.transactions[0]),
+            Parsed::Block(_) => panic!("the coinbase parsed as a block"),
+        }
+
+        // A block with trailing data isn't anything.
+        let mut extended = bytes.to_vec();
+        extended.push(0);
+        assert!(parse(&extended).is_err());
+    }
+}
@@ -0,0 +1,15 @@
+//! Developer tools for Zebra.
+//!
+//! The `zebra-inspect` binary parses blocks and transactions with
+//! `zebra-chain`, and prints what it finds, so it's easy to compare Zebra's
+//! view of some bytes with `zcashd`'s.
+//!
+//! The `zebra-checkpoints` binary generates Zebra's checkpoint lists from a
+//! synced `zcashd`.
+
+#![doc(html_logo_url = "https://www.zfnd.org/images/zebra-icon.png")]
+#![doc(html_root_url = "https://doc.zebra.zfnd.org/zebra_utils")]
+#![deny(missing_docs)]
+
+pub mod checkpoints;
+pub mod inspect;
@@ -1,9 +1,11 @@
 //! Zebrad Abscissa Application
 
+use std::{fs::File, io::BufWriter};
+
 use crate::{commands::ZebradCmd, config::ZebradConfig};
 use abscissa_core::{
     application::{self, AppCell},
-    config,
+    config::{self, Configurable},
     terminal::component::Terminal,
     trace::Tracing,
     Application, Component, EntryPoint, FrameworkError, StandardPaths,
@@ -39,6 +41,10 @@ pub struct ZebradApp {
 
     /// Application state.
     state: application::State<Self>,
+
+    /// Flushes span timings to the flamegraph file, if `ZEBRAD_FLAMEGRAPH`
+    /// is set.
+    flame_guard: Option<tracing_flame::FlushGuard<BufWriter<File>>>,
 }
 
 /// Initialize a new application instance.
@@ -50,6 +56,7 @@ impl Default for ZebradApp {
         Self {
             config: None,
             state: application::State::default(),
+            flame_guard: None,
         }
     }
 }
@@ -117,8 +124,11 @@ impl Application for ZebradApp {
         config: Self::Cfg,
         command: &Self::Cmd,
     ) -> Result<(), FrameworkError> {
+        use crate::components::tracing::TracingEndpoint;
+
         // Configure components
         self.state.components.after_config(&config)?;
+        let tracing_config = config.tracing.clone();
         self.config = Some(config);
 
         let level = self.level(command);
@@ -128,6 +138,12 @@ impl Application for ZebradApp {
             .expect("Tracing component should be available")
             .reload_filter(level);
 
+        self.state
+            .components
+            .get_downcast_mut::<TracingEndpoint>()
+            .expect("TracingEndpoint component should be available")
+            .open(&tracing_config, command.config_path());
+
         Ok(())
     }
 }
@@ -142,6 +158,7 @@ impl ZebradApp {
             tracing:
                 crate::config::TracingSection {
                     filter: Some(filter),
+                    ..
                 },
             ..
         }) = &self.config
@@ -152,8 +169,18 @@ impl ZebradApp {
         }
     }
 
-    fn tracing_component(&self, command: &EntryPoint<ZebradCmd>) -> Tracing {
-        use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+    /// Writes the span timings recorded so far to the flamegraph file.
+    ///
+    /// Does nothing if `ZEBRAD_FLAMEGRAPH` isn't set.
+    pub fn flush_flamegraph(&self) -> std::io::Result<()> {
+        match &self.flame_guard {
+            Some(guard) => guard.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn tracing_component(&mut self, command: &EntryPoint<ZebradCmd>) -> Tracing {
+        use tracing_subscriber::layer::SubscriberExt;
 
         // Construct a tracing subscriber with the supplied filter and enable reloading.
         let builder = tracing_subscriber::FmtSubscriber::builder()
@@ -161,11 +188,54 @@ impl ZebradApp {
             .with_filter_reloading();
         let filter_handle = builder.reload_handle();
 
-        builder
-            .finish()
-            .with(tracing_error::ErrorLayer::default())
-            .init();
+        let subscriber = builder.finish().with(tracing_error::ErrorLayer::default());
+
+        // Span timings are recorded in folded stack format, which can be
+        // turned into a flamegraph with `inferno-flamegraph`.
+        let flame_layer = std::env::var_os("ZEBRAD_FLAMEGRAPH").and_then(|path| {
+            match tracing_flame::FlameLayer::with_file(&path) {
+                Ok((layer, guard)) => {
+                    self.flame_guard = Some(guard);
+                    Some(layer)
+                }
+                Err(e) => {
+                    eprintln!("Could not create flamegraph file {:?}: {}", path, e);
+                    None
+                }
+            }
+        });
+        match flame_layer {
+            Some(flame_layer) => init_subscriber(subscriber.with(flame_layer)),
+            None => init_subscriber(subscriber),
+        }
 
         filter_handle.into()
     }
 }
+
+/// Installs `subscriber` as the global default.
+///
+/// Events also go to journald if `ZEBRAD_JOURNALD` is set. The service
+/// should discard standard output, so events aren't logged twice.
+fn init_subscriber<S>(subscriber: S)
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+    S: Send + Sync + 'static,
+{
+    use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+    #[cfg(feature = "systemd")]
+    {
+        if std::env::var_os("ZEBRAD_JOURNALD").is_some() {
+            match tracing_journald::layer() {
+                Ok(journald) => {
+                    subscriber.with(journald).init();
+                    return;
+                }
+                Err(e) => eprintln!("Could not connect to journald: {}", e),
+            }
+        }
+    }
+
+    subscriber.init();
+}
@@ -1,19 +1,21 @@
 //! Zebrad Subcommands
 
-mod config;
 mod connect;
+mod copy_state;
+mod generate;
 mod revhex;
 mod seed;
 mod start;
 mod version;
 
 use self::{
-    config::ConfigCmd, connect::ConnectCmd, revhex::RevhexCmd, seed::SeedCmd, start::StartCmd,
-    version::VersionCmd,
+    connect::ConnectCmd, copy_state::CopyStateCmd, generate::GenerateCmd, revhex::RevhexCmd,
+    seed::SeedCmd, start::StartCmd, version::VersionCmd,
 };
 use crate::config::ZebradConfig;
 use abscissa_core::{
-    config::Override, Command, Configurable, FrameworkError, Help, Options, Runnable,
+    config::Override, Command, Configurable, FrameworkError, FrameworkErrorKind, Help, Options,
+    Runnable,
 };
 use std::path::PathBuf;
 
@@ -23,14 +25,18 @@ pub const CONFIG_FILE: &str = "zebrad.toml";
 /// Zebrad Subcommands
 #[derive(Command, Debug, Options, Runnable)]
 pub enum ZebradCmd {
-    /// The `config` subcommand
-    #[options(help = "generate a skeleton configuration")]
-    Config(ConfigCmd),
-
     /// The `connect` subcommand
     #[options(help = "testing stub for dumping network messages")]
     Connect(ConnectCmd),
 
+    /// The `copy-state` subcommand
+    #[options(help = "copy the finalized state of a stopped node to another directory")]
+    CopyState(CopyStateCmd),
+
+    /// The `generate` subcommand
+    #[options(help = "generate a default configuration")]
+    Generate(GenerateCmd),
+
     /// The `help` subcommand
     #[options(help = "get usage information")]
     Help(Help<Self>),
@@ -72,9 +78,19 @@ impl Configurable<ZebradConfig> for ZebradCmd {
     /// This can be safely deleted if you don't want to override config
     /// settings from command-line options.
     fn process_config(&self, config: ZebradConfig) -> Result<ZebradConfig, FrameworkError> {
-        match self {
-            ZebradCmd::Start(cmd) => cmd.override_config(config),
-            _ => Ok(config),
-        }
+        // Environment variables override the config file, and command-line
+        // options override both.
+        let config = config
+            .with_env_overrides(crate::config::env_vars())
+            .map_err(|e| FrameworkErrorKind::ConfigError.context(e))?;
+        let config = match self {
+            ZebradCmd::Start(cmd) => cmd.override_config(config)?,
+            _ => config,
+        };
+
+        config
+            .validate()
+            .map_err(|e| FrameworkErrorKind::ConfigError.context(e))?;
+        Ok(config)
     }
 }
@@ -1,6 +1,6 @@
 //! `connect` subcommand - test stub for talking to zcashd
 
-use crate::prelude::*;
+use crate::{components::inbound::Inbound, prelude::*};
 
 use abscissa_core::{Command, Options, Runnable};
 
@@ -8,6 +8,9 @@ use color_eyre::Report;
 use eyre::eyre;
 use futures::prelude::*;
 
+/// The number of times a failed block request is retried on another peer.
+const BLOCK_REQUEST_RETRIES: usize = 3;
+
 /// `connect` subcommand
 #[derive(Command, Debug, Options)]
 pub struct ConnectCmd {
@@ -50,25 +53,20 @@ impl Runnable for ConnectCmd {
 impl ConnectCmd {
     async fn connect(&self) -> Result<(), Report> {
         info!("begin tower-based peer handling test stub");
-        use tower::{buffer::Buffer, service_fn, Service, ServiceExt};
-
-        // The service that our node uses to respond to requests by peers
-        let node = Buffer::new(
-            service_fn(|req| async move {
-                info!(?req);
-                Ok::<zebra_network::Response, Report>(zebra_network::Response::Nil)
-            }),
-            1,
-        );
+        use tower::{buffer::Buffer, Service, ServiceExt};
 
         let mut config = app_config().network.clone();
         // Use a different listen addr so that we don't conflict with another local node.
-        config.listen_addr = "127.0.0.1:38233".parse()?;
+        config.listen_addrs = vec!["127.0.0.1:38233".parse()?];
         // Connect only to the specified peer.
         config.initial_mainnet_peers.insert(self.addr.to_string());
 
-        let mut state = zebra_state::in_memory::init();
-        let (mut peer_set, _address_book) = zebra_network::init(config, node).await;
+        let mut state = zebra_state::in_memory::init(config.network);
+        // The service that our node uses to respond to requests by peers
+        let node = Buffer::new(Inbound::new(state.clone()), 1);
+        let best_tip_height = zebra_network::BestTipHeight::default();
+        let (mut peer_set, _address_book, _connected_peers, _peer_events, _shutdown, _) =
+            zebra_network::init(config, node, best_tip_height).await;
         let mut retry_peer_set =
             tower::retry::Retry::new(zebra_network::RetryErrors, peer_set.clone());
 
@@ -79,34 +77,32 @@ impl ConnectCmd {
 
         use futures::stream::{FuturesUnordered, StreamExt};
         use std::collections::BTreeSet;
-        use zebra_chain::block::BlockHeaderHash;
-        use zebra_chain::types::BlockHeight;
+        use zebra_chain::block;
 
         // genesis
-        let mut tip = BlockHeaderHash([
+        let mut tip = block::Hash([
             8, 206, 61, 151, 49, 176, 0, 192, 131, 56, 69, 92, 138, 74, 107, 208, 93, 161, 110, 38,
             177, 29, 170, 27, 145, 113, 132, 236, 232, 15, 4, 0,
         ]);
 
         // TODO(jlusby): Replace with real state service
-        let mut downloaded_block_heights = BTreeSet::<BlockHeight>::new();
-        downloaded_block_heights.insert(BlockHeight(0));
+        let mut downloaded_block_heights = BTreeSet::<block::Height>::new();
+        downloaded_block_heights.insert(block::Height(0));
 
         let mut block_requests = FuturesUnordered::new();
         let mut requested_block_heights = 0;
 
         while requested_block_heights < 700_000 {
             // Request the next 500 hashes.
-            let hashes = if let Ok(zebra_network::Response::BlockHeaderHashes(hashes)) =
-                retry_peer_set
-                    .ready_and()
-                    .await
-                    .map_err(|e| eyre!(e))?
-                    .call(zebra_network::Request::FindBlocks {
-                        known_blocks: vec![tip],
-                        stop: None,
-                    })
-                    .await
+            let hashes = if let Ok(zebra_network::Response::BlockHashes(hashes)) = retry_peer_set
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(zebra_network::Request::FindBlocks {
+                    known_blocks: vec![tip],
+                    stop: None,
+                })
+                .await
             {
                 info!(
                     new_hashes = hashes.len(),
@@ -124,13 +120,39 @@ impl ConnectCmd {
 
             tip = *hashes.last().unwrap();
 
-            // Request the corresponding blocks in chunks
-            for chunk in hashes.chunks(10usize) {
-                let request = peer_set.ready_and().await.map_err(|e| eyre!(e))?.call(
-                    zebra_network::Request::BlocksByHash(chunk.iter().cloned().collect()),
+            // Request the corresponding blocks in chunks, retrying chunks
+            // that fail on another peer.
+            let chunks: Vec<_> = hashes.chunks(10usize).collect();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let request = zebra_network::Request::BlocksByHash(chunk.iter().cloned().collect());
+                let mut block_peer_set = tower::retry::Retry::new(
+                    zebra_network::RetryPeerErrors::new(BLOCK_REQUEST_RETRIES),
+                    peer_set.clone(),
                 );
-
-                block_requests.push(request);
+                let first = block_peer_set
+                    .ready_and()
+                    .await
+                    .map_err(|e| eyre!(e))?
+                    .call(request.clone())
+                    .boxed();
+
+                if i + 1 == chunks.len() {
+                    // The next batch of hashes can't be used until the last
+                    // chunk arrives, so hedge it by asking a second peer.
+                    let second = block_peer_set
+                        .ready_and()
+                        .await
+                        .map_err(|e| eyre!(e))?
+                        .call(request)
+                        .boxed();
+                    block_requests.push(
+                        future::select_ok(vec![first, second])
+                            .map_ok(|(response, _)| response)
+                            .boxed(),
+                    );
+                } else {
+                    block_requests.push(first);
+                }
             }
 
             // Allow at most 300 block requests in flight.
@@ -0,0 +1,241 @@
+//! `copy-state` subcommand - copies the finalized state to another directory
+//!
+//! The source state is opened read-only, and its best chain is copied block
+//! by block, so the copy is consistent even though sled can't snapshot a
+//! database. If the target already has blocks from the same chain, the copy
+//! continues from the target's tip.
+//!
+//! sled locks the source database, even when it is opened read-only, so the
+//! node using the source state must be stopped before it is copied.
+//!
+//! With `--verify`, each block goes through the chain verifier before it is
+//! added to the target. The verifier keeps the last blocks of the chain in
+//! memory, so once every block is verified, they are committed to the
+//! target's finalized state.
+
+use std::{path::PathBuf, sync::Arc};
+
+use abscissa_core::{Command, Options, Runnable};
+use color_eyre::Report;
+use eyre::eyre;
+use futures::prelude::*;
+use tower::{Service, ServiceExt};
+
+use zebra_chain::block::{self, Block};
+use zebra_network::BoxedStdError;
+
+use crate::prelude::*;
+
+/// The number of blocks that can be waiting for verification at once.
+///
+/// The checkpoint verifier only verifies blocks once it has every block up to
+/// the next checkpoint, so this needs to be larger than the gaps between
+/// checkpoints.
+const VERIFY_LOOKAHEAD: usize = 2_000;
+
+/// How often the copy logs its progress, in blocks.
+const PROGRESS_INTERVAL: u32 = 10_000;
+
+/// `copy-state` subcommand
+#[derive(Command, Debug, Default, Options)]
+pub struct CopyStateCmd {
+    /// The cache directory to copy the state to.
+    #[options(help = "the state.cache_dir to copy the state to")]
+    target_dir: Option<PathBuf>,
+
+    /// Whether to verify each block as it is copied.
+    #[options(help = "verify each block before adding it to the copy")]
+    verify: bool,
+
+    /// The height to stop copying at.
+    #[options(help = "the last height to copy (the source tip if unspecified)")]
+    max_height: Option<u32>,
+}
+
+impl Runnable for CopyStateCmd {
+    /// Start the application.
+    fn run(&self) {
+        use crate::components::tokio::TokioComponent;
+
+        let rt = app_writer()
+            .state_mut()
+            .components
+            .get_downcast_mut::<TokioComponent>()
+            .expect("TokioComponent should be available")
+            .rt
+            .take();
+
+        rt.expect("runtime should not already be taken")
+            .block_on(self.copy())
+            // Surface any error that occurred executing the future.
+            .unwrap();
+    }
+}
+
+impl CopyStateCmd {
+    async fn copy(&self) -> Result<(), Report> {
+        let config = (*app_config()).clone();
+        let network = config.network.network;
+
+        let target_dir = self
+            .target_dir
+            .clone()
+            .ok_or_else(|| eyre!("copy-state needs a --target-dir"))?;
+        let target_config = zebra_state::Config {
+            cache_dir: target_dir,
+            ..config.state.clone()
+        };
+        if target_config.db_path(network) == config.state.db_path(network) {
+            return Err(eyre!(
+                "the target directory must be different from the source"
+            ));
+        }
+
+        // sled locks the source, so this fails if a node is using it.
+        let source =
+            zebra_state::on_disk::init_read_only(config.state.clone(), network).map_err(|e| {
+                eyre!(
+                    "could not open the source state: {}: stop any node that is using it",
+                    e
+                )
+            })?;
+        let target = zebra_state::on_disk::init(target_config, network)
+            .map_err(|e| eyre!("could not open the target state: {}", e))?;
+
+        let (source_tip, _) = tip(source.clone())
+            .await?
+            .ok_or_else(|| eyre!("the source state is empty"))?;
+        let last_height = match self.max_height {
+            Some(max_height) => source_tip.min(block::Height(max_height)),
+            None => source_tip,
+        };
+
+        let first_height = match tip(target.clone()).await? {
+            None => block::Height(0),
+            Some((height, hash)) => {
+                if height >= last_height {
+                    info!(?height, "the target state is already up to date");
+                    return Ok(());
+                }
+                if read_block(source.clone(), height).await?.hash() != hash {
+                    return Err(eyre!(
+                        "the target state is on a different chain from the source: \
+                         delete it, or choose another target directory"
+                    ));
+                }
+                block::Height(height.0 + 1)
+            }
+        };
+        info!(
+            ?first_height,
+            ?last_height,
+            verify = self.verify,
+            "copying the state"
+        );
+
+        let blocks = stream::iter(first_height.0..=last_height.0)
+            .then(move |height| read_block(source.clone(), block::Height(height)));
+        let copied = if self.verify {
+            let verifier =
+                zebra_consensus::chain::init(&config.consensus, network, target.clone(), None)
+                    .await
+                    .map_err(|e| eyre!(e))?;
+            let copied = blocks
+                .map_ok(move |block| {
+                    let height = block.coinbase_height();
+                    verifier.clone().oneshot(block).map_err(move |e| {
+                        eyre!("block at height {:?} failed verification: {}", height, e)
+                    })
+                })
+                .try_buffer_unordered(VERIFY_LOOKAHEAD);
+            copied.boxed()
+        } else {
+            let target = target.clone();
+            let copied = blocks.and_then(move |block| {
+                let hash = block.hash();
+                target
+                    .clone()
+                    .oneshot(zebra_state::Request::CommitFinalizedBlock { block })
+                    .map_ok(move |_| hash)
+                    .map_err(|e| eyre!("could not add a block to the target: {}", e))
+            });
+            copied.boxed()
+        };
+
+        let mut count = 0u32;
+        copied
+            .try_for_each(|_hash| {
+                count += 1;
+                if count % PROGRESS_INTERVAL == 0 {
+                    let height = first_height.0 + count - 1;
+                    info!(
+                        height,
+                        remaining = last_height.0 - height,
+                        "copying the state"
+                    );
+                }
+                future::ok(())
+            })
+            .await?;
+
+        if self.verify {
+            match target
+                .clone()
+                .oneshot(zebra_state::Request::FinalizeBestChain)
+                .await
+                .map_err(|e| eyre!(e))?
+            {
+                zebra_state::Response::Finalized { tip } => {
+                    info!(?tip, "finalized the verified blocks")
+                }
+                _ => unreachable!("FinalizeBestChain requests get Finalized responses"),
+            }
+        }
+
+        info!("flushing the target state to disk");
+        target
+            .oneshot(zebra_state::Request::Flush)
+            .await
+            .map_err(|e| eyre!(e))?;
+        info!(blocks = count, ?last_height, "copied the state");
+
+        Ok(())
+    }
+}
+
+/// Returns the best tip of `state`.
+async fn tip<S>(state: S) -> Result<Option<(block::Height, block::Hash)>, Report>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = BoxedStdError>,
+{
+    match state
+        .oneshot(zebra_state::Request::Tip)
+        .await
+        .map_err(|e| eyre!(e))?
+    {
+        zebra_state::Response::BestTip { tip } => Ok(tip),
+        _ => unreachable!("Tip requests get BestTip responses"),
+    }
+}
+
+/// Returns the best chain block at `height` in `state`.
+async fn read_block<S>(state: S, height: block::Height) -> Result<Arc<Block>, Report>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = BoxedStdError>,
+{
+    match state
+        .oneshot(zebra_state::Request::Block {
+            hash_or_height: height.into(),
+        })
+        .await
+    {
+        Ok(zebra_state::Response::Block { block }) => Ok(block),
+        Ok(_) => unreachable!("Block requests get Block responses"),
+        Err(e) => Err(eyre!(
+            "could not read the block at height {:?}: {}: pruned states can't be copied, \
+             because they don't have every block",
+            height,
+            e
+        )),
+    }
+}
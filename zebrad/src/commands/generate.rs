@@ -0,0 +1,102 @@
+//! `generate` subcommand - generates a default config.
+
+use crate::config::{TracingSection, ZebradConfig};
+use abscissa_core::{Command, Options, Runnable};
+
+/// The comment at the start of the generated config.
+const HEADER: &str = r"# Default configuration values for zebrad.
+#
+# This file is intended as a skeleton for custom configs.
+#
+# Because this contains default values, and the default
+# values may change, you should delete all entries except
+# for the ones you wish to change.
+#
+# zebrad reads zebrad.toml in the current directory, or
+# the file passed with -c. Missing keys have their default
+# values, and unknown keys are errors.
+#
+# Documentation on the meanings of each config option
+# can be found in Rustdoc.
+
+";
+
+/// The comment before each config section.
+const SECTION_COMMENTS: &[(&str, &str)] = &[
+    ("consensus", "# Block and transaction verification."),
+    ("mempool", "# Unmined transactions."),
+    ("metrics", "# The Prometheus metrics endpoint."),
+    (
+        "network",
+        "# Peer connections, and the network to connect to.",
+    ),
+    (
+        "rpc",
+        "# The JSON-RPC server, which is disabled unless listen_addr is set.",
+    ),
+    ("state", "# The on-disk chain state."),
+    ("sync", "# Downloading blocks from peers."),
+    (
+        "tracing",
+        "# Log filters, and the endpoint that changes them at runtime.",
+    ),
+];
+
+/// `generate` subcommand
+#[derive(Command, Debug, Options)]
+pub struct GenerateCmd {
+    /// The file to write the generated config to.
+    #[options(help = "The file to write the generated config to (stdout if unspecified)")]
+    output_file: Option<String>,
+}
+
+impl Runnable for GenerateCmd {
+    /// Start the application.
+    fn run(&self) {
+        let output = generate();
+        match self.output_file {
+            Some(ref output_file) => {
+                use std::{fs::File, io::Write};
+                File::create(output_file)
+                    .expect("must be able to open output file")
+                    .write_all(output.as_bytes())
+                    .expect("must be able to write output");
+            }
+            None => {
+                println!("{}", output);
+            }
+        }
+    }
+}
+
+/// Returns the default config, with comments.
+///
+/// The default name and location of the config file is defined in
+/// ../commands.rs
+fn generate() -> String {
+    let default_config = ZebradConfig {
+        tracing: TracingSection::populated(),
+        ..ZebradConfig::default()
+    };
+
+    // this avoids a ValueAfterTable error
+    // https://github.com/alexcrichton/toml-rs/issues/145
+    let conf = toml::Value::try_from(default_config).expect("default config should be valid TOML");
+    let body = toml::to_string_pretty(&conf).expect("default config should be serializable");
+
+    let mut output = HEADER.to_owned();
+    for line in body.lines() {
+        // Nested tables, like `[network.inbound_rate_limits]`, don't match.
+        let comment = SECTION_COMMENTS
+            .iter()
+            .find(|(name, _)| line == format!("[{}]", name))
+            .map(|(_, comment)| comment);
+        if let Some(comment) = comment {
+            output.push_str(comment);
+            output.push('\n');
+        }
+        output.push_str(line);
+        output.push('\n');
+    }
+    output
+}
@@ -1,34 +1,121 @@
 //! `seed` subcommand - runs a dns seeder
+//!
+//! The seeder doesn't sync the chain. It crawls the network for peer
+//! addresses, and checks that they accept connections by handshaking with
+//! them. Peers that pass are shared with inbound `getaddr` requests, and
+//! optionally served as DNS `A` and `AAAA` records.
 
 use std::{
+    collections::HashMap,
     future::Future,
+    net::{IpAddr, SocketAddr},
     pin::Pin,
     sync::{Arc, Mutex},
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
 use abscissa_core::{Command, Options, Runnable};
-use futures::{channel::oneshot, prelude::*};
+use futures::prelude::*;
 use tower::{buffer::Buffer, Service, ServiceExt};
 
-use zebra_network::{AddressBook, BoxedStdError, Request, Response};
+use zebra_chain::{serialization::DateTime32, Network};
+use zebra_network::{types::MetaAddr, AddressBook, BoxedStdError, Request, Response};
 
 use crate::prelude::*;
 use color_eyre::Report;
 use eyre::eyre;
 
-/// Whether our `SeedService` is poll_ready or not.
-#[derive(Debug)]
-enum SeederState {
-    /// Waiting for the address book to be shared with us via the oneshot channel.
-    AwaitingAddressBook(oneshot::Receiver<Arc<Mutex<AddressBook>>>),
-    /// Address book received, ready to service requests.
-    Ready(Arc<Mutex<AddressBook>>),
+mod dns;
+
+/// How often the seeder asks its peers for more addresses, and probes the
+/// addresses that are due.
+const CRAWL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long the seeder waits before probing a peer again.
+const REPROBE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// How long a probe can take, including the handshake.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The maximum number of probes that run at once.
+const MAX_CONCURRENT_PROBES: usize = 32;
+
+/// The maximum number of probes in each crawl.
+///
+/// This keeps each crawl shorter than `CRAWL_INTERVAL`, at most
+/// `MAX_PROBES_PER_CRAWL / MAX_CONCURRENT_PROBES * PROBE_TIMEOUT`.
+const MAX_PROBES_PER_CRAWL: usize = 128;
+
+/// The maximum number of unprobed addresses that the seeder remembers, so
+/// that peers can't fill its memory.
+const MAX_CANDIDATES: usize = 10_000;
+
+/// The maximum number of peers in each `getaddr` response.
+const MAX_PEERS_PER_RESPONSE: usize = 50;
+
+/// The default number of records in each DNS response.
+const DEFAULT_DNS_RECORDS: usize = 25;
+
+/// The peers that accepted a handshake in their most recent probe.
+#[derive(Clone, Debug)]
+pub struct LivePeers {
+    peers: Arc<Mutex<HashMap<SocketAddr, MetaAddr>>>,
+    default_port: u16,
+}
+
+impl LivePeers {
+    fn new(network: Network) -> Self {
+        Self {
+            peers: Default::default(),
+            default_port: network.default_port(),
+        }
+    }
+
+    fn record_success(&self, meta: MetaAddr) {
+        self.peers.lock().unwrap().insert(meta.addr, meta);
+    }
+
+    fn record_failure(&self, addr: &SocketAddr) {
+        self.peers.lock().unwrap().remove(addr);
+    }
+
+    fn len(&self) -> usize {
+        self.peers.lock().unwrap().len()
+    }
+
+    /// Returns up to `limit` random live peers, with sanitized timestamps.
+    fn sanitized(&self, limit: usize) -> Vec<MetaAddr> {
+        use rand::seq::IteratorRandom;
+        self.peers
+            .lock()
+            .unwrap()
+            .values()
+            .map(|meta| meta.sanitize())
+            .choose_multiple(&mut rand::thread_rng(), limit)
+    }
+
+    /// Returns up to `limit` random IP addresses of live peers that listen on
+    /// the network's default port, and match `filter`.
+    ///
+    /// DNS records can't carry ports, so peers on other ports are left out.
+    pub fn sample(&self, limit: usize, filter: impl Fn(&IpAddr) -> bool) -> Vec<IpAddr> {
+        use rand::seq::IteratorRandom;
+        self.peers
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|addr| addr.port() == self.default_port)
+            .map(|addr| addr.ip())
+            .filter(|ip| filter(ip))
+            .choose_multiple(&mut rand::thread_rng(), limit)
+    }
 }
 
+/// Answers inbound peer requests with the live peers.
 #[derive(Debug)]
 struct SeedService {
-    state: SeederState,
+    live_peers: LivePeers,
 }
 
 impl Service<Request> for SeedService {
@@ -37,26 +124,8 @@ impl Service<Request> for SeedService {
     type Future =
         Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
 
-    #[instrument(skip(self, _cx))]
     fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        match self.state {
-            SeederState::Ready(_) => Poll::Ready(Ok(())),
-            SeederState::AwaitingAddressBook(ref mut rx) => match rx.try_recv() {
-                Err(e) => {
-                    error!("oneshot sender dropped, failing service: {:?}", e);
-                    Poll::Ready(Err(e.into()))
-                }
-                Ok(None) => {
-                    trace!("awaiting address book, service is unready");
-                    Poll::Pending
-                }
-                Ok(Some(address_book)) => {
-                    debug!("received address_book via oneshot, service becomes ready");
-                    self.state = SeederState::Ready(address_book);
-                    Poll::Ready(Ok(()))
-                }
-            },
-        }
+        Poll::Ready(Ok(()))
     }
 
     // Note: the generated span applies only to this function, not
@@ -64,28 +133,12 @@ impl Service<Request> for SeedService {
     // is not actually async.
     #[instrument]
     fn call(&mut self, req: Request) -> Self::Future {
-        let address_book = if let SeederState::Ready(address_book) = &self.state {
-            address_book
-        } else {
-            panic!("SeedService::call without SeedService::poll_ready");
-        };
-
         let response = match req {
             Request::Peers => {
-                // Collect a list of known peers from the address book
-                // and sanitize their timestamps.
-                let mut peers = address_book
-                    .lock()
-                    .unwrap()
-                    .peers()
-                    .map(|addr| addr.sanitize())
-                    .collect::<Vec<_>>();
-                // The peers are still ordered by recency, so shuffle them.
-                use rand::seq::SliceRandom;
-                peers.shuffle(&mut rand::thread_rng());
-                // Finally, truncate the list so that we do not trivially
-                // reveal our entire peer set.
-                peers.truncate(50);
+                // Only share peers that we've recently connected to, in a
+                // random order, and truncate the list so that we do not
+                // trivially reveal our entire peer set.
+                let peers = self.live_peers.sanitized(MAX_PEERS_PER_RESPONSE);
                 debug!(peers.len = peers.len());
                 Ok(Response::Peers(peers))
             }
@@ -102,9 +155,20 @@ impl Service<Request> for SeedService {
 ///
 /// A DNS seeder command to spider and collect as many valid peer
 /// addresses as we can.
-// This is not a unit-like struct because it makes Command and Options sad.
 #[derive(Command, Debug, Default, Options)]
-pub struct SeedCmd {}
+pub struct SeedCmd {
+    /// The address to serve DNS records on.
+    #[options(help = "serve DNS records for live peers on this UDP address")]
+    dns_listen: Option<SocketAddr>,
+
+    /// The domain name that DNS records are served for.
+    #[options(help = "the seeder's domain name, required with --dns-listen")]
+    dns_name: Option<String>,
+
+    /// The maximum number of records in each DNS response.
+    #[options(help = "the maximum number of records in each DNS response")]
+    dns_records: Option<usize>,
+}
 
 impl Runnable for SeedCmd {
     /// Start the application.
@@ -128,28 +192,164 @@ impl Runnable for SeedCmd {
 
 impl SeedCmd {
     async fn seed(&self) -> Result<(), Report> {
-        info!("begin tower-based peer handling test stub");
+        let config = app_config().network.clone();
+        let network = config.network;
+        let proxy = config.proxy;
 
-        let (addressbook_tx, addressbook_rx) = oneshot::channel();
-        let seed_service = SeedService {
-            state: SeederState::AwaitingAddressBook(addressbook_rx),
+        let dns = match (self.dns_listen, self.dns_name.clone()) {
+            (Some(listen_addr), Some(name)) => Some((listen_addr, name)),
+            (Some(_), None) => return Err(eyre!("--dns-listen requires --dns-name")),
+            (None, _) => None,
         };
-        let buffered_svc = Buffer::new(seed_service, 1);
 
-        let config = app_config().network.clone();
+        let live_peers = LivePeers::new(network);
+        let seed_service = Buffer::new(
+            SeedService {
+                live_peers: live_peers.clone(),
+            },
+            1,
+        );
+
+        // The seeder doesn't sync the chain, so its tip height is never known,
+        // and it accepts any peer version that is valid at genesis.
+        let best_tip_height = zebra_network::BestTipHeight::default();
+        let (peer_set, address_book, _connected_peers, _peer_events, _shutdown, _) =
+            zebra_network::init(config, seed_service, best_tip_height).await;
 
-        let (mut peer_set, address_book) = zebra_network::init(config, buffered_svc).await;
+        let dns_server = match dns {
+            Some((listen_addr, name)) => {
+                let max_records = self.dns_records.unwrap_or(DEFAULT_DNS_RECORDS);
+                dns::serve(listen_addr, name, max_records, live_peers.clone())
+                    .map_err(Report::from)
+                    .boxed()
+            }
+            None => future::pending().boxed(),
+        };
 
-        let _ = addressbook_tx.send(address_book);
+        let crawler = Crawler {
+            network,
+            proxy,
+            address_book,
+            live_peers,
+            candidates: HashMap::new(),
+            last_probed: HashMap::new(),
+        };
+
+        future::try_join(crawler.run(peer_set), dns_server).await?;
+        Ok(())
+    }
+}
+
+/// Harvests peer addresses from the network, and probes them.
+struct Crawler {
+    network: Network,
+    proxy: Option<SocketAddr>,
+    address_book: Arc<Mutex<AddressBook>>,
+    live_peers: LivePeers,
+    /// The addresses that we will probe, including the live peers.
+    candidates: HashMap<SocketAddr, MetaAddr>,
+    /// When each candidate was last probed.
+    last_probed: HashMap<SocketAddr, Instant>,
+}
 
+impl Crawler {
+    async fn run<S>(mut self, mut peer_set: S) -> Result<(), Report>
+    where
+        S: Service<Request, Response = Response, Error = BoxedStdError>,
+    {
         info!("waiting for peer_set ready");
         peer_set.ready_and().await.map_err(|e| eyre!(e))?;
+        info!("peer_set became ready, crawling the network");
 
-        info!("peer_set became ready");
+        let mut interval = tokio::time::interval(CRAWL_INTERVAL);
+        loop {
+            interval.tick().await;
 
-        let eternity = future::pending::<()>();
-        eternity.await;
+            match peer_set
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(Request::Peers)
+                .await
+            {
+                Ok(Response::Peers(addrs)) => self.add_candidates(addrs),
+                Ok(_) => unreachable!("peer set responds to Peers with Peers"),
+                Err(error) => debug!(?error, "peer request failed"),
+            }
+            // Our own connections are in the address book, so probe them too.
+            let known: Vec<_> = self.address_book.lock().unwrap().peers().collect();
+            self.add_candidates(known);
 
-        Ok(())
+            self.probe_candidates().await;
+
+            let live = self.live_peers.len();
+            info!(candidates = self.candidates.len(), live, "finished crawl");
+            metrics::gauge!("seed.candidates", self.candidates.len() as i64);
+            metrics::gauge!("seed.live_peers", live as i64);
+        }
+    }
+
+    fn add_candidates(&mut self, addrs: Vec<MetaAddr>) {
+        for meta in addrs {
+            if self.candidates.len() >= MAX_CANDIDATES {
+                break;
+            }
+            self.candidates.entry(meta.addr).or_insert(meta);
+        }
+    }
+
+    /// Probes the candidates that haven't been probed recently.
+    ///
+    /// Peers that complete a handshake are live, and are marked as seen in the
+    /// address book. Peers that fail are forgotten until they are gossiped
+    /// again.
+    async fn probe_candidates(&mut self) {
+        let last_probed = &self.last_probed;
+        let due: Vec<MetaAddr> = self
+            .candidates
+            .values()
+            .filter(|meta| {
+                last_probed
+                    .get(&meta.addr)
+                    .map_or(true, |time| time.elapsed() >= REPROBE_INTERVAL)
+            })
+            .take(MAX_PROBES_PER_CRAWL)
+            .cloned()
+            .collect();
+
+        let (network, proxy) = (self.network, self.proxy);
+        let results: Vec<_> = stream::iter(due)
+            .map(|meta| async move {
+                let probe = zebra_network::connect_isolated(network, meta.addr, proxy);
+                let live = match tokio::time::timeout(PROBE_TIMEOUT, probe).await {
+                    Ok(Ok(_client)) => true,
+                    Ok(Err(_)) | Err(_) => false,
+                };
+                (meta, live)
+            })
+            .buffer_unordered(MAX_CONCURRENT_PROBES)
+            .collect()
+            .await;
+
+        for (meta, live) in results {
+            if live {
+                trace!(addr = ?meta.addr, "peer is live");
+                let meta = MetaAddr {
+                    last_seen: DateTime32::now(),
+                    ..meta
+                };
+                self.address_book.lock().unwrap().update(meta);
+                self.live_peers.record_success(meta);
+            } else {
+                trace!(addr = ?meta.addr, "peer failed its probe");
+                self.live_peers.record_failure(&meta.addr);
+                self.candidates.remove(&meta.addr);
+            }
+            // Failed peers keep their probe time, so they aren't probed again
+            // straight away if they are gossiped again.
+            self.last_probed.insert(meta.addr, Instant::now());
+        }
+        self.last_probed
+            .retain(|_, time| time.elapsed() < REPROBE_INTERVAL);
     }
 }
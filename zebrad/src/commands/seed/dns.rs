@@ -0,0 +1,297 @@
+//! A minimal DNS server that answers `A` and `AAAA` queries with live peers.
+//!
+//! DNS seeders only need to answer one kind of question, so this parses the
+//! wire format by hand rather than pulling in a full DNS implementation.
+//! Queries for other names are refused, and other record types get empty
+//! answers.
+
+use std::net::{IpAddr, SocketAddr};
+
+use tokio::net::UdpSocket;
+
+use super::LivePeers;
+use crate::prelude::*;
+
+/// The largest DNS message that fits in a UDP datagram without EDNS.
+const MAX_UDP_MESSAGE: usize = 512;
+
+/// The length of a DNS message header.
+const HEADER_LEN: usize = 12;
+
+/// How long resolvers may cache our answers, in seconds.
+///
+/// Peers come and go, so this is kept short.
+const TTL_SECONDS: u32 = 60;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+const RCODE_FORMAT_ERROR: u16 = 1;
+const RCODE_NOT_IMPLEMENTED: u16 = 4;
+const RCODE_REFUSED: u16 = 5;
+
+/// A parsed DNS query.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Query {
+    id: u16,
+    recursion_desired: bool,
+    /// The lowercased name, without a trailing dot.
+    name: String,
+    qtype: u16,
+    qclass: u16,
+    /// The raw question section, which is copied into the response.
+    question: Vec<u8>,
+}
+
+/// Answers DNS queries for `name` on `listen_addr`, with up to
+/// `max_records` addresses from `live_peers`.
+///
+/// Only fails if the seeder can't listen on `listen_addr`.
+pub async fn serve(
+    listen_addr: SocketAddr,
+    name: String,
+    max_records: usize,
+    live_peers: LivePeers,
+) -> std::io::Result<()> {
+    let mut socket = UdpSocket::bind(listen_addr).await?;
+    let name = name.trim_end_matches('.').to_lowercase();
+    info!(%listen_addr, %name, "serving DNS seed records");
+
+    let mut buf = [0u8; MAX_UDP_MESSAGE];
+    loop {
+        let (len, client) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            // Some platforms report ICMP errors from earlier sends here.
+            Err(error) => {
+                debug!(%error, "failed to receive a DNS query");
+                continue;
+            }
+        };
+        let query = match parse_query(&buf[..len]) {
+            Ok(query) => query,
+            Err(Some(response)) => {
+                send(&mut socket, &response, client).await;
+                continue;
+            }
+            // Not a query, so there's nothing to answer.
+            Err(None) => continue,
+        };
+
+        let response = if query.name != name {
+            error_response(&query, RCODE_REFUSED)
+        } else {
+            let addrs = if query.qclass == CLASS_IN {
+                live_peers.sample(max_records, |ip| match (query.qtype, ip) {
+                    (TYPE_A, IpAddr::V4(_)) | (TYPE_AAAA, IpAddr::V6(_)) => true,
+                    _ => false,
+                })
+            } else {
+                Vec::new()
+            };
+            metrics::counter!("seed.dns.queries", 1);
+            trace!(
+                ?client,
+                qtype = query.qtype,
+                answers = addrs.len(),
+                "answering DNS query"
+            );
+            answer_response(&query, &addrs)
+        };
+        send(&mut socket, &response, client).await;
+    }
+}
+
+/// Sends `response` to `client`.
+///
+/// Clients can send queries from addresses that we can't reply to, so
+/// failures are logged, and the seeder keeps serving other clients.
+async fn send(socket: &mut UdpSocket, response: &[u8], client: SocketAddr) {
+    if let Err(error) = socket.send_to(response, &client).await {
+        metrics::counter!("seed.dns.send_errors", 1);
+        debug!(?client, %error, "failed to send a DNS response");
+    }
+}
+
+/// Parses a DNS query from `message`.
+///
+/// On failure, returns the error response to send, or `None` if the message
+/// should be ignored.
+fn parse_query(message: &[u8]) -> Result<Query, Option<Vec<u8>>> {
+    if message.len() < HEADER_LEN {
+        return Err(None);
+    }
+    let read_u16 = |at: usize| u16::from_be_bytes([message[at], message[at + 1]]);
+
+    let id = read_u16(0);
+    let flags = read_u16(2);
+    let recursion_desired = flags & 0x0100 != 0;
+    let mut query = Query {
+        id,
+        recursion_desired,
+        name: String::new(),
+        qtype: 0,
+        qclass: 0,
+        question: Vec::new(),
+    };
+
+    // Never answer responses, so we can't be used to reflect traffic.
+    if flags & 0x8000 != 0 {
+        return Err(None);
+    }
+    if (flags >> 11) & 0xf != 0 {
+        return Err(Some(error_response(&query, RCODE_NOT_IMPLEMENTED)));
+    }
+    if read_u16(4) != 1 {
+        return Err(Some(error_response(&query, RCODE_FORMAT_ERROR)));
+    }
+
+    let mut labels = Vec::new();
+    let mut at = HEADER_LEN;
+    loop {
+        let len = *message
+            .get(at)
+            .ok_or_else(|| Some(error_response(&query, RCODE_FORMAT_ERROR)))?
+            as usize;
+        at += 1;
+        if len == 0 {
+            break;
+        }
+        // Queries don't use name compression, so longer labels are invalid.
+        if len > 63 || at + len > message.len() {
+            return Err(Some(error_response(&query, RCODE_FORMAT_ERROR)));
+        }
+        labels.push(String::from_utf8_lossy(&message[at..at + len]).to_lowercase());
+        at += len;
+    }
+    if at + 4 > message.len() {
+        return Err(Some(error_response(&query, RCODE_FORMAT_ERROR)));
+    }
+
+    query.name = labels.join(".");
+    query.qtype = read_u16(at);
+    query.qclass = read_u16(at + 2);
+    query.question = message[HEADER_LEN..at + 4].to_vec();
+    Ok(query)
+}
+
+/// Returns a response header for `query`, with `rcode` and `answers`.
+fn header(query: &Query, rcode: u16, answers: u16) -> Vec<u8> {
+    // A response, from an authoritative server.
+    let mut flags = 0x8000 | 0x0400 | rcode;
+    if query.recursion_desired {
+        flags |= 0x0100;
+    }
+    let questions: u16 = if query.question.is_empty() { 0 } else { 1 };
+
+    let mut message = Vec::with_capacity(MAX_UDP_MESSAGE);
+    for field in &[query.id, flags, questions, answers, 0, 0] {
+        message.extend_from_slice(&field.to_be_bytes());
+    }
+    message.extend_from_slice(&query.question);
+    message
+}
+
+/// Returns an error response to `query`, without any answers.
+fn error_response(query: &Query, rcode: u16) -> Vec<u8> {
+    header(query, rcode, 0)
+}
+
+/// Returns a response to `query` with a record for each of `addrs`.
+///
+/// Records that don't fit in a UDP message are left out.
+fn answer_response(query: &Query, addrs: &[IpAddr]) -> Vec<u8> {
+    let mut records = Vec::new();
+    let mut count = 0u16;
+    let space = MAX_UDP_MESSAGE - HEADER_LEN - query.question.len();
+
+    for addr in addrs {
+        let (rtype, rdata) = match addr {
+            IpAddr::V4(ip) => (TYPE_A, ip.octets().to_vec()),
+            IpAddr::V6(ip) => (TYPE_AAAA, ip.octets().to_vec()),
+        };
+        // The name is a pointer to the question's name, which starts right
+        // after the header.
+        let mut record = vec![0xc0, HEADER_LEN as u8];
+        record.extend_from_slice(&rtype.to_be_bytes());
+        record.extend_from_slice(&CLASS_IN.to_be_bytes());
+        record.extend_from_slice(&TTL_SECONDS.to_be_bytes());
+        record.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        record.extend_from_slice(&rdata);
+
+        if records.len() + record.len() > space {
+            break;
+        }
+        records.extend_from_slice(&record);
+        count += 1;
+    }
+
+    let mut message = header(query, 0, count);
+    message.extend_from_slice(&records);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    /// Returns a query for `name` with `qtype`, like `dig` sends.
+    fn query_message(name: &str, qtype: u16) -> Vec<u8> {
+        let mut message = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in name.split('.') {
+            message.push(label.len() as u8);
+            message.extend_from_slice(label.as_bytes());
+        }
+        message.push(0);
+        message.extend_from_slice(&qtype.to_be_bytes());
+        message.extend_from_slice(&CLASS_IN.to_be_bytes());
+        message
+    }
+
+    #[test]
+    fn queries_are_parsed() {
+        let query = parse_query(&query_message("Seed.Example.com", TYPE_AAAA)).unwrap();
+        assert_eq!(query.id, 0x1234);
+        assert!(query.recursion_desired);
+        assert_eq!(query.name, "seed.example.com");
+        assert_eq!(query.qtype, TYPE_AAAA);
+        assert_eq!(query.qclass, CLASS_IN);
+
+        // Truncated questions get format errors, with the query's ID.
+        let mut truncated = query_message("seed.example.com", TYPE_A);
+        truncated.truncate(truncated.len() - 3);
+        let response = parse_query(&truncated).unwrap_err().unwrap();
+        assert_eq!(&response[..2], &[0x12, 0x34]);
+        assert_eq!(response[3] & 0xf, RCODE_FORMAT_ERROR as u8);
+
+        // Responses and runt messages are ignored.
+        let mut response = query_message("seed.example.com", TYPE_A);
+        response[2] |= 0x80;
+        assert_eq!(parse_query(&response), Err(None));
+        assert_eq!(parse_query(&[0; 4]), Err(None));
+    }
+
+    #[test]
+    fn answers_fit_in_a_udp_message() {
+        let query = parse_query(&query_message("seed.example.com", TYPE_A)).unwrap();
+        let addrs = vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)); 100];
+        let response = answer_response(&query, &addrs);
+        assert!(response.len() <= MAX_UDP_MESSAGE);
+
+        let answers = u16::from_be_bytes([response[6], response[7]]);
+        assert!(answers > 0);
+        let record_len = 2 + 2 + 2 + 4 + 2 + 4;
+        assert_eq!(
+            response.len(),
+            HEADER_LEN + query.question.len() + record_len * answers as usize
+        );
+        assert_eq!(&response[response.len() - 4..], &[192, 0, 2, 1]);
+
+        let query = parse_query(&query_message("seed.example.com", TYPE_AAAA)).unwrap();
+        let response = answer_response(&query, &[IpAddr::V6(Ipv6Addr::LOCALHOST)]);
+        assert_eq!(u16::from_be_bytes([response[6], response[7]]), 1);
+        assert_eq!(response[response.len() - 1], 1);
+    }
+}
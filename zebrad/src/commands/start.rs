@@ -1,14 +1,64 @@
-//! `start` subcommand - example of how to write a subcommand
+//! `start` subcommand - runs a full node
+//!
+//! The node opens the state, connects to the peer set, and runs the syncer,
+//! which downloads and verifies the chain to the network tip, then follows
+//! new blocks. Verified transactions wait in the mempool until they are
+//! mined, and are gossiped to peers. Peer requests are answered from the
+//! state and the mempool. If the RPC server is enabled, it answers requests
+//! from the state and the mempool, and verifies blocks that miners submit.
+//!
+//! On `SIGINT` or `SIGTERM`, the node stops finding new peers, finishes the
+//! verifications that the syncer started, stops the RPC server once it has
+//! answered the requests in progress, and flushes the state, before it
+//! exits. The address book isn't saved, so the node finds its peers again
+//! from its initial peers after a restart.
+//!
+//! Releases are only supported for a limited time, so the verifier rejects
+//! blocks above the release's end of support height. Then the syncer stops,
+//! and the node flushes the state and halts.
+//!
+//! Peer errors are counted and logged once a minute, rather than one by one.
+//! With `--dashboard`, the node shows a live dashboard, and only logs errors.
 
 /// App-local prelude includes `app_reader()`/`app_writer()`/`app_config()`
 /// accessors along with logging macros. Customize as you see fit.
 use crate::prelude::*;
 
-use crate::config::ZebradConfig;
+use crate::{
+    components::{
+        dashboard,
+        inbound::Inbound,
+        lifecycle,
+        mempool::{
+            gossip::{self, TransactionGossip},
+            rpc::RpcMempool,
+            Mempool,
+        },
+        peer_errors, resources,
+        shutdown::{self, ShutdownSignal},
+        submit::BlockSubmitter,
+        sync::{progress, ChainSync},
+    },
+    config::ZebradConfig,
+};
+
+use std::time::Duration;
 
 use abscissa_core::{config, Command, FrameworkError, Options, Runnable};
+use color_eyre::Report;
+use eyre::eyre;
+use futures::{channel::oneshot, future};
+use tokio::sync::{broadcast, mpsc};
+use tower::{buffer::Buffer, ServiceExt};
+
+use zebra_consensus::checkpoint::CheckpointList;
 
-use futures::prelude::*;
+/// How long the RPC server can take to answer its requests in progress when
+/// the node stops.
+///
+/// Long polling `getblocktemplate` requests can wait for a new block, so the
+/// node doesn't wait for them forever.
+const RPC_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
 
 /// `start` subcommand
 ///
@@ -22,23 +72,216 @@ pub struct StartCmd {
     /// Filter strings
     #[options(free)]
     filters: Vec<String>,
+
+    /// Whether to show a live dashboard, instead of logs.
+    #[options(help = "show a live dashboard, and only log errors")]
+    dashboard: bool,
 }
 
-impl Runnable for StartCmd {
-    /// Start the application.
-    fn run(&self) {
-        warn!("starting application");
-        let config = app_config();
-        if let Some(filter) = &config.tracing.filter {
-            println!("filter: {}!", filter);
+impl StartCmd {
+    async fn start(&self, shutdown: ShutdownSignal) -> Result<(), Report> {
+        let config = (*app_config()).clone();
+        let network = config.network.network;
+        info!(?network, "starting zebrad");
+
+        resources::check(&config)?;
+        if config.network.serve_compact_filters && !config.state.index_compact_filters {
+            return Err(eyre!(
+                "network.serve_compact_filters needs the state.index_compact_filters index"
+            ));
+        }
+
+        let state =
+            zebra_state::on_disk::init(config.state.clone(), network).map_err(|e| eyre!(e))?;
+
+        let best_tip_height = zebra_network::BestTipHeight::default();
+        if let zebra_state::Response::BestTip {
+            tip: Some((height, _)),
+        } = state
+            .clone()
+            .oneshot(zebra_state::Request::Tip)
+            .await
+            .map_err(|e| eyre!(e))?
+        {
+            info!(?height, "loaded the state");
+            best_tip_height.set(height);
         }
 
-        let default_config = ZebradConfig::default();
-        println!("Default config: {:?}", default_config);
+        let transaction_verifier = Buffer::new(
+            zebra_consensus::transaction::TransactionVerifier::from_config(
+                &config.consensus,
+                network,
+                state.clone(),
+            ),
+            10,
+        );
+        let (mempool_inserted, _) = broadcast::channel(zebra_rpc::events::EVENT_CHANNEL_SIZE);
+        let mempool = Buffer::new(
+            Mempool::new(&config.mempool, state.clone(), transaction_verifier)
+                .with_inserted(mempool_inserted.clone()),
+            10,
+        );
 
-        println!("Toml:\n{}", toml::Value::try_from(&default_config).unwrap());
+        let (incoming_tx, incoming_rx) = mpsc::channel(gossip::INCOMING_CHANNEL_SIZE);
+        let inbound = Buffer::new(Inbound::new(state.clone(), mempool.clone(), incoming_tx), 1);
+        let (peer_set, _, connected_peers, peer_events, network_shutdown, peer_control) =
+            zebra_network::init(config.network.clone(), inbound, best_tip_height.clone()).await;
+        tokio::spawn(progress::report_progress(
+            network,
+            best_tip_height.clone(),
+            connected_peers.clone(),
+        ));
+        tokio::spawn(lifecycle::watch(network, connected_peers.clone()));
+        tokio::spawn(peer_errors::report(peer_events.subscribe()));
+        if self.dashboard {
+            app_writer()
+                .state_mut()
+                .components
+                .get_downcast_mut::<abscissa_core::trace::Tracing>()
+                .expect("Tracing component should be available")
+                .reload_filter("error".to_owned());
+            tokio::spawn(dashboard::run(
+                network,
+                best_tip_height.clone(),
+                connected_peers.clone(),
+            ));
+        }
+        let mut network_signal = shutdown.clone();
+        tokio::spawn(async move {
+            network_signal.wait().await;
+            info!("stopping peer listeners and crawler for shutdown");
+            network_shutdown.stop();
+        });
 
-        info!("Starting placeholder loop");
+        let verifier = zebra_consensus::chain::init(
+            &config.consensus,
+            network,
+            state.clone(),
+            lifecycle::end_of_support_height(network),
+        )
+        .await
+        .map_err(|e| eyre!(e))?;
+        let max_checkpoint_height = CheckpointList::from_config(&config.consensus, network)
+            .map_err(|e| eyre!(e))?
+            .max_height();
+
+        // The RPC server stops on the shutdown signal, or when the syncer
+        // halts.
+        let (stop_rpc, rpc_stopped) = oneshot::channel::<()>();
+        let mut rpc_server = None;
+        if config.rpc.listen_addr.is_some() {
+            let miner_address = config
+                .rpc
+                .miner_address
+                .as_ref()
+                .map(|address| address.parse())
+                .transpose()
+                .map_err(|e| eyre!("invalid RPC miner address: {}", e))?;
+            let mut rpc = zebra_rpc::methods::Rpc::new(
+                state.clone(),
+                RpcMempool::new(peer_set.clone(), mempool.clone()),
+                BlockSubmitter::new(
+                    peer_set.clone(),
+                    state.clone(),
+                    verifier.clone(),
+                    mempool.clone(),
+                ),
+                network,
+                peer_control.clone(),
+                format!("v{}", env!("CARGO_PKG_VERSION")),
+                config.network.user_agent.clone(),
+                miner_address,
+            );
+            if config.rpc.events {
+                let events = zebra_rpc::events::channel();
+                let watcher = zebra_rpc::events::watch(
+                    state.clone(),
+                    mempool_inserted.subscribe(),
+                    events.clone(),
+                );
+                tokio::spawn(async move {
+                    if let Err(error) = watcher.await {
+                        error!(?error, "RPC event watcher failed");
+                    }
+                });
+                rpc = rpc.with_events(events);
+            }
+            let mut rpc_signal = shutdown.clone();
+            let rpc_shutdown = async move {
+                let signal = Box::pin(async move { rpc_signal.wait().await });
+                let _ = future::select(signal, rpc_stopped).await;
+            };
+            let server =
+                zebra_rpc::server::bind(&config.rpc, rpc, rpc_shutdown).map_err(|e| eyre!(e))?;
+            rpc_server = Some(tokio::spawn(async move {
+                if let Err(error) = server.await {
+                    error!(?error, "RPC server failed");
+                }
+            }));
+        }
+
+        let syncer = ChainSync::new(
+            &config.sync,
+            peer_set.clone(),
+            state.clone(),
+            verifier,
+            mempool.clone(),
+            peer_control,
+            max_checkpoint_height,
+            best_tip_height,
+        );
+
+        let gossip = TransactionGossip::new(
+            peer_set,
+            mempool,
+            incoming_rx,
+            peer_events.subscribe(),
+            syncer.status(),
+        );
+        tokio::spawn(async move {
+            if let Err(error) = gossip.run().await {
+                error!(?error, "transaction gossip failed");
+            }
+        });
+
+        #[cfg(feature = "systemd")]
+        {
+            use crate::components::systemd;
+
+            systemd::notify_ready();
+            tokio::spawn(systemd::watchdog(syncer.status()));
+        }
+
+        // The syncer stops at the end of support height, after it commits the
+        // blocks below it.
+        let result = syncer.sync(shutdown).await.map_err(lifecycle::halted);
+
+        // Blocks that miners submitted are verified and committed before the
+        // state is flushed.
+        let _ = stop_rpc.send(());
+        if let Some(rpc_server) = rpc_server {
+            info!("waiting for the RPC server to answer its requests");
+            if tokio::time::timeout(RPC_SHUTDOWN_TIMEOUT, rpc_server)
+                .await
+                .is_err()
+            {
+                warn!("RPC requests didn't finish before shutdown");
+            }
+        }
+
+        info!("flushing the state to disk");
+        state
+            .oneshot(zebra_state::Request::Flush)
+            .await
+            .map_err(|e| eyre!(e))?;
+        result
+    }
+}
+
+impl Runnable for StartCmd {
+    /// Start the application.
+    fn run(&self) {
+        info!("starting application");
 
         use crate::components::tokio::TokioComponent;
 
@@ -50,8 +293,38 @@ impl Runnable for StartCmd {
             .rt
             .take();
 
-        rt.expect("runtime should not already be taken")
-            .block_on(future::pending::<()>());
+        let result = rt
+            .expect("runtime should not already be taken")
+            .block_on(async {
+                let (shutdown_tx, shutdown) = shutdown::channel();
+                let start = self.start(shutdown);
+                tokio::pin!(start);
+
+                tokio::select! {
+                    result = &mut start => return result,
+                    _ = shutdown::os_signal() => {
+                        info!("shutting down, send the signal again to exit immediately");
+                    }
+                }
+                let _ = shutdown_tx.broadcast(true);
+
+                tokio::select! {
+                    result = &mut start => result,
+                    _ = shutdown::os_signal() => Err(eyre!("exited before shutdown finished")),
+                }
+            });
+
+        if let Err(e) = app_reader().flush_flamegraph() {
+            eprintln!("Could not write flamegraph: {}", e);
+        }
+
+        match result {
+            Ok(()) => {}
+            Err(e) => {
+                eprintln!("Error: {:?}", e);
+                std::process::exit(1);
+            }
+        }
     }
 }
 
@@ -1,3 +1,14 @@
+pub mod dashboard;
+pub mod inbound;
+pub mod lifecycle;
+pub mod mempool;
 pub mod metrics;
+pub mod peer_errors;
+pub mod resources;
+pub mod shutdown;
+pub mod submit;
+pub mod sync;
+#[cfg(feature = "systemd")]
+pub mod systemd;
 pub mod tokio;
 pub mod tracing;
@@ -0,0 +1,194 @@
+//! A live terminal dashboard, for watching long syncs.
+//!
+//! `zebrad start --dashboard` redraws the dashboard every
+//! [`REFRESH_INTERVAL`], and only logs errors, so the logs don't scroll it
+//! away. Rates are measured between redraws.
+
+use std::{
+    fmt::Write as _,
+    io::{self, Write as _},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+
+use zebra_chain::{block, Network};
+use zebra_network::{BestTipHeight, ConnectedPeers};
+
+/// How often the dashboard is redrawn.
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Clears the terminal, and moves the cursor to the top left.
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+/// The node's status at one redraw.
+#[derive(Clone, Debug)]
+struct Snapshot {
+    at: Instant,
+    height: Option<block::Height>,
+    estimated_height: Option<block::Height>,
+    inbound_peers: usize,
+    outbound_peers: usize,
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+impl Snapshot {
+    fn take(
+        network: Network,
+        best_tip_height: &BestTipHeight,
+        connected_peers: &Mutex<ConnectedPeers>,
+    ) -> Self {
+        let peers = connected_peers.lock().expect("mutex should be unpoisoned");
+        let byte_counts = peers.byte_counts();
+        Snapshot {
+            at: Instant::now(),
+            height: best_tip_height.get(),
+            estimated_height: peers.estimated_tip_height(network, Utc::now()),
+            inbound_peers: peers.inbound_count(),
+            outbound_peers: peers.outbound_count(),
+            bytes_sent: byte_counts.sent(),
+            bytes_received: byte_counts.received(),
+        }
+    }
+}
+
+/// Redraws the dashboard for a node on `network` every
+/// [`REFRESH_INTERVAL`].
+pub async fn run(
+    network: Network,
+    best_tip_height: BestTipHeight,
+    connected_peers: Arc<Mutex<ConnectedPeers>>,
+) {
+    let mut ticks = tokio::time::interval(REFRESH_INTERVAL);
+    let mut last: Option<Snapshot> = None;
+
+    loop {
+        ticks.tick().await;
+
+        let snapshot = Snapshot::take(network, &best_tip_height, &connected_peers);
+        let mut stdout = io::stdout();
+        // The dashboard is only for people watching, so write errors are
+        // ignored.
+        let _ = write!(
+            stdout,
+            "{}{}",
+            CLEAR_SCREEN,
+            render(network, last.as_ref(), &snapshot)
+        );
+        let _ = stdout.flush();
+        last = Some(snapshot);
+    }
+}
+
+/// Returns the dashboard for `now`, with rates since `last`.
+fn render(network: Network, last: Option<&Snapshot>, now: &Snapshot) -> String {
+    let seconds = last.map(|last| now.at.duration_since(last.at).as_secs_f64());
+    let rate = |current: u64, previous: u64| match seconds {
+        Some(seconds) if seconds > 0.0 => Some(current.saturating_sub(previous) as f64 / seconds),
+        _ => None,
+    };
+    let height = |height: Option<block::Height>| height.map_or(0, |height| u64::from(height.0));
+
+    let blocks_per_second = last.and_then(|last| rate(height(now.height), height(last.height)));
+    let sent_per_second = last.and_then(|last| rate(now.bytes_sent, last.bytes_sent));
+    let received_per_second = last.and_then(|last| rate(now.bytes_received, last.bytes_received));
+
+    let mut out = String::new();
+    writeln!(out, "zebrad {} on {:?}", env!("CARGO_PKG_VERSION"), network).unwrap();
+    writeln!(out).unwrap();
+
+    match now.height {
+        Some(tip) => write!(out, "tip height:      {}", tip.0).unwrap(),
+        None => write!(out, "tip height:      (empty state)").unwrap(),
+    }
+    if let Some(estimate) = now.estimated_height {
+        let synced = height(now.height) as f64;
+        let percent = 100.0 * synced / f64::from(estimate.0.max(1));
+        write!(out, " of ~{} ({:.2}%)", estimate.0, percent.min(100.0)).unwrap();
+    }
+    writeln!(out).unwrap();
+    writeln!(
+        out,
+        "verified:        {}",
+        per_second(blocks_per_second, "blocks")
+    )
+    .unwrap();
+    writeln!(out).unwrap();
+
+    writeln!(
+        out,
+        "peers:           {} outbound, {} inbound",
+        now.outbound_peers, now.inbound_peers
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "received:        {} ({} total)",
+        per_second(received_per_second.map(kib), "KiB"),
+        mib(now.bytes_received)
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "sent:            {} ({} total)",
+        per_second(sent_per_second.map(kib), "KiB"),
+        mib(now.bytes_sent)
+    )
+    .unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "press Ctrl-C to stop").unwrap();
+
+    out
+}
+
+fn per_second(rate: Option<f64>, unit: &str) -> String {
+    match rate {
+        Some(rate) => format!("{:.1} {}/s", rate, unit),
+        None => format!("- {}/s", unit),
+    }
+}
+
+fn kib(bytes: f64) -> f64 {
+    bytes / 1024.0
+}
+
+fn mib(bytes: u64) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dashboard_shows_rates() {
+        let last = Snapshot {
+            at: Instant::now(),
+            height: Some(block::Height(1_000)),
+            estimated_height: Some(block::Height(2_000)),
+            inbound_peers: 2,
+            outbound_peers: 8,
+            bytes_sent: 0,
+            bytes_received: 0,
+        };
+        let now = Snapshot {
+            at: last.at + Duration::from_secs(2),
+            height: Some(block::Height(1_100)),
+            bytes_sent: 4 * 1024,
+            bytes_received: 2 * 1024 * 1024,
+            ..last.clone()
+        };
+
+        let first = render(Network::Mainnet, None, &last);
+        assert!(first.contains("1000 of ~2000 (50.00%)"));
+        assert!(first.contains("- blocks/s"));
+
+        let dashboard = render(Network::Mainnet, Some(&last), &now);
+        assert!(dashboard.contains("50.0 blocks/s"));
+        assert!(dashboard.contains("8 outbound, 2 inbound"));
+        assert!(dashboard.contains("1024.0 KiB/s (2.0 MiB total)"));
+        assert!(dashboard.contains("2.0 KiB/s"));
+    }
+}
@@ -0,0 +1,237 @@
+//! A service that answers peer requests from our local state.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::prelude::*;
+use tokio::sync::{mpsc, oneshot};
+use tower::{Service, ServiceExt};
+
+use zebra_chain::transaction::UnminedTxId;
+use zebra_network::{BoxedStdError, Request, Response};
+
+use crate::components::mempool::{self, gossip::Incoming};
+
+/// The maximum number of blocks in a `getcfilters` request, from BIP157.
+///
+/// The state allows `getcfheaders` ranges, which are twice as long.
+const MAX_COMPACT_FILTERS: usize = 1000;
+
+/// Answers inbound peer requests using the state service `S`, and the
+/// mempool service `M`.
+///
+/// Blocks, block hashes, block headers, and compact filters are served from
+/// the state, and transactions from the mempool. Pushed and advertised transactions are
+/// forwarded to the transaction gossip task, and peers that push invalid
+/// transactions are disconnected.
+#[derive(Clone, Debug)]
+pub struct Inbound<S, M> {
+    state: S,
+    mempool: M,
+    incoming: mpsc::Sender<Incoming>,
+}
+
+impl<S, M> Inbound<S, M> {
+    /// Create a new inbound service that answers requests from `state` and
+    /// `mempool`, and sends peer transactions to `incoming`.
+    pub fn new(state: S, mempool: M, incoming: mpsc::Sender<Incoming>) -> Self {
+        Self {
+            state,
+            mempool,
+            incoming,
+        }
+    }
+
+    /// Sends `incoming` to the gossip task, or drops it if the gossip task
+    /// is busy.
+    fn forward(&mut self, incoming: Incoming) {
+        if let Err(error) = self.incoming.try_send(incoming) {
+            debug!(?error, "dropping peer transactions");
+        }
+    }
+}
+
+impl<S, M> Service<Request> for Inbound<S, M>
+where
+    S: Service<zebra_state::Request, Response = zebra_state::Response, Error = BoxedStdError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+    M: Service<mempool::Request, Response = mempool::Response, Error = BoxedStdError>
+        + Clone
+        + Send
+        + 'static,
+    M::Future: Send,
+{
+    type Response = Response;
+    type Error = BoxedStdError;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.state.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        match req {
+            Request::BlocksByHash(hashes) => {
+                let state = self.state.clone();
+                async move {
+                    let mut blocks = Vec::new();
+                    for hash in hashes {
+                        // Skip blocks we don't have, like `zcashd` does.
+                        let response = state
+                            .clone()
+                            .oneshot(zebra_state::Request::GetBlock { hash })
+                            .await;
+                        if let Ok(zebra_state::Response::Block { block }) = response {
+                            blocks.push(block);
+                        }
+                    }
+                    Ok(Response::Blocks(blocks))
+                }
+                .boxed()
+            }
+            Request::FindBlocks { known_blocks, stop } => self
+                .state
+                .call(zebra_state::Request::FindBlockHashes { known_blocks, stop })
+                .map(|result| match result? {
+                    zebra_state::Response::BlockHashes { hashes } => {
+                        Ok(Response::BlockHashes(hashes))
+                    }
+                    response => Err(unexpected_response(response)),
+                })
+                .boxed(),
+            Request::FindHeaders { known_blocks, stop } => self
+                .state
+                .call(zebra_state::Request::FindBlockHeaders { known_blocks, stop })
+                .map(|result| match result? {
+                    zebra_state::Response::BlockHeaders { headers } => {
+                        Ok(Response::BlockHeaders(headers))
+                    }
+                    response => Err(unexpected_response(response)),
+                })
+                .boxed(),
+            Request::TransactionsByHash(ids) => self
+                .mempool
+                .clone()
+                .oneshot(mempool::Request::TransactionsByHash(
+                    ids.iter().map(UnminedTxId::mined_id).collect(),
+                ))
+                .map(move |result| match result? {
+                    // Skip v5 transactions whose authorizing data doesn't
+                    // match the requested wide ID.
+                    mempool::Response::Transactions(transactions) => Ok(Response::Transactions(
+                        transactions
+                            .into_iter()
+                            .filter(|transaction| ids.contains(&transaction.unmined_id()))
+                            .collect(),
+                    )),
+                    response => Err(unexpected_response(response)),
+                })
+                .boxed(),
+            Request::MempoolTransactions => self
+                .mempool
+                .clone()
+                .oneshot(mempool::Request::TransactionIds)
+                .map(|result| match result? {
+                    mempool::Response::TransactionIds(ids) => Ok(Response::TransactionHashes(ids)),
+                    response => Err(unexpected_response(response)),
+                })
+                .boxed(),
+            // Filters for unknown stop hashes are skipped, like unknown blocks.
+            Request::CompactFilters { start_height, stop } => self
+                .state
+                .call(zebra_state::Request::CompactFilters { start_height, stop })
+                .map(|result| match result? {
+                    zebra_state::Response::CompactFilters { filters: None } => Ok(Response::Nil),
+                    zebra_state::Response::CompactFilters {
+                        filters: Some(range),
+                    } => {
+                        // `getcfilters` ranges are shorter than `getcfheaders`.
+                        if range.filters.len() > MAX_COMPACT_FILTERS {
+                            return Err(format!(
+                                "compact filter requests are limited to {} blocks",
+                                MAX_COMPACT_FILTERS
+                            )
+                            .into());
+                        }
+                        Ok(Response::CompactFilters(
+                            range
+                                .filters
+                                .into_iter()
+                                .map(|(hash, filter, _)| (hash, filter))
+                                .collect(),
+                        ))
+                    }
+                    response => Err(unexpected_response(response)),
+                })
+                .boxed(),
+            Request::CompactFilterHeaders { start_height, stop } => self
+                .state
+                .call(zebra_state::Request::CompactFilters { start_height, stop })
+                .map(move |result| match result? {
+                    zebra_state::Response::CompactFilters { filters: None } => Ok(Response::Nil),
+                    zebra_state::Response::CompactFilters {
+                        filters: Some(range),
+                    } => Ok(Response::CompactFilterHeaders {
+                        stop,
+                        previous: range.previous,
+                        hashes: range
+                            .filters
+                            .iter()
+                            .map(|(_, filter, _)| filter.hash())
+                            .collect(),
+                    }),
+                    response => Err(unexpected_response(response)),
+                })
+                .boxed(),
+            Request::CompactFilterCheckpoints { stop } => self
+                .state
+                .call(zebra_state::Request::CompactFilterCheckpoints { stop })
+                .map(move |result| match result? {
+                    zebra_state::Response::CompactFilterCheckpoints { headers: None } => {
+                        Ok(Response::Nil)
+                    }
+                    zebra_state::Response::CompactFilterCheckpoints {
+                        headers: Some(headers),
+                    } => Ok(Response::CompactFilterCheckpoints { stop, headers }),
+                    response => Err(unexpected_response(response)),
+                })
+                .boxed(),
+            Request::PushTransaction(transaction) => {
+                let (misbehavior_tx, misbehavior) = oneshot::channel();
+                self.forward(Incoming::Pushed(transaction, misbehavior_tx));
+                // The gossip task drops the sender if the transaction is
+                // accepted, ignored, or can't be checked.
+                async move {
+                    match misbehavior.await {
+                        Ok(misbehavior) => Err(misbehavior.into()),
+                        Err(_) => Ok(Response::Nil),
+                    }
+                }
+                .boxed()
+            }
+            Request::AdvertiseTransactionIds(ids) => {
+                self.forward(Incoming::Advertised(ids));
+                async { Ok(Response::Nil) }.boxed()
+            }
+            req => {
+                debug!(?req, "ignoring unsupported inbound request");
+                async { Ok(Response::Nil) }.boxed()
+            }
+        }
+    }
+}
+
+/// Returns an error for a state or mempool `response` that doesn't match its
+/// request.
+///
+/// The peer gets an error, rather than the node panicking.
+fn unexpected_response(response: impl std::fmt::Debug) -> BoxedStdError {
+    format!("unexpected response: {:?}", response).into()
+}
@@ -0,0 +1,201 @@
+//! Release lifecycle checks.
+//!
+//! Each release only knows about the network upgrades that were scheduled
+//! when it was built. Upgrades are scheduled at least a few months ahead, so
+//! a release is supported for [`SUPPORT_PERIOD`] after it is built. After
+//! that, an upgrade that it doesn't know about could activate, and the node
+//! could follow a chain that upgraded nodes reject.
+//!
+//! So the node warns as its end of support approaches, and its chain verifier
+//! rejects blocks above the end of support height. The syncer stops when a
+//! block is rejected for that reason, and the node halts after committing the
+//! blocks below it. The node also warns when most of its peers use a newer
+//! protocol version, which usually means that an upgrade is coming.
+//!
+//! The node doesn't detect unknown upgrades from the chain itself. Their
+//! blocks usually fail verification, because their transactions use a
+//! consensus branch ID that this release doesn't know. But that looks like
+//! any other invalid block, so the end of support height is what stops the
+//! node.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, TimeZone, Utc};
+use color_eyre::Report;
+use eyre::eyre;
+
+use zebra_chain::{block, Network};
+use zebra_consensus::chain::AboveMaxHeight;
+use zebra_network::ConnectedPeers;
+
+use crate::prelude::*;
+
+/// The approximate date of this release.
+///
+/// Update this, and [`release_height`], for each release.
+fn release_date() -> DateTime<Utc> {
+    Utc.ymd(2022, 6, 1).and_hms(0, 0, 0)
+}
+
+/// The approximate tip height of `network` when this release was made, or
+/// `None` if the network doesn't have an end of support height.
+fn release_height(network: Network) -> Option<block::Height> {
+    match network {
+        Network::Mainnet => Some(block::Height(1_700_000)),
+        Network::Testnet => Some(block::Height(1_950_000)),
+        // Regtest chains are local, so they don't get upgrades we don't know
+        // about.
+        Network::Regtest => None,
+    }
+}
+
+/// How long a release is supported for.
+pub const SUPPORT_PERIOD: Duration = Duration::from_secs(16 * 7 * 24 * 60 * 60);
+
+/// How long before the end of support the node starts warning.
+const WARNING_PERIOD: Duration = Duration::from_secs(2 * 7 * 24 * 60 * 60);
+
+/// The target block spacing since Blossom, which is used to turn the
+/// support period into blocks.
+const BLOCK_SPACING: Duration = Duration::from_secs(75);
+
+/// How often the node repeats its warnings.
+const WARNING_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// The fewest peers that must use a newer protocol version before the node
+/// warns about it, so a few unusual peers don't cause warnings.
+const MIN_NEWER_PEERS: usize = 3;
+
+/// Whether this release is still supported.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SupportStatus {
+    /// The release is supported.
+    Supported,
+    /// The release will stop being supported in less than the warning
+    /// period.
+    EndingSoon {
+        /// The days left until the end of support.
+        days_left: i64,
+    },
+    /// The release is no longer supported.
+    Ended,
+}
+
+/// Returns the support status of this release at `now`.
+pub fn support_status(now: DateTime<Utc>) -> SupportStatus {
+    let end = release_date()
+        + chrono::Duration::from_std(SUPPORT_PERIOD).expect("support period is small");
+    let warning = chrono::Duration::from_std(WARNING_PERIOD).expect("warning period is small");
+
+    if now >= end {
+        SupportStatus::Ended
+    } else if now >= end - warning {
+        SupportStatus::EndingSoon {
+            days_left: (end - now).num_days(),
+        }
+    } else {
+        SupportStatus::Supported
+    }
+}
+
+/// Returns the height on `network` where this release stops being
+/// supported, or `None` if it is always supported.
+pub fn end_of_support_height(network: Network) -> Option<block::Height> {
+    let support_blocks = (SUPPORT_PERIOD.as_secs() / BLOCK_SPACING.as_secs()) as u32;
+    release_height(network).map(|height| block::Height(height.0 + support_blocks))
+}
+
+/// Returns an error that explains why the node halted, if the chain verifier
+/// rejected a block above the end of support height. Otherwise, returns
+/// `error`.
+pub fn halted(error: Report) -> Report {
+    let max_height = error
+        .downcast_ref::<AboveMaxHeight>()
+        .map(|above| above.max_height);
+    match max_height {
+        Some(max_height) => eyre!(
+            "this zebrad release is no longer supported, and stopped following the chain at \
+             height {:?}, past which network upgrades it doesn't know about could activate: \
+             install the latest zebrad release to continue",
+            max_height,
+        ),
+        None => error,
+    }
+}
+
+/// Warns about the release lifecycle of a node on `network`, forever.
+pub async fn watch(network: Network, connected_peers: Arc<Mutex<ConnectedPeers>>) {
+    let halt_height = end_of_support_height(network);
+    let mut ticks = tokio::time::interval(WARNING_INTERVAL);
+
+    loop {
+        ticks.tick().await;
+
+        match support_status(Utc::now()) {
+            SupportStatus::Supported => {}
+            SupportStatus::EndingSoon { days_left } => warn!(
+                days_left,
+                ?halt_height,
+                "this zebrad release will stop being supported soon: \
+                 install the latest zebrad release"
+            ),
+            SupportStatus::Ended => error!(
+                ?halt_height,
+                "this zebrad release is no longer supported, and will halt at the \
+                 end of support height: install the latest zebrad release"
+            ),
+        }
+
+        let (newer, total) = {
+            let peers = connected_peers.lock().unwrap();
+            let newer = peers
+                .peers()
+                .filter(|peer| peer.version > peer.negotiated_version)
+                .count();
+            (newer, peers.len())
+        };
+        if newer >= MIN_NEWER_PEERS && newer * 2 > total {
+            warn!(
+                newer,
+                total,
+                "most peers use a newer protocol version, which usually means that a \
+                 network upgrade is coming: check for a new zebrad release"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn support_ends_after_the_support_period() {
+        let at = |days: i64| release_date() + chrono::Duration::days(days);
+
+        assert_eq!(support_status(at(0)), SupportStatus::Supported);
+        assert_eq!(
+            support_status(at(16 * 7 - 1)),
+            SupportStatus::EndingSoon { days_left: 1 }
+        );
+        assert_eq!(support_status(at(16 * 7)), SupportStatus::Ended);
+
+        assert!(end_of_support_height(Network::Mainnet) > release_height(Network::Mainnet));
+        assert_eq!(end_of_support_height(Network::Regtest), None);
+    }
+
+    #[test]
+    fn halts_are_explained() {
+        let above = AboveMaxHeight {
+            height: block::Height(11),
+            max_height: block::Height(10),
+        };
+        assert!(halted(Report::new(above))
+            .to_string()
+            .contains("no longer supported"));
+        assert_eq!(halted(eyre!("other error")).to_string(), "other error");
+    }
+}
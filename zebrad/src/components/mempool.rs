@@ -0,0 +1,655 @@
+//! A mempool of verified transactions that haven't been mined yet.
+//!
+//! Incoming transactions are checked against the mempool and the best chain
+//! tip, then verified by the consensus crate's transaction verifier. Valid
+//! transactions are stored until they are mined, expire, or are evicted to
+//! make room for transactions with a higher fee rate.
+//!
+//! Shielded double-spends are rejected if they reveal a nullifier that the
+//! best chain or another mempool transaction has already revealed.
+//! Transactions from blocks that are rolled back by a reorg aren't returned
+//! to the mempool.
+//!
+//! Transactions are exchanged with peers by the [`gossip`] task, and sent
+//! by RPC clients through [`rpc::RpcMempool`].
+
+pub mod gossip;
+pub mod rpc;
+
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use futures::prelude::*;
+use thiserror::Error;
+use tokio::sync::broadcast;
+use tower::{Service, ServiceExt};
+
+use zebra_chain::{
+    amount::{self, Amount, NegativeAllowed, NonNegative},
+    block::{self, Block},
+    orchard, sapling,
+    serialization::ZcashSerialize,
+    sprout,
+    transaction::{self, OutPoint, Transaction, TransparentInput, TransparentOutput, UnminedTxId},
+    value_balance::ValueBalanceError,
+};
+use zebra_network::BoxedStdError;
+
+use crate::config::MempoolSection;
+
+/// A mempool request.
+#[derive(Clone, Debug)]
+pub enum Request {
+    /// Verify a transaction, and add it to the mempool.
+    Queue(Arc<Transaction>),
+    /// Get the unmined IDs of every transaction in the mempool.
+    TransactionIds,
+    /// Get the mempool transactions with these hashes.
+    ///
+    /// Hashes that aren't in the mempool are skipped.
+    TransactionsByHash(HashSet<transaction::Hash>),
+    /// Get every mempool transaction, with its fee, for a block template.
+    TransactionsWithFees,
+    /// Update the mempool after `block` was added to the best chain.
+    ///
+    /// Removes the transactions that were mined, that conflict with the
+    /// block, or that have expired. If the block isn't the child of the last
+    /// block, the chain was reorganized, so every transaction's inputs are
+    /// checked against the state again.
+    BlockCommitted(Arc<Block>),
+}
+
+/// A mempool response.
+#[derive(Clone, Debug)]
+pub enum Response {
+    /// The transaction with this hash was added to the mempool.
+    Queued(transaction::Hash),
+    /// The unmined IDs of every transaction in the mempool.
+    ///
+    /// Peers need the wide IDs of v5 transactions, and RPC clients need
+    /// their transaction IDs, so the mempool returns both.
+    TransactionIds(Vec<UnminedTxId>),
+    /// The requested mempool transactions.
+    Transactions(Vec<Arc<Transaction>>),
+    /// Every mempool transaction, with its fee.
+    TransactionsWithFees(Vec<(Arc<Transaction>, Amount<NonNegative>)>),
+    /// The mempool was updated for a new block.
+    Updated,
+}
+
+/// A reason that a transaction wasn't added to the mempool.
+#[derive(Error, Debug)]
+pub enum MempoolError {
+    /// The transaction is already in the mempool.
+    #[error("transaction is already in the mempool")]
+    Duplicate,
+    /// The transaction spends an output, or reveals a nullifier, that is
+    /// already spent by another mempool transaction.
+    #[error("transaction conflicts with mempool transaction {0:?}")]
+    Conflict(transaction::Hash),
+    /// The transaction spends an output that isn't in the best chain or the
+    /// mempool, or that has already been spent.
+    #[error("transaction spends a missing or spent output {0:?}")]
+    MissingInput(OutPoint),
+    /// The transaction reveals a nullifier that the best chain has already
+    /// revealed.
+    #[error("transaction reveals a nullifier that is already in the best chain")]
+    RevealedNullifier,
+    /// The transaction creates more value than it spends.
+    #[error("transaction fee is negative")]
+    NegativeFee,
+    /// The transaction's values don't fit in the valid range of amounts.
+    #[error("transaction value is out of range")]
+    ValueOutOfRange(#[from] amount::Error),
+    /// The transaction's fee rate is below the configured minimum.
+    #[error("transaction fee {fee:?} is below the minimum fee {min_fee:?}")]
+    LowFee {
+        /// The transaction's fee.
+        fee: Amount<NegativeAllowed>,
+        /// The minimum fee for a transaction of this size.
+        min_fee: Amount<NegativeAllowed>,
+    },
+    /// The mempool is full of transactions with a higher fee rate.
+    #[error("mempool is full of transactions with a higher fee rate")]
+    Full,
+}
+
+/// A nullifier from any shielded pool.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+enum AnyNullifier {
+    Sprout(sprout::Nullifier),
+    Sapling(sapling::Nullifier),
+    Orchard(orchard::Nullifier),
+}
+
+/// Returns every nullifier revealed by `transaction`.
+fn nullifiers(transaction: &Transaction) -> Vec<AnyNullifier> {
+    transaction
+        .sprout_nullifiers()
+        .map(|n| AnyNullifier::Sprout(*n))
+        .chain(
+            transaction
+                .sapling_nullifiers()
+                .map(|n| AnyNullifier::Sapling(*n)),
+        )
+        .chain(
+            transaction
+                .orchard_nullifiers()
+                .map(|n| AnyNullifier::Orchard(*n)),
+        )
+        .collect()
+}
+
+/// Returns every transparent output spent by `transaction`.
+fn spent_outpoints(transaction: &Transaction) -> Vec<OutPoint> {
+    transaction
+        .inputs()
+        .filter_map(|input| match input {
+            TransparentInput::PrevOut { outpoint, .. } => Some(*outpoint),
+            TransparentInput::Coinbase { .. } => None,
+        })
+        .collect()
+}
+
+/// A verified mempool transaction.
+#[derive(Debug)]
+struct Entry {
+    transaction: Arc<Transaction>,
+    /// The serialized size of the transaction, in bytes.
+    size: usize,
+    /// The transaction fee, in zatoshis.
+    fee: i64,
+}
+
+impl Entry {
+    /// Returns true if this entry pays a lower fee per byte than `other`.
+    fn has_lower_fee_rate(&self, other: &Entry) -> bool {
+        // Compare fee / size without rounding.
+        (self.fee as i128) * (other.size as i128) < (other.fee as i128) * (self.size as i128)
+    }
+}
+
+/// The mempool transactions, and the outputs and nullifiers they spend.
+#[derive(Debug, Default)]
+struct Storage {
+    transactions: HashMap<transaction::Hash, Entry>,
+    spent_outpoints: HashMap<OutPoint, transaction::Hash>,
+    spent_nullifiers: HashMap<AnyNullifier, transaction::Hash>,
+    /// The total size of the mempool transactions, in bytes.
+    bytes: usize,
+    /// The hash of the last block the mempool was updated for.
+    tip: Option<block::Hash>,
+    /// Gets the hash of each transaction that is added to the mempool.
+    inserted: Option<broadcast::Sender<transaction::Hash>>,
+}
+
+impl Storage {
+    /// Returns an error if `transaction` is already in the mempool, or
+    /// conflicts with a mempool transaction.
+    fn check_conflicts(&self, transaction: &Transaction) -> Result<(), MempoolError> {
+        if self
+            .transactions
+            .contains_key(&transaction::Hash::from(transaction))
+        {
+            return Err(MempoolError::Duplicate);
+        }
+        for outpoint in spent_outpoints(transaction) {
+            if let Some(hash) = self.spent_outpoints.get(&outpoint) {
+                return Err(MempoolError::Conflict(*hash));
+            }
+        }
+        for nullifier in nullifiers(transaction) {
+            if let Some(hash) = self.spent_nullifiers.get(&nullifier) {
+                return Err(MempoolError::Conflict(*hash));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the output at `outpoint`, if it was created by a mempool
+    /// transaction.
+    fn output(&self, outpoint: &OutPoint) -> Option<TransparentOutput> {
+        self.transactions
+            .get(&outpoint.hash)
+            .and_then(|entry| entry.transaction.outputs().nth(outpoint.index as usize))
+            .cloned()
+    }
+
+    /// Adds `entry`, which must not conflict with any mempool transaction.
+    fn insert(&mut self, entry: Entry) {
+        let hash = transaction::Hash::from(entry.transaction.as_ref());
+        for outpoint in spent_outpoints(&entry.transaction) {
+            self.spent_outpoints.insert(outpoint, hash);
+        }
+        for nullifier in nullifiers(&entry.transaction) {
+            self.spent_nullifiers.insert(nullifier, hash);
+        }
+        self.bytes += entry.size;
+        self.transactions.insert(hash, entry);
+    }
+
+    /// Removes the transaction with `hash`.
+    ///
+    /// If `with_dependents` is true, also removes the mempool transactions
+    /// that spend its outputs, because those outputs won't exist.
+    fn remove(&mut self, hash: transaction::Hash, with_dependents: bool) {
+        let entry = match self.transactions.remove(&hash) {
+            Some(entry) => entry,
+            None => return,
+        };
+        for outpoint in spent_outpoints(&entry.transaction) {
+            self.spent_outpoints.remove(&outpoint);
+        }
+        for nullifier in nullifiers(&entry.transaction) {
+            self.spent_nullifiers.remove(&nullifier);
+        }
+        self.bytes -= entry.size;
+
+        if with_dependents {
+            for index in 0..entry.transaction.outputs().count() {
+                let outpoint = OutPoint {
+                    hash,
+                    index: index as u32,
+                };
+                if let Some(dependent) = self.spent_outpoints.get(&outpoint).cloned() {
+                    self.remove(dependent, true);
+                }
+            }
+        }
+    }
+
+    /// Evicts the transactions with the lowest fee rate, until the mempool
+    /// is no larger than `max_bytes`.
+    fn evict(&mut self, max_bytes: usize) {
+        while self.bytes > max_bytes {
+            let lowest = self
+                .transactions
+                .iter()
+                .fold(
+                    None,
+                    |lowest: Option<(&transaction::Hash, &Entry)>, next| match lowest {
+                        Some(lowest) if !next.1.has_lower_fee_rate(lowest.1) => Some(lowest),
+                        _ => Some(next),
+                    },
+                )
+                .map(|(hash, _)| *hash);
+
+            match lowest {
+                Some(hash) => {
+                    self.remove(hash, true);
+                    metrics::counter!("mempool.evicted", 1);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Removes the transactions in `block`, the transactions that conflict
+    /// with it, and the transactions that expire after it.
+    fn remove_mined(&mut self, block: &Block, height: block::Height) {
+        for transaction in &block.transactions {
+            let hash = transaction::Hash::from(transaction.as_ref());
+            self.remove(hash, false);
+
+            let conflicts: Vec<_> = spent_outpoints(transaction)
+                .iter()
+                .filter_map(|outpoint| self.spent_outpoints.get(outpoint))
+                .chain(
+                    nullifiers(transaction)
+                        .iter()
+                        .filter_map(|nullifier| self.spent_nullifiers.get(nullifier)),
+                )
+                .cloned()
+                .collect();
+            for conflict in conflicts {
+                self.remove(conflict, true);
+            }
+        }
+
+        let next_height = block::Height(height.0 + 1);
+        let expired: Vec<_> = self
+            .transactions
+            .iter()
+            .filter(|(_, entry)| entry.transaction.is_expired_at(next_height))
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in expired {
+            self.remove(hash, true);
+        }
+    }
+
+    /// Updates the mempool metrics.
+    fn update_metrics(&self) {
+        metrics::gauge!("mempool.transactions", self.transactions.len() as i64);
+        metrics::gauge!("mempool.bytes", self.bytes as i64);
+    }
+}
+
+/// A mempool, which verifies transactions with `ZV`, and checks their
+/// inputs against the state `ZS`.
+#[derive(Clone, Debug)]
+pub struct Mempool<ZS, ZV> {
+    /// The maximum total size of the mempool transactions, in bytes.
+    max_bytes: usize,
+    /// The minimum fee for each 1000 bytes of a transaction, in zatoshis.
+    min_fee_per_kilobyte: i64,
+    storage: Arc<Mutex<Storage>>,
+    state: ZS,
+    verifier: ZV,
+}
+
+impl<ZS, ZV> Mempool<ZS, ZV> {
+    /// Returns an empty mempool, with the limits in `config`.
+    pub fn new(config: &MempoolSection, state: ZS, verifier: ZV) -> Self {
+        Self {
+            max_bytes: config.max_bytes,
+            min_fee_per_kilobyte: config.min_fee_per_kilobyte as i64,
+            storage: Arc::new(Mutex::new(Storage::default())),
+            state,
+            verifier,
+        }
+    }
+
+    /// Returns the mempool, sending the hash of each transaction that it
+    /// accepts to `inserted`.
+    pub fn with_inserted(self, inserted: broadcast::Sender<transaction::Hash>) -> Self {
+        self.storage.lock().unwrap().inserted = Some(inserted);
+        self
+    }
+}
+
+impl<ZS, ZV> Service<Request> for Mempool<ZS, ZV>
+where
+    ZS: Service<zebra_state::Request, Response = zebra_state::Response, Error = BoxedStdError>
+        + Clone
+        + Send
+        + 'static,
+    ZS::Future: Send,
+    ZV: Service<
+            zebra_consensus::transaction::Request,
+            Response = transaction::Hash,
+            Error = BoxedStdError,
+        > + Clone
+        + Send
+        + 'static,
+    ZV::Future: Send,
+{
+    type Response = Response;
+    type Error = BoxedStdError;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // The state and verifier readiness is checked in the response future.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        match req {
+            Request::Queue(transaction) => {
+                let max_bytes = self.max_bytes;
+                let min_fee_per_kilobyte = self.min_fee_per_kilobyte;
+                let storage = self.storage.clone();
+                let state = self.state.clone();
+                let verifier = self.verifier.clone();
+                async move {
+                    let result = queue(
+                        transaction,
+                        max_bytes,
+                        min_fee_per_kilobyte,
+                        storage,
+                        state,
+                        verifier,
+                    )
+                    .await;
+                    if result.is_err() {
+                        metrics::counter!("mempool.rejected", 1);
+                    }
+                    result.map(Response::Queued)
+                }
+                .boxed()
+            }
+            Request::TransactionIds => {
+                let storage = self.storage.lock().unwrap();
+                let ids = storage
+                    .transactions
+                    .values()
+                    .map(|entry| entry.transaction.unmined_id())
+                    .collect();
+                async move { Ok(Response::TransactionIds(ids)) }.boxed()
+            }
+            Request::TransactionsByHash(hashes) => {
+                let storage = self.storage.lock().unwrap();
+                let transactions = hashes
+                    .iter()
+                    .filter_map(|hash| storage.transactions.get(hash))
+                    .map(|entry| entry.transaction.clone())
+                    .collect();
+                async move { Ok(Response::Transactions(transactions)) }.boxed()
+            }
+            Request::TransactionsWithFees => {
+                let storage = self.storage.lock().unwrap();
+                let transactions = storage
+                    .transactions
+                    .values()
+                    .map(|entry| {
+                        let fee = Amount::try_from(entry.fee)
+                            .expect("mempool transactions have non-negative fees");
+                        (entry.transaction.clone(), fee)
+                    })
+                    .collect();
+                async move { Ok(Response::TransactionsWithFees(transactions)) }.boxed()
+            }
+            Request::BlockCommitted(block) => {
+                let storage = self.storage.clone();
+                let state = self.state.clone();
+                async move {
+                    block_committed(block, storage, state).await?;
+                    Ok(Response::Updated)
+                }
+                .boxed()
+            }
+        }
+    }
+}
+
+/// Verifies `transaction`, and adds it to `storage`.
+async fn queue<ZS, ZV>(
+    transaction: Arc<Transaction>,
+    max_bytes: usize,
+    min_fee_per_kilobyte: i64,
+    storage: Arc<Mutex<Storage>>,
+    state: ZS,
+    verifier: ZV,
+) -> Result<transaction::Hash, BoxedStdError>
+where
+    ZS: Service<zebra_state::Request, Response = zebra_state::Response, Error = BoxedStdError>
+        + Clone,
+    ZV: Service<
+        zebra_consensus::transaction::Request,
+        Response = transaction::Hash,
+        Error = BoxedStdError,
+    >,
+{
+    // Find the outputs this transaction spends from other mempool
+    // transactions, then look up the rest in the state.
+    let mut utxos = HashMap::new();
+    let mut missing = Vec::new();
+    {
+        let storage = storage.lock().unwrap();
+        storage.check_conflicts(&transaction)?;
+        for outpoint in spent_outpoints(&transaction) {
+            match storage.output(&outpoint) {
+                Some(output) => {
+                    utxos.insert(outpoint, output);
+                }
+                None => missing.push(outpoint),
+            }
+        }
+    }
+    for outpoint in missing {
+        let output = unspent_output(state.clone(), outpoint)
+            .await?
+            .ok_or(MempoolError::MissingInput(outpoint))?;
+        utxos.insert(outpoint, output);
+    }
+    for nullifier in nullifiers(&transaction) {
+        if revealed_in_chain(state.clone(), nullifier).await? {
+            Err(MempoolError::RevealedNullifier)?;
+        }
+    }
+
+    let height = match state.clone().oneshot(zebra_state::Request::Tip).await? {
+        zebra_state::Response::BestTip {
+            tip: Some((height, _)),
+        } => block::Height(height.0 + 1),
+        zebra_state::Response::BestTip { tip: None } => block::Height(0),
+        response => return Err(format!("unexpected state response: {:?}", response).into()),
+    };
+    let hash = verifier
+        .oneshot(zebra_consensus::transaction::Request::Mempool {
+            transaction: transaction.clone(),
+            height,
+            known_utxos: Arc::new(utxos.clone()),
+        })
+        .await?;
+
+    // The fee is the sum of the value that the transaction removes from
+    // each pool.
+    let balance = transaction
+        .value_balance(&utxos)
+        .map_err(|error| match error {
+            ValueBalanceError::MissingUtxo(outpoint) => MempoolError::MissingInput(outpoint),
+            ValueBalanceError::Transparent(error)
+            | ValueBalanceError::Sprout(error)
+            | ValueBalanceError::Sapling(error)
+            | ValueBalanceError::Orchard(error) => MempoolError::ValueOutOfRange(error),
+        })?;
+    let fee = (balance.transparent_amount()
+        + balance.sprout_amount()
+        + balance.sapling_amount()
+        + balance.orchard_amount())
+    .map_err(MempoolError::from)?;
+    if fee < Amount::zero() {
+        Err(MempoolError::NegativeFee)?;
+    }
+
+    let size = transaction.zcash_serialized_size();
+    let min_fee =
+        Amount::try_from(min_fee_per_kilobyte * size as i64 / 1000).map_err(MempoolError::from)?;
+    if fee < min_fee {
+        Err(MempoolError::LowFee { fee, min_fee })?;
+    }
+
+    let mut storage = storage.lock().unwrap();
+    // Another transaction could have arrived while this one was verified.
+    storage.check_conflicts(&transaction)?;
+    storage.insert(Entry {
+        transaction,
+        size,
+        fee: fee.into(),
+    });
+    storage.evict(max_bytes);
+    storage.update_metrics();
+
+    if storage.transactions.contains_key(&hash) {
+        if let Some(inserted) = &storage.inserted {
+            // Sending fails if there aren't any subscribers.
+            let _ = inserted.send(hash);
+        }
+        Ok(hash)
+    } else {
+        Err(MempoolError::Full.into())
+    }
+}
+
+/// Updates `storage` after `block` was added to the best chain.
+async fn block_committed<ZS>(
+    block: Arc<Block>,
+    storage: Arc<Mutex<Storage>>,
+    state: ZS,
+) -> Result<(), BoxedStdError>
+where
+    ZS: Service<zebra_state::Request, Response = zebra_state::Response, Error = BoxedStdError>
+        + Clone,
+{
+    let height = block
+        .coinbase_height()
+        .ok_or("committed block has no coinbase height")?;
+
+    let reorged = {
+        let mut storage = storage.lock().unwrap();
+        storage.remove_mined(&block, height);
+        let reorged =
+            storage.tip.is_some() && storage.tip != Some(block.header.previous_block_hash);
+        storage.tip = Some(block.hash());
+        reorged
+    };
+
+    if reorged {
+        // The outputs spent by mempool transactions might not be in the new
+        // best chain.
+        let spent: Vec<_> = {
+            let storage = storage.lock().unwrap();
+            storage
+                .spent_outpoints
+                .iter()
+                .filter(|(outpoint, _)| !storage.transactions.contains_key(&outpoint.hash))
+                .map(|(outpoint, hash)| (*outpoint, *hash))
+                .collect()
+        };
+        for (outpoint, hash) in spent {
+            if unspent_output(state.clone(), outpoint).await?.is_none() {
+                storage.lock().unwrap().remove(hash, true);
+            }
+        }
+    }
+
+    storage.lock().unwrap().update_metrics();
+    Ok(())
+}
+
+/// Returns true if the best chain reveals `nullifier`.
+async fn revealed_in_chain<ZS>(state: ZS, nullifier: AnyNullifier) -> Result<bool, BoxedStdError>
+where
+    ZS: Service<zebra_state::Request, Response = zebra_state::Response, Error = BoxedStdError>,
+{
+    let request = match nullifier {
+        AnyNullifier::Sprout(nullifier) => {
+            zebra_state::Request::ContainsSproutNullifier { nullifier }
+        }
+        AnyNullifier::Sapling(nullifier) => {
+            zebra_state::Request::ContainsSaplingNullifier { nullifier }
+        }
+        AnyNullifier::Orchard(nullifier) => {
+            zebra_state::Request::ContainsOrchardNullifier { nullifier }
+        }
+    };
+    match state.oneshot(request).await? {
+        zebra_state::Response::ContainsNullifier { contains } => Ok(contains),
+        response => Err(format!("unexpected state response: {:?}", response).into()),
+    }
+}
+
+/// Returns the unspent output at `outpoint` in the best chain.
+async fn unspent_output<ZS>(
+    state: ZS,
+    outpoint: OutPoint,
+) -> Result<Option<TransparentOutput>, BoxedStdError>
+where
+    ZS: Service<zebra_state::Request, Response = zebra_state::Response, Error = BoxedStdError>,
+{
+    match state
+        .oneshot(zebra_state::Request::GetUtxo { outpoint })
+        .await?
+    {
+        zebra_state::Response::Utxo { output } => Ok(output),
+        response => Err(format!("unexpected state response: {:?}", response).into()),
+    }
+}
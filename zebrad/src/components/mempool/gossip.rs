@@ -0,0 +1,364 @@
+//! Transaction gossip.
+//!
+//! Inbound peer messages can't use the peer set, because the peer set is
+//! built after the inbound service. So the inbound service forwards pushed
+//! and advertised transactions to [`TransactionGossip`], which downloads
+//! them, adds them to the mempool, and advertises the accepted ones to
+//! other peers.
+//!
+//! Once the syncer reaches the network tip, each newly connected peer
+//! prompts a `mempool` request, so the mempool fills with the transactions
+//! that were sent before we connected.
+//!
+//! Each transaction is only downloaded or checked by one task at a time,
+//! and there are at most [`MAX_GOSSIP_TASKS`] tasks. While they are all
+//! busy, new messages wait in the incoming channel, so a peer that floods
+//! us with transactions fills the channel, and the rest are dropped.
+
+use std::{collections::HashSet, sync::Arc};
+
+use color_eyre::Report;
+use eyre::eyre;
+use futures::{
+    future::{BoxFuture, FutureExt},
+    stream::{FuturesUnordered, StreamExt},
+};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tower::{Service, ServiceExt};
+use tracing::{debug, trace};
+
+use zebra_chain::transaction::{self, Transaction, UnminedTxId};
+use zebra_consensus::VerificationError;
+use zebra_network::{BoxedStdError, Misbehavior, PeerEvent};
+
+use super::{Request, Response};
+use crate::components::sync::SyncStatus;
+
+/// The number of inbound transaction messages that can wait for the gossip
+/// task, before new ones are dropped.
+pub const INCOMING_CHANNEL_SIZE: usize = 100;
+
+/// The maximum number of downloads, pushes, and mempool crawls that can run
+/// at once.
+pub const MAX_GOSSIP_TASKS: usize = 50;
+
+/// What a gossip task did, once it finishes.
+enum Finished {
+    /// The task is done with the transactions with these IDs.
+    Handled(HashSet<UnminedTxId>),
+    /// A peer's mempool has the transactions with these IDs.
+    Crawled(HashSet<UnminedTxId>),
+}
+
+/// Transactions sent to us by peers.
+#[derive(Debug)]
+pub enum Incoming {
+    /// A peer advertised transactions with these IDs.
+    ///
+    /// v5 transactions are advertised by their wide ID, so a copy with
+    /// different authorizing data has a different ID.
+    Advertised(HashSet<UnminedTxId>),
+    /// A peer pushed a transaction, without advertising it first.
+    ///
+    /// If the transaction breaks a consensus rule, the reason is sent on the
+    /// channel, so the peer's connection can be closed.
+    Pushed(Arc<Transaction>, oneshot::Sender<Misbehavior>),
+}
+
+/// Moves transactions between peers and the mempool `ZM`, using the peer
+/// set `ZN`.
+#[derive(Debug)]
+pub struct TransactionGossip<ZN, ZM> {
+    peers: ZN,
+    mempool: ZM,
+    incoming: mpsc::Receiver<Incoming>,
+    peer_events: broadcast::Receiver<PeerEvent>,
+    sync_status: SyncStatus,
+    /// The running tasks.
+    tasks: FuturesUnordered<BoxFuture<'static, Finished>>,
+    /// The IDs of the transactions that the running tasks are handling.
+    in_flight: HashSet<UnminedTxId>,
+}
+
+impl<ZN, ZM> TransactionGossip<ZN, ZM>
+where
+    ZN: Service<zebra_network::Request, Response = zebra_network::Response, Error = BoxedStdError>
+        + Send
+        + Clone
+        + 'static,
+    ZN::Future: Send,
+    ZM: Service<Request, Response = Response, Error = BoxedStdError> + Send + Clone + 'static,
+    ZM::Future: Send,
+{
+    /// Returns a gossip task for the transactions from `incoming`, and the
+    /// peers in `peer_events`.
+    ///
+    /// Transactions are ignored until `sync_status` reaches the tip, because
+    /// they can't be verified against an old tip.
+    pub fn new(
+        peers: ZN,
+        mempool: ZM,
+        incoming: mpsc::Receiver<Incoming>,
+        peer_events: broadcast::Receiver<PeerEvent>,
+        sync_status: SyncStatus,
+    ) -> Self {
+        Self {
+            peers,
+            mempool,
+            incoming,
+            peer_events,
+            sync_status,
+            tasks: FuturesUnordered::new(),
+            in_flight: HashSet::new(),
+        }
+    }
+
+    /// Handles incoming transactions and new peers, until the inbound
+    /// service or the peer set is dropped.
+    pub async fn run(mut self) -> Result<(), Report> {
+        loop {
+            tokio::select! {
+                Some(finished) = self.tasks.next(), if !self.tasks.is_empty() => match finished {
+                    Finished::Handled(ids) => {
+                        for id in &ids {
+                            self.in_flight.remove(id);
+                        }
+                    }
+                    Finished::Crawled(ids) => self.download(ids),
+                },
+                incoming = self.incoming.recv(), if self.tasks.len() < MAX_GOSSIP_TASKS => {
+                    let incoming = incoming.ok_or_else(|| eyre!("inbound service was dropped"))?;
+                    if !self.sync_status.is_close_to_tip() {
+                        trace!("ignoring peer transactions until the syncer reaches the tip");
+                        continue;
+                    }
+                    match incoming {
+                        Incoming::Advertised(ids) => self.download(ids),
+                        Incoming::Pushed(transaction, misbehavior) => {
+                            self.push(transaction, misbehavior)
+                        }
+                    }
+                }
+                event = self.peer_events.recv() => match event {
+                    Ok(PeerEvent::HandshakeCompleted { addr, .. }) => {
+                        if !self.sync_status.is_close_to_tip() {
+                            continue;
+                        }
+                        if self.tasks.len() >= MAX_GOSSIP_TASKS {
+                            debug!(?addr, "skipping mempool crawl, because gossip is busy");
+                            continue;
+                        }
+                        debug!(?addr, "crawling mempool after new peer connection");
+                        self.crawl();
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::RecvError::Lagged(skipped)) => {
+                        debug!(?skipped, "missed some peer events");
+                    }
+                    Err(broadcast::RecvError::Closed) => {
+                        return Err(eyre!("peer set was dropped"));
+                    }
+                },
+            }
+        }
+    }
+
+    /// Asks a peer for the transaction IDs in its mempool, and downloads the
+    /// ones we don't have.
+    ///
+    /// The peer set picks the peer, so it isn't always the new one.
+    fn crawl(&mut self) {
+        let peers = self.peers.clone();
+        let crawl = async move {
+            match peers
+                .oneshot(zebra_network::Request::MempoolTransactions)
+                .await
+            {
+                Ok(zebra_network::Response::TransactionHashes(ids)) => {
+                    Finished::Crawled(ids.into_iter().collect())
+                }
+                Ok(response) => {
+                    debug!(?response, "unexpected response to a mempool request");
+                    Finished::Crawled(HashSet::new())
+                }
+                Err(error) => {
+                    debug!(?error, "mempool request failed");
+                    Finished::Crawled(HashSet::new())
+                }
+            }
+        };
+        self.tasks.push(crawl.boxed());
+    }
+
+    /// Downloads the transactions with `ids`, and adds them to the mempool,
+    /// skipping the ones that other tasks are handling.
+    ///
+    /// Each crawl starts at most one download, so this doesn't check the
+    /// task limit.
+    fn download(&mut self, mut ids: HashSet<UnminedTxId>) {
+        ids.retain(|id| !self.in_flight.contains(id));
+        if ids.is_empty() {
+            return;
+        }
+        self.in_flight.extend(ids.iter().cloned());
+
+        let download = download(self.peers.clone(), self.mempool.clone(), ids.clone());
+        self.tasks
+            .push(download.map(move |_| Finished::Handled(ids)).boxed());
+    }
+
+    /// Adds the pushed `transaction` to the mempool, and advertises it to
+    /// peers.
+    ///
+    /// Downloaded transactions come from a peer chosen by the peer set, so
+    /// only pushed transactions can be blamed on the peer that sent them.
+    fn push(&mut self, transaction: Arc<Transaction>, misbehavior: oneshot::Sender<Misbehavior>) {
+        let id = transaction.unmined_id();
+        if !self.in_flight.insert(id) {
+            trace!(
+                ?id,
+                "ignoring a pushed transaction that is already being handled"
+            );
+            return;
+        }
+
+        let peers = self.peers.clone();
+        let mempool = self.mempool.clone();
+        let push = async move {
+            match queue_and_advertise(peers, mempool, transaction).await {
+                Ok(_) => {}
+                Err(error) if is_misbehavior(&error) => {
+                    debug!(%error, "peer pushed an invalid transaction");
+                    let _ = misbehavior.send(Misbehavior(error.to_string()));
+                }
+                Err(error) => trace!(?error, "mempool rejected a peer transaction"),
+            }
+
+            let mut ids = HashSet::new();
+            ids.insert(id);
+            Finished::Handled(ids)
+        };
+        self.tasks.push(push.boxed());
+    }
+}
+
+/// Returns true if `error` means that a transaction or block breaks a
+/// consensus rule, regardless of our view of the chain.
+pub(crate) fn is_misbehavior(error: &BoxedStdError) -> bool {
+    error
+        .downcast_ref::<VerificationError>()
+        .map_or(false, VerificationError::is_misbehavior)
+}
+
+/// Downloads the transactions with `ids` that aren't in the mempool, and adds
+/// them to the mempool.
+///
+/// The mempool is keyed by transaction ID, so a transaction is skipped if the
+/// mempool has a copy with different authorizing data.
+async fn download<ZN, ZM>(peers: ZN, mempool: ZM, mut ids: HashSet<UnminedTxId>)
+where
+    ZN: Service<zebra_network::Request, Response = zebra_network::Response, Error = BoxedStdError>
+        + Clone,
+    ZM: Service<Request, Response = Response, Error = BoxedStdError> + Clone,
+{
+    let hashes = ids.iter().map(UnminedTxId::mined_id).collect();
+    if let Ok(Response::Transactions(known)) = mempool
+        .clone()
+        .oneshot(Request::TransactionsByHash(hashes))
+        .await
+    {
+        let known: HashSet<_> = known
+            .iter()
+            .map(|transaction| transaction::Hash::from(transaction.as_ref()))
+            .collect();
+        ids.retain(|id| !known.contains(&id.mined_id()));
+    }
+    if ids.is_empty() {
+        return;
+    }
+
+    match peers
+        .clone()
+        .oneshot(zebra_network::Request::TransactionsByHash(ids))
+        .await
+    {
+        Ok(zebra_network::Response::Transactions(transactions)) => {
+            add(peers, mempool, transactions).await
+        }
+        Ok(response) => debug!(?response, "unexpected response to a transaction request"),
+        Err(error) => debug!(?error, "transaction download failed"),
+    }
+}
+
+/// Adds `transactions` to the mempool, and advertises the accepted ones to
+/// peers.
+async fn add<ZN, ZM>(peers: ZN, mempool: ZM, transactions: Vec<Arc<Transaction>>)
+where
+    ZN: Service<zebra_network::Request, Response = zebra_network::Response, Error = BoxedStdError>,
+    ZM: Service<Request, Response = Response, Error = BoxedStdError> + Clone,
+{
+    let mut accepted = HashSet::new();
+    for transaction in transactions {
+        let id = transaction.unmined_id();
+        match queue(mempool.clone(), transaction).await {
+            Ok(_) => {
+                accepted.insert(id);
+            }
+            Err(error) => trace!(?error, "mempool rejected a peer transaction"),
+        }
+    }
+    if !accepted.is_empty() {
+        advertise(peers, accepted).await;
+    }
+}
+
+/// Adds `transaction` to the mempool, and advertises it to peers.
+///
+/// Returns the mempool's error if it rejects the transaction.
+pub async fn queue_and_advertise<ZN, ZM>(
+    peers: ZN,
+    mempool: ZM,
+    transaction: Arc<Transaction>,
+) -> Result<transaction::Hash, BoxedStdError>
+where
+    ZN: Service<zebra_network::Request, Response = zebra_network::Response, Error = BoxedStdError>,
+    ZM: Service<Request, Response = Response, Error = BoxedStdError>,
+{
+    let id = transaction.unmined_id();
+    let hash = queue(mempool, transaction).await?;
+    let mut ids = HashSet::new();
+    ids.insert(id);
+    advertise(peers, ids).await;
+    Ok(hash)
+}
+
+/// Adds `transaction` to the mempool.
+async fn queue<ZM>(
+    mempool: ZM,
+    transaction: Arc<Transaction>,
+) -> Result<transaction::Hash, BoxedStdError>
+where
+    ZM: Service<Request, Response = Response, Error = BoxedStdError>,
+{
+    match mempool.oneshot(Request::Queue(transaction)).await? {
+        Response::Queued(hash) => Ok(hash),
+        response => Err(format!("unexpected mempool response: {:?}", response).into()),
+    }
+}
+
+/// Advertises the transactions with `ids` to peers.
+///
+/// v5 transactions are advertised by their wide ID, so peers use `MSG_WTX`
+/// to request them.
+async fn advertise<ZN>(peers: ZN, ids: HashSet<UnminedTxId>)
+where
+    ZN: Service<zebra_network::Request, Response = zebra_network::Response, Error = BoxedStdError>,
+{
+    metrics::counter!("mempool.gossiped", ids.len() as u64);
+    if let Err(error) = peers
+        .oneshot(zebra_network::Request::AdvertiseTransactionIds(ids))
+        .await
+    {
+        debug!(?error, "transaction advertisement failed");
+    }
+}
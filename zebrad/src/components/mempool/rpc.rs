@@ -0,0 +1,174 @@
+//! The mempool service for the RPC server.
+//!
+//! RPC methods can't use the mempool's own request types, because they
+//! live in `zebrad`. This service answers the RPC crate's mempool requests,
+//! and turns mempool errors into the reject reasons that RPC clients expect.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::prelude::*;
+use tower::{Service, ServiceExt};
+
+use zebra_chain::transaction::UnminedTxId;
+use zebra_consensus::VerificationError;
+use zebra_network::BoxedStdError;
+use zebra_rpc::mempool::{Candidate, Rejection, Request, Response};
+
+use super::{gossip, MempoolError};
+
+/// Answers RPC mempool requests using the mempool `ZM`, and advertises sent
+/// transactions using the peer set `ZN`.
+#[derive(Clone, Debug)]
+pub struct RpcMempool<ZN, ZM> {
+    peers: ZN,
+    mempool: ZM,
+}
+
+impl<ZN, ZM> RpcMempool<ZN, ZM> {
+    /// Returns a service for RPC requests to `mempool`.
+    pub fn new(peers: ZN, mempool: ZM) -> Self {
+        Self { peers, mempool }
+    }
+}
+
+impl<ZN, ZM> Service<Request> for RpcMempool<ZN, ZM>
+where
+    ZN: Service<zebra_network::Request, Response = zebra_network::Response, Error = BoxedStdError>
+        + Clone
+        + Send
+        + 'static,
+    ZN::Future: Send,
+    ZM: Service<super::Request, Response = super::Response, Error = BoxedStdError>
+        + Clone
+        + Send
+        + 'static,
+    ZM::Future: Send,
+{
+    type Response = Response;
+    type Error = BoxedStdError;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        match req {
+            Request::TransactionIds => self
+                .mempool
+                .clone()
+                .oneshot(super::Request::TransactionIds)
+                .map_ok(|response| match response {
+                    super::Response::TransactionIds(ids) => {
+                        Response::TransactionIds(ids.iter().map(UnminedTxId::mined_id).collect())
+                    }
+                    _ => unreachable!("TransactionIds always returns TransactionIds"),
+                })
+                .boxed(),
+            Request::TransactionsByHash(hashes) => self
+                .mempool
+                .clone()
+                .oneshot(super::Request::TransactionsByHash(hashes))
+                .map_ok(|response| match response {
+                    super::Response::Transactions(transactions) => {
+                        Response::Transactions(transactions)
+                    }
+                    _ => unreachable!("TransactionsByHash always returns Transactions"),
+                })
+                .boxed(),
+            Request::Send(transaction) => {
+                let peers = self.peers.clone();
+                let mempool = self.mempool.clone();
+                async move {
+                    match gossip::queue_and_advertise(peers, mempool, transaction).await {
+                        Ok(hash) => Ok(Response::Sent(hash)),
+                        Err(error) => rejection(error).map(Response::Rejected),
+                    }
+                }
+                .boxed()
+            }
+            Request::BlockCandidates => self
+                .mempool
+                .clone()
+                .oneshot(super::Request::TransactionsWithFees)
+                .map_ok(|response| match response {
+                    super::Response::TransactionsWithFees(transactions) => {
+                        Response::BlockCandidates(
+                            transactions
+                                .into_iter()
+                                .map(|(transaction, fee)| Candidate { transaction, fee })
+                                .collect(),
+                        )
+                    }
+                    _ => unreachable!("TransactionsWithFees always returns TransactionsWithFees"),
+                })
+                .boxed(),
+        }
+    }
+}
+
+/// Returns the reject reason for a mempool `error`.
+///
+/// Mempool and verification errors are both rejections, because the
+/// transaction was checked, and found unacceptable. Other errors, like an
+/// overloaded verifier or a failed state request, mean that the transaction
+/// couldn't be checked, so they are returned unchanged.
+fn rejection(error: BoxedStdError) -> Result<Rejection, BoxedStdError> {
+    if let Some(mempool_error) = error.downcast_ref::<MempoolError>() {
+        use MempoolError::*;
+
+        return Ok(match mempool_error {
+            Duplicate => Rejection::AlreadyInMempool,
+            MissingInput(_) => Rejection::MissingInputs,
+            Conflict(_)
+            | RevealedNullifier
+            | NegativeFee
+            | ValueOutOfRange(_)
+            | LowFee { .. }
+            | Full => Rejection::Invalid(error.to_string()),
+        });
+    }
+
+    if let Some(verification_error) = error.downcast_ref::<VerificationError>() {
+        use VerificationError::*;
+
+        return Ok(match verification_error {
+            Header { .. }
+            | Block { .. }
+            | Coinbase { .. }
+            | Transaction { .. }
+            | Script { .. }
+            | Proof { .. }
+            | Signature { .. } => Rejection::Invalid(error.to_string()),
+            // These rules depend on our view of the chain.
+            Contextual { .. } => Rejection::InvalidInChain(error.to_string()),
+        });
+    }
+
+    Err(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unchecked_transactions_are_not_rejected() {
+        assert_eq!(
+            rejection(MempoolError::Duplicate.into()).unwrap(),
+            Rejection::AlreadyInMempool
+        );
+        assert!(matches!(
+            rejection(MempoolError::Full.into()),
+            Ok(Rejection::Invalid(_))
+        ));
+
+        let error = rejection("transaction verifier is overloaded".into()).unwrap_err();
+        assert_eq!(error.to_string(), "transaction verifier is overloaded");
+    }
+}
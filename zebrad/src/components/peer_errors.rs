@@ -0,0 +1,143 @@
+//! Aggregated reporting of peer errors.
+//!
+//! Peers fail all the time, and logging each failure drowns out everything
+//! else during a long sync. Instead, failures are counted by kind, and the
+//! counts are logged once per [`REPORT_INTERVAL`].
+
+use std::{collections::HashMap, time::Duration};
+
+use tokio::sync::broadcast::{self, RecvError};
+
+use zebra_network::PeerEvent;
+
+use crate::prelude::*;
+
+/// How often peer error counts are logged.
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The number of error kinds included in each report, from most to least
+/// common.
+const REPORTED_KINDS: usize = 5;
+
+/// The longest error kind, in characters, so that errors with long details
+/// don't make very long log lines.
+const MAX_KIND_LEN: usize = 60;
+
+/// Counts of peer errors, by their kind.
+#[derive(Debug, Default)]
+pub struct ErrorCounts {
+    counts: HashMap<String, usize>,
+}
+
+impl ErrorCounts {
+    /// Count the error or disconnection in `event`, if it has one.
+    ///
+    /// Connections that closed without an error aren't counted.
+    pub fn add(&mut self, event: &PeerEvent) {
+        let error = match event {
+            PeerEvent::RequestFailed { error, .. }
+            | PeerEvent::InboundRequestFailed { error, .. } => error,
+            PeerEvent::Disconnected { reason, .. } if !reason.is_empty() => reason,
+            _ => return,
+        };
+        *self.counts.entry(error_kind(error)).or_insert(0) += 1;
+    }
+
+    /// Returns the total number of errors.
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    /// Returns the `limit` most common error kinds, and their counts.
+    pub fn most_common(&self, limit: usize) -> Vec<(&str, usize)> {
+        let mut counts: Vec<(&str, usize)> = self
+            .counts
+            .iter()
+            .map(|(kind, count)| (kind.as_str(), *count))
+            .collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        counts.truncate(limit);
+        counts
+    }
+}
+
+/// Returns the kind of `error`, which is its message without the details
+/// after the first `:`.
+///
+/// Most details, like addresses and hashes, are different for every error.
+fn error_kind(error: &str) -> String {
+    let kind = error.split(':').next().unwrap_or(error).trim();
+    kind.chars().take(MAX_KIND_LEN).collect()
+}
+
+/// Counts the peer errors in `events`, and logs the counts every
+/// [`REPORT_INTERVAL`], if there were any.
+pub async fn report(mut events: broadcast::Receiver<PeerEvent>) {
+    let mut ticks = tokio::time::interval(REPORT_INTERVAL);
+    // The first tick finishes immediately.
+    ticks.tick().await;
+    let mut counts = ErrorCounts::default();
+
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {
+                if counts.total() > 0 {
+                    let kinds = counts
+                        .most_common(REPORTED_KINDS)
+                        .iter()
+                        .map(|(kind, count)| format!("{} x{}", kind, count))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    info!(
+                        total = counts.total(),
+                        interval = ?REPORT_INTERVAL,
+                        %kinds,
+                        "peer errors"
+                    );
+                    metrics::gauge!("peer.errors_per_interval", counts.total() as i64);
+                }
+                counts = ErrorCounts::default();
+            }
+            event = events.recv() => match event {
+                Ok(event) => counts.add(&event),
+                Err(RecvError::Lagged(skipped)) => {
+                    debug!(?skipped, "peer error report missed some peer events");
+                }
+                Err(RecvError::Closed) => return,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_are_counted_by_kind() {
+        let addr = "192.0.2.1:8233".parse().unwrap();
+        let mut counts = ErrorCounts::default();
+        for error in &[
+            "Timeout: request to 192.0.2.1:8233 timed out",
+            "Timeout: request to 192.0.2.2:8233 timed out",
+            "Connection closed",
+        ] {
+            counts.add(&PeerEvent::RequestFailed {
+                addr,
+                error: error.to_string(),
+            });
+        }
+        // Clean disconnections aren't errors.
+        counts.add(&PeerEvent::Disconnected {
+            addr,
+            reason: String::new(),
+        });
+
+        assert_eq!(counts.total(), 3);
+        assert_eq!(
+            counts.most_common(5),
+            vec![("Timeout", 2), ("Connection closed", 1)]
+        );
+        assert_eq!(counts.most_common(1), vec![("Timeout", 2)]);
+    }
+}
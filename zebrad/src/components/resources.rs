@@ -0,0 +1,228 @@
+//! Startup checks for the resources that a node needs.
+//!
+//! Running out of disk space or file descriptors part way through a sync
+//! fails in confusing ways, so the node checks them before it opens the
+//! state.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use color_eyre::Report;
+use eyre::{eyre, WrapErr};
+
+use zebra_chain::Network;
+
+use crate::{config::ZebradConfig, prelude::*};
+
+const GIGABYTE: u64 = 1024 * 1024 * 1024;
+
+/// An estimate of the size of the full chain state, in bytes.
+///
+/// This includes room for the chain to grow for a while after it is synced.
+fn expected_state_size(network: Network) -> u64 {
+    match network {
+        Network::Mainnet => 50 * GIGABYTE,
+        Network::Testnet => 20 * GIGABYTE,
+        Network::Regtest => GIGABYTE,
+    }
+}
+
+/// The free space that a node needs when it prunes old blocks.
+///
+/// The pruned state size depends on the prune depth and the number of
+/// unspent outputs, so this is only a minimum.
+const MIN_PRUNED_FREE_SPACE: u64 = GIGABYTE;
+
+/// The file descriptors that the node needs, apart from peer connections.
+///
+/// These are used by the state database, the RPC and metrics endpoints, and
+/// logging.
+const BASE_FILE_DESCRIPTORS: u64 = 512;
+
+/// The fewest file descriptors that the node can run with.
+const MIN_FILE_DESCRIPTORS: u64 = 256;
+
+/// Checks that the node has the resources it needs to run with `config`, and
+/// raises its file descriptor limit if it can.
+///
+/// Returns an error that says how to fix the problem, if a check fails.
+pub fn check(config: &ZebradConfig) -> Result<(), Report> {
+    let network = config.network.network;
+    let db_path = config.state.db_path(network);
+
+    check_writable(&db_path)?;
+    check_filesystem(&db_path);
+
+    let needed = if config.state.prune_depth.is_some() {
+        MIN_PRUNED_FREE_SPACE
+    } else {
+        expected_state_size(network).saturating_sub(directory_size(&db_path))
+    };
+    let available = fs2::available_space(&db_path)
+        .wrap_err_with(|| format!("could not check the free space in {:?}", db_path))?;
+    if available < needed {
+        return Err(eyre!(
+            "the state needs about {} GB of free disk space, but {:?} only has {} GB free: \
+             free up some space, set state.cache_dir to a larger disk, \
+             or set state.prune_depth to keep less of the chain",
+            gigabytes(needed),
+            db_path,
+            gigabytes(available),
+        ));
+    }
+    info!(
+        available_gb = gigabytes(available),
+        needed_gb = gigabytes(needed),
+        "checked free disk space"
+    );
+
+    let wanted = BASE_FILE_DESCRIPTORS
+        + config.network.max_inbound_connections as u64
+        + config.network.target_outbound_peers as u64;
+    raise_file_descriptor_limit(wanted)?;
+
+    Ok(())
+}
+
+fn gigabytes(bytes: u64) -> u64 {
+    bytes / GIGABYTE
+}
+
+/// Creates `dir` if needed, and checks that we can write files to it.
+fn check_writable(dir: &Path) -> Result<(), Report> {
+    let advice = "check that the directory is owned by the user running zebrad, \
+                  or set state.cache_dir to another directory";
+
+    fs::create_dir_all(dir)
+        .wrap_err_with(|| format!("could not create the state directory {:?}: {}", dir, advice))?;
+
+    let test_file = dir.join(".zebrad-write-test");
+    let write = || -> io::Result<()> {
+        fs::File::create(&test_file)?.write_all(b"zebrad")?;
+        fs::remove_file(&test_file)
+    };
+    write().wrap_err_with(|| format!("the state directory {:?} is not writable: {}", dir, advice))
+}
+
+/// Warns if `dir` is on a filesystem that the state database doesn't work
+/// well with.
+#[cfg(target_os = "linux")]
+#[allow(clippy::unnecessary_cast)]
+fn check_filesystem(dir: &Path) {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    const TMPFS_MAGIC: i64 = 0x0102_1994;
+    const NFS_SUPER_MAGIC: i64 = 0x6969;
+    const SMB_SUPER_MAGIC: i64 = 0x517b;
+    const CIFS_MAGIC_NUMBER: i64 = 0xff53_4d42;
+
+    let path = match CString::new(dir.as_os_str().as_bytes()) {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+    let mut stats: libc::statfs = unsafe { std::mem::zeroed() };
+    // Safety: `path` is a valid C string, and `stats` is a valid statfs.
+    if unsafe { libc::statfs(path.as_ptr(), &mut stats) } != 0 {
+        debug!(error = ?io::Error::last_os_error(), "could not check the state filesystem");
+        return;
+    }
+
+    match stats.f_type as i64 {
+        TMPFS_MAGIC => warn!(
+            ?dir,
+            "the state is in memory, on tmpfs, so it will be lost on reboot: \
+             set state.cache_dir to a directory on disk"
+        ),
+        NFS_SUPER_MAGIC | SMB_SUPER_MAGIC | CIFS_MAGIC_NUMBER => warn!(
+            ?dir,
+            "the state is on a network filesystem, which can be slow and can corrupt \
+             the database if the connection drops: set state.cache_dir to a local disk"
+        ),
+        _ => {}
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn check_filesystem(_dir: &Path) {}
+
+/// Returns the total size of the files in `dir`, or zero if it can't be read.
+fn directory_size(dir: &Path) -> u64 {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => directory_size(&entry.path()),
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+/// Raises the soft file descriptor limit towards `wanted`, up to the hard
+/// limit.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+fn raise_file_descriptor_limit(wanted: u64) -> Result<(), Report> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // Safety: `limit` is a valid rlimit.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        warn!(error = ?io::Error::last_os_error(), "could not check the file descriptor limit");
+        return Ok(());
+    }
+
+    let current = limit.rlim_cur as u64;
+    if current < wanted {
+        let raised = wanted.min(limit.rlim_max as u64);
+        limit.rlim_cur = raised as libc::rlim_t;
+        // Safety: `limit` is a valid rlimit, with a soft limit below the
+        // hard limit.
+        if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) } == 0 {
+            info!(
+                from = current,
+                to = raised,
+                "raised the file descriptor limit"
+            );
+        } else {
+            warn!(error = ?io::Error::last_os_error(), "could not raise the file descriptor limit");
+        }
+    }
+
+    // Safety: `limit` is a valid rlimit.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return Ok(());
+    }
+    let limit = limit.rlim_cur as u64;
+    if limit < MIN_FILE_DESCRIPTORS {
+        return Err(eyre!(
+            "zebrad needs at least {} file descriptors, but its limit is {}: \
+             raise the limit with `ulimit -n`, or LimitNOFILE in a systemd unit",
+            MIN_FILE_DESCRIPTORS,
+            limit,
+        ));
+    }
+    if limit < wanted {
+        warn!(
+            limit,
+            wanted,
+            "the file descriptor limit is low, so some peer connections may fail: \
+             raise it with `ulimit -n`, or reduce the network connection limits"
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn raise_file_descriptor_limit(_wanted: u64) -> Result<(), Report> {
+    Ok(())
+}
@@ -0,0 +1,60 @@
+//! Graceful shutdown.
+//!
+//! When `zebrad` gets `SIGINT` or `SIGTERM` (or Ctrl-C on Windows), it stops
+//! accepting and dialing peers, lets the syncer finish the verifications it
+//! has started, stops the RPC server, and flushes the state to disk, before
+//! it exits. A second signal exits immediately.
+
+use tokio::sync::watch;
+
+/// Returns a sender that starts a shutdown, and a signal that tasks can
+/// wait on.
+pub fn channel() -> (watch::Sender<bool>, ShutdownSignal) {
+    let (sender, receiver) = watch::channel(false);
+    (sender, ShutdownSignal(receiver))
+}
+
+/// A cloneable handle to whether `zebrad` is shutting down.
+#[derive(Clone, Debug)]
+pub struct ShutdownSignal(watch::Receiver<bool>);
+
+impl ShutdownSignal {
+    /// Returns true if `zebrad` is shutting down.
+    pub fn is_shutting_down(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Waits until `zebrad` is shutting down.
+    ///
+    /// Also returns if the sender is dropped, because nothing can start a
+    /// shutdown after that.
+    pub async fn wait(&mut self) {
+        while !self.is_shutting_down() {
+            if self.0.recv().await.is_none() {
+                return;
+            }
+        }
+    }
+}
+
+/// Waits for an operating system signal that asks `zebrad` to stop.
+pub async fn os_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            return;
+        }
+    }
+
+    // If we can't listen for signals, wait forever, rather than shutting
+    // down straight away.
+    if tokio::signal::ctrl_c().await.is_err() {
+        futures::future::pending::<()>().await;
+    }
+}
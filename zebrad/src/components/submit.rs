@@ -0,0 +1,168 @@
+//! Block submission for the RPC server.
+//!
+//! Mined blocks go through the same chain verifier as the syncer's blocks.
+//! Blocks that become the new best tip are advertised to peers, and removed
+//! from the mempool, because the syncer doesn't download our own blocks.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::prelude::*;
+use tower::{Service, ServiceExt};
+use tracing::{debug, warn};
+
+use zebra_chain::{
+    block::{self, Block},
+    parameters::GENESIS_PREVIOUS_BLOCK_HASH,
+};
+use zebra_consensus::VerificationError;
+use zebra_network::BoxedStdError;
+use zebra_rpc::submit::{Request, Response};
+
+use crate::components::mempool;
+
+/// Answers RPC block submissions using the chain verifier `ZV`, the state
+/// `ZS`, the peer set `ZN`, and the mempool `ZM`.
+#[derive(Clone, Debug)]
+pub struct BlockSubmitter<ZN, ZS, ZV, ZM> {
+    peers: ZN,
+    state: ZS,
+    verifier: ZV,
+    mempool: ZM,
+}
+
+impl<ZN, ZS, ZV, ZM> BlockSubmitter<ZN, ZS, ZV, ZM> {
+    /// Returns a service that verifies submitted blocks with `verifier`.
+    pub fn new(peers: ZN, state: ZS, verifier: ZV, mempool: ZM) -> Self {
+        Self {
+            peers,
+            state,
+            verifier,
+            mempool,
+        }
+    }
+}
+
+impl<ZN, ZS, ZV, ZM> Service<Request> for BlockSubmitter<ZN, ZS, ZV, ZM>
+where
+    ZN: Service<zebra_network::Request, Response = zebra_network::Response, Error = BoxedStdError>
+        + Clone
+        + Send
+        + 'static,
+    ZN::Future: Send,
+    ZS: Service<zebra_state::Request, Response = zebra_state::Response, Error = BoxedStdError>
+        + Clone
+        + Send
+        + 'static,
+    ZS::Future: Send,
+    ZV: Service<Arc<Block>, Response = block::Hash, Error = BoxedStdError> + Clone + Send + 'static,
+    ZV::Future: Send,
+    ZM: Service<mempool::Request, Response = mempool::Response, Error = BoxedStdError>
+        + Clone
+        + Send
+        + 'static,
+    ZM::Future: Send,
+{
+    type Response = Response;
+    type Error = BoxedStdError;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        match req {
+            Request::Submit(block) => submit(
+                self.peers.clone(),
+                self.state.clone(),
+                self.verifier.clone(),
+                self.mempool.clone(),
+                block,
+            )
+            .boxed(),
+        }
+    }
+}
+
+/// Verifies `block`, and if it is the new best tip, advertises it to
+/// `peers` and updates `mempool`.
+///
+/// Blocks that break a consensus rule are rejected. Blocks that couldn't be
+/// checked, because their parent is missing or a service failed, are
+/// inconclusive.
+async fn submit<ZN, ZS, ZV, ZM>(
+    peers: ZN,
+    state: ZS,
+    verifier: ZV,
+    mempool: ZM,
+    block: Arc<Block>,
+) -> Result<Response, BoxedStdError>
+where
+    ZN: Service<zebra_network::Request, Response = zebra_network::Response, Error = BoxedStdError>,
+    ZS: Service<zebra_state::Request, Response = zebra_state::Response, Error = BoxedStdError>
+        + Clone,
+    ZV: Service<Arc<Block>, Response = block::Hash, Error = BoxedStdError>,
+    ZM: Service<mempool::Request, Response = mempool::Response, Error = BoxedStdError>,
+{
+    let hash = block.hash();
+
+    if let zebra_state::Response::Depth { depth: Some(_) } = state
+        .clone()
+        .oneshot(zebra_state::Request::Depth { hash })
+        .await?
+    {
+        return Ok(Response::Duplicate);
+    }
+
+    // The state queues blocks until their parent arrives, so an orphan
+    // would never finish verifying. Every block in any chain has a Sapling
+    // tree after it.
+    let parent = block.header.previous_block_hash;
+    if parent != GENESIS_PREVIOUS_BLOCK_HASH {
+        if let zebra_state::Response::SaplingTree { tree: None } = state
+            .clone()
+            .oneshot(zebra_state::Request::GetSaplingTree { hash: parent })
+            .await?
+        {
+            debug!(?hash, ?parent, "submitted block is an orphan");
+            return Ok(Response::Inconclusive);
+        }
+    }
+
+    if let Err(error) = verifier.oneshot(block.clone()).await {
+        debug!(?hash, ?error, "submitted block failed verification");
+        return match error.downcast_ref::<VerificationError>() {
+            Some(error) => Ok(Response::Rejected(format!("{}: {}", error, error.inner()))),
+            None => Ok(Response::Inconclusive),
+        };
+    }
+
+    match state.oneshot(zebra_state::Request::Tip).await? {
+        zebra_state::Response::BestTip {
+            tip: Some((_, tip_hash)),
+        } if tip_hash == hash => {}
+        _ => return Ok(Response::Inconclusive),
+    }
+
+    metrics::counter!("rpc.submitted_blocks", 1);
+    if let Err(error) = peers
+        .oneshot(zebra_network::Request::AdvertiseBlock(hash))
+        .await
+    {
+        debug!(?error, "block advertisement failed");
+    }
+    if let Err(error) = mempool
+        .oneshot(mempool::Request::BlockCommitted(block))
+        .await
+    {
+        warn!(?error, "failed to update the mempool for a submitted block");
+    }
+
+    Ok(Response::Accepted)
+}
@@ -0,0 +1,719 @@
+//! The syncer, which downloads the chain from peers, and verifies it.
+//!
+//! Each sync round starts by sending a block locator for the state's best
+//! chain to several peers (`ObtainTips`). Their responses are trimmed to the
+//! hashes that aren't in the state, and the last two hashes of each response
+//! become a prospective tip. Then each tip is extended by asking a peer for
+//! the hashes after it (`ExtendTips`), checking that the response continues
+//! from the expected next hash, so peers that send unrelated hashes are
+//! ignored.
+//!
+//! Every new hash is downloaded, a few blocks per request, until the
+//! lookahead limit is reached. Blocks are sent to the verifier in the order
+//! they were found, and verified concurrently. The verifier checks each
+//! block against the blocks before it, so it waits for their verification.
+//!
+//! If a download fails, the round is abandoned, and the next round restarts
+//! from the state tip. Above the final checkpoint, an invalid block is
+//! reported against the peer that sent it, and the rest of the round
+//! continues. If the verifier rejects a block above its maximum height, the
+//! syncer commits the blocks below it, then stops.
+
+pub mod progress;
+
+use std::{
+    collections::HashSet,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use color_eyre::Report;
+use eyre::eyre;
+use futures::{
+    future::BoxFuture,
+    stream::{FuturesOrdered, FuturesUnordered, StreamExt},
+    FutureExt,
+};
+use tokio::task::JoinHandle;
+use tower::{retry::Retry, Service, ServiceExt};
+use tracing::{debug, info, warn};
+
+use zebra_chain::block::{self, Block};
+use zebra_consensus::{block::ParentNotAdded, chain::AboveMaxHeight, checkpoint::CheckpointError};
+use zebra_network::{BestTipHeight, BoxedStdError, PeerControl, RetryPeerErrors};
+
+use crate::{
+    components::{
+        mempool::{self, gossip::is_misbehavior},
+        shutdown::ShutdownSignal,
+    },
+    config::SyncSection,
+};
+
+/// The number of blocks in each download request.
+const BLOCKS_PER_REQUEST: usize = 10;
+
+/// The number of peers that are asked for tips at the start of each round.
+const FANOUT: usize = 4;
+
+/// The number of times a failed peer request is retried on another peer.
+const PEER_REQUEST_RETRIES: usize = 3;
+
+/// How long the syncer waits at the tip before it looks for new blocks.
+const TIP_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long the syncer waits after a failure, before it restarts from the
+/// state tip.
+const FAILURE_RESTART_DELAY: Duration = Duration::from_secs(10);
+
+/// How long the syncer waits for checkpoint verifications during a shutdown.
+const SHUTDOWN_VERIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A tip that a peer advertised, and the hash that it said comes next.
+///
+/// Extending the tip only accepts responses that start with `expected_next`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+struct CheckedTip {
+    tip: block::Hash,
+    expected_next: block::Hash,
+}
+
+/// A cloneable handle to whether the syncer has reached the network tip.
+///
+/// The syncer sets it after a round that finds no new blocks. Components
+/// that are only useful near the tip, like the mempool, can wait until then.
+///
+/// It also records when the syncer last made progress, so a stuck syncer
+/// can be detected.
+#[derive(Clone, Debug)]
+pub struct SyncStatus {
+    close_to_tip: Arc<Mutex<bool>>,
+    last_progress: Arc<Mutex<Instant>>,
+}
+
+impl Default for SyncStatus {
+    fn default() -> Self {
+        SyncStatus {
+            close_to_tip: Arc::new(Mutex::new(false)),
+            last_progress: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+}
+
+impl SyncStatus {
+    /// Returns true if the syncer has reached the network tip.
+    pub fn is_close_to_tip(&self) -> bool {
+        *self
+            .close_to_tip
+            .lock()
+            .expect("mutex should be unpoisoned")
+    }
+
+    /// Returns the time since the syncer last verified a block, or finished
+    /// a round without errors.
+    pub fn time_since_progress(&self) -> Duration {
+        self.last_progress
+            .lock()
+            .expect("mutex should be unpoisoned")
+            .elapsed()
+    }
+
+    /// Records that the syncer has reached the network tip.
+    fn set_close_to_tip(&self) {
+        *self
+            .close_to_tip
+            .lock()
+            .expect("mutex should be unpoisoned") = true;
+    }
+
+    /// Records that the syncer has made progress.
+    fn record_progress(&self) {
+        *self
+            .last_progress
+            .lock()
+            .expect("mutex should be unpoisoned") = Instant::now();
+    }
+}
+
+/// A download of a few blocks, in a separate task.
+type Download = JoinHandle<Result<Vec<Arc<Block>>, Report>>;
+
+/// The verification of a block above the final checkpoint, with the block
+/// and its height.
+type TipVerification = BoxFuture<
+    'static,
+    (
+        Arc<Block>,
+        block::Height,
+        Result<block::Hash, BoxedStdError>,
+    ),
+>;
+
+/// Downloads blocks from the peer set `ZN`, and verifies them using the
+/// verifier `ZV`, which adds them to the state `ZS`.
+///
+/// The mempool `ZM` is updated with each block after the final checkpoint.
+#[derive(Debug)]
+pub struct ChainSync<ZN, ZS, ZV, ZM> {
+    /// The peer set, with retries for failed requests.
+    peers: Retry<RetryPeerErrors, ZN>,
+    /// The state service, for block locators and known hashes.
+    state: ZS,
+    /// The chain verifier.
+    verifier: ZV,
+    /// The mempool, which removes mined transactions.
+    mempool: ZM,
+    /// Disconnects the peers that send invalid blocks.
+    peer_control: PeerControl,
+    /// The height of the final checkpoint.
+    ///
+    /// Checkpointed blocks are verified in ranges, so the syncer sends every
+    /// block in a range before it waits for any of them.
+    max_checkpoint_height: block::Height,
+    /// The height of the best verified block, which is sent to peers.
+    best_tip_height: BestTipHeight,
+    /// The maximum number of blocks that are downloaded, but not verified.
+    lookahead_limit: usize,
+    /// Whether the syncer has reached the network tip.
+    status: SyncStatus,
+
+    /// The tips that haven't been extended yet, in this round.
+    prospective_tips: HashSet<CheckedTip>,
+    /// The hashes that have been downloaded, or are being downloaded, in
+    /// this round.
+    requested: HashSet<block::Hash>,
+    /// The blocks being downloaded, in the order they were found.
+    downloads: FuturesOrdered<Download>,
+    /// The number of blocks in `downloads`.
+    in_flight: usize,
+}
+
+impl<ZN, ZS, ZV, ZM> ChainSync<ZN, ZS, ZV, ZM>
+where
+    ZN: Service<zebra_network::Request, Response = zebra_network::Response, Error = BoxedStdError>
+        + Send
+        + Clone
+        + 'static,
+    ZN::Future: Send,
+    ZS: Service<zebra_state::Request, Response = zebra_state::Response, Error = BoxedStdError>
+        + Send
+        + Clone
+        + 'static,
+    ZS::Future: Send,
+    ZV: Service<Arc<Block>, Response = block::Hash, Error = BoxedStdError> + Send + Clone + 'static,
+    ZV::Future: Send + 'static,
+    ZM: Service<mempool::Request, Response = mempool::Response, Error = BoxedStdError>
+        + Send
+        + 'static,
+    ZM::Future: Send,
+{
+    /// Returns a syncer that downloads blocks from `peers`, and verifies them
+    /// using `verifier`.
+    pub fn new(
+        config: &SyncSection,
+        peers: ZN,
+        state: ZS,
+        verifier: ZV,
+        mempool: ZM,
+        peer_control: PeerControl,
+        max_checkpoint_height: block::Height,
+        best_tip_height: BestTipHeight,
+    ) -> Self {
+        ChainSync {
+            peers: Retry::new(RetryPeerErrors::new(PEER_REQUEST_RETRIES), peers),
+            state,
+            verifier,
+            mempool,
+            peer_control,
+            max_checkpoint_height,
+            best_tip_height,
+            lookahead_limit: config.lookahead_limit,
+            status: SyncStatus::default(),
+            prospective_tips: HashSet::new(),
+            requested: HashSet::new(),
+            downloads: FuturesOrdered::new(),
+            in_flight: 0,
+        }
+    }
+
+    /// Returns a handle to whether this syncer has reached the network tip.
+    pub fn status(&self) -> SyncStatus {
+        self.status.clone()
+    }
+
+    /// Syncs to the network tip, then follows new blocks, until `shutdown`.
+    ///
+    /// Failed rounds are restarted from the state tip, so this only returns
+    /// an error if the state or verifier returns an unexpected response
+    /// kind, or the verifier rejects a block above its maximum height.
+    ///
+    /// After `shutdown`, waits for the blocks that have already been sent to
+    /// the verifier, but doesn't start any new downloads.
+    pub async fn sync(mut self, mut shutdown: ShutdownSignal) -> Result<(), Report> {
+        loop {
+            if shutdown.is_shutting_down() {
+                info!("syncer stopped for shutdown");
+                return Ok(());
+            }
+
+            let result = self.sync_round(&mut shutdown).await;
+            self.reset();
+
+            if result.is_ok() {
+                self.status.record_progress();
+            }
+            match result {
+                Ok(true) => {}
+                Ok(false) => {
+                    debug!("no new blocks, waiting for the tip to advance");
+                    self.status.set_close_to_tip();
+                    tokio::select! {
+                        _ = tokio::time::delay_for(TIP_POLL_INTERVAL) => {}
+                        _ = shutdown.wait() => {}
+                    }
+                }
+                Err(error) if error.downcast_ref::<AboveMaxHeight>().is_some() => {
+                    info!(?error, "syncer stopped at the verifier's maximum height");
+                    return Err(error);
+                }
+                Err(error) => {
+                    warn!(?error, "sync failed, restarting from the state tip");
+                    metrics::counter!("sync.restarts", 1);
+                    tokio::select! {
+                        _ = tokio::time::delay_for(FAILURE_RESTART_DELAY) => {}
+                        _ = shutdown.wait() => {}
+                    }
+                }
+            }
+        }
+    }
+
+    /// Drops the tips and downloads from the last round.
+    ///
+    /// Downloads that are already running finish in their own tasks, but
+    /// their blocks are ignored.
+    fn reset(&mut self) {
+        self.prospective_tips.clear();
+        self.requested.clear();
+        self.downloads = FuturesOrdered::new();
+        self.in_flight = 0;
+    }
+
+    /// Downloads and verifies blocks until there are no tips left to extend,
+    /// or until `shutdown`.
+    ///
+    /// Returns true if any blocks were verified.
+    async fn sync_round(&mut self, shutdown: &mut ShutdownSignal) -> Result<bool, Report> {
+        self.obtain_tips().await?;
+
+        let mut verified_any = false;
+        let mut checkpoint_verifications = FuturesUnordered::new();
+        let mut tip_verifications = FuturesUnordered::new();
+        while !shutdown.is_shutting_down() {
+            while !self.prospective_tips.is_empty() && self.in_flight < self.lookahead_limit {
+                self.extend_tips().await?;
+            }
+
+            let blocks = tokio::select! {
+                blocks = self.downloads.next() => match blocks {
+                    Some(blocks) => blocks??,
+                    None => break,
+                },
+                _ = shutdown.wait() => break,
+            };
+            self.in_flight -= blocks.len();
+            metrics::gauge!("sync.in_flight_blocks", self.in_flight as i64);
+
+            for block in blocks {
+                self.verify(block, &mut checkpoint_verifications, &mut tip_verifications)
+                    .await?;
+                self.status.record_progress();
+                verified_any = true;
+            }
+        }
+
+        let verified = async {
+            drain(&mut checkpoint_verifications).await?;
+            self.drain_tips(&mut tip_verifications, 0).await
+        };
+        if shutdown.is_shutting_down() {
+            // Checkpointed blocks are only verified once their whole range
+            // has been sent, so an incomplete range never finishes. Its
+            // blocks are downloaded again after a restart.
+            match tokio::time::timeout(SHUTDOWN_VERIFY_TIMEOUT, verified).await {
+                Ok(result) => result?,
+                Err(_) => debug!("abandoning incomplete checkpoint ranges for shutdown"),
+            }
+        } else {
+            verified.await?;
+        }
+
+        if verified_any {
+            info!(tip = ?self.best_tip_height.get(), "verified new blocks");
+        }
+        Ok(verified_any)
+    }
+
+    /// Asks several peers for the hashes after the state's best chain, and
+    /// downloads the new ones.
+    async fn obtain_tips(&mut self) -> Result<(), Report> {
+        let locator = match self
+            .state
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(zebra_state::Request::BlockLocator)
+            .await
+            .map_err(|e| eyre!(e))?
+        {
+            zebra_state::Response::BlockLocator { hashes } => hashes,
+            response => return Err(eyre!("unexpected state response: {:?}", response)),
+        };
+
+        let mut responses = FuturesUnordered::new();
+        for _ in 0..FANOUT {
+            let request = zebra_network::Request::FindBlocks {
+                known_blocks: locator.clone(),
+                stop: None,
+            };
+            responses.push(
+                self.peers
+                    .ready_and()
+                    .await
+                    .map_err(|e| eyre!(e))?
+                    .call(request),
+            );
+        }
+
+        while let Some(response) = responses.next().await {
+            let hashes = match response {
+                Ok(zebra_network::Response::BlockHashes(hashes)) => hashes,
+                Ok(response) => {
+                    warn!(?response, "unexpected response to a tip request");
+                    continue;
+                }
+                Err(error) => {
+                    debug!(?error, "tip request failed");
+                    continue;
+                }
+            };
+
+            // Peers start from the last locator hash they know, so the
+            // response can start with blocks that we already have.
+            let mut first_unknown = None;
+            for (index, hash) in hashes.iter().enumerate() {
+                if !self.state_contains(*hash).await? {
+                    first_unknown = Some(index);
+                    break;
+                }
+            }
+            if let Some(first_unknown) = first_unknown {
+                self.add_hashes(&hashes[first_unknown..]);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Asks a peer for the hashes after each prospective tip, and downloads
+    /// the new ones.
+    ///
+    /// Responses that don't start with the expected next hash are from
+    /// peers on another chain, or misbehaving peers, so they are ignored.
+    async fn extend_tips(&mut self) -> Result<(), Report> {
+        let tips: Vec<_> = self.prospective_tips.drain().collect();
+
+        let mut responses = FuturesUnordered::new();
+        for tip in tips {
+            let request = zebra_network::Request::FindBlocks {
+                known_blocks: vec![tip.tip],
+                stop: None,
+            };
+            let response = self
+                .peers
+                .ready_and()
+                .await
+                .map_err(|e| eyre!(e))?
+                .call(request);
+            responses.push(async move { (tip, response.await) });
+        }
+
+        while let Some((tip, response)) = responses.next().await {
+            let hashes = match response {
+                Ok(zebra_network::Response::BlockHashes(hashes)) => hashes,
+                Ok(response) => {
+                    warn!(?response, "unexpected response to a tip extension request");
+                    continue;
+                }
+                Err(error) => {
+                    debug!(?error, "tip extension request failed");
+                    continue;
+                }
+            };
+
+            if hashes.first() != Some(&tip.expected_next) {
+                debug!(?tip, "tip extension doesn't continue from the tip");
+                metrics::counter!("sync.unexpected_tip_extensions", 1);
+                continue;
+            }
+            self.add_hashes(&hashes);
+        }
+
+        Ok(())
+    }
+
+    /// Downloads each of `hashes` that hasn't been requested, and adds the
+    /// last two hashes as a prospective tip.
+    ///
+    /// Responses without any new hashes don't add a tip, so peers can't keep
+    /// the syncer extending the same tips.
+    fn add_hashes(&mut self, hashes: &[block::Hash]) {
+        let new_hashes: Vec<_> = hashes
+            .iter()
+            .filter(|hash| self.requested.insert(**hash))
+            .cloned()
+            .collect();
+        if new_hashes.is_empty() {
+            return;
+        }
+
+        if let [.., tip, expected_next] = hashes {
+            let _ = self.prospective_tips.insert(CheckedTip {
+                tip: *tip,
+                expected_next: *expected_next,
+            });
+        }
+
+        for chunk in new_hashes.chunks(BLOCKS_PER_REQUEST) {
+            self.in_flight += chunk.len();
+            self.downloads
+                .push(tokio::spawn(download(self.peers.clone(), chunk.to_vec())));
+        }
+        metrics::gauge!("sync.in_flight_blocks", self.in_flight as i64);
+    }
+
+    /// Returns true if the block with `hash` is in the state's best chain.
+    async fn state_contains(&mut self, hash: block::Hash) -> Result<bool, Report> {
+        match self
+            .state
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(zebra_state::Request::Depth { hash })
+            .await
+            .map_err(|e| eyre!(e))?
+        {
+            zebra_state::Response::Depth { depth } => Ok(depth.is_some()),
+            response => Err(eyre!("unexpected state response: {:?}", response)),
+        }
+    }
+
+    /// Sends `block` to the verifier.
+    ///
+    /// Blocks above the final checkpoint are checked against the final
+    /// checkpoint, so the syncer waits for any pending checkpoint ranges
+    /// before it sends the first one. The verifier checks each of them
+    /// against its parent, so they are sent without waiting, up to the
+    /// lookahead limit.
+    async fn verify(
+        &mut self,
+        block: Arc<Block>,
+        checkpoint_verifications: &mut FuturesUnordered<ZV::Future>,
+        tip_verifications: &mut FuturesUnordered<TipVerification>,
+    ) -> Result<(), Report> {
+        let height = block
+            .coinbase_height()
+            .ok_or_else(|| eyre!("downloaded block has no coinbase height"))?;
+        let verified = self
+            .verifier
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(block.clone());
+
+        if height <= self.max_checkpoint_height {
+            checkpoint_verifications.push(verified);
+
+            // Blocks above the verifier's maximum height are rejected
+            // straight away, so stop downloading as soon as one is.
+            while let Some(Some(verified)) = checkpoint_verifications.next().now_or_never() {
+                if let Err(error) = verified {
+                    if is_duplicate_block(&error) {
+                        continue;
+                    }
+                    let error = verify_error(error);
+                    if error.downcast_ref::<AboveMaxHeight>().is_some() {
+                        drain(checkpoint_verifications).await?;
+                    }
+                    return Err(error);
+                }
+            }
+            return Ok(());
+        }
+
+        drain(checkpoint_verifications).await?;
+        tip_verifications.push(
+            verified
+                .map(move |verified| (block, height, verified))
+                .boxed(),
+        );
+        self.drain_tips(tip_verifications, self.lookahead_limit)
+            .await
+    }
+
+    /// Handles the finished blocks in `tip_verifications`, and waits for
+    /// more until fewer than `limit` are left.
+    ///
+    /// Like [`drain`], a block above the verifier's maximum height waits for
+    /// the blocks below it, so they are still committed.
+    async fn drain_tips(
+        &mut self,
+        tip_verifications: &mut FuturesUnordered<TipVerification>,
+        mut limit: usize,
+    ) -> Result<(), Report> {
+        let mut above_max_height = None;
+        loop {
+            let next = if tip_verifications.len() >= limit {
+                tip_verifications.next().await
+            } else {
+                tip_verifications.next().now_or_never().flatten()
+            };
+            let (block, height, verified) = match next {
+                Some(next) => next,
+                None => break,
+            };
+            match self.tip_verified(block, height, verified).await {
+                Ok(()) => {}
+                Err(error) if error.downcast_ref::<AboveMaxHeight>().is_some() => {
+                    above_max_height = Some(error);
+                    limit = 0;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        above_max_height.map_or(Ok(()), Err)
+    }
+
+    /// Updates the mempool and the tip height after `block` is verified
+    /// above the final checkpoint.
+    ///
+    /// If the block is invalid, reports it against the peer that sent it.
+    /// Invalid blocks, and the blocks after them, don't stop the round, but
+    /// other verifier failures do.
+    async fn tip_verified(
+        &mut self,
+        block: Arc<Block>,
+        height: block::Height,
+        verified: Result<block::Hash, BoxedStdError>,
+    ) -> Result<(), Report> {
+        let hash = block.hash();
+        match verified {
+            Ok(_) => {}
+            Err(error) if is_misbehavior(&error) => {
+                let peer = self.peer_control.report_invalid_block(&hash);
+                warn!(?hash, ?peer, %error, "peer sent an invalid block");
+                metrics::counter!("sync.invalid_blocks", 1);
+                return Ok(());
+            }
+            Err(error) if error.downcast_ref::<ParentNotAdded>().is_some() => {
+                debug!(?hash, %error, "skipping a block after a block that wasn't added");
+                return Ok(());
+            }
+            Err(error) => return Err(verify_error(error)),
+        }
+
+        // A mempool failure doesn't affect the chain, so keep syncing.
+        let updated = self
+            .mempool
+            .ready_and()
+            .await
+            .map_err(|e| eyre!(e))?
+            .call(mempool::Request::BlockCommitted(block))
+            .await;
+        if let Err(error) = updated {
+            warn!(?error, "failed to update the mempool for a new block");
+        }
+
+        self.best_tip_height.set(height);
+        metrics::gauge!("sync.verified_height", height.0 as i64);
+        Ok(())
+    }
+}
+
+/// Waits for every block in `verifications`.
+///
+/// Returns the first error, except that blocks above the verifier's maximum
+/// height don't stop the wait, so the ranges below them are still committed.
+/// Those blocks are rejected without being verified, so they don't delay
+/// the other blocks. Blocks that are already queued in the checkpoint
+/// verifier are skipped.
+async fn drain<F>(verifications: &mut FuturesUnordered<F>) -> Result<(), Report>
+where
+    F: Future<Output = Result<block::Hash, BoxedStdError>>,
+{
+    let mut above_max_height = None;
+    while let Some(verified) = verifications.next().await {
+        match verified {
+            Ok(_) => {}
+            Err(error) if is_duplicate_block(&error) => {}
+            Err(error) => {
+                let error = verify_error(error);
+                if error.downcast_ref::<AboveMaxHeight>().is_none() {
+                    return Err(error);
+                }
+                above_max_height = Some(error);
+            }
+        }
+    }
+    above_max_height.map_or(Ok(()), Err)
+}
+
+/// Returns true if `error` is for a block that the checkpoint verifier has
+/// already queued.
+///
+/// The queued copy is still verified, so the round can continue.
+fn is_duplicate_block(error: &BoxedStdError) -> bool {
+    matches!(
+        error.downcast_ref::<CheckpointError>(),
+        Some(CheckpointError::DuplicateBlock(_))
+    )
+}
+
+/// Converts a verifier error into a report, keeping an [`AboveMaxHeight`]
+/// error's type, so the syncer can stop for it.
+fn verify_error(error: BoxedStdError) -> Report {
+    match error.downcast::<AboveMaxHeight>() {
+        Ok(above) => Report::new(*above),
+        Err(error) => eyre!(error),
+    }
+}
+
+/// Downloads the blocks with `hashes` from `peers`, and returns them in
+/// height order.
+async fn download<ZN>(mut peers: ZN, hashes: Vec<block::Hash>) -> Result<Vec<Arc<Block>>, Report>
+where
+    ZN: Service<zebra_network::Request, Response = zebra_network::Response, Error = BoxedStdError>,
+{
+    let request = zebra_network::Request::BlocksByHash(hashes.iter().cloned().collect());
+    let mut blocks = match peers
+        .ready_and()
+        .await
+        .map_err(|e| eyre!(e))?
+        .call(request)
+        .await
+        .map_err(|e| eyre!(e))?
+    {
+        zebra_network::Response::Blocks(blocks) => blocks,
+        response => return Err(eyre!("unexpected peer response: {:?}", response)),
+    };
+
+    let received: HashSet<_> = blocks.iter().map(|block| block.hash()).collect();
+    if hashes.iter().any(|hash| !received.contains(hash)) {
+        return Err(eyre!("peers didn't send every requested block"));
+    }
+
+    blocks.sort_by_key(|block| block.coinbase_height());
+    metrics::counter!("sync.downloaded_blocks", blocks.len() as u64);
+    Ok(blocks)
+}
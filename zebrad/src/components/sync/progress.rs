@@ -0,0 +1,188 @@
+//! Sync progress reporting.
+//!
+//! The network tip height is estimated from the heights that peers sent when
+//! they connected, advanced by the target block spacing. The sync rate is
+//! measured between reports, so it follows the current phase of the sync,
+//! which is much faster for checkpointed blocks.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use tracing::info;
+
+use zebra_chain::{block, Network};
+use zebra_network::{BestTipHeight, ConnectedPeers};
+
+/// How often sync progress is logged.
+pub const PROGRESS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The number of blocks below the estimated network tip that still count
+/// as synced, because the estimate is approximate.
+const SYNCED_THRESHOLD: u32 = 10;
+
+/// Logs the sync progress of `best_tip_height` on `network` every
+/// [`PROGRESS_INTERVAL`], and updates the sync progress metrics.
+pub async fn report_progress(
+    network: Network,
+    best_tip_height: BestTipHeight,
+    connected_peers: Arc<Mutex<ConnectedPeers>>,
+) {
+    let mut ticks = tokio::time::interval(PROGRESS_INTERVAL);
+    let mut last: Option<(Instant, block::Height)> = None;
+
+    loop {
+        ticks.tick().await;
+
+        let now = Instant::now();
+        let height = best_tip_height.get().unwrap_or(block::Height(0));
+        let estimated_height = connected_peers
+            .lock()
+            .expect("mutex should be unpoisoned")
+            .estimated_tip_height(network, Utc::now());
+
+        let blocks_per_second = last.map(|(last_time, last_height)| {
+            blocks_per_second(height, last_height, now.duration_since(last_time))
+        });
+        last = Some((now, height));
+
+        let estimated_height = match estimated_height {
+            Some(estimated_height) => estimated_height,
+            None => {
+                info!(?height, "waiting for peers to estimate sync progress");
+                continue;
+            }
+        };
+        let remaining = estimated_height.0.saturating_sub(height.0);
+        metrics::gauge!("sync.estimated_network_height", estimated_height.0 as i64);
+        metrics::gauge!("sync.remaining_blocks", remaining as i64);
+
+        match sync_progress(height, estimated_height, blocks_per_second) {
+            Progress::Synced => info!(?height, ?estimated_height, "close to the network tip"),
+            Progress::Syncing {
+                percent,
+                eta: Some(eta),
+            } => {
+                metrics::gauge!("sync.eta_seconds", eta.as_secs() as i64);
+                info!(
+                    ?height,
+                    ?estimated_height,
+                    remaining,
+                    percent = format!("{:.2}", percent).as_str(),
+                    blocks_per_second = format!("{:.1}", blocks_per_second.unwrap_or(0.0)).as_str(),
+                    ?eta,
+                    "syncing"
+                );
+            }
+            Progress::Syncing { percent, eta: None } => info!(
+                ?height,
+                ?estimated_height,
+                remaining,
+                percent = format!("{:.2}", percent).as_str(),
+                "syncing, but no blocks were verified since the last report"
+            ),
+        }
+    }
+}
+
+/// The sync progress at one report.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Progress {
+    /// The tip is within [`SYNCED_THRESHOLD`] blocks of the estimated
+    /// network tip.
+    Synced,
+    /// The tip is further behind, so the syncer has `percent` of the chain,
+    /// and will finish after `eta`, if any blocks were verified since the
+    /// last report.
+    Syncing { percent: f64, eta: Option<Duration> },
+}
+
+/// Returns the rate that the tip moved from `last_height` to `height`, over
+/// `elapsed`.
+fn blocks_per_second(height: block::Height, last_height: block::Height, elapsed: Duration) -> f64 {
+    let blocks = height.0.saturating_sub(last_height.0);
+    f64::from(blocks) / elapsed.as_secs_f64()
+}
+
+/// Returns the progress of a tip at `height` towards `estimated_height`,
+/// while verifying `blocks_per_second`.
+fn sync_progress(
+    height: block::Height,
+    estimated_height: block::Height,
+    blocks_per_second: Option<f64>,
+) -> Progress {
+    let remaining = estimated_height.0.saturating_sub(height.0);
+    if remaining <= SYNCED_THRESHOLD {
+        return Progress::Synced;
+    }
+
+    let percent = 100.0 * f64::from(height.0) / f64::from(estimated_height.0);
+    let eta = blocks_per_second
+        .filter(|rate| *rate > 0.0)
+        .map(|rate| Duration::from_secs((f64::from(remaining) / rate) as u64));
+    Progress::Syncing { percent, eta }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_per_second_is_measured_between_reports() {
+        let rate = blocks_per_second(
+            block::Height(1_600),
+            block::Height(1_000),
+            Duration::from_secs(60),
+        );
+        assert!((rate - 10.0).abs() < f64::EPSILON);
+
+        // A rollback doesn't make the rate negative.
+        let rate = blocks_per_second(
+            block::Height(900),
+            block::Height(1_000),
+            Duration::from_secs(60),
+        );
+        assert!(rate.abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn nearly_synced_tips_are_synced() {
+        let estimate = block::Height(1_000_000);
+        assert_eq!(sync_progress(estimate, estimate, None), Progress::Synced);
+        assert_eq!(
+            sync_progress(block::Height(1_000_000 - SYNCED_THRESHOLD), estimate, None),
+            Progress::Synced
+        );
+        // Estimates from slow peers can be below the tip.
+        assert_eq!(
+            sync_progress(block::Height(1_000_100), estimate, Some(1.0)),
+            Progress::Synced
+        );
+    }
+
+    #[test]
+    fn eta_uses_the_sync_rate() {
+        let progress = sync_progress(block::Height(250_000), block::Height(1_000_000), Some(50.0));
+        assert_eq!(
+            progress,
+            Progress::Syncing {
+                percent: 25.0,
+                eta: Some(Duration::from_secs(15_000)),
+            }
+        );
+
+        // Without a rate, there is no ETA.
+        for rate in &[None, Some(0.0)] {
+            let progress = sync_progress(block::Height(250_000), block::Height(1_000_000), *rate);
+            assert_eq!(
+                progress,
+                Progress::Syncing {
+                    percent: 25.0,
+                    eta: None,
+                }
+            );
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! systemd service notifications.
+//!
+//! With `Type=notify`, systemd waits for `zebrad` to send `READY=1` before it
+//! starts dependent units. With `WatchdogSec=`, systemd restarts `zebrad` if
+//! it stops sending `WATCHDOG=1`, which we only send while the syncer is
+//! making progress.
+//!
+//! If `zebrad` isn't running under systemd, notifications are ignored.
+
+use std::time::Duration;
+
+use sd_notify::NotifyState;
+use tracing::{debug, warn};
+
+use crate::components::sync::SyncStatus;
+
+/// How long the syncer can go without progress before the watchdog stops
+/// pinging systemd.
+///
+/// This is much longer than the syncer's tip poll and restart delays, so it
+/// only catches a syncer that is stuck.
+pub const MAX_TIME_WITHOUT_PROGRESS: Duration = Duration::from_secs(10 * 60);
+
+/// Tells systemd that `zebrad` has started.
+pub fn notify_ready() {
+    if let Err(error) = sd_notify::notify(false, &[NotifyState::Ready]) {
+        debug!(?error, "could not notify systemd");
+    }
+}
+
+/// Pings the systemd watchdog while the syncer with `status` is making
+/// progress.
+///
+/// Returns immediately if the watchdog isn't enabled for this process.
+pub async fn watchdog(status: SyncStatus) {
+    let interval = match watchdog_interval() {
+        Some(interval) => interval,
+        None => return,
+    };
+
+    // systemd recommends pinging at half the timeout.
+    let mut ticks = tokio::time::interval(interval / 2);
+    loop {
+        ticks.tick().await;
+
+        let stalled = status.time_since_progress();
+        if stalled > MAX_TIME_WITHOUT_PROGRESS {
+            warn!(
+                ?stalled,
+                "syncer isn't making progress, skipping systemd watchdog ping"
+            );
+            continue;
+        }
+        if let Err(error) = sd_notify::notify(false, &[NotifyState::Watchdog]) {
+            debug!(?error, "could not ping the systemd watchdog");
+        }
+    }
+}
+
+/// Returns the watchdog timeout that systemd set for this process.
+fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec)).filter(|interval| *interval > Duration::from_secs(0))
+}
@@ -1,16 +1,26 @@
 //! An HTTP endpoint for dynamically setting tracing filters.
+//!
+//! On Unix, `zebrad` also reloads the filter from its config file when it
+//! gets a `SIGHUP`.
 
-use crate::{components::tokio::TokioComponent, prelude::*};
+use std::{net::SocketAddr, path::PathBuf};
+
+use crate::{components::tokio::TokioComponent, config::TracingSection, prelude::*};
 
 use abscissa_core::{Component, FrameworkError};
 
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Request, Response, Server};
+use tokio::runtime::Handle;
 
 /// Abscissa component which runs a tracing filter endpoint.
 #[derive(Debug, Component)]
 #[component(inject = "init_tokio(zebrad::components::tokio::TokioComponent)")]
-pub struct TracingEndpoint {}
+pub struct TracingEndpoint {
+    /// The runtime that the endpoint runs on, which is set before the
+    /// config is loaded.
+    runtime: Option<Handle>,
+}
 
 async fn read_filter(req: Request<Body>) -> Result<String, String> {
     std::str::from_utf8(
@@ -25,66 +35,152 @@ async fn read_filter(req: Request<Body>) -> Result<String, String> {
 impl TracingEndpoint {
     /// Create the component.
     pub fn new() -> Result<Self, FrameworkError> {
-        Ok(Self {})
+        Ok(Self { runtime: None })
     }
 
     /// Do setup after receiving a tokio runtime.
     pub fn init_tokio(&mut self, tokio_component: &TokioComponent) -> Result<(), FrameworkError> {
-        info!("Initializing tracing endpoint");
-
-        let service =
-            make_service_fn(|_| async { Ok::<_, hyper::Error>(service_fn(request_handler)) });
+        self.runtime = Some(
+            tokio_component
+                .rt
+                .as_ref()
+                .expect("runtime should not be taken")
+                .handle()
+                .clone(),
+        );
+        Ok(())
+    }
 
-        // XXX load tracing addr from config
-        let addr = "127.0.0.1:3000"
-            .parse()
-            .expect("Hardcoded address should be parseable");
+    /// Opens the endpoint on the address in `config`, and reloads the filter
+    /// from the file at `config_path` on `SIGHUP`.
+    ///
+    /// Called after the config is loaded.
+    pub fn open(&mut self, config: &TracingSection, config_path: Option<PathBuf>) {
+        info!("Initializing tracing endpoint");
 
-        tokio_component
-            .rt
+        let runtime = self
+            .runtime
             .as_ref()
-            .expect("runtime should not be taken")
-            .spawn(async move {
-                // try_bind uses the tokio runtime, so we
-                // need to construct it inside the task.
-                let server = match Server::try_bind(&addr) {
-                    Ok(s) => s,
-                    Err(e) => {
-                        error!("Could not open tracing endpoint listener");
-                        error!("Error: {}", e);
-                        return;
-                    }
+            .expect("tokio runtime should be injected before the config is loaded");
+
+        let addr = config.endpoint_addr;
+        let service = make_service_fn(move |_| async move {
+            Ok::<_, hyper::Error>(service_fn(move |req| request_handler(addr, req)))
+        });
+
+        #[cfg(unix)]
+        runtime.spawn(reload_on_hangup(config_path));
+        #[cfg(not(unix))]
+        let _ = config_path;
+
+        runtime.spawn(async move {
+            // try_bind uses the tokio runtime, so we
+            // need to construct it inside the task.
+            let server = match Server::try_bind(&addr) {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("Could not open tracing endpoint listener");
+                    error!("Error: {}", e);
+                    return;
                 }
-                .serve(service);
+            }
+            .serve(service);
 
-                if let Err(e) = server.await {
-                    error!("Server error: {}", e);
-                }
-            });
+            if let Err(e) = server.await {
+                error!("Server error: {}", e);
+            }
+        });
+    }
+}
 
-        Ok(())
+/// Reloads the tracing filter from the config file at `config_path` each
+/// time `zebrad` gets a `SIGHUP`.
+///
+/// `ZEBRA_` environment variables override the config file, like they do at
+/// startup. If neither has a filter, resets the filter to `info`. The
+/// `ZEBRAD_LOG` environment variable and the `--verbose` flag only apply at
+/// startup.
+#[cfg(unix)]
+async fn reload_on_hangup(config_path: Option<PathBuf>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            error!("Could not listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+
+    while hangups.recv().await.is_some() {
+        let filter = match config_filter(config_path.as_deref()) {
+            Ok(filter) => filter,
+            Err(e) => {
+                warn!(
+                    "Could not reload the tracing filter from {:?}: {}",
+                    config_path, e
+                );
+                continue;
+            }
+        };
+        let filter = filter.unwrap_or_else(|| "info".to_owned());
+
+        info!(?filter, "reloading tracing filter after SIGHUP");
+        app_writer()
+            .state_mut()
+            .components
+            .get_downcast_mut::<abscissa_core::trace::Tracing>()
+            .expect("Tracing component should be available")
+            .reload_filter(filter);
     }
 }
 
+/// Returns the tracing filter in the config file at `path`, or in the
+/// default config if there isn't a file, after applying the environment
+/// variable overrides.
+#[cfg(unix)]
+fn config_filter(path: Option<&std::path::Path>) -> Result<Option<String>, String> {
+    let config: crate::config::ZebradConfig = match path {
+        Some(path) => {
+            let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+            toml::from_str(&contents).map_err(|e| e.to_string())?
+        }
+        None => Default::default(),
+    };
+    let config = config.with_env_overrides(crate::config::env_vars())?;
+    Ok(config.tracing.filter)
+}
+
 #[instrument]
-async fn request_handler(req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+async fn request_handler(
+    addr: SocketAddr,
+    req: Request<Body>,
+) -> Result<Response<Body>, hyper::Error> {
     use hyper::{Method, StatusCode};
 
     let rsp = match (req.method(), req.uri().path()) {
-        (&Method::GET, "/") => Response::new(Body::from(
+        (&Method::GET, "/") => Response::new(Body::from(format!(
             r#"
 This HTTP endpoint allows dynamic control of the filter applied to
 tracing events.
 
 To get the current filter, GET /filter:
 
-    curl -X GET localhost:3000/filter
+    curl -X GET {addr}/filter
 
 To set the filter, POST the new filter string to /filter:
 
-    curl -X POST localhost:3000/filter -d "zebrad=trace"
+    curl -X POST {addr}/filter -d "zebrad=trace"
+
+To reload the filter from the config file, send SIGHUP to zebrad.
+
+If zebrad was started with ZEBRAD_FLAMEGRAPH set, POST to /flamegraph to
+write the span timings so far to that file:
+
+    curl -X POST {addr}/flamegraph
 "#,
-        )),
+            addr = addr,
+        ))),
         (&Method::GET, "/filter") => Response::builder()
             .status(StatusCode::OK)
             .body(Body::from(
@@ -112,6 +208,13 @@ To set the filter, POST the new filter string to /filter:
                 .body(Body::from(e))
                 .expect("response with known status code cannot fail"),
         },
+        (&Method::POST, "/flamegraph") => match app_reader().flush_flamegraph() {
+            Ok(()) => Response::new(Body::from("")),
+            Err(e) => Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(e.to_string()))
+                .expect("response with known status code cannot fail"),
+        },
         _ => Response::builder()
             .status(StatusCode::NOT_FOUND)
             .body(Body::from(""))
@@ -1,14 +1,29 @@
 //! Zebrad Config
 //!
-//! See instructions in `commands.rs` to specify the path to your
-//! application's configuration file and/or command-line options
-//! for specifying it.
+//! `zebrad` reads its config from `zebrad.toml` in the current directory,
+//! or from the file passed with `-c`. Every section and key is optional, and
+//! missing keys have their default values. Unknown keys are errors, so typos
+//! aren't silently ignored.
+//!
+//! `zebrad generate` writes the default config.
+//!
+//! Environment variables override the config file, and command-line options
+//! override both. Each variable is named after a key, with a `ZEBRA_` prefix,
+//! and `__` between the section and key names, like
+//! `ZEBRA_STATE__CACHE_DIR` for `state.cache_dir`. Values are parsed
+//! as TOML values, or used as strings if they aren't valid TOML, so strings
+//! that look like numbers or booleans must be quoted.
 
 use std::net::SocketAddr;
 
 use serde::{Deserialize, Serialize};
 
+use zebra_chain::transparent;
+
+use zebra_consensus::Config as ConsensusSection;
 use zebra_network::Config as NetworkSection;
+use zebra_rpc::Config as RpcSection;
+use zebra_state::Config as StateSection;
 
 /// Zebrad Configuration
 #[derive(Clone, Default, Debug, Deserialize, Serialize)]
@@ -19,22 +34,145 @@ pub struct ZebradConfig {
     pub tracing: TracingSection,
     /// Networking configuration
     pub network: NetworkSection,
+    /// Consensus configuration
+    pub consensus: ConsensusSection,
+    /// State configuration
+    pub state: StateSection,
     /// Metrics configuration
     pub metrics: MetricsSection,
+    /// Sync configuration
+    pub sync: SyncSection,
+    /// Mempool configuration
+    pub mempool: MempoolSection,
+    /// RPC configuration
+    pub rpc: RpcSection,
+}
+
+impl ZebradConfig {
+    /// Checks the values that parse, but can't be used.
+    ///
+    /// Returns an error naming the first invalid key.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.sync.lookahead_limit == 0 {
+            return Err("sync.lookahead_limit must be at least 1".to_owned());
+        }
+        if self.sync.lookahead_limit >= zebra_consensus::checkpoint::MAX_QUEUED_BLOCKS {
+            return Err(format!(
+                "sync.lookahead_limit must be less than {}",
+                zebra_consensus::checkpoint::MAX_QUEUED_BLOCKS
+            ));
+        }
+        self.consensus.validate()?;
+        if self.rpc.user.is_some() != self.rpc.password.is_some() {
+            return Err("rpc.user and rpc.password must be set together".to_owned());
+        }
+        if let Some(address) = &self.rpc.miner_address {
+            address
+                .parse::<transparent::Address>()
+                .map_err(|e| format!("rpc.miner_address is invalid: {}", e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns this config, with the keys named by the `ZEBRA_` variables in
+    /// `vars` replaced by their values.
+    ///
+    /// Returns an error naming the first variable that isn't a valid key or
+    /// value.
+    pub fn with_env_overrides<I>(self, vars: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut config =
+            toml::Value::try_from(&self).map_err(|e| format!("invalid config: {}", e))?;
+        let mut overridden = Vec::new();
+
+        for (name, raw_value) in vars {
+            if !name.starts_with(ENV_PREFIX) {
+                continue;
+            }
+            let path: Vec<String> = name[ENV_PREFIX.len()..]
+                .split("__")
+                .map(str::to_lowercase)
+                .collect();
+            let (key, sections) = path.split_last().expect("split returns at least one item");
+
+            let mut table = &mut config;
+            for section in sections {
+                table = table
+                    .as_table_mut()
+                    .ok_or_else(|| format!("{} does not name a config key", name))?
+                    .entry(section.clone())
+                    .or_insert_with(|| toml::Value::Table(Default::default()));
+            }
+            let value = toml::from_str::<toml::Value>(&format!("value = {}", raw_value))
+                .ok()
+                .and_then(|parsed| parsed.get("value").cloned())
+                .unwrap_or(toml::Value::String(raw_value));
+            table
+                .as_table_mut()
+                .ok_or_else(|| format!("{} does not name a config key", name))?
+                .insert(key.clone(), value);
+            overridden.push(name);
+        }
+
+        if overridden.is_empty() {
+            return Ok(self);
+        }
+        config.try_into().map_err(|e| {
+            format!(
+                "invalid config override in {}: {}",
+                overridden.join(", "),
+                e
+            )
+        })
+    }
+}
+
+/// The prefix of environment variables that override config keys.
+const ENV_PREFIX: &str = "ZEBRA_";
+
+/// Returns the environment variables of this process, for
+/// [`ZebradConfig::with_env_overrides`].
+///
+/// `env::vars` panics on non-Unicode variables, so they are skipped.
+pub fn env_vars() -> impl Iterator<Item = (String, String)> {
+    std::env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
 }
 
 /// Tracing configuration section.
-#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
+#[serde(default)]
 pub struct TracingSection {
     /// The filter used for tracing events.
+    ///
+    /// Sending `zebrad` a `SIGHUP` reloads this filter from the config file.
     pub filter: Option<String>,
+
+    /// The address of the HTTP endpoint that changes the filter.
+    ///
+    /// Anyone who can connect to the endpoint can make `zebrad` log a lot,
+    /// so it should be a local address.
+    pub endpoint_addr: SocketAddr,
 }
 
 impl TracingSection {
     pub fn populated() -> Self {
         Self {
             filter: Some("info".to_owned()),
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for TracingSection {
+    fn default() -> Self {
+        Self {
+            filter: None,
+            endpoint_addr: "127.0.0.1:3000".parse().unwrap(),
         }
     }
 }
@@ -42,7 +180,9 @@ impl TracingSection {
 /// Metrics configuration section.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
+#[serde(default)]
 pub struct MetricsSection {
+    /// The address of the Prometheus metrics endpoint.
     pub endpoint_addr: SocketAddr,
 }
 
@@ -54,15 +194,135 @@ impl Default for MetricsSection {
     }
 }
 
+/// Sync configuration section.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+#[serde(default)]
+pub struct SyncSection {
+    /// The maximum number of blocks that are being downloaded, but haven't
+    /// been sent to the verifier yet.
+    pub lookahead_limit: usize,
+}
+
+impl Default for SyncSection {
+    fn default() -> Self {
+        Self {
+            lookahead_limit: 2_000,
+        }
+    }
+}
+
+/// Mempool configuration section.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+#[serde(default)]
+pub struct MempoolSection {
+    /// The maximum total size of the mempool transactions, in bytes.
+    ///
+    /// When the mempool is full, the transactions with the lowest fee per
+    /// byte are evicted.
+    pub max_bytes: usize,
+    /// The minimum fee for each 1000 bytes of a transaction, in zatoshis.
+    pub min_fee_per_kilobyte: u64,
+}
+
+impl Default for MempoolSection {
+    fn default() -> Self {
+        Self {
+            max_bytes: 80_000_000,
+            min_fee_per_kilobyte: 100,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use super::ZebradConfig;
+
     #[test]
     fn test_toml_ser() -> color_eyre::Result<()> {
-        let default_config = super::ZebradConfig::default();
+        let default_config = ZebradConfig::default();
         println!("Default config: {:?}", default_config);
 
         println!("Toml:\n{}", toml::Value::try_from(&default_config)?);
 
         Ok(())
     }
+
+    #[test]
+    fn partial_configs_use_defaults() -> color_eyre::Result<()> {
+        let config: ZebradConfig = toml::from_str("[network]\nrelay = true\n[metrics]\n")?;
+        assert!(config.network.relay);
+        assert_eq!(
+            config.metrics.endpoint_addr,
+            ZebradConfig::default().metrics.endpoint_addr
+        );
+        config.validate().map_err(|e| eyre::eyre!(e))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn old_network_keys_are_accepted() -> color_eyre::Result<()> {
+        let config: ZebradConfig = toml::from_str(
+            "[network]\nlisten_addr = '0.0.0.0:8233'\npeerset_initial_target_size = 20\n",
+        )?;
+        assert_eq!(config.network.listen_addrs, vec!["0.0.0.0:8233".parse()?]);
+        assert_eq!(config.network.target_outbound_peers, 20);
+
+        let config: ZebradConfig =
+            toml::from_str("[network]\nlisten_addrs = ['0.0.0.0:8233', '[::]:8233']\n")?;
+        assert_eq!(config.network.listen_addrs.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn bad_configs_are_rejected() {
+        assert!(toml::from_str::<ZebradConfig>("[network]\nrelays = false\n").is_err());
+        assert!(toml::from_str::<ZebradConfig>("[mempol]\n").is_err());
+
+        let mut config = ZebradConfig::default();
+        config.sync.lookahead_limit = 0;
+        assert!(config.validate().is_err());
+        config.sync.lookahead_limit = zebra_consensus::checkpoint::MAX_QUEUED_BLOCKS;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn env_vars_override_keys() -> color_eyre::Result<()> {
+        let vars = |vars: &[(&str, &str)]| {
+            vars.iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        let config = ZebradConfig::default()
+            .with_env_overrides(vars(&[
+                ("ZEBRA_NETWORK__NETWORK", "Testnet"),
+                ("ZEBRA_NETWORK__LISTEN_ADDRS", r#"["0.0.0.0:18233"]"#),
+                ("ZEBRA_STATE__CACHE_DIR", "/var/cache/zebra"),
+                ("ZEBRA_SYNC__LOOKAHEAD_LIMIT", "500"),
+                ("ZEBRA_RPC__LISTEN_ADDR", "127.0.0.1:8232"),
+                ("ZEBRAD_LOG", "debug"),
+            ]))
+            .map_err(|e| eyre::eyre!(e))?;
+        assert_eq!(config.network.network, zebra_chain::Network::Testnet);
+        assert_eq!(config.network.listen_addrs, vec!["0.0.0.0:18233".parse()?]);
+        assert_eq!(
+            config.state.cache_dir,
+            std::path::PathBuf::from("/var/cache/zebra")
+        );
+        assert_eq!(config.sync.lookahead_limit, 500);
+        assert_eq!(config.rpc.listen_addr, Some("127.0.0.1:8232".parse()?));
+
+        assert!(ZebradConfig::default()
+            .with_env_overrides(vars(&[("ZEBRA_NETWORK__RELAYS", "true")]))
+            .is_err());
+        assert!(ZebradConfig::default()
+            .with_env_overrides(vars(&[("ZEBRA_SYNC__LOOKAHEAD_LIMIT", "many")]))
+            .is_err());
+
+        Ok(())
+    }
 }